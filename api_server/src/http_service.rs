@@ -6,25 +6,28 @@ use std::result;
 use std::str;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 
 use futures::future::{self, Either};
+use futures::sync::oneshot;
 use futures::{Future, Stream};
 
 use hyper::{self, Chunk, Headers, Method, StatusCode};
 use serde_json;
 
 use logger::{Metric, METRICS};
-use mmds::data_store::Mmds;
+use mmds::data_store::{Error as MmdsError, Mmds, MAX_VERSIONS};
 use request::actions::ActionBody;
 use request::drive::PatchDrivePayload;
 use request::{GenerateHyperResponse, IntoParsedRequest, ParsedRequest};
 use sys_util::EventFd;
 use vmm::vmm_config::boot_source::BootSourceConfig;
+use vmm::vmm_config::cpu_template::CustomCpuTemplate;
 use vmm::vmm_config::drive::BlockDeviceConfig;
 use vmm::vmm_config::instance_info::InstanceInfo;
 use vmm::vmm_config::logger::LoggerConfig;
 use vmm::vmm_config::machine_config::VmConfig;
-use vmm::vmm_config::net::NetworkInterfaceConfig;
+use vmm::vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceUpdateConfig};
 #[cfg(feature = "vsock")]
 use vmm::vmm_config::vsock::VsockDeviceConfig;
 use vmm::VmmAction;
@@ -69,6 +72,54 @@ pub fn json_fault_message<T: AsRef<str>>(msg: T) -> String {
     basic_json_body("fault_message", msg)
 }
 
+// The response to an MMDS update which would exceed the limit of the data store.
+fn mmds_limit_exceeded_response(limit: usize) -> hyper::Response {
+    json_response(
+        StatusCode::PayloadTooLarge,
+        json_fault_message(format!(
+            "The MMDS data store is limited to {} bytes.",
+            limit
+        )),
+    )
+}
+
+// The maximum size of the body of a request: the data store limit for the updates of the MMDS,
+// which can't be stored when they are larger anyway, and no limit for the other requests.
+fn body_limit(method: &Method, path: &str, mmds_info: &Mutex<Mmds>) -> usize {
+    let is_mmds_update = (*method == Method::Put || *method == Method::Patch)
+        && (path == "/mmds" || path.starts_with("/mmds/"));
+    if !is_mmds_update {
+        return usize::max_value();
+    }
+    mmds_info
+        .lock()
+        .expect("Failed to acquire lock on MMDS info")
+        .data_store_limit()
+}
+
+// The response to a request storing a version of the MMDS metadata which failed.
+fn mmds_version_error_response(error: MmdsError) -> hyper::Response {
+    match error {
+        MmdsError::NotFound => json_response(
+            StatusCode::NotFound,
+            json_fault_message("The MMDS data store has no latest metadata."),
+        ),
+        MmdsError::TooManyVersions => json_response(
+            StatusCode::BadRequest,
+            json_fault_message(format!(
+                "At most {} versions of the MMDS metadata can be stored.",
+                MAX_VERSIONS
+            )),
+        ),
+        _ => json_response(
+            StatusCode::BadRequest,
+            json_fault_message(
+                "The MMDS version names are made of ASCII letters, digits, dots and dashes.",
+            ),
+        ),
+    }
+}
+
 enum Error<'a> {
     // A generic error, with a given status code and message to be turned into a fault message.
     Generic(StatusCode, String),
@@ -160,6 +211,32 @@ fn parse_boot_source_req<'a>(
     }
 }
 
+// Turns a PUT /cpu-template HTTP request into a ParsedRequest
+fn parse_cpu_template_req<'a>(
+    path: &'a str,
+    method: Method,
+    body: &Chunk,
+) -> Result<'a, ParsedRequest> {
+    let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
+
+    match path_tokens[1..].len() {
+        0 if method == Method::Put => {
+            METRICS.put_api_requests.cpu_template_count.inc();
+            Ok(serde_json::from_slice::<CustomCpuTemplate>(body)
+                .map_err(|e| {
+                    METRICS.put_api_requests.cpu_template_fails.inc();
+                    Error::SerdeJson(e)
+                })?
+                .into_parsed_request(None, method)
+                .map_err(|s| {
+                    METRICS.put_api_requests.cpu_template_fails.inc();
+                    Error::Generic(StatusCode::BadRequest, s)
+                })?)
+        }
+        _ => Err(Error::InvalidPathMethod(path, method)),
+    }
+}
+
 // Turns HTTP requests on /mmds into a ParsedRequest
 // This is a rather dummy method with the purpose of keeping the same code structure as before.
 // We will need to refactor this as some point.
@@ -182,6 +259,53 @@ fn parse_mmds_request<'a>(
             Ok(val) => return Ok(ParsedRequest::PatchMMDS(val)),
             Err(e) => return Err(Error::SerdeJson(e)),
         },
+        // The user data is opaque, so the body is not parsed.
+        1 if path_tokens[1] == "user-data" && method == Method::Put => {
+            Ok(ParsedRequest::PutMMDSUserData(body.to_vec()))
+        }
+        // The current metadata is stored as the version, so there is no body.
+        2 if path_tokens[1] == "versions" && method == Method::Put => {
+            Ok(ParsedRequest::PutMMDSVersion(path_tokens[2].to_string()))
+        }
+        _ => Err(Error::InvalidPathMethod(path, method)),
+    }
+}
+
+// Turns a GET /devices/{id}/features HTTP request into a ParsedRequest
+fn parse_devices_req<'a>(path: &'a str, method: Method) -> Result<'a, ParsedRequest> {
+    let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
+    let id_from_path = if path_tokens.len() > 1 {
+        checked_id(path_tokens[1])?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    match path_tokens[1..].len() {
+        2 if path_tokens[2] == "features" && method == Method::Get => {
+            METRICS.get_api_requests.device_features_count.inc();
+            let (sender, receiver) = oneshot::channel();
+            Ok(ParsedRequest::Sync(
+                VmmAction::GetDeviceFeatures(id_from_path.to_string(), sender),
+                receiver,
+            ))
+        }
+        _ => Err(Error::InvalidPathMethod(path, method)),
+    }
+}
+
+// Turns a GET /events HTTP request into a ParsedRequest
+fn parse_events_req<'a>(path: &'a str, method: Method) -> Result<'a, ParsedRequest> {
+    let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
+
+    match path_tokens[1..].len() {
+        0 if method == Method::Get => {
+            METRICS.get_api_requests.events_count.inc();
+            let (sender, receiver) = oneshot::channel();
+            Ok(ParsedRequest::Sync(
+                VmmAction::GetGuestEvents(sender),
+                receiver,
+            ))
+        }
         _ => Err(Error::InvalidPathMethod(path, method)),
     }
 }
@@ -269,6 +393,15 @@ fn parse_machine_config_req<'a>(
                 mem_size_mib: None,
                 ht_enabled: None,
                 cpu_template: None,
+                thp_policy: None,
+                dontfork: None,
+                prefault: None,
+                watchdog_action: None,
+                vpmu: None,
+                mergeable: None,
+                mmio_gap_size_mib: None,
+                mmio_base: None,
+                acpi: None,
             };
             Ok(empty_machine_config
                 .into_parsed_request(None, method)
@@ -295,7 +428,7 @@ fn parse_machine_config_req<'a>(
     }
 }
 
-// Turns a GET/PUT /network-interfaces HTTP request into a ParsedRequest
+// Turns a PUT/PATCH /network-interfaces HTTP request into a ParsedRequest
 fn parse_netif_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'a, ParsedRequest> {
     let path_tokens: Vec<&str> = path[1..].split_terminator('/').collect();
     let id_from_path = if path_tokens.len() > 1 {
@@ -319,6 +452,20 @@ fn parse_netif_req<'a>(path: &'a str, method: Method, body: &Chunk) -> Result<'a
                     Error::Generic(StatusCode::BadRequest, s)
                 })?)
         }
+        1 if method == Method::Patch => {
+            METRICS.patch_api_requests.network_count.inc();
+
+            Ok(serde_json::from_slice::<NetworkInterfaceUpdateConfig>(body)
+                .map_err(|e| {
+                    METRICS.patch_api_requests.network_fails.inc();
+                    Error::SerdeJson(e)
+                })?
+                .into_parsed_request(Some(id_from_path.to_string()), method)
+                .map_err(|s| {
+                    METRICS.patch_api_requests.network_fails.inc();
+                    Error::Generic(StatusCode::BadRequest, s)
+                })?)
+        }
         _ => Err(Error::InvalidPathMethod(path, method)),
     }
 }
@@ -385,7 +532,10 @@ fn parse_request<'a>(method: Method, path: &'a str, body: &Chunk) -> Result<'a,
     match path_tokens[0] {
         "actions" => parse_actions_req(path, method, body),
         "boot-source" => parse_boot_source_req(path, method, body),
+        "cpu-template" => parse_cpu_template_req(path, method, body),
+        "devices" => parse_devices_req(path, method),
         "drives" => parse_drives_req(path, method, body),
+        "events" => parse_events_req(path, method),
         "logger" => parse_logger_req(path, method, body),
         "machine-config" => parse_machine_config_req(path, method, body),
         "network-interfaces" => parse_netif_req(path, method, body),
@@ -456,14 +606,37 @@ impl hyper::server::Service for ApiServerHttpService {
         let shared_info_lock = self.vmm_shared_info.clone();
         let api_request_sender = self.api_request_sender.clone();
         let vmm_send_event = self.vmm_send_event.clone();
+        let endpoint_metrics = METRICS
+            .api_server
+            .endpoints
+            .register(&endpoint(req.method(), req.path()));
+        let start_time = Instant::now();
+        let body_limit = body_limit(req.method(), req.path(), &self.mmds_info);
 
         // for nice looking match arms
         use request::ParsedRequest::*;
 
         // The request body is itself a future (a stream of Chunks to be more precise),
-        // so we have to define a future that waits for all the pieces first (via concat2),
-        // and then does something with the newly available body (via and_then).
-        Box::new(req.body().concat2().and_then(move |b| {
+        // so we have to define a future that waits for all the pieces first (via fold),
+        // and then does something with the newly available body (via and_then). The pieces
+        // are dropped instead of accumulated once the body is larger than its limit.
+        let body = req
+            .body()
+            .fold((Vec::new(), false), move |(mut body, too_large), chunk| {
+                let too_large = too_large || body.len() + chunk.len() > body_limit;
+                if too_large {
+                    body = Vec::new();
+                } else {
+                    body.extend_from_slice(&chunk);
+                }
+                future::ok::<_, hyper::Error>((body, too_large))
+            });
+        let response = body.and_then(move |(body, too_large)| {
+            if too_large {
+                log_received_api_request(describe(&method_copy, &path, &None));
+                return Either::A(future::ok(mmds_limit_exceeded_response(body_limit)));
+            }
+            let b = Chunk::from(body);
             // When this will be executed, the body is available. We start by parsing the request.
             match parse_request(method, path.as_ref(), &b) {
                 Ok(parsed_req) => match parsed_req {
@@ -496,10 +669,14 @@ impl hyper::server::Service for ApiServerHttpService {
                             .lock()
                             .expect("Failed to acquire lock on MMDS info");
                         match mmds.is_initialized() {
-                            true => {
-                                mmds.patch_data(json_value);
-                                Either::A(future::ok(empty_response(StatusCode::NoContent)))
-                            }
+                            true => match mmds.patch_data(json_value) {
+                                Ok(()) => {
+                                    Either::A(future::ok(empty_response(StatusCode::NoContent)))
+                                }
+                                Err(_) => Either::A(future::ok(mmds_limit_exceeded_response(
+                                    mmds.data_store_limit(),
+                                ))),
+                            },
                             false => Either::A(future::ok(json_response(
                                 StatusCode::NotFound,
                                 json_fault_message("The MMDS resource does not exist."),
@@ -510,11 +687,38 @@ impl hyper::server::Service for ApiServerHttpService {
                         // Requests on /mmds should not have the body in the logs as the data
                         // store contains customer data.
                         log_received_api_request(describe(&method_copy, &path, &None));
-                        mmds_info
+                        let mut mmds = mmds_info
                             .lock()
-                            .expect("Failed to acquire lock on MMDS info")
-                            .put_data(json_value);
-                        Either::A(future::ok(empty_response(StatusCode::NoContent)))
+                            .expect("Failed to acquire lock on MMDS info");
+                        match mmds.put_data(json_value) {
+                            Ok(()) => Either::A(future::ok(empty_response(StatusCode::NoContent))),
+                            Err(_) => Either::A(future::ok(mmds_limit_exceeded_response(
+                                mmds.data_store_limit(),
+                            ))),
+                        }
+                    }
+                    PutMMDSUserData(user_data) => {
+                        // The user data is not logged either.
+                        log_received_api_request(describe(&method_copy, &path, &None));
+                        let mut mmds = mmds_info
+                            .lock()
+                            .expect("Failed to acquire lock on MMDS info");
+                        match mmds.put_user_data(user_data) {
+                            Ok(()) => Either::A(future::ok(empty_response(StatusCode::NoContent))),
+                            Err(_) => Either::A(future::ok(mmds_limit_exceeded_response(
+                                mmds.data_store_limit(),
+                            ))),
+                        }
+                    }
+                    PutMMDSVersion(version) => {
+                        log_received_api_request(describe(&method_copy, &path, &None));
+                        let mut mmds = mmds_info
+                            .lock()
+                            .expect("Failed to acquire lock on MMDS info");
+                        match mmds.put_version(&version) {
+                            Ok(()) => Either::A(future::ok(empty_response(StatusCode::NoContent))),
+                            Err(e) => Either::A(future::ok(mmds_version_error_response(e))),
+                        }
                     }
                     GetMMDS => {
                         log_received_api_request(describe(&method_copy, &path, &None));
@@ -587,10 +791,67 @@ impl hyper::server::Service for ApiServerHttpService {
                 },
                 Err(e) => Either::A(future::ok(e.into())),
             }
+        });
+
+        // Once the response is ready, or the request timed out, update the endpoint metrics.
+        Box::new(response.then(move |result| {
+            let latency = start_time.elapsed();
+            endpoint_metrics.count.inc();
+            endpoint_metrics
+                .latency_us
+                .record(latency.as_secs() * 1_000_000 + u64::from(latency.subsec_micros()));
+            let failed = match result {
+                Ok(ref response) => {
+                    response.status().is_client_error() || response.status().is_server_error()
+                }
+                Err(_) => true,
+            };
+            if failed {
+                endpoint_metrics.fails.inc();
+            }
+            result
         }))
     }
 }
 
+/// Returns the method and the route of a request on `path`, which identify the API endpoint in
+/// the metrics, such as "PUT /drives/{id}".
+///
+/// The resource IDs are replaced in the routes, and all the invalid paths are grouped under a
+/// single route, so that clients can't create an unbounded number of metrics.
+fn endpoint(method: &Method, path: &str) -> String {
+    const RESOURCES: [&str; 11] = [
+        "actions",
+        "boot-source",
+        "cpu-template",
+        "devices",
+        "drives",
+        "events",
+        "logger",
+        "machine-config",
+        "mmds",
+        "network-interfaces",
+        "vsocks",
+    ];
+
+    let method = match *method {
+        Method::Extension(_) => String::from("OTHER"),
+        ref method => method.to_string(),
+    };
+    let tokens: Vec<&str> = path.split('/').filter(|token| !token.is_empty()).collect();
+    let route = match tokens.len() {
+        0 => String::from("/"),
+        _ if !RESOURCES.contains(&tokens[0]) => String::from("other"),
+        1 => format!("/{}", tokens[0]),
+        2 if tokens[0] == "mmds" && tokens[1] == "user-data" => String::from("/mmds/user-data"),
+        2 => format!("/{}/{{id}}", tokens[0]),
+        3 if tokens[0] == "mmds" && tokens[1] == "versions" => String::from("/mmds/versions/{id}"),
+        3 if tokens[2] == "features" => format!("/{}/{{id}}/features", tokens[0]),
+        _ => String::from("other"),
+    };
+    format!("{} {}", method, route)
+}
+
 /// Helper function for writing the received API requests to the log.
 ///
 /// The `info` macro is used for logging.
@@ -633,6 +894,7 @@ mod tests {
     use hyper::Body;
     use vmm::vmm_config::logger::LoggerLevel;
     use vmm::vmm_config::machine_config::CpuFeaturesTemplate;
+    use vmm::vmm_config::net::PacketCaptureConfig;
     use vmm::VmmAction;
 
     impl<'a> PartialEq for Error<'a> {
@@ -735,6 +997,57 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_mmds_version_error_response() {
+        let resp = mmds_version_error_response(MmdsError::NotFound);
+        assert_eq!(resp.status(), StatusCode::NotFound);
+        let resp = mmds_version_error_response(MmdsError::TooManyVersions);
+        assert_eq!(resp.status(), StatusCode::BadRequest);
+        assert_eq!(
+            body_to_string(resp.body()),
+            json_fault_message("At most 16 versions of the MMDS metadata can be stored.")
+        );
+        let resp = mmds_version_error_response(MmdsError::InvalidVersion);
+        assert_eq!(resp.status(), StatusCode::BadRequest);
+    }
+
+    #[test]
+    fn test_mmds_limit_exceeded_response() {
+        let resp = mmds_limit_exceeded_response(51200);
+        assert_eq!(resp.status(), StatusCode::PayloadTooLarge);
+        assert_eq!(
+            body_to_string(resp.body()),
+            json_fault_message("The MMDS data store is limited to 51200 bytes.")
+        );
+    }
+
+    #[test]
+    fn test_body_limit() {
+        let mut mmds = Mmds::default();
+        mmds.set_data_store_limit(1024);
+        let mmds_info = Mutex::new(mmds);
+
+        // Only the bodies of the MMDS updates are limited.
+        assert_eq!(body_limit(&Method::Put, "/mmds", &mmds_info), 1024);
+        assert_eq!(body_limit(&Method::Patch, "/mmds", &mmds_info), 1024);
+        assert_eq!(
+            body_limit(&Method::Put, "/mmds/user-data", &mmds_info),
+            1024
+        );
+        assert_eq!(
+            body_limit(&Method::Get, "/mmds", &mmds_info),
+            usize::max_value()
+        );
+        assert_eq!(
+            body_limit(&Method::Put, "/mmdsfoo", &mmds_info),
+            usize::max_value()
+        );
+        assert_eq!(
+            body_limit(&Method::Put, "/drives/rootfs", &mmds_info),
+            usize::max_value()
+        );
+    }
+
     #[test]
     fn test_error_to_response() {
         let json_err_key = "fault_message";
@@ -917,6 +1230,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_cpu_template_req() {
+        let cpu_template_path = "/cpu-template";
+        let cpu_template_json = r#"{
+                "cpuid_modifiers": [
+                    {
+                        "leaf": "0x1",
+                        "ecx": "0bxxxxxxxxxxxxxxxxxxxxxxxxxxx0xxxx"
+                    }
+                ],
+                "msr_modifiers": []
+              }"#;
+        let body: Chunk = Chunk::from(cpu_template_json);
+
+        // PUT
+        let cpu_template = serde_json::from_slice::<CustomCpuTemplate>(&body).unwrap();
+        match parse_cpu_template_req(cpu_template_path, Method::Put, &body) {
+            Ok(pr) => {
+                let (sender, receiver) = oneshot::channel();
+                assert!(pr.eq(&ParsedRequest::Sync(
+                    VmmAction::SetCpuTemplate(cpu_template, sender),
+                    receiver,
+                )));
+            }
+            _ => assert!(false),
+        }
+
+        // Error cases
+        // Test case for invalid path.
+        let dummy_path = "/cpu-template/dummy";
+        let expected_err = Error::InvalidPathMethod(dummy_path, Method::Put);
+        assert!(parse_cpu_template_req(dummy_path, Method::Put, &body) == Err(expected_err));
+
+        // Test case for invalid method (GET).
+        let expected_err = Error::InvalidPathMethod(cpu_template_path, Method::Get);
+        assert!(
+            parse_cpu_template_req(cpu_template_path, Method::Get, &Chunk::from("{}"))
+                == Err(expected_err)
+        );
+
+        // Test case for invalid body (serde error).
+        assert!(
+            parse_cpu_template_req(cpu_template_path, Method::Put, &Chunk::from("foo"))
+                == Err(Error::SerdeJson(get_dummy_serde_error()))
+        );
+    }
+
     #[test]
     fn test_parse_drives_req() {
         let valid_drive_path = "/drives/id_1";
@@ -1102,6 +1462,15 @@ mod tests {
             mem_size_mib: Some(1025),
             ht_enabled: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
         };
 
         match vm_config.into_parsed_request(None, Method::Put) {
@@ -1127,6 +1496,58 @@ mod tests {
         assert!(parse_machine_config_req(path, Method::Put, &Chunk::from("{}")) == expected_err);
     }
 
+    #[test]
+    fn test_parse_devices_req() {
+        let path = "/devices/rootfs/features";
+
+        // GET
+        let (sender, receiver) = oneshot::channel();
+        let parsed_req = ParsedRequest::Sync(
+            VmmAction::GetDeviceFeatures(String::from("rootfs"), sender),
+            receiver,
+        );
+        assert!(parse_devices_req(path, Method::Get) == Ok(parsed_req));
+
+        // Error Cases
+        // Error Case: The device ID is missing.
+        assert!(parse_devices_req("/devices", Method::Get) == Err(Error::EmptyID));
+
+        // Error Case: Invalid Path.
+        for path in vec![
+            "/devices/rootfs",
+            "/devices/rootfs/foo",
+            "/devices/rootfs/features/foo",
+        ] {
+            let expected_err = Err(Error::InvalidPathMethod(path, Method::Get));
+            assert!(parse_devices_req(path, Method::Get) == expected_err);
+        }
+
+        // Error Case: Invalid Method.
+        let expected_err = Err(Error::InvalidPathMethod(path, Method::Put));
+        assert!(parse_devices_req(path, Method::Put) == expected_err);
+    }
+
+    #[test]
+    fn test_parse_events_req() {
+        let path = "/events";
+
+        // GET
+        let (sender, receiver) = oneshot::channel();
+        let parsed_req = ParsedRequest::Sync(VmmAction::GetGuestEvents(sender), receiver);
+        assert!(parse_events_req(path, Method::Get) == Ok(parsed_req));
+
+        // Error Cases
+        // Error Case: Invalid Path.
+        let path = "/events/foo";
+        let expected_err = Err(Error::InvalidPathMethod(path, Method::Get));
+        assert!(parse_events_req(path, Method::Get) == expected_err);
+
+        // Error Case: Invalid Method.
+        let path = "/events";
+        let expected_err = Err(Error::InvalidPathMethod(path, Method::Put));
+        assert!(parse_events_req(path, Method::Put) == expected_err);
+    }
+
     #[test]
     fn test_parse_netif_req() {
         let path = "/network-interfaces/id_1";
@@ -1145,6 +1566,7 @@ mod tests {
             guest_mac: Some(MacAddr::parse_str("12:34:56:78:9a:BC").unwrap()),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            rx_ebpf_filter: None,
             allow_mmds_requests: false,
             tap: None,
         };
@@ -1173,10 +1595,41 @@ mod tests {
                 == Err(Error::SerdeJson(get_dummy_serde_error()))
         );
 
+        // PATCH
+        let path = "/network-interfaces/id_1";
+        let json = "{
+                \"iface_id\": \"id_1\",
+                \"capture\": {
+                    \"path_on_host\": \"/capture.pcap\",
+                    \"snap_len\": 128
+                }
+              }";
+        let netif_update = NetworkInterfaceUpdateConfig {
+            iface_id: String::from("id_1"),
+            capture: Some(PacketCaptureConfig {
+                path_on_host: PathBuf::from("/capture.pcap"),
+                snap_len: 128,
+                max_bytes: None,
+            }),
+        };
+        match netif_update.into_parsed_request(Some(String::from("id_1")), Method::Patch) {
+            Ok(pr) => match parse_netif_req(&path, Method::Patch, &Chunk::from(json)) {
+                Ok(pr_netif) => assert!(pr.eq(&pr_netif)),
+                _ => assert!(false),
+            },
+            _ => assert!(false),
+        }
+
+        // Error Case: The PATCH payload can't update the configuration of the interface.
+        assert!(
+            parse_netif_req(path, Method::Patch, &body)
+                == Err(Error::SerdeJson(get_dummy_serde_error()))
+        );
+
         // Error Case: Invalid Path.
         assert!(
-            parse_netif_req(path, Method::Patch, &body,)
-                == Err(Error::InvalidPathMethod(path, Method::Patch))
+            parse_netif_req(path, Method::Get, &body,)
+                == Err(Error::InvalidPathMethod(path, Method::Get))
         )
     }
 
@@ -1236,12 +1689,65 @@ mod tests {
                 == Err(Error::SerdeJson(get_dummy_serde_error()))
         );
 
+        // Test for the user data, which can be any bytes.
+        let body = Chunk::from(b"#cloud-config\n\x1f\x8b".to_vec());
+        match parse_mmds_request("/mmds/user-data", Method::Put, &body) {
+            Ok(parsed_req) => assert!(parsed_req.eq(&ParsedRequest::PutMMDSUserData(
+                b"#cloud-config\n\x1f\x8b".to_vec()
+            ))),
+            Err(_) => assert!(false),
+        };
+        let path = "/mmds/user-data";
+        let expected_err = Err(Error::InvalidPathMethod(path, Method::Get));
+        assert!(parse_mmds_request(path, Method::Get, &body) == expected_err);
+
+        // Test for storing a version of the metadata.
+        match parse_mmds_request("/mmds/versions/2023-11-01", Method::Put, &Chunk::from("")) {
+            Ok(parsed_req) => {
+                assert!(parsed_req.eq(&ParsedRequest::PutMMDSVersion(String::from("2023-11-01"))))
+            }
+            Err(_) => assert!(false),
+        };
+        let path = "/mmds/versions/2023-11-01";
+        let expected_err = Err(Error::InvalidPathMethod(path, Method::Get));
+        assert!(parse_mmds_request(path, Method::Get, &body) == expected_err);
+
         // Test for invalid path
         let path = "/mmds/something";
         let expected_err = Err(Error::InvalidPathMethod(path, Method::Get));
         assert!(parse_mmds_request(path, Method::Get, &body) == expected_err);
     }
 
+    #[test]
+    fn test_endpoint() {
+        assert_eq!(endpoint(&Method::Get, "/"), "GET /");
+        assert_eq!(endpoint(&Method::Put, "/actions"), "PUT /actions");
+        assert_eq!(endpoint(&Method::Put, "/drives/rootfs"), "PUT /drives/{id}");
+        assert_eq!(
+            endpoint(&Method::Patch, "/drives/rootfs/"),
+            "PATCH /drives/{id}"
+        );
+        assert_eq!(
+            endpoint(&Method::Get, "/devices/eth0/features"),
+            "GET /devices/{id}/features"
+        );
+        assert_eq!(endpoint(&Method::Get, "/events"), "GET /events");
+        assert_eq!(
+            endpoint(&Method::Put, "/mmds/user-data"),
+            "PUT /mmds/user-data"
+        );
+        assert_eq!(
+            endpoint(&Method::Put, "/mmds/versions/2023-11-01"),
+            "PUT /mmds/versions/{id}"
+        );
+        assert_eq!(endpoint(&Method::Get, "/drives/a/b"), "GET other");
+        assert_eq!(endpoint(&Method::Get, "/foo/bar"), "GET other");
+        assert_eq!(
+            endpoint(&Method::Extension(String::from("FOO")), "/mmds"),
+            "OTHER /mmds"
+        );
+    }
+
     #[test]
     fn test_parse_request() {
         let body: Chunk = Chunk::from("{ \"foo\": \"bar\" }");
@@ -1279,6 +1785,7 @@ mod tests {
         // Each request type is unit tested separately
         for path in vec![
             "/boot-source",
+            "/cpu-template",
             "/drives",
             "/machine-config",
             "/network-interfaces",
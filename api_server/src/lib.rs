@@ -21,7 +21,8 @@ extern crate vmm;
 mod http_service;
 pub mod request;
 
-use std::io;
+use std::cell::Cell;
+use std::io::{self, Write};
 use std::os::unix::io::FromRawFd;
 use std::path::Path;
 use std::rc::Rc;
@@ -33,7 +34,7 @@ use hyper::server::Http;
 use tokio_core::reactor::Core;
 use tokio_uds::UnixListener;
 
-use http_service::ApiServerHttpService;
+use http_service::{json_fault_message, ApiServerHttpService};
 use logger::{Metric, METRICS};
 use mmds::data_store::Mmds;
 use sys_util::EventFd;
@@ -54,6 +55,26 @@ pub enum UnixDomainSocket<P> {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+// The number of seconds after which clients rejected because of the connection limit are told to
+// try again.
+const RETRY_AFTER_SECS: u32 = 1;
+
+// The response sent on the connections accepted beyond the limit, right before closing them.
+fn too_many_connections_response() -> Vec<u8> {
+    let body = json_fault_message("Too many open API connections.");
+    format!(
+        "HTTP/1.1 503 Service Unavailable\r\n\
+         Retry-After: {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        RETRY_AFTER_SECS,
+        body.len(),
+        body
+    )
+    .into_bytes()
+}
+
 pub struct ApiServer {
     // MMDS info directly accessible from the API thread.
     mmds_info: Arc<Mutex<Mmds>>,
@@ -62,6 +83,9 @@ pub struct ApiServer {
     // Sender which allows passing messages to the VMM.
     api_request_sender: Rc<mpsc::Sender<Box<VmmAction>>>,
     efd: Rc<EventFd>,
+    // The maximum number of connections open at the same time. The event loop is single
+    // threaded, so clients which leak connections would otherwise slow down everyone else.
+    max_connections: usize,
 }
 
 impl ApiServer {
@@ -69,12 +93,14 @@ impl ApiServer {
         mmds_info: Arc<Mutex<Mmds>>,
         vmm_shared_info: Arc<RwLock<InstanceInfo>>,
         api_request_sender: mpsc::Sender<Box<VmmAction>>,
+        max_connections: usize,
     ) -> Result<Self> {
         Ok(ApiServer {
             mmds_info,
             vmm_shared_info,
             api_request_sender: Rc::new(api_request_sender),
             efd: Rc::new(EventFd::new().map_err(Error::Eventfd)?),
+            max_connections,
         })
     }
 
@@ -119,10 +145,21 @@ impl ApiServer {
         }
 
         let http: Http<hyper::Chunk> = Http::new();
+        // The connections run on the same thread as the event loop, so a `Cell` is enough.
+        let open_connections = Rc::new(Cell::new(0usize));
 
         let f = listener
             .incoming()
-            .for_each(|(stream, _)| {
+            .for_each(|(mut stream, _)| {
+                if open_connections.get() >= self.max_connections {
+                    METRICS.api_server.connections_rejected_count.inc();
+                    // This is a best effort attempt, as the socket is non-blocking. It only fails
+                    // if the client already filled the buffer of a brand new socket.
+                    let _ = stream.write_all(&too_many_connections_response());
+                    return Ok(());
+                }
+                open_connections.set(open_connections.get() + 1);
+
                 // For the sake of clarity: when we use self.efd.clone(), the intent is to
                 // clone the wrapping Rc, not the EventFd itself.
                 let service = ApiServerHttpService::new(
@@ -134,7 +171,11 @@ impl ApiServer {
                 let connection = http.serve_connection(stream, service);
                 // todo: is spawn() any better/worse than execute()?
                 // We have to adjust the future item and error, to fit spawn()'s definition.
-                handle.spawn(connection.map(|_| ()).map_err(|_| ()));
+                let open_connections = open_connections.clone();
+                handle.spawn(connection.then(move |_| {
+                    open_connections.set(open_connections.get() - 1);
+                    Ok(())
+                }));
                 Ok(())
             })
             .map_err(Error::Io);
@@ -160,3 +201,21 @@ impl ApiServer {
         self.efd.try_clone().map_err(Error::Eventfd)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_too_many_connections_response() {
+        let response = String::from_utf8(too_many_connections_response()).unwrap();
+        let (head, body) = response.split_at(response.find("\r\n\r\n").unwrap() + 4);
+        assert!(head.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(head.contains("Retry-After: 1\r\n"));
+        assert!(head.contains("Connection: close\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["fault_message"], "Too many open API connections.");
+    }
+}
@@ -8,6 +8,7 @@ use hyper::Method;
 use serde_json::Value;
 
 use request::{IntoParsedRequest, ParsedRequest};
+use vmm::vmm_config::serial::SerialInput;
 use vmm::VmmAction;
 
 // The names of the members from this enum must precisely correspond (as a string) to the possible
@@ -17,6 +18,9 @@ use vmm::VmmAction;
 enum ActionType {
     BlockDeviceRescan,
     InstanceStart,
+    ResetMetrics,
+    SendSerialInput,
+    SendSysRq,
 }
 
 // The model of the json body from a sync request. We use Serde to transform each associated
@@ -53,6 +57,34 @@ fn validate_payload(action_body: &ActionBody) -> Result<(), String> {
             }
             Ok(())
         }
+        ActionType::ResetMetrics => match action_body.payload {
+            // Without a payload, all the metrics are reset.
+            None => Ok(()),
+            Some(Value::Array(ref groups)) if groups.iter().all(Value::is_string) => Ok(()),
+            _ => Err(
+                "Invalid payload type. Expected an array of strings representing the metrics \
+                 groups."
+                    .to_string(),
+            ),
+        },
+        ActionType::SendSerialInput => match action_body.payload {
+            Some(Value::String(_)) => Ok(()),
+            _ => Err(
+                "Invalid payload type. Expected a string representing the serial input."
+                    .to_string(),
+            ),
+        },
+        ActionType::SendSysRq => match action_body.payload {
+            // The Linux SysRq keys are all printable ASCII characters.
+            Some(Value::String(ref key))
+                if key.len() == 1 && key.as_bytes()[0].is_ascii_graphic() =>
+            {
+                Ok(())
+            }
+            _ => Err(
+                "Invalid payload type. Expected a string representing the SysRq key.".to_string(),
+            ),
+        },
     }
 }
 
@@ -80,6 +112,35 @@ impl IntoParsedRequest for ActionBody {
                     sync_receiver,
                 ))
             }
+            ActionType::ResetMetrics => {
+                // Safe to unwrap because we validated the payload in the validate_payload func.
+                let groups = match self.payload {
+                    Some(Value::Array(groups)) => groups
+                        .iter()
+                        .map(|group| group.as_str().unwrap().to_string())
+                        .collect(),
+                    _ => vec![],
+                };
+                let (sync_sender, sync_receiver) = oneshot::channel();
+                Ok(ParsedRequest::Sync(
+                    VmmAction::ResetMetrics(groups, sync_sender),
+                    sync_receiver,
+                ))
+            }
+            ActionType::SendSerialInput | ActionType::SendSysRq => {
+                // Safe to unwrap because we validated the payload in the validate_payload func.
+                let payload = self.payload.unwrap().as_str().unwrap().as_bytes().to_vec();
+                let serial_input = if self.action_type == ActionType::SendSysRq {
+                    SerialInput::SysRq(payload[0])
+                } else {
+                    SerialInput::Bytes(payload)
+                };
+                let (sync_sender, sync_receiver) = oneshot::channel();
+                Ok(ParsedRequest::Sync(
+                    VmmAction::SendSerialInput(serial_input, sync_sender),
+                    sync_receiver,
+                ))
+            }
         }
     }
 }
@@ -122,6 +183,61 @@ mod tests {
             payload: Some(Value::Bool(false)),
         };
         assert!(validate_payload(&action_body).is_err());
+
+        // Test ResetMetrics.
+        let action_body = ActionBody {
+            action_type: ActionType::ResetMetrics,
+            payload: None,
+        };
+        assert!(validate_payload(&action_body).is_ok());
+        let action_body = ActionBody {
+            action_type: ActionType::ResetMetrics,
+            payload: Some(Value::Array(vec![
+                Value::String(String::from("net")),
+                Value::String(String::from("block")),
+            ])),
+        };
+        assert!(validate_payload(&action_body).is_ok());
+        // Error case: payload is not an array of strings.
+        let payloads = [
+            Value::String(String::from("net")),
+            Value::Array(vec![Value::String(String::from("net")), Value::Bool(false)]),
+        ];
+        for payload in payloads.iter() {
+            let action_body = ActionBody {
+                action_type: ActionType::ResetMetrics,
+                payload: Some(payload.clone()),
+            };
+            assert!(validate_payload(&action_body).is_err());
+        }
+
+        // Test SendSerialInput.
+        let action_body = ActionBody {
+            action_type: ActionType::SendSerialInput,
+            payload: Some(Value::String(String::from("root\n"))),
+        };
+        assert!(validate_payload(&action_body).is_ok());
+        // Error case: no payload.
+        let action_body = ActionBody {
+            action_type: ActionType::SendSerialInput,
+            payload: None,
+        };
+        assert!(validate_payload(&action_body).is_err());
+
+        // Test SendSysRq.
+        let action_body = ActionBody {
+            action_type: ActionType::SendSysRq,
+            payload: Some(Value::String(String::from("c"))),
+        };
+        assert!(validate_payload(&action_body).is_ok());
+        // Error case: more than one key, or not a printable one.
+        for key in ["cb", "", " ", "\u{e9}"].iter() {
+            let action_body = ActionBody {
+                action_type: ActionType::SendSysRq,
+                payload: Some(Value::String(String::from(*key))),
+            };
+            assert!(validate_payload(&action_body).is_err());
+        }
     }
 
     #[test]
@@ -161,5 +277,62 @@ mod tests {
                 .unwrap()
                 .eq(&req));
         }
+
+        {
+            let json = r#"{
+                "action_type": "ResetMetrics",
+                "payload": ["net", "vcpu"]
+              }"#;
+            let (sender, receiver) = oneshot::channel();
+            let req = ParsedRequest::Sync(
+                VmmAction::ResetMetrics(vec!["net".to_string(), "vcpu".to_string()], sender),
+                receiver,
+            );
+
+            let result: Result<ActionBody, serde_json::Error> = serde_json::from_str(json);
+            assert!(result
+                .unwrap()
+                .into_parsed_request(None, Method::Put)
+                .unwrap()
+                .eq(&req));
+        }
+
+        {
+            let json = r#"{
+                "action_type": "SendSysRq",
+                "payload": "b"
+              }"#;
+            let (sender, receiver) = oneshot::channel();
+            let req = ParsedRequest::Sync(
+                VmmAction::SendSerialInput(SerialInput::SysRq(b'b'), sender),
+                receiver,
+            );
+
+            let result: Result<ActionBody, serde_json::Error> = serde_json::from_str(json);
+            assert!(result
+                .unwrap()
+                .into_parsed_request(None, Method::Put)
+                .unwrap()
+                .eq(&req));
+        }
+
+        {
+            let json = r#"{
+                "action_type": "SendSerialInput",
+                "payload": "ls\n"
+              }"#;
+            let (sender, receiver) = oneshot::channel();
+            let req = ParsedRequest::Sync(
+                VmmAction::SendSerialInput(SerialInput::Bytes(b"ls\n".to_vec()), sender),
+                receiver,
+            );
+
+            let result: Result<ActionBody, serde_json::Error> = serde_json::from_str(json);
+            assert!(result
+                .unwrap()
+                .into_parsed_request(None, Method::Put)
+                .unwrap()
+                .eq(&req));
+        }
     }
 }
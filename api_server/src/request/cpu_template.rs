@@ -0,0 +1,46 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::result;
+
+use futures::sync::oneshot;
+use hyper::Method;
+
+use request::{IntoParsedRequest, ParsedRequest};
+use vmm::vmm_config::cpu_template::CustomCpuTemplate;
+use vmm::VmmAction;
+
+impl IntoParsedRequest for CustomCpuTemplate {
+    fn into_parsed_request(
+        self,
+        _: Option<String>,
+        _: Method,
+    ) -> result::Result<ParsedRequest, String> {
+        let (sender, receiver) = oneshot::channel();
+        Ok(ParsedRequest::Sync(
+            VmmAction::SetCpuTemplate(self, sender),
+            receiver,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_parsed_request() {
+        let body = CustomCpuTemplate {
+            cpuid_modifiers: vec![],
+            msr_modifiers: vec![],
+        };
+        let (sender, receiver) = oneshot::channel();
+        assert!(body
+            .clone()
+            .into_parsed_request(None, Method::Put)
+            .eq(&Ok(ParsedRequest::Sync(
+                VmmAction::SetCpuTemplate(body, sender),
+                receiver
+            ))))
+    }
+}
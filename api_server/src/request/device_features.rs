@@ -0,0 +1,40 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use hyper::{Response, StatusCode};
+use serde_json;
+
+use http_service::{json_fault_message, json_response};
+use request::GenerateHyperResponse;
+use vmm::vmm_config::device_features::DeviceFeatures;
+
+impl GenerateHyperResponse for DeviceFeatures {
+    fn generate_response(&self) -> Response {
+        match serde_json::to_string(self) {
+            Ok(body) => json_response(StatusCode::Ok, body),
+            Err(e) => json_response(
+                StatusCode::InternalServerError,
+                json_fault_message(e.to_string()),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm::vmm_config::device_features::QueueFeatures;
+
+    #[test]
+    fn test_generate_response() {
+        let features = DeviceFeatures {
+            offered_features: 0x1_0000_0020,
+            acked_features: 0x20,
+            queues: vec![QueueFeatures {
+                max_size: 256,
+                size: 128,
+            }],
+        };
+        assert_eq!(features.generate_response().status(), StatusCode::Ok);
+    }
+}
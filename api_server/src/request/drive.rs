@@ -5,9 +5,9 @@ use std::result;
 
 use futures::sync::oneshot;
 use hyper::Method;
-use serde_json::{Map, Value};
+use serde_json::{self, Map, Value};
 
-use vmm::vmm_config::drive::BlockDeviceConfig;
+use vmm::vmm_config::drive::{BlockDeviceConfig, BlockDeviceTraceConfig};
 use vmm::VmmAction;
 
 use request::{IntoParsedRequest, ParsedRequest};
@@ -40,19 +40,24 @@ impl PatchDrivePayload {
         Ok(())
     }
 
-    /// Validates that only path_on_host and drive_id are present in the payload.
+    /// Validates that only drive_id and either path_on_host or io_trace are present in the
+    /// payload.
     fn validate(&self) -> result::Result<(), String> {
         match self.fields.as_object() {
             Some(fields_map) => {
                 // Check that field `drive_id` exists and its type is String.
                 PatchDrivePayload::check_field_is_string(fields_map, "drive_id")?;
-                // Check that field `drive_id` exists and its type is String.
-                PatchDrivePayload::check_field_is_string(fields_map, "path_on_host")?;
+                // The type of the field `io_trace` is checked when it is deserialized.
+                if !fields_map.contains_key("io_trace") {
+                    // Check that field `path_on_host` exists and its type is String.
+                    PatchDrivePayload::check_field_is_string(fields_map, "path_on_host")?;
+                }
 
                 // Check that there are no other fields in the object.
                 if fields_map.len() > 2 {
                     return Err(
-                        "Invalid PATCH payload. Only updates on path_on_host are allowed."
+                        "Invalid PATCH payload. Only updates on path_on_host or io_trace are \
+                         allowed, one at a time."
                             .to_string(),
                     );
                 }
@@ -84,7 +89,6 @@ impl IntoParsedRequest for PatchDrivePayload {
             Method::Patch => {
                 self.validate()?;
                 let drive_id: String = self.get_string_field_unchecked("drive_id");
-                let path_on_host: String = self.get_string_field_unchecked("path_on_host");
 
                 let id_from_path = id_from_path.unwrap_or(String::new());
                 if id_from_path != drive_id {
@@ -94,10 +98,20 @@ impl IntoParsedRequest for PatchDrivePayload {
                 }
 
                 let (sender, receiver) = oneshot::channel();
-                Ok(ParsedRequest::Sync(
-                    VmmAction::UpdateBlockDevicePath(drive_id, path_on_host, sender),
-                    receiver,
-                ))
+                let action = match self.fields.get("io_trace") {
+                    // A null io_trace stops the running trace.
+                    Some(io_trace) => {
+                        let trace_config: Option<BlockDeviceTraceConfig> =
+                            serde_json::from_value(io_trace.clone())
+                                .map_err(|e| format!("Invalid io_trace: {}", e))?;
+                        VmmAction::UpdateBlockDeviceTrace(drive_id, trace_config, sender)
+                    }
+                    None => {
+                        let path_on_host: String = self.get_string_field_unchecked("path_on_host");
+                        VmmAction::UpdateBlockDevicePath(drive_id, path_on_host, sender)
+                    }
+                };
+                Ok(ParsedRequest::Sync(action, receiver))
             }
             _ => Err(format!("Invalid method {}!", method)),
         }
@@ -209,10 +223,40 @@ mod tests {
         let patch_payload = PatchDrivePayload {
             fields: Value::Object(payload_map),
         };
-        let expected_err =
-            Err("Invalid PATCH payload. Only updates on path_on_host are allowed.".to_string());
+        let expected_err = Err(
+            "Invalid PATCH payload. Only updates on path_on_host or io_trace are allowed, one at \
+             a time."
+                .to_string(),
+        );
         assert!(patch_payload.into_parsed_request(None, Method::Patch) == expected_err);
 
+        // PATCH that tries to update both path_on_host and io_trace.
+        let mut payload_map = Map::new();
+        payload_map.insert(
+            String::from("drive_id"),
+            Value::String(String::from("1234")),
+        );
+        payload_map.insert(
+            String::from("path_on_host"),
+            Value::String(String::from("dummy")),
+        );
+        payload_map.insert(String::from("io_trace"), Value::Null);
+        let patch_payload = PatchDrivePayload {
+            fields: Value::Object(payload_map),
+        };
+        assert!(patch_payload.into_parsed_request(None, Method::Patch) == expected_err);
+
+        // PATCH with an io_trace which is not a trace configuration.
+        let mut payload_map = Map::new();
+        payload_map.insert(String::from("drive_id"), Value::String(String::from("foo")));
+        payload_map.insert(String::from("io_trace"), Value::Bool(true));
+        let patch_payload = PatchDrivePayload {
+            fields: Value::Object(payload_map),
+        };
+        assert!(patch_payload
+            .into_parsed_request(Some("foo".to_string()), Method::Patch)
+            .is_err());
+
         // PATCH with payload that is not a json.
         let patch_payload = PatchDrivePayload {
             fields: Value::String(String::from("dummy_payload")),
@@ -244,6 +288,43 @@ mod tests {
         assert!(
             pdp.into_parsed_request(None, Method::Put) == Err(String::from("Invalid method PUT!"))
         );
+
+        // PATCH that starts, then stops an I/O trace.
+        let mut trace_map = Map::<String, Value>::new();
+        trace_map.insert(
+            String::from("path_on_host"),
+            Value::String(String::from("trace.jsonl")),
+        );
+        let mut payload_map = Map::<String, Value>::new();
+        payload_map.insert(String::from("drive_id"), Value::String(String::from("foo")));
+        payload_map.insert(String::from("io_trace"), Value::Object(trace_map));
+        let pdp = PatchDrivePayload {
+            fields: Value::Object(payload_map),
+        };
+        let trace_config = BlockDeviceTraceConfig {
+            path_on_host: PathBuf::from("trace.jsonl"),
+        };
+        let (sender, receiver) = oneshot::channel();
+        assert!(pdp
+            .into_parsed_request(Some("foo".to_string()), Method::Patch)
+            .eq(&Ok(ParsedRequest::Sync(
+                VmmAction::UpdateBlockDeviceTrace("foo".to_string(), Some(trace_config), sender),
+                receiver
+            ))));
+
+        let mut payload_map = Map::<String, Value>::new();
+        payload_map.insert(String::from("drive_id"), Value::String(String::from("foo")));
+        payload_map.insert(String::from("io_trace"), Value::Null);
+        let pdp = PatchDrivePayload {
+            fields: Value::Object(payload_map),
+        };
+        let (sender, receiver) = oneshot::channel();
+        assert!(pdp
+            .into_parsed_request(Some("foo".to_string()), Method::Patch)
+            .eq(&Ok(ParsedRequest::Sync(
+                VmmAction::UpdateBlockDeviceTrace("foo".to_string(), None, sender),
+                receiver
+            ))));
     }
 
     #[test]
@@ -0,0 +1,40 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use hyper::{Response, StatusCode};
+use serde_json;
+
+use http_service::{json_fault_message, json_response};
+use request::GenerateHyperResponse;
+use vmm::vmm_config::guest_events::GuestEvent;
+
+impl GenerateHyperResponse for Vec<GuestEvent> {
+    fn generate_response(&self) -> Response {
+        match serde_json::to_string(self) {
+            Ok(body) => json_response(StatusCode::Ok, body),
+            Err(e) => json_response(
+                StatusCode::InternalServerError,
+                json_fault_message(e.to_string()),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm::vmm_config::guest_events::GuestEventKind;
+
+    #[test]
+    fn test_generate_response() {
+        let events = vec![GuestEvent {
+            kind: GuestEventKind::Panic,
+            timestamp_us: 1_500_000,
+        }];
+        assert_eq!(events.generate_response().status(), StatusCode::Ok);
+        assert_eq!(
+            Vec::<GuestEvent>::new().generate_response().status(),
+            StatusCode::Ok
+        );
+    }
+}
@@ -20,13 +20,44 @@ impl GenerateHyperResponse for VmConfig {
             .cpu_template
             .map_or("Uninitialized".to_string(), |c| c.to_string());
 
-        json_response(
-            StatusCode::Ok,
-            format!(
-                "{{ \"vcpu_count\": {:?}, \"mem_size_mib\": {:?},  \"ht_enabled\": {:?},  \"cpu_template\": {:?} }}",
-                vcpu_count, mem_size, ht_enabled, cpu_template
-            ),
-        )
+        let mut body = format!(
+            "{{ \"vcpu_count\": {:?}, \"mem_size_mib\": {:?},  \"ht_enabled\": {:?},  \"cpu_template\": {:?}",
+            vcpu_count, mem_size, ht_enabled, cpu_template
+        );
+        // The memory options are only reported when they were set.
+        if let Some(thp_policy) = self.thp_policy {
+            body.push_str(&format!(", \"thp_policy\": {:?}", thp_policy.to_string()));
+        }
+        if let Some(dontfork) = self.dontfork {
+            body.push_str(&format!(", \"dontfork\": {:?}", dontfork));
+        }
+        if let Some(prefault) = self.prefault {
+            body.push_str(&format!(", \"prefault\": {:?}", prefault));
+        }
+        if let Some(watchdog_action) = self.watchdog_action {
+            body.push_str(&format!(
+                ", \"watchdog_action\": {:?}",
+                watchdog_action.to_string()
+            ));
+        }
+        if let Some(vpmu) = self.vpmu {
+            body.push_str(&format!(", \"vpmu\": {:?}", vpmu));
+        }
+        if let Some(mergeable) = self.mergeable {
+            body.push_str(&format!(", \"mergeable\": {:?}", mergeable));
+        }
+        if let Some(mmio_gap_size_mib) = self.mmio_gap_size_mib {
+            body.push_str(&format!(", \"mmio_gap_size_mib\": {}", mmio_gap_size_mib));
+        }
+        if let Some(mmio_base) = self.mmio_base {
+            body.push_str(&format!(", \"mmio_base\": {}", mmio_base));
+        }
+        if let Some(acpi) = self.acpi {
+            body.push_str(&format!(", \"acpi\": {:?}", acpi));
+        }
+        body.push_str(" }");
+
+        json_response(StatusCode::Ok, body)
     }
 }
 
@@ -47,6 +78,15 @@ impl IntoParsedRequest for VmConfig {
                     && self.mem_size_mib.is_none()
                     && self.cpu_template.is_none()
                     && self.ht_enabled.is_none()
+                    && self.thp_policy.is_none()
+                    && self.dontfork.is_none()
+                    && self.prefault.is_none()
+                    && self.watchdog_action.is_none()
+                    && self.vpmu.is_none()
+                    && self.mergeable.is_none()
+                    && self.mmio_gap_size_mib.is_none()
+                    && self.mmio_base.is_none()
+                    && self.acpi.is_none()
                 {
                     return Err(String::from("Empty request."));
                 }
@@ -63,7 +103,7 @@ impl IntoParsedRequest for VmConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use vmm::vmm_config::machine_config::CpuFeaturesTemplate;
+    use vmm::vmm_config::machine_config::{CpuFeaturesTemplate, ThpPolicy, WatchdogAction};
 
     #[test]
     fn test_into_parsed_request() {
@@ -72,6 +112,15 @@ mod tests {
             mem_size_mib: Some(1024),
             ht_enabled: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
         };
         let (sender, receiver) = oneshot::channel();
         assert!(body
@@ -86,6 +135,15 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: None,
             cpu_template: None,
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
         };
         assert!(uninitialized
             .clone()
@@ -96,9 +154,39 @@ mod tests {
             .into_parsed_request(None, Method::Patch)
             .is_err());
 
-        match uninitialized.into_parsed_request(None, Method::Put) {
+        match uninitialized.clone().into_parsed_request(None, Method::Put) {
             Ok(_) => assert!(false),
             Err(e) => assert_eq!(e, String::from("Empty request.")),
         };
+
+        let memory_options = VmConfig {
+            thp_policy: Some(ThpPolicy::Hugepage),
+            dontfork: Some(true),
+            prefault: Some(true),
+            mergeable: Some(true),
+            ..uninitialized.clone()
+        };
+        assert!(memory_options
+            .into_parsed_request(None, Method::Put)
+            .is_ok());
+
+        let memory_layout = VmConfig {
+            mmio_gap_size_mib: Some(1024),
+            mmio_base: Some(0xc000_0000),
+            ..uninitialized.clone()
+        };
+        assert!(memory_layout.into_parsed_request(None, Method::Put).is_ok());
+
+        let watchdog = VmConfig {
+            watchdog_action: Some(WatchdogAction::Log),
+            ..uninitialized.clone()
+        };
+        assert!(watchdog.into_parsed_request(None, Method::Put).is_ok());
+
+        let vpmu = VmConfig {
+            vpmu: Some(true),
+            ..uninitialized
+        };
+        assert!(vpmu.into_parsed_request(None, Method::Put).is_ok());
     }
 }
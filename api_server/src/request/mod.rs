@@ -3,7 +3,10 @@
 
 pub mod actions;
 pub mod boot_source;
+pub mod cpu_template;
+pub mod device_features;
 pub mod drive;
+pub mod guest_events;
 pub mod logger;
 pub mod machine_configuration;
 pub mod net;
@@ -24,6 +27,8 @@ pub enum ParsedRequest {
     GetMMDS,
     PatchMMDS(Value),
     PutMMDS(Value),
+    PutMMDSUserData(Vec<u8>),
+    PutMMDSVersion(String),
     Sync(VmmAction, OutcomeReceiver),
 }
 
@@ -56,6 +61,8 @@ impl GenerateHyperResponse for VmmData {
     fn generate_response(&self) -> hyper::Response {
         match *self {
             VmmData::MachineConfiguration(ref machine_config) => machine_config.generate_response(),
+            VmmData::DeviceFeatures(ref features) => features.generate_response(),
+            VmmData::GuestEvents(ref events) => events.generate_response(),
             VmmData::Empty => empty_response(StatusCode::NoContent),
         }
     }
@@ -90,6 +97,14 @@ impl PartialEq for ParsedRequest {
             (&ParsedRequest::PatchMMDS(ref val), &ParsedRequest::PatchMMDS(ref other_val)) => {
                 val == other_val
             }
+            (
+                &ParsedRequest::PutMMDSUserData(ref user_data),
+                &ParsedRequest::PutMMDSUserData(ref other_user_data),
+            ) => user_data == other_user_data,
+            (
+                &ParsedRequest::PutMMDSVersion(ref version),
+                &ParsedRequest::PutMMDSVersion(ref other_version),
+            ) => version == other_version,
             _ => false,
         }
     }
@@ -110,10 +125,12 @@ mod tests {
 
     use sys_util;
     use vmm::vmm_config::boot_source::BootSourceConfigError;
+    use vmm::vmm_config::device_features::{DeviceFeatures, DeviceFeaturesError, QueueFeatures};
     use vmm::vmm_config::drive::DriveError;
+    use vmm::vmm_config::guest_events::{GuestEvent, GuestEventKind};
     use vmm::vmm_config::instance_info::StartMicrovmError;
     use vmm::vmm_config::logger::LoggerConfigError;
-    use vmm::vmm_config::machine_config::{VmConfig, VmConfigError};
+    use vmm::vmm_config::machine_config::{ThpPolicy, VmConfig, VmConfigError, WatchdogAction};
     use vmm::vmm_config::net::NetworkInterfaceError;
 
     use futures::{Future, Stream};
@@ -167,6 +184,70 @@ mod tests {
         let vm_config_json: serde_json::Value = serde_json::from_str(vm_config_json).unwrap();
         assert_eq!(get_body(hyper_resp).unwrap(), vm_config_json);
 
+        // The memory, watchdog, vPMU, layout and ACPI options are only part of the response when
+        // they are set.
+        let vm_config = VmConfig {
+            thp_policy: Some(ThpPolicy::NoHugepage),
+            dontfork: Some(true),
+            prefault: Some(false),
+            watchdog_action: Some(WatchdogAction::Reset),
+            vpmu: Some(true),
+            mergeable: Some(false),
+            mmio_gap_size_mib: Some(1024),
+            mmio_base: Some(0xc000_1000),
+            acpi: Some(true),
+            ..Default::default()
+        };
+        let hyper_resp = Ok(VmmData::MachineConfiguration(vm_config)).generate_response();
+        assert_eq!(hyper_resp.status(), StatusCode::Ok);
+        let vm_config_json = r#"{
+            "vcpu_count": 1,
+            "mem_size_mib": 128,
+            "ht_enabled": false,
+            "cpu_template": "Uninitialized",
+            "thp_policy": "NoHugepage",
+            "dontfork": true,
+            "prefault": false,
+            "watchdog_action": "Reset",
+            "vpmu": true,
+            "mergeable": false,
+            "mmio_gap_size_mib": 1024,
+            "mmio_base": 3221229568,
+            "acpi": true
+        }"#;
+        let vm_config_json: serde_json::Value = serde_json::from_str(vm_config_json).unwrap();
+        assert_eq!(get_body(hyper_resp).unwrap(), vm_config_json);
+
+        // Test OK response from VMM that contains the features of a device.
+        let features = DeviceFeatures {
+            offered_features: 0x1_0000_0020,
+            acked_features: 0x20,
+            queues: vec![QueueFeatures {
+                max_size: 256,
+                size: 0,
+            }],
+        };
+        let hyper_resp = Ok(VmmData::DeviceFeatures(features)).generate_response();
+        assert_eq!(hyper_resp.status(), StatusCode::Ok);
+        let features_json = r#"{
+            "offered_features": 4294967328,
+            "acked_features": 32,
+            "queues": [{ "max_size": 256, "size": 0 }]
+        }"#;
+        let features_json: serde_json::Value = serde_json::from_str(features_json).unwrap();
+        assert_eq!(get_body(hyper_resp).unwrap(), features_json);
+
+        // Test OK response from VMM that contains the events reported by the guest.
+        let events = vec![GuestEvent {
+            kind: GuestEventKind::TripleFault,
+            timestamp_us: 1_500_000,
+        }];
+        let hyper_resp = Ok(VmmData::GuestEvents(events)).generate_response();
+        assert_eq!(hyper_resp.status(), StatusCode::Ok);
+        let events_json = r#"[{ "kind": "TripleFault", "timestamp_us": 1500000 }]"#;
+        let events_json: serde_json::Value = serde_json::from_str(events_json).unwrap();
+        assert_eq!(get_body(hyper_resp).unwrap(), events_json);
+
         // Tests Error Cases
         // Tests for BootSource Errors.
         let vmm_resp =
@@ -215,6 +296,16 @@ mod tests {
         );
         check_error_response(vmm_resp, StatusCode::BadRequest);
 
+        // Tests for DeviceFeatures Errors.
+        let vmm_resp = VmmActionError::DeviceFeatures(
+            ErrorKind::User,
+            DeviceFeaturesError::DeviceNotFound(String::from("rootfs")),
+        );
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+        let vmm_resp =
+            VmmActionError::DeviceFeatures(ErrorKind::User, DeviceFeaturesError::MicroVMNotStarted);
+        check_error_response(vmm_resp, StatusCode::BadRequest);
+
         // Tests for MachineConfig Errors.
         let vmm_resp =
             VmmActionError::MachineConfig(ErrorKind::User, VmConfigError::InvalidVcpuCount);
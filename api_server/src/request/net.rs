@@ -7,7 +7,7 @@ use futures::sync::oneshot;
 use hyper::Method;
 
 use request::{IntoParsedRequest, ParsedRequest};
-use vmm::vmm_config::net::NetworkInterfaceConfig;
+use vmm::vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceUpdateConfig};
 use vmm::VmmAction;
 
 impl IntoParsedRequest for NetworkInterfaceConfig {
@@ -31,6 +31,32 @@ impl IntoParsedRequest for NetworkInterfaceConfig {
     }
 }
 
+impl IntoParsedRequest for NetworkInterfaceUpdateConfig {
+    fn into_parsed_request(
+        self,
+        id_from_path: Option<String>,
+        method: Method,
+    ) -> result::Result<ParsedRequest, String> {
+        match method {
+            Method::Patch => {
+                let id_from_path = id_from_path.unwrap_or(String::new());
+                if id_from_path != self.iface_id {
+                    return Err(String::from(
+                        "The id from the path does not match the id from the body!",
+                    ));
+                }
+
+                let (sender, receiver) = oneshot::channel();
+                Ok(ParsedRequest::Sync(
+                    VmmAction::UpdateNetworkInterface(self, sender),
+                    receiver,
+                ))
+            }
+            _ => Err(format!("Invalid method {}!", method)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate net_util;
@@ -40,6 +66,7 @@ mod tests {
     use super::*;
 
     use serde_json;
+    use std::path::PathBuf;
 
     use self::rate_limiter::RateLimiter;
 
@@ -54,6 +81,7 @@ mod tests {
             guest_mac: Some(MacAddr::parse_str(mac).unwrap()),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            rx_ebpf_filter: None,
             allow_mmds_requests: false,
             tap: None,
         }
@@ -90,6 +118,31 @@ mod tests {
             ))));
     }
 
+    #[test]
+    fn test_netif_update_into_parsed_request() {
+        let netif_update = NetworkInterfaceUpdateConfig {
+            iface_id: String::from("foo"),
+            capture: None,
+        };
+        assert!(netif_update
+            .clone()
+            .into_parsed_request(Some(String::from("bar")), Method::Patch)
+            .is_err());
+        assert!(netif_update
+            .clone()
+            .into_parsed_request(Some(String::from("foo")), Method::Put)
+            .is_err());
+
+        let (sender, receiver) = oneshot::channel();
+        assert!(netif_update
+            .clone()
+            .into_parsed_request(Some(String::from("foo")), Method::Patch)
+            .eq(&Ok(ParsedRequest::Sync(
+                VmmAction::UpdateNetworkInterface(netif_update, sender),
+                receiver
+            ))));
+    }
+
     #[test]
     fn test_network_interface_body_serialization_and_deserialization() {
         let netif = NetworkInterfaceConfig {
@@ -98,6 +151,7 @@ mod tests {
             guest_mac: Some(MacAddr::parse_str("12:34:56:78:9A:BC").unwrap()),
             rx_rate_limiter: Some(RateLimiter::default()),
             tx_rate_limiter: Some(RateLimiter::default()),
+            rx_ebpf_filter: Some(PathBuf::from("/sys/fs/bpf/filter")),
             allow_mmds_requests: true,
             tap: None,
         };
@@ -111,13 +165,14 @@ mod tests {
             },
             "tx_rate_limiter": {
             },
+            "rx_ebpf_filter": "/sys/fs/bpf/filter",
             "allow_mmds_requests": true
         }"#;
 
         let x = serde_json::from_str(jstr).expect("deserialization failed.");
         assert_eq!(netif, x);
 
-        // Check that guest_mac and rate limiters are truly optional.
+        // Check that guest_mac, rate limiters and the eBPF filter are truly optional.
         let jstr_no_mac = r#"{
             "iface_id": "foo",
             "host_dev_name": "bar"
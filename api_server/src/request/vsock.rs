@@ -40,6 +40,7 @@ mod tests {
         let vsock = VsockDeviceConfig {
             id: String::from("foo"),
             guest_cid: 42,
+            port_forwards: Vec::new(),
         };
         assert!(vsock
             .clone()
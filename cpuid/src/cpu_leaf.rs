@@ -42,6 +42,8 @@ pub mod leaf_0x1 {
         // PDCM = Perfmon and Debug Capability
         pub const PDCM_SHIFT: u32 = 15;
         // 18 = DCA Direct Cache Access (prefetch data from a memory mapped device)
+        // X2APIC = the APIC IDs are 32 bits wide, addressed through MSRs
+        pub const X2APIC_SHIFT: u32 = 21;
         pub const MOVBE_SHIFT: u32 = 22;
         pub const TSC_DEADLINE_TIMER_SHIFT: u32 = 24;
         pub const OSXSAVE_SHIFT: u32 = 27;
@@ -29,8 +29,6 @@ use cpu_leaf::*;
 /// Errors associated with configuring the CPUID entries.
 #[derive(Debug)]
 pub enum Error {
-    /// The maximum number of addressable logical CPUs cannot be stored in an `u8`.
-    VcpuCountOverflow,
     /// Failure with getting brand string.
     CreateBrandString(brand_string::Error),
 }
@@ -48,6 +46,7 @@ pub type PartialError<T, E> = (T, Option<E>);
 /// * `cpu_id` - The index of the VCPU for which the CPUID entries are configured.
 /// * `cpu_count` - The total number of present VCPUs.
 /// * `ht_enabled` - Whether or not to enable HT.
+/// * `vpmu_enabled` - Whether or not to expose the performance monitoring counters of the host.
 /// * `kvm_cpuid` - KVM related structure holding the relevant CPUID info.
 ///
 /// # Example
@@ -60,7 +59,7 @@ pub type PartialError<T, E> = (T, Option<E>);
 ///
 /// let kvm = Kvm::new().unwrap();
 /// let mut kvm_cpuid: CpuId = kvm.get_supported_cpuid(MAX_KVM_CPUID_ENTRIES).unwrap();
-/// filter_cpuid(0, 1, true, &mut kvm_cpuid).unwrap();
+/// filter_cpuid(0, 1, true, false, &mut kvm_cpuid).unwrap();
 ///
 /// // Get expected `kvm_cpuid` entries.
 /// let entries = kvm_cpuid.mut_entries_slice();
@@ -70,10 +69,11 @@ pub fn filter_cpuid(
     cpu_id: u8,
     cpu_count: u8,
     ht_enabled: bool,
+    vpmu_enabled: bool,
     kvm_cpuid: &mut CpuId,
 ) -> Result<()> {
     let entries = kvm_cpuid.mut_entries_slice();
-    let max_addr_cpu = get_max_addressable_lprocessors(cpu_count)? as u32;
+    let max_addr_cpu = get_max_addressable_lprocessors(cpu_count) as u32;
 
     let res = get_brand_string();
     let bstr = res.0;
@@ -84,6 +84,9 @@ pub fn filter_cpuid(
                 // X86 hypervisor feature
                 entry.ecx |= 1 << leaf_0x1::ecx::TSC_DEADLINE_TIMER_SHIFT;
                 entry.ecx |= 1 << leaf_0x1::ecx::HYPERVISOR_SHIFT;
+                // KVM always emulates the x2APIC; exposing it lets the guest address the APICs
+                // of many vCPUs through MSRs instead of the MMIO page
+                entry.ecx |= 1 << leaf_0x1::ecx::X2APIC_SHIFT;
                 entry.ebx = ((cpu_id as u32) << leaf_0x1::ebx::APICID_SHIFT) as u32
                     | (EBX_CLFLUSH_CACHELINE << leaf_0x1::ebx::CLFLUSH_SIZE_SHIFT);
                 entry.ebx |= max_addr_cpu << leaf_0x1::ebx::CPU_COUNT_SHIFT;
@@ -136,7 +139,9 @@ pub fn filter_cpuid(
                 if cpu_count >= 2 {
                     // We don't handle properly the case where we have more than one socket
                     // Put all cores in the same socket
-                    entry.eax |= ((cpu_count - 1) as u32) << leaf_0x4::eax::MAX_ADDR_IDS_IN_PACKAGE;
+                    // The field only has 6 bits, so it saturates at 64 cores
+                    entry.eax |= (u32::from(cpu_count - 1).min(0b111111))
+                        << leaf_0x4::eax::MAX_ADDR_IDS_IN_PACKAGE;
                 }
             }
             0x6 => {
@@ -147,11 +152,14 @@ pub fn filter_cpuid(
             }
             0xA => {
                 // Architectural Performance Monitor Leaf
-                // Disable PMU
-                entry.eax = 0;
-                entry.ebx = 0;
-                entry.ecx = 0;
-                entry.edx = 0;
+                // KVM virtualizes the counters it reports as supported, keep them when the vPMU
+                // is enabled and disable PMU otherwise
+                if !vpmu_enabled {
+                    entry.eax = 0;
+                    entry.ebx = 0;
+                    entry.ecx = 0;
+                    entry.edx = 0;
+                }
             }
             0xB => {
                 // Hide the actual topology of the underlying host
@@ -187,7 +195,7 @@ pub fn filter_cpuid(
                     }
                     1 => {
                         // Core Level Processor Topology; index = 1
-                        entry.eax = LEAFBH_INDEX1_APICID_SHIFT;
+                        entry.eax = get_leafbh_index1_apicid_shift(cpu_count);
                         if cpu_count == 1 {
                             // For 1 vCPU, this level is invalid
                             entry.ebx = 0;
@@ -236,7 +244,8 @@ pub fn filter_cpuid(
 const EBX_CLFLUSH_CACHELINE: u32 = 8; // Flush a cache line size.
 
 // The APIC ID shift in leaf 0xBh specifies the number of bits to shit the x2APIC ID to get a
-// unique topology of the next level. This allows 64 logical processors/package.
+// unique topology of the next level. This allows 64 logical processors/package, and is the
+// minimum used for any CPU count.
 const LEAFBH_INDEX1_APICID_SHIFT: u32 = 6;
 
 const DEFAULT_BRAND_STRING: &[u8] = b"Intel(R) Xeon(R) Processor";
@@ -244,15 +253,24 @@ const DEFAULT_BRAND_STRING: &[u8] = b"Intel(R) Xeon(R) Processor";
 /// Sets leaf 01H EBX[23-16].
 ///
 /// The maximum number of addressable logical CPUs is computed as the closest power of 2
-/// higher or equal to the CPU count configured by the user.
-fn get_max_addressable_lprocessors(cpu_count: u8) -> Result<u8> {
+/// higher or equal to the CPU count configured by the user. The field only has 8 bits, so above
+/// 128 CPUs it saturates at 255, as on physical hosts with that many logical processors.
+fn get_max_addressable_lprocessors(cpu_count: u8) -> u8 {
     let mut max_addressable_lcpu = (cpu_count as f64).log2().ceil();
     max_addressable_lcpu = (2 as f64).powf(max_addressable_lcpu);
-    // check that this number is still an u8
     if max_addressable_lcpu > u8::max_value().into() {
-        return Err(Error::VcpuCountOverflow);
+        return u8::max_value();
     }
-    Ok(max_addressable_lcpu as u8)
+    max_addressable_lcpu as u8
+}
+
+/// Sets leaf 0BH index 1 EAX[4-0].
+///
+/// All the vCPUs are in the same package, so the shift has to cover the APIC IDs of all of
+/// them; otherwise, the guest splits them into several packages.
+fn get_leafbh_index1_apicid_shift(cpu_count: u8) -> u32 {
+    let bits = (cpu_count as f64).log2().ceil() as u32;
+    bits.max(LEAFBH_INDEX1_APICID_SHIFT)
 }
 
 /// Generates the emulated brand string.
@@ -293,11 +311,27 @@ mod tests {
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     #[test]
     fn test_get_max_addressable_lprocessors() {
-        assert_eq!(get_max_addressable_lprocessors(1).unwrap(), 1);
-        assert_eq!(get_max_addressable_lprocessors(2).unwrap(), 2);
-        assert_eq!(get_max_addressable_lprocessors(4).unwrap(), 4);
-        assert_eq!(get_max_addressable_lprocessors(6).unwrap(), 8);
-        assert!(get_max_addressable_lprocessors(u8::max_value()).is_err());
+        assert_eq!(get_max_addressable_lprocessors(1), 1);
+        assert_eq!(get_max_addressable_lprocessors(2), 2);
+        assert_eq!(get_max_addressable_lprocessors(4), 4);
+        assert_eq!(get_max_addressable_lprocessors(6), 8);
+        assert_eq!(get_max_addressable_lprocessors(128), 128);
+        assert_eq!(get_max_addressable_lprocessors(129), 255);
+        assert_eq!(get_max_addressable_lprocessors(u8::max_value()), 255);
+    }
+
+    #[test]
+    fn test_get_leafbh_index1_apicid_shift() {
+        assert_eq!(
+            get_leafbh_index1_apicid_shift(1),
+            LEAFBH_INDEX1_APICID_SHIFT
+        );
+        assert_eq!(
+            get_leafbh_index1_apicid_shift(64),
+            LEAFBH_INDEX1_APICID_SHIFT
+        );
+        assert_eq!(get_leafbh_index1_apicid_shift(65), 7);
+        assert_eq!(get_leafbh_index1_apicid_shift(254), 8);
     }
 
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
@@ -305,7 +339,7 @@ mod tests {
     fn test_cpuid() {
         let kvm = Kvm::new().unwrap();
         let mut kvm_cpuid: CpuId = kvm.get_supported_cpuid(MAX_KVM_CPUID_ENTRIES).unwrap();
-        filter_cpuid(0, 1, true, &mut kvm_cpuid).unwrap();
+        filter_cpuid(0, 1, true, false, &mut kvm_cpuid).unwrap();
 
         let entries = kvm_cpuid.mut_entries_slice();
         // TODO: This should be tested as part of the CI; only check that the function result is ok
@@ -350,6 +384,30 @@ mod tests {
         assert!(entries.contains(&leaf11_index2));
     }
 
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn test_filter_cpuid_vpmu() {
+        let mut kvm_cpuid = CpuId::new(1);
+        {
+            let entries = kvm_cpuid.mut_entries_slice();
+            entries[0].function = 0xA;
+            // Version 4, 8 general-purpose counters, 3 fixed counters.
+            entries[0].eax = 0x0830_0804;
+            entries[0].edx = 0x0603;
+        }
+        filter_cpuid(0, 1, false, true, &mut kvm_cpuid).unwrap();
+        {
+            let entries = kvm_cpuid.mut_entries_slice();
+            assert_eq!(entries[0].eax, 0x0830_0804);
+            assert_eq!(entries[0].edx, 0x0603);
+        }
+
+        filter_cpuid(0, 1, false, false, &mut kvm_cpuid).unwrap();
+        let entries = kvm_cpuid.mut_entries_slice();
+        assert_eq!(entries[0].eax, 0);
+        assert_eq!(entries[0].edx, 0);
+    }
+
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     #[test]
     fn test_filter_cpuid_1vcpu_ht_off() {
@@ -407,8 +465,8 @@ mod tests {
             let entries = kvm_cpuid.mut_entries_slice();
             entries[10].function = 0x80000003;
         }
-        filter_cpuid(0, 1, false, &mut kvm_cpuid).unwrap();
-        let max_addr_cpu = get_max_addressable_lprocessors(1).unwrap() as u32;
+        filter_cpuid(0, 1, false, false, &mut kvm_cpuid).unwrap();
+        let max_addr_cpu = get_max_addressable_lprocessors(1) as u32;
 
         let cpuid_f1 = kvm_cpuid_entry2 {
             function: 1,
@@ -418,7 +476,8 @@ mod tests {
             ebx: (EBX_CLFLUSH_CACHELINE << leaf_0x1::ebx::CLFLUSH_SIZE_SHIFT)
                 | max_addr_cpu << leaf_0x1::ebx::CPU_COUNT_SHIFT,
             ecx: 1 << leaf_0x1::ecx::TSC_DEADLINE_TIMER_SHIFT
-                | 1 << leaf_0x1::ecx::HYPERVISOR_SHIFT,
+                | 1 << leaf_0x1::ecx::HYPERVISOR_SHIFT
+                | 1 << leaf_0x1::ecx::X2APIC_SHIFT,
             edx: 0,
             padding: [0, 0, 0],
         };
@@ -627,8 +686,8 @@ mod tests {
             entries[10].function = 0x80000003;
         }
         let cpu_count = 3;
-        filter_cpuid(0, cpu_count, false, &mut kvm_cpuid).unwrap();
-        let max_addr_cpu = get_max_addressable_lprocessors(cpu_count).unwrap() as u32;
+        filter_cpuid(0, cpu_count, false, false, &mut kvm_cpuid).unwrap();
+        let max_addr_cpu = get_max_addressable_lprocessors(cpu_count) as u32;
 
         let cpuid_f1 = kvm_cpuid_entry2 {
             function: 1,
@@ -638,7 +697,8 @@ mod tests {
             ebx: (EBX_CLFLUSH_CACHELINE << leaf_0x1::ebx::CLFLUSH_SIZE_SHIFT)
                 | max_addr_cpu << leaf_0x1::ebx::CPU_COUNT_SHIFT,
             ecx: 1 << leaf_0x1::ecx::TSC_DEADLINE_TIMER_SHIFT
-                | 1 << leaf_0x1::ecx::HYPERVISOR_SHIFT,
+                | 1 << leaf_0x1::ecx::HYPERVISOR_SHIFT
+                | 1 << leaf_0x1::ecx::X2APIC_SHIFT,
             edx: 1 << leaf_0x1::edx::HTT_SHIFT,
             padding: [0, 0, 0],
         };
@@ -856,8 +916,8 @@ mod tests {
             let entries = kvm_cpuid.mut_entries_slice();
             entries[10].function = 0x80000003;
         }
-        filter_cpuid(0, 1, true, &mut kvm_cpuid).unwrap();
-        let max_addr_cpu = get_max_addressable_lprocessors(1).unwrap() as u32;
+        filter_cpuid(0, 1, true, false, &mut kvm_cpuid).unwrap();
+        let max_addr_cpu = get_max_addressable_lprocessors(1) as u32;
 
         let cpuid_f1 = kvm_cpuid_entry2 {
             function: 1,
@@ -867,7 +927,8 @@ mod tests {
             ebx: (EBX_CLFLUSH_CACHELINE << leaf_0x1::ebx::CLFLUSH_SIZE_SHIFT)
                 | max_addr_cpu << leaf_0x1::ebx::CPU_COUNT_SHIFT,
             ecx: 1 << leaf_0x1::ecx::TSC_DEADLINE_TIMER_SHIFT
-                | 1 << leaf_0x1::ecx::HYPERVISOR_SHIFT,
+                | 1 << leaf_0x1::ecx::HYPERVISOR_SHIFT
+                | 1 << leaf_0x1::ecx::X2APIC_SHIFT,
             edx: 0,
             padding: [0, 0, 0],
         };
@@ -1076,8 +1137,8 @@ mod tests {
             entries[10].function = 0x80000003;
         }
         let cpu_count = 3;
-        filter_cpuid(0, cpu_count, true, &mut kvm_cpuid).unwrap();
-        let max_addr_cpu = get_max_addressable_lprocessors(cpu_count).unwrap() as u32;
+        filter_cpuid(0, cpu_count, true, false, &mut kvm_cpuid).unwrap();
+        let max_addr_cpu = get_max_addressable_lprocessors(cpu_count) as u32;
 
         let cpuid_f1 = kvm_cpuid_entry2 {
             function: 1,
@@ -1087,7 +1148,8 @@ mod tests {
             ebx: (EBX_CLFLUSH_CACHELINE << leaf_0x1::ebx::CLFLUSH_SIZE_SHIFT)
                 | max_addr_cpu << leaf_0x1::ebx::CPU_COUNT_SHIFT,
             ecx: 1 << leaf_0x1::ecx::TSC_DEADLINE_TIMER_SHIFT
-                | 1 << leaf_0x1::ecx::HYPERVISOR_SHIFT,
+                | 1 << leaf_0x1::ecx::HYPERVISOR_SHIFT
+                | 1 << leaf_0x1::ecx::X2APIC_SHIFT,
             edx: 1 << leaf_0x1::edx::HTT_SHIFT,
             padding: [0, 0, 0],
         };
@@ -8,6 +8,7 @@
 use logger::{Metric, METRICS};
 use sys_util::{EventFd, Result};
 
+use persist::{self, Persist};
 use BusDevice;
 
 const RESET_CMD: u8 = 0xfe;
@@ -52,6 +53,25 @@ impl BusDevice for I8042Device {
     }
 }
 
+versioned_state! {
+    /// The controller only emulates the reset line, so it has no guest visible state yet.
+    pub struct I8042State(version 1) {}
+}
+
+impl Persist for I8042Device {
+    type State = I8042State;
+    type ConstructorArgs = EventFd;
+    type Error = persist::Error;
+
+    fn save(&self) -> I8042State {
+        I8042State {}
+    }
+
+    fn restore(reset_evt: EventFd, _state: &I8042State) -> persist::Result<Self> {
+        Ok(I8042Device::new(reset_evt))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
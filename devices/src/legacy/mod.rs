@@ -6,7 +6,11 @@
 // found in the THIRD-PARTY file.
 
 mod i8042;
+mod pvpanic;
 mod serial;
+mod watchdog;
 
-pub use self::i8042::I8042Device;
-pub use self::serial::Serial;
+pub use self::i8042::{I8042Device, I8042State};
+pub use self::pvpanic::{PvPanic, PVPANIC_CRASH_LOADED, PVPANIC_PANICKED, PVPANIC_PORT};
+pub use self::serial::{Serial, SerialConstructorArgs, SerialState};
+pub use self::watchdog::{Watchdog, WatchdogState, WATCHDOG_PORT_BASE, WATCHDOG_PORT_LEN};
@@ -0,0 +1,113 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{Metric, METRICS};
+use sys_util::{EventFd, Result};
+
+use BusDevice;
+
+/// The I/O port of the pvpanic device.
+pub const PVPANIC_PORT: u64 = 0x505;
+
+/// Bit written by the guest when its kernel panicked.
+pub const PVPANIC_PANICKED: u8 = 1 << 0;
+/// Bit written by the guest when its kernel panicked and a crash kernel was loaded to handle it.
+pub const PVPANIC_CRASH_LOADED: u8 = 1 << 1;
+
+const SUPPORTED_EVENTS: u8 = PVPANIC_PANICKED | PVPANIC_CRASH_LOADED;
+
+/// A pvpanic device, which the guest driven by the `pvpanic` driver writes to when its kernel
+/// panics.
+///
+/// The device accumulates the events written by the guest and signals the given event. The VMM
+/// then takes the pending events with `take_events` and reports them.
+pub struct PvPanic {
+    pending: u8,
+    event_evt: EventFd,
+}
+
+impl PvPanic {
+    /// Constructs a pvpanic device which signals `event_evt` whenever the guest reports an event.
+    pub fn new(event_evt: EventFd) -> PvPanic {
+        PvPanic {
+            pending: 0,
+            event_evt,
+        }
+    }
+
+    /// Returns a clone of the EventFd signaled on every event reported by the guest.
+    pub fn get_eventfd_clone(&self) -> Result<EventFd> {
+        self.event_evt.try_clone()
+    }
+
+    /// Returns the `PVPANIC_*` bits reported by the guest since the last call, and clears them.
+    pub fn take_events(&mut self) -> u8 {
+        let pending = self.pending;
+        self.pending = 0;
+        pending
+    }
+}
+
+impl BusDevice for PvPanic {
+    // Reading the port returns the events the device supports.
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        match (offset, data.len()) {
+            (0, 1) => data[0] = SUPPORTED_EVENTS,
+            _ => METRICS.guest_events.missed_read_count.inc(),
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        match (offset, data.len()) {
+            (0, 1) if data[0] & SUPPORTED_EVENTS != 0 => {
+                // Unknown bits are ignored, like on other hypervisors.
+                self.pending |= data[0] & SUPPORTED_EVENTS;
+                if let Err(e) = self.event_evt.write(1) {
+                    error!("Failed to trigger pvpanic event: {:?}", e);
+                    METRICS.guest_events.error_count.inc();
+                }
+            }
+            _ => METRICS.guest_events.missed_write_count.inc(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pvpanic_read_write_and_event() {
+        let mut pvpanic = PvPanic::new(EventFd::new().unwrap());
+        let event_evt = pvpanic.get_eventfd_clone().unwrap();
+        assert_eq!(pvpanic.take_events(), 0);
+
+        let mut data = [0];
+        pvpanic.read(0, &mut data);
+        assert_eq!(data[0], PVPANIC_PANICKED | PVPANIC_CRASH_LOADED);
+
+        // The events accumulate until they are taken.
+        pvpanic.write(0, &[PVPANIC_PANICKED]);
+        pvpanic.write(0, &[PVPANIC_CRASH_LOADED | 0x80]);
+        assert_eq!(event_evt.read(), Ok(2));
+        assert_eq!(
+            pvpanic.take_events(),
+            PVPANIC_PANICKED | PVPANIC_CRASH_LOADED
+        );
+        assert_eq!(pvpanic.take_events(), 0);
+
+        // Check invalid accesses.
+        let before = METRICS.guest_events.missed_write_count.count();
+        pvpanic.write(0, &[0x80]);
+        pvpanic.write(0, &[PVPANIC_PANICKED, 0]);
+        pvpanic.write(1, &[PVPANIC_PANICKED]);
+        assert_eq!(METRICS.guest_events.missed_write_count.count(), before + 3);
+        assert_eq!(pvpanic.take_events(), 0);
+
+        let before = METRICS.guest_events.missed_read_count.count();
+        let mut data = [1, 1];
+        pvpanic.read(0, &mut data);
+        assert_eq!(data, [1, 1]);
+        assert_eq!(METRICS.guest_events.missed_read_count.count(), before + 1);
+    }
+}
@@ -8,12 +8,17 @@
 use std::collections::VecDeque;
 use std::io;
 
+use libc::ENOSPC;
 use logger::{Metric, METRICS};
-use sys_util::{EventFd, Result};
+use sys_util::{Error as SysError, EventFd, Result};
 
+use persist::{self, Persist};
 use BusDevice;
 
 const LOOP_SIZE: usize = 0x40;
+// The most bytes of input waiting for the guest to read them. The input sent faster than the
+// guest reads it is refused beyond that, instead of growing the buffer.
+const MAX_IN_BUFFER_LEN: usize = 64 * 1024;
 
 const DATA: u8 = 0;
 const IER: u8 = 1;
@@ -39,6 +44,7 @@ const IIR_RECV_BIT: u8 = 0x4;
 const LCR_DLAB_BIT: u8 = 0x80;
 
 const LSR_DATA_BIT: u8 = 0x1;
+const LSR_BREAK_BIT: u8 = 0x10;
 const LSR_EMPTY_BIT: u8 = 0x20;
 const LSR_IDLE_BIT: u8 = 0x40;
 
@@ -54,7 +60,8 @@ const DEFAULT_BAUD_DIVISOR: u16 = 12; // 9600 bps
 /// Emulates serial COM ports commonly seen on x86 I/O ports 0x3f8/0x2f8/0x3e8/0x2e8.
 ///
 /// This can optionally write the guest's output to a Write trait object. To send input to the
-/// guest, use `queue_input_bytes`.
+/// guest, use `queue_input_bytes`, and `queue_break` for a break condition, or `queue_sysrq` for
+/// both.
 pub struct Serial {
     interrupt_enable: u8,
     interrupt_identification: u8,
@@ -66,6 +73,8 @@ pub struct Serial {
     scratch: u8,
     baud_divisor: u16,
     in_buffer: VecDeque<u8>,
+    // The positions in `in_buffer` of the NUL bytes received with a break condition.
+    breaks: VecDeque<usize>,
     out: Option<Box<io::Write + Send>>,
 }
 
@@ -82,6 +91,7 @@ impl Serial {
             scratch: 0,
             baud_divisor: DEFAULT_BAUD_DIVISOR,
             in_buffer: VecDeque::new(),
+            breaks: VecDeque::new(),
             out,
         }
     }
@@ -98,14 +108,64 @@ impl Serial {
 
     /// Queues raw bytes for the guest to read and signals the interrupt if the line status would
     /// change.
+    ///
+    /// # Errors
+    /// Fails with `ENOSPC`, without queueing any of the bytes, when the guest didn't read enough
+    /// of the pending input for them to fit.
     pub fn queue_input_bytes(&mut self, c: &[u8]) -> Result<()> {
         if !self.is_loop() {
+            self.reserve_input(c.len())?;
             self.in_buffer.extend(c);
             self.recv_data()?;
         }
         Ok(())
     }
 
+    /// Queues a break condition for the guest, which reads it as a NUL byte with the break bit
+    /// set in the line status. The Linux serial drivers handle the byte received right after a
+    /// break as a magic SysRq key.
+    ///
+    /// # Errors
+    /// Fails with `ENOSPC` like `queue_input_bytes`.
+    pub fn queue_break(&mut self) -> Result<()> {
+        if !self.is_loop() {
+            self.reserve_input(1)?;
+            self.breaks.push_back(self.in_buffer.len());
+            self.in_buffer.push_back(0);
+            self.recv_data()?;
+        }
+        Ok(())
+    }
+
+    /// Queues a break condition followed by `key`, the magic SysRq key the guest handles.
+    ///
+    /// # Errors
+    /// Fails with `ENOSPC` like `queue_input_bytes`, without queueing the break either.
+    pub fn queue_sysrq(&mut self, key: u8) -> Result<()> {
+        if !self.is_loop() {
+            self.reserve_input(2)?;
+        }
+        self.queue_break()?;
+        self.queue_input_bytes(&[key])
+    }
+
+    fn reserve_input(&self, len: usize) -> Result<()> {
+        if self.in_buffer.len() + len > MAX_IN_BUFFER_LEN {
+            return Err(SysError::new(ENOSPC));
+        }
+        Ok(())
+    }
+
+    fn pop_input_byte(&mut self) -> u8 {
+        if self.breaks.front() == Some(&0) {
+            self.breaks.pop_front();
+        }
+        for position in self.breaks.iter_mut() {
+            *position -= 1;
+        }
+        self.in_buffer.pop_front().unwrap_or_default()
+    }
+
     fn is_dlab_set(&self) -> bool {
         (self.line_control & LCR_DLAB_BIT) != 0
     }
@@ -209,7 +269,7 @@ impl BusDevice for Serial {
                     self.line_status &= !LSR_DATA_BIT;
                 }
                 METRICS.uart.read_count.inc();
-                self.in_buffer.pop_front().unwrap_or_default()
+                self.pop_input_byte()
             }
             IER => self.interrupt_enable,
             IIR => {
@@ -219,6 +279,7 @@ impl BusDevice for Serial {
             }
             LCR => self.line_control,
             MCR => self.modem_control,
+            LSR if self.breaks.front() == Some(&0) => self.line_status | LSR_BREAK_BIT,
             LSR => self.line_status,
             MSR => self.modem_status,
             SCR => self.scratch,
@@ -239,9 +300,93 @@ impl BusDevice for Serial {
     }
 }
 
+versioned_state! {
+    /// The registers of the serial port and the input not yet read by the guest.
+    pub struct SerialState(version 2) {
+        pub interrupt_enable: u8 = since 1,
+        pub interrupt_identification: u8 = since 1,
+        pub line_control: u8 = since 1,
+        pub line_status: u8 = since 1,
+        pub modem_control: u8 = since 1,
+        pub modem_status: u8 = since 1,
+        pub scratch: u8 = since 1,
+        pub baud_divisor: u16 = since 1,
+        pub in_buffer: Vec<u8> = since 1,
+        pub breaks: Vec<u64> = since 2,
+    }
+}
+
+/// The host resources a restored serial port is connected to.
+pub struct SerialConstructorArgs {
+    pub interrupt_evt: EventFd,
+    pub out: Option<Box<io::Write + Send>>,
+}
+
+impl Persist for Serial {
+    type State = SerialState;
+    type ConstructorArgs = SerialConstructorArgs;
+    type Error = persist::Error;
+
+    fn save(&self) -> SerialState {
+        SerialState {
+            interrupt_enable: self.interrupt_enable,
+            interrupt_identification: self.interrupt_identification,
+            line_control: self.line_control,
+            line_status: self.line_status,
+            modem_control: self.modem_control,
+            modem_status: self.modem_status,
+            scratch: self.scratch,
+            baud_divisor: self.baud_divisor,
+            in_buffer: self.in_buffer.iter().cloned().collect(),
+            breaks: self
+                .breaks
+                .iter()
+                .map(|&position| position as u64)
+                .collect(),
+        }
+    }
+
+    fn restore(
+        constructor_args: SerialConstructorArgs,
+        state: &SerialState,
+    ) -> persist::Result<Self> {
+        // The breaks have to be in order, on bytes of the pending input.
+        let mut next_break = 0;
+        for &position in state.breaks.iter() {
+            if position < next_break || position >= state.in_buffer.len() as u64 {
+                return Err(persist::Error::InvalidValue("SerialState::breaks"));
+            }
+            next_break = position + 1;
+        }
+        if state.in_buffer.len() > MAX_IN_BUFFER_LEN {
+            return Err(persist::Error::InvalidValue("SerialState::in_buffer"));
+        }
+
+        Ok(Serial {
+            interrupt_enable: state.interrupt_enable,
+            interrupt_identification: state.interrupt_identification,
+            interrupt_evt: constructor_args.interrupt_evt,
+            line_control: state.line_control,
+            line_status: state.line_status,
+            modem_control: state.modem_control,
+            modem_status: state.modem_status,
+            scratch: state.scratch,
+            baud_divisor: state.baud_divisor,
+            in_buffer: state.in_buffer.iter().cloned().collect(),
+            breaks: state
+                .breaks
+                .iter()
+                .map(|&position| position as usize)
+                .collect(),
+            out: constructor_args.out,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use persist::Versionize;
     use std::io;
     use std::sync::{Arc, Mutex};
 
@@ -322,6 +467,58 @@ mod tests {
         assert_eq!(data[0], 0);
     }
 
+    #[test]
+    fn serial_break() {
+        let mut serial = Serial::new_sink(EventFd::new().unwrap());
+        serial.queue_input_bytes(&['a' as u8]).unwrap();
+        serial.queue_break().unwrap();
+        serial.queue_input_bytes(&['c' as u8]).unwrap();
+
+        // The break bit is only set while the NUL byte of the break is the next one to read.
+        let mut data = [0u8];
+        serial.read(LSR as u64, &mut data[..]);
+        assert_eq!(data[0] & LSR_BREAK_BIT, 0);
+        serial.read(DATA as u64, &mut data[..]);
+        assert_eq!(data[0], 'a' as u8);
+        serial.read(LSR as u64, &mut data[..]);
+        assert_eq!(
+            data[0] & (LSR_BREAK_BIT | LSR_DATA_BIT),
+            LSR_BREAK_BIT | LSR_DATA_BIT
+        );
+        serial.read(DATA as u64, &mut data[..]);
+        assert_eq!(data[0], 0);
+        serial.read(LSR as u64, &mut data[..]);
+        assert_eq!(data[0] & LSR_BREAK_BIT, 0);
+        serial.read(DATA as u64, &mut data[..]);
+        assert_eq!(data[0], 'c' as u8);
+        assert!(serial.breaks.is_empty());
+    }
+
+    #[test]
+    fn serial_input_full() {
+        let mut serial = Serial::new_sink(EventFd::new().unwrap());
+        serial
+            .queue_input_bytes(&[b'a'; MAX_IN_BUFFER_LEN - 2])
+            .unwrap();
+
+        // The input which doesn't fit is refused as a whole.
+        assert_eq!(
+            serial.queue_input_bytes(&[b'b'; 3]).unwrap_err().errno(),
+            ENOSPC
+        );
+        serial.queue_sysrq(b'c').unwrap();
+        assert_eq!(serial.in_buffer.len(), MAX_IN_BUFFER_LEN);
+        assert_eq!(serial.queue_break().unwrap_err().errno(), ENOSPC);
+        assert_eq!(serial.queue_sysrq(b'c').unwrap_err().errno(), ENOSPC);
+        assert_eq!(serial.breaks.len(), 1);
+
+        // The input fits again once the guest reads it.
+        let mut data = [0u8];
+        serial.read(DATA as u64, &mut data[..]);
+        serial.queue_input_bytes(&[b'b']).unwrap();
+        assert_eq!(serial.in_buffer.back(), Some(&b'b'));
+    }
+
     #[test]
     fn serial_thr() {
         let intr_evt = EventFd::new().unwrap();
@@ -390,4 +587,62 @@ mod tests {
         serial.read(SCR as u64, &mut data[..]);
         assert_eq!(data[0], 0x12 as u8);
     }
+
+    #[test]
+    fn serial_save_restore() {
+        let mut serial = Serial::new_sink(EventFd::new().unwrap());
+        serial.write(LCR as u64, &[LCR_DLAB_BIT as u8]);
+        serial.write(DLAB_LOW as u64, &[0x12 as u8]);
+        serial.write(SCR as u64, &[0x34 as u8]);
+        serial.queue_input_bytes(&['a' as u8, 'b' as u8]).unwrap();
+        serial.queue_break().unwrap();
+
+        let mut bytes = Vec::new();
+        serial.save().serialize(&mut bytes).unwrap();
+        let state = SerialState::deserialize(&mut bytes.as_slice()).unwrap();
+        assert_eq!(state, serial.save());
+
+        let serial_out = SharedBuffer::new();
+        let mut restored = Serial::restore(
+            SerialConstructorArgs {
+                interrupt_evt: EventFd::new().unwrap(),
+                out: Some(Box::new(serial_out.clone())),
+            },
+            &state,
+        )
+        .unwrap();
+
+        let mut data = [0u8];
+        restored.read(DLAB_LOW as u64, &mut data[..]);
+        assert_eq!(data[0], 0x12);
+        restored.read(SCR as u64, &mut data[..]);
+        assert_eq!(data[0], 0x34);
+
+        // The pending input survives and the restored port writes to its new output.
+        restored.write(LCR as u64, &[0]);
+        restored.read(DATA as u64, &mut data[..]);
+        assert_eq!(data[0], 'a' as u8);
+        restored.read(DATA as u64, &mut data[..]);
+        assert_eq!(data[0], 'b' as u8);
+        restored.read(LSR as u64, &mut data[..]);
+        assert_ne!(data[0] & LSR_BREAK_BIT, 0);
+        restored.write(DATA as u64, &['c' as u8]);
+        assert_eq!(serial_out.buf.lock().unwrap().as_slice(), &['c' as u8]);
+
+        // Breaks which aren't on the pending input are rejected.
+        let state = SerialState {
+            breaks: vec![3],
+            ..state
+        };
+        match Serial::restore(
+            SerialConstructorArgs {
+                interrupt_evt: EventFd::new().unwrap(),
+                out: None,
+            },
+            &state,
+        ) {
+            Err(persist::Error::InvalidValue(_)) => (),
+            _ => panic!("Expected an invalid value error."),
+        }
+    }
 }
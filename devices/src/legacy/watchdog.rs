@@ -0,0 +1,177 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{Metric, METRICS};
+use sys_util::{EventFd, Result};
+
+use persist::{self, Persist};
+use BusDevice;
+
+/// The first I/O port of the watchdog. The device spans the ports from 0x441 to 0x443.
+pub const WATCHDOG_PORT_BASE: u64 = 0x441;
+/// The number of I/O ports of the watchdog.
+pub const WATCHDOG_PORT_LEN: u64 = 0x3;
+
+// Writing to port 0x441 stops the watchdog, writing to port 0x443 starts or pings it.
+const STOP_OFFSET: u64 = 0x0;
+const START_OFFSET: u64 = 0x2;
+
+// The timeouts, in seconds, selected by the low nibble of the value written to start the watchdog.
+const TIMEOUTS: [u64; 16] = [30, 28, 26, 24, 22, 20, 18, 16, 14, 12, 10, 8, 6, 4, 2, 0];
+
+/// A watchdog timer compatible with the iB700 single board computer, which the guest drives with
+/// the `ib700wdt` driver.
+///
+/// The device only tracks the timeout requested by the guest, and signals the given event every
+/// time it changes. The VMM arms its own timer with `timeout`, and applies the configured action
+/// when the timer expires before the guest pings the watchdog again.
+pub struct Watchdog {
+    timeout: Option<u64>,
+    update_evt: EventFd,
+}
+
+impl Watchdog {
+    /// Constructs a stopped watchdog which signals `update_evt` whenever the guest starts, pings
+    /// or stops it.
+    pub fn new(update_evt: EventFd) -> Watchdog {
+        Watchdog {
+            timeout: None,
+            update_evt,
+        }
+    }
+
+    /// Returns a clone of the EventFd signaled on every update of the timeout.
+    pub fn get_eventfd_clone(&self) -> Result<EventFd> {
+        self.update_evt.try_clone()
+    }
+
+    /// Returns the number of seconds before the watchdog expires, counted from the last update,
+    /// or `None` when the watchdog is stopped.
+    pub fn timeout(&self) -> Option<u64> {
+        self.timeout
+    }
+
+    fn update(&mut self, timeout: Option<u64>) {
+        self.timeout = timeout;
+        if let Err(e) = self.update_evt.write(1) {
+            error!("Failed to trigger watchdog update event: {:?}", e);
+            METRICS.watchdog.error_count.inc();
+        }
+    }
+}
+
+impl BusDevice for Watchdog {
+    // The watchdog has no readable registers.
+    fn read(&mut self, _offset: u64, _data: &mut [u8]) {
+        METRICS.watchdog.missed_read_count.inc();
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        match (offset, data.len()) {
+            (START_OFFSET, 1) => {
+                self.update(Some(TIMEOUTS[(data[0] & 0xf) as usize]));
+                METRICS.watchdog.ping_count.inc();
+            }
+            (STOP_OFFSET, 1) => {
+                self.update(None);
+                METRICS.watchdog.stop_count.inc();
+            }
+            _ => METRICS.watchdog.missed_write_count.inc(),
+        }
+    }
+}
+
+versioned_state! {
+    /// Whether the watchdog was started by the guest, and the timeout it is running with.
+    pub struct WatchdogState(version 1) {
+        pub enabled: bool = since 1,
+        pub timeout: u64 = since 1,
+    }
+}
+
+impl Persist for Watchdog {
+    type State = WatchdogState;
+    type ConstructorArgs = EventFd;
+    type Error = persist::Error;
+
+    fn save(&self) -> WatchdogState {
+        WatchdogState {
+            enabled: self.timeout.is_some(),
+            timeout: self.timeout.unwrap_or(0),
+        }
+    }
+
+    fn restore(update_evt: EventFd, state: &WatchdogState) -> persist::Result<Self> {
+        if state.timeout > TIMEOUTS[0] {
+            return Err(persist::Error::InvalidValue("WatchdogState::timeout"));
+        }
+        // Signal the restored timeout, so that the VMM arms its timer for it.
+        let mut watchdog = Watchdog::new(update_evt);
+        if state.enabled {
+            watchdog.update(Some(state.timeout));
+        }
+        Ok(watchdog)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_read_write_and_event() {
+        let mut watchdog = Watchdog::new(EventFd::new().unwrap());
+        let update_evt = watchdog.get_eventfd_clone().unwrap();
+        assert_eq!(watchdog.timeout(), None);
+
+        // Start the watchdog with the longest and the shortest timeouts.
+        watchdog.write(START_OFFSET, &[0x0]);
+        assert_eq!(watchdog.timeout(), Some(30));
+        watchdog.write(START_OFFSET, &[0xff]);
+        assert_eq!(watchdog.timeout(), Some(0));
+        assert_eq!(update_evt.read(), Ok(2));
+
+        // Ping it, then stop it.
+        watchdog.write(START_OFFSET, &[0x5]);
+        assert_eq!(watchdog.timeout(), Some(20));
+        watchdog.write(STOP_OFFSET, &[0x0]);
+        assert_eq!(watchdog.timeout(), None);
+        assert_eq!(update_evt.read(), Ok(2));
+
+        // Check invalid accesses.
+        let before = METRICS.watchdog.missed_write_count.count();
+        watchdog.write(1, &[0x0]);
+        watchdog.write(START_OFFSET, &[0x0, 0x0]);
+        assert_eq!(METRICS.watchdog.missed_write_count.count(), before + 2);
+        assert_eq!(watchdog.timeout(), None);
+
+        let before = METRICS.watchdog.missed_read_count.count();
+        let mut data = [1];
+        watchdog.read(START_OFFSET, &mut data);
+        assert_eq!(data, [1]);
+        assert_eq!(METRICS.watchdog.missed_read_count.count(), before + 1);
+    }
+
+    #[test]
+    fn test_watchdog_persist() {
+        let mut watchdog = Watchdog::new(EventFd::new().unwrap());
+        watchdog.write(START_OFFSET, &[0x1]);
+        let state = watchdog.save();
+        assert!(state.enabled);
+        assert_eq!(state.timeout, 28);
+
+        let restored = Watchdog::restore(EventFd::new().unwrap(), &state).unwrap();
+        assert_eq!(restored.timeout(), Some(28));
+        assert_eq!(restored.get_eventfd_clone().unwrap().read(), Ok(1));
+
+        let stopped = Watchdog::new(EventFd::new().unwrap()).save();
+        let restored = Watchdog::restore(EventFd::new().unwrap(), &stopped).unwrap();
+        assert_eq!(restored.timeout(), None);
+
+        let invalid = WatchdogState {
+            enabled: true,
+            timeout: 31,
+        };
+        assert!(Watchdog::restore(EventFd::new().unwrap(), &invalid).is_err());
+    }
+}
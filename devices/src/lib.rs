@@ -26,6 +26,11 @@ extern crate virtio_gen;
 
 use std::fs::File;
 
+use net_util::PcapWriter;
+
+#[macro_use]
+pub mod persist;
+
 mod bus;
 pub mod legacy;
 pub mod virtio;
@@ -39,6 +44,10 @@ pub type DeviceEventT = u16;
 pub enum EpollHandlerPayload {
     /// DrivePayload(disk_image)
     DrivePayload(File),
+    /// DriveTracePayload(io_trace), where `None` stops the trace.
+    DriveTracePayload(Option<File>),
+    /// NetCapturePayload(packet_capture), where `None` stops the capture.
+    NetCapturePayload(Option<PcapWriter<File>>),
     /// Events that do not need a payload.
     Empty,
 }
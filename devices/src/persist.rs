@@ -0,0 +1,334 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Saving and restoring the state of the emulated devices.
+//!
+//! Every device implements `Persist`, which converts it to and from a state structure holding
+//! everything the guest can observe. State structures are declared with `versioned_state!`: each
+//! field records the version of the state it was introduced in, so a state saved by an older
+//! build can still be restored, with the fields it does not know about set to their default.
+
+use std::io::{self, Read, Write};
+use std::result;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use sys_util::Error as SysError;
+
+use virtio::ActivateError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Failure while reading or writing a device state.
+    Io(io::Error),
+    /// The state was saved by a newer build than the one restoring it.
+    UnsupportedVersion(&'static str, u16),
+    /// The saved state contains a value which is not valid for its type.
+    InvalidValue(&'static str),
+    /// The saved state does not match the device it is restored into.
+    Mismatch(&'static str),
+    /// Failure while creating an event for the restored device.
+    EventFd(SysError),
+    /// Failure while activating a restored virtio device.
+    Activate(ActivateError),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// A type which can be written to and read back from a device state blob.
+pub trait Versionize: Sized {
+    /// Writes `self` to `writer`.
+    fn serialize(&self, writer: &mut Write) -> Result<()>;
+
+    /// Reads a value previously written with `serialize` from `reader`.
+    fn deserialize(reader: &mut Read) -> Result<Self>;
+}
+
+/// A device whose state can be saved and then used to rebuild an identical device.
+pub trait Persist: Sized {
+    /// The versioned structure holding the state of the device.
+    type State: Versionize;
+    /// The host resources the device is rebuilt on top of, such as files and events.
+    type ConstructorArgs;
+    /// The error returned when the device cannot be rebuilt.
+    type Error;
+
+    /// Returns the current state of the device.
+    fn save(&self) -> Self::State;
+
+    /// Rebuilds a device from the host resources in `constructor_args` and a saved `state`.
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> result::Result<Self, Self::Error>;
+}
+
+impl Versionize for u8 {
+    fn serialize(&self, writer: &mut Write) -> Result<()> {
+        Ok(writer.write_u8(*self)?)
+    }
+
+    fn deserialize(reader: &mut Read) -> Result<Self> {
+        Ok(reader.read_u8()?)
+    }
+}
+
+impl Versionize for u16 {
+    fn serialize(&self, writer: &mut Write) -> Result<()> {
+        Ok(writer.write_u16::<LittleEndian>(*self)?)
+    }
+
+    fn deserialize(reader: &mut Read) -> Result<Self> {
+        Ok(reader.read_u16::<LittleEndian>()?)
+    }
+}
+
+impl Versionize for u32 {
+    fn serialize(&self, writer: &mut Write) -> Result<()> {
+        Ok(writer.write_u32::<LittleEndian>(*self)?)
+    }
+
+    fn deserialize(reader: &mut Read) -> Result<Self> {
+        Ok(reader.read_u32::<LittleEndian>()?)
+    }
+}
+
+impl Versionize for u64 {
+    fn serialize(&self, writer: &mut Write) -> Result<()> {
+        Ok(writer.write_u64::<LittleEndian>(*self)?)
+    }
+
+    fn deserialize(reader: &mut Read) -> Result<Self> {
+        Ok(reader.read_u64::<LittleEndian>()?)
+    }
+}
+
+impl Versionize for bool {
+    fn serialize(&self, writer: &mut Write) -> Result<()> {
+        (*self as u8).serialize(writer)
+    }
+
+    fn deserialize(reader: &mut Read) -> Result<Self> {
+        match u8::deserialize(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::InvalidValue("bool")),
+        }
+    }
+}
+
+impl<T: Versionize> Versionize for Vec<T> {
+    fn serialize(&self, writer: &mut Write) -> Result<()> {
+        (self.len() as u32).serialize(writer)?;
+        for item in self {
+            item.serialize(writer)?;
+        }
+        Ok(())
+    }
+
+    fn deserialize(reader: &mut Read) -> Result<Self> {
+        let len = u32::deserialize(reader)?;
+        // Don't preallocate based on the length: it comes from a blob we can't trust, and a
+        // truncated blob will fail on read anyway.
+        let mut items = Vec::new();
+        for _ in 0..len {
+            items.push(T::deserialize(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+/// Declares a device state structure and implements `Versionize` for it.
+///
+/// The structure is written prefixed by its version. Every field is annotated with the version it
+/// was introduced in; fields newer than the version of the state being read are not present in it
+/// and are set to their default value instead.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate devices;
+/// # fn main() {
+/// versioned_state! {
+///     /// State of a device which gained a register in its second version.
+///     pub struct DummyState(version 2) {
+///         pub status: u8 = since 1,
+///         pub control: u32 = since 2,
+///     }
+/// }
+/// assert_eq!(DummyState::VERSION, 2);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! versioned_state {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident(version $version:expr) {
+            $(
+                $(#[$field_attr:meta])*
+                pub $field:ident: $ty:ty = since $since:expr,
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Clone, Debug, Default, PartialEq)]
+        pub struct $name {
+            $(
+                $(#[$field_attr])*
+                pub $field: $ty,
+            )*
+        }
+
+        impl $name {
+            /// The version of the state written by this build.
+            pub const VERSION: u16 = $version;
+        }
+
+        impl $crate::persist::Versionize for $name {
+            fn serialize(&self, writer: &mut ::std::io::Write) -> $crate::persist::Result<()> {
+                $crate::persist::Versionize::serialize(&Self::VERSION, writer)?;
+                $(
+                    $crate::persist::Versionize::serialize(&self.$field, writer)?;
+                )*
+                Ok(())
+            }
+
+            fn deserialize(reader: &mut ::std::io::Read) -> $crate::persist::Result<Self> {
+                let version: u16 = $crate::persist::Versionize::deserialize(reader)?;
+                if version == 0 || version > Self::VERSION {
+                    return Err($crate::persist::Error::UnsupportedVersion(
+                        stringify!($name),
+                        version,
+                    ));
+                }
+                Ok($name {
+                    $(
+                        $field: if $since <= version {
+                            $crate::persist::Versionize::deserialize(reader)?
+                        } else {
+                            Default::default()
+                        },
+                    )*
+                })
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    versioned_state! {
+        pub struct OldState(version 1) {
+            pub status: u8 = since 1,
+            pub data: Vec<u16> = since 1,
+        }
+    }
+
+    versioned_state! {
+        pub struct NewState(version 2) {
+            pub status: u8 = since 1,
+            pub data: Vec<u16> = since 1,
+            pub enabled: bool = since 2,
+            pub address: u64 = since 2,
+        }
+    }
+
+    fn to_bytes<T: Versionize>(state: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        state.serialize(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_primitives() {
+        let mut bytes = Vec::new();
+        0x12u8.serialize(&mut bytes).unwrap();
+        0x3456u16.serialize(&mut bytes).unwrap();
+        0x789a_bcdeu32.serialize(&mut bytes).unwrap();
+        0x0102_0304_0506_0708u64.serialize(&mut bytes).unwrap();
+        true.serialize(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 16);
+
+        let mut reader = bytes.as_slice();
+        assert_eq!(u8::deserialize(&mut reader).unwrap(), 0x12);
+        assert_eq!(u16::deserialize(&mut reader).unwrap(), 0x3456);
+        assert_eq!(u32::deserialize(&mut reader).unwrap(), 0x789a_bcde);
+        assert_eq!(
+            u64::deserialize(&mut reader).unwrap(),
+            0x0102_0304_0506_0708
+        );
+        assert_eq!(bool::deserialize(&mut reader).unwrap(), true);
+
+        // Nothing left to read.
+        match u8::deserialize(&mut reader) {
+            Err(Error::Io(_)) => (),
+            _ => panic!("Expected an I/O error."),
+        }
+
+        match bool::deserialize(&mut [2u8].as_ref()) {
+            Err(Error::InvalidValue("bool")) => (),
+            _ => panic!("Expected an invalid value error."),
+        }
+    }
+
+    #[test]
+    fn test_same_version() {
+        let state = NewState {
+            status: 3,
+            data: vec![1, 2, 3],
+            enabled: true,
+            address: 0xd000_0000,
+        };
+        let bytes = to_bytes(&state);
+        assert_eq!(NewState::deserialize(&mut bytes.as_slice()).unwrap(), state);
+    }
+
+    #[test]
+    fn test_older_version() {
+        let old_state = OldState {
+            status: 3,
+            data: vec![4, 5],
+        };
+        let bytes = to_bytes(&old_state);
+
+        // The fields introduced in version 2 take their default value.
+        let new_state = NewState::deserialize(&mut bytes.as_slice()).unwrap();
+        assert_eq!(new_state.status, 3);
+        assert_eq!(new_state.data, vec![4, 5]);
+        assert_eq!(new_state.enabled, false);
+        assert_eq!(new_state.address, 0);
+    }
+
+    #[test]
+    fn test_newer_version() {
+        let bytes = to_bytes(&NewState::default());
+        match OldState::deserialize(&mut bytes.as_slice()) {
+            Err(Error::UnsupportedVersion("OldState", 2)) => (),
+            _ => panic!("Expected an unsupported version error."),
+        }
+
+        match OldState::deserialize(&mut [0u8, 0].as_ref()) {
+            Err(Error::UnsupportedVersion("OldState", 0)) => (),
+            _ => panic!("Expected an unsupported version error."),
+        }
+    }
+
+    #[test]
+    fn test_truncated_state() {
+        let bytes = to_bytes(&NewState {
+            data: vec![1, 2, 3],
+            ..Default::default()
+        });
+        match NewState::deserialize(&mut &bytes[..bytes.len() - 1]) {
+            Err(Error::Io(_)) => (),
+            _ => panic!("Expected an I/O error."),
+        }
+    }
+}
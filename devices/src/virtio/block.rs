@@ -15,6 +15,7 @@ use std::result;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
+use std::time::Instant;
 
 use super::{
     ActivateError, ActivateResult, DescriptorChain, EpollHandlerPayload, Queue, VirtioDevice,
@@ -22,7 +23,9 @@ use super::{
 };
 use logger::{Metric, METRICS};
 use memory_model::{GuestAddress, GuestMemory, GuestMemoryError};
+use persist::Persist;
 use rate_limiter::{RateLimiter, TokenType};
+use sys_util::Error as SysError;
 use sys_util::EventFd;
 use sys_util::Result as SysResult;
 use virtio_gen::virtio_blk::*;
@@ -42,8 +45,10 @@ const QUEUE_AVAIL_EVENT: DeviceEventT = 0;
 const RATE_LIMITER_EVENT: DeviceEventT = 1;
 // Backing file on the host has changed.
 pub const FS_UPDATE_EVENT: DeviceEventT = 2;
+// The I/O trace of the device was started or stopped.
+pub const TRACE_UPDATE_EVENT: DeviceEventT = 3;
 // Number of DeviceEventT events supported by this implementation.
-pub const BLOCK_EVENTS_COUNT: usize = 3;
+pub const BLOCK_EVENTS_COUNT: usize = 4;
 
 #[derive(Debug)]
 enum Error {
@@ -93,6 +98,19 @@ enum RequestType {
     Unsupported(u32),
 }
 
+impl RequestType {
+    // The name of the request type in the I/O traces.
+    fn trace_name(&self) -> &'static str {
+        match *self {
+            RequestType::In => "read",
+            RequestType::Out => "write",
+            RequestType::Flush => "flush",
+            RequestType::GetDeviceID => "get_id",
+            RequestType::Unsupported(_) => "unsupported",
+        }
+    }
+}
+
 fn request_type(mem: &GuestMemory, desc_addr: GuestAddress) -> result::Result<RequestType, Error> {
     let type_ = mem
         .read_obj_from_addr(desc_addr)
@@ -247,6 +265,7 @@ struct BlockEpollHandler {
     queue_evt: EventFd,
     rate_limiter: RateLimiter,
     disk_image_id: Vec<u8>,
+    io_trace: Option<File>,
 }
 
 impl BlockEpollHandler {
@@ -284,6 +303,7 @@ impl BlockEpollHandler {
                             break;
                         }
                     }
+                    let start = Instant::now();
                     let status =
                         match request.execute(&mut self.disk_image, &self.mem, &self.disk_image_id)
                         {
@@ -303,6 +323,7 @@ impl BlockEpollHandler {
                     self.mem
                         .write_obj_at_addr(status, request.status_addr)
                         .unwrap();
+                    Self::trace_request(&mut self.io_trace, &request, start, status);
                 }
                 Err(e) => {
                     error!("Failed to parse available descriptor chain: {:?}", e);
@@ -325,6 +346,34 @@ impl BlockEpollHandler {
         used_count > 0
     }
 
+    // Appends a JSON line describing the executed `request` to the I/O trace, if one is running.
+    // A trace which fails to write is stopped.
+    fn trace_request(io_trace: &mut Option<File>, request: &Request, start: Instant, status: u32) {
+        let result = match io_trace.as_mut() {
+            Some(file) => {
+                let latency = start.elapsed();
+                let line = format!(
+                    concat!(
+                        "{{\"type\":\"{}\",\"offset\":{},\"len\":{},",
+                        "\"latency_us\":{},\"status\":{}}}\n"
+                    ),
+                    request.request_type.trace_name(),
+                    request.sector << SECTOR_SHIFT,
+                    request.data_len,
+                    latency.as_secs() * 1_000_000 + u64::from(latency.subsec_micros()),
+                    status
+                );
+                file.write_all(line.as_bytes())
+            }
+            None => return,
+        };
+        if let Err(e) = result {
+            error!("Failed to write request to I/O trace: {:?}", e);
+            METRICS.block.trace_fails.inc();
+            *io_trace = None;
+        }
+    }
+
     fn signal_used_queue(&self) {
         self.interrupt_status
             .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
@@ -377,6 +426,14 @@ impl EpollHandler for BlockEpollHandler {
                     panic!("Received update disk image event with empty payload.")
                 }
             }
+            TRACE_UPDATE_EVENT => {
+                if let EpollHandlerPayload::DriveTracePayload(io_trace) = payload {
+                    self.io_trace = io_trace;
+                } else {
+                    // This path can only be reached if we have a logical problem in our code.
+                    panic!("Received update I/O trace event with a wrong payload.")
+                }
+            }
             _ => panic!("Unknown event type was received."),
         }
     }
@@ -563,6 +620,7 @@ impl VirtioDevice for Block {
                 queue_evt,
                 rate_limiter: self.rate_limiter.take().unwrap_or_default(),
                 disk_image_id,
+                io_trace: None,
             };
             let rate_limiter_rawfd = handler.rate_limiter.as_raw_fd();
 
@@ -604,6 +662,51 @@ impl VirtioDevice for Block {
     }
 }
 
+versioned_state! {
+    /// The features negotiated with the guest and the configuration space it has seen.
+    pub struct BlockState(version 1) {
+        pub avail_features: u64 = since 1,
+        pub acked_features: u64 = since 1,
+        pub config_space: Vec<u8> = since 1,
+    }
+}
+
+/// The host resources a restored block device is built on top of.
+pub struct BlockConstructorArgs {
+    pub disk_image: File,
+    pub epoll_config: EpollConfig,
+    pub rate_limiter: Option<RateLimiter>,
+}
+
+impl Persist for Block {
+    type State = BlockState;
+    type ConstructorArgs = BlockConstructorArgs;
+    type Error = SysError;
+
+    fn save(&self) -> BlockState {
+        BlockState {
+            avail_features: self.avail_features,
+            acked_features: self.acked_features,
+            config_space: self.config_space.clone(),
+        }
+    }
+
+    fn restore(constructor_args: BlockConstructorArgs, state: &BlockState) -> SysResult<Self> {
+        // The read-only flag is part of the saved features.
+        let mut block = Block::new(
+            constructor_args.disk_image,
+            false,
+            constructor_args.epoll_config,
+            constructor_args.rate_limiter,
+        )?;
+        block.avail_features = state.avail_features;
+        block.acked_features = state.acked_features;
+        // Keep exposing the capacity the guest has seen, even if the disk was resized since.
+        block.config_space = state.config_space.clone();
+        Ok(block)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate tempfile;
@@ -711,6 +814,7 @@ mod tests {
                 queue_evt,
                 rate_limiter: RateLimiter::default(),
                 disk_image_id,
+                io_trace: None,
             },
             vq,
         )
@@ -1029,6 +1133,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_persist() {
+        let mut dummy = DummyBlock::new(true);
+        let b = dummy.block();
+        b.ack_features(0, 1u32 << VIRTIO_F_VERSION_1);
+        let state = b.save();
+
+        let epoll_raw_fd = epoll::create(true).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        let f: File = tempfile().unwrap();
+        // The guest keeps seeing the size of the disk it was using when the state was saved.
+        f.set_len(0x2000).unwrap();
+        let restored = Block::restore(
+            BlockConstructorArgs {
+                disk_image: f,
+                epoll_config: EpollConfig::new(0, epoll_raw_fd, sender),
+                rate_limiter: None,
+            },
+            &state,
+        )
+        .unwrap();
+        unsafe { libc::close(epoll_raw_fd) };
+
+        assert_eq!(restored.save(), state);
+        assert_ne!(restored.avail_features & (1u64 << VIRTIO_BLK_F_RO), 0);
+        assert_eq!(restored.acked_features, 1u64 << VIRTIO_F_VERSION_1);
+        let mut num_sectors = [0u8; 4];
+        restored.read_config(0, &mut num_sectors);
+        assert_eq!([0x08, 0x00, 0x00, 0x00], num_sectors);
+    }
+
     #[test]
     #[should_panic]
     fn test_invalid_event_handler() {
@@ -1051,6 +1186,69 @@ mod tests {
         h.handle_event(FS_UPDATE_EVENT, 0, EpollHandlerPayload::Empty);
     }
 
+    #[test]
+    #[should_panic]
+    fn test_trace_update_event_error() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, _vq) = default_test_blockepollhandler(&m);
+        // This should panic because payload is empty for event type TRACE_UPDATE_EVENT.
+        h.handle_event(TRACE_UPDATE_EVENT, 0, EpollHandlerPayload::Empty);
+    }
+
+    #[test]
+    fn test_io_trace() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, vq) = default_test_blockepollhandler(&m);
+
+        let mut f: File = tempfile().unwrap();
+        h.handle_event(
+            TRACE_UPDATE_EVENT,
+            0,
+            EpollHandlerPayload::DriveTracePayload(Some(f.try_clone().unwrap())),
+        );
+
+        // Set up a flush request.
+        for i in 0..3 {
+            vq.avail.ring[i].set(i as u16);
+            vq.dtable[i].set(
+                (0x1000 * (i + 1)) as u64,
+                0x1000,
+                VIRTQ_DESC_F_NEXT,
+                (i + 1) as u16,
+            );
+        }
+        vq.dtable[1]
+            .flags
+            .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+        vq.dtable[2].flags.set(VIRTQ_DESC_F_WRITE);
+        vq.avail.idx.set(1);
+        m.write_obj_at_addr::<u32>(VIRTIO_BLK_T_FLUSH, GuestAddress(0x1000))
+            .unwrap();
+        m.write_obj_at_addr::<u64>(0, GuestAddress(0x1000 + 8))
+            .unwrap();
+
+        invoke_handler_for_queue_event(&mut h);
+        assert_eq!(vq.used.idx.get(), 1);
+
+        // Requests are no longer traced once the trace is stopped.
+        h.handle_event(
+            TRACE_UPDATE_EVENT,
+            0,
+            EpollHandlerPayload::DriveTracePayload(None),
+        );
+        vq.used.idx.set(0);
+        h.set_queue(0, vq.create_queue());
+        invoke_handler_for_queue_event(&mut h);
+        assert_eq!(vq.used.idx.get(), 1);
+
+        let mut trace = String::new();
+        f.seek(SeekFrom::Start(0)).unwrap();
+        f.read_to_string(&mut trace).unwrap();
+        assert_eq!(trace.lines().count(), 1);
+        assert!(trace.starts_with(r#"{"type":"flush","offset":0,"len":4096,"latency_us":"#));
+        assert!(trace.ends_with(&format!(",\"status\":{}}}\n", VIRTIO_BLK_S_OK)));
+    }
+
     #[test]
     fn test_handler() {
         let m = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
@@ -12,6 +12,7 @@ use byteorder::{ByteOrder, LittleEndian};
 
 use super::*;
 use memory_model::{GuestAddress, GuestMemory};
+use persist::{self, Persist};
 use sys_util::{EventFd, Result};
 use BusDevice;
 
@@ -90,6 +91,7 @@ pub struct MmioDevice {
 
     features_select: u32,
     acked_features_select: u32,
+    acked_features: u64,
     queue_select: u32,
     interrupt_status: Arc<AtomicUsize>,
     interrupt_evt: Option<EventFd>,
@@ -117,6 +119,7 @@ impl MmioDevice {
             device_activated: false,
             features_select: 0,
             acked_features_select: 0,
+            acked_features: 0,
             queue_select: 0,
             interrupt_status: Arc::new(AtomicUsize::new(0)),
             interrupt_evt: Some(EventFd::new()?),
@@ -140,6 +143,34 @@ impl MmioDevice {
         self.interrupt_evt.as_ref()
     }
 
+    /// Returns the virtio feature bits the device offers to the guest driver.
+    pub fn offered_features(&self) -> u64 {
+        // VIRTIO_F_VERSION_1 is offered by the transport on top of the device features.
+        let low = self.device.features(0) as u64;
+        let high = (self.device.features(1) | 0x1) as u64;
+        low | high << 32
+    }
+
+    /// Returns the virtio feature bits the guest driver acknowledged.
+    pub fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    /// Returns the queues of the device, with the sizes selected by the guest driver.
+    pub fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn ack_features(&mut self, value: u32) {
+        self.device.ack_features(self.acked_features_select, value);
+        // Only the first two pages of feature bits are defined.
+        match self.acked_features_select {
+            0 => self.acked_features = (self.acked_features & !0xffff_ffff) | value as u64,
+            1 => self.acked_features = (self.acked_features & 0xffff_ffff) | (value as u64) << 32,
+            _ => (),
+        }
+    }
+
     fn is_driver_ready(&self) -> bool {
         let ready_bits = DEVICE_ACKNOWLEDGE | DEVICE_DRIVER | DEVICE_DRIVER_OK | DEVICE_FEATURES_OK;
         self.driver_status == ready_bits && self.driver_status & DEVICE_FAILED == 0
@@ -153,6 +184,22 @@ impl MmioDevice {
         }
     }
 
+    fn activate(&mut self) -> ActivateResult {
+        if let Some(ref interrupt_evt) = self.interrupt_evt {
+            if let Some(mem) = self.mem.take() {
+                self.device.activate(
+                    mem,
+                    interrupt_evt.try_clone().map_err(ActivateError::TryClone)?,
+                    self.interrupt_status.clone(),
+                    self.queues.clone(),
+                    self.queue_evts.split_off(0),
+                )?;
+                self.device_activated = true;
+            }
+        }
+        Ok(())
+    }
+
     fn with_queue<U, F>(&self, d: U, f: F) -> U
     where
         F: FnOnce(&Queue) -> U,
@@ -224,7 +271,7 @@ impl BusDevice for MmioDevice {
                 let v = LittleEndian::read_u32(data);
                 match offset {
                     0x14 => self.features_select = v,
-                    0x20 => self.device.ack_features(self.acked_features_select, v),
+                    0x20 => self.ack_features(v),
                     0x24 => self.acked_features_select = v,
                     0x30 => self.queue_select = v,
                     0x38 => mut_q = self.with_queue_mut(|q| q.size = v as u16),
@@ -262,20 +309,7 @@ impl BusDevice for MmioDevice {
         }
 
         if !self.device_activated && self.is_driver_ready() && self.are_queues_valid() {
-            if let Some(ref interrupt_evt) = self.interrupt_evt {
-                if let Some(mem) = self.mem.take() {
-                    self.device
-                        .activate(
-                            mem,
-                            interrupt_evt.try_clone().expect("Failed to clone eventfd"),
-                            self.interrupt_status.clone(),
-                            self.queues.clone(),
-                            self.queue_evts.split_off(0),
-                        )
-                        .expect("Failed to activate device");
-                    self.device_activated = true;
-                }
-            }
+            self.activate().expect("Failed to activate device");
         }
     }
 
@@ -289,6 +323,96 @@ impl BusDevice for MmioDevice {
     }
 }
 
+versioned_state! {
+    /// The registers of the virtio MMIO transport and the configuration of the device queues.
+    pub struct MmioDeviceState(version 2) {
+        pub device_activated: bool = since 1,
+        pub features_select: u32 = since 1,
+        pub acked_features_select: u32 = since 1,
+        pub acked_features: u64 = since 2,
+        pub queue_select: u32 = since 1,
+        pub interrupt_status: u32 = since 1,
+        pub driver_status: u32 = since 1,
+        pub config_generation: u32 = since 1,
+        pub queues: Vec<QueueState> = since 1,
+    }
+}
+
+/// The guest memory and the already restored virtio device a restored transport drives.
+pub struct MmioDeviceConstructorArgs {
+    pub mem: GuestMemory,
+    pub device: Box<VirtioDevice>,
+}
+
+impl Persist for MmioDevice {
+    type State = MmioDeviceState;
+    type ConstructorArgs = MmioDeviceConstructorArgs;
+    type Error = persist::Error;
+
+    fn save(&self) -> MmioDeviceState {
+        MmioDeviceState {
+            device_activated: self.device_activated,
+            features_select: self.features_select,
+            acked_features_select: self.acked_features_select,
+            acked_features: self.acked_features,
+            queue_select: self.queue_select,
+            interrupt_status: self.interrupt_status.load(Ordering::SeqCst) as u32,
+            driver_status: self.driver_status,
+            config_generation: self.config_generation,
+            queues: self.queues.iter().map(|q| q.save()).collect(),
+        }
+    }
+
+    fn restore(
+        constructor_args: MmioDeviceConstructorArgs,
+        state: &MmioDeviceState,
+    ) -> persist::Result<Self> {
+        let mem = constructor_args.mem;
+        let mut mmio_device = MmioDevice::new(mem.clone(), constructor_args.device)
+            .map_err(persist::Error::EventFd)?;
+        if state.queues.len() != mmio_device.queues.len() {
+            return Err(persist::Error::Mismatch("number of virtio queues"));
+        }
+
+        mmio_device.features_select = state.features_select;
+        mmio_device.acked_features_select = state.acked_features_select;
+        mmio_device.acked_features = state.acked_features;
+        mmio_device.queue_select = state.queue_select;
+        mmio_device
+            .interrupt_status
+            .store(state.interrupt_status as usize, Ordering::SeqCst);
+        mmio_device.driver_status = state.driver_status;
+        mmio_device.config_generation = state.config_generation;
+
+        for (queue, queue_state) in mmio_device.queues.iter_mut().zip(state.queues.iter()) {
+            let mut queue_state = queue_state.clone();
+            if state.device_activated {
+                // Once the device is activated it works on its own copy of the queues, so the
+                // saved indexes are stale. The used ring is the source of truth: every
+                // descriptor chain the device consumed was also returned to the guest.
+                let used_idx_addr = GuestAddress(queue_state.used_ring as usize)
+                    .checked_add(2)
+                    .ok_or(persist::Error::InvalidValue("virtio queue used ring"))?;
+                let used_idx: u16 = mem
+                    .read_obj_from_addr(used_idx_addr)
+                    .map_err(|_| persist::Error::InvalidValue("virtio queue used ring"))?;
+                queue_state.next_avail = used_idx;
+                queue_state.next_used = used_idx;
+            }
+            *queue = Queue::restore(queue.get_max_size(), &queue_state)?;
+        }
+
+        if state.device_activated {
+            if !mmio_device.are_queues_valid() {
+                return Err(persist::Error::InvalidValue("virtio queue"));
+            }
+            mmio_device.activate().map_err(persist::Error::Activate)?;
+        }
+
+        Ok(mmio_device)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use byteorder::{ByteOrder, LittleEndian};
@@ -383,6 +507,25 @@ mod tests {
         assert!(!d.are_queues_valid());
     }
 
+    #[test]
+    fn test_features() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let mut d = MmioDevice::new(m, Box::new(DummyDevice::new())).unwrap();
+
+        // The dummy device offers no features of its own.
+        assert_eq!(d.offered_features(), 0x1_0000_0000);
+        assert_eq!(d.acked_features(), 0);
+
+        d.queue_select = 1;
+        assert!(d.with_queue_mut(|q| q.size = 8));
+        let sizes: Vec<(u16, u16)> = d
+            .queues()
+            .iter()
+            .map(|q| (q.get_max_size(), q.size))
+            .collect();
+        assert_eq!(sizes, vec![(16, 0), (32, 8)]);
+    }
+
     #[test]
     fn test_bus_device_read() {
         let m = GuestMemory::new(&[(GuestAddress(0), 0x1000)]).unwrap();
@@ -475,6 +618,16 @@ mod tests {
         LittleEndian::write_u32(&mut buf[..], 1);
         d.write(0x20, &buf[..]);
         assert_eq!(unsafe { *p }, 0x124);
+        // Pages past the defined feature bits are not recorded.
+        assert_eq!(d.acked_features(), 0);
+
+        d.acked_features_select = 1;
+        LittleEndian::write_u32(&mut buf[..], 1);
+        d.write(0x20, &buf[..]);
+        d.acked_features_select = 0;
+        LittleEndian::write_u32(&mut buf[..], 0x20);
+        d.write(0x20, &buf[..]);
+        assert_eq!(d.acked_features(), 0x1_0000_0020);
 
         d.acked_features_select = 0;
         LittleEndian::write_u32(&mut buf[..], 2);
@@ -588,4 +741,70 @@ mod tests {
         // a warning path.
         d.write(0x44, &buf[..]);
     }
+
+    #[test]
+    fn test_save_restore() {
+        let m = GuestMemory::new(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let mut d = MmioDevice::new(m.clone(), Box::new(DummyDevice::new())).unwrap();
+
+        d.features_select = 1;
+        d.queue_select = 1;
+        d.interrupt_status
+            .store(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        for q in d.queues.iter_mut() {
+            q.size = 16;
+            q.ready = true;
+        }
+
+        let state = d.save();
+        assert!(!state.device_activated);
+        assert_eq!(state.queues.len(), 2);
+
+        let restored = MmioDevice::restore(
+            MmioDeviceConstructorArgs {
+                mem: m.clone(),
+                device: Box::new(DummyDevice::new()),
+            },
+            &state,
+        )
+        .unwrap();
+        assert_eq!(restored.save(), state);
+        assert!(!restored.device_activated);
+
+        // Activate the device by setting the driver status.
+        let mut buf = vec![0; 4];
+        LittleEndian::write_u32(
+            &mut buf[..],
+            DEVICE_ACKNOWLEDGE | DEVICE_DRIVER | DEVICE_DRIVER_OK | DEVICE_FEATURES_OK,
+        );
+        d.write(0x70, &buf[..]);
+        assert!(d.device_activated);
+
+        let state = d.save();
+        assert!(state.device_activated);
+        let restored = MmioDevice::restore(
+            MmioDeviceConstructorArgs {
+                mem: m.clone(),
+                device: Box::new(DummyDevice::new()),
+            },
+            &state,
+        )
+        .unwrap();
+        assert!(restored.device_activated);
+        assert!(restored.mem.is_none());
+
+        // The state must match the queues of the device it is restored into.
+        let mut state = d.save();
+        state.queues.pop();
+        match MmioDevice::restore(
+            MmioDeviceConstructorArgs {
+                mem: m,
+                device: Box::new(DummyDevice::new()),
+            },
+            &state,
+        ) {
+            Err(persist::Error::Mismatch(_)) => (),
+            _ => panic!("Expected a mismatch error."),
+        }
+    }
 }
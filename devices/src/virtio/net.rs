@@ -8,6 +8,7 @@
 use epoll;
 use libc::EAGAIN;
 use std::cmp;
+use std::fs::File;
 #[cfg(not(test))]
 use std::io::Read;
 use std::io::{self, Write};
@@ -28,7 +29,8 @@ use dumbo::ns::MmdsNetworkStack;
 use logger::{Metric, METRICS};
 use memory_model::{GuestAddress, GuestMemory};
 use net_gen;
-use net_util::{MacAddr, Tap, TapError, MAC_ADDR_LEN};
+use net_util::{MacAddr, PcapWriter, Tap, TapError, MAC_ADDR_LEN};
+use persist::Persist;
 use rate_limiter::{RateLimiter, TokenType};
 use sys_util::EventFd;
 use virtio_gen::virtio_config::*;
@@ -53,8 +55,10 @@ const TX_QUEUE_EVENT: DeviceEventT = 2;
 const RX_RATE_LIMITER_EVENT: DeviceEventT = 3;
 // tx rate limiter budget is now available.
 const TX_RATE_LIMITER_EVENT: DeviceEventT = 4;
+// The packet capture of the device was started or stopped.
+pub const CAPTURE_UPDATE_EVENT: DeviceEventT = 5;
 // Number of DeviceEventT events supported by this implementation.
-pub const NET_EVENTS_COUNT: usize = 5;
+pub const NET_EVENTS_COUNT: usize = 6;
 
 #[derive(Debug)]
 pub enum Error {
@@ -156,6 +160,7 @@ struct NetEpollHandler {
     #[allow(dead_code)]
     acked_features: u64,
     mmds_ns: Option<MmdsNetworkStack>,
+    capture: Option<PcapWriter<File>>,
 
     #[cfg(test)]
     test_mutators: tests::TestMutators,
@@ -266,12 +271,29 @@ impl NetEpollHandler {
         if write_count >= self.rx.bytes_read {
             METRICS.net.rx_bytes_count.add(write_count);
             METRICS.net.rx_packets_count.inc();
+            Self::capture_frame(&mut self.capture, &self.rx.frame_buf[..self.rx.bytes_read]);
             return true;
         } else {
             return false;
         }
     }
 
+    // Mirrors the frame in `frame_buf` to the packet capture, if one is running. A capture which
+    // fails to write is stopped.
+    fn capture_frame(capture: &mut Option<PcapWriter<File>>, frame_buf: &[u8]) {
+        if frame_buf.len() < vnet_hdr_len() {
+            return;
+        }
+        if let Some(Err(e)) = capture
+            .as_mut()
+            .map(|writer| writer.write_frame(frame_bytes_from_buf(frame_buf)))
+        {
+            error!("Failed to write frame to packet capture: {:?}", e);
+            METRICS.net.capture_fails.inc();
+            *capture = None;
+        }
+    }
+
     // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
     //
     // `frame_buf` should contain the frame bytes in a slice of exact length.
@@ -446,6 +468,7 @@ impl NetEpollHandler {
                 }
             }
 
+            Self::capture_frame(&mut self.capture, &self.tx.frame_buf[..read_count]);
             if Self::write_to_mmds_or_tap(
                 self.mmds_ns.as_mut(),
                 &mut self.tx.rate_limiter,
@@ -489,7 +512,7 @@ impl NetEpollHandler {
 }
 
 impl EpollHandler for NetEpollHandler {
-    fn handle_event(&mut self, device_event: DeviceEventT, _: u32, _: EpollHandlerPayload) {
+    fn handle_event(&mut self, device_event: DeviceEventT, _: u32, payload: EpollHandlerPayload) {
         match device_event {
             RX_TAP_EVENT => {
                 METRICS.net.rx_tap_event_count.inc();
@@ -568,6 +591,14 @@ impl EpollHandler for NetEpollHandler {
                     }
                 }
             }
+            CAPTURE_UPDATE_EVENT => {
+                if let EpollHandlerPayload::NetCapturePayload(capture) = payload {
+                    self.capture = capture;
+                } else {
+                    // This path can only be reached if we have a logical problem in our code.
+                    panic!("Received update packet capture event with a wrong payload.")
+                }
+            }
             _ => panic!("Unknown event type was received."),
         }
     }
@@ -805,6 +836,7 @@ impl VirtioDevice for Net {
                 interrupt_evt,
                 acked_features: self.acked_features,
                 mmds_ns,
+                capture: None,
 
                 #[cfg(test)]
                 test_mutators: tests::TestMutators::default(),
@@ -891,8 +923,59 @@ impl VirtioDevice for Net {
     }
 }
 
+versioned_state! {
+    /// The features negotiated with the guest and the configuration space it has seen.
+    pub struct NetState(version 1) {
+        pub avail_features: u64 = since 1,
+        pub acked_features: u64 = since 1,
+        pub config_space: Vec<u8> = since 1,
+        pub allow_mmds_requests: bool = since 1,
+    }
+}
+
+/// The host resources a restored network device is built on top of.
+pub struct NetConstructorArgs {
+    pub tap: Tap,
+    pub epoll_config: EpollConfig,
+    pub rx_rate_limiter: Option<RateLimiter>,
+    pub tx_rate_limiter: Option<RateLimiter>,
+}
+
+impl Persist for Net {
+    type State = NetState;
+    type ConstructorArgs = NetConstructorArgs;
+    type Error = Error;
+
+    fn save(&self) -> NetState {
+        NetState {
+            avail_features: self.avail_features,
+            acked_features: self.acked_features,
+            config_space: self.config_space.clone(),
+            allow_mmds_requests: self.allow_mmds_requests,
+        }
+    }
+
+    fn restore(constructor_args: NetConstructorArgs, state: &NetState) -> Result<Self> {
+        // The guest MAC address is part of the saved configuration space.
+        let mut net = Net::new_with_tap(
+            constructor_args.tap,
+            None,
+            constructor_args.epoll_config,
+            constructor_args.rx_rate_limiter,
+            constructor_args.tx_rate_limiter,
+            state.allow_mmds_requests,
+        )?;
+        net.avail_features = state.avail_features;
+        net.acked_features = state.acked_features;
+        net.config_space = state.config_space.clone();
+        Ok(net)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    extern crate tempfile;
+
     use std::sync::mpsc::Receiver;
     use std::thread;
     use std::time::Duration;
@@ -900,6 +983,7 @@ mod tests {
 
     use libc;
 
+    use self::tempfile::tempfile;
     use super::*;
     use memory_model::GuestAddress;
     use virtio::queue::tests::*;
@@ -1084,6 +1168,7 @@ mod tests {
                 interrupt_evt,
                 acked_features: n.acked_features,
                 mmds_ns: Some(MmdsNetworkStack::new_with_defaults()),
+                capture: None,
                 test_mutators,
             },
             txq,
@@ -1339,6 +1424,19 @@ mod tests {
             1,
             h.read_from_mmds_or_tap().unwrap()
         );
+
+        // The interfaces which don't allow MMDS requests have no MMDS network stack, so the
+        // same frame goes to the tap instead.
+        check_metric_after_block!(
+            &METRICS.mmds.rx_accepted,
+            0,
+            assert!(!NetEpollHandler::write_to_mmds_or_tap(
+                None,
+                &mut h.tx.rate_limiter,
+                &h.tx.frame_buf[..packet_len],
+                &mut h.tap,
+            ))
+        );
     }
 
     #[test]
@@ -1376,6 +1474,47 @@ mod tests {
         );
     }
 
+    #[test]
+    #[should_panic]
+    fn test_capture_update_empty_payload() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, _txq, _rxq) = default_test_netepollhandler(&mem, TestMutators::default());
+        // This should panic because the payload is empty for event type CAPTURE_UPDATE_EVENT.
+        h.handle_event(CAPTURE_UPDATE_EVENT, 0, EpollHandlerPayload::Empty);
+    }
+
+    #[test]
+    fn test_packet_capture() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let (mut h, txq, _rxq) = default_test_netepollhandler(&mem, TestMutators::default());
+
+        let f: File = tempfile().unwrap();
+        let capture = PcapWriter::new(f.try_clone().unwrap(), 100, None).unwrap();
+        h.handle_event(
+            CAPTURE_UPDATE_EVENT,
+            0,
+            EpollHandlerPayload::NetCapturePayload(Some(capture)),
+        );
+
+        let daddr = 0x2000;
+        txq.avail.idx.set(1);
+        txq.avail.ring[0].set(0);
+        txq.dtable[0].set(daddr, (vnet_hdr_len() + 200) as u32, 0, 0);
+        h.tx.queue_evt.write(1).unwrap();
+        h.handle_event(TX_QUEUE_EVENT, 0, EpollHandlerPayload::Empty);
+        assert_eq!(txq.used.idx.get(), 1);
+
+        // The pcap header and the frame, without its vnet header and truncated to 100 bytes.
+        assert_eq!(f.metadata().unwrap().len(), 24 + 16 + 100);
+
+        h.handle_event(
+            CAPTURE_UPDATE_EVENT,
+            0,
+            EpollHandlerPayload::NetCapturePayload(None),
+        );
+        assert!(h.capture.is_none());
+    }
+
     #[test]
     fn test_handler() {
         let mem = GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
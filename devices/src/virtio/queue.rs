@@ -10,6 +10,7 @@ use std::num::Wrapping;
 use std::sync::atomic::{fence, Ordering};
 
 use memory_model::{GuestAddress, GuestMemory};
+use persist::{self, Persist};
 
 pub(super) const VIRTQ_DESC_F_NEXT: u16 = 0x1;
 pub(super) const VIRTQ_DESC_F_WRITE: u16 = 0x2;
@@ -380,6 +381,56 @@ impl Queue {
     }
 }
 
+versioned_state! {
+    /// The configuration of a virtio queue and the position of the device in its rings.
+    pub struct QueueState(version 1) {
+        pub max_size: u16 = since 1,
+        pub size: u16 = since 1,
+        pub ready: bool = since 1,
+        pub desc_table: u64 = since 1,
+        pub avail_ring: u64 = since 1,
+        pub used_ring: u64 = since 1,
+        pub next_avail: u16 = since 1,
+        pub next_used: u16 = since 1,
+    }
+}
+
+impl Persist for Queue {
+    type State = QueueState;
+    /// The maximum size of the queue supported by the restored device.
+    type ConstructorArgs = u16;
+    type Error = persist::Error;
+
+    fn save(&self) -> QueueState {
+        QueueState {
+            max_size: self.max_size,
+            size: self.size,
+            ready: self.ready,
+            desc_table: self.desc_table.offset() as u64,
+            avail_ring: self.avail_ring.offset() as u64,
+            used_ring: self.used_ring.offset() as u64,
+            next_avail: self.next_avail.0,
+            next_used: self.next_used.0,
+        }
+    }
+
+    fn restore(max_size: u16, state: &QueueState) -> persist::Result<Self> {
+        if state.max_size != max_size {
+            return Err(persist::Error::Mismatch("virtio queue maximum size"));
+        }
+        Ok(Queue {
+            max_size,
+            size: state.size,
+            ready: state.ready,
+            desc_table: GuestAddress(state.desc_table as usize),
+            avail_ring: GuestAddress(state.avail_ring as usize),
+            used_ring: GuestAddress(state.used_ring as usize),
+            next_avail: Wrapping(state.next_avail),
+            next_used: Wrapping(state.next_used),
+        })
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     extern crate memory_model;
@@ -796,4 +847,26 @@ pub(crate) mod tests {
         assert_eq!(x.id, 1);
         assert_eq!(x.len, 0x1000);
     }
+
+    #[test]
+    fn test_queue_save_restore() {
+        let m = &GuestMemory::new(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+
+        let mut q = vq.create_queue();
+        q.add_used(m, 1, 0x1000);
+
+        let state = q.save();
+        assert_eq!(state.next_used, 1);
+        assert!(Queue::restore(32, &state).is_err());
+
+        let mut restored = Queue::restore(16, &state).unwrap();
+        assert!(restored.is_valid(m));
+        assert_eq!(restored.save(), state);
+
+        // The restored queue carries on from where the saved one stopped.
+        restored.add_used(m, 2, 0x1000);
+        assert_eq!(vq.used.idx.get(), 2);
+        assert_eq!(vq.used.ring[1].get().id, 2);
+    }
 }
@@ -10,6 +10,7 @@ use super::handle::*;
 use super::*;
 
 use memory_model::GuestMemory;
+use persist::Persist;
 use sys_util::EventFd;
 use vhost_backend::Vhost;
 use vhost_backend::Vsock as VhostVsockFd;
@@ -242,3 +243,46 @@ impl VirtioDevice for Vsock {
         Err(ActivateError::BadActivate)
     }
 }
+
+versioned_state! {
+    /// The context ID of the guest and the features negotiated with it.
+    pub struct VsockState(version 1) {
+        pub cid: u64 = since 1,
+        pub avail_features: u64 = since 1,
+        pub acked_features: u64 = since 1,
+        pub config_space: Vec<u8> = since 1,
+    }
+}
+
+/// The host resources a restored vsock device is built on top of.
+pub struct VsockConstructorArgs {
+    pub mem: GuestMemory,
+    pub epoll_config: VhostEpollConfig,
+}
+
+impl Persist for Vsock {
+    type State = VsockState;
+    type ConstructorArgs = VsockConstructorArgs;
+    type Error = Error;
+
+    fn save(&self) -> VsockState {
+        VsockState {
+            cid: self.cid,
+            avail_features: self.avail_features,
+            acked_features: self.acked_features,
+            config_space: self.config_space.clone(),
+        }
+    }
+
+    fn restore(constructor_args: VsockConstructorArgs, state: &VsockState) -> Result<Self> {
+        let mut vsock = Vsock::new(
+            state.cid,
+            &constructor_args.mem,
+            constructor_args.epoll_config,
+        )?;
+        vsock.avail_features = state.avail_features;
+        vsock.acked_features = state.acked_features;
+        vsock.config_space = state.config_space.clone();
+        Ok(vsock)
+    }
+}
@@ -5,26 +5,38 @@
 #![allow(missing_docs)]
 
 use std::convert::From;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
 use std::result::Result;
 
 use fc_util::timestamp_cycles;
 use logger::{Metric, METRICS};
+use mmds::MMDS;
 use net_util::MacAddr;
 use pdu::arp::{test_speculative_tpa, Error as ArpFrameError, EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
-use pdu::ethernet::{Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use pdu::ethernet::{
+    Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6,
+};
 use pdu::ipv4::{test_speculative_dst_addr, Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP};
+use pdu::ipv6::{self, Error as IPv6PacketError, IPv6Packet, IPV6_VERSION, PROTOCOL_ICMPV6};
+use pdu::ndp::{
+    solicited_node_address, AdvertisementFlags, Error as NdpMessageError, NeighborMessage,
+    NDP_HOP_LIMIT,
+};
 use pdu::tcp::Error as TcpSegmentError;
 use pdu::Incomplete;
-use tcp::handler::{self, RecvEvent, TcpIPv4Handler, WriteEvent};
+use tcp::handler::{self, RecvError, RecvEvent, TcpIPHandler, WriteEvent};
 use tcp::NextSegmentStatus;
 
 const DEFAULT_MAC_ADDR: &str = "06:01:23:45:67:01";
 const DEFAULT_IPV4_ADDR: [u8; 4] = [169, 254, 169, 254];
+// The link-local address which embeds the IPv4 one.
+const DEFAULT_IPV6_ADDR: [u16; 8] = [0xfe80, 0, 0, 0, 0, 0, 0xa9fe, 0xa9fe];
 const DEFAULT_TCP_PORT: u16 = 80;
 const DEFAULT_MAX_CONNECTIONS: usize = 30;
 const DEFAULT_MAX_PENDING_RESETS: usize = 100;
+// The responses of the MMDS can't go beyond the guest, so they can't be relayed elsewhere.
+const DEFAULT_HOP_LIMIT: u8 = 1;
 
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum WriteArpFrameError {
@@ -32,9 +44,17 @@ enum WriteArpFrameError {
     Ethernet(EthernetFrameError),
 }
 
+#[cfg_attr(test, derive(Debug, PartialEq))]
+enum WriteNeighborAdvertisementError {
+    Ethernet(EthernetFrameError),
+    IPv6Packet(IPv6PacketError),
+    Ndp(NdpMessageError),
+}
+
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum WritePacketError {
     IPv4Packet(IPv4PacketError),
+    IPv6Packet(IPv6PacketError),
     Ethernet(EthernetFrameError),
     TcpSegment(TcpSegmentError),
 }
@@ -43,6 +63,7 @@ impl From<handler::WriteNextError> for WritePacketError {
     fn from(error: handler::WriteNextError) -> Self {
         match error {
             handler::WriteNextError::IPv4Packet(inner) => WritePacketError::IPv4Packet(inner),
+            handler::WriteNextError::IPv6Packet(inner) => WritePacketError::IPv6Packet(inner),
             handler::WriteNextError::TcpSegment(inner) => WritePacketError::TcpSegment(inner),
         }
     }
@@ -56,57 +77,126 @@ pub struct MmdsNetworkStack {
     remote_mac_addr: MacAddr,
     // The IPv4 address of the MMDS server.
     ipv4_addr: Ipv4Addr,
+    // The IPv6 address of the MMDS server, when it also answers over IPv6.
+    ipv6_addr: Option<Ipv6Addr>,
     // We only remember the most recently received ARP request, and store the remote IPv4 address
     // here (we keep the remote MAC address in self.remote_mac_addr), to be used when the next
     // opportunity to send a frame presents itself.
     pending_arp_reply: Option<Ipv4Addr>,
+    // The same goes for the most recently received neighbor solicitation, the IPv6 counterpart of
+    // ARP requests.
+    pending_neighbor_advertisement: Option<Ipv6Addr>,
+    // The MAC addresses the guest can reach the MMDS from, or `None` for any address.
+    allowed_mac_addrs: Option<Vec<MacAddr>>,
     // This handles MMDS<->guest interaction at the TCP level.
-    tcp_handler: TcpIPv4Handler,
+    tcp_handler: TcpIPHandler,
 }
 
 impl MmdsNetworkStack {
     pub fn new(
         mac_addr: MacAddr,
         ipv4_addr: Ipv4Addr,
+        ipv6_addr: Option<Ipv6Addr>,
         tcp_port: u16,
         max_connections: NonZeroUsize,
         max_pending_resets: NonZeroUsize,
     ) -> Self {
+        let mut tcp_handler = TcpIPHandler::new(
+            ipv4_addr,
+            ipv6_addr,
+            tcp_port,
+            max_connections,
+            max_pending_resets,
+        );
+        tcp_handler.set_hop_limit(Some(DEFAULT_HOP_LIMIT));
+
         MmdsNetworkStack {
             mac_addr,
             remote_mac_addr: mac_addr,
             ipv4_addr,
+            ipv6_addr,
             pending_arp_reply: None,
-            tcp_handler: TcpIPv4Handler::new(
-                ipv4_addr,
-                tcp_port,
-                max_connections,
-                max_pending_resets,
-            ),
+            pending_neighbor_advertisement: None,
+            allowed_mac_addrs: None,
+            tcp_handler,
         }
     }
 
+    // Uses the IPv6 address, the hop limit and the allowed MAC addresses configured for the MMDS,
+    // and the defaults otherwise.
     pub fn new_with_defaults() -> Self {
+        // If another thread poisoned the lock, we abort the execution.
+        let mmds = MMDS
+            .lock()
+            .expect("Failed to configure the MMDS network stack due to poisoned lock");
+
         // The unwrap is safe if parse_str() is implemented properly.
         let mac_addr = MacAddr::parse_str(DEFAULT_MAC_ADDR).unwrap();
         let ipv4_addr = Ipv4Addr::from(DEFAULT_IPV4_ADDR);
+        let ipv6_addr = mmds
+            .ipv6_addr()
+            .unwrap_or(Ipv6Addr::from(DEFAULT_IPV6_ADDR));
 
         // The unwrap()s are safe because the given literals are greater than 0.
-        Self::new(
+        let mut ns = Self::new(
             mac_addr,
             ipv4_addr,
+            Some(ipv6_addr),
             DEFAULT_TCP_PORT,
             NonZeroUsize::new(DEFAULT_MAX_CONNECTIONS).unwrap(),
             NonZeroUsize::new(DEFAULT_MAX_PENDING_RESETS).unwrap(),
-        )
+        );
+        if let Some(hop_limit) = mmds.hop_limit() {
+            ns.set_hop_limit(hop_limit);
+        }
+        ns.set_allowed_mac_addrs(mmds.allowed_mac_addrs().map(|addrs| addrs.to_vec()));
+        ns
+    }
+
+    // Sets the TTL of the IPv4 packets, and the hop limit of the IPv6 packets, which carry the
+    // responses of the MMDS. It's 1 by default, so that a router on the way drops them. The
+    // neighbor advertisements keep the hop limit of 255 required by NDP.
+    pub fn set_hop_limit(&mut self, hop_limit: u8) {
+        self.tcp_handler.set_hop_limit(Some(hop_limit));
+    }
+
+    // Drops the frames for the MMDS which are not sent from one of allowed_mac_addrs, or lets
+    // the guest reach the MMDS from any MAC address with None.
+    pub fn set_allowed_mac_addrs(&mut self, allowed_mac_addrs: Option<Vec<MacAddr>>) {
+        self.allowed_mac_addrs = allowed_mac_addrs;
+    }
+
+    // Returns true if the guest can reach the MMDS from src_mac, and counts the frames which are
+    // dropped otherwise.
+    fn allows_src_mac(&self, src_mac: MacAddr) -> bool {
+        let allowed = match self.allowed_mac_addrs {
+            Some(ref allowed_mac_addrs) => allowed_mac_addrs.contains(&src_mac),
+            None => true,
+        };
+        if !allowed {
+            METRICS.mmds.rx_bad_src_mac.inc();
+        }
+        allowed
     }
 
     // This is the entry point into the MMDS network stack. The src slice should hold the contents
     // of an Ethernet frame (of that exact size, without the CRC).
     pub fn detour_frame(&mut self, src: &[u8]) -> bool {
-        // The frame cannot possibly contain an ARP request or IPv4 packet for the MMDS.
+        // The neighbor solicitations for the MMDS are sent to its solicited-node multicast
+        // address.
+        let maybe_ipv6 = match self.ipv6_addr {
+            Some(addr) => {
+                ipv6::test_speculative_dst_addr(src, addr)
+                    || ipv6::test_speculative_dst_addr(src, solicited_node_address(addr))
+            }
+            None => false,
+        };
+
+        // The frame cannot possibly contain an ARP request, an IPv4 packet, or an IPv6 packet for
+        // the MMDS.
         if !test_speculative_tpa(src, self.ipv4_addr)
             && !test_speculative_dst_addr(src, self.ipv4_addr)
+            && !maybe_ipv6
         {
             return false;
         }
@@ -115,6 +205,7 @@ impl MmdsNetworkStack {
             match eth.ethertype() {
                 ETHERTYPE_ARP => return self.detour_arp(eth),
                 ETHERTYPE_IPV4 => return self.detour_ipv4(eth),
+                ETHERTYPE_IPV6 => return self.detour_ipv6(eth),
                 _ => (),
             };
         } else {
@@ -126,6 +217,9 @@ impl MmdsNetworkStack {
     fn detour_arp(&mut self, eth: EthernetFrame<&[u8]>) -> bool {
         if let Ok(arp) = EthIPv4ArpFrame::request_from_bytes(eth.payload()) {
             if arp.tpa() == self.ipv4_addr {
+                if !self.allows_src_mac(eth.src_mac()) {
+                    return true;
+                }
                 self.remote_mac_addr = arp.sha();
                 self.pending_arp_reply = Some(arp.spa());
                 return true;
@@ -140,24 +234,13 @@ impl MmdsNetworkStack {
         // context at some point!
         if let Ok(ip) = IPv4Packet::from_bytes(eth.payload(), false) {
             if ip.destination_address() == self.ipv4_addr {
+                if !self.allows_src_mac(eth.src_mac()) {
+                    return true;
+                }
                 if ip.protocol() == PROTOCOL_TCP {
                     self.remote_mac_addr = eth.src_mac();
-                    match self.tcp_handler.receive_packet(&ip) {
-                        Ok(event) => match event {
-                            RecvEvent::NewConnectionSuccessful => {
-                                METRICS.mmds.connections_created.inc()
-                            }
-                            RecvEvent::NewConnectionReplacing => {
-                                METRICS.mmds.connections_created.inc();
-                                METRICS.mmds.connections_destroyed.inc();
-                            }
-                            RecvEvent::EndpointDone => {
-                                METRICS.mmds.connections_destroyed.inc();
-                            }
-                            _ => (),
-                        },
-                        Err(_) => METRICS.mmds.rx_accepted_err.inc(),
-                    }
+                    let result = self.tcp_handler.receive_packet(&ip);
+                    Self::count_tcp_recv_result(result);
                 } else {
                     // A non-TCP IPv4 packet heading towards the MMDS; we consider it unusual.
                     METRICS.mmds.rx_accepted_unusual.inc();
@@ -168,12 +251,88 @@ impl MmdsNetworkStack {
         false
     }
 
+    fn detour_ipv6(&mut self, eth: EthernetFrame<&[u8]>) -> bool {
+        let ipv6_addr = match self.ipv6_addr {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        if let Ok(ip) = IPv6Packet::from_bytes(eth.payload()) {
+            let dst_addr = ip.destination_address();
+            if dst_addr == ipv6_addr {
+                if !self.allows_src_mac(eth.src_mac()) {
+                    return true;
+                }
+                if ip.next_header() == PROTOCOL_TCP {
+                    self.remote_mac_addr = eth.src_mac();
+                    let result = self.tcp_handler.receive_ipv6_packet(&ip);
+                    Self::count_tcp_recv_result(result);
+                } else if !(ip.next_header() == PROTOCOL_ICMPV6 && self.detour_ndp(&eth, &ip)) {
+                    // Anything other than TCP segments and neighbor solicitations heading
+                    // towards the MMDS is unusual (this includes IPv6 extension headers).
+                    METRICS.mmds.rx_accepted_unusual.inc();
+                }
+                return true;
+            } else if dst_addr == solicited_node_address(ipv6_addr)
+                && ip.next_header() == PROTOCOL_ICMPV6
+            {
+                // Other addresses share the same solicited-node multicast address, so the
+                // solicitations which are not for the MMDS go through.
+                return self.detour_ndp(&eth, &ip);
+            }
+        }
+        false
+    }
+
+    // Returns true if the ICMPv6 message carried by ip is a neighbor solicitation for the MMDS.
+    fn detour_ndp(&mut self, eth: &EthernetFrame<&[u8]>, ip: &IPv6Packet<&[u8]>) -> bool {
+        // Unlike TCP segments, ICMPv6 messages are not subject to checksum offloading, so their
+        // checksums can be verified. The solicitations which were forwarded by a router, or which
+        // come from an unspecified address (as used by duplicate address detection), are not
+        // answered.
+        let src_addr = ip.source_address();
+        if ip.hop_limit() != NDP_HOP_LIMIT || src_addr.is_unspecified() {
+            return false;
+        }
+        if let Ok(ns) = NeighborMessage::solicitation_from_bytes(
+            ip.payload(),
+            Some((src_addr, ip.destination_address())),
+        ) {
+            if Some(ns.target_address()) == self.ipv6_addr {
+                if !self.allows_src_mac(eth.src_mac()) {
+                    return true;
+                }
+                self.remote_mac_addr = ns.source_link_layer_address().unwrap_or(eth.src_mac());
+                self.pending_neighbor_advertisement = Some(src_addr);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn count_tcp_recv_result(result: Result<RecvEvent, RecvError>) {
+        match result {
+            Ok(event) => match event {
+                RecvEvent::NewConnectionSuccessful => METRICS.mmds.connections_created.inc(),
+                RecvEvent::NewConnectionReplacing => {
+                    METRICS.mmds.connections_created.inc();
+                    METRICS.mmds.connections_destroyed.inc();
+                }
+                RecvEvent::EndpointDone => {
+                    METRICS.mmds.connections_destroyed.inc();
+                }
+                _ => (),
+            },
+            Err(_) => METRICS.mmds.rx_accepted_err.inc(),
+        }
+    }
+
     // Allows the MMDS network stack to write a frame to the specified buffer. Will return:
     // - None, if the MMDS network stack has no frame to send at this point. The buffer can be
     // used for something else by the device model.
     // - Some(len), if a frame of the given length has been written to the specified buffer.
     pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
-        // We try to send ARP replies first.
+        // We try to send ARP replies first, and then neighbor advertisements.
         if let Some(spa) = self.pending_arp_reply {
             return match self.write_arp_reply(buf, spa) {
                 Ok(something) => {
@@ -185,6 +344,17 @@ impl MmdsNetworkStack {
                     None
                 }
             };
+        } else if let Some(dst_ipv6) = self.pending_neighbor_advertisement {
+            return match self.write_neighbor_advertisement(buf, dst_ipv6) {
+                Ok(something) => {
+                    self.pending_neighbor_advertisement = None;
+                    something
+                }
+                Err(_) => {
+                    METRICS.mmds.tx_errors.inc();
+                    None
+                }
+            };
         } else {
             let call_write = match self.tcp_handler.next_segment_status() {
                 NextSegmentStatus::Available => true,
@@ -242,6 +412,48 @@ impl MmdsNetworkStack {
         ))
     }
 
+    fn write_neighbor_advertisement(
+        &self,
+        buf: &mut [u8],
+        dst_ipv6: Ipv6Addr,
+    ) -> Result<Option<NonZeroUsize>, WriteNeighborAdvertisementError> {
+        // The unwrap() is safe because neighbor solicitations are only answered when the MMDS
+        // has an IPv6 address.
+        let ipv6_addr = self.ipv6_addr.unwrap();
+
+        let mut eth_unsized = self
+            .prepare_eth_unsized(buf, ETHERTYPE_IPV6)
+            .map_err(WriteNeighborAdvertisementError::Ethernet)?;
+
+        let packet_len = {
+            let mut packet = IPv6Packet::write_header(
+                eth_unsized.inner_mut().payload_mut(),
+                PROTOCOL_ICMPV6,
+                ipv6_addr,
+                dst_ipv6,
+            )
+            .map_err(WriteNeighborAdvertisementError::IPv6Packet)?;
+
+            let na_len = NeighborMessage::write_advertisement(
+                packet.inner_mut().payload_mut(),
+                AdvertisementFlags::SOLICITED | AdvertisementFlags::OVERRIDE,
+                ipv6_addr,
+                self.mac_addr,
+                ipv6_addr,
+                dst_ipv6,
+            )
+            .map_err(WriteNeighborAdvertisementError::Ndp)?
+            .len();
+
+            packet.with_payload_len_unchecked(na_len).len()
+        };
+
+        Ok(Some(
+            // The unwrap() is safe because packet_len > 0.
+            NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(packet_len).len()).unwrap(),
+        ))
+    }
+
     fn write_packet(&mut self, buf: &mut [u8]) -> Result<Option<NonZeroUsize>, WritePacketError> {
         let mut eth_unsized = self
             .prepare_eth_unsized(buf, ETHERTYPE_IPV4)
@@ -251,6 +463,15 @@ impl MmdsNetworkStack {
             .tcp_handler
             .write_next_packet(eth_unsized.inner_mut().payload_mut())?;
 
+        // The handler writes IPv6 packets for the connections over IPv6. The version field is
+        // at the same place in both IPv4 and IPv6 packets.
+        if maybe_len.is_some()
+            && IPv6Packet::from_bytes_unchecked(eth_unsized.inner_mut().payload_mut()).version()
+                == IPV6_VERSION
+        {
+            eth_unsized.inner_mut().set_ethertype(ETHERTYPE_IPV6);
+        }
+
         match event {
             WriteEvent::EndpointDone => METRICS.mmds.connections_destroyed.inc(),
             _ => (),
@@ -286,6 +507,10 @@ mod tests {
     const REMOTE_PORT: u16 = 1235;
     const SEQ_NUMBER: u32 = 123;
 
+    fn remote_ipv6_addr() -> Ipv6Addr {
+        Ipv6Addr::new(0xfe80, 0, 0, 0, 0x1, 0x2, 0x3, 0x4)
+    }
+
     // Helper methods which only make sense for testing.
     impl MmdsNetworkStack {
         fn write_arp_request(&self, buf: &mut [u8], for_mmds: bool) -> usize {
@@ -338,7 +563,11 @@ mod tests {
                     None,
                 )
                 .unwrap()
-                .finalize(REMOTE_PORT, MMDS_PORT, Some((REMOTE_ADDR, addr)))
+                .finalize(
+                    REMOTE_PORT,
+                    MMDS_PORT,
+                    Some((REMOTE_ADDR.into(), addr.into())),
+                )
                 .len();
 
                 packet.with_payload_len_unchecked(segment_len, true).len()
@@ -347,6 +576,81 @@ mod tests {
             eth_unsized.with_payload_len_unchecked(packet_len).len()
         }
 
+        fn write_neighbor_solicitation(&self, buf: &mut [u8], target: Ipv6Addr) -> usize {
+            let mut eth_unsized = self.prepare_eth_unsized(buf, ETHERTYPE_IPV6).unwrap();
+            let packet_len = {
+                let dst_addr = solicited_node_address(target);
+                let mut packet = IPv6Packet::write_header(
+                    eth_unsized.inner_mut().payload_mut(),
+                    PROTOCOL_ICMPV6,
+                    remote_ipv6_addr(),
+                    dst_addr,
+                )
+                .unwrap();
+
+                let ns_len = NeighborMessage::write_solicitation(
+                    packet.inner_mut().payload_mut(),
+                    target,
+                    MacAddr::parse_str(REMOTE_MAC_STR).unwrap(),
+                    remote_ipv6_addr(),
+                    dst_addr,
+                )
+                .unwrap()
+                .len();
+
+                packet.with_payload_len_unchecked(ns_len).len()
+            };
+
+            eth_unsized.with_payload_len_unchecked(packet_len).len()
+        }
+
+        fn write_incoming_ipv6_tcp_segment(
+            &self,
+            buf: &mut [u8],
+            addr: Ipv6Addr,
+            flags: TcpFlags,
+        ) -> usize {
+            let mut eth_unsized = self.prepare_eth_unsized(buf, ETHERTYPE_IPV6).unwrap();
+            let packet_len = {
+                let mut packet = IPv6Packet::write_header(
+                    eth_unsized.inner_mut().payload_mut(),
+                    PROTOCOL_TCP,
+                    remote_ipv6_addr(),
+                    addr,
+                )
+                .unwrap();
+
+                let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
+                    packet.inner_mut().payload_mut(),
+                    SEQ_NUMBER,
+                    1234,
+                    flags,
+                    10000,
+                    None,
+                    0,
+                    None,
+                )
+                .unwrap()
+                .finalize(
+                    REMOTE_PORT,
+                    MMDS_PORT,
+                    Some((remote_ipv6_addr().into(), addr.into())),
+                )
+                .len();
+
+                packet.with_payload_len_unchecked(segment_len).len()
+            };
+
+            eth_unsized.with_payload_len_unchecked(packet_len).len()
+        }
+
+        fn next_frame_as_ipv6_packet<'a>(&mut self, buf: &'a mut [u8]) -> IPv6Packet<&'a [u8]> {
+            let len = self.write_next_frame(buf.as_mut()).unwrap().get();
+            let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(eth.ethertype(), ETHERTYPE_IPV6);
+            IPv6Packet::from_bytes(&buf[eth.payload_offset()..len]).unwrap()
+        }
+
         fn next_frame_as_ipv4_packet<'a>(&mut self, buf: &'a mut [u8]) -> IPv4Packet<&'a [u8]> {
             let len = self.write_next_frame(buf.as_mut()).unwrap().get();
             let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
@@ -433,10 +737,12 @@ mod tests {
             let ip = ns.next_frame_as_ipv4_packet(buf.as_mut());
             assert_eq!(ip.source_address(), mmds_addr);
             assert_eq!(ip.destination_address(), REMOTE_ADDR);
+            // The responses can't be relayed beyond the guest.
+            assert_eq!(ip.ttl(), DEFAULT_HOP_LIMIT);
 
             let s = TcpSegment::from_bytes(
                 ip.payload(),
-                Some((ip.source_address(), ip.destination_address())),
+                Some((ip.source_address().into(), ip.destination_address().into())),
             )
             .unwrap();
             assert_eq!(s.flags_after_ns(), TcpFlags::RST);
@@ -447,7 +753,8 @@ mod tests {
         // Nothing else to send.
         assert!(ns.write_next_frame(buf.as_mut()).is_none());
 
-        // Let's send a TCP SYN into the ns.
+        // Let's send a TCP SYN into the ns, after changing the hop limit.
+        ns.set_hop_limit(64);
         {
             let len = ns.write_incoming_tcp_segment(buf.as_mut(), mmds_addr, TcpFlags::SYN);
             assert_eq!(ns.detour_frame(&buf[..len]), true);
@@ -458,10 +765,11 @@ mod tests {
             let ip = ns.next_frame_as_ipv4_packet(buf.as_mut());
             assert_eq!(ip.source_address(), mmds_addr);
             assert_eq!(ip.destination_address(), REMOTE_ADDR);
+            assert_eq!(ip.ttl(), 64);
 
             let s = TcpSegment::from_bytes(
                 ip.payload(),
-                Some((ip.source_address(), ip.destination_address())),
+                Some((ip.source_address().into(), ip.destination_address().into())),
             )
             .unwrap();
             assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
@@ -473,4 +781,150 @@ mod tests {
         // Nothing else to send.
         assert!(ns.write_next_frame(buf.as_mut()).is_none());
     }
+
+    #[test]
+    fn test_ns_allowed_mac_addrs() {
+        let mut ns = MmdsNetworkStack::new_with_defaults();
+        assert!(ns.allowed_mac_addrs.is_none());
+
+        let mut buf = [0u8; 2000];
+        let mmds_addr = ns.ipv4_addr;
+        let bad_mmds_addr = Ipv4Addr::from_str("1.2.3.4").unwrap();
+        let ipv6_mmds_addr = ns.ipv6_addr.unwrap();
+        // The frames written by the helpers are sent from the MAC address of the MMDS.
+        let src_mac = ns.mac_addr;
+        let rx_bad_src_mac = METRICS.mmds.rx_bad_src_mac.count();
+
+        ns.set_allowed_mac_addrs(Some(vec![MacAddr::parse_str(REMOTE_MAC_STR).unwrap()]));
+
+        // The frames for the MMDS are dropped, without a response.
+        let len = ns.write_arp_request(buf.as_mut(), true);
+        assert!(ns.detour_frame(&buf[..len]));
+        let len = ns.write_incoming_tcp_segment(buf.as_mut(), mmds_addr, TcpFlags::SYN);
+        assert!(ns.detour_frame(&buf[..len]));
+        let len = ns.write_neighbor_solicitation(buf.as_mut(), ipv6_mmds_addr);
+        assert!(ns.detour_frame(&buf[..len]));
+        let len = ns.write_incoming_ipv6_tcp_segment(buf.as_mut(), ipv6_mmds_addr, TcpFlags::SYN);
+        assert!(ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+        assert_eq!(METRICS.mmds.rx_bad_src_mac.count(), rx_bad_src_mac + 4);
+
+        // The other frames still go through.
+        let len = ns.write_arp_request(buf.as_mut(), false);
+        assert!(!ns.detour_frame(&buf[..len]));
+        let len = ns.write_incoming_tcp_segment(buf.as_mut(), bad_mmds_addr, TcpFlags::SYN);
+        assert!(!ns.detour_frame(&buf[..len]));
+        assert_eq!(METRICS.mmds.rx_bad_src_mac.count(), rx_bad_src_mac + 4);
+
+        // The frames sent from an allowed MAC address are answered.
+        ns.set_allowed_mac_addrs(Some(vec![src_mac]));
+        let len = ns.write_arp_request(buf.as_mut(), true);
+        assert!(ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_some());
+        let len = ns.write_incoming_tcp_segment(buf.as_mut(), mmds_addr, TcpFlags::SYN);
+        assert!(ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_some());
+        assert_eq!(METRICS.mmds.rx_bad_src_mac.count(), rx_bad_src_mac + 4);
+    }
+
+    #[test]
+    fn test_ns_ipv6() {
+        let mut ns = MmdsNetworkStack::new_with_defaults();
+        assert_eq!(ns.ipv6_addr, Some(Ipv6Addr::from(DEFAULT_IPV6_ADDR)));
+
+        let mut buf = [0u8; 2000];
+        let mut bad_buf = [0u8; 1];
+
+        let remote_mac = MacAddr::parse_str(REMOTE_MAC_STR).unwrap();
+        let mmds_addr = ns.ipv6_addr.unwrap();
+        let bad_mmds_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0xa9fe, 0xa9fd);
+
+        {
+            let len = ns.write_neighbor_solicitation(buf.as_mut(), bad_mmds_addr);
+            // Not asking for the MMDS MAC address.
+            assert!(!ns.detour_frame(&buf[..len]));
+            // There's nothing to send.
+            assert!(ns.write_next_frame(buf.as_mut()).is_none());
+        }
+
+        {
+            let len = ns.write_neighbor_solicitation(buf.as_mut(), mmds_addr);
+            // Asking for the MMDS MAC address.
+            assert!(ns.detour_frame(&buf[..len]));
+            assert_eq!(ns.remote_mac_addr, remote_mac);
+        }
+
+        // There should be a neighbor advertisement to send.
+        {
+            assert!(ns.write_next_frame(bad_buf.as_mut()).is_none());
+
+            let mac_addr = ns.mac_addr;
+            let ip = ns.next_frame_as_ipv6_packet(buf.as_mut());
+            assert_eq!(ip.next_header(), PROTOCOL_ICMPV6);
+            assert_eq!(ip.hop_limit(), NDP_HOP_LIMIT);
+            assert_eq!(ip.source_address(), mmds_addr);
+            assert_eq!(ip.destination_address(), remote_ipv6_addr());
+
+            let na = NeighborMessage::from_bytes_unchecked(ip.payload());
+            assert_eq!(
+                na.flags(),
+                AdvertisementFlags::SOLICITED | AdvertisementFlags::OVERRIDE
+            );
+            assert_eq!(na.target_address(), mmds_addr);
+            assert_eq!(na.target_link_layer_address(), Some(mac_addr));
+            assert_eq!(na.compute_checksum(mmds_addr, remote_ipv6_addr()), 0);
+        }
+
+        // Nothing to send anymore.
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // A TCP segment heading to another address is not for the MMDS.
+        {
+            let len =
+                ns.write_incoming_ipv6_tcp_segment(buf.as_mut(), bad_mmds_addr, TcpFlags::SYN);
+            assert!(!ns.detour_frame(&buf[..len]));
+            assert!(ns.write_next_frame(buf.as_mut()).is_none());
+        }
+
+        // Let's send a TCP SYN into the ns.
+        {
+            let len = ns.write_incoming_ipv6_tcp_segment(buf.as_mut(), mmds_addr, TcpFlags::SYN);
+            assert!(ns.detour_frame(&buf[..len]));
+        }
+
+        // We should be getting a SYNACK over IPv6 in response.
+        {
+            let ip = ns.next_frame_as_ipv6_packet(buf.as_mut());
+            assert_eq!(ip.next_header(), PROTOCOL_TCP);
+            assert_eq!(ip.hop_limit(), DEFAULT_HOP_LIMIT);
+            assert_eq!(ip.source_address(), mmds_addr);
+            assert_eq!(ip.destination_address(), remote_ipv6_addr());
+
+            let s = TcpSegment::from_bytes(
+                ip.payload(),
+                Some((ip.source_address().into(), ip.destination_address().into())),
+            )
+            .unwrap();
+            assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+            assert_eq!(s.source_port(), MMDS_PORT);
+            assert_eq!(s.destination_port(), REMOTE_PORT);
+            assert_eq!(s.ack_number(), SEQ_NUMBER.wrapping_add(1));
+        }
+
+        // Nothing else to send.
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // Without an IPv6 address, the solicitations are not answered.
+        let mut ns = MmdsNetworkStack::new(
+            remote_mac,
+            Ipv4Addr::from(DEFAULT_IPV4_ADDR),
+            None,
+            MMDS_PORT,
+            NonZeroUsize::new(1).unwrap(),
+            NonZeroUsize::new(1).unwrap(),
+        );
+        let len = ns.write_neighbor_solicitation(buf.as_mut(), mmds_addr);
+        assert!(!ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+    }
 }
@@ -16,13 +16,15 @@ const ETHERTYPE_OFFSET: usize = 12;
 
 // We don't support 802.1Q tags.
 // TODO: support 802.1Q tags?! If so, don't forget to change the speculative_test_* functions
-// for ARP and IPv4.
+// for ARP, IPv4, and IPv6.
 pub(super) const PAYLOAD_OFFSET: usize = 14;
 
 /// Ethertype value for ARP frames.
 pub const ETHERTYPE_ARP: u16 = 0x0806;
 /// Ethertype value for IPv4 packets.
 pub const ETHERTYPE_IPV4: u16 = 0x0800;
+/// Ethertype value for IPv6 packets.
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
 
 /// Describes the errors which may occur when handling Ethernet frames.
 #[cfg_attr(test, derive(Debug, PartialEq))]
@@ -11,8 +11,12 @@ pub mod arp;
 pub mod bytes;
 pub mod ethernet;
 pub mod ipv4;
+pub mod ipv6;
+pub mod ndp;
 pub mod tcp;
 
+use std::net::IpAddr;
+
 /// This is the baseline definition of the `Incomplete` struct, which wraps a PDU that does is
 /// still missing some values or content.
 ///
@@ -43,3 +47,32 @@ impl<T> Incomplete<T> {
         &mut self.inner
     }
 }
+
+// Sums the 16 bit words of the pseudo header which is covered by the TCP and ICMPv6 checksums,
+// made of the addresses of the enclosing IPv4 or IPv6 packet, the protocol number, and the length
+// of the upper layer data.
+fn pseudo_header_sum(src_addr: IpAddr, dst_addr: IpAddr, protocol: u8, len: usize) -> u32 {
+    let mut sum = 0u32;
+
+    for addr in &[src_addr, dst_addr] {
+        match *addr {
+            IpAddr::V4(addr) => {
+                let a = u32::from(addr);
+                sum += a & 0xffff;
+                sum += a >> 16;
+            }
+            IpAddr::V6(addr) => {
+                for segment in addr.segments().iter() {
+                    sum += *segment as u32;
+                }
+            }
+        }
+    }
+
+    // The IPv6 pseudo header holds the length on 32 bits, where the IPv4 one only uses 16, but
+    // they sum up the same for any length which fits in 16 bits.
+    sum += protocol as u32;
+    sum += (len as u32) >> 16;
+    sum += (len as u32) & 0xffff;
+    sum
+}
@@ -0,0 +1,494 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains logic that helps with handling Neighbor Discovery messages, which resolve IPv6
+//! addresses to link layer addresses the way ARP does for IPv4 addresses.
+//!
+//! Only neighbor solicitations and advertisements (which are ICMPv6 messages) over Ethernet are
+//! supported. A more detailed view of these messages can be found in [RFC 4861].
+//!
+//! [RFC 4861]: https://tools.ietf.org/html/rfc4861#section-4.3
+
+use std::convert::From;
+use std::net::{IpAddr, Ipv6Addr};
+use std::result::Result;
+
+use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use super::ipv6::PROTOCOL_ICMPV6;
+use super::pseudo_header_sum;
+use net_util::{MacAddr, MAC_ADDR_LEN};
+
+const TYPE_OFFSET: usize = 0;
+const CODE_OFFSET: usize = 1;
+const CHECKSUM_OFFSET: usize = 2;
+const FLAGS_OFFSET: usize = 4;
+const TARGET_ADDRESS_OFFSET: usize = 8;
+const OPTIONS_OFFSET: usize = 24;
+
+const TYPE_NEIGHBOR_SOLICITATION: u8 = 135;
+const TYPE_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+const OPTION_KIND_SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+const OPTION_KIND_TARGET_LINK_LAYER_ADDRESS: u8 = 2;
+// The option length is expressed in units of 8 bytes.
+const OPTION_LEN_UNIT: usize = 8;
+
+const IPV6_ADDR_LEN: usize = 16;
+
+/// The length of a neighbor solicitation or advertisement which carries an Ethernet link layer
+/// address option.
+pub const ETH_NEIGHBOR_MESSAGE_LEN: usize = OPTIONS_OFFSET + OPTION_LEN_UNIT;
+
+/// The hop limit of the IPv6 packets carrying Neighbor Discovery messages, which proves they
+/// were not forwarded by a router.
+pub const NDP_HOP_LIMIT: u8 = 255;
+
+bitflags! {
+    /// Represents the flags of a neighbor advertisement.
+    pub struct AdvertisementFlags: u8 {
+        /// The sender is a router.
+        const ROUTER = 1 << 7;
+        /// The advertisement answers a solicitation.
+        const SOLICITED = 1 << 6;
+        /// The advertisement should override the cached link layer address.
+        const OVERRIDE = 1 << 5;
+    }
+}
+
+/// Represents errors which may occur while parsing or writing a message.
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum Error {
+    /// Invalid checksum.
+    Checksum,
+    /// Invalid code.
+    Code,
+    /// Invalid message type.
+    MessageType,
+    /// One of the options has an invalid length.
+    OptionLen,
+    /// The provided slice is shorter than the message.
+    SliceTooShort,
+}
+
+/// The inner bytes will be interpreted as a neighbor solicitation or advertisement.
+pub struct NeighborMessage<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+impl<'a, T: NetworkBytes> NeighborMessage<'a, T> {
+    /// Interprets the given bytes as a neighbor message, without doing any validity checks
+    /// beforehand.
+    ///
+    ///  # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        NeighborMessage {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Tries to interpret a byte slice as a valid neighbor solicitation.
+    ///
+    /// The `verify_checksum` parameter must contain the source and destination addresses from the
+    /// enclosing IPv6 packet if the ICMPv6 checksum must be validated. If no error occurs, the
+    /// options are known to fit in the message.
+    pub fn solicitation_from_bytes(
+        bytes: T,
+        verify_checksum: Option<(Ipv6Addr, Ipv6Addr)>,
+    ) -> Result<Self, Error> {
+        if bytes.len() < OPTIONS_OFFSET {
+            return Err(Error::SliceTooShort);
+        }
+
+        let maybe = NeighborMessage::from_bytes_unchecked(bytes);
+
+        if maybe.message_type() != TYPE_NEIGHBOR_SOLICITATION {
+            return Err(Error::MessageType);
+        }
+
+        if maybe.code() != 0 {
+            return Err(Error::Code);
+        }
+
+        // Options with a length of 0 are invalid, and would make us loop forever.
+        let mut i = OPTIONS_OFFSET;
+        while i < maybe.len() {
+            if i + 1 >= maybe.len() || maybe.bytes[i + 1] == 0 {
+                return Err(Error::OptionLen);
+            }
+            i += maybe.bytes[i + 1] as usize * OPTION_LEN_UNIT;
+        }
+        if i != maybe.len() {
+            return Err(Error::OptionLen);
+        }
+
+        if let Some((src_addr, dst_addr)) = verify_checksum {
+            if maybe.compute_checksum(src_addr, dst_addr) != 0 {
+                return Err(Error::Checksum);
+            }
+        }
+
+        Ok(maybe)
+    }
+
+    /// Returns the ICMPv6 type of the message.
+    #[inline]
+    pub fn message_type(&self) -> u8 {
+        self.bytes[TYPE_OFFSET]
+    }
+
+    /// Returns the ICMPv6 code of the message.
+    #[inline]
+    pub fn code(&self) -> u8 {
+        self.bytes[CODE_OFFSET]
+    }
+
+    /// Returns the ICMPv6 checksum of the message.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        self.bytes.ntohs_unchecked(CHECKSUM_OFFSET)
+    }
+
+    /// Returns the flags of the message, which are only meaningful for advertisements.
+    #[inline]
+    pub fn flags(&self) -> AdvertisementFlags {
+        AdvertisementFlags::from_bits_truncate(self.bytes[FLAGS_OFFSET])
+    }
+
+    /// Returns the target address of the message.
+    #[inline]
+    pub fn target_address(&self) -> Ipv6Addr {
+        let mut octets = [0u8; IPV6_ADDR_LEN];
+        octets.copy_from_slice(&self.bytes[TARGET_ADDRESS_OFFSET..OPTIONS_OFFSET]);
+        Ipv6Addr::from(octets)
+    }
+
+    /// Returns the link layer address carried by the source link layer address option, if any.
+    ///
+    /// # Panics
+    ///
+    /// This method may panic if the options do not fit in the message.
+    #[inline]
+    pub fn source_link_layer_address(&self) -> Option<MacAddr> {
+        self.link_layer_address(OPTION_KIND_SOURCE_LINK_LAYER_ADDRESS)
+    }
+
+    /// Returns the link layer address carried by the target link layer address option, if any.
+    ///
+    /// # Panics
+    ///
+    /// This method may panic if the options do not fit in the message.
+    #[inline]
+    pub fn target_link_layer_address(&self) -> Option<MacAddr> {
+        self.link_layer_address(OPTION_KIND_TARGET_LINK_LAYER_ADDRESS)
+    }
+
+    fn link_layer_address(&self, kind: u8) -> Option<MacAddr> {
+        let mut i = OPTIONS_OFFSET;
+        while i + 1 < self.len() && self.bytes[i + 1] != 0 {
+            let option_len = self.bytes[i + 1] as usize * OPTION_LEN_UNIT;
+            // The options for other link layers than Ethernet have different lengths.
+            if self.bytes[i] == kind && option_len == OPTION_LEN_UNIT {
+                return Some(MacAddr::from_bytes_unchecked(
+                    &self.bytes[i + 2..i + 2 + MAC_ADDR_LEN],
+                ));
+            }
+            i += option_len;
+        }
+        None
+    }
+
+    /// Computes the ICMPv6 checksum of the message, which covers the pseudo header made of the
+    /// given addresses of the enclosing IPv6 packet.
+    pub fn compute_checksum(&self, src_addr: Ipv6Addr, dst_addr: Ipv6Addr) -> u16 {
+        let len = self.len();
+        let mut sum = pseudo_header_sum(
+            IpAddr::V6(src_addr),
+            IpAddr::V6(dst_addr),
+            PROTOCOL_ICMPV6,
+            len,
+        );
+
+        for i in 0..len / 2 {
+            sum += self.bytes.ntohs_unchecked(i * 2) as u32;
+        }
+
+        if len % 2 != 0 {
+            sum += (self.bytes[len - 1] as u32) << 8;
+        }
+
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+
+        !(sum as u16)
+    }
+
+    /// Returns the length of the message.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl<'a, T: NetworkBytesMut> NeighborMessage<'a, T> {
+    fn write_raw(
+        buf: T,
+        message_type: u8,
+        flags: AdvertisementFlags,
+        target: Ipv6Addr,
+        option_kind: u8,
+        link_layer_addr: MacAddr,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+    ) -> Result<Self, Error> {
+        if buf.len() < ETH_NEIGHBOR_MESSAGE_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        // This is ok, because we've checked the length of the slice.
+        let mut message = NeighborMessage::from_bytes_unchecked(buf);
+        message.bytes.shrink_unchecked(ETH_NEIGHBOR_MESSAGE_LEN);
+
+        message.set_message_type(message_type);
+        message.set_code(0);
+        message.set_flags(flags);
+        message.set_target_address(target);
+
+        message.bytes[OPTIONS_OFFSET] = option_kind;
+        message.bytes[OPTIONS_OFFSET + 1] = 1;
+        message.bytes[OPTIONS_OFFSET + 2..OPTIONS_OFFSET + 2 + MAC_ADDR_LEN]
+            .copy_from_slice(link_layer_addr.get_bytes());
+
+        message.set_checksum(0);
+        let checksum = message.compute_checksum(src_addr, dst_addr);
+        message.set_checksum(checksum);
+
+        Ok(message)
+    }
+
+    /// Attempts to write a neighbor solicitation for `target` to `buf`, which carries the link
+    /// layer address of the sender, and the checksum computed for the addresses of the enclosing
+    /// IPv6 packet.
+    #[inline]
+    pub fn write_solicitation(
+        buf: T,
+        target: Ipv6Addr,
+        sender_mac: MacAddr,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+    ) -> Result<Self, Error> {
+        Self::write_raw(
+            buf,
+            TYPE_NEIGHBOR_SOLICITATION,
+            AdvertisementFlags::empty(),
+            target,
+            OPTION_KIND_SOURCE_LINK_LAYER_ADDRESS,
+            sender_mac,
+            src_addr,
+            dst_addr,
+        )
+    }
+
+    /// Attempts to write a neighbor advertisement for `target` to `buf`, which carries the link
+    /// layer address of the target, and the checksum computed for the addresses of the enclosing
+    /// IPv6 packet.
+    #[inline]
+    pub fn write_advertisement(
+        buf: T,
+        flags: AdvertisementFlags,
+        target: Ipv6Addr,
+        target_mac: MacAddr,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+    ) -> Result<Self, Error> {
+        Self::write_raw(
+            buf,
+            TYPE_NEIGHBOR_ADVERTISEMENT,
+            flags,
+            target,
+            OPTION_KIND_TARGET_LINK_LAYER_ADDRESS,
+            target_mac,
+            src_addr,
+            dst_addr,
+        )
+    }
+
+    /// Sets the ICMPv6 type of the message.
+    #[inline]
+    pub fn set_message_type(&mut self, value: u8) {
+        self.bytes[TYPE_OFFSET] = value;
+    }
+
+    /// Sets the ICMPv6 code of the message.
+    #[inline]
+    pub fn set_code(&mut self, value: u8) {
+        self.bytes[CODE_OFFSET] = value;
+    }
+
+    /// Sets the ICMPv6 checksum of the message.
+    #[inline]
+    pub fn set_checksum(&mut self, value: u16) {
+        self.bytes.htons_unchecked(CHECKSUM_OFFSET, value);
+    }
+
+    /// Sets the flags of the message, and clears the reserved bits which follow them.
+    #[inline]
+    pub fn set_flags(&mut self, flags: AdvertisementFlags) {
+        self.bytes
+            .htonl_unchecked(FLAGS_OFFSET, (flags.bits() as u32) << 24);
+    }
+
+    /// Sets the target address of the message.
+    #[inline]
+    pub fn set_target_address(&mut self, addr: Ipv6Addr) {
+        self.bytes[TARGET_ADDRESS_OFFSET..OPTIONS_OFFSET].copy_from_slice(&addr.octets());
+    }
+}
+
+/// Returns the solicited-node multicast address of `addr`, where the neighbor solicitations for
+/// `addr` are sent to.
+#[inline]
+pub fn solicited_node_address(addr: Ipv6Addr) -> Ipv6Addr {
+    let segments = addr.segments();
+    Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | (segments[6] & 0xff),
+        segments[7],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::*;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for NeighborMessage<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(Neighbor message)")
+        }
+    }
+
+    #[test]
+    fn test_neighbor_message() {
+        let mut a = [1u8; 1000];
+        let mac = MacAddr::parse_str("01:23:45:67:89:ab").unwrap();
+        let src_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 1, 2, 3, 4);
+        let target = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0xa9fe, 0xa9fe);
+        let dst_addr = solicited_node_address(target);
+
+        {
+            let m =
+                NeighborMessage::write_solicitation(a.as_mut(), target, mac, src_addr, dst_addr)
+                    .unwrap();
+            assert_eq!(m.len(), ETH_NEIGHBOR_MESSAGE_LEN);
+            assert_eq!(m.message_type(), TYPE_NEIGHBOR_SOLICITATION);
+            assert_eq!(m.code(), 0);
+            assert!(m.flags().is_empty());
+            assert_eq!(m.target_address(), target);
+            assert_eq!(m.source_link_layer_address(), Some(mac));
+            assert_eq!(m.target_link_layer_address(), None);
+            assert_eq!(m.compute_checksum(src_addr, dst_addr), 0);
+        }
+
+        let len = ETH_NEIGHBOR_MESSAGE_LEN;
+        {
+            let m = NeighborMessage::solicitation_from_bytes(&a[..len], Some((src_addr, dst_addr)))
+                .unwrap();
+            assert_eq!(m.target_address(), target);
+            assert_eq!(m.source_link_layer_address(), Some(mac));
+        }
+
+        let look_for_error = |buf: &[u8], err: Error| {
+            assert_eq!(
+                NeighborMessage::solicitation_from_bytes(buf, Some((src_addr, dst_addr)))
+                    .unwrap_err(),
+                err
+            );
+        };
+
+        // The checksum does not match the other addresses.
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&a[..len], Some((target, dst_addr)))
+                .unwrap_err(),
+            Error::Checksum
+        );
+        // Unless it's not checked.
+        assert!(NeighborMessage::solicitation_from_bytes(&a[..len], None).is_ok());
+
+        // A solicitation without options is fine too.
+        assert!(NeighborMessage::solicitation_from_bytes(&a[..OPTIONS_OFFSET], None).is_ok());
+        look_for_error(&a[..OPTIONS_OFFSET - 1], Error::SliceTooShort);
+        // The option does not fit.
+        look_for_error(&a[..len - 1], Error::OptionLen);
+        look_for_error(&a[..OPTIONS_OFFSET + 1], Error::OptionLen);
+
+        // An option with a length of 0.
+        a[OPTIONS_OFFSET + 1] = 0;
+        look_for_error(&a[..len], Error::OptionLen);
+        a[OPTIONS_OFFSET + 1] = 1;
+
+        a[CODE_OFFSET] = 1;
+        look_for_error(&a[..len], Error::Code);
+
+        {
+            let m = NeighborMessage::write_advertisement(
+                a.as_mut(),
+                AdvertisementFlags::SOLICITED | AdvertisementFlags::OVERRIDE,
+                target,
+                mac,
+                target,
+                src_addr,
+            )
+            .unwrap();
+            assert_eq!(m.len(), ETH_NEIGHBOR_MESSAGE_LEN);
+            assert_eq!(m.message_type(), TYPE_NEIGHBOR_ADVERTISEMENT);
+            assert_eq!(m.code(), 0);
+            assert_eq!(
+                m.flags(),
+                AdvertisementFlags::SOLICITED | AdvertisementFlags::OVERRIDE
+            );
+            assert_eq!(m.target_address(), target);
+            assert_eq!(m.source_link_layer_address(), None);
+            assert_eq!(m.target_link_layer_address(), Some(mac));
+            assert_eq!(m.compute_checksum(target, src_addr), 0);
+        }
+        // An advertisement is not a solicitation.
+        look_for_error(&a[..len], Error::MessageType);
+
+        let mut small_buf = [0u8; ETH_NEIGHBOR_MESSAGE_LEN - 1];
+        assert_eq!(
+            NeighborMessage::write_advertisement(
+                small_buf.as_mut(),
+                AdvertisementFlags::empty(),
+                target,
+                mac,
+                target,
+                src_addr,
+            )
+            .unwrap_err(),
+            Error::SliceTooShort
+        );
+    }
+
+    #[test]
+    fn test_solicited_node_address() {
+        assert_eq!(
+            solicited_node_address(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0xa9fe, 0xa9fe)),
+            Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xfffe, 0xa9fe)
+        );
+        assert_eq!(
+            solicited_node_address(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0x1234, 0x5678)),
+            Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff34, 0x5678)
+        );
+    }
+}
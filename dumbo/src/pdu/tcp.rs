@@ -9,13 +9,13 @@
 
 use std::cmp::min;
 use std::convert::From;
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 use std::num::NonZeroU16;
 use std::result::Result;
 
 use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
 use super::ipv4::PROTOCOL_TCP;
-use super::Incomplete;
+use super::{pseudo_header_sum, Incomplete};
 use ByteBuffer;
 
 const SOURCE_PORT_OFFSET: usize = 0;
@@ -85,9 +85,6 @@ pub enum Error {
     SliceTooShort,
 }
 
-// TODO: The implementation of TcpSegment is IPv4 specific in regard to checksum computation. Maybe
-// make it more generic at some point.
-
 /// Interprets the inner bytes as a TCP segment.
 pub struct TcpSegment<'a, T: 'a> {
     bytes: InnerBytes<'a, T>,
@@ -203,23 +200,15 @@ impl<'a, T: NetworkBytes> TcpSegment<'a, T> {
     /// Computes the TCP checksum of the segment. More details about TCP checksum computation can
     /// be found [here].
     ///
+    /// The addresses are those of the enclosing IPv4 or IPv6 packet, which make up the pseudo
+    /// header covered by the checksum.
+    ///
     /// [here]: https://en.wikipedia.org/wiki/Transmission_Control_Protocol#Checksum_computation
-    pub fn compute_checksum(&self, src_addr: Ipv4Addr, dst_addr: Ipv4Addr) -> u16 {
+    pub fn compute_checksum(&self, src_addr: IpAddr, dst_addr: IpAddr) -> u16 {
         // TODO: Is u32 enough to prevent overflow for the code in this function? I think so, but it
         // would be nice to double-check.
-        let mut sum = 0u32;
-
-        let a = u32::from(src_addr);
-        sum += a & 0xffff;
-        sum += a >> 16;
-
-        let b = u32::from(dst_addr);
-        sum += b & 0xffff;
-        sum += b >> 16;
-
         let len = self.len();
-        sum += PROTOCOL_TCP as u32;
-        sum += len as u32;
+        let mut sum = pseudo_header_sum(src_addr, dst_addr, PROTOCOL_TCP, len);
 
         for i in 0..len / 2 {
             sum += self.bytes.ntohs_unchecked(i * 2) as u32;
@@ -301,12 +290,9 @@ impl<'a, T: NetworkBytes> TcpSegment<'a, T> {
     /// Attempts to interpret `bytes` as a TCP segment, checking the validity of the header fields.
     ///
     /// The `verify_checksum` parameter must contain the source and destination addresses from the
-    /// enclosing IPv4 packet if the TCP checksum must be validated.
+    /// enclosing IPv4 or IPv6 packet if the TCP checksum must be validated.
     #[inline]
-    pub fn from_bytes(
-        bytes: T,
-        verify_checksum: Option<(Ipv4Addr, Ipv4Addr)>,
-    ) -> Result<Self, Error> {
+    pub fn from_bytes(bytes: T, verify_checksum: Option<(IpAddr, IpAddr)>) -> Result<Self, Error> {
         if bytes.len() < OPTIONS_OFFSET {
             return Err(Error::SliceTooShort);
         }
@@ -436,7 +422,7 @@ impl<'a, T: NetworkBytesMut> TcpSegment<'a, T> {
     ///    or changing something.
     /// * `payload` - May contain a buffer which holds payload data and the maximum amount of bytes
     ///    we should read from that buffer. When `None`, the TCP segment will carry no payload.
-    /// * `compute_checksum` - May contain the pair addresses from the enclosing IPv4 packet, which
+    /// * `compute_checksum` - May contain the pair addresses from the enclosing IP packet, which
     ///    are required for TCP checksum computation. Skip the checksum altogether when `None`.
     #[inline]
     pub fn write_segment<R: ByteBuffer + ?Sized>(
@@ -450,7 +436,7 @@ impl<'a, T: NetworkBytesMut> TcpSegment<'a, T> {
         mss_option: Option<u16>,
         mss_remaining: u16,
         payload: Option<(&R, usize)>,
-        compute_checksum: Option<(Ipv4Addr, Ipv4Addr)>,
+        compute_checksum: Option<(IpAddr, IpAddr)>,
     ) -> Result<Self, Error> {
         Ok(Self::write_incomplete_segment(
             buf,
@@ -574,7 +560,7 @@ impl<'a, T: NetworkBytesMut> Incomplete<TcpSegment<'a, T>> {
         mut self,
         src_port: u16,
         dst_port: u16,
-        compute_checksum: Option<(Ipv4Addr, Ipv4Addr)>,
+        compute_checksum: Option<(IpAddr, IpAddr)>,
     ) -> TcpSegment<'a, T> {
         self.inner.set_source_port(src_port);
         self.inner.set_destination_port(dst_port);
@@ -591,6 +577,7 @@ impl<'a, T: NetworkBytesMut> Incomplete<TcpSegment<'a, T>> {
 #[cfg(test)]
 mod tests {
     use std::fmt;
+    use std::net::{Ipv4Addr, Ipv6Addr};
 
     use super::*;
 
@@ -658,8 +645,8 @@ mod tests {
         let b = [2u8; 1000];
         let c = [3u8; 2000];
 
-        let src_addr = Ipv4Addr::new(10, 1, 2, 3);
-        let dst_addr = Ipv4Addr::new(192, 168, 44, 77);
+        let src_addr = IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3));
+        let dst_addr = IpAddr::V4(Ipv4Addr::new(192, 168, 44, 77));
         let src_port = 1234;
         let dst_port = 5678;
         let seq_number = 11111222;
@@ -828,4 +815,39 @@ mod tests {
             Error::MssRemaining
         );
     }
+
+    #[test]
+    fn test_ipv6_checksum() {
+        let mut a = [0u8; 100];
+        let src_addr = IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+        let dst_addr = IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0xa9fe, 0xa9fe));
+        let other_addr = IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2));
+
+        let segment_len = TcpSegment::write_segment::<[u8]>(
+            a.as_mut(),
+            1234,
+            80,
+            1,
+            0,
+            Flags::SYN,
+            10000,
+            None,
+            0,
+            None,
+            Some((src_addr, dst_addr)),
+        )
+        .unwrap()
+        .len();
+
+        // The checksum of the same segment, computed over the IPv6 pseudo header by hand.
+        assert_eq!(
+            TcpSegment::from_bytes_unchecked(&a[..segment_len]).checksum(),
+            0x32b0
+        );
+        assert!(TcpSegment::from_bytes(&a[..segment_len], Some((src_addr, dst_addr))).is_ok());
+        assert_eq!(
+            TcpSegment::from_bytes(&a[..segment_len], Some((src_addr, other_addr))).unwrap_err(),
+            Error::Checksum
+        );
+    }
 }
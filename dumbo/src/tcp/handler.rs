@@ -1,22 +1,23 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-//! Exposes simple TCP over IPv4 listener functionality via the [`TcpIPv4Handler`] structure.
+//! Exposes simple TCP over IPv4 and IPv6 listener functionality via the [`TcpIPHandler`]
+//! structure.
 //!
-//! [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
+//! [`TcpIPHandler`]: struct.TcpIPHandler.html
 
 use std::collections::{HashMap, HashSet};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
 
 use pdu::bytes::NetworkBytes;
 use pdu::ipv4::{Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP};
+use pdu::ipv6::{Error as IPv6PacketError, IPv6Packet};
 use pdu::tcp::{Error as TcpSegmentError, Flags as TcpFlags, TcpSegment};
+use pdu::Incomplete;
 use tcp::endpoint::Endpoint;
 use tcp::{NextSegmentStatus, RstConfig};
 
-// TODO: This is currently IPv4 specific. Maybe change it to a more generic implementation.
-
 /// Describes events which may occur when the handler receives packets.
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum RecvEvent {
@@ -49,11 +50,12 @@ pub enum WriteEvent {
     Nothing,
 }
 
-/// Describes errors which may be encountered by the [`receive_packet`] method from
-/// [`TcpIPv4Handler`].
+/// Describes errors which may be encountered by the [`receive_packet`] and
+/// [`receive_ipv6_packet`] methods from [`TcpIPHandler`].
 ///
-/// [`receive_packet`]: struct.TcpIPv4Handler.html#method.receive_packet
-/// [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
+/// [`receive_packet`]: struct.TcpIPHandler.html#method.receive_packet
+/// [`receive_ipv6_packet`]: struct.TcpIPHandler.html#method.receive_ipv6_packet
+/// [`TcpIPHandler`]: struct.TcpIPHandler.html
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum RecvError {
     /// The packet has an invalid destination address.
@@ -65,30 +67,33 @@ pub enum RecvError {
 }
 
 /// Describes errors which may be encountered by the [`write_next_packet`] method from
-/// [`TcpIPv4Handler`].
+/// [`TcpIPHandler`].
 ///
-/// [`write_next_packet`]: struct.TcpIPv4Handler.html#method.write_next_packet
-/// [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
+/// [`write_next_packet`]: struct.TcpIPHandler.html#method.write_next_packet
+/// [`TcpIPHandler`]: struct.TcpIPHandler.html
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum WriteNextError {
     /// There was an error while writing the contents of the IPv4 packet.
     IPv4Packet(IPv4PacketError),
+    /// There was an error while writing the contents of the IPv6 packet.
+    IPv6Packet(IPv6PacketError),
     /// There was an error while writing the contents of the inner TCP segment.
     TcpSegment(TcpSegmentError),
 }
 
-// Generally speaking, a TCP/IPv4 connection is identified using the four-tuple (src_addr, src_port,
-// dst_addr, dst_port). However, the IPv4 address and TCP port of the MMDS endpoint are fixed, so
-// we can get away with uniquely identifying connections using just the remote address and port.
+// Generally speaking, a TCP/IP connection is identified using the four-tuple (src_addr, src_port,
+// dst_addr, dst_port). However, the IP addresses and TCP port of the MMDS endpoint are fixed, so
+// we can get away with uniquely identifying connections using just the remote address and port
+// (whose IP version tells which of the local addresses is used).
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 #[cfg_attr(test, derive(Debug))]
 struct ConnectionTuple {
-    remote_addr: Ipv4Addr,
+    remote_addr: IpAddr,
     remote_port: u16,
 }
 
 impl ConnectionTuple {
-    fn new(remote_addr: Ipv4Addr, remote_port: u16) -> Self {
+    fn new(remote_addr: IpAddr, remote_port: u16) -> Self {
         ConnectionTuple {
             remote_addr,
             remote_port,
@@ -96,33 +101,36 @@ impl ConnectionTuple {
     }
 }
 
-/// Implements a minimalist TCP over IPv4 listener.
+/// Implements a minimalist TCP over IPv4 and IPv6 listener.
 ///
 /// Forwards incoming TCP segments to the appropriate connection object, based on the associated
 /// tuple, or attempts to establish new connections (when receiving `SYN` segments). Aside from
 /// constructors, the handler operation is based on three methods:
 ///
-/// * [`receive_packet`] examines an incoming IPv4 packet. It checks whether the destination
-///   address is correct, the attempts examine the inner TCP segment, making sure the destination
-///   port number is also correct. Then, it steers valid segments towards exiting connections,
+/// * [`receive_packet`] examines an incoming IPv4 packet (and [`receive_ipv6_packet`] an
+///   incoming IPv6 packet). It checks whether the destination address is correct, the attempts
+///   examine the inner TCP segment, making sure the destination port number is also correct. Then, it steers valid segments towards exiting connections,
 ///   creates new connections for incoming `SYN` segments, and enqueues `RST` replies in response
 ///   to any segments which cannot be associated with a connection (except other `RST` segments).
 ///   On success, also describes any internal status changes triggered by the reception of the
 ///   packet.
-/// * [`write_next_packet`] writes the next IPv4 packet (if available) that would be sent by the
+/// * [`write_next_packet`] writes the next IP packet (if available) that would be sent by the
 ///   handler itself (right now it can only mean an enqueued `RST`), or one of the existing
-///   connections. On success, also describes any internal status changes triggered as the packet
-///   gets transmitted.
+///   connections, using the IP version of the connection. On success, also describes any
+///   internal status changes triggered as the packet gets transmitted.
 /// * [`next_segment_status`] describes whether the handler can send a packet immediately, or
 ///   after some retransmission timeout associated with a connection fires, or if there's nothing
 ///   to send for the moment. This is used to determine whether it's appropriate to call
 ///   [`write_next_packet`].
 ///
-/// [`receive_packet`]: ../handler/struct.TcpIPv4Handler.html#method.receive_packet
-/// [`write_next_packet`]: ../handler/struct.TcpIPv4Handler.html#method.write_next_packet
-/// [`next_segment_status`]: ../handler/struct.TcpIPv4Handler.html#method.next_segment_status
-pub struct TcpIPv4Handler {
+/// [`receive_packet`]: ../handler/struct.TcpIPHandler.html#method.receive_packet
+/// [`receive_ipv6_packet`]: ../handler/struct.TcpIPHandler.html#method.receive_ipv6_packet
+/// [`write_next_packet`]: ../handler/struct.TcpIPHandler.html#method.write_next_packet
+/// [`next_segment_status`]: ../handler/struct.TcpIPHandler.html#method.next_segment_status
+pub struct TcpIPHandler {
     local_addr: Ipv4Addr,
+    // The handler only accepts connections over IPv6 when it has an IPv6 address.
+    local_ipv6_addr: Option<Ipv6Addr>,
     local_port: u16,
     // This map holds the currently active endpoints, identified by their connection tuple.
     connections: HashMap<ConnectionTuple, Endpoint>,
@@ -137,6 +145,9 @@ pub struct TcpIPv4Handler {
     rst_queue: Vec<(ConnectionTuple, RstConfig)>,
     // Maximum size of the RST queue.
     max_pending_resets: usize,
+    // The TTL of the IPv4 packets and the hop limit of the IPv6 packets we write, or None for
+    // the defaults of the packet writers.
+    hop_limit: Option<u8>,
 }
 
 // Only used locally, in the receive_packet method, to differentiate between different outcomes
@@ -148,23 +159,26 @@ enum RecvSegmentOutcome {
     UnexpectedSegment(bool),
 }
 
-impl TcpIPv4Handler {
-    /// Creates a new `TcpIPv4Handler`.
+impl TcpIPHandler {
+    /// Creates a new `TcpIPHandler`.
     ///
-    /// The handler acts as if bound to `local_addr`:`local_port`, and will accept at most
+    /// The handler acts as if bound to `local_addr`:`local_port`, and also to
+    /// `local_ipv6_addr`:`local_port` when an IPv6 address is specified. It will accept at most
     /// `max_connections` concurrent connections. `RST` segments generated by unexpected incoming
     /// segments are placed in a queue which is at most `max_pending_resets` long.
     #[inline]
     pub fn new(
         local_addr: Ipv4Addr,
+        local_ipv6_addr: Option<Ipv6Addr>,
         local_port: u16,
         max_connections: NonZeroUsize,
         max_pending_resets: NonZeroUsize,
     ) -> Self {
         let max_connections = max_connections.get();
         let max_pending_resets = max_pending_resets.get();
-        TcpIPv4Handler {
+        TcpIPHandler {
             local_addr,
+            local_ipv6_addr,
             local_port,
             connections: HashMap::with_capacity(max_connections),
             max_connections,
@@ -172,9 +186,18 @@ impl TcpIPv4Handler {
             next_timeout: None,
             rst_queue: Vec::with_capacity(max_pending_resets),
             max_pending_resets,
+            hop_limit: None,
         }
     }
 
+    /// Sets the TTL of the IPv4 packets, and the hop limit of the IPv6 packets, written by the
+    /// handler, or restores the default values with `None`. A hop limit of 1 keeps the packets
+    /// from being forwarded beyond the link of the remote endpoints.
+    #[inline]
+    pub fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
+        self.hop_limit = hop_limit;
+    }
+
     /// Contains logic for handling incoming segments.
     ///
     /// Any changes to the state if the handler are communicated through an `Ok(RecvEvent)`.
@@ -186,17 +209,39 @@ impl TcpIPv4Handler {
             return Err(RecvError::InvalidAddress);
         }
 
+        self.receive_segment(IpAddr::V4(packet.source_address()), packet.payload())
+    }
+
+    /// Contains logic for handling incoming segments carried by IPv6 packets, which are only
+    /// accepted when the handler has an IPv6 address.
+    ///
+    /// Any changes to the state if the handler are communicated through an `Ok(RecvEvent)`.
+    pub fn receive_ipv6_packet<T: NetworkBytes>(
+        &mut self,
+        packet: &IPv6Packet<T>,
+    ) -> Result<RecvEvent, RecvError> {
+        if Some(packet.destination_address()) != self.local_ipv6_addr {
+            return Err(RecvError::InvalidAddress);
+        }
+
+        self.receive_segment(IpAddr::V6(packet.source_address()), packet.payload())
+    }
+
+    fn receive_segment(
+        &mut self,
+        remote_addr: IpAddr,
+        bytes: &[u8],
+    ) -> Result<RecvEvent, RecvError> {
         // TODO: We skip verifying the checksum, just in case the device model relies on offloading
         // checksum computation from the guest to some other entity. Clear this up at some point!
         // (Issue #520)
-        let segment =
-            TcpSegment::from_bytes(packet.payload(), None).map_err(RecvError::TcpSegment)?;
+        let segment = TcpSegment::from_bytes(bytes, None).map_err(RecvError::TcpSegment)?;
 
         if segment.destination_port() != self.local_port {
             return Err(RecvError::InvalidPort);
         }
 
-        let tuple = ConnectionTuple::new(packet.source_address(), segment.source_port());
+        let tuple = ConnectionTuple::new(remote_addr, segment.source_port());
 
         let outcome = if let Some(endpoint) = self.connections.get_mut(&tuple) {
             endpoint.receive_segment(&segment);
@@ -366,12 +411,11 @@ impl TcpIPv4Handler {
         let mut writer_status = None;
         let mut event = WriteEvent::Nothing;
 
-        // We use self.local_addr for the dst_addr parameter also just as a placeholder value. The
-        // actual destination address is written below, after deciding which endpoint is allowed
-        // to send the next packet.
-        let mut packet =
-            IPv4Packet::write_header(buf, PROTOCOL_TCP, self.local_addr, self.local_addr)
-                .map_err(WriteNextError::IPv4Packet)?;
+        // The packet headers are written once we know which connection sends the next packet,
+        // because its IP version decides which header, and which local address, it gets.
+        let local_addr = self.local_addr;
+        let local_ipv6_addr = self.local_ipv6_addr;
+        let hop_limit = self.hop_limit;
 
         // We set mss_used to 0, because we don't add any IP options.
         // TODO: Maybe get this nicely from packet at some point.
@@ -381,9 +425,16 @@ impl TcpIPv4Handler {
         // number, and using mss_remaining = 0 is perfectly fine in this case, because we don't add
         // any TCP options, or a payload.
         if let Some((tuple, rst_cfg)) = self.rst_queue.pop() {
+            let (mut packet, addrs) = OutgoingPacket::write_header(
+                buf,
+                local_addr,
+                local_ipv6_addr,
+                tuple.remote_addr,
+                hop_limit,
+            )?;
             let (seq, ack, flags_after_ns) = rst_cfg.seq_ack_tcp_flags();
             let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
-                packet.inner_mut().payload_mut(),
+                packet.payload_mut(),
                 seq,
                 ack,
                 flags_after_ns,
@@ -393,18 +444,10 @@ impl TcpIPv4Handler {
                 None,
             )
             .map_err(WriteNextError::TcpSegment)?
-            .finalize(
-                self.local_port,
-                tuple.remote_port,
-                Some((self.local_addr, tuple.remote_addr)),
-            )
+            .finalize(self.local_port, tuple.remote_port, Some(addrs))
             .len();
 
-            packet
-                .inner_mut()
-                .set_destination_address(tuple.remote_addr);
-
-            let packet_len = packet.with_payload_len_unchecked(segment_len, true).len();
+            let packet_len = packet.with_payload_len_unchecked(segment_len);
             // The unwrap() is safe because packet_len > 0.
             return Ok((
                 Some(NonZeroUsize::new(packet_len).unwrap()),
@@ -420,29 +463,27 @@ impl TcpIPv4Handler {
             // Tuples in self.active_connection or self.next_timeout should also appear as keys
             // in self.connections.
             let endpoint = self.connections.get_mut(tuple).unwrap();
+            let (mut packet, addrs) = OutgoingPacket::write_header(
+                &mut *buf,
+                local_addr,
+                local_ipv6_addr,
+                tuple.remote_addr,
+                hop_limit,
+            )?;
             // We need this block to clearly delimit the lifetime of the mutable borrow started by
-            // the following packet.inner_mut().
+            // the following packet.payload_mut().
             let segment_len = {
-                let maybe_segment =
-                    endpoint.write_next_segment(packet.inner_mut().payload_mut(), mss_reserved);
+                let maybe_segment = endpoint.write_next_segment(packet.payload_mut(), mss_reserved);
 
                 match maybe_segment {
                     Some(segment) => segment
-                        .finalize(
-                            self.local_port,
-                            tuple.remote_port,
-                            Some((self.local_addr, tuple.remote_addr)),
-                        )
+                        .finalize(self.local_port, tuple.remote_port, Some(addrs))
                         .len(),
                     None => continue,
                 }
             };
 
-            packet
-                .inner_mut()
-                .set_destination_address(tuple.remote_addr);
-
-            let ip_len = packet.with_payload_len_unchecked(segment_len, true).len();
+            let ip_len = packet.with_payload_len_unchecked(segment_len);
 
             // The unwrap is safe because ip_len > 0.
             len = Some(NonZeroUsize::new(ip_len).unwrap());
@@ -484,6 +525,74 @@ impl TcpIPv4Handler {
     }
 }
 
+// The incomplete packet written by write_next_packet, whose IP version is the one of the
+// connection sending it.
+enum OutgoingPacket<'a> {
+    V4(Incomplete<IPv4Packet<'a, &'a mut [u8]>>),
+    V6(Incomplete<IPv6Packet<'a, &'a mut [u8]>>),
+}
+
+impl<'a> OutgoingPacket<'a> {
+    // Writes the header of a packet heading towards remote_addr, and returns it along with the
+    // source and destination addresses, which the TCP checksum covers. The hop limit is left to
+    // the default of the packet writers when None.
+    fn write_header(
+        buf: &'a mut [u8],
+        local_addr: Ipv4Addr,
+        local_ipv6_addr: Option<Ipv6Addr>,
+        remote_addr: IpAddr,
+        hop_limit: Option<u8>,
+    ) -> Result<(Self, (IpAddr, IpAddr)), WriteNextError> {
+        match remote_addr {
+            IpAddr::V4(remote_addr) => {
+                let mut packet =
+                    IPv4Packet::write_header(buf, PROTOCOL_TCP, local_addr, remote_addr)
+                        .map_err(WriteNextError::IPv4Packet)?;
+                // The header checksum is computed once the packet is complete.
+                if let Some(ttl) = hop_limit {
+                    packet.inner_mut().set_ttl(ttl);
+                }
+                Ok((
+                    OutgoingPacket::V4(packet),
+                    (IpAddr::V4(local_addr), IpAddr::V4(remote_addr)),
+                ))
+            }
+            IpAddr::V6(remote_addr) => {
+                // The unwrap() is safe because connections over IPv6 are only accepted when the
+                // handler has an IPv6 address.
+                let local_addr = local_ipv6_addr.unwrap();
+                let mut packet =
+                    IPv6Packet::write_header(buf, PROTOCOL_TCP, local_addr, remote_addr)
+                        .map_err(WriteNextError::IPv6Packet)?;
+                if let Some(hop_limit) = hop_limit {
+                    packet.inner_mut().set_hop_limit(hop_limit);
+                }
+                Ok((
+                    OutgoingPacket::V6(packet),
+                    (IpAddr::V6(local_addr), IpAddr::V6(remote_addr)),
+                ))
+            }
+        }
+    }
+
+    fn payload_mut(&mut self) -> &mut [u8] {
+        match *self {
+            OutgoingPacket::V4(ref mut packet) => packet.inner_mut().payload_mut(),
+            OutgoingPacket::V6(ref mut packet) => packet.inner_mut().payload_mut(),
+        }
+    }
+
+    // Completes the packet, and returns its length.
+    fn with_payload_len_unchecked(self, payload_len: usize) -> usize {
+        match self {
+            OutgoingPacket::V4(packet) => {
+                packet.with_payload_len_unchecked(payload_len, true).len()
+            }
+            OutgoingPacket::V6(packet) => packet.with_payload_len_unchecked(payload_len).len(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use pdu::bytes::NetworkBytesMut;
@@ -497,7 +606,7 @@ mod tests {
     }
 
     fn write_next<'a>(
-        h: &mut TcpIPv4Handler,
+        h: &mut TcpIPHandler,
         buf: &'a mut [u8],
     ) -> Result<(Option<IPv4Packet<'a, &'a mut [u8]>>, WriteEvent), WriteNextError> {
         h.write_next_packet(buf).map(|(o, e)| {
@@ -512,7 +621,7 @@ mod tests {
     }
 
     fn next_written_segment<'a>(
-        h: &mut TcpIPv4Handler,
+        h: &mut TcpIPHandler,
         buf: &'a mut [u8],
         expected_event: WriteEvent,
     ) -> TcpSegment<'a, &'a mut [u8]> {
@@ -529,10 +638,7 @@ mod tests {
     // Calls write_next_packet until either an error occurs, or there's nothing left to send.
     // When successful, returns how many packets were written. The remote_addr argument is used
     // to check the packets are sent to the appropriate destination.
-    fn drain_packets(
-        h: &mut TcpIPv4Handler,
-        remote_addr: Ipv4Addr,
-    ) -> Result<usize, WriteNextError> {
+    fn drain_packets(h: &mut TcpIPHandler, remote_addr: Ipv4Addr) -> Result<usize, WriteNextError> {
         let mut buf = [0u8; 2000];
         let mut count: usize = 0;
         loop {
@@ -562,8 +668,9 @@ mod tests {
         let max_connections = 2;
         let max_pending_resets = 2;
 
-        let mut h = TcpIPv4Handler::new(
+        let mut h = TcpIPHandler::new(
             local_addr,
+            None,
             local_port,
             NonZeroUsize::new(max_connections).unwrap(),
             NonZeroUsize::new(max_pending_resets).unwrap(),
@@ -665,8 +772,8 @@ mod tests {
         assert_eq!(h.next_segment_status(), NextSegmentStatus::Available);
         assert_eq!(drain_packets(&mut h, remote_addr), Ok(1));
 
-        let remote_tuple = ConnectionTuple::new(remote_addr, remote_port);
-        let remote_tuple2 = ConnectionTuple::new(remote_addr, remote_port + 1);
+        let remote_tuple = ConnectionTuple::new(IpAddr::V4(remote_addr), remote_port);
+        let remote_tuple2 = ConnectionTuple::new(IpAddr::V4(remote_addr), remote_port + 1);
 
         // Also, there should be a retransmission timer associated with the previous SYNACK now.
         assert_eq!(h.active_connections.len(), 0);
@@ -730,7 +837,10 @@ mod tests {
         // The timeout associated with the SYNACK of the second connection should be next.
         assert_eq!(h.active_connections.len(), 0);
         if let Some((_, tuple)) = h.next_timeout {
-            assert_ne!(tuple, ConnectionTuple::new(remote_addr, remote_port));
+            assert_ne!(
+                tuple,
+                ConnectionTuple::new(IpAddr::V4(remote_addr), remote_port)
+            );
         } else {
             panic!("missing third expected timeout");
         }
@@ -775,4 +885,91 @@ mod tests {
         assert_eq!(h.connections.len(), 1);
         assert_eq!(h.active_connections.len(), 0);
     }
+
+    #[test]
+    fn test_handler_ipv6() {
+        let mut buf = [0u8; 100];
+        let mut buf2 = [0u8; 2000];
+
+        let local_addr = Ipv4Addr::new(169, 254, 169, 254);
+        let local_ipv6_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0xa9fe, 0xa9fe);
+        let wrong_local_ipv6_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0xa9fe, 0xa9fd);
+        let local_port = 80;
+        let remote_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 1, 2, 3, 4);
+        let remote_port = 1012;
+
+        let mut h = TcpIPHandler::new(
+            local_addr,
+            Some(local_ipv6_addr),
+            local_port,
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+        );
+
+        let mut p = IPv6Packet::write_header(
+            buf.as_mut(),
+            PROTOCOL_TCP,
+            remote_addr,
+            wrong_local_ipv6_addr,
+        )
+        .unwrap();
+
+        let s_len = TcpSegment::write_segment::<[u8]>(
+            p.inner_mut().payload_mut(),
+            remote_port,
+            local_port,
+            123,
+            456,
+            TcpFlags::SYN,
+            10000,
+            None,
+            100,
+            None,
+            None,
+        )
+        .unwrap()
+        .len();
+
+        let mut p = p.with_payload_len_unchecked(s_len);
+
+        assert_eq!(
+            h.receive_ipv6_packet(&p).unwrap_err(),
+            RecvError::InvalidAddress
+        );
+        p.set_destination_address(local_ipv6_addr);
+        assert_eq!(
+            h.receive_ipv6_packet(&p),
+            Ok(RecvEvent::NewConnectionSuccessful)
+        );
+        assert!(h
+            .connections
+            .contains_key(&ConnectionTuple::new(IpAddr::V6(remote_addr), remote_port)));
+
+        // The SYNACK goes out over IPv6, with a checksum which covers the IPv6 addresses.
+        let (len, _) = h.write_next_packet(buf2.as_mut()).unwrap();
+        let p2 = IPv6Packet::from_bytes(&buf2[..len.unwrap().get()]).unwrap();
+        assert_eq!(p2.next_header(), PROTOCOL_TCP);
+        assert_eq!(p2.source_address(), local_ipv6_addr);
+        assert_eq!(p2.destination_address(), remote_addr);
+        let s = TcpSegment::from_bytes(
+            p2.payload(),
+            Some((local_ipv6_addr.into(), remote_addr.into())),
+        )
+        .unwrap();
+        assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(s.destination_port(), remote_port);
+
+        // A handler without an IPv6 address does not accept IPv6 packets.
+        let mut h = TcpIPHandler::new(
+            local_addr,
+            None,
+            local_port,
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+        );
+        assert_eq!(
+            h.receive_ipv6_packet(&p).unwrap_err(),
+            RecvError::InvalidAddress
+        );
+    }
 }
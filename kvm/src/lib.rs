@@ -36,6 +36,10 @@ pub use kvm_gen::KVM_API_VERSION;
 /// Taken from Linux Kernel v4.14.13 (arch/x86/include/asm/kvm_host.h)
 pub const MAX_KVM_CPUID_ENTRIES: usize = 80;
 
+/// The maximum number of MSRs queried from `KVM_GET_MSR_INDEX_LIST`, which is well above the
+/// number of MSRs KVM saves and emulates.
+pub const MAX_KVM_MSR_ENTRIES: usize = 256;
+
 /// A wrapper around opening and using `/dev/kvm`.
 ///
 /// The handle is used to issue system ioctls.
@@ -169,6 +173,32 @@ impl Kvm {
         Ok(cpuid)
     }
 
+    /// X86 specific call to get the indices of the MSRs supported by KVM
+    /// (`KVM_GET_MSR_INDEX_LIST`).
+    ///
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub fn get_msr_index_list(&self) -> Result<Vec<u32>> {
+        // A `kvm_msr_list` is the number of MSRs followed by their indices, all of them u32.
+        let mut msr_list = vec![0u32; MAX_KVM_MSR_ENTRIES + 1];
+        msr_list[0] = MAX_KVM_MSR_ENTRIES as u32;
+
+        let ret = unsafe {
+            // ioctl is unsafe. The kernel is trusted not to write more indices than the number it
+            // is given in nmsrs, which is the number of indices allocated above.
+            ioctl_with_mut_ptr(
+                self,
+                KVM_GET_MSR_INDEX_LIST(),
+                msr_list.as_mut_ptr() as *mut kvm_msr_list,
+            )
+        };
+        if ret < 0 {
+            return errno_result();
+        }
+
+        let nmsrs = (msr_list[0] as usize).min(MAX_KVM_MSR_ENTRIES);
+        Ok(msr_list[1..nmsrs + 1].to_vec())
+    }
+
     /// Creates a VM fd using the KVM fd (`KVM_CREATE_VM`).
     ///
     /// A call to this function will also initialize the supported cpuid (`KVM_GET_SUPPORTED_CPUID`)
@@ -744,23 +774,23 @@ impl VcpuFd {
 
     /// X86 specific call to setup the MSRS.
     ///
-    /// See the documentation for `KVM_SET_MSRS`.
+    /// See the documentation for `KVM_SET_MSRS`. Returns the number of MSRs written, KVM stops
+    /// at the first MSR it rejects.
     ///
     /// # Arguments
     ///
     /// * `kvm_msrs` - MSRs to be written.
     ///
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-    pub fn set_msrs(&self, msrs: &kvm_msrs) -> Result<()> {
+    pub fn set_msrs(&self, msrs: &kvm_msrs) -> Result<i32> {
         let ret = unsafe {
             // Here we trust the kernel not to read past the end of the kvm_msrs struct.
             ioctl_with_ref(self, KVM_SET_MSRS(), msrs)
         };
         if ret < 0 {
-            // KVM_SET_MSRS actually returns the number of msr entries written.
             return errno_result();
         }
-        Ok(())
+        Ok(ret)
     }
 
     /// Returns a reference to the `kvm_run` structure obtained by mmap-ing the associated `VcpuFd`.
@@ -1023,6 +1053,17 @@ mod tests {
         }
     }
 
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn test_get_msr_index_list() {
+        let kvm = Kvm::new().unwrap();
+        let msr_list = kvm.get_msr_index_list().unwrap();
+        assert!(!msr_list.is_empty());
+        assert!(msr_list.len() <= MAX_KVM_MSR_ENTRIES);
+        // IA32_SYSENTER_CS is among the MSRs saved by KVM on every host.
+        assert!(msr_list.contains(&0x174));
+    }
+
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     #[test]
     fn test_get_cpuid_features() {
@@ -1234,7 +1275,10 @@ mod tests {
             entries.copy_from_slice(&configured_entry_vec);
         }
         msrs.nmsrs = configured_entry_vec.len() as u32;
-        vcpu.set_msrs(msrs).unwrap();
+        assert_eq!(
+            vcpu.set_msrs(msrs).unwrap(),
+            configured_entry_vec.len() as i32
+        );
 
         //now test that GET_MSRS returns the same
         let wanted_kvm_msrs_entries = [
@@ -24,10 +24,14 @@
 //! If if turns out this approach is not really what we want, it's pretty easy to resort to
 //! something else, while working behind the same interface.
 
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
 
 use chrono;
+use serde::ser::SerializeMap;
 use serde::{Serialize, Serializer};
+use serde_json;
 
 const SYSCALL_MAX: usize = 350;
 
@@ -102,6 +106,14 @@ impl Metric for SharedMetric {
     }
 }
 
+impl SharedMetric {
+    /// Zeroes the counter, so that the next flush only reports what it counts from now on.
+    pub fn reset(&self) {
+        let count = self.0.load(Ordering::Relaxed);
+        self.1.store(count, Ordering::Relaxed);
+    }
+}
+
 impl Serialize for SharedMetric {
     /// Reset counters of each metrics. Here we suppose that Serialize's goal is to help with the
     /// flushing of metrics.
@@ -118,6 +130,46 @@ impl Serialize for SharedMetric {
     }
 }
 
+/// Representation of a metric which holds the last value sampled, such as the size of a resource,
+/// instead of counting events. It is not reset when the metrics are flushed.
+#[derive(Default)]
+pub struct GaugeMetric(AtomicUsize);
+
+impl GaugeMetric {
+    /// Replaces the sampled value with `value`.
+    pub fn set(&self, value: usize) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    /// Returns the last sampled value.
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Does nothing: a gauge keeps its last sampled value when the counters are zeroed.
+    pub fn reset(&self) {}
+}
+
+impl Serialize for GaugeMetric {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0.load(Ordering::Relaxed) as u64)
+    }
+}
+
+// Implements `reset` on a group of metrics, by resetting each of its fields. The group is
+// destructured so that a metric added to it can't be left out.
+macro_rules! impl_reset {
+    ($group:ident { $($field:ident),* $(,)* }) => {
+        impl $group {
+            /// Zeroes the counters of the group.
+            pub fn reset(&self) {
+                let $group { $(ref $field),* } = *self;
+                $($field.reset();)*
+            }
+        }
+    };
+}
+
 // The following structs are used to define a certain organization for the set of metrics we
 // are interested in. Whenever the name of a field differs from its ideal textual representation
 // in the serialized form, we can use the #[serde(rename = "name")] attribute to, well, rename it.
@@ -133,6 +185,114 @@ pub struct ApiServerMetrics {
     pub sync_outcome_fails: SharedMetric,
     /// Number of timeouts during communication with the VMM.
     pub sync_vmm_send_timeout_count: SharedMetric,
+    /// Number of connections closed right away because too many connections were open.
+    pub connections_rejected_count: SharedMetric,
+    /// Metrics of the requests on each API endpoint.
+    pub endpoints: ApiEndpointsMetrics,
+}
+
+impl_reset!(ApiServerMetrics {
+    process_startup_time_us,
+    process_startup_time_cpu_us,
+    sync_outcome_fails,
+    sync_vmm_send_timeout_count,
+    connections_rejected_count,
+    endpoints,
+});
+
+/// The upper bounds, in microseconds, of the buckets of the API request latency histograms.
+/// The last bucket holds the latencies above the largest bound.
+pub const API_LATENCY_BUCKETS_US: [u64; 5] = [100, 1_000, 10_000, 100_000, 1_000_000];
+
+/// A histogram of the latencies of the API requests on an endpoint.
+#[derive(Default)]
+pub struct LatencyHistogram([SharedMetric; 6]);
+
+impl LatencyHistogram {
+    /// Counts a request which took `latency_us` microseconds in its bucket.
+    pub fn record(&self, latency_us: u64) {
+        let bucket = API_LATENCY_BUCKETS_US
+            .iter()
+            .position(|bound| latency_us <= *bound)
+            .unwrap_or(API_LATENCY_BUCKETS_US.len());
+        self.0[bucket].inc();
+    }
+
+    /// Zeroes the counters of all the buckets.
+    pub fn reset(&self) {
+        for metric in self.0.iter() {
+            metric.reset();
+        }
+    }
+}
+
+impl Serialize for LatencyHistogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (bound, metric) in API_LATENCY_BUCKETS_US.iter().zip(self.0.iter()) {
+            map.serialize_entry(&format!("le_{}", bound), metric)?;
+        }
+        map.serialize_entry("inf", &self.0[API_LATENCY_BUCKETS_US.len()])?;
+        map.end()
+    }
+}
+
+/// Metrics of the requests on a single API endpoint.
+#[derive(Default, Serialize)]
+pub struct ApiEndpointMetrics {
+    /// Number of requests.
+    pub count: SharedMetric,
+    /// Number of requests which failed, with a 4xx or 5xx status code or a timeout.
+    pub fails: SharedMetric,
+    /// Latencies of the requests, in microseconds.
+    pub latency_us: LatencyHistogram,
+}
+
+impl_reset!(ApiEndpointMetrics {
+    count,
+    fails,
+    latency_us,
+});
+
+/// The metrics of all the API endpoints, serialized as a map keyed by method and route.
+// Like the vsock port metrics, the entries are created the first time the endpoint is hit, so
+// the map needs a lock. It is only taken for writing the first time.
+#[derive(Default)]
+pub struct ApiEndpointsMetrics(RwLock<BTreeMap<String, Arc<ApiEndpointMetrics>>>);
+
+impl ApiEndpointsMetrics {
+    /// Returns the metrics of `endpoint`, creating them if it was not registered before.
+    ///
+    /// The endpoints should not contain resource IDs, which would create new metrics for each
+    /// resource.
+    pub fn register(&self, endpoint: &str) -> Arc<ApiEndpointMetrics> {
+        if let Some(metrics) = self.0.read().expect("Poisoned lock").get(endpoint) {
+            return metrics.clone();
+        }
+        let mut endpoints = self.0.write().expect("Poisoned lock");
+        endpoints
+            .entry(endpoint.to_string())
+            .or_insert_with(|| Arc::new(ApiEndpointMetrics::default()))
+            .clone()
+    }
+
+    /// Zeroes the counters of all the registered entries.
+    pub fn reset(&self) {
+        for metrics in self.0.read().expect("Poisoned lock").values() {
+            metrics.reset();
+        }
+    }
+}
+
+impl Serialize for ApiEndpointsMetrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let endpoints = self.0.read().expect("Poisoned lock");
+        let mut map = serializer.serialize_map(Some(endpoints.len()))?;
+        for (endpoint, metrics) in endpoints.iter() {
+            map.serialize_entry(endpoint, metrics.as_ref())?;
+        }
+        map.end()
+    }
 }
 
 /// Metrics specific to GET API Requests for counting user triggered actions and/or failures.
@@ -146,8 +306,21 @@ pub struct GetRequestsMetrics {
     pub machine_cfg_count: SharedMetric,
     /// Number of failures during GETs for getting information on the instance.
     pub machine_cfg_fails: SharedMetric,
+    /// Number of GETs for getting the features negotiated by a device.
+    pub device_features_count: SharedMetric,
+    /// Number of GETs for getting the events reported by the guest.
+    pub events_count: SharedMetric,
 }
 
+impl_reset!(GetRequestsMetrics {
+    instance_info_count,
+    instance_info_fails,
+    machine_cfg_count,
+    machine_cfg_fails,
+    device_features_count,
+    events_count,
+});
+
 /// Metrics specific to PUT API Requests for counting user triggered actions and/or failures.
 #[derive(Default, Serialize)]
 pub struct PutRequestsMetrics {
@@ -159,6 +332,10 @@ pub struct PutRequestsMetrics {
     pub boot_source_count: SharedMetric,
     /// Number of failures during attaching source of boot.
     pub boot_source_fails: SharedMetric,
+    /// Number of PUTs for setting a CPU template defined by the user.
+    pub cpu_template_count: SharedMetric,
+    /// Number of failures in setting a CPU template defined by the user.
+    pub cpu_template_fails: SharedMetric,
     /// Number of PUTs triggering a block attach.
     pub drive_count: SharedMetric,
     /// Number of failures in attaching a block device.
@@ -177,6 +354,23 @@ pub struct PutRequestsMetrics {
     pub network_fails: SharedMetric,
 }
 
+impl_reset!(PutRequestsMetrics {
+    actions_count,
+    actions_fails,
+    boot_source_count,
+    boot_source_fails,
+    cpu_template_count,
+    cpu_template_fails,
+    drive_count,
+    drive_fails,
+    logger_count,
+    logger_fails,
+    machine_cfg_count,
+    machine_cfg_fails,
+    network_count,
+    network_fails,
+});
+
 /// Metrics specific to PATCH API Requests for counting user triggered actions and/or failures.
 #[derive(Default, Serialize)]
 pub struct PatchRequestsMetrics {
@@ -184,8 +378,19 @@ pub struct PatchRequestsMetrics {
     pub drive_count: SharedMetric,
     /// Number of failures in PATCHing a block device.
     pub drive_fails: SharedMetric,
+    /// Number of tries to PATCH a network interface.
+    pub network_count: SharedMetric,
+    /// Number of failures in PATCHing a network interface.
+    pub network_fails: SharedMetric,
 }
 
+impl_reset!(PatchRequestsMetrics {
+    drive_count,
+    drive_fails,
+    network_count,
+    network_fails,
+});
+
 /// Block Device associated metrics.
 #[derive(Default, Serialize)]
 pub struct BlockDeviceMetrics {
@@ -213,8 +418,26 @@ pub struct BlockDeviceMetrics {
     pub read_count: SharedMetric,
     /// Number of bytes written by this block device.
     pub write_count: SharedMetric,
+    /// Number of errors while writing requests to an I/O trace.
+    pub trace_fails: SharedMetric,
 }
 
+impl_reset!(BlockDeviceMetrics {
+    activate_fails,
+    cfg_fails,
+    event_fails,
+    execute_fails,
+    invalid_reqs_count,
+    flush_count,
+    queue_event_count,
+    rate_limiter_event_count,
+    update_count,
+    update_fails,
+    read_count,
+    write_count,
+    trace_fails,
+});
+
 /// Metrics specific to the i8042 device.
 #[derive(Default, Serialize)]
 pub struct I8042DeviceMetrics {
@@ -232,6 +455,67 @@ pub struct I8042DeviceMetrics {
     pub write_count: SharedMetric,
 }
 
+impl_reset!(I8042DeviceMetrics {
+    error_count,
+    missed_read_count,
+    missed_write_count,
+    read_count,
+    reset_count,
+    write_count,
+});
+
+/// Metrics for the panics and crashes of the guest.
+#[derive(Default, Serialize)]
+pub struct GuestEventMetrics {
+    /// Number of times the guest kernel reported a panic through the pvpanic device.
+    pub panic_count: SharedMetric,
+    /// Number of times the guest kernel reported a panic handled by a crash kernel.
+    pub crash_loaded_count: SharedMetric,
+    /// Number of triple faults, after which the vCPU shut down.
+    pub triple_fault_count: SharedMetric,
+    /// Errors triggered while using the pvpanic device.
+    pub error_count: SharedMetric,
+    /// Number of superfluous read intents on the pvpanic device.
+    pub missed_read_count: SharedMetric,
+    /// Number of superfluous write intents on the pvpanic device.
+    pub missed_write_count: SharedMetric,
+}
+
+impl_reset!(GuestEventMetrics {
+    panic_count,
+    crash_loaded_count,
+    triple_fault_count,
+    error_count,
+    missed_read_count,
+    missed_write_count,
+});
+
+/// Metrics specific to the watchdog device.
+#[derive(Default, Serialize)]
+pub struct WatchdogMetrics {
+    /// Number of times the watchdog expired because the guest stopped pinging it.
+    pub expired_count: SharedMetric,
+    /// Errors triggered while using the watchdog device.
+    pub error_count: SharedMetric,
+    /// Number of superfluous read intents on the watchdog device.
+    pub missed_read_count: SharedMetric,
+    /// Number of superfluous write intents on the watchdog device.
+    pub missed_write_count: SharedMetric,
+    /// Number of times the guest started or pinged the watchdog.
+    pub ping_count: SharedMetric,
+    /// Number of times the guest stopped the watchdog.
+    pub stop_count: SharedMetric,
+}
+
+impl_reset!(WatchdogMetrics {
+    expired_count,
+    error_count,
+    missed_read_count,
+    missed_write_count,
+    ping_count,
+    stop_count,
+});
+
 /// Metrics for the logging subsystem.
 #[derive(Default, Serialize)]
 pub struct LoggerSystemMetrics {
@@ -245,6 +529,13 @@ pub struct LoggerSystemMetrics {
     pub log_fails: SharedMetric,
 }
 
+impl_reset!(LoggerSystemMetrics {
+    missed_metrics_count,
+    metrics_fails,
+    missed_log_count,
+    log_fails,
+});
+
 /// Metrics for the MMDS functionality.
 #[derive(Default, Serialize)]
 pub struct MmdsMetrics {
@@ -256,18 +547,66 @@ pub struct MmdsMetrics {
     pub rx_accepted_unusual: SharedMetric,
     /// The number of buffers which couldn't be parsed as valid Ethernet frames by the MMDS.
     pub rx_bad_eth: SharedMetric,
+    /// The number of requests rejected by the MMDS because of an invalid URI.
+    pub rx_bad_uri: SharedMetric,
+    /// The number of requests rejected by the MMDS because of a malformed header.
+    pub rx_bad_header: SharedMetric,
+    /// The number of requests rejected by the MMDS because of an invalid Content-Length.
+    pub rx_bad_content_length: SharedMetric,
+    /// The number of frames for the MMDS dropped because of the MAC address of their sender.
+    pub rx_bad_src_mac: SharedMetric,
+    /// The number of requests which couldn't be parsed by the MMDS, whatever the reason.
+    pub rx_bad_request: SharedMetric,
+    /// The number of GET requests received by the MMDS.
+    pub rx_get: SharedMetric,
+    /// The number of PUT requests received by the MMDS.
+    pub rx_put: SharedMetric,
+    /// The number of PATCH requests received by the MMDS.
+    pub rx_patch: SharedMetric,
     /// The total number of bytes sent by the MMDS.
     pub tx_bytes: SharedMetric,
     /// The number of errors raised by the MMDS while attempting to send frames/packets/segments.
     pub tx_errors: SharedMetric,
     /// The number of frames sent by the MMDS.
     pub tx_frames: SharedMetric,
+    /// The number of 2xx responses sent by the MMDS.
+    pub tx_2xx: SharedMetric,
+    /// The number of 3xx responses sent by the MMDS.
+    pub tx_3xx: SharedMetric,
+    /// The number of 4xx responses sent by the MMDS.
+    pub tx_4xx: SharedMetric,
+    /// The number of 5xx responses sent by the MMDS.
+    pub tx_5xx: SharedMetric,
     /// The number of connections successfully accepted by the MMDS TCP handler.
     pub connections_created: SharedMetric,
     /// The number of connections cleaned up by the MMDS TCP handler.
     pub connections_destroyed: SharedMetric,
 }
 
+impl_reset!(MmdsMetrics {
+    rx_accepted,
+    rx_accepted_err,
+    rx_accepted_unusual,
+    rx_bad_eth,
+    rx_bad_uri,
+    rx_bad_header,
+    rx_bad_content_length,
+    rx_bad_src_mac,
+    rx_bad_request,
+    rx_get,
+    rx_put,
+    rx_patch,
+    tx_bytes,
+    tx_errors,
+    tx_frames,
+    tx_2xx,
+    tx_3xx,
+    tx_4xx,
+    tx_5xx,
+    connections_created,
+    connections_destroyed,
+});
+
 /// Network-related metrics.
 #[derive(Default, Serialize)]
 pub struct NetDeviceMetrics {
@@ -299,8 +638,28 @@ pub struct NetDeviceMetrics {
     pub tx_queue_event_count: SharedMetric,
     /// Number of events associated with the rate limiter installed on the transmitting path.
     pub tx_rate_limiter_event_count: SharedMetric,
+    /// Number of errors while writing frames to a packet capture.
+    pub capture_fails: SharedMetric,
 }
 
+impl_reset!(NetDeviceMetrics {
+    activate_fails,
+    cfg_fails,
+    event_fails,
+    rx_queue_event_count,
+    rx_event_rate_limiter_count,
+    rx_tap_event_count,
+    rx_bytes_count,
+    rx_packets_count,
+    rx_fails,
+    tx_bytes_count,
+    tx_fails,
+    tx_packets_count,
+    tx_queue_event_count,
+    tx_rate_limiter_event_count,
+    capture_fails,
+});
+
 /// Metrics for the seccomp filtering.
 #[derive(Serialize)]
 pub struct SeccompMetrics {
@@ -323,6 +682,20 @@ impl Default for SeccompMetrics {
     }
 }
 
+impl SeccompMetrics {
+    /// Zeroes the counters of the group.
+    pub fn reset(&self) {
+        let SeccompMetrics {
+            ref bad_syscalls,
+            ref num_faults,
+        } = *self;
+        for metric in bad_syscalls.iter() {
+            metric.reset();
+        }
+        num_faults.reset();
+    }
+}
+
 /// Metrics specific to the UART device.
 #[derive(Default, Serialize)]
 pub struct SerialDeviceMetrics {
@@ -340,6 +713,15 @@ pub struct SerialDeviceMetrics {
     pub write_count: SharedMetric,
 }
 
+impl_reset!(SerialDeviceMetrics {
+    error_count,
+    flush_count,
+    missed_read_count,
+    missed_write_count,
+    read_count,
+    write_count,
+});
+
 /// Metrics specific to VCPUs' mode of functioning.
 #[derive(Default, Serialize)]
 pub struct VcpuMetrics {
@@ -355,6 +737,152 @@ pub struct VcpuMetrics {
     pub failures: SharedMetric,
     /// Failures in configuring the CPUID.
     pub fitler_cpuid: SharedMetric,
+    /// CPU time used by the thread of each vCPU.
+    pub threads: VcpuThreadsMetrics,
+}
+
+impl_reset!(VcpuMetrics {
+    exit_io_in,
+    exit_io_out,
+    exit_mmio_read,
+    exit_mmio_write,
+    failures,
+    fitler_cpuid,
+    threads,
+});
+
+/// CPU time used by the thread of a single vCPU, sampled when the metrics are flushed.
+#[derive(Default, Serialize)]
+pub struct VcpuThreadMetrics {
+    /// Microseconds spent by the thread in user mode, including the time running guest code.
+    pub user_time_us: SharedMetric,
+    /// Microseconds spent by the thread in kernel mode.
+    pub sys_time_us: SharedMetric,
+    /// Microseconds spent by the thread running guest code.
+    pub guest_time_us: SharedMetric,
+    /// Percentage of the time between the last two flushes during which the thread ran. The vCPU
+    /// was halted, or waiting for the host, the rest of the time.
+    pub run_pct: GaugeMetric,
+}
+
+impl_reset!(VcpuThreadMetrics {
+    user_time_us,
+    sys_time_us,
+    guest_time_us,
+    run_pct,
+});
+
+/// The metrics of all the vCPU threads, serialized as a map keyed by vCPU index.
+// Like the vsock port metrics, the number of vCPUs is only known at runtime.
+#[derive(Default)]
+pub struct VcpuThreadsMetrics(RwLock<BTreeMap<u8, Arc<VcpuThreadMetrics>>>);
+
+impl VcpuThreadsMetrics {
+    /// Returns the metrics of the vCPU `cpu_id`, creating them if it was not registered before.
+    pub fn register(&self, cpu_id: u8) -> Arc<VcpuThreadMetrics> {
+        let mut vcpus = self.0.write().expect("Poisoned lock");
+        vcpus
+            .entry(cpu_id)
+            .or_insert_with(|| Arc::new(VcpuThreadMetrics::default()))
+            .clone()
+    }
+
+    /// Zeroes the counters of all the registered entries.
+    pub fn reset(&self) {
+        for metrics in self.0.read().expect("Poisoned lock").values() {
+            metrics.reset();
+        }
+    }
+}
+
+impl Serialize for VcpuThreadsMetrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let vcpus = self.0.read().expect("Poisoned lock");
+        let mut map = serializer.serialize_map(Some(vcpus.len()))?;
+        for (cpu_id, metrics) in vcpus.iter() {
+            map.serialize_entry(cpu_id, metrics.as_ref())?;
+        }
+        map.end()
+    }
+}
+
+/// Metrics specific to the vsock device.
+#[derive(Default, Serialize)]
+pub struct VsockDeviceMetrics {
+    /// Number of host connections relayed to the guest.
+    pub conn_established_count: SharedMetric,
+    /// Number of host connections closed because the guest could not be reached.
+    pub conn_rejected_count: SharedMetric,
+    /// Number of bytes received from the guest.
+    pub rx_bytes_count: SharedMetric,
+    /// Number of bytes sent to the guest.
+    pub tx_bytes_count: SharedMetric,
+    /// Metrics of each forwarded guest port.
+    pub ports: VsockPortsMetrics,
+}
+
+impl_reset!(VsockDeviceMetrics {
+    conn_established_count,
+    conn_rejected_count,
+    rx_bytes_count,
+    tx_bytes_count,
+    ports,
+});
+
+/// Metrics of the connections forwarded to a single guest vsock port.
+#[derive(Default, Serialize)]
+pub struct VsockPortMetrics {
+    /// Number of host connections relayed to the port.
+    pub conn_established_count: SharedMetric,
+    /// Number of host connections closed because the port could not be reached.
+    pub conn_rejected_count: SharedMetric,
+    /// Number of bytes received from the port.
+    pub rx_bytes_count: SharedMetric,
+    /// Number of bytes sent to the port.
+    pub tx_bytes_count: SharedMetric,
+}
+
+impl_reset!(VsockPortMetrics {
+    conn_established_count,
+    conn_rejected_count,
+    rx_bytes_count,
+    tx_bytes_count,
+});
+
+/// The metrics of all the forwarded guest vsock ports, serialized as a map keyed by port.
+// The set of ports is only known at runtime, so unlike the other metrics this one needs a lock.
+// It is only taken for writing when a port is registered: the entries are reference counted, so
+// the forwarding threads update their own port metrics without locking.
+#[derive(Default)]
+pub struct VsockPortsMetrics(RwLock<BTreeMap<u32, Arc<VsockPortMetrics>>>);
+
+impl VsockPortsMetrics {
+    /// Returns the metrics of `port`, creating them if the port was not registered before.
+    pub fn register(&self, port: u32) -> Arc<VsockPortMetrics> {
+        let mut ports = self.0.write().expect("Poisoned lock");
+        ports
+            .entry(port)
+            .or_insert_with(|| Arc::new(VsockPortMetrics::default()))
+            .clone()
+    }
+
+    /// Zeroes the counters of all the registered entries.
+    pub fn reset(&self) {
+        for metrics in self.0.read().expect("Poisoned lock").values() {
+            metrics.reset();
+        }
+    }
+}
+
+impl Serialize for VsockPortsMetrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ports = self.0.read().expect("Poisoned lock");
+        let mut map = serializer.serialize_map(Some(ports.len()))?;
+        for (port, metrics) in ports.iter() {
+            map.serialize_entry(port, metrics.as_ref())?;
+        }
+        map.end()
+    }
 }
 
 /// Metrics specific to the machine manager as a whole.
@@ -366,13 +894,26 @@ pub struct VmmMetrics {
     pub panic_count: SharedMetric,
 }
 
+impl_reset!(VmmMetrics {
+    device_events,
+    panic_count,
+});
+
 /// Memory usage metrics.
 #[derive(Default, Serialize)]
 pub struct MemoryMetrics {
     /// Number of pages dirtied since the last call to `KVM_GET_DIRTY_LOG`.
     pub dirty_pages: SharedMetric,
+    /// Number of pages of the process merged by Kernel Samepage Merging, sampled when the metrics
+    /// are flushed.
+    pub ksm_merging_pages: GaugeMetric,
 }
 
+impl_reset!(MemoryMetrics {
+    dirty_pages,
+    ksm_merging_pages,
+});
+
 // The sole purpose of this struct is to produce an UTC timestamp when an instance is serialized.
 #[derive(Default)]
 struct SerializeToUtcTimestampMs;
@@ -393,6 +934,8 @@ pub struct FirecrackerMetrics {
     pub block: BlockDeviceMetrics,
     /// Metrics related to API GET requests.
     pub get_api_requests: GetRequestsMetrics,
+    /// Metrics related to the panics and crashes of the guest.
+    pub guest_events: GuestEventMetrics,
     /// Metrics relaetd to the i8042 device.
     pub i8042: I8042DeviceMetrics,
     /// Logging related metrics.
@@ -411,12 +954,100 @@ pub struct FirecrackerMetrics {
     pub vcpu: VcpuMetrics,
     /// Metrics related to the virtual machine manager.
     pub vmm: VmmMetrics,
+    /// Metrics related to the vsock devices.
+    pub vsock: VsockDeviceMetrics,
     /// Metrics related to the UART device.
     pub uart: SerialDeviceMetrics,
+    /// Metrics related to the watchdog device.
+    pub watchdog: WatchdogMetrics,
     /// Memory usage metrics.
     pub memory: MemoryMetrics,
 }
 
+impl FirecrackerMetrics {
+    /// Zeroes the counters of the given groups of metrics, such as `"net"` or `"vcpu"`, or of all
+    /// the groups when none is given, so that the next flush only reports what they count from now
+    /// on. The gauges keep their last sampled value.
+    ///
+    /// Nothing is reset when one of the groups doesn't exist, and its name is returned.
+    pub fn reset(&self, groups: &[String]) -> Result<(), String> {
+        if groups.is_empty() {
+            self.reset_all();
+            return Ok(());
+        }
+
+        let mut resets: Vec<fn(&FirecrackerMetrics)> = Vec::with_capacity(groups.len());
+        for group in groups {
+            // The names are the ones of the fields in the serialized metrics.
+            resets.push(match group.as_str() {
+                "api_server" => |m| m.api_server.reset(),
+                "block" => |m| m.block.reset(),
+                "get_api_requests" => |m| m.get_api_requests.reset(),
+                "guest_events" => |m| m.guest_events.reset(),
+                "i8042" => |m| m.i8042.reset(),
+                "logger" => |m| m.logger.reset(),
+                "mmds" => |m| m.mmds.reset(),
+                "net" => |m| m.net.reset(),
+                "patch_api_requests" => |m| m.patch_api_requests.reset(),
+                "put_api_requests" => |m| m.put_api_requests.reset(),
+                "seccomp" => |m| m.seccomp.reset(),
+                "vcpu" => |m| m.vcpu.reset(),
+                "vmm" => |m| m.vmm.reset(),
+                "vsock" => |m| m.vsock.reset(),
+                "uart" => |m| m.uart.reset(),
+                "watchdog" => |m| m.watchdog.reset(),
+                "memory" => |m| m.memory.reset(),
+                _ => return Err(group.clone()),
+            });
+        }
+        for reset in resets {
+            reset(self);
+        }
+        Ok(())
+    }
+
+    // The metrics are destructured so that a group added to them can't be left out.
+    fn reset_all(&self) {
+        let FirecrackerMetrics {
+            utc_timestamp_ms: _,
+            ref api_server,
+            ref block,
+            ref get_api_requests,
+            ref guest_events,
+            ref i8042,
+            ref logger,
+            ref mmds,
+            ref net,
+            ref patch_api_requests,
+            ref put_api_requests,
+            ref seccomp,
+            ref vcpu,
+            ref vmm,
+            ref vsock,
+            ref uart,
+            ref watchdog,
+            ref memory,
+        } = *self;
+        api_server.reset();
+        block.reset();
+        get_api_requests.reset();
+        guest_events.reset();
+        i8042.reset();
+        logger.reset();
+        mmds.reset();
+        net.reset();
+        patch_api_requests.reset();
+        put_api_requests.reset();
+        seccomp.reset();
+        vcpu.reset();
+        vmm.reset();
+        vsock.reset();
+        uart.reset();
+        watchdog.reset();
+        memory.reset();
+    }
+}
+
 lazy_static! {
     /// Static instance used for handling metrics.
     ///
@@ -475,9 +1106,119 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_gauge_metric() {
+        let gauge = GaugeMetric::default();
+        gauge.set(10);
+        gauge.set(7);
+        assert_eq!(gauge.get(), 7);
+
+        // Flushing the metrics doesn't reset the gauge.
+        assert_eq!(serde_json::to_string(&gauge).unwrap(), "7");
+        assert_eq!(serde_json::to_string(&gauge).unwrap(), "7");
+    }
+
+    #[test]
+    fn test_vsock_ports_metrics() {
+        let ports = VsockPortsMetrics::default();
+        assert_eq!(serde_json::to_string(&ports).unwrap(), "{}");
+
+        ports.register(52).conn_established_count.inc();
+        ports.register(52).tx_bytes_count.add(10);
+        ports.register(1024).conn_rejected_count.inc();
+
+        let expected = "{\"52\":{\"conn_established_count\":1,\"conn_rejected_count\":0,\
+                        \"rx_bytes_count\":0,\"tx_bytes_count\":10},\
+                        \"1024\":{\"conn_established_count\":0,\"conn_rejected_count\":1,\
+                        \"rx_bytes_count\":0,\"tx_bytes_count\":0}}";
+        assert_eq!(serde_json::to_string(&ports).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_vcpu_threads_metrics() {
+        let vcpus = VcpuThreadsMetrics::default();
+        assert_eq!(serde_json::to_string(&vcpus).unwrap(), "{}");
+
+        vcpus.register(0).user_time_us.add(1500);
+        vcpus.register(0).run_pct.set(20);
+        vcpus.register(1).sys_time_us.add(10);
+
+        let expected = "{\"0\":{\"user_time_us\":1500,\"sys_time_us\":0,\"guest_time_us\":0,\
+                        \"run_pct\":20},\
+                        \"1\":{\"user_time_us\":0,\"sys_time_us\":10,\"guest_time_us\":0,\
+                        \"run_pct\":0}}";
+        assert_eq!(serde_json::to_string(&vcpus).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_api_endpoints_metrics() {
+        let endpoints = ApiEndpointsMetrics::default();
+        assert_eq!(serde_json::to_string(&endpoints).unwrap(), "{}");
+
+        let metrics = endpoints.register("PUT /drives/{id}");
+        metrics.count.inc();
+        metrics.latency_us.record(100);
+        metrics.latency_us.record(5_000_000);
+        let metrics = endpoints.register("PUT /drives/{id}");
+        metrics.count.inc();
+        metrics.fails.inc();
+        metrics.latency_us.record(101);
+        endpoints.register("GET /").count.inc();
+
+        let expected = "{\"GET /\":{\"count\":1,\"fails\":0,\"latency_us\":{\"le_100\":0,\
+                        \"le_1000\":0,\"le_10000\":0,\"le_100000\":0,\"le_1000000\":0,\"inf\":0}},\
+                        \"PUT /drives/{id}\":{\"count\":2,\"fails\":1,\"latency_us\":{\
+                        \"le_100\":1,\"le_1000\":1,\"le_10000\":0,\"le_100000\":0,\
+                        \"le_1000000\":0,\"inf\":1}}}";
+        assert_eq!(serde_json::to_string(&endpoints).unwrap(), expected);
+    }
+
     #[test]
     fn test_serialize() {
         let s = serde_json::to_string(&FirecrackerMetrics::default());
         assert!(s.is_ok());
     }
+
+    #[test]
+    fn test_reset() {
+        let metrics = FirecrackerMetrics::default();
+        metrics.net.rx_packets_count.add(5);
+        metrics.vcpu.exit_io_in.inc();
+
+        // Nothing is reset when a group doesn't exist.
+        assert_eq!(
+            metrics.reset(&["net".to_string(), "foo".to_string()]),
+            Err("foo".to_string())
+        );
+        let value = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(value["net"]["rx_packets_count"], 5);
+        assert_eq!(value["vcpu"]["exit_io_in"], 1);
+
+        metrics.net.rx_packets_count.add(5);
+        metrics.vcpu.exit_io_in.inc();
+        metrics.uart.read_count.inc();
+        assert_eq!(
+            metrics.reset(&["net".to_string(), "vcpu".to_string()]),
+            Ok(())
+        );
+        let value = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(value["net"]["rx_packets_count"], 0);
+        assert_eq!(value["vcpu"]["exit_io_in"], 0);
+        assert_eq!(value["uart"]["read_count"], 1);
+
+        // All the groups are reset when none is given, including the registered entries.
+        metrics.uart.read_count.inc();
+        metrics.vsock.ports.register(1234).rx_bytes_count.add(3);
+        assert_eq!(metrics.reset(&[]), Ok(()));
+        let value = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(value["uart"]["read_count"], 0);
+        assert_eq!(value["vsock"]["ports"]["1234"]["rx_bytes_count"], 0);
+
+        // Every group of the serialized metrics can be reset.
+        for group in value.as_object().unwrap().keys() {
+            if group != "utc_timestamp_ms" {
+                assert_eq!(metrics.reset(&[group.clone()]), Ok(()));
+            }
+        }
+    }
 }
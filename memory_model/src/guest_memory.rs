@@ -11,6 +11,8 @@ use std::io::{Read, Write};
 use std::result;
 use std::sync::Arc;
 
+use libc;
+
 use guest_address::GuestAddress;
 use mmap::{self, MemoryMapping};
 use DataInit;
@@ -22,6 +24,8 @@ pub enum Error {
     InvalidGuestAddress(GuestAddress),
     /// Failure in accessing the memory located at some address.
     MemoryAccess(GuestAddress, mmap::Error),
+    /// Failure in advising the kernel about the usage of the memory regions.
+    MemoryAdviseFailed(mmap::Error),
     /// Failure in creating an anonymous shared mapping.
     MemoryMappingFailed(mmap::Error),
     /// Failure in initializing guest memory.
@@ -156,6 +160,29 @@ impl GuestMemory {
         }
         Ok(())
     }
+
+    /// Applies the same `madvise` hint to all the memory regions.
+    ///
+    /// # Arguments
+    /// * `advice` - One of the `libc::MADV_*` values accepted by `madvise`.
+    pub fn madvise(&self, advice: libc::c_int) -> Result<()> {
+        for region in self.regions.iter() {
+            region
+                .mapping
+                .madvise(advice)
+                .map_err(Error::MemoryAdviseFailed)?;
+        }
+        Ok(())
+    }
+
+    /// Allocates host memory for all the pages of all the memory regions, so that the guest does
+    /// not take page faults on its first accesses.
+    pub fn prefault(&self) {
+        for region in self.regions.iter() {
+            region.mapping.prefault();
+        }
+    }
+
     /// Writes a slice to guest memory at the specified guest address.
     /// Returns the number of bytes written. The number of bytes written can
     /// be less than the length of the slice if there isn't enough room in the
@@ -466,6 +493,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_madvise() {
+        let gm = GuestMemory::new(&vec![
+            (GuestAddress(0x0), 0x1000),
+            (GuestAddress(0x1000), 0x1000),
+        ])
+        .unwrap();
+        assert!(gm.madvise(libc::MADV_DONTFORK).is_ok());
+        match gm.madvise(-1) {
+            Err(Error::MemoryAdviseFailed(_)) => (),
+            _ => panic!("Expected a memory advise error."),
+        }
+    }
+
     #[test]
     fn test_read_u64() {
         let start_addr1 = GuestAddress(0x0);
@@ -113,6 +113,35 @@ impl MemoryMapping {
         self.size
     }
 
+    /// Gives the kernel a hint about how the whole memory region will be used.
+    ///
+    /// # Arguments
+    /// * `advice` - One of the `libc::MADV_*` values accepted by `madvise`.
+    pub fn madvise(&self, advice: libc::c_int) -> Result<()> {
+        // This is safe because the range covers exactly the area we mapped ourselves, and advising
+        // the kernel does not change the contents of the mapping.
+        let ret = unsafe { libc::madvise(self.addr as *mut libc::c_void, self.size, advice) };
+        if ret < 0 {
+            return Err(Error::SystemCallFailed(sys_util::Error::last()));
+        }
+        Ok(())
+    }
+
+    /// Touches every page of the memory region so that it is backed by host memory before it is
+    /// first accessed. The contents of the region are left unchanged.
+    pub fn prefault(&self) {
+        // This is safe because sysconf has no side effects.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        for offset in (0..self.size).step_by(page_size) {
+            // This is safe because `offset` is within the region we mapped ourselves. A page is
+            // only allocated on write, so the current value is written back in place.
+            unsafe {
+                let addr = self.addr.offset(offset as isize);
+                std::ptr::write_volatile(addr, std::ptr::read_volatile(addr));
+            }
+        }
+    }
+
     /// Writes a slice to the memory region at the specified offset.
     /// Returns the number of bytes written.  The number of bytes written can
     /// be less than the length of the slice if there isn't enough room in the
@@ -346,6 +375,26 @@ mod tests {
         assert_eq!(1024, m.size());
     }
 
+    #[test]
+    fn test_madvise() {
+        let m = MemoryMapping::new(0x1000).unwrap();
+        assert!(m.madvise(libc::MADV_DONTFORK).is_ok());
+        assert!(m.madvise(libc::MADV_DOFORK).is_ok());
+        match m.madvise(-1) {
+            Err(Error::SystemCallFailed(e)) => assert_eq!(e.errno(), libc::EINVAL),
+            _ => panic!("Expected a system call error."),
+        }
+    }
+
+    #[test]
+    fn test_prefault() {
+        let m = MemoryMapping::new(0x3000).unwrap();
+        m.write_obj(0x55aa_u16, 0x1000).unwrap();
+        m.prefault();
+        assert_eq!(m.read_obj::<u16>(0x1000).unwrap(), 0x55aa);
+        assert_eq!(m.read_obj::<u16>(0x2000).unwrap(), 0);
+    }
+
     #[test]
     fn test_write_past_end() {
         let m = MemoryMapping::new(5).unwrap();
@@ -0,0 +1,103 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+// Benchmarks of the scanning of the requests, which compare `find_byte` to the byte by byte
+// search it replaced. They run with `cargo bench -p micro_http`.
+
+extern crate micro_http;
+
+use std::time::{Duration, Instant};
+
+use micro_http::parser::find_byte;
+use micro_http::{Request, RequestLimits};
+
+const ITERATIONS: u32 = 1000;
+
+// Returns the average duration of a run of `f`. The results of the runs are added to
+// `checksum`, so that they can't be optimized away.
+fn measure<F: FnMut() -> usize>(checksum: &mut usize, mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        *checksum = checksum.wrapping_add(f());
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn nanos(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1e9 + f64::from(duration.subsec_nanos())
+}
+
+fn compare(name: &str, bytewise: Duration, wordwise: Duration) {
+    println!(
+        "{}: {:.0} ns byte by byte, {:.0} ns with find_byte ({:.1}x)",
+        name,
+        nanos(bytewise),
+        nanos(wordwise),
+        nanos(bytewise) / nanos(wordwise)
+    );
+}
+
+// Counts the lines of `bytes` with `find`, the way the parser scans the header sections.
+fn count_lines<F: Fn(&[u8]) -> Option<usize>>(bytes: &[u8], find: F) -> usize {
+    let mut lines = 0;
+    let mut offset = 0;
+    while let Some(index) = find(&bytes[offset..]) {
+        offset += index + 1;
+        lines += 1;
+    }
+    lines
+}
+
+fn main() {
+    let mut checksum = 0;
+
+    // The end of the line after 1 MiB of body, such as the bulk PUTs of the MMDS contents.
+    let mut body = "{\"key\": \"value\"}".repeat(64 * 1024).into_bytes();
+    body.push(b'\n');
+    let bytewise = measure(&mut checksum, || {
+        body.iter().position(|byte| *byte == b'\n').unwrap()
+    });
+    let wordwise = measure(&mut checksum, || find_byte(&body, b'\n').unwrap());
+    compare("line break after 1 MiB", bytewise, wordwise);
+
+    // The lines of a header section of 256 headers, with long values.
+    let mut head = String::from("GET /latest/meta-data HTTP/1.1\r\n");
+    for index in 0..256 {
+        head.push_str(&format!("X-Header-{}: {}\r\n", index, "a".repeat(48)));
+    }
+    head.push_str("\r\n");
+    let head = head.into_bytes();
+    let bytewise = measure(&mut checksum, || {
+        count_lines(&head, |bytes| bytes.iter().position(|byte| *byte == b'\n'))
+    });
+    let wordwise = measure(&mut checksum, || {
+        count_lines(&head, |bytes| find_byte(bytes, b'\n'))
+    });
+    compare("lines of a 16 KiB header section", bytewise, wordwise);
+
+    // The parsing of whole requests.
+    let limits = RequestLimits::unlimited();
+    let parse = measure(&mut checksum, || {
+        Request::try_from_with_limits(&head, &limits)
+            .unwrap()
+            .headers()
+            .get(micro_http::Header::Host)
+            .map_or(0, str::len)
+    });
+    println!("parse of a 16 KiB header section: {:.0} ns", nanos(parse));
+    let request = format!(
+        "PUT /mmds HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        String::from_utf8_lossy(&body)
+    )
+    .into_bytes();
+    let parse = measure(&mut checksum, || {
+        Request::try_from_with_limits(&request, &limits)
+            .unwrap()
+            .body()
+            .map_or(0, |body| body.len())
+    });
+    println!("parse of a PUT of 1 MiB: {:.0} ns", nanos(parse));
+
+    println!("(checksum {})", checksum);
+}
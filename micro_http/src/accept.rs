@@ -0,0 +1,259 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Content negotiation with the [Accept](https://tools.ietf.org/html/rfc7231#section-5.3.2)
+//! header, which lists the media types a client accepts, in order of preference.
+//!
+//! A server offering a resource in several media types picks the one to send with
+//! `negotiate`. The requests without an `Accept` header accept any media type.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::{accept, Request};
+//!
+//! let request = Request::try_from(
+//!     b"GET / HTTP/1.1\r\nAccept: text/plain;q=0.5, application/json\r\n\r\n",
+//! )
+//! .unwrap();
+//! assert_eq!(
+//!     accept::negotiate(&request, &["text/plain", "application/json"]),
+//!     Some("application/json")
+//! );
+//! ```
+
+use std::cmp::Reverse;
+
+use request::Request;
+
+// The quality of the media ranges without a `q` parameter, in thousandths.
+const MAX_QUALITY: u16 = 1000;
+
+// Parses a quality value, which has at most three decimals, in thousandths.
+fn parse_quality(value: &str) -> Option<u16> {
+    let (integer, decimals) = match value.find('.') {
+        Some(dot) => (&value[..dot], &value[dot + 1..]),
+        None => (value, ""),
+    };
+    if decimals.len() > 3 || !decimals.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let thousandths = format!("{:0<3}", decimals).parse::<u16>().ok()?;
+    match integer {
+        "0" => Some(thousandths),
+        "1" if thousandths == 0 => Some(MAX_QUALITY),
+        _ => None,
+    }
+}
+
+/// A media range of an `Accept` header, such as `text/*;q=0.5`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MediaRange<'a> {
+    media_type: &'a str,
+    subtype: &'a str,
+    quality: u16,
+}
+
+impl<'a> MediaRange<'a> {
+    // Parses a media range, ignoring its parameters other than the quality.
+    fn parse(value: &'a str) -> Option<Self> {
+        let mut params = value.split(';');
+        // The unwrap is safe because `split` returns at least an item.
+        let range = params.next().unwrap().trim();
+        let slash = range.find('/')?;
+        let (media_type, subtype) = (&range[..slash], &range[slash + 1..]);
+        if media_type.is_empty() || subtype.is_empty() || (media_type == "*" && subtype != "*") {
+            return None;
+        }
+
+        let mut quality = MAX_QUALITY;
+        for param in params {
+            let param = param.trim();
+            if param.len() > 2 && param[..2].eq_ignore_ascii_case("q=") {
+                quality = parse_quality(&param[2..])?;
+            }
+        }
+        Some(MediaRange {
+            media_type,
+            subtype,
+            quality,
+        })
+    }
+
+    /// Returns the type of the range, which is `*` for any type.
+    pub fn media_type(&self) -> &'a str {
+        self.media_type
+    }
+
+    /// Returns the subtype of the range, which is `*` for any subtype.
+    pub fn subtype(&self) -> &'a str {
+        self.subtype
+    }
+
+    /// Returns the quality of the range, in thousandths: from 1000 for the preferred media
+    /// types, down to 0 for the ones which are not acceptable.
+    pub fn quality(&self) -> u16 {
+        self.quality
+    }
+
+    // Returns how specific the range is, when it matches `media_type`: the most specific
+    // matching range sets the quality of a media type.
+    fn specificity(&self, media_type: &str) -> Option<u8> {
+        let slash = media_type.find('/')?;
+        let (offered_type, offered_subtype) = (&media_type[..slash], &media_type[slash + 1..]);
+        if self.media_type == "*" {
+            Some(0)
+        } else if !self.media_type.eq_ignore_ascii_case(offered_type) {
+            None
+        } else if self.subtype == "*" {
+            Some(1)
+        } else if self.subtype.eq_ignore_ascii_case(offered_subtype) {
+            Some(2)
+        } else {
+            None
+        }
+    }
+}
+
+/// The media ranges of an `Accept` header, from the highest quality to the lowest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Accept<'a> {
+    ranges: Vec<MediaRange<'a>>,
+}
+
+impl<'a> Accept<'a> {
+    /// Parses the value of an `Accept` header. The media ranges which are malformed are
+    /// ignored.
+    pub fn parse(value: &'a str) -> Self {
+        let mut ranges: Vec<MediaRange> = value
+            .split(',')
+            .filter(|range| !range.trim().is_empty())
+            .filter_map(MediaRange::parse)
+            .collect();
+        // The sort is stable, so the ranges of the same quality keep their order.
+        ranges.sort_by_key(|range| Reverse(range.quality));
+        Accept { ranges }
+    }
+
+    /// Returns the media ranges accepted by `request`, or `*/*` when it has no `Accept`
+    /// header.
+    pub fn of(request: &'a Request) -> Self {
+        request.headers().accept()
+    }
+
+    /// Returns the media ranges, from the highest quality to the lowest.
+    pub fn ranges(&self) -> &[MediaRange<'a>] {
+        &self.ranges
+    }
+
+    /// Returns the quality of `media_type`, such as `application/json`, in thousandths, or 0
+    /// when it is not acceptable.
+    pub fn quality(&self, media_type: &str) -> u16 {
+        self.ranges
+            .iter()
+            .filter_map(|range| range.specificity(media_type).map(|s| (s, range.quality)))
+            // The first of the most specific ranges wins, since `max_by_key` returns the last.
+            .rev()
+            .max_by_key(|&(specificity, _)| specificity)
+            .map_or(0, |(_, quality)| quality)
+    }
+
+    /// Returns the acceptable media type of the highest quality among `offered`, or `None`
+    /// when none of them is acceptable. The first of the offered media types wins a tie.
+    pub fn negotiate<'b>(&self, offered: &[&'b str]) -> Option<&'b str> {
+        offered
+            .iter()
+            .map(|&media_type| (media_type, self.quality(media_type)))
+            .filter(|&(_, quality)| quality > 0)
+            .rev()
+            .max_by_key(|&(_, quality)| quality)
+            .map(|(media_type, _)| media_type)
+    }
+}
+
+/// Returns the media type among `offered` which `request` accepts with the highest quality, or
+/// `None` when it accepts none of them.
+pub fn negotiate<'b>(request: &Request, offered: &[&'b str]) -> Option<&'b str> {
+    Accept::of(request).negotiate(offered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quality() {
+        assert_eq!(parse_quality("1"), Some(1000));
+        assert_eq!(parse_quality("1.000"), Some(1000));
+        assert_eq!(parse_quality("0"), Some(0));
+        assert_eq!(parse_quality("0.5"), Some(500));
+        assert_eq!(parse_quality("0.123"), Some(123));
+        for value in &["", "2", "1.5", "0.1234", "0.a", "-0", ".5"] {
+            assert_eq!(parse_quality(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let accept = Accept::parse("text/*;q=0.3, application/json, */*;Q=0.1, text/plain;q=0.3");
+        let ranges: Vec<(&str, &str, u16)> = accept
+            .ranges()
+            .iter()
+            .map(|range| (range.media_type(), range.subtype(), range.quality()))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                ("application", "json", 1000),
+                ("text", "*", 300),
+                ("text", "plain", 300),
+                ("*", "*", 100),
+            ]
+        );
+
+        // The malformed ranges are ignored.
+        let accept = Accept::parse("text, */json, text/plain;q=2, , application/json;level=1");
+        assert_eq!(accept.ranges().len(), 1);
+        assert_eq!(accept.ranges()[0].subtype(), "json");
+    }
+
+    #[test]
+    fn test_quality() {
+        let accept = Accept::parse("text/*;q=0.3, text/html;q=0.7, text/html;level=1, */*;q=0.5");
+        assert_eq!(accept.quality("text/html"), 1000);
+        assert_eq!(accept.quality("TEXT/PLAIN"), 300);
+        assert_eq!(accept.quality("image/jpeg"), 500);
+        assert_eq!(accept.quality("invalid"), 0);
+        assert_eq!(Accept::parse("text/plain").quality("application/json"), 0);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let offered = ["text/plain", "application/json"];
+        for &(value, expected) in &[
+            ("application/json", Some("application/json")),
+            ("application/*", Some("application/json")),
+            ("*/*", Some("text/plain")),
+            (
+                "text/plain;q=0.5, application/json;q=0.5",
+                Some("text/plain"),
+            ),
+            ("text/plain;q=0, */*", Some("application/json")),
+            ("text/html", None),
+            ("*/*;q=0", None),
+        ] {
+            assert_eq!(
+                Accept::parse(value).negotiate(&offered),
+                expected,
+                "{}",
+                value
+            );
+        }
+
+        let request = Request::try_from(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(negotiate(&request, &offered), Some("text/plain"));
+        let request =
+            Request::try_from(b"GET / HTTP/1.1\r\nAccept: application/json\r\n\r\n").unwrap();
+        assert_eq!(negotiate(&request, &offered), Some("application/json"));
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Authentication of the requests received by an `HttpServer`, before they are handled.
+//!
+//! The authenticator set with `HttpServer::set_authenticator` sees every valid request, with the
+//! `Peer` of its connection, and can answer it instead of the `RequestHandler`. It can check a
+//! bearer token in the `Authorization` header of the request, or the credentials of the process
+//! on the other side of a Unix domain socket.
+//!
+//! ## Example
+//! ```no_run
+//! extern crate micro_http;
+//! use micro_http::auth;
+//! use micro_http::router::Router;
+//! use micro_http::server::HttpServer;
+//!
+//! let mut server = HttpServer::new("/tmp/micro_http.socket", Router::new()).unwrap();
+//! server.set_authenticator(|request, peer| {
+//!     // The processes of root are trusted, the other ones need the token.
+//!     if peer.credentials().map_or(false, |credentials| credentials.uid == 0) {
+//!         None
+//!     } else if auth::bearer_token(request).is_none() {
+//!         Some(auth::unauthorized_response(request, "Bearer"))
+//!     } else if !auth::has_bearer_token(request, "secret") {
+//!         Some(auth::forbidden_response(request))
+//!     } else {
+//!         None
+//!     }
+//! });
+//! server.run().unwrap();
+//! ```
+
+use std::mem;
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+use libc;
+
+use common::Body;
+use headers::Header;
+use request::Request;
+use response::{Response, StatusCode};
+
+/// The credentials of the process on the other side of a Unix domain socket, when it connected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerCredentials {
+    /// The process ID.
+    pub pid: u32,
+    /// The user ID.
+    pub uid: u32,
+    /// The group ID.
+    pub gid: u32,
+}
+
+// Returns the credentials of the peer of the Unix domain socket `fd`.
+fn peer_credentials(fd: RawFd) -> Option<PeerCredentials> {
+    let mut ucred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // Safe because the kernel writes at most `len` bytes to `ucred`, which is valid for as many,
+    // and the return value is checked.
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut ucred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 || len as usize != mem::size_of::<libc::ucred>() {
+        return None;
+    }
+    Some(PeerCredentials {
+        pid: ucred.pid as u32,
+        uid: ucred.uid,
+        gid: ucred.gid,
+    })
+}
+
+/// What is known of the client on the other side of a connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Peer {
+    credentials: Option<PeerCredentials>,
+    addr: Option<SocketAddr>,
+}
+
+impl Peer {
+    pub(crate) fn of_unix(stream: &UnixStream) -> Self {
+        Peer {
+            credentials: peer_credentials(stream.as_raw_fd()),
+            addr: None,
+        }
+    }
+
+    pub(crate) fn of_tcp(stream: &TcpStream) -> Self {
+        Peer {
+            credentials: None,
+            addr: stream.peer_addr().ok(),
+        }
+    }
+
+    /// Returns the credentials of the peer process of a Unix domain socket, or `None` for a TCP
+    /// socket.
+    pub fn credentials(&self) -> Option<PeerCredentials> {
+        self.credentials
+    }
+
+    /// Returns the address of the peer of a TCP socket, or `None` for a Unix domain socket.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+}
+
+/// Returns the token of the `Authorization` header of `request`, when it has the `Bearer`
+/// scheme, as specified by [RFC 6750](https://tools.ietf.org/html/rfc6750#section-2.1).
+///
+/// # Examples
+///
+/// ```
+/// extern crate micro_http;
+/// use micro_http::{auth, Request};
+///
+/// let request = Request::try_from(b"GET / HTTP/1.1\r\nAuthorization: Bearer mF_9.B5f\r\n\r\n");
+/// assert_eq!(auth::bearer_token(&request.unwrap()), Some("mF_9.B5f"));
+/// ```
+pub fn bearer_token<'a>(request: &'a Request) -> Option<&'a str> {
+    let value = request.header(Header::Authorization)?.trim();
+    let space = value.find(' ')?;
+    let (scheme, token) = (&value[..space], value[space + 1..].trim());
+    if scheme.eq_ignore_ascii_case("Bearer") && !token.is_empty() {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+// Compares the bytes in a time which only depends on their lengths, so that the comparisons of
+// a guessed token don't tell how much of it is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Returns `true` when the bearer token of `request` is `token`, comparing them in constant
+/// time.
+pub fn has_bearer_token(request: &Request, token: &str) -> bool {
+    bearer_token(request).map_or(false, |bearer_token| {
+        constant_time_eq(bearer_token.as_bytes(), token.as_bytes())
+    })
+}
+
+/// Builds the `401 Unauthorized` response to a `request` without credentials, whose
+/// `WWW-Authenticate` header is `challenge`, such as `Bearer realm="api"`.
+pub fn unauthorized_response(request: &Request, challenge: &str) -> Response {
+    let mut response = Response::for_request(request, StatusCode::Unauthorized);
+    response.add_header(Header::WwwAuthenticate, String::from(challenge));
+    response.set_body(Body::new("Unauthorized."));
+    response
+}
+
+/// Builds the `403 Forbidden` response to a `request` whose credentials are not allowed.
+pub fn forbidden_response(request: &Request) -> Response {
+    let mut response = Response::for_request(request, StatusCode::Forbidden);
+    response.set_body(Body::new("Forbidden."));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_with(authorization: &str) -> Vec<u8> {
+        format!("GET / HTTP/1.1\r\nAuthorization: {}\r\n\r\n", authorization).into_bytes()
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        for &(authorization, expected) in &[
+            ("Bearer secret", Some("secret")),
+            ("bearer  secret ", Some("secret")),
+            ("Basic c2VjcmV0", None),
+            ("Bearer", None),
+            ("Bearer ", None),
+            ("Bearersecret", None),
+        ] {
+            let request_bytes = request_with(authorization);
+            let request = Request::try_from(&request_bytes).unwrap();
+            assert_eq!(bearer_token(&request), expected, "{}", authorization);
+        }
+        let request = Request::try_from(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(bearer_token(&request), None);
+    }
+
+    #[test]
+    fn test_has_bearer_token() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+        assert!(constant_time_eq(b"", b""));
+
+        let request_bytes = request_with("Bearer secret");
+        let request = Request::try_from(&request_bytes).unwrap();
+        assert!(has_bearer_token(&request, "secret"));
+        assert!(!has_bearer_token(&request, "secre"));
+        let request = Request::try_from(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(!has_bearer_token(&request, ""));
+    }
+
+    #[test]
+    fn test_responses() {
+        let request = Request::try_from(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let response = unauthorized_response(&request, "Bearer realm=\"api\"");
+        assert!(response.status() == StatusCode::Unauthorized);
+        assert_eq!(response.http_version(), request.http_version());
+        let mut response_buf = Vec::new();
+        response.write_all(&mut response_buf).unwrap();
+        assert!(String::from_utf8(response_buf)
+            .unwrap()
+            .contains("WWW-Authenticate: Bearer realm=\"api\"\r\n"));
+
+        let response = forbidden_response(&request);
+        assert!(response.status() == StatusCode::Forbidden);
+        assert_eq!(response.body().unwrap(), Body::new("Forbidden."));
+    }
+
+    #[test]
+    fn test_peer() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let peer = Peer::of_unix(&server);
+        let credentials = peer.credentials().unwrap();
+        // Safe because getuid can't fail.
+        assert_eq!(credentials.uid, unsafe { libc::getuid() });
+        assert_eq!(credentials.pid, ::std::process::id());
+        assert_eq!(peer.addr(), None);
+        drop(client);
+    }
+}
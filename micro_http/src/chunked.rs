@@ -0,0 +1,422 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The [chunked transfer coding](https://tools.ietf.org/html/rfc7230#section-4.1), including the
+//! trailers which may follow the last chunk.
+//!
+//! Trailers carry header fields which are only known once the whole body was sent, such as a
+//! digest of the content computed while streaming it.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::chunked::{ChunkedDecoder, ChunkedWriter};
+//! use std::io::Write;
+//!
+//! let mut message = Vec::new();
+//! {
+//!     let mut writer = ChunkedWriter::new(&mut message);
+//!     writer.write_all(b"guest memory").unwrap();
+//!     writer.finish(&[("Digest", "crc32=aabbccdd")]).unwrap();
+//! }
+//!
+//! let mut decoder = ChunkedDecoder::new();
+//! let mut body = Vec::new();
+//! assert_eq!(decoder.decode(&message, &mut body).unwrap(), message.len());
+//! assert!(decoder.is_done());
+//! assert_eq!(body, b"guest memory");
+//! assert_eq!(decoder.trailer("digest"), Some("crc32=aabbccdd"));
+//! ```
+
+use std::io::{Error as WriteError, Write};
+use std::str::from_utf8;
+
+use ascii::{COLON, CR, LF, SP};
+use parser;
+
+// Bounds the memory used for buffering a chunk size line.
+const MAX_CHUNK_SIZE_LINE_LEN: usize = 1024;
+// Bounds the memory used for the trailers.
+const MAX_TRAILERS_LEN: usize = 8192;
+
+/// Errors associated with decoding a chunked body.
+#[derive(Debug)]
+pub enum ChunkedError {
+    /// The size of a chunk is not a hexadecimal number, or it is too large.
+    InvalidChunkSize,
+    /// A chunk is larger than the maximum size accepted by the decoder.
+    ChunkTooLarge,
+    /// A chunk is not followed by a line break.
+    InvalidChunkEnd,
+    /// The line holding the size of a chunk is longer than 1 KiB.
+    ChunkSizeLineTooLong,
+    /// A trailer is not formatted as a header field.
+    InvalidTrailer,
+    /// The trailers are larger than 8 KiB.
+    TrailersTooLarge,
+    /// The sink of the body failed.
+    Sink(WriteError),
+}
+
+impl From<WriteError> for ChunkedError {
+    fn from(error: WriteError) -> Self {
+        ChunkedError::Sink(error)
+    }
+}
+
+fn parse_trailer(line: &[u8]) -> Result<(String, String), ChunkedError> {
+    let (name, value) = parser::header_field(line).map_err(|_| ChunkedError::InvalidTrailer)?;
+    // Header names are tokens, so only the value can be invalid UTF-8.
+    let value = from_utf8(value).map_err(|_| ChunkedError::InvalidTrailer)?;
+    Ok((
+        String::from_utf8_lossy(name).into_owned(),
+        value.to_string(),
+    ))
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    // Expecting the line holding the size of the next chunk.
+    Size,
+    // Expecting the given number of bytes of chunk data.
+    Data(usize),
+    // Expecting the line break after the chunk data.
+    DataEnd,
+    // Expecting a trailer or the empty line ending the body.
+    Trailers,
+    Done,
+}
+
+/// Incremental decoder for chunked bodies.
+pub struct ChunkedDecoder {
+    state: State,
+    max_chunk_size: usize,
+    // The beginning of a line which is not complete yet.
+    line: Vec<u8>,
+    trailers: Vec<(String, String)>,
+    trailers_len: usize,
+}
+
+impl ChunkedDecoder {
+    /// Creates a decoder for a new chunked body.
+    pub fn new() -> Self {
+        ChunkedDecoder::with_max_chunk_size(usize::max_value())
+    }
+
+    /// Creates a decoder for a new chunked body, which rejects the chunks larger than
+    /// `max_chunk_size` bytes before reading their data.
+    pub fn with_max_chunk_size(max_chunk_size: usize) -> Self {
+        ChunkedDecoder {
+            state: State::Size,
+            max_chunk_size,
+            line: Vec::new(),
+            trailers: Vec::new(),
+            trailers_len: 0,
+        }
+    }
+
+    /// Decodes the next bytes of the chunked body, writing the data of the chunks to `sink`.
+    ///
+    /// Returns the number of bytes of `bytes` which are part of the body. It is smaller than the
+    /// length of `bytes` only when the body ends, and the remaining bytes belong to the next
+    /// message on the connection.
+    ///
+    /// # Errors
+    /// Returns a `ChunkedError` when the body is malformed or `sink` fails. The decoder can not
+    /// be used afterwards.
+    pub fn decode<T: Write>(&mut self, bytes: &[u8], sink: &mut T) -> Result<usize, ChunkedError> {
+        let mut consumed = 0;
+        while consumed < bytes.len() && self.state != State::Done {
+            let remaining = &bytes[consumed..];
+            if let State::Data(len) = self.state {
+                let data_len = len.min(remaining.len());
+                sink.write_all(&remaining[..data_len])?;
+                consumed += data_len;
+                self.state = if data_len == len {
+                    State::DataEnd
+                } else {
+                    State::Data(len - data_len)
+                };
+                continue;
+            }
+
+            match parser::find_byte(remaining, LF) {
+                Some(index) => {
+                    self.line.extend_from_slice(&remaining[..index]);
+                    consumed += index + 1;
+                    self.check_line_len()?;
+                    if self.line.last() == Some(&CR) {
+                        self.line.pop();
+                    }
+                    self.handle_line()?;
+                    self.line.clear();
+                }
+                None => {
+                    self.line.extend_from_slice(remaining);
+                    consumed = bytes.len();
+                    self.check_line_len()?;
+                }
+            }
+        }
+        Ok(consumed)
+    }
+
+    fn check_line_len(&mut self) -> Result<(), ChunkedError> {
+        match self.state {
+            State::Size if self.line.len() > MAX_CHUNK_SIZE_LINE_LEN => {
+                Err(ChunkedError::ChunkSizeLineTooLong)
+            }
+            State::Trailers if self.trailers_len + self.line.len() > MAX_TRAILERS_LEN => {
+                Err(ChunkedError::TrailersTooLarge)
+            }
+            // Only a line break is expected.
+            State::DataEnd if self.line.len() > 1 => Err(ChunkedError::InvalidChunkEnd),
+            _ => Ok(()),
+        }
+    }
+
+    fn handle_line(&mut self) -> Result<(), ChunkedError> {
+        self.state = match self.state {
+            State::Size => {
+                match parser::chunk_size(&self.line).map_err(|_| ChunkedError::InvalidChunkSize)? {
+                    // The last chunk is empty.
+                    0 => State::Trailers,
+                    len if len > self.max_chunk_size => return Err(ChunkedError::ChunkTooLarge),
+                    len => State::Data(len),
+                }
+            }
+            State::DataEnd if self.line.is_empty() => State::Size,
+            State::DataEnd => return Err(ChunkedError::InvalidChunkEnd),
+            State::Trailers if self.line.is_empty() => State::Done,
+            State::Trailers => {
+                self.trailers_len += self.line.len();
+                self.trailers.push(parse_trailer(&self.line)?);
+                State::Trailers
+            }
+            State::Data(_) | State::Done => unreachable!(),
+        };
+        Ok(())
+    }
+
+    /// Returns `true` once the whole body, including the trailers, was decoded.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Returns the trailers sent after the last chunk, in the order they were sent.
+    pub fn trailers(&self) -> &[(String, String)] {
+        &self.trailers
+    }
+
+    /// Returns the value of the first trailer called `name`, which is case insensitive.
+    pub fn trailer(&self, name: &str) -> Option<&str> {
+        self.trailers
+            .iter()
+            .find(|&&(ref trailer_name, _)| trailer_name.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_str())
+    }
+}
+
+/// Writes a chunked body to an underlying writer, one chunk for every write.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    /// Creates a writer sending the chunks to `inner`.
+    pub fn new(inner: W) -> Self {
+        ChunkedWriter { inner }
+    }
+
+    /// Writes the last chunk and the `trailers`, then returns the underlying writer.
+    ///
+    /// The names of the trailers should be announced with `Response::set_chunked_body`.
+    pub fn finish(mut self, trailers: &[(&str, &str)]) -> Result<W, WriteError> {
+        self.inner.write_all(&[b'0', CR, LF])?;
+        for &(name, value) in trailers {
+            self.inner.write_all(name.as_bytes())?;
+            self.inner.write_all(&[COLON, SP])?;
+            self.inner.write_all(value.as_bytes())?;
+            self.inner.write_all(&[CR, LF])?;
+        }
+        self.inner.write_all(&[CR, LF])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, WriteError> {
+        // An empty chunk would end the body.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.inner
+            .write_all(format!("{:x}\r\n", buf.len()).as_bytes())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(&[CR, LF])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), WriteError> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"4\r\n\
+        Wiki\r\n\
+        6;name=value\r\n\
+        pedia \r\n\
+        E\r\n\
+        in \r\n\r\nchunks.\r\n\
+        0\r\n\
+        Expires: Wed, 21 Oct 2015 07:28:00 GMT\r\n\
+        x-checksum:  1234\n\
+        \r\n";
+
+    #[test]
+    fn test_decode() {
+        let mut decoder = ChunkedDecoder::new();
+        let mut body = Vec::new();
+        assert_eq!(decoder.decode(BODY, &mut body).unwrap(), BODY.len());
+        assert!(decoder.is_done());
+        assert_eq!(body, b"Wikipedia in \r\n\r\nchunks.".to_vec());
+        assert_eq!(
+            decoder.trailers(),
+            &[
+                (
+                    String::from("Expires"),
+                    String::from("Wed, 21 Oct 2015 07:28:00 GMT")
+                ),
+                (String::from("x-checksum"), String::from("1234")),
+            ]
+        );
+        assert_eq!(decoder.trailer("X-Checksum"), Some("1234"));
+        assert_eq!(decoder.trailer("Digest"), None);
+
+        // The body can be split anywhere.
+        for chunk_len in 1..BODY.len() {
+            let mut decoder = ChunkedDecoder::new();
+            let mut body = Vec::new();
+            for chunk in BODY.chunks(chunk_len) {
+                assert_eq!(decoder.decode(chunk, &mut body).unwrap(), chunk.len());
+            }
+            assert!(decoder.is_done());
+            assert_eq!(body, b"Wikipedia in \r\n\r\nchunks.".to_vec());
+            assert_eq!(decoder.trailers().len(), 2);
+        }
+
+        // The bytes after the body are not consumed.
+        let mut decoder = ChunkedDecoder::new();
+        let mut body = Vec::new();
+        assert_eq!(
+            decoder
+                .decode(b"1\r\na\r\n0\r\n\r\nGET / HTTP/1.1\r\n", &mut body)
+                .unwrap(),
+            11
+        );
+        assert!(decoder.is_done());
+        assert!(decoder.trailers().is_empty());
+        assert_eq!(body, b"a");
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        for bytes in &[
+            b"x\r\n".as_ref(),
+            b"\r\n",
+            b";ext\r\n",
+            b"-1\r\n",
+            b"ffffffffffffffffffff\r\n",
+        ] {
+            match ChunkedDecoder::new().decode(bytes, &mut Vec::new()) {
+                Err(ChunkedError::InvalidChunkSize) => (),
+                _ => panic!("Expected an invalid chunk size error."),
+            }
+        }
+
+        match ChunkedDecoder::new().decode(b"1\r\nab\r\n", &mut Vec::new()) {
+            Err(ChunkedError::InvalidChunkEnd) => (),
+            _ => panic!("Expected an invalid chunk end error."),
+        }
+
+        let mut decoder = ChunkedDecoder::with_max_chunk_size(4);
+        decoder.decode(b"4\r\nWiki\r\n", &mut Vec::new()).unwrap();
+        match decoder.decode(b"5\r\n", &mut Vec::new()) {
+            Err(ChunkedError::ChunkTooLarge) => (),
+            _ => panic!("Expected a chunk too large error."),
+        }
+
+        let line = vec![b'0'; MAX_CHUNK_SIZE_LINE_LEN + 1];
+        match ChunkedDecoder::new().decode(&line, &mut Vec::new()) {
+            Err(ChunkedError::ChunkSizeLineTooLong) => (),
+            _ => panic!("Expected a chunk size line too long error."),
+        }
+
+        for trailer in &[b"Digest\r\n".as_ref(), b"Digest : 1\r\n", b": 1\r\n"] {
+            let mut decoder = ChunkedDecoder::new();
+            decoder.decode(b"0\r\n", &mut Vec::new()).unwrap();
+            match decoder.decode(trailer, &mut Vec::new()) {
+                Err(ChunkedError::InvalidTrailer) => (),
+                _ => panic!("Expected an invalid trailer error."),
+            }
+        }
+
+        let mut decoder = ChunkedDecoder::new();
+        decoder.decode(b"0\r\n", &mut Vec::new()).unwrap();
+        let trailer = format!("X-Large: {}\r\n", "a".repeat(MAX_TRAILERS_LEN / 2));
+        decoder.decode(trailer.as_bytes(), &mut Vec::new()).unwrap();
+        match decoder.decode(trailer.as_bytes(), &mut Vec::new()) {
+            Err(ChunkedError::TrailersTooLarge) => (),
+            _ => panic!("Expected a trailers too large error."),
+        }
+
+        let mut sink = [0u8; 2];
+        match ChunkedDecoder::new().decode(b"3\r\nabc\r\n", &mut sink.as_mut()) {
+            Err(ChunkedError::Sink(_)) => (),
+            _ => panic!("Expected a sink error."),
+        }
+    }
+
+    #[test]
+    fn test_writer() {
+        let mut writer = ChunkedWriter::new(Vec::new());
+        writer.write_all(b"Wiki").unwrap();
+        assert_eq!(writer.write(b"").unwrap(), 0);
+        writer.write_all(&[b'a'; 26]).unwrap();
+        let message = writer.finish(&[]).unwrap();
+        let mut expected = b"4\r\nWiki\r\n1a\r\n".to_vec();
+        expected.extend_from_slice(&[b'a'; 26]);
+        expected.extend_from_slice(b"\r\n0\r\n\r\n");
+        assert_eq!(message, expected);
+
+        let writer = ChunkedWriter::new(Vec::new());
+        let message = writer
+            .finish(&[("Digest", "sha-256=abc"), ("X-Checksum", "1234")])
+            .unwrap();
+        assert_eq!(
+            message,
+            b"0\r\nDigest: sha-256=abc\r\nX-Checksum: 1234\r\n\r\n".to_vec()
+        );
+
+        // What is written can be decoded.
+        let mut writer = ChunkedWriter::new(Vec::new());
+        writer.write_all(BODY).unwrap();
+        let message = writer.finish(&[("Digest", "abc")]).unwrap();
+        let mut decoder = ChunkedDecoder::new();
+        let mut body = Vec::new();
+        decoder.decode(&message, &mut body).unwrap();
+        assert!(decoder.is_done());
+        assert_eq!(body, BODY);
+        assert_eq!(decoder.trailer("Digest"), Some("abc"));
+
+        // Test write failed.
+        let mut buf = [0u8; 4];
+        let mut writer = ChunkedWriter::new(buf.as_mut());
+        assert!(writer.write(b"Wiki").is_err());
+    }
+}
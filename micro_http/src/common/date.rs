@@ -0,0 +1,114 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+// The 1st of January 1970 was a Thursday.
+const DAY_NAMES: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The length of an IMF-fixdate, up to the year 9999.
+pub const DATE_LEN: usize = 29;
+// The last second of the year 9999.
+const MAX_SECS: u64 = 253_402_300_799;
+
+thread_local! {
+    // The last formatted date, together with the second it was formatted for. Responses are
+    // written many times per second, but the date only changes once per second.
+    static CACHED_DATE: RefCell<(u64, [u8; DATE_LEN])> =
+        RefCell::new((u64::max_value(), [0; DATE_LEN]));
+}
+
+/// Formats `secs`, the number of seconds since the Unix epoch, as an
+/// [IMF-fixdate](https://tools.ietf.org/html/rfc7231#section-7.1.1.1), for example
+/// "Sun, 06 Nov 1994 08:49:37 GMT", without allocating.
+///
+/// The dates after the year 9999 don't fit, so they are written as its last second.
+pub fn format_into(secs: u64, date: &mut [u8; DATE_LEN]) {
+    let secs = secs.min(MAX_SECS);
+    let (days, secs_of_day) = (secs / SECONDS_PER_DAY, secs % SECONDS_PER_DAY);
+
+    // Converts the number of days to a date of the proleptic Gregorian calendar. The computation
+    // uses years starting in March, so that the leap day is the last day of the year.
+    let days_since_0000_03_01 = days + 719_468;
+    let era = days_since_0000_03_01 / 146_097;
+    let day_of_era = days_since_0000_03_01 % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = (shifted_month + 2) % 12;
+    let year = era * 400 + year_of_era + if month < 2 { 1 } else { 0 };
+
+    // The date always fits, since the year has 4 digits.
+    let _ = write!(
+        &mut date[..],
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        DAY_NAMES[(days % 7) as usize],
+        day,
+        MONTH_NAMES[month as usize],
+        year,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    );
+}
+
+/// Calls `f` with the current date, formatted as an IMF-fixdate.
+///
+/// The date is only formatted again when the second changed since the last call on the same
+/// thread, and it is never allocated, so that it can be written in every response.
+pub fn with_now<F, T>(f: F) -> T
+where
+    F: FnOnce(&[u8]) -> T,
+{
+    // A clock set before the Unix epoch is not worth failing a response for.
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    CACHED_DATE.with(|cached_date| {
+        let mut cached_date = cached_date.borrow_mut();
+        if cached_date.0 != secs {
+            cached_date.0 = secs;
+            format_into(secs, &mut cached_date.1);
+        }
+        f(&cached_date.1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(secs: u64) -> String {
+        let mut date = [0; DATE_LEN];
+        format_into(secs, &mut date);
+        String::from_utf8(date.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format(784_111_777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format(951_782_400), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(format(4_102_444_799), "Thu, 31 Dec 2099 23:59:59 GMT");
+        assert_eq!(format(253_402_300_799), "Fri, 31 Dec 9999 23:59:59 GMT");
+        assert_eq!(format(u64::max_value()), "Fri, 31 Dec 9999 23:59:59 GMT");
+    }
+
+    #[test]
+    fn test_now() {
+        with_now(|date| {
+            assert_eq!(date.len(), "Thu, 01 Jan 1970 00:00:00 GMT".len());
+            assert!(date.ends_with(b" GMT"));
+        });
+    }
+}
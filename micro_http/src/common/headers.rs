@@ -1,49 +1,321 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::HashMap;
+use alloc::collections::BTreeMap;
+use alloc::str::from_utf8;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use std::io::{Error as WriteError, Write};
 
+#[cfg(feature = "std")]
+use accept::Accept;
+#[cfg(feature = "std")]
 use ascii::{COLON, CR, LF, SP};
+use common::RequestError;
+use parser::{HeaderFields, ParseError};
 
 /// Wrapper over an HTTP Header type.
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Header {
+    /// Header `Accept`.
+    Accept,
+    /// Header `Accept-Ranges`.
+    AcceptRanges,
+    /// Header `Allow`.
+    Allow,
+    /// Header `Authorization`.
+    Authorization,
+    /// Header `Cache-Control`.
+    CacheControl,
+    /// Header `Connection`.
+    Connection,
+    /// Header `Content-Disposition`.
+    ContentDisposition,
     /// Header `Content-Length`.
     ContentLength,
+    /// Header `Content-Range`.
+    ContentRange,
     /// Header `Content-Type`.
     ContentType,
+    /// Header `Date`.
+    Date,
+    /// Header `ETag`.
+    ETag,
+    /// Header `Expect`.
+    Expect,
+    /// Header `Host`.
+    Host,
+    /// Header `If-None-Match`.
+    IfNoneMatch,
+    /// Header `Range`.
+    Range,
+    /// Header `Sec-WebSocket-Accept`.
+    SecWebSocketAccept,
+    /// Header `Sec-WebSocket-Key`.
+    SecWebSocketKey,
+    /// Header `Sec-WebSocket-Version`.
+    SecWebSocketVersion,
+    /// Header `Server`.
+    Server,
+    /// Header `Trailer`.
+    Trailer,
+    /// Header `Transfer-Encoding`.
+    TransferEncoding,
+    /// Header `Upgrade`.
+    Upgrade,
+    /// Header `WWW-Authenticate`.
+    WwwAuthenticate,
 }
 
 impl Header {
-    fn raw(&self) -> &'static [u8] {
+    pub(crate) fn raw(&self) -> &'static [u8] {
         match self {
+            Header::Accept => b"Accept",
+            Header::AcceptRanges => b"Accept-Ranges",
+            Header::Allow => b"Allow",
+            Header::Authorization => b"Authorization",
+            Header::CacheControl => b"Cache-Control",
+            Header::Connection => b"Connection",
+            Header::ContentDisposition => b"Content-Disposition",
             Header::ContentLength => b"Content-Length",
+            Header::ContentRange => b"Content-Range",
             Header::ContentType => b"Content-Type",
+            Header::Date => b"Date",
+            Header::ETag => b"ETag",
+            Header::Expect => b"Expect",
+            Header::Host => b"Host",
+            Header::IfNoneMatch => b"If-None-Match",
+            Header::Range => b"Range",
+            Header::SecWebSocketAccept => b"Sec-WebSocket-Accept",
+            Header::SecWebSocketKey => b"Sec-WebSocket-Key",
+            Header::SecWebSocketVersion => b"Sec-WebSocket-Version",
+            Header::Server => b"Server",
+            Header::Trailer => b"Trailer",
+            Header::TransferEncoding => b"Transfer-Encoding",
+            Header::Upgrade => b"Upgrade",
+            Header::WwwAuthenticate => b"WWW-Authenticate",
         }
     }
+
+    // Header names are case insensitive.
+    fn try_from(name: &[u8]) -> Option<Self> {
+        [
+            Header::Accept,
+            Header::AcceptRanges,
+            Header::Allow,
+            Header::Authorization,
+            Header::CacheControl,
+            Header::Connection,
+            Header::ContentDisposition,
+            Header::ContentLength,
+            Header::ContentRange,
+            Header::ContentType,
+            Header::Date,
+            Header::ETag,
+            Header::Expect,
+            Header::Host,
+            Header::IfNoneMatch,
+            Header::Range,
+            Header::SecWebSocketAccept,
+            Header::SecWebSocketKey,
+            Header::SecWebSocketVersion,
+            Header::Server,
+            Header::Trailer,
+            Header::TransferEncoding,
+            Header::Upgrade,
+            Header::WwwAuthenticate,
+        ]
+        .iter()
+        .find(|header| header.raw().eq_ignore_ascii_case(name))
+        .cloned()
+    }
+}
+
+// Returns the `InvalidHeader` error describing `e`, which stopped the parsing of a header
+// section.
+pub(crate) fn header_error(e: ParseError) -> RequestError {
+    RequestError::InvalidHeader(match e {
+        ParseError::MissingHeaderSeparator => "Missing header separator.",
+        ParseError::ObsoleteLineFolding => "Obsolete line folding is not supported.",
+        ParseError::InvalidHeaderName => "Invalid header name.",
+        ParseError::InvalidHeaderValue => "Header value contains control characters.",
+        ParseError::BareCarriageReturn => "Header line contains a CR without a LF.",
+        ParseError::BareLineFeed => "Header line does not end with CRLF.",
+        ParseError::NulByte => "Header line contains a NUL byte.",
+        ParseError::NonAsciiHeaderValue => "Header value contains non-ASCII characters.",
+        // Only returned for chunk size lines and request lines.
+        ParseError::InvalidChunkSize | ParseError::MalformedRequestLine => "Invalid header.",
+    })
+}
+
+// Validates the value of a Content-Length header, which has to be a plain decimal number.
+fn validate_content_length(value: &str) -> Result<(), RequestError> {
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(RequestError::InvalidContentLength);
+    }
+    value
+        .parse::<usize>()
+        .map(|_| ())
+        .map_err(|_| RequestError::InvalidContentLength)
+}
+
+/// Returns `true` if `value`, a comma separated list of tokens, contains `token`.
+#[cfg(feature = "std")]
+pub(crate) fn has_token(value: Option<&str>, token: &str) -> bool {
+    value.map_or(false, |value| {
+        value
+            .split(',')
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    })
+}
+
+/// The expectation of a request, sent in the `Expect` header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Expectation {
+    /// The client waits for a `100 Continue` response before sending the body.
+    Continue,
+    /// The expectation is not defined by HTTP/1.1, so it can't be met.
+    Unsupported,
+}
+
+/// The transfer coding of the body of a request, sent in the `Transfer-Encoding` header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferEncoding {
+    /// The body is sent in chunks.
+    Chunked,
+    /// Any other transfer coding, or a list of codings, which can't be decoded.
+    Unsupported,
 }
 
 /// Wrapper over the list of headers associated with a Request/Response.
 #[derive(Debug)]
 pub struct Headers {
-    headers: HashMap<Header, String>,
+    headers: BTreeMap<Header, String>,
 }
 
 impl Headers {
     /// By default Requests are created with no headers.
     pub fn default() -> Headers {
         return Headers {
-            headers: HashMap::new(),
+            headers: BTreeMap::new(),
         };
     }
 
+    /// Parses the header section of a request, which ends with an empty line.
+    ///
+    /// Only the headers listed in `Header` are kept, the others are ignored. The values of a
+    /// header sent more than once are combined in a comma separated list, in order.
+    ///
+    /// # Errors
+    /// Returns `InvalidHeader` when a line is not a valid header or a listed header has a value
+    /// which is not valid UTF-8, or `InvalidContentLength` when the Content-Length header is not
+    /// valid.
+    pub fn parse(bytes: &[u8]) -> Result<Headers, RequestError> {
+        let mut headers = Headers::default();
+        for field in HeaderFields::new(bytes) {
+            let (name, value) = field.map_err(header_error)?;
+
+            if let Some(header) = Header::try_from(name) {
+                // Dropping the header instead would let the request be framed differently by a
+                // proxy which does accept the value.
+                let value = from_utf8(value)
+                    .map_err(|_| RequestError::InvalidHeader("Header value is not valid UTF-8."))?;
+                let value = if header == Header::ContentLength {
+                    validate_content_length(value)?;
+                    if headers
+                        .get(header)
+                        .map_or(false, |previous| previous != value)
+                    {
+                        return Err(RequestError::InvalidContentLength);
+                    }
+                    String::from(value)
+                } else {
+                    match headers.get(header) {
+                        Some(previous) => [previous, value].join(", "),
+                        None => String::from(value),
+                    }
+                };
+                headers.add(header, value);
+            }
+        }
+        Ok(headers)
+    }
+
     /// Adds a new header to the list.
     pub fn add(&mut self, header: Header, value: String) {
         self.headers.insert(header, value);
     }
 
+    /// Removes `header` from the list.
+    pub fn remove(&mut self, header: Header) {
+        self.headers.remove(&header);
+    }
+
+    /// Returns the value of `header`, if present.
+    pub fn get(&self, header: Header) -> Option<&str> {
+        self.headers.get(&header).map(|value| value.as_str())
+    }
+
+    /// Returns the length of the body given by the `Content-Length` header, if it has a valid
+    /// one. `parse` rejects the invalid values.
+    pub fn content_length(&self) -> Option<usize> {
+        self.get(Header::ContentLength)
+            .and_then(|value| value.parse().ok())
+    }
+
+    /// Returns the media type of the `Content-Type` header in lowercase, without its
+    /// parameters, such as `multipart/form-data` for
+    /// `Multipart/Form-Data; boundary=frontier`.
+    pub fn content_type(&self) -> Option<String> {
+        self.get(Header::ContentType).map(|value| {
+            // The unwrap is safe because `split` returns at least an item.
+            value.split(';').next().unwrap().trim().to_ascii_lowercase()
+        })
+    }
+
+    /// Returns the expectation of the `Expect` header, if there is one.
+    pub fn expect(&self) -> Option<Expectation> {
+        self.get(Header::Expect).map(|value| {
+            if value.trim().eq_ignore_ascii_case("100-continue") {
+                Expectation::Continue
+            } else {
+                Expectation::Unsupported
+            }
+        })
+    }
+
+    /// Returns the media ranges of the `Accept` header, or `*/*` when there is none.
+    #[cfg(feature = "std")]
+    pub fn accept(&self) -> Accept {
+        Accept::parse(self.get(Header::Accept).unwrap_or("*/*"))
+    }
+
+    /// Returns the transfer coding of the `Transfer-Encoding` header, if there is one.
+    pub fn transfer_encoding(&self) -> Option<TransferEncoding> {
+        self.get(Header::TransferEncoding).map(|value| {
+            if value.trim().eq_ignore_ascii_case("chunked") {
+                TransferEncoding::Chunked
+            } else {
+                TransferEncoding::Unsupported
+            }
+        })
+    }
+
+    /// Returns the names of the trailers declared by the `Trailer` header, which follow a chunked
+    /// body, or an empty list when there is none.
+    pub fn trailer_names(&self) -> Vec<&str> {
+        self.get(Header::Trailer).map_or(Vec::new(), |value| {
+            value
+                .split(',')
+                .map(|name| name.trim())
+                .filter(|name| !name.is_empty())
+                .collect()
+        })
+    }
+
     /// Writes the headers to `buf` using the HTTP specification.
+    #[cfg(feature = "std")]
     pub fn write_all<T: Write>(&self, buf: &mut T) -> Result<(), WriteError> {
         for (key, val) in &self.headers {
             buf.write_all(key.raw())?;
@@ -63,6 +335,12 @@ impl Headers {
 pub enum MediaType {
     /// Media Type: "text/plain".
     PlainText,
+    /// Media Type: "text/event-stream".
+    EventStream,
+    /// Media Type: "application/json".
+    ApplicationJson,
+    /// Media Type: "application/octet-stream".
+    ApplicationOctetStream,
 }
 
 impl MediaType {
@@ -70,17 +348,21 @@ impl MediaType {
     pub fn as_str(&self) -> &'static str {
         match self {
             MediaType::PlainText => "text/plain",
+            MediaType::EventStream => "text/event-stream",
+            MediaType::ApplicationJson => "application/json",
+            MediaType::ApplicationOctetStream => "application/octet-stream",
         }
     }
 }
 
-#[cfg(test)]
+// The tests use the `std` prelude, which the `alloc` only builds lack.
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
     #[test]
     fn test_default() {
-        assert!(Headers::default().headers == HashMap::new());
+        assert!(Headers::default().headers == BTreeMap::new());
     }
 
     #[test]
@@ -107,6 +389,143 @@ mod tests {
             headers.headers.get(&Header::ContentLength).unwrap(),
             &"130".to_string()
         );
+
+        headers.remove(Header::ContentLength);
+        assert!(!headers.headers.contains_key(&Header::ContentLength));
+        assert!(headers.headers.contains_key(&Header::ContentType));
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let headers = Headers::parse(
+            b" skipped\r\n\
+              upgrade: websocket\r\n\
+              Connection:  keep-alive, Upgrade \r\n\
+              Last-Modified: Tue, 15 Nov 1994 12:45:26 GMT\r\n\
+              Content-Length: 0\n\
+              content-length: 0\r\n\
+              \r\n\
+              Content-Type: text/plain\r\n",
+        )
+        .unwrap();
+        assert_eq!(headers.get(Header::Upgrade), Some("websocket"));
+        assert_eq!(headers.get(Header::Connection), Some("keep-alive, Upgrade"));
+        assert_eq!(headers.get(Header::ContentLength), Some("0"));
+        // The headers after the empty line are not part of the header section.
+        assert_eq!(headers.get(Header::ContentType), None);
+        assert_eq!(headers.headers.len(), 3);
+
+        assert!(Headers::parse(b"").unwrap().headers.is_empty());
+
+        // The values of repeated headers are combined, except for Content-Length.
+        let headers = Headers::parse(
+            b"Accept: text/plain\r\n\
+              accept: application/json;q=0.5\r\n\
+              Content-Length: 2\r\n\
+              Content-Length: 2\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            headers.get(Header::Accept),
+            Some("text/plain, application/json;q=0.5")
+        );
+        assert_eq!(headers.get(Header::ContentLength), Some("2"));
+    }
+
+    #[test]
+    fn test_parse_invalid_headers() {
+        for &(bytes, err_msg) in &[
+            (&b"malformed\r\n"[..], "Missing header separator."),
+            (
+                &b"Upgrade: websocket\r\n folded\r\n"[..],
+                "Obsolete line folding is not supported.",
+            ),
+            (&b": websocket\r\n"[..], "Invalid header name."),
+            (&b"Content-Length : 5\r\n"[..], "Invalid header name."),
+            (&b"Up\0grade: websocket\r\n"[..], "Invalid header name."),
+            (
+                &b"Upgrade: web\0socket\r\n"[..],
+                "Header value contains control characters.",
+            ),
+            (
+                &b"Upgrade: web\rsocket\r\n"[..],
+                "Header value contains control characters.",
+            ),
+            (
+                &b"X-Custom: \x1b[31m\r\n"[..],
+                "Header value contains control characters.",
+            ),
+            (
+                &b"Content-Length: 5\xff\r\n"[..],
+                "Header value is not valid UTF-8.",
+            ),
+            (
+                &b"Transfer-Encoding: chunked\xff\r\n"[..],
+                "Header value is not valid UTF-8.",
+            ),
+        ] {
+            assert_eq!(
+                Headers::parse(bytes).unwrap_err(),
+                RequestError::InvalidHeader(err_msg)
+            );
+        }
+
+        // Tabs are allowed in values, and so are any bytes in the values of the ignored headers.
+        assert!(Headers::parse(b"X-Custom:\tvalue\r\n").is_ok());
+        assert!(Headers::parse(b"X-Custom: \xff\r\n").is_ok());
+
+        for bytes in &[
+            &b"Content-Length: -1\r\n"[..],
+            &b"Content-Length: +1\r\n"[..],
+            &b"Content-Length: 0x10\r\n"[..],
+            &b"Content-Length: 1 2\r\n"[..],
+            &b"Content-Length:\r\n"[..],
+            &b"Content-Length: 99999999999999999999999999\r\n"[..],
+            &b"Content-Length: 1\r\nContent-Length: 2\r\n"[..],
+        ] {
+            assert_eq!(
+                Headers::parse(bytes).unwrap_err(),
+                RequestError::InvalidContentLength
+            );
+        }
+    }
+
+    #[test]
+    fn test_typed_headers() {
+        let headers = Headers::parse(
+            b"content-length: 42\r\n\
+              Content-Type: Multipart/Form-Data; boundary=frontier\r\n\
+              EXPECT: 100-Continue\r\n\
+              Accept: text/plain;q=0.5, application/json\r\n\
+              Transfer-Encoding: Chunked\r\n\
+              Trailer: Digest, , X-Checksum\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(headers.content_length(), Some(42));
+        assert_eq!(
+            headers.content_type(),
+            Some(String::from("multipart/form-data"))
+        );
+        assert_eq!(headers.expect(), Some(Expectation::Continue));
+        assert_eq!(headers.accept().ranges()[0].subtype(), "json");
+        assert_eq!(headers.transfer_encoding(), Some(TransferEncoding::Chunked));
+        assert_eq!(headers.trailer_names(), vec!["Digest", "X-Checksum"]);
+
+        let headers =
+            Headers::parse(b"Expect: 200-ok\r\nTransfer-Encoding: gzip, chunked\r\n\r\n").unwrap();
+        assert_eq!(headers.expect(), Some(Expectation::Unsupported));
+        assert_eq!(
+            headers.transfer_encoding(),
+            Some(TransferEncoding::Unsupported)
+        );
+
+        let headers = Headers::default();
+        assert_eq!(headers.content_length(), None);
+        assert_eq!(headers.content_type(), None);
+        assert_eq!(headers.expect(), None);
+        assert_eq!(headers.accept().quality("application/json"), 1000);
+        assert_eq!(headers.transfer_encoding(), None);
+        assert!(headers.trailer_names().is_empty());
     }
 
     #[test]
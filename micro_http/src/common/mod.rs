@@ -1,26 +1,89 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(feature = "std")]
+pub mod date;
 pub mod headers;
 
-pub mod ascii {
-    pub const CR: u8 = b'\r';
-    pub const COLON: u8 = b':';
-    pub const LF: u8 = b'\n';
-    pub const SP: u8 = b' ';
-}
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use parser;
+#[cfg(feature = "std")]
+use response::StatusCode;
 
 /// Errors associated with parsing the HTTP Request from a u8 slice.
 #[derive(Debug, PartialEq)]
 pub enum RequestError {
-    /// The HTTP Method is not supported or it is invalid.
+    /// The HTTP Method is not a valid token.
     InvalidHttpMethod(&'static str),
     /// Cannot parse the Request Line due to invalid input.
     InvalidRequest,
+    /// The Request Line is not valid in the strict mode of the parser.
+    InvalidRequestLine(&'static str),
     /// Request URI is invalid.
     InvalidUri(&'static str),
     /// The HTTP Version in the Request is not supported or it is invalid.
     InvalidHttpVersion(&'static str),
+    /// A header line is malformed, or it has an invalid name or value.
+    InvalidHeader(&'static str),
+    /// The Content-Length header is not a number which fits in an `usize`, or it is sent more
+    /// than once with different values.
+    InvalidContentLength,
+    /// The chunked body is malformed, or it is sent with an unsupported transfer coding.
+    InvalidChunkedBody(&'static str),
+    /// The URI is longer than the limit of the parser.
+    UriTooLong,
+    /// The header section is larger than the limit of the parser.
+    HeadersTooLarge,
+    /// The header section has more headers than the limit of the parser.
+    TooManyHeaders,
+    /// A header line is longer than the limit of the parser.
+    HeaderTooLong,
+    /// The entity body is larger than the limit of the parser.
+    BodyTooLarge,
+}
+
+impl RequestError {
+    /// Returns the status code of the response to a request which failed to parse with this
+    /// error.
+    #[cfg(feature = "std")]
+    pub fn status_code(&self) -> StatusCode {
+        match *self {
+            RequestError::InvalidHttpVersion(_) => StatusCode::HttpVersionNotSupported,
+            RequestError::UriTooLong => StatusCode::UriTooLong,
+            RequestError::HeadersTooLarge
+            | RequestError::TooManyHeaders
+            | RequestError::HeaderTooLong => StatusCode::RequestHeaderFieldsTooLarge,
+            RequestError::BodyTooLarge => StatusCode::PayloadTooLarge,
+            RequestError::InvalidRequest
+            | RequestError::InvalidHttpMethod(_)
+            | RequestError::InvalidRequestLine(_)
+            | RequestError::InvalidUri(_)
+            | RequestError::InvalidHeader(_)
+            | RequestError::InvalidContentLength
+            | RequestError::InvalidChunkedBody(_) => StatusCode::BadRequest,
+        }
+    }
+
+    /// Returns a description of the error, for the body of the response.
+    pub fn message(&self) -> &'static str {
+        match *self {
+            RequestError::InvalidHttpMethod(msg)
+            | RequestError::InvalidRequestLine(msg)
+            | RequestError::InvalidUri(msg)
+            | RequestError::InvalidHttpVersion(msg)
+            | RequestError::InvalidHeader(msg)
+            | RequestError::InvalidChunkedBody(msg) => msg,
+            RequestError::InvalidRequest => "Invalid request.",
+            RequestError::InvalidContentLength => "Invalid Content-Length.",
+            RequestError::UriTooLong => "URI too long.",
+            RequestError::HeadersTooLarge => "Header section too large.",
+            RequestError::TooManyHeaders => "Too many headers.",
+            RequestError::HeaderTooLong => "Header too long.",
+            RequestError::BodyTooLarge => "Body too large.",
+        }
+    }
 }
 
 /// The Body associated with an HTTP Request or Response.
@@ -55,32 +118,83 @@ impl Body {
     }
 }
 
-/// Supported HTTP Methods.
-#[derive(Debug, PartialEq)]
+/// HTTP Methods.
+///
+/// The standard methods have their own variants, and the other methods are kept by name, so that
+/// the handlers can tell the methods they don't implement from the invalid requests.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Method {
     /// GET Method.
     Get,
+    /// POST Method.
+    Post,
+    /// PUT Method.
+    Put,
+    /// PATCH Method.
+    Patch,
+    /// DELETE Method.
+    Delete,
+    /// TRACE Method.
+    Trace,
+    /// CONNECT Method.
+    Connect,
+    /// A valid method which is not one of the above, such as "PROPFIND".
+    Other(String),
 }
 
 impl Method {
     /// Returns a `Method` object if the parsing of `bytes` is successful.
     ///
-    /// The method is case sensitive. A call to try_from with the input b"get" will return
-    /// an error, but when using the input b"GET", it returns Method::Get.
+    /// The method is case sensitive. A call to try_from with the input b"GET" returns
+    /// Method::Get, while the input b"get" is a method which is not standard, and it
+    /// returns Method::Other.
     ///
     /// # Errors
-    /// Returns `RequestError` if the method specified by `bytes` is unsupported.
+    /// Returns `RequestError` if `bytes` is not a valid method token.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate micro_http;
+    /// use micro_http::Method;
+    ///
+    /// assert_eq!(Method::try_from(b"POST").unwrap(), Method::Post);
+    /// assert_eq!(
+    ///     Method::try_from(b"PROPFIND").unwrap(),
+    ///     Method::Other(String::from("PROPFIND"))
+    /// );
+    /// assert!(Method::try_from(b"PROP(FIND)").is_err());
+    /// ```
     pub fn try_from(bytes: &[u8]) -> Result<Self, RequestError> {
         match bytes {
             b"GET" => Ok(Method::Get),
-            _ => Err(RequestError::InvalidHttpMethod("Unsupported HTTP method.")),
+            b"POST" => Ok(Method::Post),
+            b"PUT" => Ok(Method::Put),
+            b"PATCH" => Ok(Method::Patch),
+            b"DELETE" => Ok(Method::Delete),
+            b"TRACE" => Ok(Method::Trace),
+            b"CONNECT" => Ok(Method::Connect),
+            _ if !bytes.is_empty() && bytes.iter().all(|byte| parser::is_token_byte(*byte)) => {
+                // Tokens are ASCII, so they are valid UTF-8.
+                Ok(Method::Other(String::from_utf8_lossy(bytes).into_owned()))
+            }
+            _ => Err(RequestError::InvalidHttpMethod(
+                "Invalid HTTP method token.",
+            )),
         }
     }
 
     /// Returns an `u8 slice` corresponding to the Method.
-    pub fn raw(&self) -> &'static [u8] {
+    pub fn raw(&self) -> &[u8] {
         match self {
             Method::Get => b"GET",
+            Method::Post => b"POST",
+            Method::Put => b"PUT",
+            Method::Patch => b"PATCH",
+            Method::Delete => b"DELETE",
+            Method::Trace => b"TRACE",
+            Method::Connect => b"CONNECT",
+            Method::Other(name) => name.as_bytes(),
         }
     }
 }
@@ -136,12 +250,44 @@ impl Version {
     pub fn default() -> Self {
         Version::Http11
     }
+
+    /// Returns `true` if connections are kept alive after a response, unless the `Connection`
+    /// header says otherwise. HTTP/1.0 connections are closed by default.
+    pub fn keep_alive_by_default(&self) -> bool {
+        *self == Version::Http11
+    }
+
+    /// Returns `true` if the version allows chunked bodies, which were added in HTTP/1.1.
+    pub fn supports_chunked(&self) -> bool {
+        *self == Version::Http11
+    }
 }
 
-#[cfg(test)]
+// The tests use the `std` prelude, which the `alloc` only builds lack.
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_request_error() {
+        let error = RequestError::InvalidHttpMethod("Invalid HTTP method token.");
+        assert!(error.status_code() == StatusCode::BadRequest);
+        assert_eq!(error.message(), "Invalid HTTP method token.");
+        let error = RequestError::InvalidHttpVersion("Unsupported HTTP version.");
+        assert!(error.status_code() == StatusCode::HttpVersionNotSupported);
+        assert!(RequestError::InvalidUri("Invalid URI.").status_code() == StatusCode::BadRequest);
+        assert!(RequestError::InvalidContentLength.status_code() == StatusCode::BadRequest);
+        assert_eq!(
+            RequestError::InvalidContentLength.message(),
+            "Invalid Content-Length."
+        );
+        assert!(RequestError::BodyTooLarge.status_code() == StatusCode::PayloadTooLarge);
+        assert!(RequestError::UriTooLong.status_code() == StatusCode::UriTooLong);
+        assert!(
+            RequestError::HeadersTooLarge.status_code() == StatusCode::RequestHeaderFieldsTooLarge
+        );
+    }
+
     #[test]
     fn test_version() {
         // Tests for raw()
@@ -158,18 +304,53 @@ mod tests {
 
         // Test for default()
         assert_eq!(Version::default(), Version::Http11);
+
+        // Tests for the defaults of each version.
+        assert!(!Version::Http10.keep_alive_by_default());
+        assert!(Version::Http11.keep_alive_by_default());
+        assert!(!Version::Http10.supports_chunked());
+        assert!(Version::Http11.supports_chunked());
     }
 
     #[test]
     fn test_method() {
         // Test for raw
         assert_eq!(Method::Get.raw(), b"GET");
+        assert_eq!(Method::Put.raw(), b"PUT");
+        assert_eq!(Method::Patch.raw(), b"PATCH");
+        assert_eq!(Method::Delete.raw(), b"DELETE");
+
+        assert_eq!(Method::Post.raw(), b"POST");
+        assert_eq!(Method::Trace.raw(), b"TRACE");
+        assert_eq!(Method::Connect.raw(), b"CONNECT");
+        assert_eq!(Method::Other(String::from("PROPFIND")).raw(), b"PROPFIND");
 
         // Tests for try_from
-        assert_eq!(Method::try_from(b"GET").unwrap(), Method::Get);
+        for method in &[
+            Method::Get,
+            Method::Post,
+            Method::Put,
+            Method::Patch,
+            Method::Delete,
+            Method::Trace,
+            Method::Connect,
+        ] {
+            assert_eq!(&Method::try_from(method.raw()).unwrap(), method);
+        }
+        // Methods are case sensitive.
+        assert_eq!(
+            Method::try_from(b"put").unwrap(),
+            Method::Other(String::from("put"))
+        );
         assert_eq!(
-            Method::try_from(b"PUT").unwrap_err(),
-            RequestError::InvalidHttpMethod("Unsupported HTTP method.")
+            Method::try_from(b"M-SEARCH").unwrap(),
+            Method::Other(String::from("M-SEARCH"))
         );
+        for method in &[&b""[..], b"PROP FIND", b"PROP\0", b"(GET)", b"GET\xff"] {
+            assert_eq!(
+                Method::try_from(method).unwrap_err(),
+                RequestError::InvalidHttpMethod("Invalid HTTP method token.")
+            );
+        }
     }
 }
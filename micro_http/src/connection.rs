@@ -0,0 +1,907 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Parsing of the sequential requests sent on a connection.
+//!
+//! A client can send several requests on the same connection, and a read from the connection can
+//! return a part of a request, or more than one request. A `ClientConnection` buffers the bytes
+//! read from the connection, and parses the requests one by one once they are complete. This
+//! suits event loops reading from non-blocking sockets: `parse_next` tells whether the next
+//! request is complete, and the search for its end resumes where it stopped on the next call.
+//!
+//! The requests can be limited in size with `RequestLimits`, which are checked as the bytes are
+//! received, so that a request exceeding them is rejected before it is buffered whole.
+//!
+//! The bodies of large uploads can be streamed instead: `stream_next` returns the head of a
+//! request as soon as it is complete, then the bytes of its body as they are received, so that
+//! only the bytes received since the last call are buffered.
+//!
+//! The connection is kept alive as long as the requests ask for it: after a request which closes
+//! the connection, with a `Connection: close` header or by default on HTTP/1.0, the bytes which
+//! follow it are discarded and the connection should be closed once the response is written.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::connection::ClientConnection;
+//!
+//! let mut connection = ClientConnection::new(4096);
+//! connection.receive(b"GET /first HTTP/1.1\r\n\r\nGET /sec").unwrap();
+//! connection.receive(b"ond HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+//!
+//! let mut paths = Vec::new();
+//! while let Some(path) =
+//!     connection.handle_next(|request| request.unwrap().uri().get_abs_path().to_string())
+//! {
+//!     paths.push(path);
+//! }
+//! assert_eq!(paths, vec!["/first", "/second"]);
+//! assert!(!connection.keep_alive());
+//! ```
+
+use std::io::{self, Write};
+use std::ops::Deref;
+
+use ascii::{CR, LF};
+use chunked::ChunkedDecoder;
+use headers::{Headers, TransferEncoding};
+use parser::{find_byte, split, trim_cr};
+use request::{chunked_body_error, Request, RequestError, RequestLimits, MAX_CHUNK_SIZE};
+
+/// Errors associated with receiving the bytes of a `ClientConnection`.
+#[derive(Debug, PartialEq)]
+pub enum ConnectionError {
+    /// Buffering the bytes would exceed the cap of the pending input. The bytes were not
+    /// buffered.
+    InputBufferFull,
+}
+
+/// The outcome of parsing the next request of a `ClientConnection`.
+#[derive(Debug)]
+pub enum ParseStatus<'a> {
+    /// The next request is not complete yet, or the connection is not kept alive anymore.
+    NeedsMoreData,
+    /// The next request is complete.
+    Complete(Request<'a>),
+    /// The next request is invalid. The connection is not kept alive anymore, because the end of
+    /// the request is unknown.
+    Error(RequestError),
+}
+
+/// The outcome of streaming the next request of a `ClientConnection`.
+#[derive(Debug)]
+pub enum StreamStatus<'a> {
+    /// The next part of the request is not received yet, or the connection is not kept alive
+    /// anymore.
+    NeedsMoreData,
+    /// The request line and the header section of the next request are complete. The `Request`
+    /// has no body: it follows in `BodyChunk`s.
+    Head(Request<'a>),
+    /// The next bytes of the body of the current request, which are decoded when the body is
+    /// chunked.
+    BodyChunk(&'a [u8]),
+    /// The body of the current request is complete, or it has no body.
+    End,
+    /// The next request is invalid, or its chunked body is malformed. The connection is not
+    /// kept alive anymore, because the end of the request is unknown.
+    Error(RequestError),
+}
+
+// The rest of the body of a request which is streamed.
+enum StreamedBody {
+    // The number of bytes of the body which were not received yet.
+    Length(usize),
+    Chunked(ChunkedDecoder),
+}
+
+// How the end of the body of the next request is found, once its header section is complete.
+enum Framing {
+    // The length of the whole request is known.
+    Length(usize),
+    // The chunked body is decoded as it is received, up to the given offset of the buffer.
+    Chunked(ChunkedDecoder, usize, BodyLen),
+}
+
+// Counts the bytes of a chunked body, which is only decoded to find its end.
+struct BodyLen(usize);
+
+impl Write for BodyLen {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// The bytes received on a connection and not parsed yet.
+//
+// The parsed bytes are dropped by moving the start of the buffer, not the bytes which follow
+// them, and the space they leave is reused once the bytes received next wouldn't fit after the
+// end of the buffer. The bytes of a request are thus moved at most once, however the reads
+// split it, while the parser still sees them as one slice.
+struct InputBuffer {
+    bytes: Vec<u8>,
+    start: usize,
+}
+
+impl InputBuffer {
+    fn new() -> Self {
+        InputBuffer {
+            bytes: Vec::new(),
+            start: 0,
+        }
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        if self.start == self.bytes.len() {
+            self.clear();
+        } else if self.start > 0 && self.bytes.capacity() - self.bytes.len() < bytes.len() {
+            self.bytes.drain(..self.start);
+            self.start = 0;
+        }
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    // Drops the first `len` bytes.
+    fn consume(&mut self, len: usize) {
+        self.start += len;
+        debug_assert!(self.start <= self.bytes.len());
+    }
+
+    fn clear(&mut self) {
+        self.bytes.clear();
+        self.start = 0;
+    }
+}
+
+impl Deref for InputBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[self.start..]
+    }
+}
+
+/// The requests received on a connection, parsed as they are complete.
+///
+/// The buffered bytes are only scanned once: the search for the end of a request resumes where
+/// it stopped when more bytes are received.
+pub struct ClientConnection {
+    buf: InputBuffer,
+    max_pending: usize,
+    limits: RequestLimits,
+    keep_alive: bool,
+    // The length of the last parsed request, at the beginning of `buf`, which is dropped before
+    // parsing the next one.
+    parsed: usize,
+    // The offset of `buf` up to which the header section of the next request was searched for
+    // its end. It is always at the beginning of a line.
+    scanned: usize,
+    framing: Option<Framing>,
+    streamed_body: Option<StreamedBody>,
+    // The data of the chunks decoded by the last call to `stream_next`.
+    decoded: Vec<u8>,
+}
+
+impl ClientConnection {
+    /// Creates a `ClientConnection`, which buffers at most `max_pending` bytes of requests which
+    /// are not handled yet.
+    pub fn new(max_pending: usize) -> Self {
+        ClientConnection::with_limits(max_pending, RequestLimits::unlimited())
+    }
+
+    /// Creates a `ClientConnection` like `new`, whose requests are rejected as soon as one of
+    /// their parts exceeds `limits`.
+    pub fn with_limits(max_pending: usize, limits: RequestLimits) -> Self {
+        ClientConnection {
+            buf: InputBuffer::new(),
+            max_pending,
+            limits,
+            keep_alive: true,
+            parsed: 0,
+            scanned: 0,
+            framing: None,
+            streamed_body: None,
+            decoded: Vec::new(),
+        }
+    }
+
+    /// Buffers `bytes`, read from the connection.
+    ///
+    /// The bytes are dropped when the connection is not kept alive anymore.
+    ///
+    /// # Errors
+    /// Returns `InputBufferFull` when the bytes don't fit in the pending input, which happens when
+    /// a request is larger than the cap.
+    pub fn receive(&mut self, bytes: &[u8]) -> Result<(), ConnectionError> {
+        if !self.accepts_input() {
+            return Ok(());
+        }
+        if self.pending() + bytes.len() > self.max_pending {
+            return Err(ConnectionError::InputBufferFull);
+        }
+        self.buf.extend(bytes);
+        Ok(())
+    }
+
+    /// Parses the next request, if it is complete.
+    ///
+    /// The returned `Request` borrows the buffered bytes, which are dropped on the next call.
+    pub fn parse_next(&mut self) -> ParseStatus {
+        self.buf.consume(self.parsed);
+        self.parsed = 0;
+        if !self.keep_alive {
+            self.buf.clear();
+            return ParseStatus::NeedsMoreData;
+        }
+
+        let len = match self.next_request_len() {
+            Ok(Some(len)) => len,
+            Ok(None) => return ParseStatus::NeedsMoreData,
+            Err(e) => {
+                self.keep_alive = false;
+                return ParseStatus::Error(e);
+            }
+        };
+        self.parsed = len;
+        self.scanned = 0;
+        self.framing = None;
+
+        match Request::try_from_with_limits(&self.buf[..len], &self.limits) {
+            Ok(request) => {
+                self.keep_alive = request.keep_alive();
+                ParseStatus::Complete(request)
+            }
+            Err(e) => {
+                self.keep_alive = false;
+                ParseStatus::Error(e)
+            }
+        }
+    }
+
+    /// Parses the next part of the request being received, for streaming its body instead of
+    /// buffering it whole.
+    ///
+    /// The head of every request is returned first, then the chunks of its body as they are
+    /// received, then `End`, even when there is no body. The body is not limited in size: the
+    /// consumer of the chunks decides how much of it to accept. A connection is either parsed
+    /// with `parse_next` or streamed with `stream_next`, not both.
+    ///
+    /// The returned `Request` and chunks borrow the buffered bytes, which are dropped on the next
+    /// call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate micro_http;
+    /// use micro_http::connection::{ClientConnection, StreamStatus};
+    ///
+    /// let mut connection = ClientConnection::new(4096);
+    /// connection
+    ///     .receive(b"PUT /snapshot HTTP/1.1\r\nContent-Length: 12\r\n\r\nguest ")
+    ///     .unwrap();
+    /// match connection.stream_next() {
+    ///     StreamStatus::Head(request) => assert_eq!(request.uri().get_abs_path(), "/snapshot"),
+    ///     _ => panic!("Expected the head of the request."),
+    /// }
+    /// let mut body = Vec::new();
+    /// while let StreamStatus::BodyChunk(chunk) = connection.stream_next() {
+    ///     body.extend_from_slice(chunk);
+    /// }
+    /// connection.receive(b"memory").unwrap();
+    /// while let StreamStatus::BodyChunk(chunk) = connection.stream_next() {
+    ///     body.extend_from_slice(chunk);
+    /// }
+    /// assert_eq!(body, b"guest memory");
+    /// ```
+    pub fn stream_next(&mut self) -> StreamStatus {
+        self.buf.consume(self.parsed);
+        self.parsed = 0;
+        if !self.accepts_input() {
+            self.buf.clear();
+            return StreamStatus::NeedsMoreData;
+        }
+
+        let body_done = match self.streamed_body {
+            Some(StreamedBody::Length(0)) => true,
+            Some(StreamedBody::Length(ref mut remaining)) => {
+                let len = (*remaining).min(self.buf.len());
+                if len == 0 {
+                    return StreamStatus::NeedsMoreData;
+                }
+                *remaining -= len;
+                self.parsed = len;
+                return StreamStatus::BodyChunk(&self.buf[..len]);
+            }
+            Some(StreamedBody::Chunked(ref mut decoder)) => {
+                self.decoded.clear();
+                match decoder.decode(&self.buf, &mut self.decoded) {
+                    Ok(len) => self.parsed = len,
+                    Err(e) => {
+                        self.keep_alive = false;
+                        self.streamed_body = None;
+                        return StreamStatus::Error(chunked_body_error(e));
+                    }
+                }
+                if !self.decoded.is_empty() {
+                    return StreamStatus::BodyChunk(&self.decoded);
+                }
+                decoder.is_done()
+            }
+            None => false,
+        };
+        if body_done {
+            self.streamed_body = None;
+            return StreamStatus::End;
+        }
+        if self.streamed_body.is_some() {
+            return StreamStatus::NeedsMoreData;
+        }
+
+        self.stream_head()
+    }
+
+    // Parses the head of the next request, if it is complete, and prepares the streaming of its
+    // body.
+    fn stream_head(&mut self) -> StreamStatus {
+        let head_len = match self.scan_header_section() {
+            Some(len) => len,
+            None => {
+                return match self.limits.check_head(&self.buf) {
+                    Ok(()) => StreamStatus::NeedsMoreData,
+                    Err(e) => {
+                        self.keep_alive = false;
+                        StreamStatus::Error(e)
+                    }
+                }
+            }
+        };
+        self.parsed = head_len;
+        self.scanned = 0;
+
+        let head = &self.buf[..head_len];
+        match self
+            .limits
+            .check_head(head)
+            .and_then(|_| Request::try_head_from(head, &self.limits))
+        {
+            Ok((request, chunked)) => {
+                self.keep_alive = request.keep_alive();
+                self.streamed_body = Some(if chunked {
+                    // The chunks are not buffered, so their size doesn't matter.
+                    StreamedBody::Chunked(ChunkedDecoder::new())
+                } else {
+                    StreamedBody::Length(request.headers().content_length().unwrap_or(0))
+                });
+                StreamStatus::Head(request)
+            }
+            Err(e) => {
+                self.keep_alive = false;
+                StreamStatus::Error(e)
+            }
+        }
+    }
+
+    /// Parses the next complete request, and calls `handler` with it.
+    ///
+    /// Returns the value returned by `handler`, or `None` when there is no complete request.
+    /// When the request is invalid, `handler` is called with the error, and the connection is not
+    /// kept alive, because the end of the request is unknown.
+    pub fn handle_next<F, T>(&mut self, handler: F) -> Option<T>
+    where
+        F: FnOnce(Result<&Request, RequestError>) -> T,
+    {
+        match self.parse_next() {
+            ParseStatus::NeedsMoreData => None,
+            ParseStatus::Complete(request) => Some(handler(Ok(&request))),
+            ParseStatus::Error(e) => Some(handler(Err(e))),
+        }
+    }
+
+    /// Returns `false` once a request closed the connection, or an invalid request was received.
+    /// The connection should be closed after writing the pending responses.
+    pub fn keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    /// Stops keeping the connection alive, such as after a response closing it: the requests
+    /// received afterwards are dropped.
+    pub fn close(&mut self) {
+        self.keep_alive = false;
+        self.streamed_body = None;
+    }
+
+    /// Returns the number of bytes of the request parsed last, including its header section and
+    /// its body, or 0 before the first one.
+    pub fn last_request_len(&self) -> usize {
+        self.parsed
+    }
+
+    /// Returns the number of buffered bytes, which belong to the requests not handled yet.
+    pub fn pending(&self) -> usize {
+        if self.accepts_input() {
+            self.buf.len() - self.parsed
+        } else {
+            0
+        }
+    }
+
+    // The body of a request closing the connection is still received when it is streamed.
+    fn accepts_input(&self) -> bool {
+        self.keep_alive || self.streamed_body.is_some()
+    }
+
+    // Returns the length of the next request, or `None` when it is not complete yet.
+    fn next_request_len(&mut self) -> Result<Option<usize>, RequestError> {
+        if self.framing.is_none() {
+            let head_len = match self.scan_header_section() {
+                Some(len) => len,
+                None => {
+                    self.limits.check_head(&self.buf)?;
+                    return Ok(None);
+                }
+            };
+            self.limits.check_head(&self.buf[..head_len])?;
+            self.framing = Some(self.framing_of(head_len)?);
+        }
+
+        match self.framing {
+            Some(Framing::Length(len)) if len <= self.buf.len() => Ok(Some(len)),
+            Some(Framing::Chunked(ref mut decoder, ref mut decoded, ref mut body_len)) => {
+                *decoded += decoder
+                    .decode(&self.buf[*decoded..], body_len)
+                    .map_err(chunked_body_error)?;
+                if body_len.0 > self.limits.max_body_bytes {
+                    return Err(RequestError::BodyTooLarge);
+                }
+                Ok(if decoder.is_done() {
+                    Some(*decoded)
+                } else {
+                    None
+                })
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Returns the length of the request line and the header section of the next request, or
+    // `None` when the header section is not complete yet.
+    fn scan_header_section(&mut self) -> Option<usize> {
+        if self.scanned == 0 {
+            // Clients can send empty lines before a request.
+            let start = self
+                .buf
+                .iter()
+                .position(|byte| *byte != CR && *byte != LF)
+                .unwrap_or(self.buf.len());
+            self.buf.consume(start);
+        }
+
+        while let Some(line_len) = find_byte(&self.buf[self.scanned..], LF) {
+            let line = trim_cr(&self.buf[self.scanned..self.scanned + line_len]);
+            let is_empty = line.is_empty();
+            self.scanned += line_len + 1;
+            if is_empty {
+                return Some(self.scanned);
+            }
+        }
+        None
+    }
+
+    fn framing_of(&self, head_len: usize) -> Result<Framing, RequestError> {
+        // The header section follows the request line.
+        let (request_line, _) = split(&self.buf, LF);
+        let headers = Headers::parse(&self.buf[request_line.len() + 1..head_len])?;
+
+        match headers.transfer_encoding() {
+            Some(TransferEncoding::Chunked) => {
+                // The body is decoded again when the request is parsed, so it is dropped here.
+                let decoder = ChunkedDecoder::with_max_chunk_size(MAX_CHUNK_SIZE);
+                return Ok(Framing::Chunked(decoder, head_len, BodyLen(0)));
+            }
+            // The other codings are rejected when parsing the request.
+            Some(_) => return Ok(Framing::Length(head_len)),
+            None => (),
+        }
+
+        // The header parser already validated the Content-Length value.
+        let body_len = headers.content_length().unwrap_or(0);
+        // The body is rejected before it is buffered.
+        if body_len > self.limits.max_body_bytes {
+            return Err(RequestError::BodyTooLarge);
+        }
+        head_len
+            .checked_add(body_len)
+            .map(Framing::Length)
+            .ok_or(RequestError::InvalidContentLength)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{Body, Method};
+
+    // Returns the path of the next request, or the error returned when parsing it.
+    fn next_path(connection: &mut ClientConnection) -> Option<Result<String, RequestError>> {
+        connection
+            .handle_next(|request| request.map(|request| request.uri().get_abs_path().to_string()))
+    }
+
+    #[test]
+    fn test_sequential_requests() {
+        let mut connection = ClientConnection::new(4096);
+        assert_eq!(next_path(&mut connection), None);
+
+        // The requests can be split anywhere.
+        let requests: &[u8] = b"\r\nGET /a HTTP/1.1\r\n\r\n\
+            PUT /b HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}\
+            PATCH /c HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\n{\r\n1\r\n}\r\n0\r\n\r\n";
+        for chunk_len in 1..requests.len() {
+            let mut connection = ClientConnection::new(4096);
+            let mut paths = Vec::new();
+            for chunk in requests.chunks(chunk_len) {
+                connection.receive(chunk).unwrap();
+                while let Some(path) = next_path(&mut connection) {
+                    paths.push(path.unwrap());
+                }
+            }
+            assert_eq!(paths, vec!["/a", "/b", "/c"]);
+            assert!(connection.keep_alive());
+            assert_eq!(connection.pending(), 0);
+        }
+
+        // The bodies are parsed with the requests.
+        connection.receive(requests).unwrap();
+        assert!(connection
+            .handle_next(|request| request.unwrap().body().is_none())
+            .unwrap());
+        for method in &[Method::Put, Method::Patch] {
+            connection.handle_next(|request| {
+                let request = request.unwrap();
+                assert_eq!(request.method(), method);
+                assert_eq!(request.body(), Some(&Body::new("{}")));
+            });
+        }
+        assert_eq!(connection.pending(), 0);
+    }
+
+    #[test]
+    fn test_parse_next() {
+        let mut connection = ClientConnection::new(4096);
+        match connection.parse_next() {
+            ParseStatus::NeedsMoreData => (),
+            _ => panic!("Expected more data to be needed."),
+        }
+
+        // The request is complete once its whole body is received.
+        for bytes in &[
+            &b"\r\n\r\n"[..],
+            b"PUT /a HTTP/1.1\r\n",
+            b"Content-Length: 4\r\n",
+            b"\r\n",
+            b"{}",
+        ] {
+            connection.receive(bytes).unwrap();
+            match connection.parse_next() {
+                ParseStatus::NeedsMoreData => (),
+                _ => panic!("Expected more data to be needed."),
+            }
+        }
+        connection.receive(b"{}GET").unwrap();
+        match connection.parse_next() {
+            ParseStatus::Complete(request) => {
+                assert_eq!(request.method(), &Method::Put);
+                assert_eq!(request.body(), Some(&Body::new("{}{}")));
+            }
+            _ => panic!("Expected a complete request."),
+        }
+        // The parsed request is dropped on the next call.
+        assert_eq!(connection.pending(), 3);
+        match connection.parse_next() {
+            ParseStatus::NeedsMoreData => (),
+            _ => panic!("Expected more data to be needed."),
+        }
+        assert_eq!(connection.pending(), 3);
+
+        connection.receive(b" /b HTTP/2.0\r\n\r\n").unwrap();
+        match connection.parse_next() {
+            ParseStatus::Error(RequestError::InvalidHttpVersion(_)) => (),
+            _ => panic!("Expected an invalid HTTP version error."),
+        }
+        assert!(!connection.keep_alive());
+
+        // Only empty lines are dropped.
+        let mut connection = ClientConnection::new(4096);
+        connection.receive(b"\r\n\n").unwrap();
+        assert!(next_path(&mut connection).is_none());
+        assert_eq!(connection.pending(), 0);
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        // HTTP/1.0 connections are closed by default, and the requests after them are dropped.
+        let mut connection = ClientConnection::new(4096);
+        connection
+            .receive(b"GET /a HTTP/1.0\r\n\r\nGET /b HTTP/1.0\r\n\r\n")
+            .unwrap();
+        assert_eq!(next_path(&mut connection), Some(Ok(String::from("/a"))));
+        assert!(!connection.keep_alive());
+        assert_eq!(connection.pending(), 0);
+        assert_eq!(next_path(&mut connection), None);
+        connection.receive(b"GET /c HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(connection.pending(), 0);
+
+        let mut connection = ClientConnection::new(4096);
+        connection
+            .receive(
+                b"GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+                  GET /b HTTP/1.1\r\nConnection: close\r\n\r\n\
+                  GET /c HTTP/1.1\r\n\r\n",
+            )
+            .unwrap();
+        assert_eq!(next_path(&mut connection), Some(Ok(String::from("/a"))));
+        assert!(connection.keep_alive());
+        assert_eq!(next_path(&mut connection), Some(Ok(String::from("/b"))));
+        assert!(!connection.keep_alive());
+        assert_eq!(next_path(&mut connection), None);
+
+        // The connection can be closed after any request, such as when its response closes it.
+        let mut connection = ClientConnection::new(4096);
+        connection
+            .receive(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert_eq!(connection.last_request_len(), 0);
+        assert_eq!(next_path(&mut connection), Some(Ok(String::from("/a"))));
+        assert_eq!(connection.last_request_len(), 19);
+        connection.close();
+        assert!(!connection.keep_alive());
+        assert_eq!(connection.pending(), 0);
+        assert_eq!(next_path(&mut connection), None);
+    }
+
+    // Streams the parts of the requests received so far, as strings.
+    fn stream_parts(connection: &mut ClientConnection) -> Vec<String> {
+        let mut parts = Vec::new();
+        loop {
+            parts.push(match connection.stream_next() {
+                StreamStatus::NeedsMoreData => return parts,
+                StreamStatus::Head(request) => format!("{:?}", request.method()),
+                StreamStatus::BodyChunk(chunk) => String::from_utf8(chunk.to_vec()).unwrap(),
+                StreamStatus::End => String::from("End"),
+                StreamStatus::Error(e) => format!("{:?}", e),
+            });
+        }
+    }
+
+    #[test]
+    fn test_stream_next() {
+        let mut connection = ClientConnection::with_limits(
+            4096,
+            RequestLimits {
+                max_body_bytes: 4,
+                ..RequestLimits::default()
+            },
+        );
+        assert!(stream_parts(&mut connection).is_empty());
+        connection
+            .receive(b"GET /a HTTP/1.1\r\n\r\nPUT /b HTTP/1.1\r\nContent-Length: 9\r\n\r\nguest")
+            .unwrap();
+        // The body is larger than the limit, which doesn't apply to the streamed bodies.
+        assert_eq!(
+            stream_parts(&mut connection),
+            vec!["Get", "End", "Put", "guest"]
+        );
+        assert_eq!(connection.pending(), 0);
+        connection
+            .receive(
+                b" mem\
+                  PATCH /c HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nguest\r\n",
+            )
+            .unwrap();
+        assert_eq!(
+            stream_parts(&mut connection),
+            vec![" mem", "End", "Patch", "guest"]
+        );
+        connection.receive(b"0\r\n\r\nGET /d HTTP/1.1\r\n").unwrap();
+        assert_eq!(stream_parts(&mut connection), vec!["End"]);
+        connection.receive(b"\r\n").unwrap();
+        assert_eq!(stream_parts(&mut connection), vec!["Get", "End"]);
+        assert!(connection.keep_alive());
+
+        // The body of a request closing the connection is still streamed, but not what follows.
+        let mut connection = ClientConnection::new(4096);
+        connection
+            .receive(b"PUT /a HTTP/1.1\r\nConnection: close\r\nContent-Length: 2\r\n\r\n{")
+            .unwrap();
+        assert_eq!(stream_parts(&mut connection), vec!["Put", "{"]);
+        assert!(!connection.keep_alive());
+        connection.receive(b"}GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(stream_parts(&mut connection), vec!["}", "End"]);
+        assert_eq!(connection.pending(), 0);
+
+        // A malformed chunked body ends the connection.
+        let mut connection = ClientConnection::new(4096);
+        connection
+            .receive(b"PUT /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nx\r\n")
+            .unwrap();
+        let parts = stream_parts(&mut connection);
+        assert_eq!(parts[0], "Put");
+        assert!(parts[1].starts_with("InvalidChunkedBody"));
+        assert_eq!(parts.len(), 2);
+        assert!(!connection.keep_alive());
+
+        // So does an invalid head.
+        let mut connection = ClientConnection::new(4096);
+        connection
+            .receive(b"PUT /a HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n")
+            .unwrap();
+        let parts = stream_parts(&mut connection);
+        assert_eq!(parts.len(), 1);
+        assert!(parts[0].starts_with("InvalidChunkedBody"));
+    }
+
+    #[test]
+    fn test_split_reads() {
+        // The reads can split the requests anywhere, even within their request line, and more
+        // than once.
+        let requests: &[u8] = b"GET /a HTTP/1.1\r\n\r\n\
+            PUT /b HTTP/1.1\r\nContent-Length: 5\r\n\r\nguest\
+            PATCH /c HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n";
+        for first in 0..requests.len() {
+            for second in first..requests.len() {
+                let reads = [
+                    &requests[..first],
+                    &requests[first..second],
+                    &requests[second..],
+                ];
+
+                let mut connection = ClientConnection::new(requests.len());
+                let mut paths = Vec::new();
+                for read in &reads {
+                    connection.receive(read).unwrap();
+                    while let Some(path) = next_path(&mut connection) {
+                        paths.push(path.unwrap());
+                    }
+                }
+                assert_eq!(paths, vec!["/a", "/b", "/c"]);
+
+                let mut connection = ClientConnection::new(requests.len());
+                let mut parts = String::new();
+                for read in &reads {
+                    connection.receive(read).unwrap();
+                    parts.extend(stream_parts(&mut connection));
+                }
+                assert_eq!(parts, "GetEndPutguestEndPatch{}End");
+                assert_eq!(connection.pending(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_input_buffer() {
+        let mut buf = InputBuffer::new();
+        buf.extend(b"GET / HTTP/1.1\r\n");
+        buf.consume(4);
+        assert_eq!(&buf[..], b"/ HTTP/1.1\r\n");
+
+        // The parsed bytes are only dropped when the space they take is needed.
+        buf.bytes.reserve(16);
+        let capacity = buf.bytes.capacity();
+        buf.extend(&vec![b'x'; capacity - buf.bytes.len()]);
+        assert_eq!(buf.start, 4);
+        buf.extend(b"y");
+        assert_eq!(buf.start, 0);
+        assert_eq!(buf.len(), capacity - 3);
+        assert!(buf.starts_with(b"/ HTTP/1.1\r\nxx"));
+        assert!(buf.ends_with(b"xy"));
+
+        // The buffer starts over once all its bytes are parsed.
+        let len = buf.len();
+        buf.consume(len);
+        assert!(buf.is_empty());
+        buf.extend(b"GET");
+        assert_eq!(buf.start, 0);
+        assert_eq!(&buf[..], b"GET");
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        for &(request, ref err) in &[
+            (
+                &b"G(ET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n"[..],
+                RequestError::InvalidHttpMethod("Invalid HTTP method token."),
+            ),
+            (
+                &b"GET /a HTTP/1.1\r\nContent-Length: x\r\n\r\n"[..],
+                RequestError::InvalidContentLength,
+            ),
+            (
+                &b"PUT /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nx\r\n"[..],
+                RequestError::InvalidChunkedBody("Invalid chunk size."),
+            ),
+            (
+                &b"PUT /a HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\nx\r\n"[..],
+                RequestError::InvalidChunkedBody("Unsupported transfer coding."),
+            ),
+        ] {
+            let mut connection = ClientConnection::new(4096);
+            connection.receive(request).unwrap();
+            assert_eq!(&next_path(&mut connection).unwrap().unwrap_err(), err);
+            assert!(!connection.keep_alive());
+            assert_eq!(next_path(&mut connection), None);
+        }
+
+        // The pending input is capped.
+        let mut connection = ClientConnection::new(17);
+        connection.receive(b"GET /a HTTP/1.1\r\n").unwrap();
+        assert_eq!(
+            connection.receive(b"\r\n"),
+            Err(ConnectionError::InputBufferFull)
+        );
+        assert_eq!(connection.pending(), 17);
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = RequestLimits {
+            max_uri_len: 4,
+            max_header_bytes: 32,
+            max_headers: 3,
+            max_header_len: 26,
+            max_body_bytes: 4,
+            ..RequestLimits::default()
+        };
+        let connection = || ClientConnection::with_limits(4096, limits);
+
+        // The limits are checked before the requests are complete.
+        for &(bytes, ref err) in &[
+            (&b"GET /abc"[..], None),
+            (b"GET /abcd", Some(RequestError::UriTooLong)),
+            (b"GET /a HTTP/1.1\r\nAccept: text/plain\r\n", None),
+            (
+                b"GET /a HTTP/1.1\r\nAccept: text/plain\r\nServer: micro_http",
+                Some(RequestError::HeadersTooLarge),
+            ),
+            (
+                b"GET /a HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n",
+                Some(RequestError::TooManyHeaders),
+            ),
+            (
+                b"GET /a HTTP/1.1\r\nX-Long: 1234567890123456789",
+                Some(RequestError::HeaderTooLong),
+            ),
+            (
+                b"PUT /a HTTP/1.1\r\nContent-Length: 5\r\n\r\n",
+                Some(RequestError::BodyTooLarge),
+            ),
+            (
+                b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\n{  }\r\n",
+                None,
+            ),
+            (
+                b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\n{  }\r\n1\r\n ",
+                Some(RequestError::BodyTooLarge),
+            ),
+        ] {
+            let mut connection = connection();
+            connection.receive(bytes).unwrap();
+            match *err {
+                Some(ref err) => {
+                    assert_eq!(&next_path(&mut connection).unwrap().unwrap_err(), err);
+                    assert!(!connection.keep_alive());
+                }
+                None => assert_eq!(next_path(&mut connection), None),
+            }
+        }
+
+        let mut connection = connection();
+        connection
+            .receive(b"PUT /a HTTP/1.1\r\nContent-Length: 4\r\n\r\n{  }")
+            .unwrap();
+        assert_eq!(next_path(&mut connection), Some(Ok(String::from("/a"))));
+    }
+}
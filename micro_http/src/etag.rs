@@ -0,0 +1,167 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Support for [conditional requests](https://tools.ietf.org/html/rfc7232) with entity tags,
+//! which let clients polling a resource skip its body while it doesn't change.
+//!
+//! `response` tags the body of a response with an `ETag` derived from its content. A client
+//! sends the tag back in the `If-None-Match` header of its next request, and gets a
+//! `304 Not Modified` response without a body for as long as the content has the same tag.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::{etag, Body, Request, StatusCode};
+//!
+//! let tag = etag::entity_tag(b"43");
+//! let request_bytes = format!("GET /age HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", tag);
+//! let request = Request::try_from(request_bytes.as_bytes()).unwrap();
+//! let response = etag::response(&request, Body::new("43"));
+//! assert!(response.status() == StatusCode::NotModified);
+//! assert!(response.body().is_none());
+//! ```
+
+use common::Body;
+use headers::Header;
+use request::Request;
+use response::{Response, StatusCode};
+
+// The parameters of the 64 bit FNV-1a hash.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Returns the entity tag of a resource with the content `body`, in quotes, as sent in the
+/// `ETag` header.
+///
+/// The tag is a hash of the content, so it only changes when the content does.
+pub fn entity_tag(body: &[u8]) -> String {
+    let hash = body.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    });
+    format!("\"{:016x}\"", hash)
+}
+
+// Returns the opaque part of the entity tags of an `If-None-Match` header, in quotes and
+// without the weakness indicator, or `None` when the header is malformed.
+fn parse_tags(value: &str) -> Option<Vec<&str>> {
+    let mut tags = Vec::new();
+    let mut rest = value;
+    loop {
+        rest = rest.trim_start_matches(&[' ', '\t', ','][..]);
+        if rest.is_empty() {
+            return Some(tags);
+        }
+        if rest.starts_with("W/") {
+            rest = &rest[2..];
+        }
+        if !rest.starts_with('"') {
+            return None;
+        }
+        // The opaque tag can contain commas, so the tags are split at their closing quote.
+        let len = rest[1..].find('"')? + 2;
+        tags.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+}
+
+/// Returns `true` when the `If-None-Match` header of `request` matches the entity tag `tag`,
+/// which means the client already has the current content of the resource.
+///
+/// The tags are compared with the weak comparison, as required for `If-None-Match`, and `*`
+/// matches any tag.
+pub fn matches(request: &Request, tag: &str) -> bool {
+    let value = match request.header(Header::IfNoneMatch) {
+        Some(value) => value.trim(),
+        None => return false,
+    };
+    if value == "*" {
+        return true;
+    }
+    let tag = if tag.starts_with("W/") {
+        &tag[2..]
+    } else {
+        tag
+    };
+    parse_tags(value).map_or(false, |tags| tags.contains(&tag))
+}
+
+/// Builds the `200 OK` response to `request` for a resource with the content of `body`, tagged
+/// with its `ETag`, or a `304 Not Modified` response without the body when the
+/// `If-None-Match` header of the request matches the tag.
+pub fn response(request: &Request, body: Body) -> Response {
+    let tag = entity_tag(body.raw());
+    let mut response = if matches(request, &tag) {
+        Response::for_request(request, StatusCode::NotModified)
+    } else {
+        let mut response = Response::for_request(request, StatusCode::OK);
+        response.set_body(body);
+        response
+    };
+    response.add_header(Header::ETag, tag);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_with_tags(tags: &str) -> Vec<u8> {
+        format!("GET /age HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", tags).into_bytes()
+    }
+
+    #[test]
+    fn test_entity_tag() {
+        assert_eq!(entity_tag(b""), "\"cbf29ce484222325\"");
+        assert_eq!(entity_tag(b"a"), "\"af63dc4c8601ec8c\"");
+        assert_ne!(entity_tag(b"43"), entity_tag(b"44"));
+    }
+
+    #[test]
+    fn test_matches() {
+        let request = Request::try_from(b"GET /age HTTP/1.1\r\n\r\n").unwrap();
+        assert!(!matches(&request, "\"a\""));
+
+        for tags in &["\"a\"", "*", "\"b\", \"a\"", "W/\"a\"", "\"b,\",\"a\""] {
+            let request_bytes = request_with_tags(tags);
+            let request = Request::try_from(&request_bytes).unwrap();
+            assert!(matches(&request, "\"a\""), "{}", tags);
+            assert!(matches(&request, "W/\"a\""), "{}", tags);
+        }
+
+        for tags in &["\"b\"", "\"a", "a", "\"b\" \"a", "\"a,b\""] {
+            let request_bytes = request_with_tags(tags);
+            let request = Request::try_from(&request_bytes).unwrap();
+            assert!(!matches(&request, "\"a\""), "{}", tags);
+        }
+    }
+
+    #[test]
+    fn test_response() {
+        let request = Request::try_from(b"GET /age HTTP/1.1\r\n\r\n").unwrap();
+        let http_response = response(&request, Body::new("43"));
+        assert!(http_response.status() == StatusCode::OK);
+        assert_eq!(http_response.body().unwrap(), Body::new("43"));
+        let mut buf = Vec::new();
+        http_response.write_all(&mut buf).unwrap();
+        let response_str = String::from_utf8(buf).unwrap();
+        let tag = entity_tag(b"43");
+        assert!(response_str.contains(&format!("ETag: {}\r\n", tag)));
+
+        let request_bytes = request_with_tags(&tag);
+        let request = Request::try_from(&request_bytes).unwrap();
+        let http_response = response(&request, Body::new("43"));
+        assert!(http_response.status() == StatusCode::NotModified);
+        assert!(http_response.body().is_none());
+        let mut buf = Vec::new();
+        http_response.write_all(&mut buf).unwrap();
+        let response_str = String::from_utf8(buf).unwrap();
+        assert!(response_str.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(response_str.contains(&format!("ETag: {}\r\n", tag)));
+        assert!(!response_str.contains("Content-Length"));
+
+        // The content changed.
+        let http_response = response(&request, Body::new("44"));
+        assert!(http_response.status() == StatusCode::OK);
+        assert_eq!(http_response.body().unwrap(), Body::new("44"));
+    }
+}
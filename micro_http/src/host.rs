@@ -0,0 +1,131 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Validation of the `Host` header of the requests, against a list of allowed hosts.
+//!
+//! A page which a browser loaded from an attacker's domain can make the browser send requests to
+//! a local server, after resolving the domain to the server's address (DNS rebinding). Such
+//! requests still carry the attacker's domain in their `Host` header, so a server only accepting
+//! its own names rejects them.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::host::AllowedHosts;
+//! use micro_http::Request;
+//!
+//! let allowed_hosts = AllowedHosts::new(vec!["169.254.169.254"]);
+//! let request = Request::try_from(b"GET / HTTP/1.1\r\nHost: 169.254.169.254:80\r\n\r\n").unwrap();
+//! assert!(allowed_hosts.allows(&request));
+//! let request = Request::try_from(b"GET / HTTP/1.1\r\nHost: evil.example\r\n\r\n").unwrap();
+//! assert!(!allowed_hosts.allows(&request));
+//! ```
+
+use common::Body;
+use headers::Header;
+use request::{split_port, Request};
+use response::{Response, StatusCode};
+
+/// A list of the hosts a server accepts requests for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AllowedHosts {
+    hosts: Vec<String>,
+}
+
+impl AllowedHosts {
+    /// Creates a list of allowed hosts, given as host names or IP addresses, with or without a
+    /// port. The host names are case insensitive.
+    pub fn new<I, S>(hosts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        AllowedHosts {
+            hosts: hosts
+                .into_iter()
+                .map(|host| host.as_ref().trim().to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Returns `true` when the `Host` header of `request` names one of the allowed hosts.
+    ///
+    /// An allowed host without a port matches any port. The requests without a `Host` header
+    /// are not allowed, since it can't be validated.
+    pub fn allows(&self, request: &Request) -> bool {
+        let host = match request.header(Header::Host) {
+            Some(host) => host.trim().to_ascii_lowercase(),
+            None => return false,
+        };
+        let (name, _) = split_port(&host);
+        self.hosts
+            .iter()
+            .any(|allowed| *allowed == host || (split_port(allowed).1.is_none() && allowed == name))
+    }
+
+    /// Builds the `403 Forbidden` response to a `request` for a host which is not allowed.
+    pub fn forbidden_response(request: &Request) -> Response {
+        let mut response = Response::for_request(request, StatusCode::Forbidden);
+        response.set_body(Body::new("Host not allowed."));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_for(host: &str) -> Vec<u8> {
+        format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", host).into_bytes()
+    }
+
+    #[test]
+    fn test_split_port() {
+        assert_eq!(split_port("localhost"), ("localhost", None));
+        assert_eq!(split_port("localhost:8080"), ("localhost", Some("8080")));
+        assert_eq!(split_port("[::1]"), ("[::1]", None));
+        assert_eq!(split_port("[::1]:80"), ("[::1]", Some("80")));
+        assert_eq!(split_port("[::1"), ("[::1", None));
+    }
+
+    #[test]
+    fn test_allows() {
+        let allowed_hosts = AllowedHosts::new(vec!["169.254.169.254", "LocalHost:8080", "[::1]"]);
+        for host in &[
+            "169.254.169.254",
+            "169.254.169.254:80",
+            "localhost:8080",
+            " LOCALHOST:8080 ",
+            "[::1]",
+            "[::1]:80",
+        ] {
+            let request_bytes = request_for(host);
+            let request = Request::try_from(&request_bytes).unwrap();
+            assert!(allowed_hosts.allows(&request), "{}", host);
+        }
+        for host in &[
+            "169.254.169.25",
+            "localhost",
+            "localhost:80",
+            "evil.example",
+            "",
+        ] {
+            let request_bytes = request_for(host);
+            let request = Request::try_from(&request_bytes).unwrap();
+            assert!(!allowed_hosts.allows(&request), "{}", host);
+        }
+
+        let request = Request::try_from(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert!(!allowed_hosts.allows(&request));
+        assert!(!AllowedHosts::default().allows(&request));
+    }
+
+    #[test]
+    fn test_forbidden_response() {
+        let request = Request::try_from(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let response = AllowedHosts::forbidden_response(&request);
+        assert!(response.status() == StatusCode::Forbidden);
+        assert_eq!(response.http_version(), request.http_version());
+        assert_eq!(response.body().unwrap(), Body::new("Host not allowed."));
+    }
+}
@@ -1,51 +1,147 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 #![warn(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
 //! Minimal implementation of the [HTTP/1.0](https://tools.ietf.org/html/rfc1945)
 //! and [HTTP/1.1](https://www.ietf.org/rfc/rfc2616.txt) protocols.
 //!
-//! HTTP/1.1 has a mandatory header **Host**, but as this crate is only used
-//! for parsing MMDS requests, this header (if present) is ignored.
+//! HTTP/1.1 has a mandatory header **Host**, which is only validated when a
+//! list of allowed hosts is given, with the **host** module.
 //!
-//! This HTTP implementation is stateless thus it does not support chunking or
-//! compression.
+//! This HTTP implementation is stateless thus it does not support compression.
+//! Chunked bodies can be decoded and written with the **chunked** module, and
+//! the chunked bodies of requests are decoded when parsing them.
 //!
 //! ## Supported Headers
-//! The **micro_http** crate does not have support for parsing **Request**
-//! headers.
+//! The **micro_http** crate only parses the **Request** headers listed in
+//! **Header**, the other headers are ignored.
 //!
 //! The **Response** does not have a public interface for adding headers, but whenever
 //! a write to the **Body** is made, the headers **ContentLength** and **MediaType**
-//! are automatically updated.
+//! are automatically updated. The **Date** header is added when the **Response** is
+//! written, and the **Server** header can be set with `set_server`, or for all the
+//! responses of a server with `HttpServer::set_server_token`.
 //!
 //! ### Media Types
-//! The only supported media type is **text/plain**.
+//! The supported media types are **text/plain**, **text/event-stream**,
+//! **application/json** and **application/octet-stream**. The **accept**
+//! module picks the media type a request prefers, among the ones a resource is
+//! offered in.
 //!
 //! ## Supported Methods
-//! The only supported HTTP Method is **GET**.
+//! The supported HTTP Methods are **GET**, **POST**, **PUT**, **PATCH**,
+//! **DELETE**, **TRACE** and **CONNECT**. The other methods are parsed into
+//! **Method::Other**, so that the router can answer them with a **405** listing
+//! the allowed methods.
 //!
 //! ## Supported Status Codes
 //! The supported status codes are:
 //!
+//! - Switching Protocols - 101
 //! - OK - 200
+//! - Partial Content - 206
+//! - Not Modified - 304
 //! - Bad Request - 400
+//! - Unauthorized - 401
+//! - Forbidden - 403
 //! - Not Found - 404
+//! - Method Not Allowed - 405
+//! - Request Timeout - 408
+//! - Payload Too Large - 413
+//! - URI Too Long - 414
+//! - Range Not Satisfiable - 416
+//! - Request Header Fields Too Large - 431
 //! - Internal Server Error - 500
 //! - Not Implemented - 501
+//! - HTTP Version Not Supported - 505
+//!
+//! ## Multipart Bodies
+//! The **multipart** module parses `multipart/form-data` bodies in chunks,
+//! streaming the payload of each part to a sink.
+//!
+//! ## Range Requests
+//! The **range** module builds the responses to requests with a `Range`
+//! header, for resuming the download of large resources. The requested bytes
+//! of a file can be streamed from it instead of being buffered.
+//!
+//! ## Conditional Requests
+//! The **etag** module tags the responses with an `ETag` derived from their
+//! body, and answers the requests whose `If-None-Match` header matches it with
+//! `304 Not Modified`, without the body.
+//!
+//! ## Non-blocking Connections
+//! The **connection** module buffers the bytes read from a connection and
+//! parses the requests sent on it one by one, for as long as they keep the
+//! connection alive, or streams their bodies for large uploads. The **writer**
+//! module queues the responses of a connection and writes them as the
+//! connection accepts them, with a cap on the pending output.
+//!
+//! ## Routing
+//! The **router** module dispatches requests to handlers by method and path
+//! pattern, with parameters such as `/drives/{drive_id}` in the patterns.
+//!
+//! ## Server
+//! The **server** module serves the connections of a Unix domain socket or of a
+//! TCP socket, and passes their requests to a **RequestHandler**, such as a
+//! **Router**. The connections which stall can be closed after a timeout,
+//! with a `408 Request Timeout` response, and the requests served can be
+//! logged with a callback.
+//!
+//! ## Timers
+//! The **timer** module holds the timers the handler of a server defers work
+//! with, such as expiring tokens. The server waits for them in the same loop as
+//! for its connections, and calls the handler once they expire.
+//!
+//! ## Authentication
+//! The **auth** module helps authenticating the requests of a server before
+//! they are handled, with a bearer token or with the credentials of the peer
+//! process of a Unix domain socket, and answering the other ones with
+//! `401 Unauthorized` or `403 Forbidden`.
+//!
+//! ## Server-Sent Events
+//! The **sse** module builds the responses which stream events, and writes
+//! the events and the keep-alive comments as the chunks of their bodies.
+//!
+//! ## TLS
+//! The **tls** module encrypts the connections of a server bound to a TCP socket,
+//! optionally verifying the certificates of the clients. It is only built with
+//! the **tls** feature.
+//!
+//! ## WebSocket
+//! The **websocket** module implements the opening handshake and the frames of
+//! the WebSocket protocol, for connections upgraded from HTTP/1.1.
+//!
+//! ## Parser Core
+//! The **parser** module splits requests in their parts without allocating. It
+//! is the only module built without the default **std** feature, for reusing
+//! the parser in `no_std` components.
+//!
+//! With the **alloc** feature instead, the typed parts of the requests are
+//! built too, only using `core` and `alloc`: the **RequestLine** with its
+//! **Method**, **Uri** and **Version**, and the **Headers**. They suit the
+//! fuzz targets and the test harnesses which can't link `std`.
 //!
 //! ## Example for parsing an HTTP Request from a slice
 //! ```
 //! extern crate micro_http;
+//! # #[cfg(feature = "std")]
+//! # fn main() {
 //! use micro_http::{Request, Version};
 //!
 //! let http_request = Request::try_from(b"GET http://localhost/home HTTP/1.0\r\n").unwrap();
 //! assert_eq!(http_request.http_version(), Version::Http10);
 //! assert_eq!(http_request.uri().get_abs_path(), "/home");
+//! assert_eq!(http_request.uri().match_path("/{page}").unwrap(), vec!["home"]);
+//! # }
+//! # #[cfg(not(feature = "std"))]
+//! # fn main() {}
 //! ```
 //!
 //! ## Example for creating an HTTP Response
 //! ```
 //! extern crate micro_http;
+//! # #[cfg(feature = "std")]
+//! # fn main() {
 //! use micro_http::{Body, Response, StatusCode, Version};
 //!
 //! let mut response = Response::new(Version::Http10, StatusCode::OK);
@@ -56,16 +152,71 @@
 //! assert_eq!(response.body().unwrap(), Body::new(body));
 //! assert_eq!(response.http_version(), Version::Http10);
 //!
-//! let mut response_buf: [u8; 77] = [0; 77];
+//! let mut response_buf: [u8; 116] = [0; 116];
 //! assert!(response.write_all(&mut response_buf.as_mut()).is_ok());
+//! # }
+//! # #[cfg(not(feature = "std"))]
+//! # fn main() {}
 //! ```
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate epoll;
+#[cfg(feature = "std")]
+extern crate libc;
+#[cfg(feature = "tls")]
+extern crate rustls;
+
+#[cfg(feature = "std")]
+pub mod accept;
+#[cfg(feature = "std")]
+pub mod auth;
+#[cfg(feature = "std")]
+pub mod chunked;
+#[cfg(feature = "alloc")]
 mod common;
+#[cfg(feature = "std")]
+pub mod connection;
+#[cfg(feature = "std")]
+pub mod etag;
+#[cfg(feature = "std")]
+pub mod host;
+#[cfg(feature = "std")]
+pub mod multipart;
+pub mod parser;
+#[cfg(feature = "std")]
+pub mod range;
+#[cfg(feature = "alloc")]
 mod request;
+#[cfg(feature = "std")]
 mod response;
-use common::ascii;
+#[cfg(feature = "std")]
+pub mod router;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod sse;
+#[cfg(feature = "std")]
+pub mod timer;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "std")]
+pub mod websocket;
+#[cfg(feature = "std")]
+pub mod writer;
+#[cfg(feature = "std")]
 use common::headers;
+#[cfg(feature = "std")]
+use parser::ascii;
 
-pub use request::{Request, RequestError};
+#[cfg(feature = "alloc")]
+pub use request::{remove_dot_segments, QueryPairs, RequestError, RequestLine, Segments, Uri};
+#[cfg(feature = "std")]
+pub use request::{OwnedRequest, Request, RequestLimits};
+#[cfg(feature = "std")]
 pub use response::{Response, StatusCode};
 
-pub use common::{Body, Version};
+#[cfg(feature = "alloc")]
+pub use common::headers::{Expectation, Header, Headers, MediaType, TransferEncoding};
+#[cfg(feature = "alloc")]
+pub use common::{Body, Method, Version};
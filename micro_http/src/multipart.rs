@@ -0,0 +1,494 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Streaming parser for `multipart/form-data` bodies, as defined by
+//! [RFC 7578](https://tools.ietf.org/html/rfc7578).
+//!
+//! The body is fed to a `MultipartParser` in chunks of any size, as they are read from the
+//! connection. The headers of each part and its payload are passed on to a `PartSink`, so large
+//! parts never have to be held in memory.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::multipart::{self, MultipartParser, Part, PartSink};
+//! use std::io::Error;
+//!
+//! #[derive(Default)]
+//! struct Upload {
+//!     names: Vec<String>,
+//!     data: Vec<u8>,
+//! }
+//!
+//! impl PartSink for Upload {
+//!     fn start_part(&mut self, part: &Part) -> Result<(), Error> {
+//!         self.names.push(part.name().unwrap_or("").to_string());
+//!         Ok(())
+//!     }
+//!
+//!     fn write_part(&mut self, data: &[u8]) -> Result<(), Error> {
+//!         self.data.extend_from_slice(data);
+//!         Ok(())
+//!     }
+//!
+//!     fn end_part(&mut self) -> Result<(), Error> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let boundary = multipart::boundary("multipart/form-data; boundary=xyz").unwrap();
+//! let mut parser = MultipartParser::new(boundary).unwrap();
+//! let mut upload = Upload::default();
+//! parser
+//!     .parse(b"--xyz\r\nContent-Disposition: form-data; name=\"mem\"\r\n", &mut upload)
+//!     .unwrap();
+//! parser.parse(b"\r\nguest memory\r\n--xyz--\r\n", &mut upload).unwrap();
+//! assert!(parser.finish().is_ok());
+//!
+//! assert_eq!(upload.names, vec!["mem"]);
+//! assert_eq!(upload.data, b"guest memory");
+//! ```
+
+use std::io::Error as WriteError;
+
+use ascii::{CR, LF};
+use common::RequestError;
+use headers::{Header, Headers};
+use parser::find_byte;
+
+// See RFC 2046 Section 5.1.1.
+const MAX_BOUNDARY_LEN: usize = 70;
+// Bounds the memory used for buffering the headers of a part.
+const MAX_PART_HEADERS_LEN: usize = 8192;
+
+/// Errors associated with parsing a multipart body.
+#[derive(Debug)]
+pub enum MultipartError {
+    /// The boundary is empty, longer than 70 characters or it contains invalid characters.
+    InvalidBoundary,
+    /// A boundary delimiter is not followed by a line break or by the closing "--".
+    InvalidDelimiter,
+    /// The headers of a part are larger than 8 KiB.
+    PartHeadersTooLarge,
+    /// The headers of a part can't be parsed.
+    InvalidPartHeaders(RequestError),
+    /// The body ended before the closing delimiter.
+    IncompleteBody,
+    /// The `PartSink` failed to handle a part.
+    Sink(WriteError),
+}
+
+impl From<WriteError> for MultipartError {
+    fn from(error: WriteError) -> Self {
+        MultipartError::Sink(error)
+    }
+}
+
+/// Returns the boundary of a `multipart/form-data` body, given the value of the `Content-Type`
+/// header of the request. Returns `None` if the media type is different or the boundary is
+/// missing or invalid.
+pub fn boundary(content_type: &str) -> Option<&str> {
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+
+    params
+        .filter_map(|param| parse_param(param, "boundary"))
+        .next()
+        .filter(|boundary| is_valid_boundary(boundary))
+}
+
+// Returns the value of a `name=value` parameter, without the quotes around it, if it is called
+// `name`.
+fn parse_param<'a>(param: &'a str, name: &str) -> Option<&'a str> {
+    let separator = param.find('=')?;
+    if !param[..separator].trim().eq_ignore_ascii_case(name) {
+        return None;
+    }
+    let value = param[separator + 1..].trim();
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        Some(&value[1..value.len() - 1])
+    } else {
+        Some(value)
+    }
+}
+
+// See the `bchars` rule of RFC 2046 Section 5.1.1.
+fn is_valid_boundary(boundary: &str) -> bool {
+    !boundary.is_empty()
+        && boundary.len() <= MAX_BOUNDARY_LEN
+        && !boundary.ends_with(' ')
+        && boundary
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"'()+_,-./:=? ".contains(&byte))
+}
+
+// Returns the index of the first `pattern` in `bytes`. The pattern is only compared where its
+// first byte is, which is found a word at a time.
+fn find(bytes: &[u8], pattern: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    while bytes.len() - offset >= pattern.len() {
+        let index = offset + find_byte(&bytes[offset..], pattern[0])?;
+        if bytes[index..].starts_with(pattern) {
+            return Some(index);
+        }
+        offset = index + 1;
+    }
+    None
+}
+
+/// The headers of a part of a multipart body.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Part {
+    name: Option<String>,
+    filename: Option<String>,
+    content_type: Option<String>,
+}
+
+impl Part {
+    fn from_headers(headers: &Headers) -> Self {
+        let mut part = Part {
+            content_type: headers.get(Header::ContentType).map(String::from),
+            ..Default::default()
+        };
+        if let Some(disposition) = headers.get(Header::ContentDisposition) {
+            // The first item is the disposition type, which is always "form-data".
+            for param in disposition.split(';').skip(1) {
+                if let Some(name) = parse_param(param, "name") {
+                    part.name = Some(name.to_string());
+                } else if let Some(filename) = parse_param(param, "filename") {
+                    part.filename = Some(filename.to_string());
+                }
+            }
+        }
+        part
+    }
+
+    /// Returns the name of the form field of the part.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|name| name.as_str())
+    }
+
+    /// Returns the name of the file uploaded in the part, if it is a file.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_ref().map(|filename| filename.as_str())
+    }
+
+    /// Returns the media type of the payload of the part.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type
+            .as_ref()
+            .map(|content_type| content_type.as_str())
+    }
+}
+
+/// Receives the parts of a multipart body, as they are parsed.
+pub trait PartSink {
+    /// Called when a new part starts, with its headers.
+    fn start_part(&mut self, part: &Part) -> Result<(), WriteError>;
+
+    /// Called with consecutive chunks of the payload of the current part.
+    fn write_part(&mut self, data: &[u8]) -> Result<(), WriteError>;
+
+    /// Called after the whole payload of the current part was written.
+    fn end_part(&mut self) -> Result<(), WriteError>;
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    // Before the first delimiter.
+    Preamble,
+    // Right after a delimiter.
+    Delimiter,
+    Headers,
+    Payload,
+    // After the closing delimiter.
+    Epilogue,
+}
+
+/// Incremental parser for `multipart/form-data` bodies.
+pub struct MultipartParser {
+    // The line break before the boundary is part of the delimiter.
+    delimiter: Vec<u8>,
+    // The bytes which could not be handled yet, because they may be the beginning of a
+    // delimiter or of the headers of a part.
+    buffer: Vec<u8>,
+    state: State,
+}
+
+impl MultipartParser {
+    /// Creates a parser for a body delimited by `boundary`.
+    ///
+    /// # Errors
+    /// Returns `InvalidBoundary` when `boundary` is not a valid boundary.
+    pub fn new(boundary: &str) -> Result<Self, MultipartError> {
+        if !is_valid_boundary(boundary) {
+            return Err(MultipartError::InvalidBoundary);
+        }
+
+        let mut delimiter = vec![CR, LF, b'-', b'-'];
+        delimiter.extend_from_slice(boundary.as_bytes());
+        Ok(MultipartParser {
+            delimiter,
+            // The first delimiter is not preceded by a line break when there is no preamble.
+            buffer: vec![CR, LF],
+            state: State::Preamble,
+        })
+    }
+
+    /// Parses the next chunk of the body, passing on to `sink` the parts it completes.
+    ///
+    /// # Errors
+    /// Returns a `MultipartError` when the body is malformed or `sink` fails. The parser can
+    /// not be used afterwards.
+    pub fn parse<T: PartSink>(&mut self, bytes: &[u8], sink: &mut T) -> Result<(), MultipartError> {
+        self.buffer.extend_from_slice(bytes);
+
+        let mut consumed = 0;
+        loop {
+            let remaining = &self.buffer[consumed..];
+            match self.state {
+                State::Preamble | State::Payload => match find(remaining, &self.delimiter) {
+                    Some(index) => {
+                        if self.state == State::Payload {
+                            sink.write_part(&remaining[..index])?;
+                            sink.end_part()?;
+                        }
+                        consumed += index + self.delimiter.len();
+                        self.state = State::Delimiter;
+                    }
+                    None => {
+                        // The end of the buffer may be the beginning of a delimiter.
+                        let len = remaining.len().saturating_sub(self.delimiter.len() - 1);
+                        if self.state == State::Payload && len > 0 {
+                            sink.write_part(&remaining[..len])?;
+                        }
+                        consumed += len;
+                        break;
+                    }
+                },
+                State::Delimiter => {
+                    if remaining.len() < 2 {
+                        break;
+                    }
+                    match &remaining[..2] {
+                        b"--" => {
+                            consumed += 2;
+                            self.state = State::Epilogue;
+                        }
+                        // The line break is left in the buffer, so that the headers can be
+                        // found even when there are none.
+                        b"\r\n" => self.state = State::Headers,
+                        _ => return Err(MultipartError::InvalidDelimiter),
+                    }
+                }
+                State::Headers => match find(remaining, &[CR, LF, CR, LF]) {
+                    Some(index) => {
+                        let headers = Headers::parse(&remaining[2..index + 2])
+                            .map_err(MultipartError::InvalidPartHeaders)?;
+                        let part = Part::from_headers(&headers);
+                        sink.start_part(&part)?;
+                        consumed += index + 4;
+                        self.state = State::Payload;
+                    }
+                    None => {
+                        if remaining.len() > MAX_PART_HEADERS_LEN {
+                            return Err(MultipartError::PartHeadersTooLarge);
+                        }
+                        break;
+                    }
+                },
+                State::Epilogue => {
+                    consumed = self.buffer.len();
+                    break;
+                }
+            }
+        }
+
+        self.buffer.drain(..consumed);
+        Ok(())
+    }
+
+    /// Checks that the whole body was parsed, after its last chunk.
+    ///
+    /// # Errors
+    /// Returns `IncompleteBody` when the closing delimiter was not parsed.
+    pub fn finish(&self) -> Result<(), MultipartError> {
+        match self.state {
+            State::Epilogue => Ok(()),
+            _ => Err(MultipartError::IncompleteBody),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::ErrorKind;
+
+    const BODY: &[u8] = b"This is the preamble.\r\n\
+        --boundary\r\n\
+        Content-Disposition: form-data; name=\"config\"\r\n\
+        \r\n\
+        {\"vcpu_count\": 2}\r\n\
+        --boundary\r\n\
+        content-disposition: form-data; name=\"memory\"; filename=\"mem.bin\"\r\n\
+        Content-Type: application/octet-stream\r\n\
+        \r\n\
+        \r\n--boundar\r\n\
+        --boundary\r\n\
+        \r\n\
+        \r\n\
+        --boundary--\r\n\
+        This is the epilogue.";
+
+    #[derive(Default)]
+    struct Collector {
+        parts: Vec<(Part, Vec<u8>)>,
+        open: bool,
+        fail: bool,
+    }
+
+    impl PartSink for Collector {
+        fn start_part(&mut self, part: &Part) -> Result<(), WriteError> {
+            assert!(!self.open);
+            self.open = true;
+            self.parts.push((part.clone(), Vec::new()));
+            Ok(())
+        }
+
+        fn write_part(&mut self, data: &[u8]) -> Result<(), WriteError> {
+            assert!(self.open);
+            if self.fail {
+                return Err(WriteError::new(ErrorKind::Other, "sink failure"));
+            }
+            self.parts.last_mut().unwrap().1.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn end_part(&mut self) -> Result<(), WriteError> {
+            assert!(self.open);
+            self.open = false;
+            Ok(())
+        }
+    }
+
+    fn check_parts(collector: &Collector) {
+        assert!(!collector.open);
+        assert_eq!(collector.parts.len(), 3);
+
+        let (ref part, ref payload) = collector.parts[0];
+        assert_eq!(part.name(), Some("config"));
+        assert_eq!(part.filename(), None);
+        assert_eq!(part.content_type(), None);
+        assert_eq!(payload.as_slice(), b"{\"vcpu_count\": 2}");
+
+        let (ref part, ref payload) = collector.parts[1];
+        assert_eq!(part.name(), Some("memory"));
+        assert_eq!(part.filename(), Some("mem.bin"));
+        assert_eq!(part.content_type(), Some("application/octet-stream"));
+        // Line breaks and partial boundaries are part of the payload.
+        assert_eq!(payload.as_slice(), b"\r\n--boundar");
+
+        let (ref part, ref payload) = collector.parts[2];
+        assert_eq!(part, &Part::default());
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(boundary("multipart/form-data; boundary=abc"), Some("abc"));
+        assert_eq!(
+            boundary("Multipart/Form-Data;charset=utf-8; BOUNDARY=\"a b:c\""),
+            Some("a b:c")
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("multipart/mixed; boundary=abc"), None);
+        assert_eq!(boundary("text/plain; boundary=abc"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+        assert_eq!(boundary("multipart/form-data; boundary=\"abc \""), None);
+        assert_eq!(boundary("multipart/form-data; boundary=a\"b"), None);
+        let long_boundary = format!("multipart/form-data; boundary={}", "a".repeat(71));
+        assert_eq!(boundary(&long_boundary), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut parser = MultipartParser::new("boundary").unwrap();
+        let mut collector = Collector::default();
+        parser.parse(BODY, &mut collector).unwrap();
+        assert!(parser.finish().is_ok());
+        check_parts(&collector);
+
+        // The body can be split anywhere.
+        for chunk_len in 1..BODY.len() {
+            let mut parser = MultipartParser::new("boundary").unwrap();
+            let mut collector = Collector::default();
+            for chunk in BODY.chunks(chunk_len) {
+                parser.parse(chunk, &mut collector).unwrap();
+            }
+            assert!(parser.finish().is_ok());
+            check_parts(&collector);
+        }
+
+        // The body can start with a delimiter.
+        let mut parser = MultipartParser::new("boundary").unwrap();
+        let mut collector = Collector::default();
+        parser
+            .parse(b"--boundary\r\n\r\ndata\r\n--boundary--", &mut collector)
+            .unwrap();
+        assert!(parser.finish().is_ok());
+        assert_eq!(collector.parts.len(), 1);
+        assert_eq!(collector.parts[0].1, b"data");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        match MultipartParser::new("") {
+            Err(MultipartError::InvalidBoundary) => (),
+            _ => panic!("Expected an invalid boundary error."),
+        }
+
+        // The body ended early.
+        let mut parser = MultipartParser::new("boundary").unwrap();
+        let mut collector = Collector::default();
+        parser
+            .parse(b"--boundary\r\n\r\ndata", &mut collector)
+            .unwrap();
+        match parser.finish() {
+            Err(MultipartError::IncompleteBody) => (),
+            _ => panic!("Expected an incomplete body error."),
+        }
+
+        let mut parser = MultipartParser::new("boundary").unwrap();
+        match parser.parse(b"--boundary!\r\n", &mut Collector::default()) {
+            Err(MultipartError::InvalidDelimiter) => (),
+            _ => panic!("Expected an invalid delimiter error."),
+        }
+
+        let mut parser = MultipartParser::new("boundary").unwrap();
+        let mut collector = Collector::default();
+        parser.parse(b"--boundary\r\n", &mut collector).unwrap();
+        let headers = vec![b'a'; MAX_PART_HEADERS_LEN];
+        match parser.parse(&headers, &mut collector) {
+            Err(MultipartError::PartHeadersTooLarge) => (),
+            _ => panic!("Expected a part headers too large error."),
+        }
+
+        let mut parser = MultipartParser::new("boundary").unwrap();
+        let mut collector = Collector {
+            fail: true,
+            ..Default::default()
+        };
+        match parser.parse(BODY, &mut collector) {
+            Err(MultipartError::Sink(_)) => (),
+            _ => panic!("Expected a sink error."),
+        }
+    }
+}
@@ -0,0 +1,539 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The core of the HTTP parser, which splits request lines, header fields and chunk size lines
+//! into their parts.
+//!
+//! The core only borrows from the parsed bytes and it does not allocate, so it builds without
+//! `std` and without `alloc`, by disabling the default `std` feature of the crate. The rest of
+//! the crate is built on top of it.
+//!
+//! The line breaks and the separators are searched with `find_byte`, which compares the bytes a
+//! word at a time. The `parser` benchmark compares it to the byte by byte search.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::parser::{request_line, HeaderFields};
+//!
+//! let request = b"GET /home HTTP/1.1\r\nHost: localhost\r\n\r\n";
+//! let (method, uri, version) = request_line(request);
+//! assert_eq!((method, uri, version), (&b"GET"[..], &b"/home"[..], &b"HTTP/1.1"[..]));
+//!
+//! let mut fields = HeaderFields::new(&request[20..]);
+//! assert_eq!(fields.next(), Some(Ok((&b"Host"[..], &b"localhost"[..]))));
+//! assert_eq!(fields.next(), None);
+//! ```
+
+pub(crate) mod ascii {
+    pub const CR: u8 = b'\r';
+    pub const COLON: u8 = b':';
+    pub const HTAB: u8 = b'\t';
+    pub const LF: u8 = b'\n';
+    pub const SP: u8 = b' ';
+}
+
+use self::ascii::{COLON, CR, HTAB, LF, SP};
+
+/// Errors associated with parsing header fields and chunk size lines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseError {
+    /// A header line does not have a colon.
+    MissingHeaderSeparator,
+    /// A header line starts with whitespace, continuing the previous header.
+    ObsoleteLineFolding,
+    /// A header name is empty or it is not a valid token.
+    InvalidHeaderName,
+    /// A header value contains control characters.
+    InvalidHeaderValue,
+    /// The size of a chunk is not a hexadecimal number, or it does not fit in an `usize`.
+    InvalidChunkSize,
+    /// A CR is not followed by a LF. Only returned by the strict validation.
+    BareCarriageReturn,
+    /// A line ends with a LF which is not preceded by a CR. Only returned by the strict
+    /// validation.
+    BareLineFeed,
+    /// The bytes contain a NUL. Only returned by the strict validation.
+    NulByte,
+    /// The request line is not made of a method, a URI and a version separated by single
+    /// spaces. Only returned by the strict validation.
+    MalformedRequestLine,
+    /// A header value contains bytes which are not ASCII. Only returned by the strict
+    /// validation.
+    NonAsciiHeaderValue,
+}
+
+// The number of bytes compared at once by `find_byte`.
+const WORD_LEN: usize = (usize::max_value().count_ones() / 8) as usize;
+// A word with all its bytes set to 0x01.
+const LOW_BITS: usize = usize::max_value() / 0xff;
+// A word with all its bytes set to 0x80.
+const HIGH_BITS: usize = LOW_BITS << 7;
+
+// Returns `true` when one of the bytes of `word` is 0. Subtracting 1 from each byte only sets
+// the high bit of the bytes which were 0, when the high bit was not already set.
+fn has_zero_byte(word: usize) -> bool {
+    word.wrapping_sub(LOW_BITS) & !word & HIGH_BITS != 0
+}
+
+/// Returns the index of the first `needle` in `bytes`, like `memchr`.
+///
+/// The bytes are compared a word at a time, which is several times faster than comparing them
+/// one by one on the long header sections and bodies.
+pub fn find_byte(bytes: &[u8], needle: u8) -> Option<usize> {
+    let pattern = LOW_BITS * usize::from(needle);
+    let mut offset = 0;
+    for chunk in bytes.chunks_exact(WORD_LEN) {
+        let mut word = [0; WORD_LEN];
+        word.copy_from_slice(chunk);
+        // The bytes equal to `needle` are 0 once they are XORed with the pattern.
+        if has_zero_byte(usize::from_ne_bytes(word) ^ pattern) {
+            break;
+        }
+        offset += WORD_LEN;
+    }
+    // The needle is in the word where the loop stopped, or in the last bytes, which don't fill
+    // a word.
+    bytes[offset..]
+        .iter()
+        .position(|byte| *byte == needle)
+        .map(|index| offset + index)
+}
+
+/// Splits `bytes` in the bytes before and after the first `separator`, which is not included.
+///
+/// When there is no `separator`, the bytes before it are empty.
+pub fn split(bytes: &[u8], separator: u8) -> (&[u8], &[u8]) {
+    match find_byte(bytes, separator) {
+        Some(index) => (&bytes[..index], &bytes[index + 1..]),
+        None => (&[], bytes),
+    }
+}
+
+/// Returns `line` without its trailing CR, if it has one.
+pub fn trim_cr(line: &[u8]) -> &[u8] {
+    match line.last() {
+        Some(&CR) => &line[..line.len() - 1],
+        _ => line,
+    }
+}
+
+// Returns `bytes` without the trailing optional whitespace.
+fn trim_end_whitespace(mut bytes: &[u8]) -> &[u8] {
+    while let Some((&byte, rest)) = bytes.split_last() {
+        if byte != SP && byte != HTAB {
+            break;
+        }
+        bytes = rest;
+    }
+    bytes
+}
+
+// Returns `bytes` without the leading and trailing optional whitespace.
+fn trim_whitespace(mut bytes: &[u8]) -> &[u8] {
+    while let Some((&byte, rest)) = bytes.split_first() {
+        if byte != SP && byte != HTAB {
+            break;
+        }
+        bytes = rest;
+    }
+    trim_end_whitespace(bytes)
+}
+
+/// Returns `true` if `byte` is allowed in tokens, such as methods and header names.
+pub fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Splits the request line at the beginning of `bytes` in the method, the URI and the version.
+///
+/// The request line has to end with a line break, otherwise the version is empty.
+pub fn request_line(bytes: &[u8]) -> (&[u8], &[u8], &[u8]) {
+    let (method, remaining_bytes) = split(bytes, SP);
+    let (uri, remaining_bytes) = split(remaining_bytes, SP);
+    let (version, _) = split(remaining_bytes, LF);
+
+    (method, uri, trim_cr(version))
+}
+
+/// Splits a header line, without its line break, in the name and the trimmed value.
+///
+/// # Errors
+/// Returns a `ParseError` when the line is not a valid header field.
+pub fn header_field(line: &[u8]) -> Result<(&[u8], &[u8]), ParseError> {
+    let colon = find_byte(line, COLON).ok_or(ParseError::MissingHeaderSeparator)?;
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+    // Whitespace between the name and the colon is not allowed, because parsers that trim
+    // it would see a different header than the ones which don't.
+    if name.is_empty() || !name.iter().all(|byte| is_token_byte(*byte)) {
+        return Err(ParseError::InvalidHeaderName);
+    }
+    if value
+        .iter()
+        .any(|byte| byte.is_ascii_control() && *byte != HTAB)
+    {
+        return Err(ParseError::InvalidHeaderValue);
+    }
+    Ok((name, trim_whitespace(value)))
+}
+
+/// Returns the length of the header section at the beginning of `bytes`, including the empty
+/// line which ends it, or `None` when the section doesn't end in `bytes`.
+///
+/// The entity body of a request starts right after its header section.
+pub fn header_section_len(bytes: &[u8]) -> Option<usize> {
+    let mut len = 0;
+    loop {
+        let line_len = find_byte(&bytes[len..], LF)?;
+        let line = trim_cr(&bytes[len..len + line_len]);
+        len += line_len + 1;
+        if line.is_empty() {
+            return Some(len);
+        }
+    }
+}
+
+/// Iterator over the fields of a header section, which ends with an empty line.
+pub struct HeaderFields<'a> {
+    bytes: &'a [u8],
+    first_line: bool,
+}
+
+impl<'a> HeaderFields<'a> {
+    /// Creates an iterator over the header fields at the beginning of `bytes`.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        HeaderFields {
+            bytes,
+            first_line: true,
+        }
+    }
+}
+
+impl<'a> Iterator for HeaderFields<'a> {
+    type Item = Result<(&'a [u8], &'a [u8]), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line, remaining_bytes) = match find_byte(self.bytes, LF) {
+                Some(index) => (&self.bytes[..index], &self.bytes[index + 1..]),
+                None => (self.bytes, &[][..]),
+            };
+            let line = trim_cr(line);
+            if line.is_empty() {
+                self.bytes = &[];
+                return None;
+            }
+            self.bytes = remaining_bytes;
+
+            // A line starting with whitespace continues the previous header (obsolete line
+            // folding), which can hide headers from the parsers that don't support it. Such
+            // lines can only be skipped when they come first, as there is nothing to continue.
+            let first_line = self.first_line;
+            self.first_line = false;
+            if line[0] == SP || line[0] == HTAB {
+                if first_line {
+                    continue;
+                }
+                self.bytes = &[];
+                return Some(Err(ParseError::ObsoleteLineFolding));
+            }
+
+            let field = header_field(line);
+            if field.is_err() {
+                self.bytes = &[];
+            }
+            return Some(field);
+        }
+    }
+}
+
+// Checks that `bytes` have no NUL and that all their lines end with CRLF. The last line can be
+// incomplete, without a line break.
+fn check_line_breaks(bytes: &[u8]) -> Result<(), ParseError> {
+    for (index, byte) in bytes.iter().enumerate() {
+        match *byte {
+            0 => return Err(ParseError::NulByte),
+            CR if index + 1 < bytes.len() && bytes[index + 1] != LF => {
+                return Err(ParseError::BareCarriageReturn)
+            }
+            LF if index == 0 || bytes[index - 1] != CR => return Err(ParseError::BareLineFeed),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Validates the request line at the beginning of `bytes` strictly, to RFC 7230, which the
+/// parser of the request lines doesn't do on its own.
+///
+/// The request line has to end with CRLF and to be made of a method, which is a token, a URI
+/// and a version separated by single spaces, all of them made of visible ASCII characters.
+/// Other whitespace would be split differently by other parsers, which could then see another
+/// request.
+///
+/// # Errors
+/// Returns `NulByte`, `BareCarriageReturn` or `BareLineFeed` when the bytes of the line are not
+/// valid, and `MalformedRequestLine` when its parts are not.
+pub fn strict_request_line(bytes: &[u8]) -> Result<(), ParseError> {
+    let line = match find_byte(bytes, LF) {
+        Some(index) => &bytes[..=index],
+        None => bytes,
+    };
+    check_line_breaks(line)?;
+    if !line.ends_with(&[CR, LF]) {
+        return Err(ParseError::MalformedRequestLine);
+    }
+
+    let mut parts = line[..line.len() - 2].split(|byte| *byte == SP);
+    let (method, uri, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(uri), Some(version), None) => (method, uri, version),
+        _ => return Err(ParseError::MalformedRequestLine),
+    };
+    if method.is_empty()
+        || !method.iter().all(|byte| is_token_byte(*byte))
+        || uri.is_empty()
+        || version.is_empty()
+        || !uri
+            .iter()
+            .chain(version.iter())
+            .all(|byte| byte.is_ascii_graphic())
+    {
+        return Err(ParseError::MalformedRequestLine);
+    }
+    Ok(())
+}
+
+/// Validates the header section at the beginning of `bytes` strictly, to RFC 7230, before it
+/// is parsed by `HeaderFields`.
+///
+/// On top of the validation of `header_field`, all the lines have to end with CRLF, the first
+/// line can't start with whitespace, which `HeaderFields` skips otherwise, and the values can
+/// only contain ASCII characters.
+///
+/// # Errors
+/// Returns the `ParseError` of the first line which is not valid.
+pub fn strict_header_section(bytes: &[u8]) -> Result<(), ParseError> {
+    let section = &bytes[..header_section_len(bytes).unwrap_or(bytes.len())];
+    check_line_breaks(section)?;
+    for line in section.split(|byte| *byte == LF) {
+        let line = trim_cr(line);
+        if line.is_empty() {
+            break;
+        }
+        if line[0] == SP || line[0] == HTAB {
+            return Err(ParseError::ObsoleteLineFolding);
+        }
+        let (_, value) = header_field(line)?;
+        if !value.is_ascii() {
+            return Err(ParseError::NonAsciiHeaderValue);
+        }
+    }
+    Ok(())
+}
+
+/// Parses the size in a chunk size line, without its line break. Chunk extensions are ignored.
+///
+/// # Errors
+/// Returns `InvalidChunkSize` when the size is not a hexadecimal number which fits in an
+/// `usize`.
+pub fn chunk_size(line: &[u8]) -> Result<usize, ParseError> {
+    let (size, _) = match find_byte(line, b';') {
+        Some(index) => line.split_at(index),
+        None => (line, &[][..]),
+    };
+    let size = trim_end_whitespace(size);
+    if size.is_empty() {
+        return Err(ParseError::InvalidChunkSize);
+    }
+    size.iter().try_fold(0usize, |value, byte| {
+        let digit = (*byte as char)
+            .to_digit(16)
+            .ok_or(ParseError::InvalidChunkSize)?;
+        value
+            .checked_mul(16)
+            .and_then(|value| value.checked_add(digit as usize))
+            .ok_or(ParseError::InvalidChunkSize)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_byte() {
+        // The needle is found at all the offsets in a word, and in the last bytes which don't
+        // fill a word. The tests build without `alloc` too, so the buffers are arrays.
+        for len in 0..4 * WORD_LEN {
+            for index in 0..len {
+                let mut bytes = [b'a'; 4 * WORD_LEN];
+                bytes[index] = LF;
+                bytes[len - 1] = LF;
+                assert_eq!(find_byte(&bytes[..len], LF), Some(index));
+            }
+            assert_eq!(find_byte(&[b'a'; 4 * WORD_LEN][..len], LF), None);
+        }
+
+        // All the values of the bytes, including the ones with the high bit set.
+        let mut bytes = [0u8; 256];
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        for needle in 0..=255 {
+            assert_eq!(find_byte(&bytes, needle), Some(usize::from(needle)));
+        }
+        assert_eq!(find_byte(&bytes[1..], 0), None);
+        assert_eq!(
+            find_byte(b"\x80\x80\x80\x80\x80\x80\x80\x80\x80\n", LF),
+            Some(9)
+        );
+        assert_eq!(
+            find_byte(b"\xff\xfe\xff\xfe\xff\xfe\xff\xfe\xff", 0xfe),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_request_line() {
+        assert_eq!(
+            request_line(b"GET /home HTTP/1.0\r\n"),
+            (&b"GET"[..], &b"/home"[..], &b"HTTP/1.0"[..])
+        );
+        assert_eq!(
+            request_line(b"GET /home HTTP/1.1\nHost: localhost\n"),
+            (&b"GET"[..], &b"/home"[..], &b"HTTP/1.1"[..])
+        );
+        assert_eq!(
+            request_line(b"GET /home HTTP/1.1"),
+            (&b"GET"[..], &b"/home"[..], &b""[..])
+        );
+        assert_eq!(request_line(b""), (&b""[..], &b""[..], &b""[..]));
+    }
+
+    #[test]
+    fn test_header_field() {
+        assert_eq!(
+            header_field(b"Content-Type: \t text/plain \t"),
+            Ok((&b"Content-Type"[..], &b"text/plain"[..]))
+        );
+        assert_eq!(header_field(b"X-Empty:"), Ok((&b"X-Empty"[..], &b""[..])));
+        assert_eq!(
+            header_field(b"malformed"),
+            Err(ParseError::MissingHeaderSeparator)
+        );
+        assert_eq!(header_field(b": value"), Err(ParseError::InvalidHeaderName));
+        assert_eq!(
+            header_field(b"Content-Length : 1"),
+            Err(ParseError::InvalidHeaderName)
+        );
+        assert_eq!(
+            header_field(b"X-Nul: a\0b"),
+            Err(ParseError::InvalidHeaderValue)
+        );
+    }
+
+    #[test]
+    fn test_header_section_len() {
+        assert_eq!(header_section_len(b"\r\nbody"), Some(2));
+        assert_eq!(header_section_len(b"A: 1\r\nB: 2\n\r\nbody"), Some(13));
+        assert_eq!(header_section_len(b"A: 1\n\n"), Some(6));
+        // The section has to end with an empty line.
+        assert_eq!(header_section_len(b"A: 1\r\n"), None);
+        assert_eq!(header_section_len(b"A: 1\r\n\r"), None);
+        assert_eq!(header_section_len(b""), None);
+    }
+
+    #[test]
+    fn test_header_fields() {
+        let mut fields = HeaderFields::new(b" skipped\r\nA: 1\nB: 2\r\n\r\nC: 3\r\n");
+        assert_eq!(fields.next(), Some(Ok((&b"A"[..], &b"1"[..]))));
+        assert_eq!(fields.next(), Some(Ok((&b"B"[..], &b"2"[..]))));
+        assert_eq!(fields.next(), None);
+        assert_eq!(fields.next(), None);
+
+        // The last line break can be missing.
+        let mut fields = HeaderFields::new(b"A: 1");
+        assert_eq!(fields.next(), Some(Ok((&b"A"[..], &b"1"[..]))));
+        assert_eq!(fields.next(), None);
+
+        // The iteration stops after an error.
+        let mut fields = HeaderFields::new(b"A: 1\r\n folded\r\nB: 2\r\n");
+        assert_eq!(fields.next(), Some(Ok((&b"A"[..], &b"1"[..]))));
+        assert_eq!(fields.next(), Some(Err(ParseError::ObsoleteLineFolding)));
+        assert_eq!(fields.next(), None);
+
+        assert_eq!(HeaderFields::new(b"").next(), None);
+    }
+
+    #[test]
+    fn test_strict_request_line() {
+        assert_eq!(strict_request_line(b"GET /home HTTP/1.1\r\nA: 1\n"), Ok(()));
+        assert_eq!(strict_request_line(b"M-SEARCH * HTTP/1.1\r\n"), Ok(()));
+        for &(line, error) in &[
+            (&b"GET /home HTTP/1.1\n"[..], ParseError::BareLineFeed),
+            (b"GET /home\r HTTP/1.1\r\n", ParseError::BareCarriageReturn),
+            (b"GET /home\0 HTTP/1.1\r\n", ParseError::NulByte),
+            (b"GET /home HTTP/1.1", ParseError::MalformedRequestLine),
+            (b"GET /home HTTP/1.1\r", ParseError::MalformedRequestLine),
+            (b"GET  /home HTTP/1.1\r\n", ParseError::MalformedRequestLine),
+            (b" GET /home HTTP/1.1\r\n", ParseError::MalformedRequestLine),
+            (b"GET /home HTTP/1.1 \r\n", ParseError::MalformedRequestLine),
+            (b"GET\t/home HTTP/1.1\r\n", ParseError::MalformedRequestLine),
+            (b"GET /home\tHTTP/1.1\r\n", ParseError::MalformedRequestLine),
+            (
+                b"GET /home /admin HTTP/1.1\r\n",
+                ParseError::MalformedRequestLine,
+            ),
+            (b"G(T /home HTTP/1.1\r\n", ParseError::MalformedRequestLine),
+            (
+                b"GET /h\xc3\xa9 HTTP/1.1\r\n",
+                ParseError::MalformedRequestLine,
+            ),
+        ] {
+            assert_eq!(strict_request_line(line), Err(error), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_strict_header_section() {
+        assert_eq!(
+            strict_header_section(b"A: 1\r\nB:\t2 \r\n\r\nbody\n\0"),
+            Ok(())
+        );
+        assert_eq!(strict_header_section(b"\r\n"), Ok(()));
+        // The section can be incomplete.
+        assert_eq!(strict_header_section(b"A: 1\r\nB: 2"), Ok(()));
+        for &(section, error) in &[
+            (&b"A: 1\nB: 2\r\n\r\n"[..], ParseError::BareLineFeed),
+            (b"A: 1\r\n\n", ParseError::BareLineFeed),
+            (b"A: 1\rB: 2\r\n\r\n", ParseError::BareCarriageReturn),
+            (b"A: 1\0\r\n\r\n", ParseError::NulByte),
+            (b" A: 1\r\n\r\n", ParseError::ObsoleteLineFolding),
+            (b"A: 1\r\n\tB: 2\r\n\r\n", ParseError::ObsoleteLineFolding),
+            (b"A : 1\r\n\r\n", ParseError::InvalidHeaderName),
+            (b"A: \x7f\r\n\r\n", ParseError::InvalidHeaderValue),
+            (b"A: caf\xc3\xa9\r\n\r\n", ParseError::NonAsciiHeaderValue),
+        ] {
+            assert_eq!(strict_header_section(section), Err(error), "{:?}", section);
+        }
+    }
+
+    #[test]
+    fn test_chunk_size() {
+        assert_eq!(chunk_size(b"0"), Ok(0));
+        assert_eq!(chunk_size(b"1a"), Ok(26));
+        assert_eq!(chunk_size(b"FF ;name=value"), Ok(255));
+        for line in &[
+            &b""[..],
+            b";ext",
+            b"x",
+            b"-1",
+            b"+1",
+            b" 1",
+            b"1 2",
+            b"ffffffffffffffffffff",
+        ] {
+            assert_eq!(chunk_size(line), Err(ParseError::InvalidChunkSize));
+        }
+    }
+}
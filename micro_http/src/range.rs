@@ -0,0 +1,297 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Support for [range requests](https://tools.ietf.org/html/rfc7233), which let clients
+//! download a part of a large resource, for example to resume an interrupted download.
+//!
+//! Only single byte ranges are supported. Requests for multiple ranges, or with a `Range` header
+//! which can't be parsed, get the whole resource, as allowed by the RFC.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::{range, Body, Request, StatusCode};
+//!
+//! let request = Request::try_from(b"GET /log HTTP/1.1\r\nRange: bytes=-4\r\n\r\n").unwrap();
+//! let response = range::response(&request, Body::new("the log tail"));
+//! assert!(response.status() == StatusCode::PartialContent);
+//! assert_eq!(response.body().unwrap(), Body::new("tail"));
+//! ```
+
+use std::io::{Error as IoError, Read, Seek, SeekFrom};
+
+use common::Body;
+use headers::Header;
+use request::Request;
+use response::{Response, StatusCode};
+
+// The only range unit defined by the RFC.
+const BYTES_UNIT: &str = "bytes";
+
+/// A byte range, as requested with the `Range` header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteRange {
+    /// The bytes from the first position to the last position, both included.
+    FromTo(u64, u64),
+    /// The bytes from a position to the end.
+    From(u64),
+    /// The given number of bytes at the end.
+    Suffix(u64),
+}
+
+fn parse_position(bytes: &str) -> Option<u64> {
+    if bytes.is_empty() || !bytes.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    bytes.parse().ok()
+}
+
+impl ByteRange {
+    /// Parses the value of a `Range` header asking for a single byte range.
+    ///
+    /// Returns `None` when the value is invalid or asks for multiple ranges.
+    pub fn parse(value: &str) -> Option<Self> {
+        let separator = value.find('=')?;
+        if !value[..separator].trim().eq_ignore_ascii_case(BYTES_UNIT) {
+            return None;
+        }
+
+        let range = value[separator + 1..].trim();
+        let dash = range.find('-')?;
+        let (first, last) = (&range[..dash], &range[dash + 1..]);
+        match (parse_position(first), parse_position(last)) {
+            (Some(first), Some(last)) if first <= last => Some(ByteRange::FromTo(first, last)),
+            (Some(first), None) if last.is_empty() => Some(ByteRange::From(first)),
+            (None, Some(len)) if first.is_empty() => Some(ByteRange::Suffix(len)),
+            _ => None,
+        }
+    }
+
+    /// Returns the first and last positions, both included, of the range within a resource of
+    /// `len` bytes, or `None` when none of its bytes are part of the resource.
+    pub fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::FromTo(first, last) if first < len => Some((first, last.min(len - 1))),
+            ByteRange::From(first) if first < len => Some((first, len - 1)),
+            ByteRange::Suffix(suffix_len) if suffix_len > 0 && len > 0 => {
+                Some((len - suffix_len.min(len), len - 1))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Builds the response to `request` for a resource of `len` bytes, without its body. Returns it
+// with the position and the number of the bytes of the resource it carries, or `None` when the
+// range is not satisfiable.
+fn response_head(request: &Request, len: u64) -> (Response, Option<(u64, u64)>) {
+    let range = request.header(Header::Range).and_then(ByteRange::parse);
+
+    let (mut response, span) = match range.map(|range| range.resolve(len)) {
+        Some(Some((first, last))) => {
+            let mut response = Response::new(request.http_version(), StatusCode::PartialContent);
+            response.add_header(
+                Header::ContentRange,
+                format!("{} {}-{}/{}", BYTES_UNIT, first, last, len),
+            );
+            (response, Some((first, last - first + 1)))
+        }
+        Some(None) => {
+            let mut response =
+                Response::new(request.http_version(), StatusCode::RangeNotSatisfiable);
+            response.add_header(Header::ContentRange, format!("{} */{}", BYTES_UNIT, len));
+            (response, None)
+        }
+        None => (
+            Response::new(request.http_version(), StatusCode::OK),
+            Some((0, len)),
+        ),
+    };
+    response.add_header(Header::AcceptRanges, String::from(BYTES_UNIT));
+    (response, span)
+}
+
+/// Builds the response to `request` for a resource with the content of `body`, honoring the
+/// `Range` header of the request.
+///
+/// The response is `206 Partial Content` with the requested bytes when the range is
+/// satisfiable, `416 Range Not Satisfiable` when it is not, or `200 OK` with the whole `body`
+/// otherwise.
+pub fn response(request: &Request, body: Body) -> Response {
+    let (mut response, span) = response_head(request, body.len() as u64);
+    match span {
+        Some((0, len)) if len == body.len() as u64 => response.set_body(body),
+        Some((first, len)) => {
+            let first = first as usize;
+            response.set_body(Body::new(&body.raw()[first..first + len as usize]))
+        }
+        None => (),
+    }
+    response
+}
+
+/// Builds the response to `request` for a resource read from `source`, such as a file, like
+/// `response` does for a resource held in memory.
+///
+/// The requested bytes are streamed from `source` when the response is written with
+/// `ResponseWriter::push_streamed`, so that a large resource is not buffered whole. HTTP/1.0
+/// does not support streamed bodies though, so they are read at once for such requests.
+///
+/// # Errors
+/// Returns the errors of seeking in `source`, and of reading it for HTTP/1.0 requests.
+pub fn stream_response<R: Read + Seek + Send + 'static>(
+    request: &Request,
+    mut source: R,
+) -> Result<Response, IoError> {
+    let len = source.seek(SeekFrom::End(0))?;
+    let (mut response, span) = response_head(request, len);
+    if let Some((first, len)) = span {
+        source.seek(SeekFrom::Start(first))?;
+        let mut part = source.take(len);
+        if !response.http_version().supports_chunked() {
+            let mut body = Vec::new();
+            part.read_to_end(&mut body)?;
+            response.set_body(Body::new(body));
+        } else {
+            response.set_body_stream(part);
+        }
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chunked::ChunkedDecoder;
+    use parser;
+    use std::io::Cursor;
+    use writer::ResponseWriter;
+
+    fn request_with_range(range: &str) -> Vec<u8> {
+        format!("GET /memory HTTP/1.1\r\nRange: {}\r\n\r\n", range).into_bytes()
+    }
+
+    fn write_response(response: &Response) -> String {
+        let mut buf = Vec::new();
+        response.write_all(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            ByteRange::parse("bytes=0-499"),
+            Some(ByteRange::FromTo(0, 499))
+        );
+        assert_eq!(
+            ByteRange::parse("Bytes = 10-10"),
+            Some(ByteRange::FromTo(10, 10))
+        );
+        assert_eq!(ByteRange::parse("bytes=500-"), Some(ByteRange::From(500)));
+        assert_eq!(ByteRange::parse("bytes=-500"), Some(ByteRange::Suffix(500)));
+
+        for value in &[
+            "",
+            "bytes",
+            "bytes=",
+            "bytes=-",
+            "bytes=10",
+            "bytes=10-9",
+            "bytes=+1-2",
+            "bytes=0-1,5-6",
+            "bytes=a-b",
+            "items=0-1",
+            "bytes=0-18446744073709551616",
+        ] {
+            assert_eq!(ByteRange::parse(value), None);
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(ByteRange::FromTo(0, 499).resolve(1000), Some((0, 499)));
+        assert_eq!(ByteRange::FromTo(500, 2000).resolve(1000), Some((500, 999)));
+        assert_eq!(ByteRange::FromTo(1000, 2000).resolve(1000), None);
+        assert_eq!(ByteRange::From(999).resolve(1000), Some((999, 999)));
+        assert_eq!(ByteRange::From(1000).resolve(1000), None);
+        assert_eq!(ByteRange::Suffix(10).resolve(1000), Some((990, 999)));
+        assert_eq!(ByteRange::Suffix(2000).resolve(1000), Some((0, 999)));
+        assert_eq!(ByteRange::Suffix(0).resolve(1000), None);
+        assert_eq!(ByteRange::Suffix(10).resolve(0), None);
+    }
+
+    #[test]
+    fn test_response() {
+        let body = Body::new("0123456789");
+
+        let request_bytes = request_with_range("bytes=2-4");
+        let request = Request::try_from(&request_bytes).unwrap();
+        let http_response = response(&request, body.clone());
+        assert!(http_response.status() == StatusCode::PartialContent);
+        assert_eq!(http_response.body().unwrap(), Body::new("234"));
+        let response_str = write_response(&http_response);
+        assert!(response_str.contains("Content-Range: bytes 2-4/10\r\n"));
+        assert!(response_str.contains("Content-Length: 3\r\n"));
+        assert!(response_str.contains("Accept-Ranges: bytes\r\n"));
+
+        let request_bytes = request_with_range("bytes=10-");
+        let request = Request::try_from(&request_bytes).unwrap();
+        let http_response = response(&request, body.clone());
+        assert!(http_response.status() == StatusCode::RangeNotSatisfiable);
+        assert!(http_response.body().is_none());
+        assert!(write_response(&http_response).contains("Content-Range: bytes */10\r\n"));
+
+        // Multiple ranges are not supported, so the whole body is sent.
+        let request_bytes = request_with_range("bytes=0-1,3-4");
+        let request = Request::try_from(&request_bytes).unwrap();
+        let http_response = response(&request, body.clone());
+        assert!(http_response.status() == StatusCode::OK);
+        assert_eq!(http_response.body().unwrap(), body);
+
+        let request = Request::try_from(b"GET /memory HTTP/1.0\r\n").unwrap();
+        let http_response = response(&request, body.clone());
+        assert!(http_response.status() == StatusCode::OK);
+        assert_eq!(http_response.http_version(), request.http_version());
+        assert_eq!(http_response.body().unwrap(), body);
+        assert!(write_response(&http_response).contains("Accept-Ranges: bytes\r\n"));
+    }
+
+    #[test]
+    fn test_stream_response() {
+        let content: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+
+        let request_bytes = request_with_range("bytes=5000-");
+        let request = Request::try_from(&request_bytes).unwrap();
+        let http_response = stream_response(&request, Cursor::new(content.clone())).unwrap();
+        assert!(http_response.status() == StatusCode::PartialContent);
+        assert!(http_response.body().is_none());
+
+        let mut writer = ResponseWriter::new(8192);
+        writer.push_streamed(http_response).unwrap();
+        let mut connection = Vec::new();
+        writer.write_to(&mut connection).unwrap();
+        assert!(writer.is_empty());
+        let head_len = parser::header_section_len(&connection).unwrap();
+        let head = String::from_utf8_lossy(&connection[..head_len]);
+        assert!(head.contains("Content-Range: bytes 5000-9999/10000\r\n"));
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        let mut decoded = Vec::new();
+        ChunkedDecoder::new()
+            .decode(&connection[head_len..], &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, &content[5000..]);
+
+        let request_bytes = request_with_range("bytes=10000-");
+        let request = Request::try_from(&request_bytes).unwrap();
+        let http_response = stream_response(&request, Cursor::new(content.clone())).unwrap();
+        assert!(http_response.status() == StatusCode::RangeNotSatisfiable);
+        assert!(write_response(&http_response).contains("Content-Range: bytes */10000\r\n"));
+
+        // The requested bytes are read at once for HTTP/1.0.
+        let request =
+            Request::try_from(b"GET /memory HTTP/1.0\r\nRange: bytes=-100\r\n\r\n").unwrap();
+        let http_response = stream_response(&request, Cursor::new(content.clone())).unwrap();
+        assert!(http_response.status() == StatusCode::PartialContent);
+        assert_eq!(http_response.body().unwrap(), Body::new(&content[9900..]));
+    }
+}
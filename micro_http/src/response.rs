@@ -1,43 +1,104 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::io::{Error as WriteError, Write};
+use std::fmt;
+use std::io::{Error as WriteError, Read, Write};
 
-use ascii::{CR, LF, SP};
-use common::{Body, Version};
-use headers::{Header, Headers, MediaType};
+use ascii::{COLON, CR, LF, SP};
+use common::{date, Body, RequestError, Version};
+use headers::{has_token, Header, Headers, MediaType};
+use request::Request;
 
 /// Wrapper over a response status code.
 ///
 /// The status code is defined as specified in the
 /// [RFC](https://tools.ietf.org/html/rfc7231#section-6).
 #[allow(dead_code)]
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum StatusCode {
-    /// 100, OK
+    /// 101, Switching Protocols
+    SwitchingProtocols,
+    /// 200, OK
     OK,
+    /// 206, Partial Content
+    PartialContent,
+    /// 304, Not Modified
+    NotModified,
     /// 400, Bad Request
     BadRequest,
+    /// 401, Unauthorized
+    Unauthorized,
+    /// 403, Forbidden
+    Forbidden,
     /// 404, Not Found
     NotFound,
+    /// 405, Method Not Allowed
+    MethodNotAllowed,
+    /// 408, Request Timeout
+    RequestTimeout,
+    /// 413, Payload Too Large
+    PayloadTooLarge,
+    /// 414, URI Too Long
+    UriTooLong,
+    /// 416, Range Not Satisfiable
+    RangeNotSatisfiable,
+    /// 431, Request Header Fields Too Large
+    RequestHeaderFieldsTooLarge,
     /// 500, Internal Server Error
     InternalServerError,
     /// 501, Not Implemented
     NotImplemented,
+    /// 505, HTTP Version Not Supported
+    HttpVersionNotSupported,
 }
 
 impl StatusCode {
     fn raw(&self) -> &'static [u8; 3] {
         match self {
+            StatusCode::SwitchingProtocols => b"101",
             StatusCode::OK => b"200",
+            StatusCode::PartialContent => b"206",
+            StatusCode::NotModified => b"304",
             StatusCode::BadRequest => b"400",
+            StatusCode::Unauthorized => b"401",
+            StatusCode::Forbidden => b"403",
             StatusCode::NotFound => b"404",
+            StatusCode::MethodNotAllowed => b"405",
+            StatusCode::RequestTimeout => b"408",
+            StatusCode::PayloadTooLarge => b"413",
+            StatusCode::UriTooLong => b"414",
+            StatusCode::RangeNotSatisfiable => b"416",
+            StatusCode::RequestHeaderFieldsTooLarge => b"431",
             StatusCode::InternalServerError => b"500",
             StatusCode::NotImplemented => b"501",
+            StatusCode::HttpVersionNotSupported => b"505",
+        }
+    }
+
+    fn reason_phrase(&self) -> &'static [u8] {
+        match self {
+            StatusCode::SwitchingProtocols => b"Switching Protocols",
+            StatusCode::OK => b"OK",
+            StatusCode::PartialContent => b"Partial Content",
+            StatusCode::NotModified => b"Not Modified",
+            StatusCode::BadRequest => b"Bad Request",
+            StatusCode::Unauthorized => b"Unauthorized",
+            StatusCode::Forbidden => b"Forbidden",
+            StatusCode::NotFound => b"Not Found",
+            StatusCode::MethodNotAllowed => b"Method Not Allowed",
+            StatusCode::RequestTimeout => b"Request Timeout",
+            StatusCode::PayloadTooLarge => b"Payload Too Large",
+            StatusCode::UriTooLong => b"URI Too Long",
+            StatusCode::RangeNotSatisfiable => b"Range Not Satisfiable",
+            StatusCode::RequestHeaderFieldsTooLarge => b"Request Header Fields Too Large",
+            StatusCode::InternalServerError => b"Internal Server Error",
+            StatusCode::NotImplemented => b"Not Implemented",
+            StatusCode::HttpVersionNotSupported => b"HTTP Version Not Supported",
         }
     }
 }
 
+#[derive(Debug)]
 struct StatusLine {
     http_version: Version,
     status_code: StatusCode,
@@ -55,20 +116,33 @@ impl StatusLine {
         buf.write_all(self.http_version.raw())?;
         buf.write_all(&[SP])?;
         buf.write_all(self.status_code.raw())?;
-        buf.write_all(&[SP, CR, LF])?;
+        buf.write_all(&[SP])?;
+        buf.write_all(self.status_code.reason_phrase())?;
+        buf.write_all(&[CR, LF])?;
 
         Ok(())
     }
 }
 
+// The source of a body which is streamed in chunks.
+pub(crate) struct BodyStream(pub(crate) Box<Read + Send>);
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BodyStream")
+    }
+}
+
 /// Wrapper over an HTTP Response.
 ///
 /// The Response is created using a `Version` and a `StatusCode`. When creating a Response object,
 /// the body is initialize to `None`. The body can be updated with a call to `set_body`.
+#[derive(Debug)]
 pub struct Response {
     status_line: StatusLine,
     headers: Headers,
     body: Option<Body>,
+    body_stream: Option<BodyStream>,
 }
 
 impl Response {
@@ -78,9 +152,30 @@ impl Response {
             status_line: StatusLine::new(http_version, status_code),
             headers: Headers::default(),
             body: None,
+            body_stream: None,
         };
     }
 
+    /// Creates a new HTTP `Response` to `request`, with an empty body.
+    ///
+    /// The `Response` has the HTTP version of the `request`, and a `Connection` header when
+    /// the connection is not going to be kept alive, or closed, as the version implies.
+    pub fn for_request(request: &Request, status_code: StatusCode) -> Response {
+        let mut response = Response::new(request.http_version(), status_code);
+        response.set_keep_alive(request.keep_alive());
+        response
+    }
+
+    /// Creates the HTTP `Response` to a request which could not be parsed because of `error`,
+    /// with the status code of the error and a body describing it.
+    ///
+    /// The request could not be parsed, so the HTTP version of the response has to be given.
+    pub fn for_error(http_version: Version, error: &RequestError) -> Response {
+        let mut response = Response::new(http_version, error.status_code());
+        response.set_body(Body::new(error.message()));
+        response
+    }
+
     /// Updates the body of the `Response`.
     ///
     /// This function has side effects because it also updates the headers:
@@ -96,6 +191,105 @@ impl Response {
         self.body = Some(body);
     }
 
+    /// Sets the media type of the body of the `Response`, which is "text/plain" by default.
+    ///
+    /// The media type is set by `set_body`, so it has to be set afterwards.
+    pub fn set_content_type(&mut self, media_type: MediaType) {
+        self.headers
+            .add(Header::ContentType, String::from(media_type.as_str()));
+    }
+
+    /// Makes the `Response` announce a chunked body, declaring the names of the trailers sent
+    /// after it, if any.
+    ///
+    /// The body itself is not part of the `Response`: it has to be written with a
+    /// `ChunkedWriter` after the `Response`. Any body set before is dropped.
+    ///
+    /// Returns `false`, leaving the `Response` unchanged, when its HTTP version does not support
+    /// chunked bodies.
+    pub fn set_chunked_body(&mut self, trailer_names: &[&str]) -> bool {
+        if !self.http_version().supports_chunked() {
+            return false;
+        }
+
+        self.body = None;
+        self.body_stream = None;
+        self.headers.remove(Header::ContentLength);
+        self.headers
+            .add(Header::TransferEncoding, String::from("chunked"));
+        if trailer_names.is_empty() {
+            self.headers.remove(Header::Trailer);
+        } else {
+            self.headers.add(Header::Trailer, trailer_names.join(", "));
+        }
+        true
+    }
+
+    /// Makes the `Response` stream its body from `source`, in chunks, so that a body of unknown
+    /// length is not buffered whole.
+    ///
+    /// The chunks are read from `source` as the connection accepts them, until it returns 0,
+    /// when the `Response` is written with `ResponseWriter::push_streamed`. `write_all` only
+    /// writes the status line and the headers.
+    ///
+    /// Returns `false`, leaving the `Response` unchanged, when its HTTP version does not support
+    /// chunked bodies.
+    pub fn set_body_stream<R: Read + Send + 'static>(&mut self, source: R) -> bool {
+        if !self.set_chunked_body(&[]) {
+            return false;
+        }
+        self.body_stream = Some(BodyStream(Box::new(source)));
+        true
+    }
+
+    // Returns the source of the streamed body, which is written after the `Response`.
+    pub(crate) fn take_body_stream(&mut self) -> Option<BodyStream> {
+        self.body_stream.take()
+    }
+
+    /// Sets the `Server` header of the `Response`, which names the software handling the
+    /// requests.
+    pub fn set_server(&mut self, server: &str) {
+        self.headers.add(Header::Server, String::from(server));
+    }
+
+    // Sets the `Server` header like `set_server`, unless the `Response` already has one.
+    pub(crate) fn set_default_server(&mut self, server: &str) {
+        if self.headers.get(Header::Server).is_none() {
+            self.set_server(server);
+        }
+    }
+
+    /// Sets whether the connection is kept alive after the `Response`, with a `Connection`
+    /// header when the HTTP version of the `Response` implies otherwise.
+    pub fn set_keep_alive(&mut self, keep_alive: bool) {
+        match (keep_alive, self.http_version().keep_alive_by_default()) {
+            (true, false) => self
+                .headers
+                .add(Header::Connection, String::from("keep-alive")),
+            (false, true) => self.headers.add(Header::Connection, String::from("close")),
+            _ => self.headers.remove(Header::Connection),
+        }
+    }
+
+    /// Returns `true` when the connection is kept alive after the `Response`, as its
+    /// `Connection` header or its HTTP version implies.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.headers.get(Header::Connection);
+        if has_token(connection, "close") {
+            false
+        } else if has_token(connection, "keep-alive") {
+            true
+        } else {
+            self.http_version().keep_alive_by_default()
+        }
+    }
+
+    /// Adds `header` to the `Response`, replacing its previous value.
+    pub(crate) fn add_header(&mut self, header: Header, value: String) {
+        self.headers.add(header, value);
+    }
+
     fn write_body<T: Write>(&self, mut buf: T) -> Result<(), WriteError> {
         if let Some(ref body) = self.body {
             buf.write_all(body.raw())?;
@@ -103,12 +297,47 @@ impl Response {
         Ok(())
     }
 
-    /// Writes the content of the `Response` to the specified `buf`.
+    // HTTP/1.1 servers with a clock must send the `Date` header, so it is added with the current
+    // date unless the `Response` already has one.
+    fn write_date<T: Write>(&self, mut buf: T) -> Result<(), WriteError> {
+        if self.headers.get(Header::Date).is_none() {
+            buf.write_all(Header::Date.raw())?;
+            buf.write_all(&[COLON, SP])?;
+            date::with_now(|date| buf.write_all(date))?;
+            buf.write_all(&[CR, LF])?;
+        }
+        Ok(())
+    }
+
+    // A response without a body still needs a `Content-Length`, otherwise the client reads
+    // until the connection is closed. The responses switching protocols and the chunked ones have
+    // no such header, and neither have the `304 Not Modified` ones, where it would be the length
+    // of the cached body.
+    fn write_empty_content_length<T: Write>(&self, mut buf: T) -> Result<(), WriteError> {
+        if self.body.is_none()
+            && self.status() != StatusCode::SwitchingProtocols
+            && self.status() != StatusCode::NotModified
+            && self.headers.get(Header::ContentLength).is_none()
+            && self.headers.get(Header::TransferEncoding).is_none()
+        {
+            buf.write_all(Header::ContentLength.raw())?;
+            buf.write_all(&[COLON, SP, b'0', CR, LF])?;
+        }
+        Ok(())
+    }
+
+    /// Writes the content of the `Response` to the specified `buf`, which can be any writer such
+    /// as a socket or a buffer.
+    ///
+    /// A `Date` header with the current date is written along with the other headers, and a
+    /// `Content-Length` of 0 when the `Response` has no body.
     ///
     /// # Errors
     /// Returns an error when the buffer is not large enough.
     pub fn write_all<T: Write>(&self, mut buf: &mut T) -> Result<(), WriteError> {
         self.status_line.write_all(&mut buf)?;
+        self.write_date(&mut buf)?;
+        self.write_empty_content_length(&mut buf)?;
         self.headers.write_all(&mut buf)?;
         self.write_body(&mut buf)?;
 
@@ -146,19 +375,23 @@ mod tests {
         assert_eq!(response.body().unwrap(), Body::new(body));
         assert_eq!(response.http_version(), Version::Http10);
 
-        // Headers can be in either order.
-        let expected_response_1: &'static [u8] = b"HTTP/1.0 200 \r\n\
+        // The Date header comes first, the other headers can be in either order.
+        let expected_response_1: &'static [u8] = b"HTTP/1.0 200 OK\r\n\
+            Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
             Content-Type: text/plain\r\n\
             Content-Length: 14\r\n\r\n\
             This is a test";
 
-        let expected_response_2: &'static [u8] = b"HTTP/1.0 200 \r\n\
+        let expected_response_2: &'static [u8] = b"HTTP/1.0 200 OK\r\n\
+            Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
             Content-Length: 14\r\n\
             Content-Type: text/plain\r\n\r\n\
             This is a test";
 
-        let mut response_buf: [u8; 77] = [0; 77];
+        let mut response_buf: [u8; 116] = [0; 116];
         assert!(response.write_all(&mut response_buf.as_mut()).is_ok());
+        // Replace the current date, which the test can't predict.
+        response_buf[23..52].copy_from_slice(b"Sun, 06 Nov 1994 08:49:37 GMT");
         assert!(
             response_buf.as_ref() == expected_response_1
                 || response_buf.as_ref() == expected_response_2
@@ -169,12 +402,162 @@ mod tests {
         assert!(response.write_all(&mut response_buf.as_mut()).is_err());
     }
 
+    #[test]
+    fn test_write_empty_response() {
+        let mut response = Response::new(Version::Http11, StatusCode::NotFound);
+        response.add_header(Header::Date, String::from("Sun, 06 Nov 1994 08:49:37 GMT"));
+        let mut response_buf = Vec::new();
+        response.write_all(&mut response_buf).unwrap();
+        assert_eq!(
+            response_buf,
+            b"HTTP/1.1 404 Not Found\r\n\
+              Content-Length: 0\r\n\
+              Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n"
+                .to_vec()
+        );
+
+        // The responses switching protocols have no Content-Length.
+        let response = Response::new(Version::Http11, StatusCode::SwitchingProtocols);
+        let mut response_buf = Vec::new();
+        response.write_all(&mut response_buf).unwrap();
+        let response_str = String::from_utf8(response_buf).unwrap();
+        assert!(response_str.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(!response_str.contains("Content-Length"));
+    }
+
+    #[test]
+    fn test_date_and_server() {
+        let mut response = Response::new(Version::Http11, StatusCode::OK);
+        response.set_server("Firecracker");
+        response.add_header(Header::Date, String::from("Sun, 06 Nov 1994 08:49:37 GMT"));
+
+        let mut response_buf = Vec::new();
+        response.write_all(&mut response_buf).unwrap();
+        let response_str = String::from_utf8(response_buf).unwrap();
+        assert!(response_str.contains("Server: Firecracker\r\n"));
+        // The Date set on the response is not overwritten.
+        assert_eq!(response_str.matches("Date: ").count(), 1);
+        assert!(response_str.contains("Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n"));
+
+        // Neither is the Server.
+        response.set_default_server("micro_http");
+        let mut response_buf = Vec::new();
+        response.write_all(&mut response_buf).unwrap();
+        assert!(String::from_utf8(response_buf)
+            .unwrap()
+            .contains("Server: Firecracker\r\n"));
+        let mut response = Response::new(Version::Http11, StatusCode::OK);
+        response.set_default_server("micro_http");
+        let mut response_buf = Vec::new();
+        response.write_all(&mut response_buf).unwrap();
+        assert!(String::from_utf8(response_buf)
+            .unwrap()
+            .contains("Server: micro_http\r\n"));
+    }
+
+    #[test]
+    fn test_set_content_type() {
+        let mut response = Response::new(Version::Http11, StatusCode::OK);
+        response.set_body(Body::new("{}"));
+        response.set_content_type(MediaType::ApplicationJson);
+        let mut response_buf = Vec::new();
+        response.write_all(&mut response_buf).unwrap();
+        let response_str = String::from_utf8(response_buf).unwrap();
+        assert!(response_str.contains("Content-Type: application/json\r\n"));
+        assert!(!response_str.contains("text/plain"));
+    }
+
+    #[test]
+    fn test_set_chunked_body() {
+        let mut response = Response::new(Version::Http11, StatusCode::OK);
+        response.set_body(Body::new("This is a test"));
+        assert!(response.set_chunked_body(&["Digest", "X-Checksum"]));
+        assert!(response.body().is_none());
+
+        let mut response_buf = Vec::new();
+        response.write_all(&mut response_buf).unwrap();
+        let response_str = String::from_utf8(response_buf).unwrap();
+        assert!(response_str.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(response_str.contains("Trailer: Digest, X-Checksum\r\n"));
+        assert!(!response_str.contains("Content-Length"));
+        assert!(response_str.ends_with("\r\n\r\n"));
+
+        assert!(response.set_chunked_body(&[]));
+        let mut response_buf = Vec::new();
+        response.write_all(&mut response_buf).unwrap();
+        assert!(!String::from_utf8(response_buf).unwrap().contains("Trailer"));
+
+        // HTTP/1.0 does not support chunked bodies.
+        let mut response = Response::new(Version::Http10, StatusCode::OK);
+        response.set_body(Body::new("This is a test"));
+        assert!(!response.set_chunked_body(&[]));
+        assert_eq!(response.body().unwrap(), Body::new("This is a test"));
+    }
+
+    #[test]
+    fn test_for_request() {
+        let connection_header = |request_bytes| {
+            let request = Request::try_from(request_bytes).unwrap();
+            let response = Response::for_request(&request, StatusCode::OK);
+            assert_eq!(response.http_version(), request.http_version());
+            response.headers.get(Header::Connection).map(String::from)
+        };
+
+        assert_eq!(connection_header(b"GET / HTTP/1.0\r\n\r\n"), None);
+        assert_eq!(
+            connection_header(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"),
+            Some(String::from("keep-alive"))
+        );
+        assert_eq!(connection_header(b"GET / HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(
+            connection_header(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n"),
+            Some(String::from("close"))
+        );
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let mut response = Response::new(Version::Http11, StatusCode::OK);
+        assert!(response.keep_alive());
+        response.set_keep_alive(false);
+        assert!(!response.keep_alive());
+        assert_eq!(response.headers.get(Header::Connection), Some("close"));
+        response.set_keep_alive(true);
+        assert!(response.keep_alive());
+        assert_eq!(response.headers.get(Header::Connection), None);
+
+        let mut response = Response::new(Version::Http10, StatusCode::OK);
+        assert!(!response.keep_alive());
+        response.set_keep_alive(true);
+        assert!(response.keep_alive());
+        assert_eq!(response.headers.get(Header::Connection), Some("keep-alive"));
+        response.add_header(Header::Connection, String::from("Upgrade, Close"));
+        assert!(!response.keep_alive());
+    }
+
     #[test]
     fn test_status_code() {
+        assert_eq!(StatusCode::OK.reason_phrase(), b"OK");
+        assert_eq!(
+            StatusCode::RangeNotSatisfiable.reason_phrase(),
+            b"Range Not Satisfiable"
+        );
+        assert_eq!(StatusCode::SwitchingProtocols.raw(), b"101");
         assert_eq!(StatusCode::OK.raw(), b"200");
+        assert_eq!(StatusCode::PartialContent.raw(), b"206");
+        assert_eq!(StatusCode::NotModified.raw(), b"304");
         assert_eq!(StatusCode::BadRequest.raw(), b"400");
+        assert_eq!(StatusCode::Unauthorized.raw(), b"401");
+        assert_eq!(StatusCode::Forbidden.raw(), b"403");
         assert_eq!(StatusCode::NotFound.raw(), b"404");
+        assert_eq!(StatusCode::MethodNotAllowed.raw(), b"405");
+        assert_eq!(StatusCode::RequestTimeout.raw(), b"408");
+        assert_eq!(StatusCode::PayloadTooLarge.raw(), b"413");
+        assert_eq!(StatusCode::UriTooLong.raw(), b"414");
+        assert_eq!(StatusCode::RangeNotSatisfiable.raw(), b"416");
+        assert_eq!(StatusCode::RequestHeaderFieldsTooLarge.raw(), b"431");
         assert_eq!(StatusCode::InternalServerError.raw(), b"500");
         assert_eq!(StatusCode::NotImplemented.raw(), b"501");
+        assert_eq!(StatusCode::HttpVersionNotSupported.raw(), b"505");
     }
 }
@@ -0,0 +1,250 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Dispatching of requests to handlers, by method and path.
+//!
+//! The routes are matched with path patterns such as "/drives/{drive_id}", where the segments in
+//! braces are parameters matching any segment. The decoded values of the parameters are passed to
+//! the handler of the route.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::router::Router;
+//! use micro_http::{Body, Method, Request, Response, StatusCode};
+//!
+//! let mut router = Router::new();
+//! router.route(Method::Put, "/drives/{drive_id}", |request, params| {
+//!     let mut response = Response::for_request(request, StatusCode::OK);
+//!     response.set_body(Body::new(format!("Updated {}.", params.get("drive_id").unwrap())));
+//!     response
+//! });
+//!
+//! let request = Request::try_from(b"PUT /drives/rootfs HTTP/1.1\r\n\r\n").unwrap();
+//! assert_eq!(router.handle(&request).body().unwrap(), Body::new("Updated rootfs."));
+//! let request = Request::try_from(b"PUT /drives HTTP/1.1\r\n\r\n").unwrap();
+//! assert_eq!(router.handle(&request).status(), StatusCode::NotFound);
+//! ```
+
+use std::borrow::Cow;
+
+use common::{Body, Method};
+use headers::Header;
+use request::Request;
+use response::{Response, StatusCode};
+
+/// The values of the parameters of a path pattern, matched by a request.
+pub struct PathParams<'a> {
+    params: Vec<(&'a str, Cow<'a, str>)>,
+}
+
+impl<'a> PathParams<'a> {
+    /// Returns the decoded value of the parameter called `name` in the pattern, without braces.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|&&(param_name, _)| param_name == name)
+            .map(|(_, value)| value.as_ref())
+    }
+}
+
+// The handler of a route, called with the request and the values of the parameters of the route.
+type Handler = Box<Fn(&Request, &PathParams) -> Response>;
+
+struct Route {
+    method: Method,
+    pattern: String,
+    // The names of the parameters of the pattern, in order.
+    param_names: Vec<String>,
+    handler: Handler,
+}
+
+/// Dispatches requests to the handler of the first route matching their method and path.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    /// Creates a `Router` without routes.
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    /// Adds a route, which calls `handler` for the requests with `method` and a path matching
+    /// `pattern`.
+    pub fn route<F>(&mut self, method: Method, pattern: &str, handler: F)
+    where
+        F: Fn(&Request, &PathParams) -> Response + 'static,
+    {
+        let param_names = pattern
+            .split('/')
+            .filter(|segment| segment.starts_with('{') && segment.ends_with('}'))
+            .map(|segment| segment[1..segment.len() - 1].to_string())
+            .collect();
+        self.routes.push(Route {
+            method,
+            pattern: pattern.to_string(),
+            param_names,
+            handler: Box::new(handler),
+        });
+    }
+
+    /// Returns the response of the handler of the first route matching `request`.
+    ///
+    /// When no route matches the path of `request`, the response is `404 Not Found`. When routes
+    /// match the path but not the method, the response is `405 Method Not Allowed`, with the
+    /// methods of these routes in the `Allow` header.
+    pub fn handle(&self, request: &Request) -> Response {
+        let mut allowed_methods: Vec<&str> = Vec::new();
+        for route in &self.routes {
+            let values = match request.uri().match_path(&route.pattern) {
+                Some(values) => values,
+                None => continue,
+            };
+            if &route.method != request.method() {
+                // Methods are tokens, so they are valid UTF-8.
+                let method = ::std::str::from_utf8(route.method.raw()).unwrap_or("");
+                if !allowed_methods.contains(&method) {
+                    allowed_methods.push(method);
+                }
+                continue;
+            }
+
+            let params = PathParams {
+                params: route
+                    .param_names
+                    .iter()
+                    .map(|name| name.as_str())
+                    .zip(values)
+                    .collect(),
+            };
+            return (route.handler)(request, &params);
+        }
+
+        if allowed_methods.is_empty() {
+            let mut response = Response::for_request(request, StatusCode::NotFound);
+            response.set_body(Body::new("Resource not found."));
+            response
+        } else {
+            let mut response = Response::for_request(request, StatusCode::MethodNotAllowed);
+            response.add_header(Header::Allow, allowed_methods.join(", "));
+            response.set_body(Body::new("Method not allowed."));
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::Version;
+
+    fn body_response(request: &Request, body: String) -> Response {
+        let mut response = Response::for_request(request, StatusCode::OK);
+        response.set_body(Body::new(body));
+        response
+    }
+
+    fn test_router() -> Router {
+        let mut router = Router::new();
+        router.route(Method::Get, "/", |request, _| {
+            body_response(request, String::from("root"))
+        });
+        router.route(Method::Put, "/drives/{drive_id}", |request, params| {
+            body_response(request, format!("put {}", params.get("drive_id").unwrap()))
+        });
+        router.route(Method::Delete, "/drives/{drive_id}", |request, params| {
+            body_response(
+                request,
+                format!("delete {}", params.get("drive_id").unwrap()),
+            )
+        });
+        router.route(Method::Patch, "/drives/{drive_id}", |request, params| {
+            body_response(
+                request,
+                format!("patch {}", params.get("drive_id").unwrap()),
+            )
+        });
+        router.route(
+            Method::Other(String::from("PURGE")),
+            "/cache",
+            |request, _| body_response(request, String::from("purged")),
+        );
+        router.route(
+            Method::Get,
+            "/vms/{vm_id}/drives/{drive_id}",
+            |request, params| {
+                assert_eq!(params.get("foo"), None);
+                body_response(
+                    request,
+                    format!(
+                        "{} {}",
+                        params.get("vm_id").unwrap(),
+                        params.get("drive_id").unwrap()
+                    ),
+                )
+            },
+        );
+        router
+    }
+
+    fn handle(router: &Router, request_bytes: &[u8]) -> Response {
+        router.handle(&Request::try_from(request_bytes).unwrap())
+    }
+
+    #[test]
+    fn test_handle() {
+        let router = test_router();
+        let body = |request_bytes| handle(&router, request_bytes).body().unwrap();
+
+        assert_eq!(body(b"GET / HTTP/1.1\r\n\r\n"), Body::new("root"));
+        assert_eq!(
+            body(b"PUT /drives/root%20fs HTTP/1.1\r\n\r\n"),
+            Body::new("put root fs")
+        );
+        assert_eq!(
+            body(b"PATCH /drives/rootfs HTTP/1.1\r\n\r\n"),
+            Body::new("patch rootfs")
+        );
+        assert_eq!(
+            body(b"DELETE /drives/rootfs HTTP/1.1\r\n\r\n"),
+            Body::new("delete rootfs")
+        );
+        assert_eq!(
+            body(b"GET /vms/1/drives/2?a=b HTTP/1.1\r\n\r\n"),
+            Body::new("1 2")
+        );
+        assert_eq!(body(b"PURGE /cache HTTP/1.1\r\n\r\n"), Body::new("purged"));
+    }
+
+    #[test]
+    fn test_no_route() {
+        let router = test_router();
+
+        for request_bytes in &[
+            &b"GET /drives HTTP/1.1\r\n\r\n"[..],
+            b"PUT /drives/rootfs/path HTTP/1.1\r\n\r\n",
+            b"GET /vms/1/drives HTTP/1.1\r\n\r\n",
+        ] {
+            let response = handle(&router, request_bytes);
+            assert_eq!(response.status(), StatusCode::NotFound);
+            assert_eq!(response.body().unwrap(), Body::new("Resource not found."));
+        }
+
+        // The methods which are not standard are not allowed either.
+        for request_bytes in &[
+            &b"GET /drives/rootfs HTTP/1.0\r\n\r\n"[..],
+            b"PROPFIND /drives/rootfs HTTP/1.0\r\n\r\n",
+        ] {
+            let response = handle(&router, request_bytes);
+            assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+            assert_eq!(response.http_version(), Version::Http10);
+            let mut response_buf = Vec::new();
+            response.write_all(&mut response_buf).unwrap();
+            assert!(String::from_utf8(response_buf)
+                .unwrap()
+                .contains("Allow: PUT, DELETE, PATCH\r\n"));
+        }
+    }
+}
@@ -0,0 +1,1284 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A server for the HTTP requests received on a Unix domain socket, or on a TCP socket.
+//!
+//! `HttpServer` accepts the connections of the socket and waits for them with epoll, so that a
+//! single thread serves all of them without blocking on a slow client. The requests of every
+//! connection are parsed as they are complete, and passed to a `RequestHandler`, whose responses
+//! are written back in order.
+//!
+//! ## Example
+//! ```no_run
+//! extern crate micro_http;
+//! use micro_http::router::Router;
+//! use micro_http::server::HttpServer;
+//! use micro_http::{Body, Method, Response, StatusCode};
+//!
+//! let mut router = Router::new();
+//! router.route(Method::Get, "/", |request, _| {
+//!     let mut response = Response::for_request(request, StatusCode::OK);
+//!     response.set_body(Body::new("Hello."));
+//!     response
+//! });
+//!
+//! let mut server = HttpServer::new("/tmp/micro_http.socket", router).unwrap();
+//! server.run().unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use epoll;
+
+use auth::Peer;
+use common::{Body, Method, RequestError, Version};
+use connection::ClientConnection;
+use headers::Header;
+use host::AllowedHosts;
+use request::{Request, RequestLimits};
+use response::{Response, StatusCode};
+use router::Router;
+use timer::{TimerFd, TimerId, Timers};
+#[cfg(feature = "tls")]
+use tls::{TlsAcceptor, TlsStream};
+use writer::ResponseWriter;
+
+// The minimum number of bytes of requests buffered for a connection, before they are handled.
+// The buffer can also hold a whole request as large as the limits of the requests.
+const MAX_PENDING_INPUT: usize = 64 * 1024;
+// The maximum number of bytes of responses buffered for a connection, before they are written.
+const MAX_PENDING_OUTPUT: usize = 1024 * 1024;
+// The maximum number of events handled by a call to `HttpServer::poll`.
+const MAX_EVENTS: usize = 32;
+// The size of the buffer the connections are read into.
+const READ_BUF_LEN: usize = 4096;
+
+/// The number of connections an `HttpServer` serves at the same time, unless it is set with
+/// `set_max_connections`.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+
+/// Errors associated with serving requests.
+#[derive(Debug)]
+pub enum ServerError {
+    /// Cannot bind the socket, or accept its connections.
+    Socket(IoError),
+    /// Cannot create the epoll file descriptor, or wait for events with it.
+    Epoll(IoError),
+    /// Cannot create the timer file descriptor, or arm it.
+    Timer(IoError),
+}
+
+/// Handles the requests received by an `HttpServer`.
+pub trait RequestHandler {
+    /// Returns the response to `request`.
+    fn handle(&mut self, request: &Request) -> Response;
+
+    /// Returns the response to an invalid request, after which the connection is closed.
+    ///
+    /// The response is `Response::for_error` by default, with the status code mapped from the
+    /// error, such as `413 Payload Too Large` for the requests exceeding the limits.
+    fn handle_error(&mut self, error: &RequestError) -> Response {
+        Response::for_error(Version::Http11, error)
+    }
+
+    /// Returns the response to `request`, with the `Timers` of the server, on which the handler
+    /// can schedule the work it defers, such as expiring a token.
+    ///
+    /// The server calls this method instead of `handle`, which it calls by default.
+    fn handle_with_timers(&mut self, request: &Request, _timers: &mut Timers) -> Response {
+        self.handle(request)
+    }
+
+    /// Does the work deferred until `timer` expired, which was scheduled on the `Timers` of the
+    /// server. More timers can be scheduled on `timers`, such as for periodic work.
+    ///
+    /// Nothing is done by default.
+    fn handle_timer(&mut self, _timer: TimerId, _timers: &mut Timers) {}
+}
+
+/// The record of a request served by an `HttpServer`, passed to its access log.
+#[derive(Debug)]
+pub struct AccessLogEntry<'a> {
+    /// The method of the request.
+    pub method: &'a Method,
+    /// The absolute path of the request, with its query.
+    pub path: &'a str,
+    /// The status code of the response.
+    pub status: StatusCode,
+    /// The number of bytes of the request, including its header section and its body.
+    pub request_bytes: usize,
+    /// The number of bytes of the response, without its streamed body, which is only read as
+    /// the connection accepts it.
+    pub response_bytes: usize,
+    /// The time from the complete reception of the request to the queueing of its response.
+    pub latency: Duration,
+}
+
+// The callback an `HttpServer` logs the requests it serves with.
+type AccessLog = Box<FnMut(&AccessLogEntry) + Send>;
+
+// The callback an `HttpServer` authenticates the requests with, before they are handled.
+type Authenticator = Box<FnMut(&Request, &Peer) -> Option<Response> + Send>;
+
+impl RequestHandler for Router {
+    fn handle(&mut self, request: &Request) -> Response {
+        Router::handle(self, request)
+    }
+}
+
+// The socket the server accepts connections on.
+enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+impl Listener {
+    fn set_nonblocking(&self) -> IoResult<()> {
+        match *self {
+            Listener::Unix(ref listener) => listener.set_nonblocking(true),
+            Listener::Tcp(ref listener) => listener.set_nonblocking(true),
+        }
+    }
+
+    fn accept(&self) -> IoResult<Stream> {
+        match *self {
+            Listener::Unix(ref listener) => {
+                listener.accept().map(|(stream, _)| Stream::Unix(stream))
+            }
+            Listener::Tcp(ref listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Listener::Unix(ref listener) => listener.as_raw_fd(),
+            Listener::Tcp(ref listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+// A connection accepted by the server.
+enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<TlsStream>),
+}
+
+impl Stream {
+    fn set_nonblocking(&self) -> IoResult<()> {
+        match *self {
+            Stream::Unix(ref stream) => stream.set_nonblocking(true),
+            Stream::Tcp(ref stream) => stream.set_nonblocking(true),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref stream) => stream.socket().set_nonblocking(true),
+        }
+    }
+
+    fn peer(&self) -> Peer {
+        match *self {
+            Stream::Unix(ref stream) => Peer::of_unix(stream),
+            Stream::Tcp(ref stream) => Peer::of_tcp(stream),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref stream) => Peer::of_tcp(stream.socket()),
+        }
+    }
+
+    // Returns `true` when the stream buffers output of its own, which has to be flushed once
+    // the connection is writable.
+    fn wants_write(&self) -> bool {
+        match *self {
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref stream) => stream.wants_write(),
+            _ => false,
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Stream::Unix(ref stream) => stream.as_raw_fd(),
+            Stream::Tcp(ref stream) => stream.as_raw_fd(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        match *self {
+            Stream::Unix(ref mut stream) => stream.read(buf),
+            Stream::Tcp(ref mut stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        match *self {
+            Stream::Unix(ref mut stream) => stream.write(buf),
+            Stream::Tcp(ref mut stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match *self {
+            Stream::Unix(ref mut stream) => stream.flush(),
+            Stream::Tcp(ref mut stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+// A connection accepted by the server, with the state of its requests and responses.
+struct Client {
+    stream: Stream,
+    peer: Peer,
+    connection: ClientConnection,
+    writer: ResponseWriter,
+    // The events the connection is registered for.
+    interest: epoll::Events,
+    // Whether the client closed its side of the connection.
+    hung_up: bool,
+    // When the last bytes were read from the connection, or written to it.
+    last_active: Instant,
+}
+
+impl Client {
+    fn new(stream: Stream, limits: RequestLimits) -> Self {
+        let max_pending = limits
+            .max_uri_len
+            .saturating_add(limits.max_header_bytes)
+            .saturating_add(limits.max_body_bytes)
+            .max(MAX_PENDING_INPUT);
+        Client {
+            peer: stream.peer(),
+            stream,
+            connection: ClientConnection::with_limits(max_pending, limits),
+            writer: ResponseWriter::new(MAX_PENDING_OUTPUT),
+            interest: epoll::Events::EPOLLIN,
+            hung_up: false,
+            last_active: Instant::now(),
+        }
+    }
+
+    // Reads the requests which are available, and writes their responses.
+    //
+    // Returns the events the connection has to wait for next, or `None` when it has to be closed.
+    fn serve<H: RequestHandler>(
+        &mut self,
+        handler: &mut H,
+        allowed_hosts: Option<&AllowedHosts>,
+        authenticator: &mut Option<Authenticator>,
+        access_log: &mut Option<AccessLog>,
+        server_token: Option<&str>,
+        timers: &mut Timers,
+    ) -> Option<epoll::Events> {
+        let mut buf = [0u8; READ_BUF_LEN];
+        while !self.hung_up && self.connection.keep_alive() {
+            match self.stream.read(&mut buf) {
+                Ok(0) => self.hung_up = true,
+                // The requests are handled as they are received, so only a single request larger
+                // than the cap can exceed it.
+                Ok(len) => {
+                    self.last_active = Instant::now();
+                    if self.connection.receive(&buf[..len]).is_err()
+                        || !self.handle(
+                            handler,
+                            allowed_hosts,
+                            authenticator,
+                            access_log,
+                            server_token,
+                            timers,
+                        )
+                    {
+                        return None;
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return None,
+            }
+        }
+
+        match self.writer.write_to(&mut self.stream) {
+            Ok(0) => (),
+            Ok(_) => self.last_active = Instant::now(),
+            Err(_) => return None,
+        }
+        if self.stream.flush().is_err() {
+            return None;
+        }
+
+        let mut interest = epoll::Events::empty();
+        if !self.hung_up && self.connection.keep_alive() {
+            interest |= epoll::Events::EPOLLIN;
+        }
+        if !self.writer.is_empty() || self.stream.wants_write() {
+            interest |= epoll::Events::EPOLLOUT;
+        }
+        if interest.is_empty() {
+            None
+        } else {
+            Some(interest)
+        }
+    }
+
+    // Queues the responses to the complete requests received so far.
+    //
+    // Returns `false` when the responses exceed the cap of the pending output, because the
+    // client doesn't read them.
+    fn handle<H: RequestHandler>(
+        &mut self,
+        handler: &mut H,
+        allowed_hosts: Option<&AllowedHosts>,
+        authenticator: &mut Option<Authenticator>,
+        access_log: &mut Option<AccessLog>,
+        server_token: Option<&str>,
+        timers: &mut Timers,
+    ) -> bool {
+        let peer = self.peer;
+        while let Some((mut response, logged)) =
+            self.connection.handle_next(|result| match result {
+                Ok(request) => {
+                    let received = Instant::now();
+                    let mut response = match allowed_hosts {
+                        Some(allowed_hosts) if !allowed_hosts.allows(request) => {
+                            AllowedHosts::forbidden_response(request)
+                        }
+                        _ => match authenticator
+                            .as_mut()
+                            .and_then(|authenticate| authenticate(request, &peer))
+                        {
+                            Some(response) => response,
+                            None => handler.handle_with_timers(request, timers),
+                        },
+                    };
+                    // The connection is closed when either the request or the response closes it,
+                    // and the response tells the client so, whichever way it was built.
+                    let keep_alive = request.keep_alive() && response.keep_alive();
+                    response.set_keep_alive(keep_alive);
+                    let logged = access_log.as_ref().map(|_| {
+                        let path = String::from(request.uri().get_abs_path());
+                        (request.method().clone(), path, received)
+                    });
+                    (response, logged)
+                }
+                Err(e) => {
+                    let mut response = handler.handle_error(&e);
+                    response.set_keep_alive(false);
+                    (response, None)
+                }
+            })
+        {
+            if let Some(server_token) = server_token {
+                response.set_default_server(server_token);
+            }
+            if !response.keep_alive() {
+                self.connection.close();
+            }
+            let status = response.status();
+            let pending_output = self.writer.pending();
+            if self.writer.push_streamed(response).is_err() {
+                return false;
+            }
+            if let (&mut Some(ref mut access_log), Some((method, path, received))) =
+                (&mut *access_log, logged)
+            {
+                access_log(&AccessLogEntry {
+                    method: &method,
+                    path: &path,
+                    status,
+                    request_bytes: self.connection.last_request_len(),
+                    response_bytes: self.writer.pending() - pending_output,
+                    latency: received.elapsed(),
+                });
+            }
+        }
+        true
+    }
+
+    // Writes a `408 Request Timeout` response to a connection which stalled, before it's closed.
+    //
+    // The response is only written when it doesn't interleave with a pending one, and as much of
+    // it as the socket accepts right away: the client is not waited for anymore.
+    fn time_out(&mut self, server_token: Option<&str>) {
+        if !self.writer.is_empty() {
+            return;
+        }
+        let mut response = Response::new(Version::Http11, StatusCode::RequestTimeout);
+        response.set_body(Body::new("Request timed out."));
+        response.add_header(Header::Connection, String::from("close"));
+        if let Some(server_token) = server_token {
+            response.set_server(server_token);
+        }
+        if self.writer.push(&response).is_ok() {
+            let _ = self.writer.write_to(&mut self.stream);
+        }
+    }
+}
+
+/// Serves the connections of a Unix domain socket or of a TCP socket with a `RequestHandler`.
+///
+/// The connections are served the same way, with the same limits, whatever the socket is.
+pub struct HttpServer<H: RequestHandler> {
+    listener: Listener,
+    max_connections: usize,
+    limits: RequestLimits,
+    allowed_hosts: Option<AllowedHosts>,
+    authenticator: Option<Authenticator>,
+    request_timeout: Option<Duration>,
+    access_log: Option<AccessLog>,
+    server_token: Option<String>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+    timers: Timers,
+    timer_fd: TimerFd,
+    // The deadline the timer file descriptor is armed for.
+    armed: Option<Instant>,
+    epoll_fd: RawFd,
+    // The accepted connections, by file descriptor, which is also the data of their events.
+    clients: HashMap<RawFd, Client>,
+    handler: H,
+}
+
+impl<H: RequestHandler> HttpServer<H> {
+    /// Binds a Unix domain socket to `path`, whose requests are going to be handled by `handler`.
+    ///
+    /// The socket file must not exist, and it is not removed when the `HttpServer` is dropped.
+    ///
+    /// # Errors
+    /// Returns `Socket` when the socket cannot be bound, and `Epoll` when it cannot be
+    /// registered for its connections.
+    pub fn new<P: AsRef<Path>>(path: P, handler: H) -> Result<Self, ServerError> {
+        let listener = UnixListener::bind(path).map_err(ServerError::Socket)?;
+        HttpServer::with_listener(Listener::Unix(listener), handler)
+    }
+
+    /// Binds a TCP socket to `addr`, whose requests are going to be handled by `handler`.
+    ///
+    /// The requests are not authenticated, so the socket should only be bound to a loopback
+    /// address such as `127.0.0.1:8080`, for development.
+    ///
+    /// # Errors
+    /// Returns `Socket` when the socket cannot be bound, and `Epoll` when it cannot be
+    /// registered for its connections.
+    pub fn new_tcp<A: ToSocketAddrs>(addr: A, handler: H) -> Result<Self, ServerError> {
+        let listener = TcpListener::bind(addr).map_err(ServerError::Socket)?;
+        HttpServer::with_listener(Listener::Tcp(listener), handler)
+    }
+
+    /// Binds a TCP socket to `addr` like `new_tcp`, whose connections are encrypted with TLS as
+    /// configured by `acceptor`.
+    ///
+    /// This function is only available with the `tls` feature.
+    ///
+    /// # Errors
+    /// Returns `Socket` when the socket cannot be bound, and `Epoll` when it cannot be
+    /// registered for its connections.
+    #[cfg(feature = "tls")]
+    pub fn new_tls<A: ToSocketAddrs>(
+        addr: A,
+        acceptor: TlsAcceptor,
+        handler: H,
+    ) -> Result<Self, ServerError> {
+        let mut server = HttpServer::new_tcp(addr, handler)?;
+        server.tls = Some(acceptor);
+        Ok(server)
+    }
+
+    fn with_listener(listener: Listener, handler: H) -> Result<Self, ServerError> {
+        listener.set_nonblocking().map_err(ServerError::Socket)?;
+        let timer_fd = TimerFd::new().map_err(ServerError::Timer)?;
+        let epoll_fd = epoll::create(true).map_err(ServerError::Epoll)?;
+        // The epoll file descriptor is closed by `drop` from now on.
+        let server = HttpServer {
+            listener,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            limits: RequestLimits::default(),
+            allowed_hosts: None,
+            authenticator: None,
+            request_timeout: None,
+            access_log: None,
+            server_token: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            timers: Timers::new(),
+            timer_fd,
+            armed: None,
+            epoll_fd,
+            clients: HashMap::new(),
+            handler,
+        };
+
+        for &fd in &[server.listener.as_raw_fd(), server.timer_fd.as_raw_fd()] {
+            epoll::ctl(
+                server.epoll_fd,
+                epoll::ControlOptions::EPOLL_CTL_ADD,
+                fd,
+                epoll::Event::new(epoll::Events::EPOLLIN, fd as u64),
+            )
+            .map_err(ServerError::Epoll)?;
+        }
+        Ok(server)
+    }
+
+    /// Returns the address the TCP socket is bound to, such as the port picked by the system
+    /// when binding to port 0, or `None` for a Unix domain socket.
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        match self.listener {
+            Listener::Tcp(ref listener) => listener.local_addr().ok(),
+            Listener::Unix(_) => None,
+        }
+    }
+
+    /// Caps the number of connections served at the same time, whatever the socket is.
+    ///
+    /// The connections accepted beyond it are closed right away, so that clients can't exhaust
+    /// the file descriptors and the memory of the server by opening connections. The cap is
+    /// `DEFAULT_MAX_CONNECTIONS` until then.
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = max_connections;
+    }
+
+    /// Sets the limits of the sizes of the requests, for the connections accepted afterwards.
+    ///
+    /// The limits are `RequestLimits::default()` until then. The requests exceeding them are
+    /// answered by `RequestHandler::handle_error`.
+    pub fn set_request_limits(&mut self, limits: RequestLimits) {
+        self.limits = limits;
+    }
+
+    /// Restricts the requests passed to the `RequestHandler` to the ones for `allowed_hosts`,
+    /// or lifts the restriction with `None`.
+    ///
+    /// The other requests are answered with `403 Forbidden`. All the hosts are allowed until
+    /// then.
+    pub fn set_allowed_hosts(&mut self, allowed_hosts: Option<AllowedHosts>) {
+        self.allowed_hosts = allowed_hosts;
+    }
+
+    /// Calls `authenticator` for every valid request allowed by the list of allowed hosts,
+    /// with the `Peer` of its connection, before it is passed to the `RequestHandler`.
+    ///
+    /// The requests for which it returns a response, such as `auth::unauthorized_response`,
+    /// are answered with it instead of being handled.
+    pub fn set_authenticator<F>(&mut self, authenticator: F)
+    where
+        F: FnMut(&Request, &Peer) -> Option<Response> + Send + 'static,
+    {
+        self.authenticator = Some(Box::new(authenticator));
+    }
+
+    /// Closes the connections which neither send nor receive any byte for `timeout`, or lifts
+    /// the timeout with `None`.
+    ///
+    /// A client which connects and never completes its request would hold its connection
+    /// forever otherwise. The stalled connections are answered with `408 Request Timeout`
+    /// before they are closed. There is no timeout until then.
+    pub fn set_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.request_timeout = timeout;
+    }
+
+    /// Calls `access_log` for every request served from now on, once its response is queued,
+    /// such as for writing an access log or updating metrics.
+    ///
+    /// The invalid requests are not logged, since they have no method nor path.
+    pub fn set_access_log<F>(&mut self, access_log: F)
+    where
+        F: FnMut(&AccessLogEntry) + Send + 'static,
+    {
+        self.access_log = Some(Box::new(access_log));
+    }
+
+    /// Sets the `Server` header of all the responses written from now on, such as
+    /// "Firecracker/0.13.0", or stops setting it with `None`.
+    ///
+    /// The responses which already have a `Server` header keep it. No header is added until
+    /// then.
+    pub fn set_server_token(&mut self, server_token: Option<String>) {
+        self.server_token = server_token;
+    }
+
+    /// Returns the `RequestHandler` of the server.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Returns the timers of the server, for scheduling the work of the `RequestHandler` from
+    /// outside of it, such as before the server runs.
+    ///
+    /// `RequestHandler::handle_timer` is called once they expire, like for the timers the
+    /// handler schedules itself.
+    pub fn timers(&mut self) -> &mut Timers {
+        &mut self.timers
+    }
+
+    /// Serves the connections of the socket, until an error occurs.
+    pub fn run(&mut self) -> Result<(), ServerError> {
+        loop {
+            self.poll(-1)?;
+        }
+    }
+
+    /// Waits for at most `timeout` milliseconds, or indefinitely if it is -1, for the socket or
+    /// its connections to be ready, and serves them.
+    ///
+    /// Returns the number of events which were handled. The wait ends early when a timer
+    /// expires or a connection times out, so that they're handled on time.
+    ///
+    /// # Errors
+    /// Returns `Socket` when a connection cannot be accepted, `Epoll` when waiting fails, and
+    /// `Timer` when the timer cannot be armed for the next deadline. The errors of the
+    /// connections are not reported: the connections are closed instead.
+    pub fn poll(&mut self, timeout: i32) -> Result<usize, ServerError> {
+        self.arm_timer()?;
+        let mut events = vec![epoll::Event::new(epoll::Events::empty(), 0); MAX_EVENTS];
+        let num_events = match epoll::wait(self.epoll_fd, timeout, &mut events[..]) {
+            Ok(num_events) => num_events,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => return Ok(0),
+            Err(e) => return Err(ServerError::Epoll(e)),
+        };
+
+        for event in &events[..num_events] {
+            let fd = event.data as RawFd;
+            if fd == self.listener.as_raw_fd() {
+                self.accept()?;
+            } else if fd == self.timer_fd.as_raw_fd() {
+                // The timer is armed again for the next deadline, whichever it is now.
+                self.timer_fd.clear();
+                self.armed = None;
+            } else {
+                self.serve(fd);
+            }
+        }
+        self.expire_timers();
+        self.reap_stalled();
+        Ok(num_events)
+    }
+
+    // Returns when the next timer expires, or the next connection times out.
+    fn next_deadline(&self) -> Option<Instant> {
+        let timeout_deadline = self.request_timeout.and_then(|request_timeout| {
+            self.clients
+                .values()
+                .map(|client| client.last_active + request_timeout)
+                .min()
+        });
+        match (self.timers.next_deadline(), timeout_deadline) {
+            (Some(timer_deadline), Some(timeout_deadline)) => {
+                Some(timer_deadline.min(timeout_deadline))
+            }
+            (timer_deadline, timeout_deadline) => timer_deadline.or(timeout_deadline),
+        }
+    }
+
+    // Arms the timer file descriptor for the next deadline, unless it already is.
+    fn arm_timer(&mut self) -> Result<(), ServerError> {
+        let deadline = self.next_deadline();
+        if deadline != self.armed {
+            self.timer_fd.arm(deadline).map_err(ServerError::Timer)?;
+            self.armed = deadline;
+        }
+        Ok(())
+    }
+
+    // Passes the timers which expired to the `RequestHandler`.
+    //
+    // The timers it schedules meanwhile expire on the next poll at the earliest, even without a
+    // delay, so that a handler rescheduling its work right away can't starve the connections.
+    fn expire_timers(&mut self) {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        while let Some(timer) = self.timers.pop_expired(now) {
+            expired.push(timer);
+        }
+        for timer in expired {
+            self.handler.handle_timer(timer, &mut self.timers);
+        }
+    }
+
+    // Answers and closes the connections which timed out.
+    fn reap_stalled(&mut self) {
+        let request_timeout = match self.request_timeout {
+            Some(request_timeout) => request_timeout,
+            None => return,
+        };
+        let now = Instant::now();
+        let stalled: Vec<RawFd> = self
+            .clients
+            .iter()
+            .filter(|&(_, client)| now.duration_since(client.last_active) >= request_timeout)
+            .map(|(&fd, _)| fd)
+            .collect();
+        for fd in stalled {
+            if let Some(client) = self.clients.get_mut(&fd) {
+                client.time_out(self.server_token.as_ref().map(String::as_str));
+            }
+            self.close(fd);
+        }
+    }
+
+    fn accept(&mut self) -> Result<(), ServerError> {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok(stream) => stream,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ServerError::Socket(e)),
+            };
+            // The connections which cannot be served are closed right away, by dropping them.
+            // They are still accepted, since the listener would be ready again otherwise.
+            if self.clients.len() >= self.max_connections || stream.set_nonblocking().is_err() {
+                continue;
+            }
+            #[cfg(feature = "tls")]
+            let stream = match (stream, self.tls.as_ref()) {
+                (Stream::Tcp(stream), Some(acceptor)) => {
+                    Stream::Tls(Box::new(acceptor.accept(stream)))
+                }
+                (stream, _) => stream,
+            };
+            let fd = stream.as_raw_fd();
+            if epoll::ctl(
+                self.epoll_fd,
+                epoll::ControlOptions::EPOLL_CTL_ADD,
+                fd,
+                epoll::Event::new(epoll::Events::EPOLLIN, fd as u64),
+            )
+            .is_err()
+            {
+                continue;
+            }
+            self.clients.insert(fd, Client::new(stream, self.limits));
+        }
+    }
+
+    fn serve(&mut self, fd: RawFd) {
+        let interest = match self.clients.get_mut(&fd) {
+            Some(client) => client
+                .serve(
+                    &mut self.handler,
+                    self.allowed_hosts.as_ref(),
+                    &mut self.authenticator,
+                    &mut self.access_log,
+                    self.server_token.as_ref().map(String::as_str),
+                    &mut self.timers,
+                )
+                .map(|interest| {
+                    let changed = interest != client.interest;
+                    client.interest = interest;
+                    (interest, changed)
+                }),
+            None => return,
+        };
+
+        match interest {
+            Some((interest, true)) => {
+                if epoll::ctl(
+                    self.epoll_fd,
+                    epoll::ControlOptions::EPOLL_CTL_MOD,
+                    fd,
+                    epoll::Event::new(interest, fd as u64),
+                )
+                .is_err()
+                {
+                    self.close(fd);
+                }
+            }
+            Some((_, false)) => (),
+            None => self.close(fd),
+        }
+    }
+
+    fn close(&mut self, fd: RawFd) {
+        // The connection is closed when it's dropped, which also removes it from the epoll set,
+        // so the error of removing it explicitly is ignored.
+        let _ = epoll::ctl(
+            self.epoll_fd,
+            epoll::ControlOptions::EPOLL_CTL_DEL,
+            fd,
+            epoll::Event::new(epoll::Events::empty(), 0),
+        );
+        self.clients.remove(&fd);
+    }
+}
+
+impl<H: RequestHandler> Drop for HttpServer<H> {
+    fn drop(&mut self) {
+        let _ = epoll::close(self.epoll_fd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use auth;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "micro_http_server_{}_{}.socket",
+            name,
+            process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn test_server(path: &PathBuf) -> HttpServer<Router> {
+        let mut router = Router::new();
+        router.route(Method::Get, "/drives/{drive_id}", |request, params| {
+            let mut response = Response::for_request(request, StatusCode::OK);
+            response.set_body(Body::new(params.get("drive_id").unwrap()));
+            response
+        });
+        HttpServer::new(path, router).unwrap()
+    }
+
+    // Serves the requests sent by `client`, until `server` closes the connection.
+    fn read_responses<H: RequestHandler, R: Read>(
+        server: &mut HttpServer<H>,
+        client: &mut R,
+    ) -> String {
+        server.poll(10).unwrap();
+        while !server.clients.is_empty() {
+            server.poll(10).unwrap();
+        }
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).unwrap();
+        responses
+    }
+
+    #[test]
+    fn test_serve() {
+        let path = socket_path("serve");
+        let mut server = test_server(&path);
+        assert!(server.tcp_addr().is_none());
+
+        // The requests can be split in several reads, and they are answered in order.
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"GET /drives/root HTTP/1.1\r\n").unwrap();
+        server.poll(10).unwrap();
+        server.poll(10).unwrap();
+        assert_eq!(server.clients.len(), 1);
+        client
+            .write_all(b"\r\nGET /drives/scratch HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert!(responses.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(responses.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(responses.contains("\r\n\r\nroot"));
+        assert!(responses.contains("Connection: close\r\n"));
+        assert!(responses.ends_with("\r\n\r\nscratch"));
+
+        // A client closing the connection is not an error.
+        let client = UnixStream::connect(&path).unwrap();
+        server.poll(10).unwrap();
+        assert_eq!(server.clients.len(), 1);
+        drop(client);
+        server.poll(10).unwrap();
+        assert!(server.clients.is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let path = socket_path("invalid");
+        let mut server = test_server(&path);
+
+        // The requests after an invalid request are not handled.
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(
+                b"GET /drives/root HTTP/1.1\r\n\r\n\
+                  GET / HTTP/1.2\r\n\r\n\
+                  GET /drives/a HTTP/1.1\r\n\r\n",
+            )
+            .unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert_eq!(responses.matches("HTTP/1.1 ").count(), 2);
+        assert!(responses.contains("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+        assert!(responses.ends_with("Unsupported HTTP version."));
+
+        // Responses are written for the routes which don't match too.
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"GET /drives HTTP/1.0\r\n\r\n").unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert!(responses.starts_with("HTTP/1.0 404 Not Found\r\n"));
+
+        // The requests exceeding the limits are rejected.
+        server.set_request_limits(RequestLimits {
+            max_uri_len: 8,
+            ..RequestLimits::default()
+        });
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"GET /drives/rootfs HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert!(responses.starts_with("HTTP/1.1 414 URI Too Long\r\n"));
+        assert!(responses.ends_with("URI too long."));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_connection() {
+        let path = socket_path("connection");
+        let mut router = Router::new();
+        router.route(Method::Get, "/", |_, _| {
+            Response::new(Version::Http11, StatusCode::OK)
+        });
+        router.route(Method::Get, "/close", |request, _| {
+            let mut response = Response::for_request(request, StatusCode::OK);
+            response.set_keep_alive(false);
+            response
+        });
+        let mut server = HttpServer::new(&path, router).unwrap();
+
+        // The requests after a response closing the connection are not handled.
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\n\r\nGET /close HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert_eq!(responses.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert_eq!(responses.matches("Connection: close\r\n").count(), 1);
+
+        // The responses tell the clients when the connection is not kept alive, even when the
+        // handler doesn't.
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert!(responses.contains("Connection: close\r\n"));
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        server.poll(10).unwrap();
+        server.poll(10).unwrap();
+        assert_eq!(server.clients.len(), 1);
+        let mut buf = [0u8; 17];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"HTTP/1.1 200 OK\r\n");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_access_log() {
+        let path = socket_path("access_log");
+        let mut server = test_server(&path);
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let logged_entries = entries.clone();
+        server.set_access_log(move |entry| {
+            assert!(entry.latency < Duration::from_secs(10));
+            logged_entries.lock().unwrap().push(format!(
+                "{:?} {} {:?} {} {}",
+                entry.method, entry.path, entry.status, entry.request_bytes, entry.response_bytes
+            ));
+        });
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(
+                b"GET /drives/root?all HTTP/1.1\r\n\r\n\
+                  GET /drives HTTP/1.1\r\n\r\n\
+                  GET / HTTP/1.2\r\n\r\n",
+            )
+            .unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        let (first, second) = responses.split_at(responses.find("HTTP/1.1 404").unwrap());
+        assert_eq!(
+            *entries.lock().unwrap(),
+            vec![
+                format!("Get /drives/root?all OK 33 {}", first.len()),
+                format!(
+                    "Get /drives NotFound 24 {}",
+                    second.find("HTTP/1.1 505").unwrap()
+                ),
+            ]
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_authenticator() {
+        let path = socket_path("authenticator");
+        let mut server = test_server(&path);
+        server.set_authenticator(|request, peer| {
+            assert_eq!(peer.credentials().unwrap().pid, process::id());
+            if auth::bearer_token(request).is_none() {
+                Some(auth::unauthorized_response(request, "Bearer"))
+            } else if !auth::has_bearer_token(request, "secret") {
+                Some(auth::forbidden_response(request))
+            } else {
+                None
+            }
+        });
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(
+                b"GET /drives/root HTTP/1.1\r\n\r\n\
+                  GET /drives/root HTTP/1.1\r\nAuthorization: Bearer guess\r\n\r\n\
+                  GET /drives/root HTTP/1.1\r\nAuthorization: Bearer secret\r\n\
+                  Connection: close\r\n\r\n",
+            )
+            .unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        let unauthorized = responses.find("HTTP/1.1 401 Unauthorized\r\n").unwrap();
+        let forbidden = responses.find("HTTP/1.1 403 Forbidden\r\n").unwrap();
+        let ok = responses.find("HTTP/1.1 200 OK\r\n").unwrap();
+        assert!(unauthorized < forbidden && forbidden < ok);
+        assert!(responses.contains("WWW-Authenticate: Bearer\r\n"));
+        assert!(responses.ends_with("root"));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_server_token() {
+        let path = socket_path("server_token");
+        let mut server = test_server(&path);
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"GET /drives/root HTTP/1.0\r\n\r\n")
+            .unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert!(!responses.contains("Server: "));
+        assert!(responses.contains("\r\nDate: "));
+
+        // Every response has the token, including the ones to the invalid requests.
+        server.set_server_token(Some(String::from("Firecracker")));
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"GET /drives/root HTTP/1.1\r\n\r\nGET / HTTP/1.2\r\n\r\n")
+            .unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert_eq!(responses.matches("HTTP/1.1 ").count(), 2);
+        assert_eq!(responses.matches("\r\nServer: Firecracker\r\n").count(), 2);
+        assert_eq!(responses.matches("\r\nDate: ").count(), 2);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tcp() {
+        let mut router = Router::new();
+        router.route(Method::Get, "/", |request, _| {
+            Response::for_request(request, StatusCode::OK)
+        });
+        let mut server = HttpServer::new_tcp("127.0.0.1:0", router).unwrap();
+        let addr = server.tcp_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert!(responses.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(responses.ends_with("Content-Length: 0\r\n\r\n"));
+
+        // The requests for the hosts which are not allowed don't reach the handler.
+        server.set_allowed_hosts(Some(AllowedHosts::new(vec!["localhost"])));
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: evil.example\r\n\r\n\
+                  GET / HTTP/1.1\r\nHost: localhost:8080\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert!(responses.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(responses.contains("Host not allowed.HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let mut router = Router::new();
+        router.route(Method::Get, "/", |request, _| {
+            Response::for_request(request, StatusCode::OK)
+        });
+        let mut server = HttpServer::new_tcp("127.0.0.1:0", router).unwrap();
+        server.set_max_connections(1);
+        let addr = server.tcp_addr().unwrap();
+
+        let mut first = TcpStream::connect(addr).unwrap();
+        server.poll(10).unwrap();
+        assert_eq!(server.clients.len(), 1);
+
+        // The connections beyond the cap are closed without being served.
+        let mut second = TcpStream::connect(addr).unwrap();
+        server.poll(10).unwrap();
+        assert_eq!(server.clients.len(), 1);
+        let mut buf = Vec::new();
+        assert_eq!(second.read_to_end(&mut buf).unwrap(), 0);
+
+        // A connection can be opened again once one is closed.
+        first
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let responses = read_responses(&mut server, &mut first);
+        assert!(responses.starts_with("HTTP/1.1 200 OK\r\n"));
+        let mut third = TcpStream::connect(addr).unwrap();
+        third.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let responses = read_responses(&mut server, &mut third);
+        assert!(responses.starts_with("HTTP/1.0 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let path = socket_path("timeout");
+        let mut server = test_server(&path);
+        server.set_request_timeout(Some(Duration::from_millis(50)));
+        assert_eq!(server.next_deadline(), None);
+
+        // The connection of a request which is never completed is closed.
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"GET /drives/root HTTP/1.1\r\n").unwrap();
+        server.poll(10).unwrap();
+        assert_eq!(server.clients.len(), 1);
+        assert!(server.next_deadline().unwrap() <= Instant::now() + Duration::from_millis(50));
+        let responses = read_responses(&mut server, &mut client);
+        assert!(responses.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(responses.contains("Connection: close\r\n"));
+        assert!(responses.ends_with("Request timed out."));
+
+        // The connections which are served in time are kept.
+        let mut client = UnixStream::connect(&path).unwrap();
+        server.poll(10).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        client
+            .write_all(b"GET /drives/root HTTP/1.1\r\n\r\n")
+            .unwrap();
+        server.poll(10).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        server.poll(0).unwrap();
+        assert_eq!(server.clients.len(), 1);
+        let mut buf = [0u8; 17];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"HTTP/1.1 200 OK\r\n");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Grants a token, which expires after a while.
+    #[derive(Default)]
+    struct TokenHandler {
+        token: Option<TimerId>,
+        expired: usize,
+    }
+
+    impl RequestHandler for TokenHandler {
+        fn handle(&mut self, request: &Request) -> Response {
+            match self.token {
+                Some(_) => Response::for_request(request, StatusCode::OK),
+                None => Response::for_request(request, StatusCode::Forbidden),
+            }
+        }
+
+        fn handle_with_timers(&mut self, request: &Request, timers: &mut Timers) -> Response {
+            if *request.method() == Method::Post {
+                if let Some(token) = self.token.take() {
+                    assert!(timers.cancel(token));
+                }
+                self.token = Some(timers.schedule(Duration::from_millis(20)));
+            }
+            self.handle(request)
+        }
+
+        fn handle_timer(&mut self, timer: TimerId, timers: &mut Timers) {
+            assert!(timers.is_empty());
+            if self.token == Some(timer) {
+                self.token = None;
+            }
+            self.expired += 1;
+        }
+    }
+
+    #[test]
+    fn test_timers() {
+        let path = socket_path("timers");
+        let mut server = HttpServer::new(&path, TokenHandler::default()).unwrap();
+
+        // The token is replaced by a second request, and only the second timer expires.
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(
+                b"POST / HTTP/1.1\r\n\r\n\
+                  POST / HTTP/1.1\r\n\r\n\
+                  GET / HTTP/1.1\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert_eq!(responses.matches("HTTP/1.1 200 OK\r\n").count(), 3);
+        assert_eq!(server.timers().len(), 1);
+
+        // The server waits for the timer without any connection, and wakes up once it expires.
+        let start = Instant::now();
+        while server.handler().expired == 0 {
+            assert!(start.elapsed() < Duration::from_secs(1));
+            server.poll(1000).unwrap();
+        }
+        assert_eq!(server.handler().expired, 1);
+        assert!(server.timers().is_empty());
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let responses = read_responses(&mut server, &mut client);
+        assert!(responses.starts_with("HTTP/1.0 403 Forbidden\r\n"));
+
+        // The timers can be scheduled from outside of the handler too.
+        server.timers().schedule_at(Instant::now());
+        server.poll(1000).unwrap();
+        assert_eq!(server.handler().expired, 2);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_socket_error() {
+        let path = socket_path("error");
+        let _server = test_server(&path);
+        // The socket is already bound.
+        match HttpServer::new(&path, Router::new()) {
+            Err(ServerError::Socket(_)) => (),
+            _ => panic!("The socket should not be bound twice."),
+        }
+        fs::remove_file(&path).unwrap();
+
+        // The TCP address is already bound.
+        let server = HttpServer::new_tcp("127.0.0.1:0", Router::new()).unwrap();
+        match HttpServer::new_tcp(server.tcp_addr().unwrap(), Router::new()) {
+            Err(ServerError::Socket(_)) => (),
+            _ => panic!("The TCP address should not be bound twice."),
+        }
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Minimal implementation of [Server-Sent Events](https://html.spec.whatwg.org/multipage/server-sent-events.html).
+//!
+//! An event stream is the `text/event-stream` body of a response which never ends on its own:
+//! `response` builds the head of such a response, and each `Event` is then written as one
+//! chunk of its body by an `EventWriter`, as the events happen. Between the events, keep-alive
+//! comments stop the proxies and the clients from dropping an idle connection.
+//!
+//! On a non-blocking connection, the events are written to a buffer, which is queued after the
+//! response with `ResponseWriter::push_bytes`.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::sse::{self, Event, EventWriter};
+//! use micro_http::writer::ResponseWriter;
+//! use micro_http::Request;
+//!
+//! let request = Request::try_from(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+//! let mut writer = ResponseWriter::new(4096);
+//! writer.push(&sse::response(&request).unwrap()).unwrap();
+//!
+//! let mut chunk = Vec::new();
+//! EventWriter::new(&mut chunk)
+//!     .send(&Event::new("Running").with_name("state"))
+//!     .unwrap();
+//! assert_eq!(chunk, b"1c\r\nevent: state\ndata: Running\n\n\r\n".to_vec());
+//! writer.push_bytes(&chunk).unwrap();
+//! ```
+
+use std::io::{Error as WriteError, Write};
+
+use chunked::ChunkedWriter;
+use headers::{Header, MediaType};
+use request::Request;
+use response::{Response, StatusCode};
+
+// The comment sent by `EventWriter::keep_alive`.
+const KEEP_ALIVE_COMMENT: &str = "keep-alive";
+
+/// An event sent on an event stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event<'a> {
+    data: &'a str,
+    name: Option<&'a str>,
+    id: Option<&'a str>,
+    retry: Option<u64>,
+}
+
+impl<'a> Event<'a> {
+    /// Creates an unnamed event carrying `data`, which can span several lines.
+    pub fn new(data: &'a str) -> Self {
+        Event {
+            data,
+            name: None,
+            id: None,
+            retry: None,
+        }
+    }
+
+    /// Sets the name of the event, which the clients listen for. The line breaks in `name` are
+    /// dropped, since they would end the field.
+    pub fn with_name(mut self, name: &'a str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Sets the ID of the event, which a client sends in the `Last-Event-ID` header when it
+    /// reconnects. The line breaks in `id` are dropped, since they would end the field.
+    pub fn with_id(mut self, id: &'a str) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Sets the number of milliseconds a client waits before reconnecting, once the stream is
+    /// closed.
+    pub fn with_retry(mut self, retry_ms: u64) -> Self {
+        self.retry = Some(retry_ms);
+        self
+    }
+
+    // Serializes the event in the `text/event-stream` format, ending with an empty line.
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if let Some(name) = self.name {
+            write_field(&mut bytes, "event", name);
+        }
+        if let Some(id) = self.id {
+            write_field(&mut bytes, "id", id);
+        }
+        if let Some(retry) = self.retry {
+            write_field(&mut bytes, "retry", &retry.to_string());
+        }
+        // Every line of the data is a field of its own: the client joins them back with LFs.
+        for line in self
+            .data
+            .split("\r\n")
+            .flat_map(|line| line.split(&['\r', '\n'][..]))
+        {
+            write_field(&mut bytes, "data", line);
+        }
+        bytes.push(b'\n');
+        bytes
+    }
+}
+
+fn write_field(bytes: &mut Vec<u8>, name: &str, value: &str) {
+    bytes.extend_from_slice(name.as_bytes());
+    bytes.extend_from_slice(b": ");
+    bytes.extend(value.bytes().filter(|&byte| byte != b'\r' && byte != b'\n'));
+    bytes.push(b'\n');
+}
+
+/// Builds the head of a response to `request` which streams events.
+///
+/// The events follow as the chunks of the body, so `None` is returned when the HTTP version of
+/// `request` does not support chunked bodies.
+pub fn response(request: &Request) -> Option<Response> {
+    let mut response = Response::for_request(request, StatusCode::OK);
+    if !response.set_chunked_body(&[]) {
+        return None;
+    }
+    response.add_header(
+        Header::ContentType,
+        String::from(MediaType::EventStream.as_str()),
+    );
+    // The events are live, so the caches must not replay them.
+    response.add_header(Header::CacheControl, String::from("no-cache"));
+    Some(response)
+}
+
+/// Writes the events of a stream to an underlying writer, one chunk for every event.
+pub struct EventWriter<W: Write> {
+    inner: ChunkedWriter<W>,
+}
+
+impl<W: Write> EventWriter<W> {
+    /// Creates a writer sending the events to `inner`, after the head built by `response`.
+    pub fn new(inner: W) -> Self {
+        EventWriter {
+            inner: ChunkedWriter::new(inner),
+        }
+    }
+
+    /// Writes `event`.
+    pub fn send(&mut self, event: &Event) -> Result<(), WriteError> {
+        self.inner.write_all(&event.encode())?;
+        self.inner.flush()
+    }
+
+    /// Writes a comment, which the clients ignore, to keep an idle connection alive.
+    pub fn keep_alive(&mut self) -> Result<(), WriteError> {
+        self.inner
+            .write_all(format!(": {}\n\n", KEEP_ALIVE_COMMENT).as_bytes())?;
+        self.inner.flush()
+    }
+
+    /// Ends the stream, then returns the underlying writer.
+    pub fn finish(self) -> Result<W, WriteError> {
+        self.inner.finish(&[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chunked::ChunkedDecoder;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            Event::new("Running").encode(),
+            b"data: Running\n\n".to_vec()
+        );
+        assert_eq!(
+            Event::new("first\nsecond\r\nthird")
+                .with_name("state")
+                .with_id("4\n2")
+                .with_retry(3000)
+                .encode(),
+            b"event: state\nid: 42\nretry: 3000\ndata: first\ndata: second\ndata: third\n\n"
+                .to_vec()
+        );
+        assert_eq!(Event::new("").encode(), b"data: \n\n".to_vec());
+    }
+
+    #[test]
+    fn test_response() {
+        let request = Request::try_from(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let response = response(&request).unwrap();
+        assert!(response.status() == StatusCode::OK);
+        let mut head = Vec::new();
+        response.write_all(&mut head).unwrap();
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.contains("Content-Type: text/event-stream\r\n"));
+        assert!(head.contains("Cache-Control: no-cache\r\n"));
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length"));
+
+        let request = Request::try_from(b"GET /events HTTP/1.0\r\n\r\n").unwrap();
+        assert!(super::response(&request).is_none());
+    }
+
+    #[test]
+    fn test_event_writer() {
+        let mut writer = EventWriter::new(Vec::new());
+        writer
+            .send(&Event::new("Running").with_name("state"))
+            .unwrap();
+        writer.keep_alive().unwrap();
+        writer
+            .send(&Event::new("Paused").with_name("state"))
+            .unwrap();
+        let body = writer.finish().unwrap();
+
+        let mut decoder = ChunkedDecoder::new();
+        let mut decoded = Vec::new();
+        assert_eq!(decoder.decode(&body, &mut decoded).unwrap(), body.len());
+        assert!(decoder.is_done());
+        assert_eq!(
+            String::from_utf8(decoded).unwrap(),
+            "event: state\ndata: Running\n\n: keep-alive\n\nevent: state\ndata: Paused\n\n"
+        );
+    }
+}
@@ -0,0 +1,308 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The timers of an `HttpServer`, for the work its `RequestHandler` defers.
+//!
+//! The handler schedules timers on the `Timers` of the server while it handles the requests,
+//! with `RequestHandler::handle_with_timers`, and the server calls
+//! `RequestHandler::handle_timer` once they expire. The server waits for the next one with a
+//! timer file descriptor in its epoll set, next to the connections, so that the deadlines are
+//! met on the same thread, such as for expiring tokens or refilling the budget of a rate limiter.
+//!
+//! ## Example
+//! ```no_run
+//! extern crate micro_http;
+//! use std::time::Duration;
+//! use micro_http::server::{HttpServer, RequestHandler};
+//! use micro_http::timer::{TimerId, Timers};
+//! use micro_http::{Method, Request, Response, StatusCode};
+//!
+//! // Accepts the requests for a minute after a `POST /unlock`.
+//! #[derive(Default)]
+//! struct Unlockable {
+//!     unlocked: Option<TimerId>,
+//! }
+//!
+//! impl RequestHandler for Unlockable {
+//!     fn handle(&mut self, request: &Request) -> Response {
+//!         match self.unlocked {
+//!             Some(_) => Response::for_request(request, StatusCode::OK),
+//!             None => Response::for_request(request, StatusCode::Forbidden),
+//!         }
+//!     }
+//!
+//!     fn handle_with_timers(&mut self, request: &Request, timers: &mut Timers) -> Response {
+//!         if *request.method() == Method::Post && request.uri().get_abs_path() == "/unlock" {
+//!             if let Some(timer) = self.unlocked.take() {
+//!                 timers.cancel(timer);
+//!             }
+//!             self.unlocked = Some(timers.schedule(Duration::from_secs(60)));
+//!         }
+//!         self.handle(request)
+//!     }
+//!
+//!     fn handle_timer(&mut self, timer: TimerId, _timers: &mut Timers) {
+//!         if self.unlocked == Some(timer) {
+//!             self.unlocked = None;
+//!         }
+//!     }
+//! }
+//!
+//! let mut server = HttpServer::new("/tmp/micro_http.socket", Unlockable::default()).unwrap();
+//! server.run().unwrap();
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Error as IoError, Result as IoResult};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::time::{Duration, Instant};
+
+use libc;
+
+/// Identifies a timer scheduled on `Timers`, when it expires or for cancelling it.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TimerId(u64);
+
+/// The pending timers of an `HttpServer`, which expire in the order of their deadlines.
+#[derive(Debug, Default)]
+pub struct Timers {
+    next_id: u64,
+    // The pending timers, by deadline, then in the order they were scheduled in.
+    queue: BTreeSet<(Instant, u64)>,
+    // The deadlines of the pending timers, for cancelling them.
+    deadlines: HashMap<u64, Instant>,
+}
+
+impl Timers {
+    /// Creates an empty set of timers.
+    pub fn new() -> Self {
+        Timers::default()
+    }
+
+    /// Schedules a timer which expires after `delay`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate micro_http;
+    /// use std::time::Duration;
+    /// use micro_http::timer::Timers;
+    ///
+    /// let mut timers = Timers::new();
+    /// let token_ttl = timers.schedule(Duration::from_secs(60));
+    /// assert_eq!(timers.len(), 1);
+    /// assert!(timers.cancel(token_ttl));
+    /// assert!(timers.is_empty());
+    /// ```
+    pub fn schedule(&mut self, delay: Duration) -> TimerId {
+        self.schedule_at(Instant::now() + delay)
+    }
+
+    /// Schedules a timer which expires at `deadline`, or as soon as possible if it has passed.
+    pub fn schedule_at(&mut self, deadline: Instant) -> TimerId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.queue.insert((deadline, id));
+        self.deadlines.insert(id, deadline);
+        TimerId(id)
+    }
+
+    /// Cancels `timer`, which then never expires.
+    ///
+    /// Returns `false` when the timer already expired, or was already cancelled.
+    pub fn cancel(&mut self, timer: TimerId) -> bool {
+        match self.deadlines.remove(&timer.0) {
+            Some(deadline) => self.queue.remove(&(deadline, timer.0)),
+            None => false,
+        }
+    }
+
+    /// Returns when `timer` expires, or `None` when it is not pending anymore.
+    pub fn deadline(&self, timer: TimerId) -> Option<Instant> {
+        self.deadlines.get(&timer.0).cloned()
+    }
+
+    /// Returns when the next timer expires, or `None` when there is none.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.queue.iter().next().map(|&(deadline, _)| deadline)
+    }
+
+    /// Returns the number of pending timers.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` when there is no pending timer.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    // Removes the next timer whose deadline is at or before `now`, if any.
+    pub(crate) fn pop_expired(&mut self, now: Instant) -> Option<TimerId> {
+        let next = match self.queue.iter().next() {
+            Some(&(deadline, id)) if deadline <= now => (deadline, id),
+            _ => return None,
+        };
+        self.queue.remove(&next);
+        self.deadlines.remove(&next.1);
+        Some(TimerId(next.1))
+    }
+}
+
+// A timer file descriptor, on the monotonic clock like `Instant`, which is readable once the
+// deadline it's armed for has passed.
+pub(crate) struct TimerFd {
+    fd: RawFd,
+}
+
+impl TimerFd {
+    pub(crate) fn new() -> IoResult<Self> {
+        // Safe because the arguments are valid, and the return value is checked.
+        let fd = unsafe {
+            libc::timerfd_create(
+                libc::CLOCK_MONOTONIC,
+                libc::TFD_NONBLOCK | libc::TFD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(IoError::last_os_error());
+        }
+        Ok(TimerFd { fd })
+    }
+
+    // Arms the timer for `deadline`, replacing the previous one, or disarms it with `None`.
+    pub(crate) fn arm(&self, deadline: Option<Instant>) -> IoResult<()> {
+        let value = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                // A zero value disarms the timer, so the deadlines which passed expire in 1ns.
+                if deadline > now {
+                    deadline - now
+                } else {
+                    Duration::new(0, 1)
+                }
+            }
+            None => Duration::new(0, 0),
+        };
+        // Safe because `itimerspec` is plain data, for which zeroes are valid.
+        let mut spec: libc::itimerspec = unsafe { mem::zeroed() };
+        spec.it_value.tv_sec = value.as_secs() as libc::time_t;
+        spec.it_value.tv_nsec = value.subsec_nanos() as libc::c_long;
+        // Safe because `spec` is valid, the old value is not written, and the return value is
+        // checked.
+        let ret = unsafe { libc::timerfd_settime(self.fd, 0, &spec, ptr::null_mut()) };
+        if ret < 0 {
+            return Err(IoError::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Clears the readiness of the timer, once it expired.
+    pub(crate) fn clear(&self) {
+        let mut expirations = 0u64;
+        // Safe because the kernel writes at most 8 bytes to `expirations`, which is valid for as
+        // many. There is nothing to read when the timer was re-armed since it expired, which is
+        // not an error, so the return value is ignored.
+        unsafe {
+            libc::read(
+                self.fd,
+                &mut expirations as *mut u64 as *mut libc::c_void,
+                mem::size_of::<u64>(),
+            );
+        }
+    }
+}
+
+impl AsRawFd for TimerFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for TimerFd {
+    fn drop(&mut self) {
+        // Safe because the file descriptor is owned by the timer, and not used anymore.
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_timers() {
+        let mut timers = Timers::new();
+        assert_eq!(timers.next_deadline(), None);
+        let now = Instant::now();
+        let later = timers.schedule_at(now + Duration::from_secs(1));
+        let first = timers.schedule_at(now);
+        let second = timers.schedule_at(now);
+        let cancelled = timers.schedule_at(now);
+        assert_eq!(timers.len(), 4);
+        assert_eq!(timers.next_deadline(), Some(now));
+        assert_eq!(timers.deadline(later), Some(now + Duration::from_secs(1)));
+
+        assert!(timers.cancel(cancelled));
+        assert!(!timers.cancel(cancelled));
+        assert_eq!(timers.deadline(cancelled), None);
+
+        // The timers with the same deadline expire in the order they were scheduled in.
+        assert_eq!(timers.pop_expired(now), Some(first));
+        assert_eq!(timers.pop_expired(now), Some(second));
+        assert_eq!(timers.pop_expired(now), None);
+        assert!(!timers.cancel(first));
+        assert_eq!(timers.next_deadline(), Some(now + Duration::from_secs(1)));
+        assert_eq!(
+            timers.pop_expired(now + Duration::from_secs(1)),
+            Some(later)
+        );
+        assert!(timers.is_empty());
+    }
+
+    fn is_readable(timer_fd: &TimerFd) -> bool {
+        let mut expirations = 0u64;
+        // Safe because the kernel writes at most 8 bytes to `expirations`.
+        let len = unsafe {
+            libc::read(
+                timer_fd.as_raw_fd(),
+                &mut expirations as *mut u64 as *mut libc::c_void,
+                mem::size_of::<u64>(),
+            )
+        };
+        len == mem::size_of::<u64>() as isize
+    }
+
+    #[test]
+    fn test_timer_fd() {
+        let timer_fd = TimerFd::new().unwrap();
+        assert!(!is_readable(&timer_fd));
+
+        // The deadlines which passed expire right away.
+        timer_fd.arm(Some(Instant::now())).unwrap();
+        thread::sleep(Duration::from_millis(1));
+        assert!(is_readable(&timer_fd));
+        assert!(!is_readable(&timer_fd));
+
+        timer_fd
+            .arm(Some(Instant::now() + Duration::from_millis(10)))
+            .unwrap();
+        assert!(!is_readable(&timer_fd));
+        thread::sleep(Duration::from_millis(20));
+        timer_fd.clear();
+        assert!(!is_readable(&timer_fd));
+
+        // A disarmed timer never expires.
+        timer_fd
+            .arm(Some(Instant::now() + Duration::from_millis(1)))
+            .unwrap();
+        timer_fd.arm(None).unwrap();
+        thread::sleep(Duration::from_millis(5));
+        assert!(!is_readable(&timer_fd));
+    }
+}
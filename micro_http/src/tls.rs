@@ -0,0 +1,376 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! TLS for the connections of an `HttpServer` bound to a TCP socket, with
+//! [rustls](https://github.com/ctz/rustls).
+//!
+//! A `TlsAcceptor` holds the certificate chain and the private key of the server, loaded from
+//! PEM files, and how the certificates of the clients are verified. The server encrypts all the
+//! connections it accepts with it, once it's passed to `HttpServer::new_tls`.
+//!
+//! This module is only built with the `tls` feature.
+//!
+//! ## Example
+//! ```no_run
+//! extern crate micro_http;
+//! use micro_http::router::Router;
+//! use micro_http::server::HttpServer;
+//! use micro_http::tls::{ClientAuth, TlsAcceptor};
+//!
+//! let acceptor = TlsAcceptor::new(
+//!     "/etc/firecracker/server.crt",
+//!     "/etc/firecracker/server.key",
+//!     ClientAuth::Required("/etc/firecracker/clients-ca.crt".into()),
+//! )
+//! .unwrap();
+//! let mut server = HttpServer::new_tls("127.0.0.1:8443", acceptor, Router::new()).unwrap();
+//! server.run().unwrap();
+//! ```
+
+use std::fs::File;
+use std::io::{BufReader, Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth, PrivateKey,
+    RootCertStore, ServerConfig, ServerSession, Session, TLSError,
+};
+
+/// Errors associated with loading the configuration of a `TlsAcceptor`.
+#[derive(Debug)]
+pub enum TlsError {
+    /// Cannot open a certificate or key file.
+    Io(IoError),
+    /// A certificate file has no valid PEM certificate.
+    InvalidCertificate(PathBuf),
+    /// The key file has no valid PEM private key, in the PKCS #8 or the RSA format.
+    InvalidPrivateKey(PathBuf),
+    /// The private key does not match the certificate, or it is not supported.
+    Config(TLSError),
+}
+
+/// How the certificates of the clients are verified.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientAuth {
+    /// The clients are not asked for a certificate.
+    None,
+    /// The clients may send a certificate, which has to be signed by one of the certificate
+    /// authorities in the PEM file.
+    Optional(PathBuf),
+    /// The clients must send a certificate signed by one of the certificate authorities in the
+    /// PEM file, otherwise the handshake fails.
+    Required(PathBuf),
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path).map(BufReader::new).map_err(TlsError::Io)
+}
+
+// Loads the private key in `path`, which is either a PKCS #8 key or an RSA one.
+fn load_private_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let mut keys = pkcs8_private_keys(&mut open(path)?)
+        .map_err(|_| TlsError::InvalidPrivateKey(path.to_path_buf()))?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(path)?)
+            .map_err(|_| TlsError::InvalidPrivateKey(path.to_path_buf()))?;
+    }
+    keys.into_iter()
+        .next()
+        .ok_or_else(|| TlsError::InvalidPrivateKey(path.to_path_buf()))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+    match roots.add_pem_file(&mut open(path)?) {
+        Ok((valid, _)) if valid > 0 => Ok(roots),
+        _ => Err(TlsError::InvalidCertificate(path.to_path_buf())),
+    }
+}
+
+/// The TLS configuration the connections of an `HttpServer` are accepted with.
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsAcceptor {
+    /// Loads the certificate chain in `cert_path`, starting with the certificate of the server,
+    /// and its private key in `key_path`, both in PEM files.
+    ///
+    /// # Errors
+    /// Returns `Io` when a file cannot be opened, `InvalidCertificate` or `InvalidPrivateKey`
+    /// when they don't hold what they should, and `Config` when the key is not the one of the
+    /// certificate.
+    pub fn new<C: AsRef<Path>, K: AsRef<Path>>(
+        cert_path: C,
+        key_path: K,
+        client_auth: ClientAuth,
+    ) -> Result<Self, TlsError> {
+        let cert_path = cert_path.as_ref();
+        let cert_chain = certs(&mut open(cert_path)?)
+            .map_err(|_| TlsError::InvalidCertificate(cert_path.to_path_buf()))?;
+        if cert_chain.is_empty() {
+            return Err(TlsError::InvalidCertificate(cert_path.to_path_buf()));
+        }
+        let key = load_private_key(key_path.as_ref())?;
+
+        let verifier = match client_auth {
+            ClientAuth::None => NoClientAuth::new(),
+            ClientAuth::Optional(ref path) => {
+                AllowAnyAnonymousOrAuthenticatedClient::new(load_roots(path)?)
+            }
+            ClientAuth::Required(ref path) => AllowAnyAuthenticatedClient::new(load_roots(path)?),
+        };
+        let mut config = ServerConfig::new(verifier);
+        config
+            .set_single_cert(cert_chain, key)
+            .map_err(TlsError::Config)?;
+        Ok(TlsAcceptor {
+            config: Arc::new(config),
+        })
+    }
+
+    // Starts the handshake of a connection accepted on the TCP socket.
+    pub(crate) fn accept(&self, sock: TcpStream) -> TlsStream {
+        TlsStream {
+            session: ServerSession::new(&self.config),
+            sock,
+        }
+    }
+}
+
+// A TCP connection encrypted with TLS, which can be non-blocking.
+//
+// The reads and the writes return `WouldBlock` like the ones of the socket, including during
+// the handshake, which progresses as the connection is served.
+pub(crate) struct TlsStream {
+    session: ServerSession,
+    sock: TcpStream,
+}
+
+impl TlsStream {
+    pub(crate) fn socket(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    // Returns `true` when encrypted bytes are waiting for the socket to be writable.
+    pub(crate) fn wants_write(&self) -> bool {
+        self.session.wants_write()
+    }
+
+    // Reads the records available on the socket, and answers the handshake messages among them.
+    //
+    // Returns the number of bytes read, which is 0 once the client closed the connection.
+    fn read_tls(&mut self) -> IoResult<usize> {
+        let len = self.session.read_tls(&mut self.sock)?;
+        if let Err(e) = self.session.process_new_packets() {
+            // The alert describing the error is sent before the connection is closed.
+            let _ = self.write_tls();
+            return Err(IoError::new(ErrorKind::InvalidData, e));
+        }
+        self.write_tls()?;
+        Ok(len)
+    }
+
+    // Writes as many of the pending records as the socket accepts without blocking.
+    fn write_tls(&mut self) -> IoResult<()> {
+        while self.session.wants_write() {
+            match self.session.write_tls(&mut self.sock) {
+                Ok(_) => (),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for TlsStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sock.as_raw_fd()
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        loop {
+            let len = self.session.read(buf)?;
+            if len > 0 {
+                return Ok(len);
+            }
+            // No plaintext is buffered, so more records are read from the socket.
+            if self.read_tls()? == 0 {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        // The plaintext is only encrypted once the previous records were written, so that the
+        // output of a slow client is bounded by the cap of the pending responses.
+        self.write_tls()?;
+        if self.session.wants_write() {
+            return Err(IoError::new(
+                ErrorKind::WouldBlock,
+                "the pending records were not written",
+            ));
+        }
+        let len = self.session.write(buf)?;
+        self.write_tls()?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.write_tls()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate webpki;
+
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use rustls::{ClientConfig, ClientSession, Stream};
+
+    use router::Router;
+    use server::HttpServer;
+    use {Body, Method, Response, StatusCode};
+
+    // The certificates of the test CA, and of the server and the client it signed for
+    // "localhost" and 127.0.0.1, with their keys.
+    fn resource(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("resources/tls")
+            .join(name)
+    }
+
+    fn tls_server(client_auth: ClientAuth) -> HttpServer<Router> {
+        let acceptor =
+            TlsAcceptor::new(resource("server.crt"), resource("server.key"), client_auth).unwrap();
+        let mut router = Router::new();
+        router.route(Method::Get, "/", |request, _| {
+            let mut response = Response::for_request(request, StatusCode::OK);
+            response.set_body(Body::new("secret"));
+            response
+        });
+        HttpServer::new_tls("127.0.0.1:0", acceptor, router).unwrap()
+    }
+
+    fn client_config(client_cert: bool) -> Arc<ClientConfig> {
+        let mut config = ClientConfig::new();
+        config
+            .root_store
+            .add_pem_file(&mut open(&resource("ca.crt")).unwrap())
+            .unwrap();
+        if client_cert {
+            let cert_chain = certs(&mut open(&resource("client.crt")).unwrap()).unwrap();
+            let key = load_private_key(&resource("client.key")).unwrap();
+            config.set_single_client_cert(cert_chain, key);
+        }
+        Arc::new(config)
+    }
+
+    fn send_request(
+        config: &Arc<ClientConfig>,
+        addr: SocketAddr,
+        request: &[u8],
+    ) -> IoResult<String> {
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        let mut session = ClientSession::new(config, dns_name);
+        let mut sock = TcpStream::connect(addr)?;
+        let mut stream = Stream::new(&mut session, &mut sock);
+        stream.write_all(request)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    // Sends `request` from another thread, since the client blocks, and serves it meanwhile.
+    fn round_trip(
+        server: &mut HttpServer<Router>,
+        config: Arc<ClientConfig>,
+        request: &'static [u8],
+    ) -> IoResult<String> {
+        let addr = server.tcp_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || sender.send(send_request(&config, addr, request)).unwrap());
+        loop {
+            server.poll(10).unwrap();
+            if let Ok(result) = receiver.try_recv() {
+                return result;
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut server = tls_server(ClientAuth::None);
+        let responses = round_trip(
+            &mut server,
+            client_config(false),
+            b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+        assert!(responses.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(responses.matches("secret").count(), 2);
+    }
+
+    #[test]
+    fn test_client_auth() {
+        let request = b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n";
+        let mut server = tls_server(ClientAuth::Required(resource("ca.crt")));
+        let responses = round_trip(&mut server, client_config(true), request).unwrap();
+        assert!(responses.contains("secret"));
+
+        // The handshake fails without a client certificate, before any request is handled.
+        match round_trip(&mut server, client_config(false), request) {
+            Ok(responses) => assert!(!responses.contains("secret")),
+            Err(_) => (),
+        }
+
+        // The certificate is optional otherwise.
+        let mut server = tls_server(ClientAuth::Optional(resource("ca.crt")));
+        for &client_cert in &[true, false] {
+            let responses = round_trip(&mut server, client_config(client_cert), request).unwrap();
+            assert!(responses.contains("secret"));
+        }
+    }
+
+    #[test]
+    fn test_acceptor_errors() {
+        let new = |cert: &str, key: &str, client_auth| {
+            TlsAcceptor::new(resource(cert), resource(key), client_auth)
+        };
+        match new("missing.crt", "server.key", ClientAuth::None) {
+            Err(TlsError::Io(_)) => (),
+            _ => panic!("a missing certificate should not be loaded"),
+        }
+        match new("server.key", "server.key", ClientAuth::None) {
+            Err(TlsError::InvalidCertificate(path)) => assert_eq!(path, resource("server.key")),
+            _ => panic!("a key should not be loaded as a certificate"),
+        }
+        match new("server.crt", "server.crt", ClientAuth::None) {
+            Err(TlsError::InvalidPrivateKey(path)) => assert_eq!(path, resource("server.crt")),
+            _ => panic!("a certificate should not be loaded as a key"),
+        }
+        match new(
+            "server.crt",
+            "server.key",
+            ClientAuth::Required(resource("client.key")),
+        ) {
+            Err(TlsError::InvalidCertificate(_)) => (),
+            _ => panic!("a key should not be loaded as a certificate authority"),
+        }
+        assert!(new("server.crt", "server.key", ClientAuth::None).is_ok());
+    }
+}
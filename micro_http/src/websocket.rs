@@ -0,0 +1,673 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Minimal implementation of the [WebSocket](https://tools.ietf.org/html/rfc6455) protocol.
+//!
+//! A WebSocket starts as an HTTP/1.1 GET request asking for an upgrade. `handshake` validates
+//! such a request and builds the `101 Switching Protocols` response which accepts it. From then
+//! on, both ends exchange `Frame`s over the same connection.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::websocket::{self, Frame, Opcode};
+//! use micro_http::{Request, StatusCode};
+//!
+//! let request = Request::try_from(
+//!     b"GET /console HTTP/1.1\r\n\
+//!       Upgrade: websocket\r\n\
+//!       Connection: Upgrade\r\n\
+//!       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+//!       Sec-WebSocket-Version: 13\r\n\r\n",
+//! )
+//! .unwrap();
+//! assert!(websocket::handshake(&request).unwrap().status() == StatusCode::SwitchingProtocols);
+//!
+//! // Frames sent by the client are masked.
+//! let (frame, len) = Frame::decode(&[0x81, 0x82, 0, 0, 0, 0, b'h', b'i'])
+//!     .unwrap()
+//!     .unwrap();
+//! assert_eq!(frame.opcode(), Opcode::Text);
+//! assert_eq!(frame.payload(), b"hi");
+//! assert_eq!(len, 8);
+//!
+//! // Frames larger than the limit are answered with a close frame before closing the connection.
+//! let error = Frame::decode_with_limit(&[0x82, 0x83, 0, 0, 0, 0, 1, 2, 3], 2).unwrap_err();
+//! assert_eq!(Frame::close(error.close_code()).payload(), &[0x03, 0xf1]);
+//! ```
+
+use std::io::{Error as WriteError, Write};
+
+use common::Version;
+use headers::{has_token, Header};
+use request::Request;
+use response::{Response, StatusCode};
+
+// Appended to the key of the client when computing the accept key.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// The only version of the protocol defined by RFC 6455.
+const VERSION: &str = "13";
+// Control frames can't carry more than 125 bytes.
+const MAX_CONTROL_PAYLOAD_LEN: u64 = 125;
+// The close status codes of RFC 6455, section 7.4.1.
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+/// The largest payload of the frames decoded by `Frame::decode`, which bounds the memory used for
+/// each frame sent by an untrusted client.
+pub const DEFAULT_MAX_PAYLOAD_LEN: usize = 1 << 20;
+
+/// Errors associated with the opening handshake.
+#[derive(Debug, PartialEq)]
+pub enum HandshakeError {
+    /// The request is not an HTTP/1.1 request.
+    InvalidHttpVersion,
+    /// The `Upgrade` or `Connection` headers do not ask for a WebSocket.
+    NotUpgrade,
+    /// The `Sec-WebSocket-Version` header is missing or it is not 13.
+    UnsupportedVersion,
+    /// The `Sec-WebSocket-Key` header is missing or it is not a base64 encoded 16 byte value.
+    InvalidKey,
+}
+
+/// Errors associated with decoding a frame.
+#[derive(Debug, PartialEq)]
+pub enum FrameError {
+    /// The frame uses reserved bits, which require an extension.
+    ReservedBits,
+    /// The opcode of the frame is reserved.
+    InvalidOpcode(u8),
+    /// The frame was not masked, as the frames sent by clients must be.
+    UnmaskedFrame,
+    /// A control frame is fragmented or carries more than 125 bytes.
+    InvalidControlFrame,
+    /// The payload length can not be represented on this host.
+    PayloadTooLarge,
+    /// The payload is larger than the limit of the decoder.
+    MessageTooBig,
+}
+
+impl FrameError {
+    /// Returns the status code of the close frame sent before closing the connection because of
+    /// this error: 1009 when the frame is too large to be decoded, or 1002 for a protocol error.
+    pub fn close_code(&self) -> u16 {
+        match *self {
+            FrameError::MessageTooBig => CLOSE_MESSAGE_TOO_BIG,
+            _ => CLOSE_PROTOCOL_ERROR,
+        }
+    }
+}
+
+/// Returns `true` if `request` asks for its connection to be upgraded to a WebSocket.
+pub fn is_upgrade_request(request: &Request) -> bool {
+    has_token(request.header(Header::Upgrade), "websocket")
+        && has_token(request.header(Header::Connection), "upgrade")
+}
+
+/// Validates the opening handshake of `request` and returns the response which accepts it.
+///
+/// # Errors
+/// Returns a `HandshakeError` when `request` is not a valid WebSocket upgrade request.
+pub fn handshake(request: &Request) -> Result<Response, HandshakeError> {
+    if request.http_version() != Version::Http11 {
+        return Err(HandshakeError::InvalidHttpVersion);
+    }
+    if !is_upgrade_request(request) {
+        return Err(HandshakeError::NotUpgrade);
+    }
+    if request.header(Header::SecWebSocketVersion) != Some(VERSION) {
+        return Err(HandshakeError::UnsupportedVersion);
+    }
+    let key = match request.header(Header::SecWebSocketKey) {
+        Some(key) if is_valid_key(key) => key,
+        _ => return Err(HandshakeError::InvalidKey),
+    };
+
+    let mut response = Response::new(Version::Http11, StatusCode::SwitchingProtocols);
+    response.add_header(Header::Upgrade, String::from("websocket"));
+    response.add_header(Header::Connection, String::from("Upgrade"));
+    response.add_header(Header::SecWebSocketAccept, accept_key(key));
+    Ok(response)
+}
+
+/// Computes the value of the `Sec-WebSocket-Accept` header for the `Sec-WebSocket-Key` of the
+/// client.
+pub fn accept_key(key: &str) -> String {
+    base64_encode(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+// The key is a base64 encoded 16 byte value, so it has 22 significant characters and 2 padding
+// characters.
+fn is_valid_key(key: &str) -> bool {
+    key.len() == 24
+        && key.ends_with("==")
+        && key[..22]
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'+' || byte == b'/')
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | u32::from(*byte) << (16 - 8 * i)
+        });
+        // Each input byte produces at least one output character, the rest is padding.
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(
+                    ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize],
+                ));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// SHA-1 is broken as a cryptographic hash, but RFC 6455 uses it to prove that the server
+// understood the handshake, not for security.
+fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [
+        0x6745_2301,
+        0xefcd_ab89,
+        0x98ba_dcfe,
+        0x1032_5476,
+        0xc3d2_e1f0,
+    ];
+
+    // The message is padded with a 1 bit, then with zeros up to 8 bytes short of a block, then
+    // with its length in bits.
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bit_len = (bytes.len() as u64).wrapping_mul(8);
+    for i in (0..8).rev() {
+        message.push((bit_len >> (8 * i)) as u8);
+    }
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for i in 0..16 {
+            words[i] = block[4 * i..4 * i + 4]
+                .iter()
+                .fold(0, |word, byte| word << 8 | u32::from(*byte));
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) =
+            (state[0], state[1], state[2], state[3], state[4]);
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (value, new_value) in state.iter_mut().zip(&[a, b, c, d, e]) {
+            *value = value.wrapping_add(*new_value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (i, value) in state.iter().enumerate() {
+        for j in 0..4 {
+            digest[4 * i + j] = (value >> (24 - 8 * j)) as u8;
+        }
+    }
+    digest
+}
+
+/// The type of a WebSocket frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Opcode {
+    /// A fragment of a message, following the first frame of the message.
+    Continuation,
+    /// A UTF-8 text message.
+    Text,
+    /// A binary message.
+    Binary,
+    /// A request to close the connection.
+    Close,
+    /// A keep-alive request, which is answered with a `Pong` carrying the same payload.
+    Ping,
+    /// The answer to a `Ping`.
+    Pong,
+}
+
+impl Opcode {
+    fn try_from(value: u8) -> Option<Self> {
+        match value {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xa => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn raw(&self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+
+    /// Returns `true` for the opcodes of control frames.
+    pub fn is_control(&self) -> bool {
+        self.raw() & 0x8 != 0
+    }
+}
+
+/// Wrapper over a WebSocket frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    fin: bool,
+    opcode: Opcode,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Creates a new `Frame`. `fin` is `false` for all the frames of a fragmented message but
+    /// the last one.
+    pub fn new(fin: bool, opcode: Opcode, payload: Vec<u8>) -> Self {
+        Frame {
+            fin,
+            opcode,
+            payload,
+        }
+    }
+
+    /// Creates a close `Frame` carrying the status `code`, such as the `close_code` of a
+    /// `FrameError`.
+    pub fn close(code: u16) -> Self {
+        Frame::new(true, Opcode::Close, vec![(code >> 8) as u8, code as u8])
+    }
+
+    /// Decodes the first frame sent by a client in `bytes`, with a payload of up to
+    /// `DEFAULT_MAX_PAYLOAD_LEN` bytes.
+    ///
+    /// Returns the frame together with the number of bytes it takes, or `None` when `bytes` does
+    /// not hold the complete frame yet.
+    ///
+    /// # Errors
+    /// Returns a `FrameError` when the frame is invalid. The connection has to be closed
+    /// afterwards, since the end of the frame is unknown.
+    pub fn decode(bytes: &[u8]) -> Result<Option<(Frame, usize)>, FrameError> {
+        Frame::decode_with_limit(bytes, DEFAULT_MAX_PAYLOAD_LEN)
+    }
+
+    /// Decodes the first frame sent by a client in `bytes`, like `decode`, with a payload of up
+    /// to `max_payload_len` bytes.
+    ///
+    /// # Errors
+    /// Returns `MessageTooBig` as soon as the header announces a larger payload, without waiting
+    /// for the payload, and the other errors of `decode`.
+    pub fn decode_with_limit(
+        bytes: &[u8],
+        max_payload_len: usize,
+    ) -> Result<Option<(Frame, usize)>, FrameError> {
+        if bytes.len() < 2 {
+            return Ok(None);
+        }
+        if bytes[0] & 0x70 != 0 {
+            return Err(FrameError::ReservedBits);
+        }
+        let fin = bytes[0] & 0x80 != 0;
+        let opcode = Opcode::try_from(bytes[0] & 0x0f)
+            .ok_or_else(|| FrameError::InvalidOpcode(bytes[0] & 0x0f))?;
+        if bytes[1] & 0x80 == 0 {
+            return Err(FrameError::UnmaskedFrame);
+        }
+
+        // The payload length takes 7 bits, or 16 or 64 more bits when it doesn't fit.
+        let (payload_len, header_len) = match bytes[1] & 0x7f {
+            126 if bytes.len() < 4 => return Ok(None),
+            127 if bytes.len() < 10 => return Ok(None),
+            126 => (u64::from(bytes[2]) << 8 | u64::from(bytes[3]), 4),
+            127 => (
+                bytes[2..10]
+                    .iter()
+                    .fold(0, |len, byte| len << 8 | u64::from(*byte)),
+                10,
+            ),
+            len => (u64::from(len), 2),
+        };
+        if opcode.is_control() && (!fin || payload_len > MAX_CONTROL_PAYLOAD_LEN) {
+            return Err(FrameError::InvalidControlFrame);
+        }
+        // The most significant bit of a 64 bit length must be 0.
+        if payload_len >> 63 != 0 || payload_len > (usize::max_value() - header_len - 4) as u64 {
+            return Err(FrameError::PayloadTooLarge);
+        }
+        if payload_len > max_payload_len as u64 {
+            return Err(FrameError::MessageTooBig);
+        }
+
+        let payload_start = header_len + 4;
+        let frame_len = payload_start + payload_len as usize;
+        if bytes.len() < frame_len {
+            return Ok(None);
+        }
+        let mask = &bytes[header_len..payload_start];
+        let payload = bytes[payload_start..frame_len]
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ mask[i % 4])
+            .collect();
+
+        Ok(Some((
+            Frame {
+                fin,
+                opcode,
+                payload,
+            },
+            frame_len,
+        )))
+    }
+
+    /// Writes the `Frame` to `buf`, as sent by a server, without masking it.
+    pub fn write_all<T: Write>(&self, buf: &mut T) -> Result<(), WriteError> {
+        let fin = if self.fin { 0x80 } else { 0 };
+        buf.write_all(&[fin | self.opcode.raw()])?;
+
+        let len = self.payload.len();
+        if len < 126 {
+            buf.write_all(&[len as u8])?;
+        } else if len <= 0xffff {
+            buf.write_all(&[126, (len >> 8) as u8, len as u8])?;
+        } else {
+            buf.write_all(&[127])?;
+            for i in (0..8).rev() {
+                buf.write_all(&[(len as u64 >> (8 * i)) as u8])?;
+            }
+        }
+        buf.write_all(&self.payload)
+    }
+
+    /// Returns `true` if this is the last frame of a message.
+    pub fn fin(&self) -> bool {
+        self.fin
+    }
+
+    /// Returns the `Opcode` of the `Frame`.
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    /// Returns the payload of the `Frame`, already unmasked.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Masks `payload` the way a client does.
+    fn client_frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut bytes = vec![first_byte];
+        if payload.len() < 126 {
+            bytes.push(0x80 | payload.len() as u8);
+        } else {
+            bytes.extend_from_slice(&[0x80 | 126, (payload.len() >> 8) as u8, payload.len() as u8]);
+        }
+        bytes.extend_from_slice(&mask);
+        bytes.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        bytes
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // The example from RFC 6455.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_sha1_and_base64() {
+        assert_eq!(base64_encode(&sha1(b"")), "2jmj7l5rSw0yVb/vlWAYkK/YBwk=");
+        assert_eq!(base64_encode(&sha1(b"abc")), "qZk+NkcGgWq6PiVxeFDCbJzQ2J0=");
+        // A message longer than a block.
+        assert_eq!(
+            base64_encode(&sha1(&[b'a'; 100])),
+            "f5AAJXpJGNcHJlXqRoVAzcvULgw="
+        );
+
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+    }
+
+    #[test]
+    fn test_handshake() {
+        let request_bytes = b"GET /console HTTP/1.1\r\n\
+                              Host: localhost\r\n\
+                              upgrade: WebSocket\r\n\
+                              Connection: keep-alive, Upgrade\r\n\
+                              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                              Sec-WebSocket-Version: 13\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        assert!(is_upgrade_request(&request));
+
+        let response = handshake(&request).unwrap();
+        assert!(response.status() == StatusCode::SwitchingProtocols);
+        assert!(response.body().is_none());
+        let mut response_buf = Vec::new();
+        response.write_all(&mut response_buf).unwrap();
+        let response_str = String::from_utf8(response_buf).unwrap();
+        assert!(response_str.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response_str.contains("Upgrade: websocket\r\n"));
+        assert!(response_str.contains("Connection: Upgrade\r\n"));
+        assert!(response_str.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(response_str.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_handshake_errors() {
+        let request = Request::try_from(b"GET /console HTTP/1.0\r\n").unwrap();
+        assert_eq!(
+            handshake(&request).unwrap_err(),
+            HandshakeError::InvalidHttpVersion
+        );
+
+        let request =
+            Request::try_from(b"GET /console HTTP/1.1\r\nUpgrade: websocket\r\n\r\n").unwrap();
+        assert!(!is_upgrade_request(&request));
+        assert_eq!(handshake(&request).unwrap_err(), HandshakeError::NotUpgrade);
+
+        let request = Request::try_from(
+            b"GET /console HTTP/1.1\r\n\
+              Upgrade: websocket\r\n\
+              Connection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+              Sec-WebSocket-Version: 8\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            handshake(&request).unwrap_err(),
+            HandshakeError::UnsupportedVersion
+        );
+
+        for key in &[
+            "",
+            "dGhlIHNhbXBsZSBub25jZQ",
+            "dGhlIHNhbXBsZSBub25j*Q==",
+            "dGhlIHNhbXBsZSBub25jZQA",
+        ] {
+            let request_bytes = format!(
+                "GET /console HTTP/1.1\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Key: {}\r\n\
+                 Sec-WebSocket-Version: 13\r\n\r\n",
+                key
+            );
+            let request = Request::try_from(request_bytes.as_bytes()).unwrap();
+            assert_eq!(handshake(&request).unwrap_err(), HandshakeError::InvalidKey);
+        }
+    }
+
+    #[test]
+    fn test_decode_frame() {
+        // Incomplete frames.
+        let bytes = client_frame(0x81, b"Hello");
+        for len in 0..bytes.len() {
+            assert_eq!(Frame::decode(&bytes[..len]), Ok(None));
+        }
+
+        // A complete frame, followed by the beginning of the next one.
+        let mut bytes = client_frame(0x81, b"Hello");
+        bytes.push(0x82);
+        let (frame, len) = Frame::decode(&bytes).unwrap().unwrap();
+        assert_eq!(frame, Frame::new(true, Opcode::Text, b"Hello".to_vec()));
+        assert_eq!(len, bytes.len() - 1);
+
+        // A fragment with a 16 bit payload length.
+        let payload = vec![0xaa; 300];
+        let bytes = client_frame(0x02, &payload);
+        let (frame, len) = Frame::decode(&bytes).unwrap().unwrap();
+        assert!(!frame.fin());
+        assert_eq!(frame.opcode(), Opcode::Binary);
+        assert_eq!(frame.payload(), payload.as_slice());
+        assert_eq!(len, 2 + 2 + 4 + 300);
+
+        // A 64 bit payload length is only complete with all its bytes.
+        assert_eq!(Frame::decode(&[0x82, 0x80 | 127, 0, 0, 0, 0]), Ok(None));
+
+        // Error cases.
+        assert_eq!(
+            Frame::decode(&client_frame(0xc1, b"")),
+            Err(FrameError::ReservedBits)
+        );
+        assert_eq!(
+            Frame::decode(&client_frame(0x83, b"")),
+            Err(FrameError::InvalidOpcode(0x3))
+        );
+        assert_eq!(Frame::decode(&[0x81, 0x00]), Err(FrameError::UnmaskedFrame));
+        assert_eq!(
+            Frame::decode(&client_frame(0x09, b"")),
+            Err(FrameError::InvalidControlFrame)
+        );
+        assert_eq!(
+            Frame::decode(&client_frame(0x89, &[0; 126])),
+            Err(FrameError::InvalidControlFrame)
+        );
+        assert_eq!(
+            Frame::decode(&[0x82, 0x80 | 127, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Err(FrameError::PayloadTooLarge)
+        );
+        assert_eq!(FrameError::PayloadTooLarge.close_code(), 1002);
+    }
+
+    #[test]
+    fn test_decode_frame_limit() {
+        let bytes = client_frame(0x82, &[0xaa; 300]);
+        assert!(Frame::decode_with_limit(&bytes, 300).unwrap().is_some());
+        assert_eq!(
+            Frame::decode_with_limit(&bytes, 299),
+            Err(FrameError::MessageTooBig)
+        );
+        // The frame is rejected from its header, before the payload is received.
+        assert_eq!(
+            Frame::decode_with_limit(&bytes[..4], 299),
+            Err(FrameError::MessageTooBig)
+        );
+
+        // The default limit.
+        let mut bytes = vec![0x82, 0x80 | 127, 0, 0, 0, 0, 0, 0x10, 0, 0];
+        assert_eq!(Frame::decode(&bytes), Ok(None));
+        bytes[9] = 1;
+        assert_eq!(Frame::decode(&bytes), Err(FrameError::MessageTooBig));
+
+        // The error is answered with a close frame carrying the 1009 status code.
+        let error = FrameError::MessageTooBig;
+        assert_eq!(error.close_code(), 1009);
+        let mut buf = Vec::new();
+        Frame::close(error.close_code())
+            .write_all(&mut buf)
+            .unwrap();
+        assert_eq!(buf, [0x88, 0x02, 0x03, 0xf1]);
+    }
+
+    #[test]
+    fn test_write_frame() {
+        let mut buf = Vec::new();
+        Frame::new(true, Opcode::Pong, b"ping".to_vec())
+            .write_all(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"\x8a\x04ping");
+
+        let mut buf = Vec::new();
+        Frame::new(false, Opcode::Binary, vec![0xaa; 300])
+            .write_all(&mut buf)
+            .unwrap();
+        assert_eq!(&buf[..4], &[0x02, 126, 0x01, 0x2c]);
+        assert_eq!(buf.len(), 4 + 300);
+
+        let mut buf = Vec::new();
+        Frame::new(true, Opcode::Text, vec![b'a'; 0x1_0000])
+            .write_all(&mut buf)
+            .unwrap();
+        assert_eq!(&buf[..10], &[0x81, 127, 0, 0, 0, 0, 0, 0x01, 0, 0]);
+        assert_eq!(buf.len(), 10 + 0x1_0000);
+
+        // Test write failed.
+        let mut buf = [0u8; 3];
+        assert!(Frame::new(true, Opcode::Text, b"text".to_vec())
+            .write_all(&mut buf.as_mut())
+            .is_err());
+    }
+
+    #[test]
+    fn test_opcode() {
+        for value in 0..16 {
+            match Opcode::try_from(value) {
+                Some(opcode) => assert_eq!(opcode.raw(), value),
+                None => {
+                    assert!([0x3, 0x4, 0x5, 0x6, 0x7, 0xb, 0xc, 0xd, 0xe, 0xf].contains(&value))
+                }
+            }
+        }
+        assert!(!Opcode::Text.is_control());
+        assert!(Opcode::Close.is_control());
+        assert!(Opcode::Pong.is_control());
+    }
+}
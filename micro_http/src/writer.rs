@@ -0,0 +1,392 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Resumable writing of responses to non-blocking connections.
+//!
+//! A slow reader must not block the event loop serving the other connections, so the responses
+//! of a connection are queued in a `ResponseWriter`, which writes as much as the connection
+//! accepts and keeps the rest for later. While `ResponseWriter::is_empty` returns `false` after
+//! a write, the connection should be registered for `EPOLLOUT`, and written to again when it
+//! becomes writable.
+//!
+//! The pending output of each connection is capped, so that a client which sends requests
+//! without reading the responses can't make the server buffer an unbounded amount of data.
+//!
+//! The bodies of unknown length, such as a live feed, are streamed from a source set with
+//! `Response::set_body_stream`. They are read one chunk at a time, once the connection accepted
+//! all the output before them.
+//!
+//! ## Example
+//! ```
+//! extern crate micro_http;
+//! use micro_http::writer::ResponseWriter;
+//! use micro_http::{Body, Response, StatusCode, Version};
+//!
+//! let mut response = Response::new(Version::Http11, StatusCode::OK);
+//! response.set_body(Body::new("This is a test"));
+//!
+//! let mut writer = ResponseWriter::new(4096);
+//! writer.push(&response).unwrap();
+//! let mut connection = Vec::new();
+//! writer.write_to(&mut connection).unwrap();
+//! assert!(writer.is_empty());
+//! assert!(connection.ends_with(b"This is a test"));
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{Error as WriteError, ErrorKind, Read, Write};
+
+use chunked::ChunkedWriter;
+use response::{BodyStream, Response};
+
+// The maximum number of bytes read at once from a body stream, and sent as one chunk.
+const STREAM_CHUNK_SIZE: usize = 4096;
+
+/// Errors associated with queueing responses in a `ResponseWriter`.
+#[derive(Debug, PartialEq)]
+pub enum WriterError {
+    /// Queueing the response would exceed the cap of the pending output. The response was
+    /// not queued.
+    OutputBufferFull,
+}
+
+// The output of a connection, in the order it is written.
+enum Output {
+    // Serialized responses, of which the given number of bytes were already written.
+    Bytes(Vec<u8>, usize),
+    // A streamed body, which is read in chunks once the output before it is written.
+    Stream(BodyStream),
+}
+
+// Reads the next chunk of a streamed body, and returns it framed as a chunk, with `true` when it
+// is the last one.
+fn read_chunk(source: &mut BodyStream) -> Result<(Vec<u8>, bool), WriteError> {
+    let mut data = [0u8; STREAM_CHUNK_SIZE];
+    let len = loop {
+        match source.0.read(&mut data) {
+            Ok(len) => break len,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    };
+
+    let mut chunk = Vec::new();
+    if len == 0 {
+        ChunkedWriter::new(&mut chunk).finish(&[])?;
+        Ok((chunk, true))
+    } else {
+        ChunkedWriter::new(&mut chunk).write_all(&data[..len])?;
+        Ok((chunk, false))
+    }
+}
+
+/// Queue of serialized responses, written to a non-blocking connection as it accepts them.
+pub struct ResponseWriter {
+    queue: VecDeque<Output>,
+    // The number of bytes of the queue which were not written yet.
+    pending: usize,
+    max_pending: usize,
+}
+
+impl ResponseWriter {
+    /// Creates an empty `ResponseWriter`, which holds at most `max_pending` bytes of output.
+    pub fn new(max_pending: usize) -> Self {
+        ResponseWriter {
+            queue: VecDeque::new(),
+            pending: 0,
+            max_pending,
+        }
+    }
+
+    /// Serializes `response` after the pending output.
+    ///
+    /// The body stream of `response`, if it has one, is not written: such responses are queued
+    /// with `push_streamed`.
+    ///
+    /// # Errors
+    /// Returns `OutputBufferFull`, leaving the pending output unchanged, when the cap of the
+    /// pending output would be exceeded.
+    pub fn push(&mut self, response: &Response) -> Result<(), WriterError> {
+        let mut bytes = Vec::new();
+        // The unwrap is safe because a Vec will allocate more space until all the writes succeed.
+        response.write_all(&mut bytes).unwrap();
+        self.push_bytes(&bytes)
+    }
+
+    /// Queues `bytes` after the pending output, such as the chunks of a body written as the
+    /// application produces them.
+    ///
+    /// # Errors
+    /// Returns `OutputBufferFull`, leaving the pending output unchanged, when the cap of the
+    /// pending output would be exceeded.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> Result<(), WriterError> {
+        if self.pending + bytes.len() > self.max_pending {
+            return Err(WriterError::OutputBufferFull);
+        }
+
+        self.pending += bytes.len();
+        if let Some(&mut Output::Bytes(ref mut buf, ref mut written)) = self.queue.back_mut() {
+            // Drop the output which was already written, so that it is not kept in memory.
+            buf.drain(..*written);
+            *written = 0;
+            buf.extend_from_slice(bytes);
+            return Ok(());
+        }
+        self.queue.push_back(Output::Bytes(bytes.to_vec(), 0));
+        Ok(())
+    }
+
+    /// Serializes `response` after the pending output, like `push`, followed by its body stream
+    /// if it has one.
+    ///
+    /// The body stream is read in chunks once the output before it is written, so only the
+    /// chunk being written counts towards the cap of the pending output.
+    ///
+    /// # Errors
+    /// Returns `OutputBufferFull`, leaving the pending output unchanged, when the cap of the
+    /// pending output would be exceeded.
+    pub fn push_streamed(&mut self, mut response: Response) -> Result<(), WriterError> {
+        let body_stream = response.take_body_stream();
+        self.push(&response)?;
+        if let Some(body_stream) = body_stream {
+            self.queue.push_back(Output::Stream(body_stream));
+        }
+        Ok(())
+    }
+
+    /// Writes as much of the pending output to `out` as it accepts without blocking.
+    ///
+    /// Returns the number of bytes written. Some output can still be pending afterwards, if
+    /// `out` would have blocked.
+    ///
+    /// # Errors
+    /// Returns the errors of `out`, other than `WouldBlock` and `Interrupted`, or `WriteZero`
+    /// when `out` does not accept any more bytes. The errors of the body streams, other than
+    /// `Interrupted`, are returned too, because the responses can't be completed anymore.
+    pub fn write_to<T: Write>(&mut self, out: &mut T) -> Result<usize, WriteError> {
+        let mut len = 0;
+        loop {
+            let chunk = match self.queue.front_mut() {
+                Some(&mut Output::Bytes(ref buf, ref mut written)) => {
+                    while *written < buf.len() {
+                        match out.write(&buf[*written..]) {
+                            Ok(0) => {
+                                return Err(WriteError::new(
+                                    ErrorKind::WriteZero,
+                                    "failed to write the pending response",
+                                ))
+                            }
+                            Ok(written_len) => {
+                                *written += written_len;
+                                self.pending -= written_len;
+                                len += written_len;
+                            }
+                            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(len),
+                            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    None
+                }
+                Some(&mut Output::Stream(ref mut source)) => Some(read_chunk(source)?),
+                None => return Ok(len),
+            };
+
+            match chunk {
+                // The front of the queue was written.
+                None => {
+                    self.queue.pop_front();
+                }
+                Some((chunk, is_last)) => {
+                    self.pending += chunk.len();
+                    if is_last {
+                        self.queue.pop_front();
+                    }
+                    self.queue.push_front(Output::Bytes(chunk, 0));
+                }
+            }
+        }
+    }
+
+    /// Returns the number of bytes of output which were not written yet, without the body
+    /// streams which were not read yet.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Returns `true` when all the queued responses were written, with their body streams.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chunked::ChunkedDecoder;
+    use common::{Body, Version};
+    use parser;
+    use response::StatusCode;
+    use std::io::Cursor;
+
+    // A connection which accepts a limited number of bytes before it would block.
+    struct SlowConnection {
+        data: Vec<u8>,
+        capacity: usize,
+        interrupted: bool,
+    }
+
+    impl Write for SlowConnection {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, WriteError> {
+            if self.interrupted {
+                self.interrupted = false;
+                return Err(WriteError::new(ErrorKind::Interrupted, "interrupted"));
+            }
+            let len = buf.len().min(self.capacity - self.data.len()).min(10);
+            if len == 0 {
+                return Err(WriteError::new(ErrorKind::WouldBlock, "would block"));
+            }
+            self.data.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> Result<(), WriteError> {
+            Ok(())
+        }
+    }
+
+    fn response(body: &str) -> (Response, Vec<u8>) {
+        let mut response = Response::new(Version::Http11, StatusCode::OK);
+        response.set_body(Body::new(body));
+        let mut bytes = Vec::new();
+        response.write_all(&mut bytes).unwrap();
+        (response, bytes)
+    }
+
+    #[test]
+    fn test_write_to() {
+        let (response, bytes) = response("This is a test");
+        let mut writer = ResponseWriter::new(bytes.len() * 2);
+        writer.push(&response).unwrap();
+        assert_eq!(writer.pending(), bytes.len());
+
+        // The connection accepts some of the bytes, then it would block.
+        let mut connection = SlowConnection {
+            data: Vec::new(),
+            capacity: 25,
+            interrupted: true,
+        };
+        assert_eq!(writer.write_to(&mut connection).unwrap(), 25);
+        assert_eq!(writer.pending(), bytes.len() - 25);
+        assert!(!writer.is_empty());
+        assert_eq!(writer.write_to(&mut connection).unwrap(), 0);
+
+        // The connection became writable again.
+        connection.capacity = usize::max_value();
+        assert_eq!(writer.write_to(&mut connection).unwrap(), bytes.len() - 25);
+        assert!(writer.is_empty());
+        // The Date header may differ between the two serializations.
+        assert_eq!(connection.data.len(), bytes.len());
+        assert!(connection.data.ends_with(b"This is a test"));
+        assert_eq!(writer.write_to(&mut connection).unwrap(), 0);
+
+        // A connection which doesn't accept any more bytes is an error.
+        writer.push(&response).unwrap();
+        let mut full_buf = [0u8; 0];
+        assert_eq!(
+            writer.write_to(&mut full_buf.as_mut()).unwrap_err().kind(),
+            ErrorKind::WriteZero
+        );
+    }
+
+    #[test]
+    fn test_body_stream() {
+        let body: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        let mut streamed = Response::new(Version::Http11, StatusCode::OK);
+        assert!(streamed.set_body_stream(Cursor::new(body.clone())));
+        let (next_response, next_bytes) = response("This is a test");
+
+        let mut writer = ResponseWriter::new(2 * next_bytes.len());
+        writer.push_streamed(streamed).unwrap();
+        writer.push(&next_response).unwrap();
+        assert!(!writer.is_empty());
+
+        let mut connection = SlowConnection {
+            data: Vec::new(),
+            capacity: 100,
+            interrupted: false,
+        };
+        writer.write_to(&mut connection).unwrap();
+        // The chunks are read as they are written.
+        assert!(writer.pending() < STREAM_CHUNK_SIZE + 10 + next_bytes.len());
+        connection.capacity = usize::max_value();
+        writer.write_to(&mut connection).unwrap();
+        assert!(writer.is_empty());
+        assert_eq!(writer.pending(), 0);
+
+        // The body is followed by the next response.
+        let head_len = parser::header_section_len(&connection.data).unwrap();
+        assert!(String::from_utf8_lossy(&connection.data[..head_len])
+            .contains("Transfer-Encoding: chunked\r\n"));
+        let mut decoder = ChunkedDecoder::new();
+        let mut decoded = Vec::new();
+        let body_len = decoder
+            .decode(&connection.data[head_len..], &mut decoded)
+            .unwrap();
+        assert!(decoder.is_done());
+        assert_eq!(decoded, body);
+        assert_eq!(
+            connection.data[head_len + body_len..].len(),
+            next_bytes.len()
+        );
+
+        // The errors of the source are returned.
+        struct FailingSource;
+        impl Read for FailingSource {
+            fn read(&mut self, _: &mut [u8]) -> Result<usize, WriteError> {
+                Err(WriteError::new(ErrorKind::Other, "failed"))
+            }
+        }
+        let mut streamed = Response::new(Version::Http11, StatusCode::OK);
+        assert!(streamed.set_body_stream(FailingSource));
+        writer.push_streamed(streamed).unwrap();
+        assert_eq!(
+            writer.write_to(&mut Vec::new()).unwrap_err().kind(),
+            ErrorKind::Other
+        );
+
+        // HTTP/1.0 does not support chunked bodies.
+        let mut streamed = Response::new(Version::Http10, StatusCode::OK);
+        assert!(!streamed.set_body_stream(Cursor::new(body)));
+    }
+
+    #[test]
+    fn test_output_buffer_cap() {
+        let (response, bytes) = response("This is a test");
+        let mut writer = ResponseWriter::new(bytes.len() + 10);
+        writer.push(&response).unwrap();
+        assert_eq!(
+            writer.push(&response).unwrap_err(),
+            WriterError::OutputBufferFull
+        );
+        assert_eq!(writer.pending(), bytes.len());
+
+        // The output which was written does not count towards the cap.
+        let mut connection = SlowConnection {
+            data: Vec::new(),
+            capacity: bytes.len() - 5,
+            interrupted: false,
+        };
+        writer.write_to(&mut connection).unwrap();
+        writer.push(&response).unwrap();
+        assert_eq!(writer.pending(), bytes.len() + 5);
+
+        // Raw bytes are capped the same way.
+        assert_eq!(
+            writer.push_bytes(&[0u8; 6]).unwrap_err(),
+            WriterError::OutputBufferFull
+        );
+        writer.push_bytes(&[0u8; 5]).unwrap();
+        assert_eq!(writer.pending(), bytes.len() + 10);
+    }
+}
@@ -1,27 +1,114 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::BTreeMap;
+use std::net::Ipv6Addr;
+
 use json_patch::merge;
+use micro_http::host::AllowedHosts;
+use net_util::MacAddr;
 use serde_json::Value;
 
+/// The default limit on the size of the data store, and of the user data, in bytes.
+pub const DEFAULT_DATA_STORE_LIMIT: usize = 51200;
+
+/// The maximum number of versions of the metadata stored apart from the latest one.
+pub const MAX_VERSIONS: usize = 16;
+
+/// The variable holding the ID of the instance, for the `{{instance_id}}` placeholders.
+pub const INSTANCE_ID_VARIABLE: &str = "instance_id";
+/// The variable holding the time the microVM was started at, for the `{{launch_time}}`
+/// placeholders.
+pub const LAUNCH_TIME_VARIABLE: &str = "launch_time";
+
+// The key of the data store holding the current version of the metadata.
+const LATEST_VERSION: &str = "latest";
+// The name the user data goes by in a version of the metadata.
+const USER_DATA_KEY: &str = "user-data";
+
+// A snapshot of the metadata and of the user data, as of a version.
+#[derive(Clone)]
+struct Version {
+    metadata: Value,
+    user_data: Option<Vec<u8>>,
+}
+
 /// The Mmds is the Microvm Metadata Service represented as an untyped json.
 #[derive(Clone)]
 pub struct Mmds {
     data_store: Value,
     is_initialized: bool,
+    // The hosts the guest can send requests for, or `None` for any host.
+    allowed_hosts: Option<AllowedHosts>,
+    // The MAC addresses the guest can send requests from, or `None` for any address.
+    allowed_mac_addrs: Option<Vec<MacAddr>>,
+    // The IPv6 link-local address the MMDS answers on, or `None` for the default one.
+    ipv6_addr: Option<Ipv6Addr>,
+    // The TTL, or IPv6 hop limit, of the packets carrying the responses, or `None` for the
+    // default one.
+    hop_limit: Option<u8>,
+    // The opaque user data of the guest, apart from the metadata.
+    user_data: Option<Vec<u8>>,
+    // The maximum size of the data store serialized as JSON, and of the user data, in bytes.
+    data_store_limit: usize,
+    // The versions of the metadata the guest can get apart from the latest one, by name.
+    versions: BTreeMap<String, Version>,
+    // The values substituted for the "{{name}}" placeholders of the metadata, by name.
+    variables: BTreeMap<String, String>,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
+    DataStoreLimitExceeded,
+    InvalidVersion,
     NotFound,
+    TooManyVersions,
     UnsupportedValueType,
 }
 
+/// The formats the metadata can be sent to the guests in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// The value serialized as JSON, whatever its type.
+    Json,
+    /// The plain text format of IMDS, as returned by `Mmds::get_value`, with one line per
+    /// key or index of a dictionary or an array, and the leaves printed raw.
+    Imds,
+}
+
+// Returns the value named `segment` in `value`, which is either a key of a dictionary or an index
+// of an array. A leaf has no children, so the paths going through it are not found.
+fn child<'a>(value: &'a Value, segment: &str) -> Option<&'a Value> {
+    match *value {
+        Value::Object(ref map) => map.get(segment),
+        // The indices are decimal, without a sign nor leading zeros, so that an element is only
+        // named by one path.
+        Value::Array(ref array)
+            if segment.bytes().all(|b| b.is_ascii_digit())
+                && (segment == "0" || !segment.starts_with('0')) =>
+        {
+            segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| array.get(index))
+        }
+        _ => None,
+    }
+}
+
 impl Default for Mmds {
     fn default() -> Self {
         Mmds {
             data_store: Value::default(),
             is_initialized: false,
+            allowed_hosts: None,
+            allowed_mac_addrs: None,
+            ipv6_addr: None,
+            hop_limit: None,
+            user_data: None,
+            data_store_limit: DEFAULT_DATA_STORE_LIMIT,
+            versions: BTreeMap::new(),
+            variables: BTreeMap::new(),
         }
     }
 }
@@ -35,15 +122,268 @@ impl Mmds {
         return self.is_initialized;
     }
 
-    pub fn put_data(&mut self, data: Value) {
+    /// Replaces the data store with `data`.
+    ///
+    /// When `data` serialized as JSON is larger than the data store limit, a
+    /// DataStoreLimitExceeded error is returned, and the data store is left unchanged.
+    pub fn put_data(&mut self, data: Value) -> Result<(), Error> {
         // TODO: we should add a data validator and only accept Strings, arrays & dictionaries
         // https://github.com/firecracker-microvm/firecracker/issues/401
+        self.check_data_store_limit(&data)?;
         self.data_store = data;
         self.is_initialized = true;
+        Ok(())
+    }
+
+    /// Merges `patch_data` into the data store, as a JSON merge patch.
+    ///
+    /// When the patched data store serialized as JSON is larger than the data store limit, a
+    /// DataStoreLimitExceeded error is returned, and the data store is left unchanged.
+    pub fn patch_data(&mut self, patch_data: Value) -> Result<(), Error> {
+        let mut data_store = self.data_store.clone();
+        merge(&mut data_store, &patch_data);
+        self.check_data_store_limit(&data_store)?;
+        self.data_store = data_store;
+        Ok(())
+    }
+
+    /// Sets the maximum size, in bytes, of the data store serialized as JSON, and of the user
+    /// data, which bounds the memory an API client can make Firecracker use. It only applies
+    /// to the later updates.
+    pub fn set_data_store_limit(&mut self, data_store_limit: usize) {
+        self.data_store_limit = data_store_limit;
+    }
+
+    /// Returns the maximum size of the data store serialized as JSON, and of the user data.
+    pub fn data_store_limit(&self) -> usize {
+        self.data_store_limit
+    }
+
+    fn check_data_store_limit(&self, data: &Value) -> Result<(), Error> {
+        if data.to_string().len() > self.data_store_limit {
+            return Err(Error::DataStoreLimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Restricts the requests of the guest to the ones whose `Host` header names one of
+    /// `allowed_hosts`, which protects the metadata from DNS rebinding, or lifts the
+    /// restriction with `None`.
+    pub fn set_allowed_hosts(&mut self, allowed_hosts: Option<Vec<String>>) {
+        self.allowed_hosts = allowed_hosts.map(AllowedHosts::new);
+    }
+
+    /// Restricts the frames of the guest reaching the MMDS to the ones sent from one of
+    /// `allowed_mac_addrs`, which hides the metadata from the other network interfaces of the
+    /// guest, or lifts the restriction with `None`.
+    pub fn set_allowed_mac_addrs(&mut self, allowed_mac_addrs: Option<Vec<MacAddr>>) {
+        self.allowed_mac_addrs = allowed_mac_addrs;
+    }
+
+    /// Sets the IPv6 link-local address the guest can reach the MMDS on, or restores the
+    /// default `fe80::a9fe:a9fe` with `None`.
+    pub fn set_ipv6_addr(&mut self, ipv6_addr: Option<Ipv6Addr>) {
+        self.ipv6_addr = ipv6_addr;
+    }
+
+    /// Sets the TTL of the IPv4 packets, and the hop limit of the IPv6 packets, which carry the
+    /// responses to the guest, or restores the default of 1 with `None`. A greater hop limit
+    /// lets the guest relay the responses beyond the microVM.
+    pub fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
+        self.hop_limit = hop_limit;
+    }
+
+    /// Stores the user data of the guest, such as a cloud-init configuration, which can be
+    /// compressed or a multipart MIME message. It's sent as is, without being parsed.
+    ///
+    /// When the user data is larger than the data store limit, a DataStoreLimitExceeded error
+    /// is returned, and the previous user data is kept.
+    pub fn put_user_data(&mut self, user_data: Vec<u8>) -> Result<(), Error> {
+        if user_data.len() > self.data_store_limit {
+            return Err(Error::DataStoreLimitExceeded);
+        }
+        self.user_data = Some(user_data);
+        Ok(())
+    }
+
+    /// Returns the user data of the guest, or `None` when it was not stored.
+    pub fn user_data(&self) -> Option<&[u8]> {
+        self.user_data.as_ref().map(Vec::as_slice)
+    }
+
+    /// Returns the user data the guest gets on `path`, which is `/latest/user-data` for the
+    /// current user data, or `/<version>/user-data` for the user data of a stored version, or
+    /// `None` when there is no such user data.
+    pub fn user_data_at(&self, path: &str) -> Option<&[u8]> {
+        let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+        match (segments.next(), segments.next(), segments.next()) {
+            (Some(LATEST_VERSION), Some(USER_DATA_KEY), None) => self.user_data(),
+            (Some(version), Some(USER_DATA_KEY), None) => self
+                .version(version)
+                .and_then(|version| version.user_data.as_ref())
+                .map(Vec::as_slice),
+            _ => None,
+        }
+    }
+
+    /// Stores a snapshot of the current metadata, which is the value of the `latest` key of the
+    /// data store, and of the user data, as `version`. The guest gets them on the paths
+    /// starting with `/<version>` instead of `/latest`, such as `/2023-11-01/meta-data/ami-id`,
+    /// unless the data store has a key of the same name. A previous snapshot of `version` is
+    /// replaced.
+    ///
+    /// The names of the versions are made of ASCII letters, digits, dots and dashes, such as
+    /// the dates of the EC2 versions, otherwise an InvalidVersion error is returned. When the
+    /// data store has no `latest` key, a NotFound error is returned. When MAX_VERSIONS other
+    /// versions are stored already, a TooManyVersions error is returned.
+    pub fn put_version(&mut self, version: &str) -> Result<(), Error> {
+        let valid = !version.is_empty()
+            && version != LATEST_VERSION
+            && version
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'-');
+        if !valid {
+            return Err(Error::InvalidVersion);
+        }
+        if !self.versions.contains_key(version) && self.versions.len() >= MAX_VERSIONS {
+            return Err(Error::TooManyVersions);
+        }
+        let metadata = match child(&self.data_store, LATEST_VERSION) {
+            Some(metadata) => metadata.clone(),
+            None => return Err(Error::NotFound),
+        };
+        self.versions.insert(
+            version.to_string(),
+            Version {
+                metadata,
+                user_data: self.user_data.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Returns the names of the stored versions of the metadata, in ascending order.
+    pub fn versions(&self) -> Vec<&str> {
+        self.versions.keys().map(String::as_str).collect()
     }
 
-    pub fn patch_data(&mut self, patch_data: Value) {
-        merge(&mut self.data_store, &patch_data);
+    // Returns the stored version named `name`, unless the data store has a key of the same
+    // name, which takes precedence.
+    fn version(&self, name: &str) -> Option<&Version> {
+        if child(&self.data_store, name).is_some() {
+            return None;
+        }
+        self.versions.get(name)
+    }
+
+    /// Returns the hosts the guest can send requests for, or `None` when any host is allowed.
+    pub fn allowed_hosts(&self) -> Option<&AllowedHosts> {
+        self.allowed_hosts.as_ref()
+    }
+
+    /// Returns the MAC addresses the guest can send requests from, or `None` when any address
+    /// is allowed.
+    pub fn allowed_mac_addrs(&self) -> Option<&[MacAddr]> {
+        self.allowed_mac_addrs
+            .as_ref()
+            .map(|addrs| addrs.as_slice())
+    }
+
+    /// Returns the IPv6 link-local address of the MMDS, or `None` when it's the default one.
+    pub fn ipv6_addr(&self) -> Option<Ipv6Addr> {
+        self.ipv6_addr
+    }
+
+    /// Returns the hop limit of the responses, or `None` when it's the default one.
+    pub fn hop_limit(&self) -> Option<u8> {
+        self.hop_limit
+    }
+
+    /// Sets the value substituted for the `{{name}}` placeholders of the strings of the
+    /// metadata when they are sent to the guest, such as the `INSTANCE_ID_VARIABLE` known
+    /// when Firecracker starts. The data store keeps the placeholders.
+    pub fn set_variable(&mut self, name: &str, value: String) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    // Replaces the "{{name}}" placeholders of `string` with the values of the variables they
+    // name, in a single pass. The placeholders of the unknown variables are kept as is.
+    fn substitute(&self, string: &str) -> String {
+        let mut substituted = String::with_capacity(string.len());
+        let mut rest = string;
+        while let Some(start) = rest.find("{{") {
+            substituted.push_str(&rest[..start]);
+            let placeholder = &rest[start + 2..];
+            let variable = placeholder.find("}}").and_then(|end| {
+                self.variables
+                    .get(&placeholder[..end])
+                    .map(|value| (end, value))
+            });
+            match variable {
+                Some((end, value)) => {
+                    substituted.push_str(value);
+                    rest = &placeholder[end + 2..];
+                }
+                None => {
+                    substituted.push_str("{{");
+                    rest = placeholder;
+                }
+            }
+        }
+        substituted.push_str(rest);
+        substituted
+    }
+
+    // Substitutes the placeholders of all the strings in `value`.
+    fn substitute_value(&self, value: &mut Value) {
+        match *value {
+            Value::String(ref mut string) => *string = self.substitute(string),
+            Value::Array(ref mut array) => {
+                for value in array.iter_mut() {
+                    self.substitute_value(value);
+                }
+            }
+            Value::Object(ref mut map) => {
+                for (_, value) in map.iter_mut() {
+                    self.substitute_value(value);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Returns the value at `path` serialized as JSON, whatever its type, for the guests which
+    /// prefer JSON over the format of `get_value`.
+    ///
+    /// When the path is not found, a NotFound error is returned.
+    pub fn get_value_json(&self, path: &str) -> Result<String, Error> {
+        self.find(path)
+            .map(|value| {
+                let mut value = value.clone();
+                self.substitute_value(&mut value);
+                value.to_string()
+            })
+            .ok_or(Error::NotFound)
+    }
+
+    // Walks down the dictionaries and the arrays along the segments of `path`, such as
+    // `/latest/meta-data/network/interfaces/macs/AA:BB/`. The keys are taken literally, unlike
+    // in a JSON pointer, and the empty segments, such as the one after a trailing "/", are
+    // skipped. The paths starting with a stored version are looked up in its snapshot.
+    fn find(&self, path: &str) -> Option<&Value> {
+        let mut segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .peekable();
+        let first = segments.peek().cloned();
+        let root = match first.and_then(|segment| self.version(segment)) {
+            Some(version) => {
+                segments.next();
+                &version.metadata
+            }
+            None => &self.data_store,
+        };
+        segments.try_fold(root, |value, segment| child(value, segment))
     }
 
     pub fn get_data_str(&self) -> String {
@@ -53,57 +393,70 @@ impl Mmds {
         return self.data_store.to_string();
     }
 
+    /// Returns the value at `path` serialized in `format`.
+    ///
+    /// When the path is not found, a NotFound error is returned.
+    pub fn get_value_as(&self, path: &str, format: OutputFormat) -> Result<String, Error> {
+        match format {
+            OutputFormat::Json => self.get_value_json(path),
+            OutputFormat::Imds => self
+                .get_value(path.to_string())
+                .map(|lines| lines.join("\n")),
+        }
+    }
+
     /// This function replicates the behavior of the Instance Metadata Service
     /// https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/ec2-instance-metadata.html
     /// 1. For a (key, value) pair where the value is a dictionary, it will return all the keys
     /// in the dictionary.
-    /// 2. For a (key, value) pair where the value is a simple type (bool, string, number),
-    /// it will return the value.
+    /// 2. For a (key, value) pair where the value is an array, it will return all the indices
+    /// of the array.
+    /// 3. For a (key, value) pair where the value is a simple type (bool, string, number),
+    /// it will return the value, without the quotes of the strings, and with the values of the
+    /// variables substituted for their placeholders.
     ///
-    /// When the path is not found, a NotFound error is returned.
+    /// The keys and the indices naming a dictionary or an array have a "/" appended. The
+    /// stored versions of the metadata are listed at the root, next to the keys of the data
+    /// store.
+    ///
+    /// When the path is not found, a NotFound error is returned. A null value is
+    /// an UnsupportedValueType error.
     pub fn get_value(&self, path: String) -> Result<Vec<String>, Error> {
-        // The pointer function splits the input by "/". With a trailing "/", pointer does not
-        // know how to get the object.
-        let value = match path.ends_with('/') {
-            true => self.data_store.pointer(&path.as_str()[..(path.len() - 1)]),
-            false => self.data_store.pointer(path.as_str()),
-        };
-
-        match value {
-            Some(val) => {
-                let mut ret = Vec::new();
-                // If the `dict` is Value::Null, Error::NotFound is thrown.
-                // If the `dict` is not a dictionary, a Vec with the value corresponding to
-                // the key is returned.
-                match val.as_object() {
-                    Some(map) => {
-                        // When the object is a map, push all the keys in the Vec.
-                        for key in map.keys() {
-                            let mut key = key.clone();
-                            // If the key corresponds to a dictionary, a "/" is appended
-                            // to the key name.
-                            if map[&key].is_object() {
-                                key.push_str("/");
-                            }
+        // A "/" is appended to the keys of the values which have keys or indices of their own.
+        fn entry(name: String, value: &Value) -> String {
+            if value.is_object() || value.is_array() {
+                name + "/"
+            } else {
+                name
+            }
+        }
 
-                            ret.push(key);
-                        }
-                        return Ok(ret);
-                    }
-                    None => {
-                        // When the object is not a map, return the value.
-                        // The only supported Value type is String.
-                        match val.as_str() {
-                            Some(str_val) => {
-                                ret.push(str_val.to_string());
-                                return Ok(ret);
-                            }
-                            None => return Err(Error::UnsupportedValueType),
-                        };
-                    }
-                };
+        match self.find(&path) {
+            Some(&Value::Object(ref map)) => {
+                let mut entries: Vec<String> = map
+                    .iter()
+                    .map(|(key, value)| entry(key.clone(), value))
+                    .collect();
+                if path.split('/').all(|segment| segment.is_empty()) {
+                    entries.extend(
+                        self.versions
+                            .keys()
+                            .filter(|version| !map.contains_key(version.as_str()))
+                            .map(|version| format!("{}/", version)),
+                    );
+                }
+                Ok(entries)
             }
-            None => return Err(Error::NotFound),
+            Some(&Value::Array(ref array)) => Ok(array
+                .iter()
+                .enumerate()
+                .map(|(index, value)| entry(index.to_string(), value))
+                .collect()),
+            Some(&Value::String(ref string)) => Ok(vec![self.substitute(string)]),
+            Some(&Value::Null) => Err(Error::UnsupportedValueType),
+            // The numbers and the booleans are printed like in JSON.
+            Some(leaf) => Ok(vec![leaf.to_string()]),
+            None => Err(Error::NotFound),
         }
     }
 }
@@ -120,18 +473,286 @@ mod tests {
 
         let mut mmds_json = "{\"meta-data\":{\"iam\":\"dummy\"},\"user-data\":\"1522850095\"}";
 
-        mmds.put_data(serde_json::from_str(mmds_json).unwrap());
+        mmds.put_data(serde_json::from_str(mmds_json).unwrap())
+            .unwrap();
         assert_eq!(mmds.is_initialized(), true);
 
         assert_eq!(mmds.get_data_str(), mmds_json);
 
         // update the user-data field add test that patch works as expected
         let patch_json = "{\"user-data\":\"10\"}";
-        mmds.patch_data(serde_json::from_str(patch_json).unwrap());
+        mmds.patch_data(serde_json::from_str(patch_json).unwrap())
+            .unwrap();
         mmds_json = "{\"meta-data\":{\"iam\":\"dummy\"},\"user-data\":\"10\"}";
         assert_eq!(mmds.get_data_str(), mmds_json);
     }
 
+    #[test]
+    fn test_patch_data() {
+        let mut mmds = Mmds::default();
+        let data = r#"{
+            "latest": {
+                "meta-data": {
+                    "ami-id": "ami-12345678",
+                    "credentials": {"secret-key": "old", "session-token": "token"},
+                    "keys": ["a", "b"]
+                }
+            }
+        }"#;
+        mmds.put_data(serde_json::from_str(data).unwrap()).unwrap();
+
+        // The nested keys are updated without resending their siblings, the keys set to null
+        // are removed, and the arrays are replaced.
+        let patch = r#"{
+            "latest": {
+                "meta-data": {
+                    "credentials": {"secret-key": "new", "session-token": null},
+                    "keys": ["c"]
+                }
+            }
+        }"#;
+        mmds.patch_data(serde_json::from_str(patch).unwrap())
+            .unwrap();
+        assert_eq!(
+            mmds.get_data_str(),
+            r#"{"latest":{"meta-data":{"ami-id":"ami-12345678","credentials":{"secret-key":"new"},"keys":["c"]}}}"#
+        );
+        assert_eq!(
+            mmds.get_value(String::from("/latest/meta-data/credentials/secret-key")),
+            Ok(vec![String::from("new")])
+        );
+        assert_eq!(
+            mmds.get_value(String::from("/latest/meta-data/credentials/session-token")),
+            Err(Error::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_user_data() {
+        let mut mmds = Mmds::default();
+        assert_eq!(mmds.user_data(), None);
+
+        // The user data is kept apart from the metadata, whatever its bytes are.
+        mmds.put_user_data(vec![0x1f, 0x8b, 0x08, 0x00, 0xff])
+            .unwrap();
+        assert_eq!(mmds.user_data(), Some(&[0x1f, 0x8b, 0x08, 0x00, 0xff][..]));
+        assert_eq!(mmds.is_initialized(), false);
+        assert_eq!(mmds.get_data_str(), "{}");
+        mmds.put_user_data(Vec::new()).unwrap();
+        assert_eq!(mmds.user_data(), Some(&[][..]));
+    }
+
+    #[test]
+    fn test_data_store_limit() {
+        let mut mmds = Mmds::default();
+        assert_eq!(mmds.data_store_limit(), DEFAULT_DATA_STORE_LIMIT);
+
+        // {"key":"value"} is 15 bytes long.
+        mmds.set_data_store_limit(15);
+        mmds.put_data(serde_json::from_str(r#"{"key": "value"}"#).unwrap())
+            .unwrap();
+        assert_eq!(
+            mmds.put_data(serde_json::from_str(r#"{"key": "values"}"#).unwrap()),
+            Err(Error::DataStoreLimitExceeded)
+        );
+        assert_eq!(mmds.get_data_str(), r#"{"key":"value"}"#);
+
+        // The limit applies to the patched data store, which is left unchanged when it's too
+        // large.
+        assert_eq!(
+            mmds.patch_data(serde_json::from_str(r#"{"other": 1}"#).unwrap()),
+            Err(Error::DataStoreLimitExceeded)
+        );
+        assert_eq!(mmds.get_data_str(), r#"{"key":"value"}"#);
+        mmds.patch_data(serde_json::from_str(r#"{"key": null, "k": 1}"#).unwrap())
+            .unwrap();
+        assert_eq!(mmds.get_data_str(), r#"{"k":1}"#);
+
+        // The user data is limited too.
+        mmds.put_user_data(vec![0; 15]).unwrap();
+        assert_eq!(
+            mmds.put_user_data(vec![1; 16]),
+            Err(Error::DataStoreLimitExceeded)
+        );
+        assert_eq!(mmds.user_data(), Some(&[0; 15][..]));
+    }
+
+    #[test]
+    fn test_versions() {
+        let mut mmds = Mmds::default();
+        assert_eq!(mmds.put_version("2023-11-01"), Err(Error::NotFound));
+
+        let data = r#"{"latest": {"meta-data": {"ami-id": "ami-1"}}, "1.0": {"old": "key"}}"#;
+        mmds.put_data(serde_json::from_str(data).unwrap()).unwrap();
+        mmds.put_user_data(b"#cloud-config 1".to_vec()).unwrap();
+        mmds.put_version("2023-11-01").unwrap();
+
+        // The latest version changes, while the stored one is kept as is.
+        let patch = r#"{"latest": {"meta-data": {"ami-id": "ami-2"}}}"#;
+        mmds.patch_data(serde_json::from_str(patch).unwrap())
+            .unwrap();
+        mmds.put_user_data(b"#cloud-config 2".to_vec()).unwrap();
+        assert_eq!(
+            mmds.get_value(String::from("/latest/meta-data/ami-id")),
+            Ok(vec![String::from("ami-2")])
+        );
+        assert_eq!(
+            mmds.get_value(String::from("/2023-11-01/meta-data/ami-id")),
+            Ok(vec![String::from("ami-1")])
+        );
+        assert_eq!(
+            mmds.get_value_json("/2023-11-01/"),
+            Ok(String::from(r#"{"meta-data":{"ami-id":"ami-1"}}"#))
+        );
+        assert_eq!(
+            mmds.get_value(String::from("/2023-11-01/user-data")),
+            Err(Error::NotFound)
+        );
+        assert_eq!(
+            mmds.user_data_at("/latest/user-data"),
+            Some(&b"#cloud-config 2"[..])
+        );
+        assert_eq!(
+            mmds.user_data_at("/2023-11-01/user-data/"),
+            Some(&b"#cloud-config 1"[..])
+        );
+        assert_eq!(mmds.user_data_at("/2023-11-02/user-data"), None);
+        assert_eq!(mmds.user_data_at("/2023-11-01/meta-data"), None);
+
+        // The keys of the data store take precedence over the versions of the same name.
+        mmds.put_version("1.0").unwrap();
+        assert_eq!(
+            mmds.get_value(String::from("/1.0")),
+            Ok(vec![String::from("old")])
+        );
+        assert_eq!(mmds.user_data_at("/1.0/user-data"), None);
+
+        // The versions are listed at the root.
+        assert_eq!(mmds.versions(), vec!["1.0", "2023-11-01"]);
+        assert_eq!(
+            mmds.get_value(String::from("/")),
+            Ok(vec![
+                String::from("1.0/"),
+                String::from("latest/"),
+                String::from("2023-11-01/")
+            ])
+        );
+
+        for version in &["", "latest", "2023/11/01", "../latest"] {
+            assert_eq!(mmds.put_version(version), Err(Error::InvalidVersion));
+        }
+
+        for i in 2..MAX_VERSIONS {
+            mmds.put_version(&i.to_string()).unwrap();
+        }
+        assert_eq!(mmds.versions().len(), MAX_VERSIONS);
+        assert_eq!(mmds.put_version("2024-01-01"), Err(Error::TooManyVersions));
+        // A stored version can be replaced.
+        mmds.put_version("2023-11-01").unwrap();
+        assert_eq!(
+            mmds.get_value(String::from("/2023-11-01/meta-data/ami-id")),
+            Ok(vec![String::from("ami-2")])
+        );
+    }
+
+    #[test]
+    fn test_allowed_hosts() {
+        let mut mmds = Mmds::default();
+        assert!(mmds.allowed_hosts().is_none());
+
+        mmds.set_allowed_hosts(Some(vec![String::from("169.254.169.254")]));
+        assert_eq!(
+            mmds.allowed_hosts(),
+            Some(&AllowedHosts::new(vec!["169.254.169.254"]))
+        );
+        mmds.set_allowed_hosts(None);
+        assert!(mmds.allowed_hosts().is_none());
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut mmds = Mmds::default();
+        let data = r#"{
+            "instance-id": "{{instance_id}}",
+            "tags": ["launched at {{launch_time}}", "{{unknown}}", "{{instance_id", 3],
+            "first": {"second": "{{instance_id}}/{{launch_time}}"}
+        }"#;
+        mmds.put_data(serde_json::from_str(data).unwrap()).unwrap();
+
+        // The placeholders are kept until the variables are set.
+        assert_eq!(
+            mmds.get_value_as("/instance-id", OutputFormat::Imds),
+            Ok(String::from("{{instance_id}}"))
+        );
+
+        mmds.set_variable(INSTANCE_ID_VARIABLE, String::from("i-1234"));
+        mmds.set_variable(LAUNCH_TIME_VARIABLE, String::from("2019-01-01T00:00:00Z"));
+        assert_eq!(
+            mmds.get_value_as("/instance-id", OutputFormat::Imds),
+            Ok(String::from("i-1234"))
+        );
+        assert_eq!(
+            mmds.get_value_as("/tags/0", OutputFormat::Imds),
+            Ok(String::from("launched at 2019-01-01T00:00:00Z"))
+        );
+        assert_eq!(
+            mmds.get_value_as("/tags/1", OutputFormat::Imds),
+            Ok(String::from("{{unknown}}"))
+        );
+        assert_eq!(
+            mmds.get_value_as("/tags/2", OutputFormat::Imds),
+            Ok(String::from("{{instance_id"))
+        );
+        assert_eq!(
+            mmds.get_value_as("/first", OutputFormat::Json),
+            Ok(String::from(r#"{"second":"i-1234/2019-01-01T00:00:00Z"}"#))
+        );
+        assert_eq!(
+            mmds.get_value_as("/tags", OutputFormat::Json),
+            Ok(String::from(
+                r#"["launched at 2019-01-01T00:00:00Z","{{unknown}}","{{instance_id",3]"#
+            ))
+        );
+
+        // The data store keeps the placeholders.
+        assert!(mmds.get_data_str().contains("{{instance_id}}"));
+    }
+
+    #[test]
+    fn test_allowed_mac_addrs() {
+        let mut mmds = Mmds::default();
+        assert!(mmds.allowed_mac_addrs().is_none());
+
+        let mac_addr = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        mmds.set_allowed_mac_addrs(Some(vec![mac_addr]));
+        assert_eq!(mmds.allowed_mac_addrs(), Some(&[mac_addr][..]));
+        mmds.set_allowed_mac_addrs(None);
+        assert!(mmds.allowed_mac_addrs().is_none());
+    }
+
+    #[test]
+    fn test_ipv6_addr() {
+        let mut mmds = Mmds::default();
+        assert!(mmds.ipv6_addr().is_none());
+
+        let ipv6_addr = "fe80::1".parse::<Ipv6Addr>().unwrap();
+        mmds.set_ipv6_addr(Some(ipv6_addr));
+        assert_eq!(mmds.ipv6_addr(), Some(ipv6_addr));
+        mmds.set_ipv6_addr(None);
+        assert!(mmds.ipv6_addr().is_none());
+    }
+
+    #[test]
+    fn test_hop_limit() {
+        let mut mmds = Mmds::default();
+        assert!(mmds.hop_limit().is_none());
+
+        mmds.set_hop_limit(Some(64));
+        assert_eq!(mmds.hop_limit(), Some(64));
+        mmds.set_hop_limit(None);
+        assert!(mmds.hop_limit().is_none());
+    }
+
     #[test]
     fn test_get_value() {
         let mut mmds = Mmds::default();
@@ -151,7 +772,7 @@ mod tests {
         }"#;
 
         let data_store: Value = serde_json::from_str(data).unwrap();
-        mmds.put_data(data_store);
+        mmds.put_data(data_store).unwrap();
 
         // Test invalid path.
         match mmds.get_value("/invalid_path".to_string()) {
@@ -194,6 +815,51 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_get_value_listing() {
+        let mut mmds = Mmds::default();
+        let data = r#"{"empty": {}, "none": [], "nested": [[1], {"a": 2}, 3]}"#;
+        mmds.put_data(serde_json::from_str(data).unwrap()).unwrap();
+
+        // The listings of the empty dictionaries and arrays have no lines.
+        assert_eq!(mmds.get_value(String::from("/empty")), Ok(vec![]));
+        assert_eq!(mmds.get_value(String::from("/none/")), Ok(vec![]));
+        assert_eq!(
+            mmds.get_value_as("/empty/", OutputFormat::Imds),
+            Ok(String::new())
+        );
+
+        // The indices naming an array or a dictionary have a trailing "/" too.
+        assert_eq!(
+            mmds.get_value_as("/nested", OutputFormat::Imds),
+            Ok(String::from("0/\n1/\n2"))
+        );
+        assert_eq!(
+            mmds.get_value_as("/nested/1/", OutputFormat::Imds),
+            Ok(String::from("a"))
+        );
+        assert_eq!(
+            mmds.get_value_as("/", OutputFormat::Imds),
+            Ok(String::from("empty/\nnested/\nnone/"))
+        );
+    }
+
+    #[test]
+    fn test_get_value_json() {
+        let mut mmds = Mmds::default();
+        let data = r#"{"name": {"first": "John"}, "age": 43}"#;
+        mmds.put_data(serde_json::from_str(data).unwrap()).unwrap();
+
+        assert_eq!(
+            mmds.get_value_json("/name/").unwrap(),
+            r#"{"first":"John"}"#
+        );
+        assert_eq!(mmds.get_value_json("/name/first").unwrap(), r#""John""#);
+        // The values of any type can be serialized as JSON.
+        assert_eq!(mmds.get_value_json("/age").unwrap(), "43");
+        assert_eq!(mmds.get_value_json("/invalid"), Err(Error::NotFound));
+    }
+
     #[test]
     fn test_get_element_from_array() {
         let mut mmds = Mmds::default();
@@ -205,13 +871,142 @@ mod tests {
         }"#;
 
         let data_store: Value = serde_json::from_str(data).unwrap();
-        mmds.put_data(data_store);
+        mmds.put_data(data_store).unwrap();
 
         // Test path does NOT end with /; Value is a String.
         match mmds.get_value("/phones/0".to_string()) {
             Ok(ret) => assert_eq!(ret, vec!["+40 1234567"]),
             Err(_) => assert!(false),
         };
+
+        // Test the indices of an array are listed.
+        match mmds.get_value("/phones/".to_string()) {
+            Ok(ret) => assert_eq!(ret, vec!["0", "1"]),
+            Err(_) => assert!(false),
+        };
+    }
+
+    #[test]
+    fn test_deep_paths() {
+        let mut mmds = Mmds::default();
+        let data = r#"{
+            "latest": {
+                "meta-data": {
+                    "network": {
+                        "interfaces": {
+                            "macs": {
+                                "AA:BB": {
+                                    "device-number": "0",
+                                    "ipv4-associations": ["10.0.0.1", "10.0.0.2"]
+                                }
+                            }
+                        }
+                    },
+                    "a~1b": "escaped"
+                }
+            }
+        }"#;
+        mmds.put_data(serde_json::from_str(data).unwrap()).unwrap();
+
+        // The intermediate dictionaries and arrays are listed.
+        let mac = "/latest/meta-data/network/interfaces/macs/AA:BB/";
+        assert_eq!(
+            mmds.get_value(mac.to_string()),
+            Ok(vec![
+                String::from("device-number"),
+                String::from("ipv4-associations/")
+            ])
+        );
+        assert_eq!(
+            mmds.get_value(String::from("/latest/meta-data/network/")),
+            Ok(vec![String::from("interfaces/")])
+        );
+        assert_eq!(
+            mmds.get_value(format!("{}ipv4-associations", mac)),
+            Ok(vec![String::from("0"), String::from("1")])
+        );
+        assert_eq!(
+            mmds.get_value(format!("{}ipv4-associations/1", mac)),
+            Ok(vec![String::from("10.0.0.2")])
+        );
+        assert_eq!(
+            mmds.get_value(String::from("/latest//meta-data/network")),
+            Ok(vec![String::from("interfaces/")])
+        );
+        assert_eq!(
+            mmds.get_value(String::from("/")),
+            Ok(vec![String::from("latest/")])
+        );
+
+        // The paths going through a leaf, or a missing key or index, are not found.
+        for path in &[
+            "/latest/meta-data/network/interfaces/macs/AA:CC/",
+            "/latest/meta-data/network/interfaces/macs/AA:BB/device-number/0",
+            "/latest/meta-data/network/interfaces/macs/AA:BB/ipv4-associations/2",
+            "/latest/meta-data/network/interfaces/macs/AA:BB/ipv4-associations/01",
+            "/latest/meta-data/network/interfaces/macs/AA:BB/ipv4-associations/+1",
+            "/latest/meta-data/network/interfaces/macs/AA:BB/ipv4-associations/-1",
+        ] {
+            assert_eq!(
+                mmds.get_value(path.to_string()),
+                Err(Error::NotFound),
+                "{}",
+                path
+            );
+            assert_eq!(mmds.get_value_json(path), Err(Error::NotFound), "{}", path);
+        }
+
+        // The keys are not unescaped like in a JSON pointer.
+        assert_eq!(
+            mmds.get_value(String::from("/latest/meta-data/a~1b")),
+            Ok(vec![String::from("escaped")])
+        );
+        assert_eq!(
+            mmds.get_value(String::from("/latest/meta-data/a/b")),
+            Err(Error::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_get_value_as() {
+        let mut mmds = Mmds::default();
+        let data = r#"{
+            "age": 43,
+            "enabled": true,
+            "name": {"first": "John"},
+            "keys": [{"id": "0"}, "key"]
+        }"#;
+        mmds.put_data(serde_json::from_str(data).unwrap()).unwrap();
+
+        assert_eq!(
+            mmds.get_value_as("/", OutputFormat::Imds).unwrap(),
+            "age\nenabled\nkeys/\nname/"
+        );
+        assert_eq!(
+            mmds.get_value_as("/keys", OutputFormat::Imds).unwrap(),
+            "0/\n1"
+        );
+        assert_eq!(
+            mmds.get_value_as("/keys/1", OutputFormat::Imds).unwrap(),
+            "key"
+        );
+        assert_eq!(mmds.get_value_as("/age", OutputFormat::Imds).unwrap(), "43");
+        assert_eq!(
+            mmds.get_value_as("/enabled", OutputFormat::Imds).unwrap(),
+            "true"
+        );
+
+        assert_eq!(
+            mmds.get_value_as("/keys", OutputFormat::Json).unwrap(),
+            r#"[{"id":"0"},"key"]"#
+        );
+        assert_eq!(
+            mmds.get_value_as("/keys/1", OutputFormat::Json).unwrap(),
+            r#""key""#
+        );
+        for &format in &[OutputFormat::Imds, OutputFormat::Json] {
+            assert_eq!(mmds.get_value_as("/invalid", format), Err(Error::NotFound));
+        }
     }
 
     #[test]
@@ -222,12 +1017,11 @@ mod tests {
                 "first": "John",
                 "second": "Doe"
             },
-            "age": 43
+            "age": null
         }"#;
 
         let data_store: Value = serde_json::from_str(data).unwrap();
-        // TODO: This should fail; we should only accept String types
-        mmds.put_data(data_store);
+        mmds.put_data(data_store).unwrap();
 
         match mmds.get_value("/age".to_string()) {
             Ok(_) => assert!(false),
@@ -6,14 +6,21 @@ extern crate json_patch;
 extern crate lazy_static;
 extern crate serde_json;
 
+extern crate logger;
 extern crate micro_http;
+extern crate net_util;
 
 pub mod data_store;
 
 use std::sync::{Arc, Mutex};
 
-use data_store::{Error as MmdsError, Mmds};
-use micro_http::{Body, Request, RequestError, Response, StatusCode, Version};
+use data_store::{Error as MmdsError, Mmds, OutputFormat};
+use logger::{Metric, METRICS};
+use micro_http::host::AllowedHosts;
+use micro_http::{
+    accept, etag, Body, Header, MediaType, Method, Request, RequestError, RequestLimits, Response,
+    StatusCode, Version,
+};
 
 lazy_static! {
     // A static reference to a global Mmds instance. We currently use this for ease of access during
@@ -22,47 +29,156 @@ lazy_static! {
     pub static ref MMDS: Arc<Mutex<Mmds>> = Arc::new(Mutex::new(Mmds::default()));
 }
 
-fn build_response(http_version: Version, status_code: StatusCode, body: Body) -> Response {
-    let mut response = Response::new(http_version, status_code);
+// Builds the response to a valid request, which mirrors its HTTP version and keep alive.
+fn build_request_response(request: &Request, status_code: StatusCode, body: Body) -> Response {
+    let mut response = Response::for_request(request, status_code);
     response.set_body(body);
     response
 }
 
+// Counts the response sent to the guest by the class of its status code.
+fn count_response(status_code: StatusCode) {
+    match status_code {
+        StatusCode::SwitchingProtocols => (),
+        StatusCode::OK | StatusCode::PartialContent => METRICS.mmds.tx_2xx.inc(),
+        StatusCode::NotModified => METRICS.mmds.tx_3xx.inc(),
+        StatusCode::BadRequest
+        | StatusCode::Unauthorized
+        | StatusCode::Forbidden
+        | StatusCode::NotFound
+        | StatusCode::MethodNotAllowed
+        | StatusCode::RequestTimeout
+        | StatusCode::PayloadTooLarge
+        | StatusCode::UriTooLong
+        | StatusCode::RangeNotSatisfiable
+        | StatusCode::RequestHeaderFieldsTooLarge => METRICS.mmds.tx_4xx.inc(),
+        StatusCode::InternalServerError
+        | StatusCode::NotImplemented
+        | StatusCode::HttpVersionNotSupported => METRICS.mmds.tx_5xx.inc(),
+    }
+}
+
 pub fn parse_request(request_bytes: &[u8]) -> Response {
-    let request = Request::try_from(request_bytes);
+    let response = build_response(request_bytes);
+    count_response(response.status());
+    response
+}
+
+fn build_response(request_bytes: &[u8]) -> Response {
+    // The requests come from the guest, so their size is limited.
+    let request = Request::try_from_with_limits(request_bytes, &RequestLimits::default());
     match request {
         Ok(request) => {
-            let uri = request.uri().get_abs_path();
-            if uri.len() == 0 {
-                return build_response(
-                    request.http_version(),
-                    StatusCode::BadRequest,
-                    Body::new("Invalid URI.".to_string()),
-                );
+            match *request.method() {
+                Method::Get => METRICS.mmds.rx_get.inc(),
+                Method::Put => METRICS.mmds.rx_put.inc(),
+                Method::Patch => METRICS.mmds.rx_patch.inc(),
+                _ => (),
             }
 
             // The lock can be held by one thread only, so it is safe to unwrap.
             // If another thread poisoned the lock, we abort the execution.
-            let response = MMDS
+            let host_allowed = MMDS
                 .lock()
                 .expect("Failed to build MMDS response due to poisoned lock")
-                .get_value(uri.to_string());
+                .allowed_hosts()
+                .map_or(true, |allowed_hosts| allowed_hosts.allows(&request));
+            if !host_allowed {
+                return AllowedHosts::forbidden_response(&request);
+            }
+
+            // The metadata is read only.
+            if request.method() != &Method::Get {
+                let mut response = build_request_response(
+                    &request,
+                    StatusCode::MethodNotAllowed,
+                    Body::new("Unsupported HTTP method.".to_string()),
+                );
+                response.add_header(Header::Allow, String::from("GET"));
+                return response;
+            }
+
+            // The paths are looked up decoded, without the query and the dot segments, so that
+            // the paths naming the same metadata are the same.
+            let uri = match request.uri().normalized_path() {
+                Ok(uri) => uri,
+                Err(e) => {
+                    METRICS.mmds.rx_bad_uri.inc();
+                    let err_msg = match e {
+                        RequestError::InvalidUri(err_msg) => err_msg,
+                        _ => "Invalid URI.",
+                    };
+                    return build_request_response(
+                        &request,
+                        StatusCode::BadRequest,
+                        Body::new(err_msg.to_string()),
+                    );
+                }
+            };
+            if uri.len() == 0 {
+                return build_request_response(
+                    &request,
+                    StatusCode::BadRequest,
+                    Body::new("Invalid URI.".to_string()),
+                );
+            }
+
+            // The user data stored apart from the metadata is found on the user data path of
+            // each version.
+            {
+                // The lock can be held by one thread only, so it is safe to unwrap.
+                // If another thread poisoned the lock, we abort the execution.
+                let mmds = MMDS
+                    .lock()
+                    .expect("Failed to build MMDS response due to poisoned lock");
+                if let Some(user_data) = mmds.user_data_at(&uri) {
+                    // The user data is opaque, so it's sent as is, whatever the guest accepts.
+                    let mut response = etag::response(&request, Body::new(user_data));
+                    response.set_content_type(MediaType::ApplicationOctetStream);
+                    return response;
+                }
+            }
+
+            // The metadata is sent as JSON to the guests which prefer it, and in the format of
+            // IMDS otherwise.
+            let format = match accept::negotiate(
+                &request,
+                &[
+                    MediaType::PlainText.as_str(),
+                    MediaType::ApplicationJson.as_str(),
+                ],
+            ) {
+                Some(media_type) if media_type == MediaType::ApplicationJson.as_str() => {
+                    OutputFormat::Json
+                }
+                _ => OutputFormat::Imds,
+            };
+
+            let response = {
+                // The lock can be held by one thread only, so it is safe to unwrap.
+                // If another thread poisoned the lock, we abort the execution.
+                let mmds = MMDS
+                    .lock()
+                    .expect("Failed to build MMDS response due to poisoned lock");
+                mmds.get_value_as(&uri, format)
+            };
             match response {
-                Ok(response) => {
-                    let response_body = response.join("\n");
-                    build_response(
-                        request.http_version(),
-                        StatusCode::OK,
-                        Body::new(response_body),
-                    )
+                Ok(response_body) => {
+                    // The guests poll the metadata, which rarely changes, so the responses are
+                    // tagged for conditional requests.
+                    let mut response = etag::response(&request, Body::new(response_body));
+                    if format == OutputFormat::Json {
+                        response.set_content_type(MediaType::ApplicationJson);
+                    }
+                    response
                 }
                 Err(e) => {
                     match e {
                         MmdsError::NotFound => {
                             // NotFound
                             let error_msg = format!("Resource not found: {}.", uri);
-                            return build_response(
-                                request.http_version(),
+                            return build_request_response(
+                                &request,
                                 StatusCode::NotFound,
                                 Body::new(error_msg),
                             );
@@ -71,35 +187,43 @@ pub fn parse_request(request_bytes: &[u8]) -> Response {
                             // InternalServerError
                             let error_msg =
                                 format!("The resource {} has an invalid format.", uri.to_string());
-                            return build_response(
-                                request.http_version(),
+                            return build_request_response(
+                                &request,
                                 StatusCode::InternalServerError,
                                 Body::new(error_msg),
                             );
                         }
+                        _ => {
+                            // The other errors only come from the updates of the data store,
+                            // which the guest can't make.
+                            return build_request_response(
+                                &request,
+                                StatusCode::InternalServerError,
+                                Body::new("Internal error.".to_string()),
+                            );
+                        }
                     }
                 }
             }
         }
-        Err(e) => match e {
-            RequestError::InvalidHttpVersion(err_msg) => build_response(
-                Version::default(),
-                StatusCode::NotImplemented,
-                Body::new(err_msg.to_string()),
-            ),
-            RequestError::InvalidUri(err_msg) | RequestError::InvalidHttpMethod(err_msg) => {
-                build_response(
-                    Version::default(),
-                    StatusCode::BadRequest,
-                    Body::new(err_msg.to_string()),
-                )
+        Err(e) => {
+            METRICS.mmds.rx_bad_request.inc();
+            // Mirror the HTTP version of the request, if the request line has a valid one.
+            let http_version =
+                Request::http_version_of(request_bytes).unwrap_or(Version::default());
+            match e {
+                RequestError::InvalidUri(_) | RequestError::UriTooLong => {
+                    METRICS.mmds.rx_bad_uri.inc()
+                }
+                RequestError::InvalidHeader(_)
+                | RequestError::HeadersTooLarge
+                | RequestError::TooManyHeaders
+                | RequestError::HeaderTooLong => METRICS.mmds.rx_bad_header.inc(),
+                RequestError::InvalidContentLength => METRICS.mmds.rx_bad_content_length.inc(),
+                _ => (),
             }
-            RequestError::InvalidRequest => build_response(
-                Version::default(),
-                StatusCode::BadRequest,
-                Body::new("Invalid request.".to_string()),
-            ),
-        },
+            Response::for_error(http_version, &e)
+        }
     }
 }
 
@@ -126,7 +250,8 @@ mod tests {
         }"#;
         MMDS.lock()
             .unwrap()
-            .put_data(serde_json::from_str(data).unwrap());
+            .put_data(serde_json::from_str(data).unwrap())
+            .unwrap();
 
         // Test invalid request.
         let request = b"HTTP/1.1";
@@ -135,7 +260,8 @@ mod tests {
 
         // Test unsupported HTTP version.
         let request = b"GET http://169.254.169.255/ HTTP/2.0\r\n";
-        let mut expected_response = Response::new(Version::Http11, StatusCode::NotImplemented);
+        let mut expected_response =
+            Response::new(Version::Http11, StatusCode::HttpVersionNotSupported);
         expected_response.set_body(Body::new("Unsupported HTTP version.".to_string()));
         let actual_response = parse_request(request);
 
@@ -144,14 +270,109 @@ mod tests {
         assert!(expected_response.http_version() == actual_response.http_version());
 
         // Test invalid HTTP Method.
-        let request = b"PUT http://169.254.169.255/ HTTP/1.0\r\n";
+        let request = b"G(ET http://169.254.169.255/ HTTP/1.0\r\n";
+        let mut expected_response = Response::new(Version::Http10, StatusCode::BadRequest);
+        expected_response.set_body(Body::new("Invalid HTTP method token.".to_string()));
+        let actual_response = parse_request(request);
+
+        assert!(expected_response.status() == actual_response.status());
+        assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
+        assert!(expected_response.http_version() == actual_response.http_version());
+
+        // Test the HTTP Methods which MMDS doesn't accept, standard or not.
+        for request in &[
+            &b"PUT http://169.254.169.255/ HTTP/1.0\r\n"[..],
+            b"POST http://169.254.169.255/ HTTP/1.0\r\n",
+            b"PROPFIND http://169.254.169.255/ HTTP/1.0\r\n",
+        ] {
+            let mut expected_response =
+                Response::new(Version::Http10, StatusCode::MethodNotAllowed);
+            expected_response.set_body(Body::new("Unsupported HTTP method.".to_string()));
+            let actual_response = parse_request(request);
+
+            assert!(expected_response.status() == actual_response.status());
+            assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
+            assert!(expected_response.http_version() == actual_response.http_version());
+            let mut response_buf = Vec::new();
+            actual_response.write_all(&mut response_buf).unwrap();
+            assert!(String::from_utf8(response_buf)
+                .unwrap()
+                .contains("Allow: GET\r\n"));
+        }
+
+        // Test control characters in the URI.
+        let request = b"GET /age\0 HTTP/1.1\r\n";
+        let rx_bad_uri = METRICS.mmds.rx_bad_uri.count();
         let mut expected_response = Response::new(Version::Http11, StatusCode::BadRequest);
-        expected_response.set_body(Body::new("Unsupported HTTP method.".to_string()));
+        expected_response.set_body(Body::new("URI contains control characters.".to_string()));
         let actual_response = parse_request(request);
 
         assert!(expected_response.status() == actual_response.status());
         assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
         assert!(expected_response.http_version() == actual_response.http_version());
+        assert_eq!(METRICS.mmds.rx_bad_uri.count(), rx_bad_uri + 1);
+
+        // Test invalid header name.
+        let request = b"GET /age HTTP/1.0\r\nContent-Length : 0\r\n\r\n";
+        let rx_bad_header = METRICS.mmds.rx_bad_header.count();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::BadRequest);
+        expected_response.set_body(Body::new("Invalid header name.".to_string()));
+        let actual_response = parse_request(request);
+
+        assert!(expected_response.status() == actual_response.status());
+        assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
+        assert!(expected_response.http_version() == actual_response.http_version());
+        assert_eq!(METRICS.mmds.rx_bad_header.count(), rx_bad_header + 1);
+
+        // Test oversized Content-Length.
+        let request = b"GET /age HTTP/1.1\r\nContent-Length: 184467440737095516160\r\n\r\n";
+        let rx_bad_content_length = METRICS.mmds.rx_bad_content_length.count();
+        let mut expected_response = Response::new(Version::Http11, StatusCode::BadRequest);
+        expected_response.set_body(Body::new("Invalid Content-Length.".to_string()));
+        let actual_response = parse_request(request);
+
+        assert!(expected_response.status() == actual_response.status());
+        assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
+        assert_eq!(
+            METRICS.mmds.rx_bad_content_length.count(),
+            rx_bad_content_length + 1
+        );
+
+        // Test invalid percent-encoding in the URI.
+        let request = b"GET /a%0 HTTP/1.1\r\n";
+        let rx_bad_uri = METRICS.mmds.rx_bad_uri.count();
+        let mut expected_response = Response::new(Version::Http11, StatusCode::BadRequest);
+        expected_response.set_body(Body::new("Invalid percent-encoding.".to_string()));
+        let actual_response = parse_request(request);
+
+        assert!(expected_response.status() == actual_response.status());
+        assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
+        assert_eq!(METRICS.mmds.rx_bad_uri.count(), rx_bad_uri + 1);
+
+        // Test the requests exceeding the size limits.
+        let mut request = b"GET /".to_vec();
+        request.resize(RequestLimits::default().max_uri_len + 2, b'a');
+        request.extend_from_slice(b" HTTP/1.1\r\n\r\n");
+        let rx_bad_uri = METRICS.mmds.rx_bad_uri.count();
+        let actual_response = parse_request(&request);
+        assert!(actual_response.status() == StatusCode::UriTooLong);
+        assert_eq!(METRICS.mmds.rx_bad_uri.count(), rx_bad_uri + 1);
+
+        let request = b"PUT / HTTP/1.1\r\nContent-Length: 1048576\r\n\r\n";
+        let actual_response = parse_request(request);
+        assert!(actual_response.status() == StatusCode::PayloadTooLarge);
+        assert!(actual_response.body().unwrap() == Body::new("Body too large.".to_string()));
+
+        let mut request = b"GET / HTTP/1.1\r\n".to_vec();
+        for _ in 0..=RequestLimits::default().max_headers {
+            request.extend_from_slice(b"A: 1\r\n");
+        }
+        request.extend_from_slice(b"\r\n");
+        let rx_bad_header = METRICS.mmds.rx_bad_header.count();
+        let actual_response = parse_request(&request);
+        assert!(actual_response.status() == StatusCode::RequestHeaderFieldsTooLarge);
+        assert!(actual_response.body().unwrap() == Body::new("Too many headers.".to_string()));
+        assert_eq!(METRICS.mmds.rx_bad_header.count(), rx_bad_header + 1);
 
         // Test invalid (empty absolute path) URI.
         let request = b"GET http:// HTTP/1.0\r\n";
@@ -173,6 +394,12 @@ mod tests {
         assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
         assert!(expected_response.http_version() == actual_response.http_version());
 
+        // The paths going through a leaf are not found, and the nested dictionaries are listed.
+        let request = b"GET /phones/home/RO/0 HTTP/1.1\r\n";
+        assert!(parse_request(request).status() == StatusCode::NotFound);
+        let request = b"GET /phones/home/ HTTP/1.1\r\n";
+        assert!(parse_request(request).body().unwrap() == Body::new("RO\nUK".to_string()));
+
         // Test Ok path.
         let request = b"GET http://169.254.169.254/ HTTP/1.0\r\n";
         let mut expected_response = Response::new(Version::Http10, StatusCode::OK);
@@ -194,17 +421,103 @@ mod tests {
         assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
         assert!(expected_response.http_version() == actual_response.http_version());
 
+        // The responses are tagged, and the requests with a matching tag get no body.
+        let mut response_buf = Vec::new();
+        actual_response.write_all(&mut response_buf).unwrap();
+        let tag = etag::entity_tag(b"43");
+        assert!(String::from_utf8(response_buf)
+            .unwrap()
+            .contains(&format!("ETag: {}\r\n", tag)));
+        let request = format!("GET /age HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", tag);
+        let actual_response = parse_request(request.as_bytes());
+        assert!(actual_response.status() == StatusCode::NotModified);
+        assert!(actual_response.body().is_none());
+        let request = b"GET /age HTTP/1.1\r\nIf-None-Match: \"0\"\r\n\r\n";
+        assert!(parse_request(request).status() == StatusCode::OK);
+
+        // The path is decoded, and the query is ignored.
+        let request = b"GET /a%67e?format=json HTTP/1.1\r\n";
+        let actual_response = parse_request(request);
+        assert!(actual_response.status() == StatusCode::OK);
+        assert!(actual_response.body().unwrap() == Body::new("43".to_string()));
+        // The dot segments are removed.
+        let request = b"GET /name/../name/./%2E%2E/age HTTP/1.1\r\n";
+        let actual_response = parse_request(request);
+        assert!(actual_response.status() == StatusCode::OK);
+        assert!(actual_response.body().unwrap() == Body::new("43".to_string()));
+
+        // Test the requests for hosts which are not allowed.
+        MMDS.lock()
+            .unwrap()
+            .set_allowed_hosts(Some(vec![String::from("169.254.169.254")]));
+        let request = b"GET /age HTTP/1.1\r\nHost: 169.254.169.254\r\n\r\n";
+        assert!(parse_request(request).status() == StatusCode::OK);
+        let request = b"GET /age HTTP/1.1\r\nHost: evil.example\r\n\r\n";
+        let actual_response = parse_request(request);
+        assert!(actual_response.status() == StatusCode::Forbidden);
+        assert!(actual_response.body().unwrap() == Body::new("Host not allowed.".to_string()));
+        assert!(parse_request(b"GET /age HTTP/1.0\r\n\r\n").status() == StatusCode::Forbidden);
+        MMDS.lock().unwrap().set_allowed_hosts(None);
+
+        // Test the guests which prefer JSON.
+        let request = b"GET /name HTTP/1.1\r\nAccept: application/json\r\n\r\n";
+        let actual_response = parse_request(request);
+        assert!(actual_response.status() == StatusCode::OK);
+        assert!(
+            actual_response.body().unwrap()
+                == Body::new(r#"{"first":"John","second":"Doe"}"#.to_string())
+        );
+        let mut response_buf = Vec::new();
+        actual_response.write_all(&mut response_buf).unwrap();
+        assert!(String::from_utf8(response_buf)
+            .unwrap()
+            .contains("Content-Type: application/json\r\n"));
+        let request = b"GET /age HTTP/1.1\r\nAccept: text/plain, application/json\r\n\r\n";
+        assert!(parse_request(request).body().unwrap() == Body::new("43".to_string()));
+        let request = b"GET /age HTTP/1.1\r\nAccept: text/html\r\n\r\n";
+        assert!(parse_request(request).body().unwrap() == Body::new("43".to_string()));
+        let request = b"GET /phones/ HTTP/1.1\r\nAccept: application/json\r\n\r\n";
+        assert!(
+            parse_request(request).body().unwrap()
+                == Body::new(
+                    r#"{"home":{"RO":"+40 1234567","UK":"+44 1234567"},"mobile":"+44 2345678"}"#
+                        .to_string()
+                )
+        );
+
+        // Test the user data, which is sent as is, and takes precedence over the metadata.
+        let request = b"GET /latest/user-data HTTP/1.1\r\n\r\n";
+        assert!(parse_request(request).status() == StatusCode::NotFound);
+        let user_data = b"MIME-Version: 1.0\r\n\r\n\x1f\x8b\x00".to_vec();
+        MMDS.lock()
+            .unwrap()
+            .put_user_data(user_data.clone())
+            .unwrap();
+        for request in &[
+            &b"GET /latest/user-data HTTP/1.1\r\n\r\n"[..],
+            b"GET /latest/meta-data/../user-data HTTP/1.1\r\nAccept: application/json\r\n\r\n",
+        ] {
+            let actual_response = parse_request(request);
+            assert!(actual_response.status() == StatusCode::OK);
+            assert!(actual_response.body().unwrap() == Body::new(user_data.clone()));
+            let mut response_buf = Vec::new();
+            actual_response.write_all(&mut response_buf).unwrap();
+            assert!(String::from_utf8_lossy(&response_buf)
+                .contains("Content-Type: application/octet-stream\r\n"));
+        }
+
         // Test Internal Server Error.
         let data = r#"{
             "name": {
                 "first": "John",
                 "second": "Doe"
             },
-            "age": 43
+            "age": null
         }"#;
         MMDS.lock()
             .unwrap()
-            .put_data(serde_json::from_str(data).unwrap());
+            .put_data(serde_json::from_str(data).unwrap())
+            .unwrap();
 
         let request = b"GET http://169.254.169.254/age HTTP/1.0\r\n";
         let mut expected_response = Response::new(Version::Http10, StatusCode::InternalServerError);
@@ -214,5 +527,30 @@ mod tests {
         assert!(expected_response.status() == actual_response.status());
         assert!(expected_response.body().unwrap() == actual_response.body().unwrap());
         assert!(expected_response.http_version() == actual_response.http_version());
+
+        // Test the metrics of the requests and of the responses.
+        let rx_get = METRICS.mmds.rx_get.count();
+        let rx_put = METRICS.mmds.rx_put.count();
+        let rx_patch = METRICS.mmds.rx_patch.count();
+        let rx_bad_request = METRICS.mmds.rx_bad_request.count();
+        let tx_2xx = METRICS.mmds.tx_2xx.count();
+        let tx_3xx = METRICS.mmds.tx_3xx.count();
+        let tx_4xx = METRICS.mmds.tx_4xx.count();
+        let tx_5xx = METRICS.mmds.tx_5xx.count();
+        assert!(parse_request(b"GET /name/first HTTP/1.1\r\n").status() == StatusCode::OK);
+        assert!(
+            parse_request(b"GET /age HTTP/1.1\r\n").status() == StatusCode::InternalServerError
+        );
+        assert!(parse_request(b"PUT / HTTP/1.1\r\n").status() == StatusCode::MethodNotAllowed);
+        assert!(parse_request(b"PATCH / HTTP/1.1\r\n").status() == StatusCode::MethodNotAllowed);
+        assert!(parse_request(b"G(ET / HTTP/1.1\r\n").status() == StatusCode::BadRequest);
+        assert_eq!(METRICS.mmds.rx_get.count(), rx_get + 2);
+        assert_eq!(METRICS.mmds.rx_put.count(), rx_put + 1);
+        assert_eq!(METRICS.mmds.rx_patch.count(), rx_patch + 1);
+        assert_eq!(METRICS.mmds.rx_bad_request.count(), rx_bad_request + 1);
+        assert_eq!(METRICS.mmds.tx_2xx.count(), tx_2xx + 1);
+        assert_eq!(METRICS.mmds.tx_3xx.count(), tx_3xx);
+        assert_eq!(METRICS.mmds.tx_4xx.count(), tx_4xx + 3);
+        assert_eq!(METRICS.mmds.tx_5xx.count(), tx_5xx + 1);
     }
 }
@@ -58,3 +58,5 @@ ioctl_iow_nr!(TUNSETVNETLE, TUNTAP, 220, ::std::os::raw::c_int);
 ioctl_ior_nr!(TUNGETVNETLE, TUNTAP, 221, ::std::os::raw::c_int);
 ioctl_iow_nr!(TUNSETVNETBE, TUNTAP, 222, ::std::os::raw::c_int);
 ioctl_ior_nr!(TUNGETVNETBE, TUNTAP, 223, ::std::os::raw::c_int);
+ioctl_ior_nr!(TUNSETSTEERINGEBPF, TUNTAP, 224, ::std::os::raw::c_int);
+ioctl_ior_nr!(TUNSETFILTEREBPF, TUNTAP, 225, ::std::os::raw::c_int);
@@ -0,0 +1,72 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+
+use libc;
+
+// The command of the bpf syscall which opens a pinned object.
+const BPF_OBJ_GET: libc::c_int = 7;
+
+// The part of `union bpf_attr` used by BPF_OBJ_GET.
+#[repr(C)]
+#[derive(Default)]
+struct BpfObjAttr {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+/// Opens the eBPF program pinned at `path`, in a BPF filesystem.
+///
+/// The program is loaded, and kept loaded, by whoever pinned it, e.g. with
+/// `bpftool prog load filter.o /sys/fs/bpf/filter`. The returned file holds a reference to the
+/// program until it is closed.
+pub fn open_pinned_program(path: &Path) -> IoResult<File> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| IoError::new(ErrorKind::InvalidInput, "the path contains a nul byte"))?;
+    let attr = BpfObjAttr {
+        pathname: path.as_ptr() as u64,
+        ..Default::default()
+    };
+
+    // This is safe because the kernel only reads `attr`, and the path it points to outlives the
+    // call. The return value is checked.
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_OBJ_GET,
+            &attr as *const BpfObjAttr,
+            mem::size_of::<BpfObjAttr>(),
+        )
+    };
+    if fd < 0 {
+        return Err(IoError::last_os_error());
+    }
+
+    // This is safe because the kernel just returned this fd, which nothing else owns.
+    Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_pinned_program() {
+        assert_eq!(
+            open_pinned_program(Path::new("/sys/fs/bpf/firecracker/\0missing"))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidInput
+        );
+        // Nothing is pinned outside of a BPF filesystem.
+        assert!(open_pinned_program(Path::new("/dev/null")).is_err());
+    }
+}
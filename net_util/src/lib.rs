@@ -16,7 +16,9 @@ extern crate serde;
 extern crate net_gen;
 extern crate sys_util;
 
+mod bpf;
 mod mac;
+mod pcap;
 mod tap;
 
 use std::io::Error as IoError;
@@ -24,7 +26,9 @@ use std::mem;
 use std::net;
 use std::os::unix::io::FromRawFd;
 
+pub use bpf::open_pinned_program;
 pub use mac::{MacAddr, MAC_ADDR_LEN};
+pub use pcap::{PcapWriter, DEFAULT_SNAP_LEN};
 pub use tap::{Error as TapError, Tap};
 
 #[derive(Debug)]
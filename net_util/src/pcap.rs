@@ -0,0 +1,167 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{Result as IoResult, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The magic number of the pcap format, with timestamps in microseconds.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+// The link type of Ethernet frames.
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// The length of the global header of a pcap file.
+pub const PCAP_HEADER_LEN: usize = 24;
+/// The length of the header of every frame in a pcap file.
+pub const PCAP_RECORD_HEADER_LEN: usize = 16;
+
+/// The default number of bytes captured from every frame, which is enough for a whole frame.
+pub const DEFAULT_SNAP_LEN: u32 = 65535;
+
+fn write_u16<W: Write>(out: &mut W, value: u16) -> IoResult<()> {
+    out.write_all(&[value as u8, (value >> 8) as u8])
+}
+
+fn write_u32<W: Write>(out: &mut W, value: u32) -> IoResult<()> {
+    out.write_all(&[
+        value as u8,
+        (value >> 8) as u8,
+        (value >> 16) as u8,
+        (value >> 24) as u8,
+    ])
+}
+
+/// Writes Ethernet frames to `out` in the pcap format, which tools such as `tcpdump` and
+/// `wireshark` read.
+///
+/// Every frame is truncated to at most `snap_len` bytes. When `max_bytes` is set, the capture
+/// stops before the output grows past it, so that a busy interface can't fill up the disk.
+pub struct PcapWriter<W: Write> {
+    out: W,
+    snap_len: u32,
+    max_bytes: Option<u64>,
+    written: u64,
+    full: bool,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the pcap header to `out` and returns a writer for the frames that follow it.
+    pub fn new(mut out: W, snap_len: u32, max_bytes: Option<u64>) -> IoResult<Self> {
+        write_u32(&mut out, PCAP_MAGIC)?;
+        write_u16(&mut out, PCAP_VERSION_MAJOR)?;
+        write_u16(&mut out, PCAP_VERSION_MINOR)?;
+        // The timezone offset and the accuracy of the timestamps, both always 0.
+        write_u32(&mut out, 0)?;
+        write_u32(&mut out, 0)?;
+        write_u32(&mut out, snap_len)?;
+        write_u32(&mut out, LINKTYPE_ETHERNET)?;
+
+        Ok(PcapWriter {
+            out,
+            snap_len,
+            max_bytes,
+            written: PCAP_HEADER_LEN as u64,
+            full: false,
+        })
+    }
+
+    /// Appends `frame`, truncated to the snap length, with the current time as its timestamp.
+    ///
+    /// Returns the number of bytes written, which is 0 once the byte budget ran out.
+    pub fn write_frame(&mut self, frame: &[u8]) -> IoResult<usize> {
+        if self.full {
+            return Ok(0);
+        }
+
+        let incl_len = frame.len().min(self.snap_len as usize);
+        let len = PCAP_RECORD_HEADER_LEN + incl_len;
+        if let Some(max_bytes) = self.max_bytes {
+            if self.written + len as u64 > max_bytes {
+                // Don't write any more frames, even smaller ones, so that the capture doesn't
+                // have holes in it.
+                self.full = true;
+                return Ok(0);
+            }
+        }
+
+        // A clock before the epoch is clamped to it.
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        write_u32(&mut self.out, timestamp.as_secs() as u32)?;
+        write_u32(&mut self.out, timestamp.subsec_micros())?;
+        write_u32(&mut self.out, incl_len as u32)?;
+        write_u32(&mut self.out, frame.len() as u32)?;
+        self.out.write_all(&frame[..incl_len])?;
+
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    /// Returns `true` when the byte budget ran out, and no more frames are written.
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(bytes: &[u8]) -> u32 {
+        bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+    }
+
+    #[test]
+    fn test_pcap_header() {
+        let writer = PcapWriter::new(Vec::new(), 128, None).unwrap();
+        let out = writer.out;
+        assert_eq!(out.len(), PCAP_HEADER_LEN);
+        assert_eq!(&out[..8], &[0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0]);
+        assert_eq!(read_u32(&out[16..]), 128);
+        assert_eq!(read_u32(&out[20..]), LINKTYPE_ETHERNET);
+    }
+
+    #[test]
+    fn test_write_frame() {
+        let mut writer = PcapWriter::new(Vec::new(), 4, None).unwrap();
+        let frame = [1u8, 2, 3, 4, 5, 6];
+        assert_eq!(
+            writer.write_frame(&frame).unwrap(),
+            PCAP_RECORD_HEADER_LEN + 4
+        );
+        assert_eq!(
+            writer.write_frame(&frame[..2]).unwrap(),
+            PCAP_RECORD_HEADER_LEN + 2
+        );
+
+        let record = &writer.out[PCAP_HEADER_LEN..];
+        assert_eq!(record.len(), 2 * PCAP_RECORD_HEADER_LEN + 6);
+        // The first frame is truncated to the snap length.
+        assert_eq!(read_u32(&record[8..]), 4);
+        assert_eq!(read_u32(&record[12..]), 6);
+        assert_eq!(&record[16..20], &frame[..4]);
+        assert_eq!(read_u32(&record[28..]), 2);
+        assert_eq!(read_u32(&record[32..]), 2);
+        assert_eq!(&record[36..], &frame[..2]);
+    }
+
+    #[test]
+    fn test_byte_budget() {
+        let max_bytes = (PCAP_HEADER_LEN + 2 * PCAP_RECORD_HEADER_LEN + 10) as u64;
+        let mut writer = PcapWriter::new(Vec::new(), DEFAULT_SNAP_LEN, Some(max_bytes)).unwrap();
+        let frame = [0u8; 6];
+        assert!(writer.write_frame(&frame).unwrap() > 0);
+        assert!(!writer.is_full());
+
+        // The second frame doesn't fit, and neither does a smaller one after it.
+        assert_eq!(writer.write_frame(&frame).unwrap(), 0);
+        assert!(writer.is_full());
+        assert_eq!(writer.write_frame(&frame[..1]).unwrap(), 0);
+        assert_eq!(
+            writer.out.len(),
+            PCAP_HEADER_LEN + PCAP_RECORD_HEADER_LEN + 6
+        );
+    }
+}
@@ -212,6 +212,22 @@ impl Tap {
         Ok(())
     }
 
+    /// Attach the eBPF socket filter program `prog` to the tap interface, or detach the current
+    /// program when `prog` is None.
+    ///
+    /// The program filters the packets sent by the host to the tap interface, which are read by
+    /// the guest, and drops the packets it returns 0 for.
+    pub fn set_ebpf_filter(&self, prog: Option<&File>) -> Result<()> {
+        let prog_fd: c_int = prog.map_or(-1, |prog| prog.as_raw_fd());
+        // ioctl is safe. Called with a valid tap fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.tap_file, net_gen::TUNSETFILTEREBPF(), &prog_fd) };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+
+        Ok(())
+    }
+
     fn get_ifreq(&self) -> net_gen::ifreq {
         let mut ifreq: net_gen::ifreq = Default::default();
 
@@ -449,6 +465,16 @@ mod tests {
         assert!(ret.is_ok());
     }
 
+    #[test]
+    fn test_tap_ebpf_filter() {
+        let tap = Tap::new().unwrap();
+        // Detaching succeeds even when no program is attached.
+        assert!(tap.set_ebpf_filter(None).is_ok());
+        // Only eBPF socket filter programs can be attached.
+        let not_a_prog = File::open("/dev/null").unwrap();
+        assert!(tap.set_ebpf_filter(Some(&not_a_prog)).is_err());
+    }
+
     #[test]
     fn test_tap_get_ifreq() {
         let tap = Tap::new().unwrap();
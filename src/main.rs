@@ -12,6 +12,7 @@ extern crate jailer;
 #[macro_use]
 extern crate logger;
 extern crate mmds;
+extern crate net_util;
 extern crate seccomp;
 extern crate vmm;
 
@@ -19,6 +20,7 @@ use backtrace::Backtrace;
 use clap::{App, Arg};
 
 use std::io::ErrorKind;
+use std::net::Ipv6Addr;
 use std::panic;
 use std::path::PathBuf;
 use std::sync::mpsc::channel;
@@ -27,10 +29,13 @@ use std::sync::{Arc, RwLock};
 use api_server::{ApiServer, Error, UnixDomainSocket};
 use jailer::FirecrackerContext;
 use logger::{Metric, LOGGER, METRICS};
+use mmds::data_store::INSTANCE_ID_VARIABLE;
 use mmds::MMDS;
+use net_util::MacAddr;
 use vmm::vmm_config::instance_info::{InstanceInfo, InstanceState};
 
 const DEFAULT_API_SOCK_PATH: &str = "/tmp/firecracker.socket";
+const DEFAULT_API_MAX_CONNECTIONS: &str = "64";
 const DEFAULT_INSTANCE_ID: &str = "anonymous-instance";
 
 fn main() {
@@ -70,6 +75,61 @@ fn main() {
                 .default_value(DEFAULT_API_SOCK_PATH)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("api_max_connections")
+                .long("api-max-connections")
+                .help(
+                    "Maximum number of connections open at the same time on the API socket, \
+                     at least 1",
+                )
+                .default_value(DEFAULT_API_MAX_CONNECTIONS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mmds_allowed_hosts")
+                .long("mmds-allowed-hosts")
+                .help(
+                    "Comma separated hosts the guest can send MMDS requests for, \
+                     others get 403 Forbidden",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mmds_allowed_macs")
+                .long("mmds-allowed-macs")
+                .help(
+                    "Comma separated MAC addresses the guest can reach MMDS from, \
+                     the frames for MMDS sent from others are dropped",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mmds_hop_limit")
+                .long("mmds-hop-limit")
+                .help(
+                    "TTL, or IPv6 hop limit, of the packets carrying the MMDS responses, \
+                     from 1 (the default) to 255",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mmds_ipv6_address")
+                .long("mmds-ipv6-address")
+                .help(
+                    "IPv6 link-local address the guest can reach MMDS on, \
+                     defaults to fe80::a9fe:a9fe",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mmds_size_limit")
+                .long("mmds-size-limit")
+                .help(
+                    "Maximum size in bytes of the MMDS data store, and of the user data, \
+                     larger updates get 413 Payload Too Large",
+                )
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("context")
                 .long("context")
@@ -82,6 +142,12 @@ fn main() {
         .value_of("api_sock")
         .map(|s| PathBuf::from(s))
         .expect("Missing argument: api_sock");
+    // The API server would answer every connection with 503 if it could keep none open.
+    let api_max_connections = cmd_arguments
+        .value_of("api_max_connections")
+        .map(|s| s.parse::<usize>().ok().filter(|max| *max > 0))
+        .expect("Missing argument: api_max_connections")
+        .expect("Invalid argument: api_max_connections");
 
     let mut instance_id = String::from(DEFAULT_INSTANCE_ID);
     let mut seccomp_level = seccomp::SECCOMP_LEVEL_ADVANCED;
@@ -99,14 +165,67 @@ fn main() {
         start_time_cpu_us = Some(context.start_time_cpu_us);
     }
 
+    // The metadata can refer to the ID of the instance with the {{instance_id}} placeholder.
+    MMDS.lock()
+        .expect("Failed to configure MMDS due to poisoned lock")
+        .set_variable(INSTANCE_ID_VARIABLE, instance_id.clone());
+
     let shared_info = Arc::new(RwLock::new(InstanceInfo {
         state: InstanceState::Uninitialized,
         id: instance_id,
     }));
+    if let Some(hosts) = cmd_arguments.value_of("mmds_allowed_hosts") {
+        MMDS.lock()
+            .expect("Failed to configure MMDS due to poisoned lock")
+            .set_allowed_hosts(Some(hosts.split(',').map(String::from).collect()));
+    }
+    if let Some(mac_addrs) = cmd_arguments.value_of("mmds_allowed_macs") {
+        let mac_addrs = mac_addrs
+            .split(',')
+            .map(|mac_addr| {
+                MacAddr::parse_str(mac_addr).expect("Invalid argument: mmds_allowed_macs")
+            })
+            .collect();
+        MMDS.lock()
+            .expect("Failed to configure MMDS due to poisoned lock")
+            .set_allowed_mac_addrs(Some(mac_addrs));
+    }
+    if let Some(hop_limit) = cmd_arguments.value_of("mmds_hop_limit") {
+        // The packets can't be sent with a TTL of 0.
+        let hop_limit = hop_limit
+            .parse::<u8>()
+            .ok()
+            .filter(|hop_limit| *hop_limit > 0)
+            .expect("Invalid argument: mmds_hop_limit");
+        MMDS.lock()
+            .expect("Failed to configure MMDS due to poisoned lock")
+            .set_hop_limit(Some(hop_limit));
+    }
+    if let Some(addr) = cmd_arguments.value_of("mmds_ipv6_address") {
+        // Only the link-local addresses (fe80::/10) are known to be on the link of the guest.
+        let addr = addr
+            .parse::<Ipv6Addr>()
+            .ok()
+            .filter(|addr| addr.segments()[0] & 0xffc0 == 0xfe80)
+            .expect("Invalid argument: mmds_ipv6_address");
+        MMDS.lock()
+            .expect("Failed to configure MMDS due to poisoned lock")
+            .set_ipv6_addr(Some(addr));
+    }
+    if let Some(limit) = cmd_arguments.value_of("mmds_size_limit") {
+        MMDS.lock()
+            .expect("Failed to configure MMDS due to poisoned lock")
+            .set_data_store_limit(
+                limit
+                    .parse::<usize>()
+                    .expect("Invalid argument: mmds_size_limit"),
+            );
+    }
+
     let mmds_info = MMDS.clone();
     let (to_vmm, from_api) = channel();
-    let server =
-        ApiServer::new(mmds_info, shared_info.clone(), to_vmm).expect("Cannot create API server");
+    let server = ApiServer::new(mmds_info, shared_info.clone(), to_vmm, api_max_connections)
+        .expect("Cannot create API server");
 
     let api_event_fd = server
         .get_event_fd_clone()
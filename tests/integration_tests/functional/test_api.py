@@ -648,8 +648,8 @@ def _drive_patch(test_microvm):
         is_read_only=True
     )
     assert not test_microvm.api_session.is_good_response(response.status_code)
-    assert "Invalid PATCH payload. Only updates on path_on_host are allowed." \
-           in response.text
+    assert "Invalid PATCH payload. Only updates on path_on_host or " \
+           "io_trace are allowed, one at a time." in response.text
 
     # Updates to `is_root_device` with a valid value are not allowed.
     response = test_microvm.drive.patch(
@@ -658,8 +658,8 @@ def _drive_patch(test_microvm):
         is_root_device=False
     )
     assert not test_microvm.api_session.is_good_response(response.status_code)
-    assert "Invalid PATCH payload. Only updates on path_on_host are allowed." \
-           in response.text
+    assert "Invalid PATCH payload. Only updates on path_on_host or " \
+           "io_trace are allowed, one at a time." in response.text
 
     # Updates to `path_on_host` with an invalid path are not allowed.
     response = test_microvm.drive.patch(
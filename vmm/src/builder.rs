@@ -0,0 +1,503 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Builder used to configure and boot a microVM directly from a Rust application, without going
+//! through the API server.
+//!
+//! The builder drives the VMM thread with the same actions the API server sends, so every
+//! configuration is validated exactly as it would be if it came from an API request.
+//!
+//! # Example
+//!
+//! ```no_run
+//! extern crate vmm;
+//!
+//! use std::path::PathBuf;
+//!
+//! use vmm::builder::VmBuilder;
+//! use vmm::vmm_config::drive::BlockDeviceConfig;
+//!
+//! fn main() {
+//!     let microvm = VmBuilder::new()
+//!         .memory(256)
+//!         .vcpus(2)
+//!         .kernel("/path/to/vmlinux", Some("console=ttyS0 reboot=k panic=1 pci=off"))
+//!         .add_drive(BlockDeviceConfig {
+//!             drive_id: String::from("rootfs"),
+//!             path_on_host: PathBuf::from("/path/to/rootfs.ext4"),
+//!             is_root_device: true,
+//!             partuuid: None,
+//!             is_read_only: false,
+//!             rate_limiter: None,
+//!         })
+//!         .on_event(|event| println!("microVM event: {:?}", event))
+//!         .boot()
+//!         .expect("Failed to boot the microVM");
+//!
+//!     microvm.join();
+//! }
+//! ```
+
+use std::fmt::{Display, Formatter};
+use std::os::unix::io::RawFd;
+use std::result;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use futures::sync::oneshot;
+use futures::Future;
+
+use sys_util::EventFd;
+use vmm_config::boot_source::BootSourceConfig;
+use vmm_config::device_features::DeviceFeatures;
+use vmm_config::drive::BlockDeviceConfig;
+use vmm_config::guest_events::{GuestEvent, GuestEventKind};
+use vmm_config::instance_info::{InstanceInfo, InstanceState};
+use vmm_config::machine_config::{CpuFeaturesTemplate, VmConfig, WatchdogAction};
+use vmm_config::net::NetworkInterfaceConfig;
+use vmm_config::serial::SerialInput;
+#[cfg(feature = "vsock")]
+use vmm_config::vsock::VsockDeviceConfig;
+use {OutcomeSender, VmmAction, VmmActionError, VmmData};
+
+const DEFAULT_INSTANCE_ID: &str = "anonymous-instance";
+
+/// Events reported to the callbacks registered with `VmBuilder::on_event`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VmEvent {
+    /// The microVM was configured and its vCPUs are running.
+    Started,
+    /// The guest stopped pinging its watchdog device before the watchdog timeout. The callbacks
+    /// are invoked before the configured `WatchdogAction` is taken.
+    WatchdogExpired,
+    /// The guest panicked or crashed. The event is also recorded in the list returned by the
+    /// `GET /events` API request.
+    Guest(GuestEventKind),
+    /// The VMM is stopping with the given exit code. The callbacks are invoked right before the
+    /// process exits.
+    Exited(i32),
+}
+
+/// Callback invoked on every `VmEvent`.
+pub type EventCallback = Arc<Fn(&VmEvent) + Send + Sync>;
+
+/// Errors associated with building and controlling an embedded microVM.
+#[derive(Debug)]
+pub enum Error {
+    /// The VMM refused one of the actions.
+    Action(VmmActionError),
+    /// The VMM thread is no longer servicing actions.
+    ChannelClosed,
+    /// Cannot create or write the event used to notify the VMM of new actions.
+    EventFd(::sys_util::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        use self::Error::*;
+
+        match *self {
+            Action(ref err) => write!(f, "{}", err),
+            ChannelClosed => write!(f, "The VMM thread is no longer servicing actions."),
+            EventFd(ref err) => write!(f, "Cannot notify the VMM thread: {:?}", err),
+        }
+    }
+}
+
+/// Result type for the embedding API.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Configures a microVM and boots it.
+pub struct VmBuilder {
+    instance_id: String,
+    vm_config: VmConfig,
+    boot_source: Option<BootSourceConfig>,
+    drives: Vec<BlockDeviceConfig>,
+    network_interfaces: Vec<NetworkInterfaceConfig>,
+    #[cfg(feature = "vsock")]
+    vsock_devices: Vec<VsockDeviceConfig>,
+    seccomp_level: u32,
+    kvm_fd: Option<RawFd>,
+    event_callbacks: Vec<EventCallback>,
+}
+
+impl VmBuilder {
+    /// Creates a builder for a microVM with the default machine configuration and no devices.
+    pub fn new() -> Self {
+        VmBuilder {
+            instance_id: String::from(DEFAULT_INSTANCE_ID),
+            vm_config: VmConfig::default(),
+            boot_source: None,
+            drives: Vec::new(),
+            network_interfaces: Vec::new(),
+            #[cfg(feature = "vsock")]
+            vsock_devices: Vec::new(),
+            seccomp_level: ::seccomp::SECCOMP_LEVEL_ADVANCED,
+            kvm_fd: None,
+            event_callbacks: Vec::new(),
+        }
+    }
+
+    /// Sets the identifier of the microVM instance.
+    pub fn instance_id(mut self, instance_id: &str) -> Self {
+        self.instance_id = String::from(instance_id);
+        self
+    }
+
+    /// Sets the size of the guest memory, in MiB.
+    pub fn memory(mut self, mem_size_mib: usize) -> Self {
+        self.vm_config.mem_size_mib = Some(mem_size_mib);
+        self
+    }
+
+    /// Sets the number of vCPUs.
+    pub fn vcpus(mut self, vcpu_count: u8) -> Self {
+        self.vm_config.vcpu_count = Some(vcpu_count);
+        self
+    }
+
+    /// Enables or disables hyperthreading.
+    pub fn ht_enabled(mut self, ht_enabled: bool) -> Self {
+        self.vm_config.ht_enabled = Some(ht_enabled);
+        self
+    }
+
+    /// Sets the CPU template applied to the guest CPUID.
+    pub fn cpu_template(mut self, cpu_template: CpuFeaturesTemplate) -> Self {
+        self.vm_config.cpu_template = Some(cpu_template);
+        self
+    }
+
+    /// Exposes the performance monitoring counters of the host to the guest.
+    pub fn vpmu(mut self, vpmu: bool) -> Self {
+        self.vm_config.vpmu = Some(vpmu);
+        self
+    }
+
+    /// Lets the kernel same-page merging deduplicate the guest memory.
+    pub fn mergeable(mut self, mergeable: bool) -> Self {
+        self.vm_config.mergeable = Some(mergeable);
+        self
+    }
+
+    /// Sets the size in MiB of the gap at the end of the 32bit address space, where the devices
+    /// are mapped.
+    pub fn mmio_gap_size(mut self, mmio_gap_size_mib: usize) -> Self {
+        self.vm_config.mmio_gap_size_mib = Some(mmio_gap_size_mib);
+        self
+    }
+
+    /// Sets the guest physical address the MMIO devices are mapped from.
+    pub fn mmio_base(mut self, mmio_base: u64) -> Self {
+        self.vm_config.mmio_base = Some(mmio_base);
+        self
+    }
+
+    /// Also describes the guest in ACPI tables, next to the MP table.
+    pub fn acpi(mut self, acpi: bool) -> Self {
+        self.vm_config.acpi = Some(acpi);
+        self
+    }
+
+    /// Attaches a watchdog device, and sets what happens when the guest stops pinging it.
+    pub fn watchdog(mut self, watchdog_action: WatchdogAction) -> Self {
+        self.vm_config.watchdog_action = Some(watchdog_action);
+        self
+    }
+
+    /// Sets the kernel image and, optionally, the kernel command line. When no command line is
+    /// given, the default one is used.
+    pub fn kernel(mut self, kernel_image_path: &str, boot_args: Option<&str>) -> Self {
+        self.boot_source = Some(BootSourceConfig {
+            kernel_image_path: String::from(kernel_image_path),
+            boot_args: boot_args.map(String::from),
+        });
+        self
+    }
+
+    /// Attaches a block device.
+    pub fn add_drive(mut self, drive: BlockDeviceConfig) -> Self {
+        self.drives.push(drive);
+        self
+    }
+
+    /// Attaches a network interface.
+    pub fn add_network_interface(mut self, network_interface: NetworkInterfaceConfig) -> Self {
+        self.network_interfaces.push(network_interface);
+        self
+    }
+
+    #[cfg(feature = "vsock")]
+    /// Attaches a vsock device.
+    pub fn add_vsock_device(mut self, vsock_device: VsockDeviceConfig) -> Self {
+        self.vsock_devices.push(vsock_device);
+        self
+    }
+
+    /// Sets the level of seccomp filtering applied to the VMM and vCPU threads.
+    /// See `seccomp::SeccompLevel` for more information about seccomp levels.
+    pub fn seccomp_level(mut self, seccomp_level: u32) -> Self {
+        self.seccomp_level = seccomp_level;
+        self
+    }
+
+    /// Uses an already opened file descriptor of `/dev/kvm` instead of opening it.
+    pub fn kvm_fd(mut self, kvm_fd: RawFd) -> Self {
+        self.kvm_fd = Some(kvm_fd);
+        self
+    }
+
+    /// Registers a callback invoked on every `VmEvent`. `VmEvent::Exited` is reported from the
+    /// VMM thread, after the seccomp filters were installed, so callbacks should return quickly
+    /// and avoid syscalls the filters don't allow.
+    pub fn on_event<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&VmEvent) + Send + Sync + 'static,
+    {
+        self.event_callbacks.push(Arc::new(callback));
+        self
+    }
+
+    /// Starts the VMM thread, applies the configuration and boots the microVM.
+    ///
+    /// The logger is not initialized by the builder; applications that want the VMM logs and
+    /// metrics have to initialize `logger::LOGGER` before booting. When the microVM fails to
+    /// boot, the VMM thread is terminated before the error is returned.
+    pub fn boot(self) -> Result<MicroVm> {
+        let shared_info = Arc::new(RwLock::new(InstanceInfo {
+            state: InstanceState::Uninitialized,
+            id: self.instance_id.clone(),
+        }));
+        let event_fd = EventFd::new().map_err(Error::EventFd)?;
+        let (to_vmm, from_api) = channel();
+        let event_callbacks = self.event_callbacks.clone();
+        let vmm_thread_handle = ::spawn_vmm_thread(
+            shared_info.clone(),
+            event_fd.try_clone().map_err(Error::EventFd)?,
+            from_api,
+            self.seccomp_level,
+            self.kvm_fd,
+            event_callbacks.clone(),
+        );
+
+        let microvm = MicroVm {
+            shared_info,
+            to_vmm,
+            event_fd,
+            vmm_thread_handle,
+        };
+
+        if let Err(e) = self.configure(&microvm) {
+            microvm.shut_down();
+            return Err(e);
+        }
+
+        for callback in event_callbacks.iter() {
+            callback(&VmEvent::Started);
+        }
+
+        Ok(microvm)
+    }
+
+    // Applies the configuration to the VMM thread of `microvm` and starts the microVM.
+    fn configure(self, microvm: &MicroVm) -> Result<()> {
+        let vm_config = self.vm_config;
+        microvm.send_action(|sender| VmmAction::SetVmConfiguration(vm_config, sender))?;
+        if let Some(boot_source) = self.boot_source {
+            microvm.send_action(|sender| VmmAction::ConfigureBootSource(boot_source, sender))?;
+        }
+        for drive in self.drives {
+            microvm.send_action(|sender| VmmAction::InsertBlockDevice(drive, sender))?;
+        }
+        for network_interface in self.network_interfaces {
+            microvm
+                .send_action(|sender| VmmAction::InsertNetworkDevice(network_interface, sender))?;
+        }
+        #[cfg(feature = "vsock")]
+        for vsock_device in self.vsock_devices {
+            microvm.send_action(|sender| VmmAction::InsertVsockDevice(vsock_device, sender))?;
+        }
+        microvm.send_action(VmmAction::StartMicroVm)?;
+        Ok(())
+    }
+}
+
+impl Default for VmBuilder {
+    fn default() -> Self {
+        VmBuilder::new()
+    }
+}
+
+/// Handle to a microVM booted with `VmBuilder`.
+pub struct MicroVm {
+    shared_info: Arc<RwLock<InstanceInfo>>,
+    to_vmm: Sender<Box<VmmAction>>,
+    event_fd: EventFd,
+    vmm_thread_handle: thread::JoinHandle<()>,
+}
+
+impl MicroVm {
+    /// Returns the current state of the microVM.
+    pub fn state(&self) -> InstanceState {
+        // Use expect() to crash if the VMM thread poisoned this lock.
+        self.shared_info
+            .read()
+            .expect("Failed to read the shared info due to poisoned lock")
+            .state
+            .clone()
+    }
+
+    /// Returns the machine configuration of the microVM.
+    pub fn vm_config(&self) -> Result<VmConfig> {
+        match self.send_action(VmmAction::GetVmConfiguration)? {
+            VmmData::MachineConfiguration(vm_config) => Ok(vm_config),
+            _ => Err(Error::ChannelClosed),
+        }
+    }
+
+    /// Returns the virtio features negotiated by the device with the given ID.
+    pub fn device_features(&self, device_id: &str) -> Result<DeviceFeatures> {
+        let device_id = String::from(device_id);
+        match self.send_action(|sender| VmmAction::GetDeviceFeatures(device_id, sender))? {
+            VmmData::DeviceFeatures(features) => Ok(features),
+            _ => Err(Error::ChannelClosed),
+        }
+    }
+
+    /// Returns the most recent panics and crashes of the guest, oldest first.
+    pub fn guest_events(&self) -> Result<Vec<GuestEvent>> {
+        match self.send_action(VmmAction::GetGuestEvents)? {
+            VmmData::GuestEvents(events) => Ok(events),
+            _ => Err(Error::ChannelClosed),
+        }
+    }
+
+    /// Makes the guest see the new size of the backing file of a block device.
+    pub fn rescan_block_device(&self, drive_id: &str) -> Result<()> {
+        let drive_id = String::from(drive_id);
+        self.send_action(|sender| VmmAction::RescanBlockDevice(drive_id, sender))
+            .map(|_| ())
+    }
+
+    /// Changes the backing file of a block device.
+    pub fn update_block_device_path(&self, drive_id: &str, path_on_host: &str) -> Result<()> {
+        let drive_id = String::from(drive_id);
+        let path_on_host = String::from(path_on_host);
+        self.send_action(|sender| VmmAction::UpdateBlockDevicePath(drive_id, path_on_host, sender))
+            .map(|_| ())
+    }
+
+    /// Sends input to the guest through the serial console, such as a magic SysRq key to make a
+    /// stuck guest reboot or crash.
+    pub fn send_serial_input(&self, serial_input: SerialInput) -> Result<()> {
+        self.send_action(|sender| VmmAction::SendSerialInput(serial_input, sender))
+            .map(|_| ())
+    }
+
+    /// Zeroes the counters of the given groups of metrics, or of all of them when `groups` is
+    /// empty, to measure a phase of the workload on its own.
+    pub fn reset_metrics(&self, groups: Vec<String>) -> Result<()> {
+        self.send_action(|sender| VmmAction::ResetMetrics(groups, sender))
+            .map(|_| ())
+    }
+
+    /// Blocks until the VMM thread terminates. Note that the VMM exits the process when the guest
+    /// shuts down, so this only returns if the VMM thread panics.
+    pub fn join(self) {
+        if self.vmm_thread_handle.join().is_err() {
+            error!("The VMM thread panicked.");
+        }
+    }
+
+    // Terminates the VMM thread of a microVM which failed to boot and waits for it.
+    fn shut_down(self) {
+        if let Err(e) = self.send_action(VmmAction::Shutdown) {
+            error!("Cannot shut down the VMM thread: {}", e);
+        }
+        if self.vmm_thread_handle.join().is_err() {
+            error!("The VMM thread panicked.");
+        }
+    }
+
+    // Sends an action to the VMM thread and waits for its outcome.
+    fn send_action<F>(&self, action: F) -> Result<VmmData>
+    where
+        F: FnOnce(OutcomeSender) -> VmmAction,
+    {
+        let (sender, receiver) = oneshot::channel();
+        self.to_vmm
+            .send(Box::new(action(sender)))
+            .map_err(|_| Error::ChannelClosed)?;
+        self.event_fd.write(1).map_err(Error::EventFd)?;
+        receiver
+            .wait()
+            .map_err(|_| Error::ChannelClosed)?
+            .map_err(Error::Action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_config() {
+        let builder = VmBuilder::new()
+            .instance_id("embedded")
+            .memory(256)
+            .vcpus(2)
+            .ht_enabled(true)
+            .watchdog(WatchdogAction::Log)
+            .vpmu(true)
+            .mergeable(true)
+            .mmio_gap_size(1024)
+            .mmio_base(0xc000_0000)
+            .acpi(true)
+            .kernel("/tmp/vmlinux", None)
+            .seccomp_level(::seccomp::SECCOMP_LEVEL_NONE)
+            .on_event(|_| ());
+
+        assert_eq!(builder.instance_id, "embedded");
+        assert_eq!(
+            builder.vm_config,
+            VmConfig {
+                vcpu_count: Some(2),
+                mem_size_mib: Some(256),
+                ht_enabled: Some(true),
+                cpu_template: None,
+                thp_policy: None,
+                dontfork: None,
+                prefault: None,
+                watchdog_action: Some(WatchdogAction::Log),
+                vpmu: Some(true),
+                mergeable: Some(true),
+                mmio_gap_size_mib: Some(1024),
+                mmio_base: Some(0xc000_0000),
+                acpi: Some(true),
+            }
+        );
+        assert_eq!(
+            builder.boot_source,
+            Some(BootSourceConfig {
+                kernel_image_path: String::from("/tmp/vmlinux"),
+                boot_args: None,
+            })
+        );
+        assert_eq!(builder.seccomp_level, ::seccomp::SECCOMP_LEVEL_NONE);
+        assert_eq!(builder.event_callbacks.len(), 1);
+    }
+
+    #[test]
+    fn test_boot_without_kernel() {
+        // The configuration is applied but the microVM can't start without a kernel.
+        let result = VmBuilder::new()
+            .seccomp_level(::seccomp::SECCOMP_LEVEL_NONE)
+            .on_event(|_| panic!("No event expected."))
+            .boot();
+        match result {
+            Err(Error::Action(VmmActionError::StartMicrovm(..))) => (),
+            _ => panic!("Expected a StartMicrovm error."),
+        }
+    }
+}
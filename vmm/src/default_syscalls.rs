@@ -92,6 +92,16 @@ const TUNSETIFF: u64 = 0x400454ca;
 const TUNSETOFFLOAD: u64 = 0x400454d0;
 const TUNSETVNETHDRSZ: u64 = 0x400454d8;
 
+// See /usr/include/linux/socket.h and /usr/include/x86_64-linux-gnu/bits/socket_type.h
+const AF_VSOCK: u64 = 40;
+const SOCK_STREAM: u64 = 1;
+const SOCK_NONBLOCK: u64 = 0x00000800;
+const SOCK_CLOEXEC: u64 = 0x00080000;
+
+// See /usr/include/asm-generic/socket.h
+const SOL_SOCKET: u64 = 1;
+const SO_ERROR: u64 = 4;
+
 // See /usr/include/asm-generic/mman-common.h and /usr/include/asm-generic/mman.h
 const PROT_NONE: u64 = 0x0;
 const PROT_READ: u64 = 0x1;
@@ -113,6 +123,81 @@ pub fn set_seccomp_level(seccomp_level: u32) -> Result<(), Error> {
     }
 }
 
+/// Syscalls a vsock forwarding thread makes on top of `ALLOWED_SYSCALLS`, to accept the host
+/// connections and connect them to the guest.
+pub const VSOCK_FORWARD_SYSCALLS: &[i64] = &[
+    libc::SYS_accept4,
+    libc::SYS_connect,
+    libc::SYS_getsockopt,
+    libc::SYS_shutdown,
+    libc::SYS_socket,
+];
+
+/// Applies the configured level of seccomp filtering to the current thread, which forwards host
+/// connections to vsock ports of the guest.
+pub fn set_vsock_forward_seccomp_level(seccomp_level: u32) -> Result<(), Error> {
+    match seccomp_level {
+        SECCOMP_LEVEL_ADVANCED => setup_seccomp(SeccompLevel::Advanced(vsock_forward_context()?)),
+        SECCOMP_LEVEL_BASIC => {
+            let mut syscalls = ALLOWED_SYSCALLS.to_vec();
+            syscalls.extend_from_slice(VSOCK_FORWARD_SYSCALLS);
+            setup_seccomp(SeccompLevel::Basic(&syscalls))
+        }
+        SECCOMP_LEVEL_NONE | _ => Ok(()),
+    }
+}
+
+/// The context of the vsock forwarding threads: the default context, with the rules of the
+/// syscalls in `VSOCK_FORWARD_SYSCALLS`. Only vsock stream sockets can be created.
+pub fn vsock_forward_context() -> Result<SeccompFilterContext, Error> {
+    let mut context = default_context()?;
+    context.add_rules(
+        libc::SYS_accept4,
+        None,
+        vec![SeccompRule::new(
+            vec![SeccompCondition::new(3, SeccompCmpOp::Eq, SOCK_CLOEXEC)?],
+            SeccompAction::Allow,
+        )],
+    )?;
+    context.add_rules(
+        libc::SYS_connect,
+        None,
+        vec![SeccompRule::new(vec![], SeccompAction::Allow)],
+    )?;
+    context.add_rules(
+        libc::SYS_getsockopt,
+        None,
+        vec![SeccompRule::new(
+            vec![
+                SeccompCondition::new(1, SeccompCmpOp::Eq, SOL_SOCKET)?,
+                SeccompCondition::new(2, SeccompCmpOp::Eq, SO_ERROR)?,
+            ],
+            SeccompAction::Allow,
+        )],
+    )?;
+    context.add_rules(
+        libc::SYS_shutdown,
+        None,
+        vec![SeccompRule::new(vec![], SeccompAction::Allow)],
+    )?;
+    context.add_rules(
+        libc::SYS_socket,
+        None,
+        vec![SeccompRule::new(
+            vec![
+                SeccompCondition::new(0, SeccompCmpOp::Eq, AF_VSOCK)?,
+                SeccompCondition::new(
+                    1,
+                    SeccompCmpOp::Eq,
+                    SOCK_STREAM | SOCK_NONBLOCK | SOCK_CLOEXEC,
+                )?,
+            ],
+            SeccompAction::Allow,
+        )],
+    )?;
+    Ok(context)
+}
+
 /// The default context containing the white listed syscall rules required by `Firecracker` to
 /// function.
 pub fn default_context() -> Result<SeccompFilterContext, Error> {
@@ -596,4 +681,29 @@ mod tests {
         }
         assert!(seccomp::setup_seccomp(seccomp::SeccompLevel::Advanced(context)).is_ok());
     }
+
+    #[test]
+    fn test_vsock_forward_seccomp() {
+        // Sets up context with additional rules required by the test.
+        let mut context = vsock_forward_context().unwrap();
+        for rule in vec![
+            libc::SYS_clone,
+            libc::SYS_mprotect,
+            libc::SYS_rt_sigprocmask,
+            libc::SYS_set_tid_address,
+            libc::SYS_sigaltstack,
+        ] {
+            assert!(context
+                .add_rules(
+                    rule,
+                    None,
+                    vec![seccomp::SeccompRule::new(
+                        vec![],
+                        seccomp::SeccompAction::Allow,
+                    )],
+                )
+                .is_ok());
+        }
+        assert!(seccomp::setup_seccomp(seccomp::SeccompLevel::Advanced(context)).is_ok());
+    }
 }
@@ -25,12 +25,14 @@ pub enum Error {
 type Result<T> = ::std::result::Result<T, Error>;
 
 /// The `LegacyDeviceManager` is a wrapper that is used for registering legacy devices
-/// on an I/O Bus. It currently manages the uart and i8042 devices.
+/// on an I/O Bus. It currently manages the uart, i8042, pvpanic and watchdog devices.
 /// The `LegacyDeviceManger` should be initialized only by using the constructor.
 pub struct LegacyDeviceManager {
     pub io_bus: devices::Bus,
     pub stdio_serial: Arc<Mutex<devices::legacy::Serial>>,
     pub i8042: Arc<Mutex<devices::legacy::I8042Device>>,
+    pub pvpanic: Arc<Mutex<devices::legacy::PvPanic>>,
+    pub watchdog: Option<Arc<Mutex<devices::legacy::Watchdog>>>,
 
     pub com_evt_1_3: EventFd,
     pub com_evt_2_4: EventFd,
@@ -38,7 +40,7 @@ pub struct LegacyDeviceManager {
 }
 
 impl LegacyDeviceManager {
-    /// Create a new DeviceManager handling legacy devices (uart, i8042).
+    /// Create a new DeviceManager handling legacy devices (uart, i8042, pvpanic).
     pub fn new() -> Result<Self> {
         let io_bus = devices::Bus::new();
         let com_evt_1_3 = EventFd::new().map_err(Error::EventFd)?;
@@ -52,10 +54,15 @@ impl LegacyDeviceManager {
         let exit_evt = EventFd::new().map_err(Error::EventFd)?;
         let i8042 = Arc::new(Mutex::new(devices::legacy::I8042Device::new(exit_evt)));
 
+        let pvpanic_evt = EventFd::new().map_err(Error::EventFd)?;
+        let pvpanic = Arc::new(Mutex::new(devices::legacy::PvPanic::new(pvpanic_evt)));
+
         Ok(LegacyDeviceManager {
             io_bus,
             stdio_serial,
             i8042,
+            pvpanic,
+            watchdog: None,
             com_evt_1_3,
             com_evt_2_4,
             stdin_handle: io::stdin(),
@@ -101,6 +108,24 @@ impl LegacyDeviceManager {
         self.io_bus
             .insert(self.i8042.clone(), 0x064, 0x1)
             .map_err(|err| Error::BusError(err))?;
+        self.io_bus
+            .insert(self.pvpanic.clone(), devices::legacy::PVPANIC_PORT, 0x1)
+            .map_err(|err| Error::BusError(err))?;
+        Ok(())
+    }
+
+    /// Register a watchdog device. The guest can only use it once it was registered.
+    pub fn register_watchdog(&mut self) -> Result<()> {
+        let update_evt = EventFd::new().map_err(Error::EventFd)?;
+        let watchdog = Arc::new(Mutex::new(devices::legacy::Watchdog::new(update_evt)));
+        self.io_bus
+            .insert(
+                watchdog.clone(),
+                devices::legacy::WATCHDOG_PORT_BASE,
+                devices::legacy::WATCHDOG_PORT_LEN,
+            )
+            .map_err(|err| Error::BusError(err))?;
+        self.watchdog = Some(watchdog);
         Ok(())
     }
 }
@@ -119,6 +144,16 @@ mod tests {
         stdin_handle.lock().set_canon_mode().unwrap();
     }
 
+    #[test]
+    fn test_register_watchdog() {
+        let mut ldm = LegacyDeviceManager::new().unwrap();
+        assert!(ldm.watchdog.is_none());
+        assert!(ldm.register_watchdog().is_ok());
+        assert!(ldm.watchdog.is_some());
+        // The watchdog ports are taken.
+        assert!(ldm.register_watchdog().is_err());
+    }
+
     #[test]
     fn test_debug_error() {
         assert_eq!(
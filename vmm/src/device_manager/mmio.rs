@@ -15,6 +15,8 @@ use kvm::IoeventAddress;
 use memory_model::GuestMemory;
 use sys_util;
 use vm_control::VmRequest;
+use vmm_config::device_features::{DeviceFeatures, QueueFeatures};
+use x86_64;
 
 /// Errors for MMIO device manager.
 #[derive(Debug)]
@@ -31,6 +33,8 @@ pub enum Error {
     Cmdline(kernel_cmdline::Error),
     /// No more IRQs are available.
     IrqsExhausted,
+    /// No more MMIO addresses are available below the IOAPIC.
+    MmioAddressesExhausted,
     /// Failed to update the mmio device.
     UpdateFailed,
 }
@@ -46,6 +50,7 @@ impl fmt::Display for Error {
                 write!(f, "unable to add device to kernel command line: {}", e)
             }
             &Error::IrqsExhausted => write!(f, "no more IRQs are available"),
+            &Error::MmioAddressesExhausted => write!(f, "no more MMIO addresses are available"),
             &Error::UpdateFailed => write!(f, "failed to update the mmio device"),
         }
     }
@@ -76,6 +81,7 @@ pub struct MMIODeviceManager {
     mmio_base: u64,
     irq: u32,
     id_to_addr_map: HashMap<String, u64>,
+    id_to_dev_map: HashMap<String, Arc<Mutex<devices::virtio::MmioDevice>>>,
 }
 
 impl MMIODeviceManager {
@@ -88,6 +94,7 @@ impl MMIODeviceManager {
             irq: IRQ_BASE,
             bus: devices::Bus::new(),
             id_to_addr_map: HashMap::new(),
+            id_to_dev_map: HashMap::new(),
         }
     }
 
@@ -101,6 +108,10 @@ impl MMIODeviceManager {
         if self.irq > MAX_IRQ {
             return Err(Error::IrqsExhausted);
         }
+        // The devices can't overlap the IOAPIC and the local APICs.
+        if self.mmio_base + MMIO_LEN > x86_64::MMIO_DEVICES_END as u64 {
+            return Err(Error::MmioAddressesExhausted);
+        }
 
         let mmio_device = devices::virtio::MmioDevice::new(self.guest_mem.clone(), device)
             .map_err(Error::CreateMmioDevice)?;
@@ -121,8 +132,9 @@ impl MMIODeviceManager {
             ));
         }
 
+        let mmio_device = Arc::new(Mutex::new(mmio_device));
         self.bus
-            .insert(Arc::new(Mutex::new(mmio_device)), self.mmio_base, MMIO_LEN)
+            .insert(mmio_device.clone(), self.mmio_base, MMIO_LEN)
             .map_err(|err| Error::BusError(err))?;
 
         // as per doc, [virtio_mmio.]device=<size>@<baseaddr>:<irq> needs to be appended
@@ -142,6 +154,7 @@ impl MMIODeviceManager {
 
         if let Some(device_id) = id {
             self.id_to_addr_map.insert(device_id.clone(), ret);
+            self.id_to_dev_map.insert(device_id, mmio_device);
         }
 
         Ok(ret)
@@ -167,6 +180,23 @@ impl MMIODeviceManager {
         return self.id_to_addr_map.get(id.as_str());
     }
 
+    /// Gets the virtio features negotiated by the specified device with the guest driver.
+    pub fn get_device_features(&self, id: &str) -> Option<DeviceFeatures> {
+        let device = self.id_to_dev_map.get(id)?.lock().ok()?;
+        Some(DeviceFeatures {
+            offered_features: device.offered_features(),
+            acked_features: device.acked_features(),
+            queues: device
+                .queues()
+                .iter()
+                .map(|q| QueueFeatures {
+                    max_size: q.get_max_size(),
+                    size: q.size,
+                })
+                .collect(),
+        })
+    }
+
     /// Removing the address of a device will generate an error when you try to update the
     /// drive. The purpose of this method is to test error scenarios and should otherwise
     /// not be used.
@@ -272,6 +302,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn register_device_below_ioapic() {
+        let guest_mem = GuestMemory::new(&vec![(GuestAddress(0x0), 0x1000)]).unwrap();
+        let mut device_manager =
+            MMIODeviceManager::new(guest_mem, x86_64::MMIO_DEVICES_END as u64 - MMIO_LEN);
+
+        let mut cmdline = kernel_cmdline::Cmdline::new(4096);
+        let dummy_box = Box::new(DummyDevice { dummy: 0 });
+        device_manager
+            .register_device(dummy_box.clone(), &mut cmdline, None)
+            .unwrap();
+        assert_eq!(
+            format!(
+                "{}",
+                device_manager
+                    .register_device(dummy_box, &mut cmdline, None)
+                    .unwrap_err()
+            ),
+            "no more MMIO addresses are available".to_string()
+        );
+    }
+
     #[test]
     fn test_dummy_device() {
         let mut dummy = DummyDevice { dummy: 0 };
@@ -316,6 +368,8 @@ mod tests {
         assert_eq!(format!("{}", e), "failed to clone ioeventfd: Error(0)");
         let e = Error::CloneIrqFd(sys_util::Error::new(0));
         assert_eq!(format!("{}", e), "failed to clone irqfd: Error(0)");
+        let e = Error::MmioAddressesExhausted;
+        assert_eq!(format!("{}", e), "no more MMIO addresses are available");
         let e = Error::UpdateFailed;
         assert_eq!(format!("{}", e), "failed to update the mmio device");
     }
@@ -355,4 +409,31 @@ mod tests {
         }
         assert_eq!(None, device_manager.get_address(&String::from("bar")));
     }
+
+    #[test]
+    fn test_get_device_features() {
+        let guest_mem = GuestMemory::new(&vec![(GuestAddress(0x0), 0x1000)]).unwrap();
+        let mut device_manager = MMIODeviceManager::new(guest_mem, 0xd0000000);
+        let mut cmdline = kernel_cmdline::Cmdline::new(4096);
+
+        let id = String::from("foo");
+        device_manager
+            .register_device(
+                Box::new(DummyDevice { dummy: 0 }),
+                &mut cmdline,
+                Some(id.clone()),
+            )
+            .unwrap();
+        let features = device_manager.get_device_features(&id).unwrap();
+        assert_eq!(features.offered_features, 0x1_0000_0000);
+        assert_eq!(features.acked_features, 0);
+        assert_eq!(
+            features.queues,
+            vec![QueueFeatures {
+                max_size: 64,
+                size: 0,
+            }]
+        );
+        assert!(device_manager.get_device_features("bar").is_none());
+    }
 }
@@ -28,20 +28,26 @@ extern crate kvm;
 #[macro_use]
 extern crate logger;
 extern crate memory_model;
+extern crate mmds;
 extern crate net_util;
 extern crate rate_limiter;
 extern crate seccomp;
 extern crate sys_util;
 extern crate x86_64;
 
+/// Builder API for embedding the VMM in other applications.
+pub mod builder;
 /// Syscalls allowed through the seccomp filter.
 pub mod default_syscalls;
 mod device_manager;
 /// Signal handling utilities for seccomp violations.
 mod sigsys_handler;
+mod vcpu_metrics;
 mod vm_control;
 /// Wrappers over structures used to configure the VMM.
 pub mod vmm_config;
+#[cfg(feature = "vsock")]
+mod vsock_forward;
 mod vstate;
 
 use futures::sync::oneshot;
@@ -49,6 +55,7 @@ use std::collections::HashMap;
 use std::ffi::CString;
 use std::fmt::{Display, Formatter};
 use std::fs::{metadata, File, OpenOptions};
+use std::io::Read;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::PathBuf;
 use std::result;
@@ -61,6 +68,7 @@ use std::time::Duration;
 use libc::{c_void, siginfo_t};
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 
+use builder::{EventCallback, VmEvent};
 use device_manager::legacy::LegacyDeviceManager;
 use device_manager::mmio::MMIODeviceManager;
 use devices::virtio;
@@ -71,16 +79,28 @@ use kernel::loader as kernel_loader;
 use kvm::*;
 use logger::{Level, LogOption, Metric, LOGGER, METRICS};
 use memory_model::{GuestAddress, GuestMemory};
+use mmds::data_store::LAUNCH_TIME_VARIABLE;
+use mmds::MMDS;
 use serde_json::Value;
 pub use sigsys_handler::setup_sigsys_handler;
 use sys_util::{register_signal_handler, EventFd, Killable, Terminal};
+use vcpu_metrics::VcpuTimeSampler;
 use vm_control::VmResponse;
 use vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
-use vmm_config::drive::{BlockDeviceConfig, BlockDeviceConfigs, DriveError};
+use vmm_config::cpu_template::CustomCpuTemplate;
+use vmm_config::device_features::{DeviceFeatures, DeviceFeaturesError};
+use vmm_config::drive::{
+    BlockDeviceConfig, BlockDeviceConfigs, BlockDeviceTraceConfig, DriveError,
+};
+use vmm_config::guest_events::{GuestEvent, GuestEventKind};
 use vmm_config::instance_info::{InstanceInfo, InstanceState, StartMicrovmError};
 use vmm_config::logger::{LoggerConfig, LoggerConfigError, LoggerLevel};
-use vmm_config::machine_config::{VmConfig, VmConfigError};
-use vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceConfigs, NetworkInterfaceError};
+use vmm_config::machine_config::{ThpPolicy, VmConfig, VmConfigError, WatchdogAction};
+use vmm_config::net::{
+    NetworkInterfaceConfig, NetworkInterfaceConfigs, NetworkInterfaceError,
+    NetworkInterfaceUpdateConfig,
+};
+use vmm_config::serial::{SerialInput, SerialInputError};
 #[cfg(feature = "vsock")]
 use vmm_config::vsock::{VsockDeviceConfig, VsockDeviceConfigs, VsockError};
 use vstate::{Vcpu, Vm};
@@ -91,6 +111,10 @@ const MAGIC_VALUE_SIGNAL_GUEST_BOOT_COMPLETE: u8 = 123;
 const DEFAULT_KERNEL_CMDLINE: &str = "reboot=k panic=1 pci=off nomodules 8250.nr_uarts=0";
 const VCPU_RTSIG_OFFSET: i32 = 0;
 const WRITE_METRICS_PERIOD_SECONDS: u64 = 60;
+// The number of guest events kept for the `GET /events` API request.
+const MAX_GUEST_EVENTS: usize = 64;
+// The number of pages of the process merged by KSM, which Linux reports since version 5.19.
+const KSM_MERGING_PAGES_PATH: &str = "/proc/self/ksm_merging_pages";
 static START_INSTANCE_REQUEST_TS: AtomicUsize = ATOMIC_USIZE_INIT;
 static START_INSTANCE_REQUEST_CPU_TS: AtomicUsize = ATOMIC_USIZE_INIT;
 
@@ -116,7 +140,7 @@ enum Error {
     /// The host kernel reports an invalid KVM API version.
     KvmApiVersion(i32),
     /// Cannot initialize the KVM context due to missing capabilities.
-    KvmCap(kvm::Cap),
+    KvmCap(Vec<kvm::Cap>),
     /// Epoll wait failed.
     Poll(std::io::Error),
     /// Write to the serial console failed.
@@ -143,19 +167,25 @@ pub enum VmmActionError {
     /// The action `ConfigureBootSource` failed either because of bad user input (`ErrorKind::User`)
     /// or an internal error (`ErrorKind::Internal`).
     BootSource(ErrorKind, BootSourceConfigError),
-    /// One of the actions `InsertBlockDevice`, `RescanBlockDevice` or `UpdateBlockDevicePath`
-    /// failed either because of bad user input (`ErrorKind::User`) or an
+    /// One of the actions `InsertBlockDevice`, `RescanBlockDevice`, `UpdateBlockDevicePath` or
+    /// `UpdateBlockDeviceTrace` failed either because of bad user input (`ErrorKind::User`) or an
     /// internal error (`ErrorKind::Internal`).
     DriveConfig(ErrorKind, DriveError),
-    /// The action `ConfigureLogger` failed either because of bad user input (`ErrorKind::User`) or
-    /// an internal error (`ErrorKind::Internal`).
-    Logger(ErrorKind, LoggerConfigError),
-    /// One of the actions `GetVmConfiguration` or `SetVmConfiguration` failed either because of bad
+    /// The action `GetDeviceFeatures` failed because of bad user input (`ErrorKind::User`).
+    DeviceFeatures(ErrorKind, DeviceFeaturesError),
+    /// One of the actions `ConfigureLogger` or `ResetMetrics` failed either because of bad user
     /// input (`ErrorKind::User`) or an internal error (`ErrorKind::Internal`).
+    Logger(ErrorKind, LoggerConfigError),
+    /// One of the actions `GetVmConfiguration`, `SetVmConfiguration` or `SetCpuTemplate` failed
+    /// either because of bad input (`ErrorKind::User`) or an internal error
+    /// (`ErrorKind::Internal`).
     MachineConfig(ErrorKind, VmConfigError),
-    /// The action `InsertNetworkDevice` failed either because of bad user input (`ErrorKind::User`)
-    /// or an internal error (`ErrorKind::Internal`).
+    /// One of the actions `InsertNetworkDevice` or `UpdateNetworkInterface` failed either because
+    /// of bad user input (`ErrorKind::User`) or an internal error (`ErrorKind::Internal`).
     NetworkConfig(ErrorKind, NetworkInterfaceError),
+    /// The action `SendSerialInput` failed either because of bad user input (`ErrorKind::User`)
+    /// or an internal error (`ErrorKind::Internal`).
+    SerialInput(ErrorKind, SerialInputError),
     /// The action `StartMicroVm` failed either because of bad user input (`ErrorKind::User`) or
     /// an internal error (`ErrorKind::Internal`).
     StartMicrovm(ErrorKind, StartMicrovmError),
@@ -173,9 +203,11 @@ impl VmmActionError {
         match *self {
             BootSource(ref kind, _) => kind,
             DriveConfig(ref kind, _) => kind,
+            DeviceFeatures(ref kind, _) => kind,
             Logger(ref kind, _) => kind,
             MachineConfig(ref kind, _) => kind,
             NetworkConfig(ref kind, _) => kind,
+            SerialInput(ref kind, _) => kind,
             StartMicrovm(ref kind, _) => kind,
             #[cfg(feature = "vsock")]
             VsockConfig(ref kind, _) => kind,
@@ -190,9 +222,11 @@ impl Display for VmmActionError {
         match *self {
             BootSource(_, ref err) => write!(f, "{}", err.to_string()),
             DriveConfig(_, ref err) => write!(f, "{}", err.to_string()),
+            DeviceFeatures(_, ref err) => write!(f, "{}", err.to_string()),
             Logger(_, ref err) => write!(f, "{}", err.to_string()),
             MachineConfig(_, ref err) => write!(f, "{}", err.to_string()),
             NetworkConfig(_, ref err) => write!(f, "{}", err.to_string()),
+            SerialInput(_, ref err) => write!(f, "{}", err.to_string()),
             StartMicrovm(_, ref err) => write!(f, "{}", err.to_string()),
             #[cfg(feature = "vsock")]
             VsockConfig(_, ref err) => write!(f, "{}", err.to_string()),
@@ -210,6 +244,13 @@ pub enum VmmAction {
     /// Configure the logger using as input the `LoggerConfig`. This action can only be called
     /// before the microVM has booted. The response is sent using the `OutcomeSender`.
     ConfigureLogger(LoggerConfig, OutcomeSender),
+    /// Get the virtio features negotiated by the device with the ID given as the first data
+    /// associated with this enum variant. This action can only be called after the microVM is
+    /// started. The response is sent using the `OutcomeSender`.
+    GetDeviceFeatures(String, OutcomeSender),
+    /// Get the panics and crashes recently reported by the guest. The action response is sent
+    /// using the `OutcomeSender`.
+    GetGuestEvents(OutcomeSender),
     /// Get the configuration of the microVM. The action response is sent using the `OutcomeSender`.
     GetVmConfiguration(OutcomeSender),
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
@@ -229,10 +270,26 @@ pub enum VmmAction {
     /// associated with this enum variant. This action can only be called after the microVM is
     /// started. The response is sent using the `OutcomeSender`.
     RescanBlockDevice(String, OutcomeSender),
+    /// Zero the counters of the groups of metrics named by the data associated with this enum
+    /// variant, or of all the groups when it is empty. The response is sent using the
+    /// `OutcomeSender`.
+    ResetMetrics(Vec<String>, OutcomeSender),
+    /// Send input to the guest through the serial console, using `SerialInput` as input. This
+    /// action can only be called after the microVM is started. The response is sent using the
+    /// `OutcomeSender`.
+    SendSerialInput(SerialInput, OutcomeSender),
+    /// Set the CPU template defined by the user, applied on top of the built-in one, using
+    /// `CustomCpuTemplate` as input. This action can only be called before the microVM has
+    /// booted. The response is sent using the `OutcomeSender`.
+    SetCpuTemplate(CustomCpuTemplate, OutcomeSender),
     /// Set the microVM configuration (memory & vcpu) using `VmConfig` as input. This
     /// action can only be called before the microVM has booted. The action
     /// response is sent using the `OutcomeSender`.
     SetVmConfiguration(VmConfig, OutcomeSender),
+    /// Stop the vCPUs and terminate the VMM thread, without exiting the process. `VmBuilder` uses
+    /// it to clean up a microVM which failed to boot. The response is sent using the
+    /// `OutcomeSender`, right before the VMM thread terminates.
+    Shutdown(OutcomeSender),
     /// Launch the microVM. This action can only be called before the microVM has booted.
     /// The response is sent using the `OutcomeSender`.
     StartMicroVm(OutcomeSender),
@@ -240,6 +297,15 @@ pub enum VmmAction {
     /// represents the `drive_id` and the `path_on_host`. The response is sent using
     /// the `OutcomeSender`.
     UpdateBlockDevicePath(String, String, OutcomeSender),
+    /// Start or stop the I/O trace of an existing block device. The data associated with this
+    /// variant represents the `drive_id` and the trace to start, or `None` to stop the running
+    /// trace. This action can only be called after the microVM is started. The response is sent
+    /// using the `OutcomeSender`.
+    UpdateBlockDeviceTrace(String, Option<BlockDeviceTraceConfig>, OutcomeSender),
+    /// Update a network interface of the running microVM, such as starting or stopping its
+    /// packet capture, using `NetworkInterfaceUpdateConfig` as input. This action can only be
+    /// called after the microVM is started. The response is sent using the `OutcomeSender`.
+    UpdateNetworkInterface(NetworkInterfaceUpdateConfig, OutcomeSender),
 }
 
 /// The enum represents the response sent by the VMM in case of success. The response is either
@@ -248,6 +314,10 @@ pub enum VmmAction {
 pub enum VmmData {
     /// No data is sent on the channel.
     Empty,
+    /// The virtio features negotiated by a device represented by `DeviceFeatures`.
+    DeviceFeatures(DeviceFeatures),
+    /// The panics and crashes recently reported by the guest, oldest first.
+    GuestEvents(Vec<GuestEvent>),
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(VmConfig),
 }
@@ -261,23 +331,51 @@ pub type OutcomeReceiver = oneshot::Receiver<VmmRequestOutcome>;
 
 type Result<T> = std::result::Result<T, Error>;
 
+// KVM capabilities that must be present on the host, together with what they are needed for.
+// They are all probed when the KVM context is created, so that every missing one is reported at
+// startup instead of surfacing later as the errno of a failed ioctl.
+const REQUIRED_KVM_CAPS: &[(Cap, &str)] = &[
+    (
+        Cap::Irqchip,
+        "creating the in-kernel interrupt controllers (PIC, IOAPIC and local APICs)",
+    ),
+    (
+        Cap::Ioeventfd,
+        "notifying virtio devices of guest writes to their queue notify registers",
+    ),
+    (
+        Cap::Irqfd,
+        "injecting interrupts from the serial console and the virtio devices",
+    ),
+    (
+        Cap::SetTssAddr,
+        "setting up the task state segment required by Intel VMX",
+    ),
+    (
+        Cap::UserMemory,
+        "mapping the guest memory and tracking the pages it dirties",
+    ),
+    (
+        Cap::Pit2,
+        "creating the in-kernel i8254 programmable interval timer",
+    ),
+    (
+        Cap::TscDeadlineTimer,
+        "exposing the TSC deadline timer that is advertised in the guest CPUID",
+    ),
+];
+
 /// Describes a KVM context that gets attached to the micro vm instance.
 /// It gives access to the functionality of the KVM wrapper as long as every required
 /// KVM capability is present on the host.
 pub struct KvmContext {
     kvm: Kvm,
     max_memslots: usize,
+    max_vcpus: usize,
 }
 
 impl KvmContext {
     fn new(kvm_fd: Option<RawFd>) -> Result<Self> {
-        fn check_cap(kvm: &Kvm, cap: Cap) -> std::result::Result<(), Error> {
-            if !kvm.check_extension(cap) {
-                return Err(Error::KvmCap(cap));
-            }
-            Ok(())
-        }
-
         let kvm = if let Some(fd) = kvm_fd {
             // Safe because we expect kvm_fd to contain a valid fd number when is_some() == true.
             unsafe { Kvm::new_with_fd_number(fd) }
@@ -289,15 +387,34 @@ impl KvmContext {
             return Err(Error::KvmApiVersion(kvm.get_api_version()));
         }
 
-        check_cap(&kvm, Cap::Irqchip)?;
-        check_cap(&kvm, Cap::Ioeventfd)?;
-        check_cap(&kvm, Cap::Irqfd)?;
-        // check_cap(&kvm, Cap::ImmediateExit)?;
-        check_cap(&kvm, Cap::SetTssAddr)?;
-        check_cap(&kvm, Cap::UserMemory)?;
+        let missing_caps = KvmContext::missing_caps(&kvm);
+        if !missing_caps.is_empty() {
+            return Err(Error::KvmCap(missing_caps));
+        }
 
         let max_memslots = kvm.get_nr_memslots();
-        Ok(KvmContext { kvm, max_memslots })
+        let max_vcpus = kvm.get_max_vcpus();
+        Ok(KvmContext {
+            kvm,
+            max_memslots,
+            max_vcpus,
+        })
+    }
+
+    // Probes all the required capabilities and returns the ones the host does not provide.
+    // Each missing capability is logged along with the reason Firecracker needs it.
+    fn missing_caps(kvm: &Kvm) -> Vec<Cap> {
+        let mut missing_caps = vec![];
+        for &(cap, reason) in REQUIRED_KVM_CAPS {
+            if !kvm.check_extension(cap) {
+                error!(
+                    "The host KVM does not support the {:?} capability, which is required for {}.",
+                    cap, reason
+                );
+                missing_caps.push(cap);
+            }
+        }
+        missing_caps
     }
 
     fn fd(&self) -> &Kvm {
@@ -308,6 +425,12 @@ impl KvmContext {
     pub fn max_memslots(&self) -> usize {
         self.max_memslots
     }
+
+    /// Get the maximum number of vCPUs of a microVM, as limited by this KVM context and by the
+    /// guest firmware tables.
+    pub fn max_vcpus(&self) -> u8 {
+        self.max_vcpus.min(x86_64::MAX_SUPPORTED_CPUS as usize) as u8
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -317,6 +440,9 @@ enum EpollDispatch {
     DeviceHandler(usize, DeviceEventT),
     VmmActionRequest,
     WriteMetrics,
+    WatchdogUpdate,
+    WatchdogExpired,
+    PvPanic,
 }
 
 struct MaybeHandler {
@@ -466,9 +592,12 @@ impl EpollContext {
         )
     }
 
-    fn allocate_virtio_net_tokens(&mut self) -> virtio::net::EpollConfig {
+    fn allocate_virtio_net_tokens(&mut self) -> (virtio::net::EpollConfig, usize) {
         let (dispatch_base, sender) = self.allocate_tokens(virtio::net::NET_EVENTS_COUNT);
-        virtio::net::EpollConfig::new(dispatch_base, self.epoll_raw_fd, sender)
+        (
+            virtio::net::EpollConfig::new(dispatch_base, self.epoll_raw_fd, sender),
+            self.device_handlers.len(),
+        )
     }
 
     #[cfg(feature = "vsock")]
@@ -516,6 +645,7 @@ struct Vmm {
     kvm: KvmContext,
 
     vm_config: VmConfig,
+    cpu_template: Option<CustomCpuTemplate>,
     shared_info: Arc<RwLock<InstanceInfo>>,
 
     // Guest VM core resources.
@@ -523,13 +653,22 @@ struct Vmm {
     kernel_config: Option<KernelConfig>,
     kill_signaled: Option<Arc<AtomicBool>>,
     vcpu_handles: Option<Vec<thread::JoinHandle<()>>>,
+    vcpu_samplers: Vec<VcpuTimeSampler>,
     exit_evt: Option<EpollEvent<EventFd>>,
+    watchdog_update_evt: Option<EpollEvent<EventFd>>,
+    watchdog_timer: Option<EpollEvent<TimerFd>>,
+    pvpanic_evt: Option<EpollEvent<EventFd>>,
+    // Set by the vCPU threads when the guest triple faults, before they signal the exit event.
+    triple_fault: Arc<AtomicBool>,
+    // The most recent panics and crashes of the guest, oldest first.
+    guest_events: Vec<GuestEvent>,
     vm: Vm,
 
     // Guest VM devices.
     mmio_device_manager: Option<MMIODeviceManager>,
     legacy_device_manager: LegacyDeviceManager,
     drive_handler_id_map: HashMap<String, usize>,
+    net_handler_id_map: HashMap<String, usize>,
 
     // Device configurations.
     // If there is a Root Block Device, this should be added as the first element of the list.
@@ -550,6 +689,11 @@ struct Vmm {
     // The level of seccomp filtering used. Seccomp filters are loaded before executing guest code.
     // See `seccomp::SeccompLevel` for more information about seccomp levels.
     seccomp_level: u32,
+
+    // Callbacks registered by applications embedding the VMM.
+    event_callbacks: Vec<EventCallback>,
+    // Set when a `VmmAction::Shutdown` is received, to terminate the VMM thread.
+    shutdown_requested: bool,
 }
 
 impl Vmm {
@@ -581,17 +725,25 @@ impl Vmm {
         Ok(Vmm {
             kvm,
             vm_config: VmConfig::default(),
+            cpu_template: None,
             shared_info: api_shared_info,
             guest_memory: None,
             kernel_config: None,
             kill_signaled: None,
             vcpu_handles: None,
+            vcpu_samplers: Vec::new(),
             exit_evt: None,
+            watchdog_update_evt: None,
+            watchdog_timer: None,
+            pvpanic_evt: None,
+            triple_fault: Arc::new(AtomicBool::new(false)),
+            guest_events: Vec::new(),
             vm,
             mmio_device_manager: None,
             legacy_device_manager: LegacyDeviceManager::new().map_err(Error::CreateLegacyDevice)?,
             block_device_configs,
             drive_handler_id_map: HashMap::new(),
+            net_handler_id_map: HashMap::new(),
             network_interface_configs: NetworkInterfaceConfigs::new(),
             #[cfg(feature = "vsock")]
             vsock_device_configs: VsockDeviceConfigs::new(),
@@ -600,22 +752,21 @@ impl Vmm {
             from_api,
             write_metrics_event,
             seccomp_level,
+            event_callbacks: Vec::new(),
+            shutdown_requested: false,
         })
     }
 
     fn update_drive_handler(
         &mut self,
         drive_id: &String,
-        disk_image: File,
+        device_event: DeviceEventT,
+        payload: EpollHandlerPayload,
     ) -> result::Result<(), DriveError> {
         if let Some(device_idx) = self.drive_handler_id_map.get(drive_id) {
             match self.epoll_context.get_device_handler(*device_idx) {
                 Ok(handler) => {
-                    handler.handle_event(
-                        virtio::block::FS_UPDATE_EVENT,
-                        *device_idx as u32,
-                        EpollHandlerPayload::DrivePayload(disk_image),
-                    );
+                    handler.handle_event(device_event, *device_idx as u32, payload);
                     Ok(())
                 }
                 Err(e) => {
@@ -718,7 +869,9 @@ impl Vmm {
             .ok_or(StartMicrovmError::MissingKernelConfig)?;
 
         for cfg in self.network_interface_configs.iter_mut() {
-            let epoll_config = self.epoll_context.allocate_virtio_net_tokens();
+            let (epoll_config, curr_device_idx) = self.epoll_context.allocate_virtio_net_tokens();
+            self.net_handler_id_map
+                .insert(cfg.iface_id.clone(), curr_device_idx - 1);
 
             let allow_mmds_requests = cfg.allow_mmds_requests();
             let rx_rate_limiter = cfg.rx_rate_limiter.take();
@@ -738,7 +891,11 @@ impl Vmm {
                 );
 
                 device_manager
-                    .register_device(net_box, &mut kernel_config.cmdline, None)
+                    .register_device(
+                        net_box,
+                        &mut kernel_config.cmdline,
+                        Some(cfg.iface_id.clone()),
+                    )
                     .map_err(StartMicrovmError::RegisterNetDevice)?;
             } else {
                 return Err(StartMicrovmError::NetDeviceNotConfigured)?;
@@ -766,8 +923,15 @@ impl Vmm {
                     .map_err(StartMicrovmError::CreateVsockDevice)?,
             );
             device_manager
-                .register_device(vsock_box, &mut kernel_config.cmdline, None)
+                .register_device(vsock_box, &mut kernel_config.cmdline, Some(cfg.id.clone()))
                 .map_err(StartMicrovmError::RegisterVsockDevice)?;
+
+            // The forwarding threads load seccomp filters of their own, which also allow them to
+            // connect vsock sockets.
+            for rule in cfg.port_forwards.iter() {
+                vsock_forward::start(cfg.guest_cid, rule, self.seccomp_level)
+                    .map_err(StartMicrovmError::VsockPortForward)?;
+            }
         }
         Ok(())
     }
@@ -784,12 +948,45 @@ impl Vmm {
                 memory_model::GuestMemoryError::MemoryNotInitialized,
             ))?
             << 20;
-        let arch_mem_regions = x86_64::arch_memory_regions(mem_size);
-        self.guest_memory =
-            Some(GuestMemory::new(&arch_mem_regions).map_err(StartMicrovmError::GuestMemory)?);
+        let arch_mem_regions = x86_64::arch_memory_regions(mem_size, self.mmio_gap_size());
+        let guest_memory =
+            GuestMemory::new(&arch_mem_regions).map_err(StartMicrovmError::GuestMemory)?;
+
+        if let Some(thp_policy) = self.vm_config.thp_policy {
+            let advice = match thp_policy {
+                ThpPolicy::Hugepage => libc::MADV_HUGEPAGE,
+                ThpPolicy::NoHugepage => libc::MADV_NOHUGEPAGE,
+            };
+            guest_memory
+                .madvise(advice)
+                .map_err(StartMicrovmError::GuestMemory)?;
+        }
+        if self.vm_config.dontfork == Some(true) {
+            guest_memory
+                .madvise(libc::MADV_DONTFORK)
+                .map_err(StartMicrovmError::GuestMemory)?;
+        }
+        if self.vm_config.mergeable == Some(true) {
+            guest_memory
+                .madvise(libc::MADV_MERGEABLE)
+                .map_err(StartMicrovmError::GuestMemory)?;
+        }
+        // Prefaulting goes last so that the pages are allocated according to the advice above.
+        if self.vm_config.prefault == Some(true) {
+            guest_memory.prefault();
+        }
+
+        self.guest_memory = Some(guest_memory);
         Ok(())
     }
 
+    // The size of the gap at the end of the 32bit address space, in bytes.
+    fn mmio_gap_size(&self) -> usize {
+        self.vm_config
+            .mmio_gap_size_mib
+            .map_or(x86_64::DEFAULT_32BIT_GAP_SIZE, |size| size << 20)
+    }
+
     fn check_health(&self) -> std::result::Result<(), StartMicrovmError> {
         if self.kernel_config.is_none() {
             return Err(StartMicrovmError::MissingKernelConfig)?;
@@ -806,9 +1003,12 @@ impl Vmm {
             ))?;
         // Instantiate the MMIO device manager.
         // 'mmio_base' address has to be an address which is protected by the kernel, in this case
-        // the start of the x86 specific gap of memory (currently hardcoded at 768MiB).
-        let mut device_manager =
-            MMIODeviceManager::new(guest_mem.clone(), x86_64::get_32bit_gap_start() as u64);
+        // an address in the x86 specific gap of memory, by default its start.
+        let mmio_base = self
+            .vm_config
+            .mmio_base
+            .unwrap_or(x86_64::get_32bit_gap_start(self.mmio_gap_size()) as u64);
+        let mut device_manager = MMIODeviceManager::new(guest_mem.clone(), mmio_base);
 
         self.attach_block_devices(&mut device_manager)?;
         self.attach_net_devices(&mut device_manager)?;
@@ -854,6 +1054,11 @@ impl Vmm {
         self.legacy_device_manager
             .register_devices()
             .map_err(StartMicrovmError::LegacyIOBus)?;
+        if self.vm_config.watchdog_action.is_some() {
+            self.legacy_device_manager
+                .register_watchdog()
+                .map_err(StartMicrovmError::LegacyIOBus)?;
+        }
 
         Ok(())
     }
@@ -886,6 +1091,10 @@ impl Vmm {
                 .ok_or(StartMicrovmError::DeviceManager)?;
             let mmio_bus = device_manager.bus.clone();
             let kill_signaled = kill_signaled.clone();
+            let triple_fault = self.triple_fault.clone();
+            let sampler = VcpuTimeSampler::new(cpu_id);
+            let vcpu_tid = sampler.tid();
+            self.vcpu_samplers.push(sampler);
             let vcpu_thread_barrier = vcpu_thread_barrier.clone();
             // If the lock is poisoned, it's OK to panic.
             let vcpu_exit_evt = self
@@ -900,8 +1109,13 @@ impl Vmm {
             let seccomp_level = self.seccomp_level;
             // It is safe to unwrap the ht_enabled flag because the machine configure
             // has default values for all fields.
-            vcpu.configure(&self.vm_config, entry_addr, &self.vm)
-                .map_err(StartMicrovmError::VcpuConfigure)?;
+            vcpu.configure(
+                &self.vm_config,
+                self.cpu_template.as_ref(),
+                entry_addr,
+                &self.vm,
+            )
+            .map_err(StartMicrovmError::VcpuConfigure)?;
             vcpu_handles.push(
                 thread::Builder::new()
                     .name(format!("fc_vcpu{}", cpu_id))
@@ -918,6 +1132,8 @@ impl Vmm {
                             .expect("Failed to register vcpu signal handler");
                         }
 
+                        vcpu_tid.store(vcpu_metrics::gettid(), Ordering::Release);
+
                         // Load seccomp filters for this vCPU thread.
                         // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping filters
                         // altogether is the desired behaviour.
@@ -976,7 +1192,9 @@ impl Vmm {
                                         break;
                                     }
                                     VcpuExit::Shutdown => {
+                                        // On x86, KVM only shuts a vCPU down after a triple fault.
                                         info!("Received KVM_EXIT_SHUTDOWN signal");
+                                        triple_fault.store(true, Ordering::SeqCst);
                                         break;
                                     }
                                     // Documentation specifies that below kvm exits are considered
@@ -1063,11 +1281,18 @@ impl Vmm {
             .vm_config
             .vcpu_count
             .ok_or(StartMicrovmError::VcpusNotConfigured)?;
+        // The legacy device manager always registers the pvpanic device.
+        let acpi_devices = match self.vm_config.acpi {
+            Some(true) => Some(x86_64::acpi::LegacyDevices { pvpanic: true }),
+            _ => None,
+        };
         x86_64::configure_system(
             vm_memory,
             kernel_config.cmdline_addr,
             cmdline_cstring.to_bytes().len() + 1,
             vcpu_count,
+            self.mmio_gap_size(),
+            acpi_devices.as_ref(),
         )
         .map_err(|e| StartMicrovmError::ConfigureSystem(e))?;
         Ok(entry_addr)
@@ -1088,6 +1313,41 @@ impl Vmm {
             .map_err(|_| StartMicrovmError::RegisterEvent)?;
         self.exit_evt = Some(exit_epoll_evt);
 
+        if let Some(ref watchdog) = self.legacy_device_manager.watchdog {
+            let update_fd = watchdog
+                .lock()
+                .expect("Failed to register events on the watchdog due to poisoned lock")
+                .get_eventfd_clone()
+                .map_err(|_| StartMicrovmError::EventFd)?;
+            let update_epoll_evt = self
+                .epoll_context
+                .add_event(update_fd, EpollDispatch::WatchdogUpdate)
+                .map_err(|_| StartMicrovmError::RegisterEvent)?;
+            self.watchdog_update_evt = Some(update_epoll_evt);
+
+            // non-blocking & close on exec
+            let timer_fd = TimerFd::new_custom(ClockId::Monotonic, true, true)
+                .map_err(|_| StartMicrovmError::RegisterEvent)?;
+            let timer_epoll_evt = self
+                .epoll_context
+                .add_event(timer_fd, EpollDispatch::WatchdogExpired)
+                .map_err(|_| StartMicrovmError::RegisterEvent)?;
+            self.watchdog_timer = Some(timer_epoll_evt);
+        }
+
+        let pvpanic_fd = self
+            .legacy_device_manager
+            .pvpanic
+            .lock()
+            .expect("Failed to register events on the pvpanic device due to poisoned lock")
+            .get_eventfd_clone()
+            .map_err(|_| StartMicrovmError::EventFd)?;
+        let pvpanic_epoll_evt = self
+            .epoll_context
+            .add_event(pvpanic_fd, EpollDispatch::PvPanic)
+            .map_err(|_| StartMicrovmError::RegisterEvent)?;
+        self.pvpanic_evt = Some(pvpanic_epoll_evt);
+
         self.epoll_context
             .enable_stdin_event()
             .map_err(|_| StartMicrovmError::RegisterEvent)?;
@@ -1140,6 +1400,16 @@ impl Vmm {
             .expect("Failed to start microVM because shared info couldn't be written due to poisoned lock")
             .state = InstanceState::Running;
 
+        // The metadata can refer to the time the microVM was started at with the
+        // {{launch_time}} placeholder.
+        // Use expect() to crash if the other thread poisoned this lock.
+        MMDS.lock()
+            .expect("Failed to start microVM because MMDS couldn't be written due to poisoned lock")
+            .set_variable(
+                LAUNCH_TIME_VARIABLE,
+                chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            );
+
         // Arm the log write timer.
         // TODO: the timer does not stop on InstanceStop.
         let timer_state = TimerState::Periodic {
@@ -1158,8 +1428,8 @@ impl Vmm {
         Ok(VmmData::Empty)
     }
 
-    /// Waits for all vCPUs to exit and terminates the Firecracker process.
-    fn stop(&mut self, exit_code: i32) {
+    /// Waits for all vCPUs to exit and releases the terminal, without terminating the process.
+    fn shut_down(&mut self) {
         info!("Vmm is stopping.");
 
         if let Some(v) = self.kill_signaled.take() {
@@ -1204,12 +1474,21 @@ impl Vmm {
         {
             warn!("Cannot set canonical mode for the terminal. {:?}", e);
         }
+    }
+
+    /// Waits for all vCPUs to exit and terminates the Firecracker process.
+    fn stop(&mut self, exit_code: i32) {
+        self.shut_down();
 
         // Log the metrics before exiting.
         if let Err(e) = LOGGER.log_metrics() {
             error!("Failed to log metrics while stopping: {}", e);
         }
 
+        for callback in self.event_callbacks.iter() {
+            callback(&VmEvent::Exited(exit_code));
+        }
+
         // Exit from Firecracker using the provided exit code.
         std::process::exit(exit_code);
     }
@@ -1250,6 +1529,9 @@ impl Vmm {
                                 }
                                 None => warn!("leftover exit-evt in epollcontext!"),
                             }
+                            if self.triple_fault.load(Ordering::SeqCst) {
+                                self.record_guest_event(GuestEventKind::TripleFault);
+                            }
                             self.stop(0);
                         }
                         EpollDispatch::Stdin => {
@@ -1267,14 +1549,22 @@ impl Vmm {
                                 Ok(count) => {
                                     // Use expect() to panic if another thread panicked
                                     // while holding the lock.
-                                    self.legacy_device_manager
+                                    let result = self
+                                        .legacy_device_manager
                                         .stdio_serial
                                         .lock()
                                         .expect(
                                             "Failed to process stdin event due to poisoned lock",
                                         )
-                                        .queue_input_bytes(&out[..count])
-                                        .map_err(Error::Serial)?;
+                                        .queue_input_bytes(&out[..count]);
+                                    match result {
+                                        // The guest isn't reading its console, so the input is
+                                        // dropped like a UART overrun would.
+                                        Err(ref e) if e.errno() == libc::ENOSPC => {
+                                            warn!("serial input buffer full, dropping stdin input");
+                                        }
+                                        result => result.map_err(Error::Serial)?,
+                                    }
                                 }
                             }
                         }
@@ -1297,10 +1587,22 @@ impl Vmm {
                                 warn!("got spurious notification from api thread");
                                 ()
                             });
+                            if self.shutdown_requested {
+                                return Ok(());
+                            }
                         }
                         EpollDispatch::WriteMetrics => {
                             self.write_metrics();
                         }
+                        EpollDispatch::WatchdogUpdate => {
+                            self.update_watchdog_timer()?;
+                        }
+                        EpollDispatch::WatchdogExpired => {
+                            self.handle_watchdog_expiry();
+                        }
+                        EpollDispatch::PvPanic => {
+                            self.handle_pvpanic_event()?;
+                        }
                     }
                 }
             }
@@ -1340,6 +1642,16 @@ impl Vmm {
         if LOGGER.flags() | LogOption::LogDirtyPages as usize > 0 {
             METRICS.memory.dirty_pages.add(self.get_dirty_page_count());
         }
+        for sampler in self.vcpu_samplers.iter_mut() {
+            sampler.sample();
+        }
+        // If the guest memory is mergeable, sample how many of its pages KSM merged. Kernels which
+        // don't report it leave the metric at 0.
+        if self.vm_config.mergeable == Some(true) {
+            if let Some(pages) = ksm_merging_pages() {
+                METRICS.memory.ksm_merging_pages.set(pages);
+            }
+        }
         // Please note that, since LOGGER has no output file configured yet, it will write to
         // stdout, so logging will interfere with console output.
         if let Err(e) = LOGGER.log_metrics() {
@@ -1347,6 +1659,98 @@ impl Vmm {
         }
     }
 
+    // Arms the watchdog timer with the timeout last set by the guest, or disarms it when the guest
+    // stopped the watchdog.
+    fn update_watchdog_timer(&mut self) -> Result<()> {
+        if let Some(ref evt) = self.watchdog_update_evt {
+            evt.fd.read().map_err(Error::EventFd)?;
+        }
+        let timeout = match self.legacy_device_manager.watchdog {
+            // Use expect() to panic if another thread panicked while holding the lock.
+            Some(ref watchdog) => watchdog
+                .lock()
+                .expect("Failed to update the watchdog timer due to poisoned lock")
+                .timeout(),
+            None => return Ok(()),
+        };
+        let timer_state = match timeout {
+            // A zero duration would disarm the timer instead of expiring it right away.
+            Some(0) => TimerState::Oneshot(Duration::from_nanos(1)),
+            Some(secs) => TimerState::Oneshot(Duration::from_secs(secs)),
+            None => TimerState::Disarmed,
+        };
+        if let Some(ref timer) = self.watchdog_timer {
+            timer.fd.set_state(timer_state, SetTimeFlags::Default);
+        }
+        Ok(())
+    }
+
+    fn handle_watchdog_expiry(&mut self) {
+        if let Some(ref timer) = self.watchdog_timer {
+            // The guest pinged the watchdog after the timer expired, but before this event was
+            // handled, so the timer was armed again.
+            if timer.fd.read() == 0 {
+                return;
+            }
+        }
+
+        METRICS.watchdog.expired_count.inc();
+        for callback in self.event_callbacks.iter() {
+            callback(&VmEvent::WatchdogExpired);
+        }
+        match self.vm_config.watchdog_action {
+            Some(WatchdogAction::Reset) => {
+                error!("The guest watchdog expired. Resetting the microVM.");
+                self.stop(0);
+            }
+            _ => error!("The guest watchdog expired."),
+        }
+    }
+
+    // Records the events the guest reported through the pvpanic device.
+    fn handle_pvpanic_event(&mut self) -> Result<()> {
+        if let Some(ref evt) = self.pvpanic_evt {
+            evt.fd.read().map_err(Error::EventFd)?;
+        }
+        let events = self
+            .legacy_device_manager
+            .pvpanic
+            .lock()
+            .expect("Failed to handle the pvpanic event due to poisoned lock")
+            .take_events();
+        if events & devices::legacy::PVPANIC_PANICKED != 0 {
+            self.record_guest_event(GuestEventKind::Panic);
+        }
+        if events & devices::legacy::PVPANIC_CRASH_LOADED != 0 {
+            self.record_guest_event(GuestEventKind::CrashLoaded);
+        }
+        Ok(())
+    }
+
+    // Counts a failure of the guest, logs it, reports it to the embedding applications and keeps
+    // it for the `GET /events` API request.
+    fn record_guest_event(&mut self, kind: GuestEventKind) {
+        match kind {
+            GuestEventKind::Panic => METRICS.guest_events.panic_count.inc(),
+            GuestEventKind::CrashLoaded => METRICS.guest_events.crash_loaded_count.inc(),
+            GuestEventKind::TripleFault => METRICS.guest_events.triple_fault_count.inc(),
+        }
+        error!("Guest event: {}", kind);
+        for callback in self.event_callbacks.iter() {
+            callback(&VmEvent::Guest(kind));
+        }
+
+        // Only the most recent events are kept, so that a guest panicking in a loop can't make
+        // the VMM use an unbounded amount of memory.
+        if self.guest_events.len() == MAX_GUEST_EVENTS {
+            self.guest_events.remove(0);
+        }
+        self.guest_events.push(GuestEvent {
+            kind,
+            timestamp_us: (chrono::Utc::now().timestamp_nanos() / 1000) as u64,
+        });
+    }
+
     fn configure_boot_source(
         &mut self,
         kernel_image_path: String,
@@ -1401,6 +1805,13 @@ impl Vmm {
                     VmConfigError::InvalidVcpuCount,
                 ));
             }
+            let max_vcpus = self.kvm.max_vcpus();
+            if vcpu_count_value > max_vcpus {
+                return Err(VmmActionError::MachineConfig(
+                    ErrorKind::User,
+                    VmConfigError::TooManyVcpus(max_vcpus),
+                ));
+            }
         }
 
         if let Some(mem_size_mib_value) = machine_config.mem_size_mib {
@@ -1432,6 +1843,33 @@ impl Vmm {
             ));
         }
 
+        // The gap and the device region are checked together, since either can be set first.
+        let mmio_gap_size_mib = machine_config
+            .mmio_gap_size_mib
+            .or(self.vm_config.mmio_gap_size_mib);
+        let mmio_gap_size = mmio_gap_size_mib.map_or(x86_64::DEFAULT_32BIT_GAP_SIZE, |size| {
+            size.checked_mul(1 << 20).unwrap_or(usize::max_value())
+        });
+        if mmio_gap_size < x86_64::MIN_32BIT_GAP_SIZE || mmio_gap_size > x86_64::MAX_32BIT_GAP_SIZE
+        {
+            return Err(VmmActionError::MachineConfig(
+                ErrorKind::User,
+                VmConfigError::InvalidMmioGapSize,
+            ));
+        }
+        if let Some(mmio_base) = machine_config.mmio_base.or(self.vm_config.mmio_base) {
+            // The devices are mapped on guest pages, which are 4KiB.
+            if mmio_base % 0x1000 != 0
+                || mmio_base < x86_64::get_32bit_gap_start(mmio_gap_size) as u64
+                || mmio_base >= x86_64::MMIO_DEVICES_END as u64
+            {
+                return Err(VmmActionError::MachineConfig(
+                    ErrorKind::User,
+                    VmConfigError::InvalidMmioBase,
+                ));
+            }
+        }
+
         // Update all the fields that have a new value.
         self.vm_config.vcpu_count = Some(vcpu_count_value);
         self.vm_config.ht_enabled = Some(ht_enabled);
@@ -1444,6 +1882,73 @@ impl Vmm {
             self.vm_config.cpu_template = machine_config.cpu_template;
         }
 
+        if machine_config.thp_policy.is_some() {
+            self.vm_config.thp_policy = machine_config.thp_policy;
+        }
+
+        if machine_config.dontfork.is_some() {
+            self.vm_config.dontfork = machine_config.dontfork;
+        }
+
+        if machine_config.prefault.is_some() {
+            self.vm_config.prefault = machine_config.prefault;
+        }
+
+        if machine_config.watchdog_action.is_some() {
+            self.vm_config.watchdog_action = machine_config.watchdog_action;
+        }
+
+        if machine_config.vpmu.is_some() {
+            self.vm_config.vpmu = machine_config.vpmu;
+        }
+
+        if machine_config.mergeable.is_some() {
+            self.vm_config.mergeable = machine_config.mergeable;
+        }
+
+        if machine_config.mmio_gap_size_mib.is_some() {
+            self.vm_config.mmio_gap_size_mib = machine_config.mmio_gap_size_mib;
+        }
+
+        if machine_config.mmio_base.is_some() {
+            self.vm_config.mmio_base = machine_config.mmio_base;
+        }
+
+        if machine_config.acpi.is_some() {
+            self.vm_config.acpi = machine_config.acpi;
+        }
+
+        Ok(VmmData::Empty)
+    }
+
+    fn set_cpu_template(
+        &mut self,
+        cpu_template: CustomCpuTemplate,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        if self.is_instance_initialized() {
+            return Err(VmmActionError::MachineConfig(
+                ErrorKind::User,
+                VmConfigError::UpdateNotAllowedPostBoot,
+            ));
+        }
+
+        // The template is checked against what the host KVM supports, so that a template written
+        // for other hosts is rejected here instead of failing, or being ignored, at boot time.
+        let mut supported_cpuid = self
+            .kvm
+            .fd()
+            .get_supported_cpuid(MAX_KVM_CPUID_ENTRIES)
+            .map_err(|e| {
+                VmmActionError::MachineConfig(ErrorKind::Internal, VmConfigError::HostCpuQuery(e))
+            })?;
+        let supported_msrs = self.kvm.fd().get_msr_index_list().map_err(|e| {
+            VmmActionError::MachineConfig(ErrorKind::Internal, VmConfigError::HostCpuQuery(e))
+        })?;
+        cpu_template
+            .validate(&mut supported_cpuid, &supported_msrs)
+            .map_err(|e| VmmActionError::MachineConfig(ErrorKind::User, e))?;
+
+        self.cpu_template = Some(cpu_template);
         Ok(VmmData::Empty)
     }
 
@@ -1463,6 +1968,51 @@ impl Vmm {
             .map_err(|e| VmmActionError::NetworkConfig(ErrorKind::User, e))
     }
 
+    fn update_net_device(
+        &mut self,
+        update_config: NetworkInterfaceUpdateConfig,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        // The frames are captured by the device model, which only exists after boot.
+        if !self.is_instance_initialized() {
+            return Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::UpdateNotAllowedPreBoot,
+            ));
+        }
+        let device_idx = *self.net_handler_id_map.get(&update_config.iface_id).ok_or(
+            VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::InvalidIfaceId(update_config.iface_id.clone()),
+            ),
+        )?;
+        let capture = match update_config.capture {
+            Some(ref capture_config) => Some(
+                capture_config
+                    .open()
+                    .map_err(|e| VmmActionError::NetworkConfig(ErrorKind::User, e))?,
+            ),
+            None => None,
+        };
+
+        match self.epoll_context.get_device_handler(device_idx) {
+            Ok(handler) => {
+                handler.handle_event(
+                    virtio::net::CAPTURE_UPDATE_EVENT,
+                    device_idx as u32,
+                    EpollHandlerPayload::NetCapturePayload(capture),
+                );
+                Ok(VmmData::Empty)
+            }
+            Err(e) => {
+                warn!("invalid handler for device {}: {:?}", device_idx, e);
+                Err(VmmActionError::NetworkConfig(
+                    ErrorKind::User,
+                    NetworkInterfaceError::DeviceUpdateFailed,
+                ))
+            }
+        }
+    }
+
     #[cfg(feature = "vsock")]
     fn insert_vsock_device(
         &mut self,
@@ -1510,13 +2060,103 @@ impl Vmm {
         // When the microvm is running, we also need to update the drive handler and send a
         // rescan command to the drive.
         if self.is_instance_initialized() {
-            self.update_drive_handler(&drive_id, disk_file)
-                .map_err(|e| VmmActionError::DriveConfig(ErrorKind::User, e))?;
+            self.update_drive_handler(
+                &drive_id,
+                virtio::block::FS_UPDATE_EVENT,
+                EpollHandlerPayload::DrivePayload(disk_file),
+            )
+            .map_err(|e| VmmActionError::DriveConfig(ErrorKind::User, e))?;
             self.rescan_block_device(&drive_id)?;
         }
         Ok(VmmData::Empty)
     }
 
+    fn set_block_device_trace(
+        &mut self,
+        drive_id: String,
+        trace_config: Option<BlockDeviceTraceConfig>,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        // The requests are traced by the device model, which only exists after boot.
+        if !self.is_instance_initialized() {
+            return Err(VmmActionError::DriveConfig(
+                ErrorKind::User,
+                DriveError::OperationNotAllowedPreBoot,
+            ));
+        }
+        if self
+            .block_device_configs
+            .get_index_of_drive_id(&drive_id)
+            .is_none()
+        {
+            return Err(VmmActionError::DriveConfig(
+                ErrorKind::User,
+                DriveError::InvalidBlockDeviceID,
+            ));
+        }
+
+        let io_trace = match trace_config {
+            Some(ref trace_config) => Some(
+                trace_config
+                    .open()
+                    .map_err(|e| VmmActionError::DriveConfig(ErrorKind::User, e))?,
+            ),
+            None => None,
+        };
+        self.update_drive_handler(
+            &drive_id,
+            virtio::block::TRACE_UPDATE_EVENT,
+            EpollHandlerPayload::DriveTracePayload(io_trace),
+        )
+        .map(|_| VmmData::Empty)
+        .map_err(|e| VmmActionError::DriveConfig(ErrorKind::User, e))
+    }
+
+    fn get_device_features(&self, device_id: &str) -> std::result::Result<VmmData, VmmActionError> {
+        // The devices are only attached when the microVM starts.
+        let device_manager =
+            self.mmio_device_manager
+                .as_ref()
+                .ok_or(VmmActionError::DeviceFeatures(
+                    ErrorKind::User,
+                    DeviceFeaturesError::MicroVMNotStarted,
+                ))?;
+        device_manager
+            .get_device_features(device_id)
+            .map(VmmData::DeviceFeatures)
+            .ok_or(VmmActionError::DeviceFeatures(
+                ErrorKind::User,
+                DeviceFeaturesError::DeviceNotFound(String::from(device_id)),
+            ))
+    }
+
+    fn send_serial_input(
+        &mut self,
+        serial_input: SerialInput,
+    ) -> std::result::Result<VmmData, VmmActionError> {
+        // Before boot, there is no guest to read the input yet.
+        if !self.is_instance_initialized() {
+            return Err(VmmActionError::SerialInput(
+                ErrorKind::User,
+                SerialInputError::MicroVMNotStarted,
+            ));
+        }
+        let mut serial = self
+            .legacy_device_manager
+            .stdio_serial
+            .lock()
+            .expect("Failed to send serial input due to poisoned lock");
+        let result = match serial_input {
+            SerialInput::Bytes(bytes) => serial.queue_input_bytes(&bytes),
+            SerialInput::SysRq(key) => serial.queue_sysrq(key),
+        };
+        result.map(|_| VmmData::Empty).map_err(|e| match e.errno() {
+            libc::ENOSPC => {
+                VmmActionError::SerialInput(ErrorKind::User, SerialInputError::BufferFull)
+            }
+            _ => VmmActionError::SerialInput(ErrorKind::Internal, SerialInputError::QueueInput(e)),
+        })
+    }
+
     fn rescan_block_device(
         &mut self,
         drive_id: &String,
@@ -1643,6 +2283,18 @@ impl Vmm {
             })
     }
 
+    fn reset_metrics(&self, groups: &[String]) -> std::result::Result<VmmData, VmmActionError> {
+        METRICS
+            .reset(groups)
+            .map(|_| VmmData::Empty)
+            .map_err(|group| {
+                VmmActionError::Logger(
+                    ErrorKind::User,
+                    LoggerConfigError::UnknownMetricsGroup(group),
+                )
+            })
+    }
+
     fn send_response(outcome: VmmRequestOutcome, sender: OutcomeSender) {
         sender
             .send(outcome)
@@ -1674,6 +2326,12 @@ impl Vmm {
             VmmAction::ConfigureLogger(logger_description, sender) => {
                 Vmm::send_response(self.init_logger(logger_description), sender);
             }
+            VmmAction::GetDeviceFeatures(device_id, sender) => {
+                Vmm::send_response(self.get_device_features(&device_id), sender);
+            }
+            VmmAction::GetGuestEvents(sender) => {
+                Vmm::send_response(Ok(VmmData::GuestEvents(self.guest_events.clone())), sender);
+            }
             VmmAction::GetVmConfiguration(sender) => {
                 Vmm::send_response(
                     Ok(VmmData::MachineConfiguration(self.vm_config.clone())),
@@ -1693,15 +2351,35 @@ impl Vmm {
             VmmAction::RescanBlockDevice(drive_id, sender) => {
                 Vmm::send_response(self.rescan_block_device(&drive_id), sender);
             }
+            VmmAction::ResetMetrics(groups, sender) => {
+                Vmm::send_response(self.reset_metrics(&groups), sender);
+            }
+            VmmAction::SendSerialInput(serial_input, sender) => {
+                Vmm::send_response(self.send_serial_input(serial_input), sender);
+            }
+            VmmAction::Shutdown(sender) => {
+                self.shut_down();
+                self.shutdown_requested = true;
+                Vmm::send_response(Ok(VmmData::Empty), sender);
+            }
             VmmAction::StartMicroVm(sender) => {
                 Vmm::send_response(self.start_microvm(), sender);
             }
+            VmmAction::SetCpuTemplate(cpu_template, sender) => {
+                Vmm::send_response(self.set_cpu_template(cpu_template), sender);
+            }
             VmmAction::SetVmConfiguration(machine_config_body, sender) => {
                 Vmm::send_response(self.set_vm_configuration(machine_config_body), sender);
             }
             VmmAction::UpdateBlockDevicePath(drive_id, path_on_host, sender) => {
                 Vmm::send_response(self.set_block_device_path(drive_id, path_on_host), sender);
             }
+            VmmAction::UpdateBlockDeviceTrace(drive_id, trace_config, sender) => {
+                Vmm::send_response(self.set_block_device_trace(drive_id, trace_config), sender);
+            }
+            VmmAction::UpdateNetworkInterface(netif_update, sender) => {
+                Vmm::send_response(self.update_net_device(netif_update), sender);
+            }
         };
         Ok(())
     }
@@ -1717,6 +2395,10 @@ impl PartialEq for VmmAction {
                 &VmmAction::UpdateBlockDevicePath(ref drive_id, ref path_on_host, _),
                 &VmmAction::UpdateBlockDevicePath(ref other_drive_id, ref other_path_on_host, _),
             ) => drive_id == other_drive_id && path_on_host == other_path_on_host,
+            (
+                &VmmAction::UpdateBlockDeviceTrace(ref drive_id, ref trace_config, _),
+                &VmmAction::UpdateBlockDeviceTrace(ref other_drive_id, ref other_trace_config, _),
+            ) => drive_id == other_drive_id && trace_config == other_trace_config,
             (
                 &VmmAction::ConfigureBootSource(ref boot_source, _),
                 &VmmAction::ConfigureBootSource(ref other_boot_source, _),
@@ -1729,6 +2411,10 @@ impl PartialEq for VmmAction {
                 &VmmAction::ConfigureLogger(ref log, _),
                 &VmmAction::ConfigureLogger(ref other_log, _),
             ) => log == other_log,
+            (
+                &VmmAction::SetCpuTemplate(ref cpu_template, _),
+                &VmmAction::SetCpuTemplate(ref other_cpu_template, _),
+            ) => cpu_template == other_cpu_template,
             (
                 &VmmAction::SetVmConfiguration(ref vm_config, _),
                 &VmmAction::SetVmConfiguration(ref other_vm_config, _),
@@ -1741,12 +2427,39 @@ impl PartialEq for VmmAction {
                 &VmmAction::RescanBlockDevice(ref req, _),
                 &VmmAction::RescanBlockDevice(ref other_req, _),
             ) => req == other_req,
+            (
+                &VmmAction::ResetMetrics(ref groups, _),
+                &VmmAction::ResetMetrics(ref other_groups, _),
+            ) => groups == other_groups,
+            (
+                &VmmAction::GetDeviceFeatures(ref device_id, _),
+                &VmmAction::GetDeviceFeatures(ref other_device_id, _),
+            ) => device_id == other_device_id,
+            (
+                &VmmAction::UpdateNetworkInterface(ref netif_update, _),
+                &VmmAction::UpdateNetworkInterface(ref other_netif_update, _),
+            ) => netif_update == other_netif_update,
+            (
+                &VmmAction::SendSerialInput(ref serial_input, _),
+                &VmmAction::SendSerialInput(ref other_serial_input, _),
+            ) => serial_input == other_serial_input,
+            (&VmmAction::GetGuestEvents(_), &VmmAction::GetGuestEvents(_)) => true,
+            (&VmmAction::Shutdown(_), &VmmAction::Shutdown(_)) => true,
             (&VmmAction::StartMicroVm(_), &VmmAction::StartMicroVm(_)) => true,
             _ => false,
         }
     }
 }
 
+// Reads the number of pages of the process merged by KSM.
+fn ksm_merging_pages() -> Option<usize> {
+    let mut pages = String::new();
+    File::open(KSM_MERGING_PAGES_PATH)
+        .and_then(|mut file| file.read_to_string(&mut pages))
+        .ok()?;
+    pages.trim().parse().ok()
+}
+
 /// Starts a new vmm thread that can service API requests.
 ///
 /// # Arguments
@@ -1765,6 +2478,25 @@ pub fn start_vmm_thread(
     from_api: Receiver<Box<VmmAction>>,
     seccomp_level: u32,
     kvm_fd: Option<RawFd>,
+) -> thread::JoinHandle<()> {
+    spawn_vmm_thread(
+        api_shared_info,
+        api_event_fd,
+        from_api,
+        seccomp_level,
+        kvm_fd,
+        Vec::new(),
+    )
+}
+
+// Same as `start_vmm_thread`, but also registers the callbacks of an embedding application.
+fn spawn_vmm_thread(
+    api_shared_info: Arc<RwLock<InstanceInfo>>,
+    api_event_fd: EventFd,
+    from_api: Receiver<Box<VmmAction>>,
+    seccomp_level: u32,
+    kvm_fd: Option<RawFd>,
+    event_callbacks: Vec<EventCallback>,
 ) -> thread::JoinHandle<()> {
     thread::Builder::new()
         .name("fc_vmm".to_string())
@@ -1778,7 +2510,9 @@ pub fn start_vmm_thread(
                 kvm_fd,
             )
             .expect("Cannot create VMM.");
+            vmm.event_callbacks = event_callbacks;
             match vmm.run_control() {
+                Ok(()) if vmm.shutdown_requested => info!("The VMM thread was shut down"),
                 Ok(()) => {
                     info!("Gracefully terminated VMM control loop");
                     vmm.stop(0)
@@ -1803,8 +2537,12 @@ mod tests {
 
     use self::tempfile::NamedTempFile;
     use devices::virtio::ActivateResult;
+    use devices::BusDevice;
+    use futures::Future;
     use net_util::MacAddr;
+    use vmm_config::cpu_template::{CpuidLeafModifier, MsrModifier, RegisterBitmap};
     use vmm_config::machine_config::CpuFeaturesTemplate;
+    use vmm_config::net::PacketCaptureConfig;
 
     impl Vmm {
         fn get_kernel_cmdline_str(&self) -> &str {
@@ -2041,6 +2779,71 @@ mod tests {
         assert!(vmm.insert_block_device(root_block_device).is_err())
     }
 
+    #[test]
+    fn test_set_block_device_trace() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let f = NamedTempFile::new().unwrap();
+        let root_block_device = BlockDeviceConfig {
+            drive_id: String::from("root"),
+            path_on_host: f.path().to_path_buf(),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: false,
+            rate_limiter: None,
+        };
+        assert!(vmm.insert_block_device(root_block_device).is_ok());
+
+        let trace_file = NamedTempFile::new().unwrap();
+        let trace_config = BlockDeviceTraceConfig {
+            path_on_host: trace_file.path().to_path_buf(),
+        };
+
+        // Test that tracing pre-boot fails.
+        match vmm.set_block_device_trace(String::from("root"), Some(trace_config.clone())) {
+            Err(VmmActionError::DriveConfig(
+                ErrorKind::User,
+                DriveError::OperationNotAllowedPreBoot,
+            )) => (),
+            _ => assert!(false),
+        }
+
+        // Test that tracing an unknown drive fails.
+        vmm.set_instance_state(InstanceState::Running);
+        match vmm.set_block_device_trace(String::from("other"), None) {
+            Err(VmmActionError::DriveConfig(ErrorKind::User, DriveError::InvalidBlockDeviceID)) => {
+            }
+            _ => assert!(false),
+        }
+
+        // Test that tracing fails until the guest activates the device.
+        let (_, sender) = vmm
+            .epoll_context
+            .allocate_tokens(virtio::block::BLOCK_EVENTS_COUNT);
+        let device_idx = vmm.epoll_context.device_handlers.len() - 1;
+        vmm.drive_handler_id_map
+            .insert(String::from("root"), device_idx);
+        match vmm.set_block_device_trace(String::from("root"), Some(trace_config.clone())) {
+            Err(VmmActionError::DriveConfig(
+                ErrorKind::User,
+                DriveError::BlockDeviceUpdateFailed,
+            )) => (),
+            _ => assert!(false),
+        }
+
+        let handler = DummyEpollHandler {
+            evt: None,
+            flags: None,
+            payload: None,
+        };
+        assert!(sender.send(Box::new(handler)).is_ok());
+        assert!(vmm
+            .set_block_device_trace(String::from("root"), Some(trace_config))
+            .is_ok());
+        assert!(vmm
+            .set_block_device_trace(String::from("root"), None)
+            .is_ok());
+    }
+
     #[test]
     fn test_insert_net_device() {
         let mut vmm = create_vmm_object(InstanceState::Uninitialized);
@@ -2052,6 +2855,7 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            rx_ebpf_filter: None,
             allow_mmds_requests: false,
             tap: None,
         };
@@ -2065,6 +2869,7 @@ mod tests {
             guest_mac: Some(mac.clone()),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            rx_ebpf_filter: None,
             allow_mmds_requests: false,
             tap: None,
         };
@@ -2077,6 +2882,7 @@ mod tests {
             guest_mac: Some(mac),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            rx_ebpf_filter: None,
             allow_mmds_requests: false,
             tap: None,
         };
@@ -2090,12 +2896,71 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            rx_ebpf_filter: None,
             allow_mmds_requests: false,
             tap: None,
         };
         assert!(vmm.insert_net_device(network_interface).is_err());
     }
 
+    #[test]
+    fn test_update_net_device() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let capture_file = NamedTempFile::new().unwrap();
+        let update_config = NetworkInterfaceUpdateConfig {
+            iface_id: String::from("netif"),
+            capture: Some(PacketCaptureConfig {
+                path_on_host: capture_file.path().to_path_buf(),
+                snap_len: 128,
+                max_bytes: None,
+            }),
+        };
+
+        // Test that update pre-boot fails.
+        match vmm.update_net_device(update_config.clone()) {
+            Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::UpdateNotAllowedPreBoot,
+            )) => (),
+            _ => assert!(false),
+        }
+
+        // Test that updating an unknown network interface fails.
+        vmm.set_instance_state(InstanceState::Running);
+        match vmm.update_net_device(update_config.clone()) {
+            Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::InvalidIfaceId(_),
+            )) => (),
+            _ => assert!(false),
+        }
+
+        // Test that update fails until the guest activates the device.
+        let (_, sender) = vmm
+            .epoll_context
+            .allocate_tokens(virtio::net::NET_EVENTS_COUNT);
+        let device_idx = vmm.epoll_context.device_handlers.len() - 1;
+        vmm.net_handler_id_map
+            .insert(String::from("netif"), device_idx);
+        match vmm.update_net_device(update_config.clone()) {
+            Err(VmmActionError::NetworkConfig(
+                ErrorKind::User,
+                NetworkInterfaceError::DeviceUpdateFailed,
+            )) => (),
+            _ => assert!(false),
+        }
+
+        let handler = DummyEpollHandler {
+            evt: None,
+            flags: None,
+            payload: None,
+        };
+        assert!(sender.send(Box::new(handler)).is_ok());
+        assert!(vmm.update_net_device(update_config).is_ok());
+        // The capture file only holds the pcap header.
+        assert_eq!(capture_file.path().metadata().unwrap().len(), 24);
+    }
+
     #[test]
     fn test_machine_configuration() {
         let mut vmm = create_vmm_object(InstanceState::Uninitialized);
@@ -2109,6 +2974,15 @@ mod tests {
         assert_eq!(vmm.vm_config.ht_enabled, Some(false));
         // no cpu template
         assert!(vmm.vm_config.cpu_template.is_none());
+        // no memory advice
+        assert!(vmm.vm_config.thp_policy.is_none());
+        assert!(vmm.vm_config.dontfork.is_none());
+        assert!(vmm.vm_config.prefault.is_none());
+        assert!(vmm.vm_config.mergeable.is_none());
+        // no watchdog
+        assert!(vmm.vm_config.watchdog_action.is_none());
+        // no vPMU
+        assert!(vmm.vm_config.vpmu.is_none());
 
         // 1. Tests with no hyperthreading
         // test put machine configuration for vcpu count with valid value
@@ -2117,6 +2991,15 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: None,
             cpu_template: None,
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_ok());
         assert_eq!(vmm.vm_config.vcpu_count, Some(3));
@@ -2129,6 +3012,15 @@ mod tests {
             mem_size_mib: Some(256),
             ht_enabled: None,
             cpu_template: None,
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_ok());
         assert_eq!(vmm.vm_config.vcpu_count, Some(3));
@@ -2142,10 +3034,102 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: None,
             cpu_template: None,
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_err());
         assert_eq!(vmm.vm_config.vcpu_count, Some(3));
 
+        // More vCPUs than the APIC IDs can address are rejected.
+        let machine_config = VmConfig {
+            vcpu_count: Some(x86_64::MAX_SUPPORTED_CPUS + 1),
+            mem_size_mib: None,
+            ht_enabled: None,
+            cpu_template: None,
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
+        };
+        match vmm.set_vm_configuration(machine_config) {
+            Err(VmmActionError::MachineConfig(
+                ErrorKind::User,
+                VmConfigError::TooManyVcpus(max_vcpus),
+            )) => assert_eq!(max_vcpus, vmm.kvm.max_vcpus()),
+            _ => panic!("Setting too many vCPUs should fail."),
+        }
+        assert_eq!(vmm.vm_config.vcpu_count, Some(3));
+
+        // The MMIO gap has to be in the supported range, and the devices inside it.
+        let layout_config = |mmio_gap_size_mib, mmio_base| VmConfig {
+            vcpu_count: None,
+            mem_size_mib: None,
+            ht_enabled: None,
+            cpu_template: None,
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib,
+            mmio_base,
+            acpi: None,
+        };
+        let config_error = |result: std::result::Result<VmmData, VmmActionError>| match result {
+            Err(VmmActionError::MachineConfig(ErrorKind::User, e)) => Some(e),
+            _ => None,
+        };
+        let gap_size_error = Some(VmConfigError::InvalidMmioGapSize);
+        let base_error = Some(VmConfigError::InvalidMmioBase);
+        assert_eq!(
+            config_error(vmm.set_vm_configuration(layout_config(Some(32), None))),
+            gap_size_error
+        );
+        assert_eq!(
+            config_error(vmm.set_vm_configuration(layout_config(Some(4096), None))),
+            gap_size_error
+        );
+        assert_eq!(
+            config_error(vmm.set_vm_configuration(layout_config(None, Some(0xc000_0800)))),
+            base_error
+        );
+        assert_eq!(
+            config_error(vmm.set_vm_configuration(layout_config(None, Some(0xbfff_f000)))),
+            base_error
+        );
+        assert_eq!(
+            config_error(vmm.set_vm_configuration(layout_config(None, Some(0xfec0_0000)))),
+            base_error
+        );
+        assert!(vmm.vm_config.mmio_gap_size_mib.is_none());
+        assert!(vmm.vm_config.mmio_base.is_none());
+
+        assert!(vmm
+            .set_vm_configuration(layout_config(Some(1024), Some(0xd000_0000)))
+            .is_ok());
+        assert_eq!(vmm.mmio_gap_size(), 1 << 30);
+        // The devices have to stay in the gap when it shrinks.
+        assert_eq!(
+            config_error(vmm.set_vm_configuration(layout_config(Some(256), None))),
+            base_error
+        );
+        assert_eq!(vmm.vm_config.mmio_gap_size_mib, Some(1024));
+        vmm.vm_config.mmio_gap_size_mib = None;
+        vmm.vm_config.mmio_base = None;
+
         // Test Error cases for put_machine_configuration with invalid value for the mem_size_mib
         // Test that the put method return error & that the mem_size_mib value is not changed
         let machine_config = VmConfig {
@@ -2153,6 +3137,15 @@ mod tests {
             mem_size_mib: Some(0),
             ht_enabled: Some(false),
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_err());
         assert_eq!(vmm.vm_config.vcpu_count, Some(3));
@@ -2168,21 +3161,45 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: Some(true),
             cpu_template: None,
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_err());
         assert_eq!(vmm.vm_config.ht_enabled, Some(false));
         // Test that you can change the ht flag when you have a valid vcpu count
-        // Also set the CPU Template since we are here
+        // Also set the CPU Template, the memory advice, the watchdog and the vPMU since we are here
         let machine_config = VmConfig {
             vcpu_count: Some(2),
             mem_size_mib: None,
             ht_enabled: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            thp_policy: Some(ThpPolicy::NoHugepage),
+            dontfork: Some(true),
+            prefault: Some(true),
+            watchdog_action: Some(WatchdogAction::Reset),
+            vpmu: Some(true),
+            mergeable: Some(true),
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_ok());
         assert_eq!(vmm.vm_config.vcpu_count, Some(2));
         assert_eq!(vmm.vm_config.ht_enabled, Some(true));
         assert_eq!(vmm.vm_config.cpu_template, Some(CpuFeaturesTemplate::T2));
+        assert_eq!(vmm.vm_config.thp_policy, Some(ThpPolicy::NoHugepage));
+        assert_eq!(vmm.vm_config.dontfork, Some(true));
+        assert_eq!(vmm.vm_config.prefault, Some(true));
+        assert_eq!(vmm.vm_config.watchdog_action, Some(WatchdogAction::Reset));
+        assert_eq!(vmm.vm_config.vpmu, Some(true));
+        assert_eq!(vmm.vm_config.mergeable, Some(true));
 
         // 3. Test update vm configuration after boot.
         vmm.set_instance_state(InstanceState::Running);
@@ -2191,10 +3208,89 @@ mod tests {
             mem_size_mib: None,
             ht_enabled: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
         };
         assert!(vmm.set_vm_configuration(machine_config).is_err());
     }
 
+    #[test]
+    fn test_set_cpu_template() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        assert!(vmm.cpu_template.is_none());
+
+        // The host doesn't support a CPUID leaf past the extended ones.
+        let unsupported_leaf = CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidLeafModifier {
+                leaf: 0x8fff_ffff,
+                subleaf: 0x0,
+                eax: None,
+                ebx: None,
+                ecx: None,
+                edx: None,
+            }],
+            msr_modifiers: vec![],
+        };
+        match vmm.set_cpu_template(unsupported_leaf) {
+            Err(VmmActionError::MachineConfig(
+                ErrorKind::User,
+                VmConfigError::UnsupportedCpuidLeaf(0x8fff_ffff, 0x0),
+            )) => (),
+            _ => assert!(false),
+        }
+        assert!(vmm.cpu_template.is_none());
+
+        // Nor an MSR which doesn't exist.
+        let unsupported_msr = CustomCpuTemplate {
+            cpuid_modifiers: vec![],
+            msr_modifiers: vec![MsrModifier {
+                addr: 0xffff_ffff,
+                bitmap: RegisterBitmap { mask: 1, value: 0 },
+            }],
+        };
+        match vmm.set_cpu_template(unsupported_msr) {
+            Err(VmmActionError::MachineConfig(
+                ErrorKind::User,
+                VmConfigError::UnsupportedMsr(0xffff_ffff),
+            )) => (),
+            _ => assert!(false),
+        }
+
+        // Leaf 0x0 and IA32_SYSENTER_CS are supported everywhere.
+        let cpu_template = CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidLeafModifier {
+                leaf: 0x0,
+                subleaf: 0x0,
+                eax: None,
+                ebx: None,
+                ecx: None,
+                edx: None,
+            }],
+            msr_modifiers: vec![MsrModifier {
+                addr: 0x174,
+                bitmap: RegisterBitmap { mask: 1, value: 0 },
+            }],
+        };
+        assert!(vmm.set_cpu_template(cpu_template.clone()).is_ok());
+        assert_eq!(vmm.cpu_template, Some(cpu_template.clone()));
+
+        vmm.set_instance_state(InstanceState::Running);
+        match vmm.set_cpu_template(cpu_template) {
+            Err(VmmActionError::MachineConfig(
+                ErrorKind::User,
+                VmConfigError::UpdateNotAllowedPostBoot,
+            )) => (),
+            _ => assert!(false),
+        }
+    }
+
     #[test]
     fn new_epoll_context_test() {
         assert!(EpollContext::new().is_ok());
@@ -2330,6 +3426,8 @@ mod tests {
         let c = KvmContext::new(None).unwrap();
 
         assert!(c.max_memslots >= 32);
+        assert!(c.max_vcpus() >= 1 && c.max_vcpus() <= x86_64::MAX_SUPPORTED_CPUS);
+        assert!(KvmContext::missing_caps(c.fd()).is_empty());
 
         let kvm = Kvm::new().unwrap();
         let f = unsafe { File::from_raw_fd(kvm.as_raw_fd()) };
@@ -2372,6 +3470,194 @@ mod tests {
         assert_eq!(vmm.is_instance_initialized(), true);
     }
 
+    #[test]
+    fn test_init_guest_memory() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        // The transparent huge pages hints are not tested since they depend on the host kernel
+        // config.
+        vmm.vm_config.dontfork = Some(true);
+        vmm.vm_config.mergeable = Some(true);
+        vmm.vm_config.prefault = Some(true);
+        assert!(vmm.init_guest_memory().is_ok());
+        assert!(vmm.guest_memory.is_some());
+    }
+
+    #[test]
+    fn test_ksm_merging_pages() {
+        // Only the kernels which report the merged pages of a process can be checked.
+        if std::path::Path::new(KSM_MERGING_PAGES_PATH).exists() {
+            assert!(ksm_merging_pages().is_some());
+        }
+    }
+
+    #[test]
+    fn test_watchdog_timer() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        vmm.vm_config.watchdog_action = Some(WatchdogAction::Log);
+        assert!(vmm.legacy_device_manager.register_watchdog().is_ok());
+        assert!(vmm.register_events().is_ok());
+        assert!(vmm.watchdog_update_evt.is_some());
+        let watchdog = vmm.legacy_device_manager.watchdog.clone().unwrap();
+
+        // The guest starts the watchdog with a timeout of 2 seconds.
+        watchdog.lock().unwrap().write(0x2, &[0xe]);
+        assert!(vmm.update_watchdog_timer().is_ok());
+        match vmm.watchdog_timer.as_ref().unwrap().fd.get_state() {
+            TimerState::Oneshot(remaining) => assert!(remaining <= Duration::from_secs(2)),
+            _ => assert!(false),
+        }
+
+        // An expiry handled after the guest pinged the watchdog again is ignored.
+        let expired_count = METRICS.watchdog.expired_count.count();
+        vmm.handle_watchdog_expiry();
+        assert_eq!(METRICS.watchdog.expired_count.count(), expired_count);
+
+        // The guest stops the watchdog.
+        watchdog.lock().unwrap().write(0x0, &[0x0]);
+        assert!(vmm.update_watchdog_timer().is_ok());
+        assert_eq!(
+            vmm.watchdog_timer.as_ref().unwrap().fd.get_state(),
+            TimerState::Disarmed
+        );
+    }
+
+    #[test]
+    fn test_guest_events() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let before = METRICS.guest_events.panic_count.count();
+
+        // The guest reports a panic handled by a crash kernel.
+        vmm.legacy_device_manager.pvpanic.lock().unwrap().write(
+            0,
+            &[devices::legacy::PVPANIC_PANICKED | devices::legacy::PVPANIC_CRASH_LOADED],
+        );
+        assert!(vmm.handle_pvpanic_event().is_ok());
+        assert_eq!(METRICS.guest_events.panic_count.count(), before + 1);
+        let kinds: Vec<GuestEventKind> = vmm.guest_events.iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![GuestEventKind::Panic, GuestEventKind::CrashLoaded]
+        );
+        assert!(vmm.guest_events[0].timestamp_us > 0);
+
+        // Only the most recent events are kept.
+        for _ in 0..MAX_GUEST_EVENTS {
+            vmm.record_guest_event(GuestEventKind::TripleFault);
+        }
+        assert_eq!(vmm.guest_events.len(), MAX_GUEST_EVENTS);
+        assert!(vmm
+            .guest_events
+            .iter()
+            .all(|e| e.kind == GuestEventKind::TripleFault));
+    }
+
+    #[test]
+    fn test_get_device_features() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        match vmm.get_device_features("root") {
+            Err(VmmActionError::DeviceFeatures(_, DeviceFeaturesError::MicroVMNotStarted)) => (),
+            _ => panic!("Expected a microVM not started error."),
+        }
+
+        let block_file = NamedTempFile::new().unwrap();
+        let root_block_device = BlockDeviceConfig {
+            drive_id: String::from("root"),
+            path_on_host: block_file.path().to_path_buf(),
+            is_root_device: true,
+            partuuid: None,
+            is_read_only: false,
+            rate_limiter: None,
+        };
+        assert!(vmm.insert_block_device(root_block_device).is_ok());
+        assert!(vmm.init_guest_memory().is_ok());
+        vmm.default_kernel_config();
+
+        let guest_mem = vmm.guest_memory.clone().unwrap();
+        let mut device_manager = MMIODeviceManager::new(
+            guest_mem,
+            x86_64::get_32bit_gap_start(x86_64::DEFAULT_32BIT_GAP_SIZE) as u64,
+        );
+        assert!(vmm.attach_block_devices(&mut device_manager).is_ok());
+        vmm.mmio_device_manager = Some(device_manager);
+
+        match vmm.get_device_features("root") {
+            Ok(VmmData::DeviceFeatures(features)) => {
+                assert_eq!(features.acked_features, 0);
+                assert_eq!(features.queues.len(), 1);
+            }
+            _ => panic!("Expected the features of the block device."),
+        }
+        match vmm.get_device_features("foo") {
+            Err(VmmActionError::DeviceFeatures(_, DeviceFeaturesError::DeviceNotFound(id))) => {
+                assert_eq!(id, "foo")
+            }
+            _ => panic!("Expected a device not found error."),
+        }
+    }
+
+    #[test]
+    fn test_send_serial_input() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        match vmm.send_serial_input(SerialInput::Bytes(vec![b'a'])) {
+            Err(VmmActionError::SerialInput(
+                ErrorKind::User,
+                SerialInputError::MicroVMNotStarted,
+            )) => (),
+            _ => panic!("Expected a microVM not started error."),
+        }
+
+        vmm.set_instance_state(InstanceState::Running);
+        assert!(vmm
+            .send_serial_input(SerialInput::Bytes(vec![b'a']))
+            .is_ok());
+        assert!(vmm.send_serial_input(SerialInput::SysRq(b'c')).is_ok());
+
+        // The guest reads the bytes, then the NUL byte of the break and the SysRq key.
+        let mut serial = vmm.legacy_device_manager.stdio_serial.lock().unwrap();
+        let mut data = [0u8];
+        for expected in [b'a', 0, b'c'].iter() {
+            serial.read(0, &mut data);
+            assert_eq!(data[0], *expected);
+        }
+    }
+
+    #[test]
+    fn test_reset_metrics() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        match vmm.reset_metrics(&["seccomp".to_string(), "foo".to_string()]) {
+            Err(VmmActionError::Logger(
+                ErrorKind::User,
+                LoggerConfigError::UnknownMetricsGroup(ref group),
+            )) => assert_eq!(group, "foo"),
+            _ => panic!("Expected an unknown metrics group error."),
+        }
+        assert_eq!(
+            format!(
+                "{}",
+                LoggerConfigError::UnknownMetricsGroup("foo".to_string())
+            ),
+            "Unknown metrics group: foo"
+        );
+
+        // The metrics can be reset before and after the microVM starts.
+        assert!(vmm.reset_metrics(&["seccomp".to_string()]).is_ok());
+        vmm.set_instance_state(InstanceState::Running);
+        assert!(vmm.reset_metrics(&[]).is_ok());
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let mut vmm = create_vmm_object(InstanceState::Uninitialized);
+        let (to_vmm, from_api) = channel();
+        vmm.from_api = from_api;
+        let (sender, receiver) = oneshot::channel();
+        to_vmm.send(Box::new(VmmAction::Shutdown(sender))).unwrap();
+
+        assert!(vmm.run_vmm_action().is_ok());
+        assert!(vmm.shutdown_requested);
+        assert!(receiver.wait().unwrap().is_ok());
+    }
+
     #[test]
     fn test_attach_block_devices() {
         let mut vmm = create_vmm_object(InstanceState::Uninitialized);
@@ -2394,8 +3680,10 @@ mod tests {
         vmm.default_kernel_config();
 
         let guest_mem = vmm.guest_memory.clone().unwrap();
-        let mut device_manager =
-            MMIODeviceManager::new(guest_mem.clone(), x86_64::get_32bit_gap_start() as u64);
+        let mut device_manager = MMIODeviceManager::new(
+            guest_mem.clone(),
+            x86_64::get_32bit_gap_start(x86_64::DEFAULT_32BIT_GAP_SIZE) as u64,
+        );
         assert!(vmm.attach_block_devices(&mut device_manager).is_ok());
         assert!(vmm.get_kernel_cmdline_str().contains("root=/dev/vda"));
 
@@ -2418,8 +3706,10 @@ mod tests {
         vmm.default_kernel_config();
 
         let guest_mem = vmm.guest_memory.clone().unwrap();
-        let mut device_manager =
-            MMIODeviceManager::new(guest_mem.clone(), x86_64::get_32bit_gap_start() as u64);
+        let mut device_manager = MMIODeviceManager::new(
+            guest_mem.clone(),
+            x86_64::get_32bit_gap_start(x86_64::DEFAULT_32BIT_GAP_SIZE) as u64,
+        );
         assert!(vmm.attach_block_devices(&mut device_manager).is_ok());
         assert!(vmm
             .get_kernel_cmdline_str()
@@ -2446,8 +3736,10 @@ mod tests {
         vmm.default_kernel_config();
 
         let guest_mem = vmm.guest_memory.clone().unwrap();
-        let mut device_manager =
-            MMIODeviceManager::new(guest_mem.clone(), x86_64::get_32bit_gap_start() as u64);
+        let mut device_manager = MMIODeviceManager::new(
+            guest_mem.clone(),
+            x86_64::get_32bit_gap_start(x86_64::DEFAULT_32BIT_GAP_SIZE) as u64,
+        );
         assert!(vmm.attach_block_devices(&mut device_manager).is_ok());
         // Test that kernel commandline does not contain either /dev/vda or PARTUUID.
         assert!(!vmm.get_kernel_cmdline_str().contains("root=PARTUUID="));
@@ -2480,8 +3772,10 @@ mod tests {
         vmm.default_kernel_config();
 
         let guest_mem = vmm.guest_memory.clone().unwrap();
-        let mut device_manager =
-            MMIODeviceManager::new(guest_mem.clone(), x86_64::get_32bit_gap_start() as u64);
+        let mut device_manager = MMIODeviceManager::new(
+            guest_mem.clone(),
+            x86_64::get_32bit_gap_start(x86_64::DEFAULT_32BIT_GAP_SIZE) as u64,
+        );
 
         // test create network interface
         let network_interface = NetworkInterfaceConfig {
@@ -2490,6 +3784,7 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            rx_ebpf_filter: None,
             allow_mmds_requests: false,
             tap: None,
         };
@@ -2577,8 +3872,10 @@ mod tests {
         assert!(vmm.guest_memory.is_some());
 
         let guest_mem = vmm.guest_memory.clone().unwrap();
-        let mut device_manager =
-            MMIODeviceManager::new(guest_mem.clone(), x86_64::get_32bit_gap_start() as u64);
+        let mut device_manager = MMIODeviceManager::new(
+            guest_mem.clone(),
+            x86_64::get_32bit_gap_start(x86_64::DEFAULT_32BIT_GAP_SIZE) as u64,
+        );
 
         let dummy_box = Box::new(DummyDevice { dummy: 0 });
         // Use a dummy command line as it is not used in this test.
@@ -0,0 +1,157 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use libc;
+
+use logger::metrics::VcpuThreadMetrics;
+use logger::{Metric, METRICS};
+
+// The CPU times of a thread, in clock ticks.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct ThreadTimes {
+    user: u64,
+    sys: u64,
+    guest: u64,
+}
+
+// Parses the CPU times out of the content of `/proc/<pid>/task/<tid>/stat`.
+fn parse_thread_stat(stat: &str) -> Option<ThreadTimes> {
+    // The command name can contain spaces and parentheses, so the fields are split after the last
+    // parenthesis; the first one left is the third field of the file, the state of the thread.
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let field = |n: usize| -> Option<u64> { fields.get(n - 3).and_then(|f| f.parse().ok()) };
+    Some(ThreadTimes {
+        user: field(14)?,
+        sys: field(15)?,
+        guest: field(43)?,
+    })
+}
+
+/// Returns the ID of the calling thread.
+pub fn gettid() -> usize {
+    // This is safe because gettid has no arguments and can't fail.
+    unsafe { libc::syscall(libc::SYS_gettid) as usize }
+}
+
+/// Samples the CPU time used by the thread of a vCPU into the metrics of the vCPU.
+pub struct VcpuTimeSampler {
+    // The ID of the vCPU thread, stored by the thread when it starts. It is 0 until then.
+    tid: Arc<AtomicUsize>,
+    metrics: Arc<VcpuThreadMetrics>,
+    last: Option<(ThreadTimes, Instant)>,
+}
+
+impl VcpuTimeSampler {
+    /// Creates a sampler for the vCPU `cpu_id`, whose thread did not start yet.
+    pub fn new(cpu_id: u8) -> Self {
+        VcpuTimeSampler {
+            tid: Arc::new(AtomicUsize::new(0)),
+            metrics: METRICS.vcpu.threads.register(cpu_id),
+            last: None,
+        }
+    }
+
+    /// Returns where the vCPU thread has to store its ID, as returned by `gettid`.
+    pub fn tid(&self) -> Arc<AtomicUsize> {
+        self.tid.clone()
+    }
+
+    /// Adds the CPU time used by the vCPU thread since the last call to the metrics.
+    ///
+    /// Because this is used for metrics, a thread which didn't start yet or already exited is
+    /// simply not sampled.
+    pub fn sample(&mut self) {
+        let tid = self.tid.load(Ordering::Acquire);
+        if tid == 0 {
+            return;
+        }
+        let mut stat = String::new();
+        if File::open(format!("/proc/self/task/{}/stat", tid))
+            .and_then(|mut file| file.read_to_string(&mut stat))
+            .is_err()
+        {
+            return;
+        }
+        let times = match parse_thread_stat(&stat) {
+            Some(times) => times,
+            None => return,
+        };
+        let now = Instant::now();
+
+        if let Some((last_times, last_instant)) = self.last {
+            let user_us = ticks_to_us(times.user.saturating_sub(last_times.user));
+            let sys_us = ticks_to_us(times.sys.saturating_sub(last_times.sys));
+            self.metrics.user_time_us.add(user_us as usize);
+            self.metrics.sys_time_us.add(sys_us as usize);
+            self.metrics
+                .guest_time_us
+                .add(ticks_to_us(times.guest.saturating_sub(last_times.guest)) as usize);
+
+            let elapsed = now.duration_since(last_instant);
+            let elapsed_us = elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_micros());
+            if elapsed_us > 0 {
+                // The times are only accurate to a clock tick, so the percentage is capped.
+                let run_pct = ((user_us + sys_us) * 100 / elapsed_us).min(100);
+                self.metrics.run_pct.set(run_pct as usize);
+            }
+        }
+        self.last = Some((times, now));
+    }
+}
+
+fn ticks_to_us(ticks: u64) -> u64 {
+    // This is safe because sysconf only reads a system setting.
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_sec <= 0 {
+        return 0;
+    }
+    ticks * 1_000_000 / ticks_per_sec as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_thread_stat() {
+        let mut stat = String::from("1234 (fc_vcpu 0) S");
+        for field in 4..53 {
+            stat.push_str(&format!(" {}", field));
+        }
+        assert_eq!(
+            parse_thread_stat(&stat),
+            Some(ThreadTimes {
+                user: 14,
+                sys: 15,
+                guest: 43,
+            })
+        );
+
+        // Parentheses in the command name don't shift the fields.
+        let stat = stat.replace("(fc_vcpu 0)", "(a) (b))");
+        assert_eq!(parse_thread_stat(&stat).unwrap().guest, 43);
+
+        assert_eq!(parse_thread_stat("1234 (fc_vcpu0) S 1 2 3"), None);
+        assert_eq!(parse_thread_stat("garbage"), None);
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut sampler = VcpuTimeSampler::new(200);
+        // The thread didn't start yet.
+        sampler.sample();
+        assert!(sampler.last.is_none());
+
+        sampler.tid().store(gettid(), Ordering::Release);
+        sampler.sample();
+        assert!(sampler.last.is_some());
+        sampler.sample();
+        assert!(sampler.metrics.run_pct.get() <= 100);
+    }
+}
@@ -0,0 +1,378 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::result;
+
+use kvm::CpuId;
+use serde::de::{Deserialize, Deserializer, Error as DeserializeError};
+use x86_64::regs::MsrOverride;
+
+use super::machine_config::VmConfigError;
+
+/// Bits of a register which are set to a given value, while the other bits are left unchanged.
+///
+/// A bitmap is written as `0b` followed by one character per bit, starting from the most
+/// significant one: `0` or `1` for the bits which are set, `x` for the bits which are left
+/// unchanged. Underscores can separate the groups of bits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterBitmap {
+    /// The bits which are set.
+    pub mask: u64,
+    /// The value of the bits which are set.
+    pub value: u64,
+}
+
+impl RegisterBitmap {
+    fn parse(bitmap: &str, bits: usize) -> result::Result<Self, String> {
+        if !bitmap.starts_with("0b") {
+            return Err(format!("The bitmap {:?} does not start with 0b.", bitmap));
+        }
+
+        let mut mask = 0u64;
+        let mut value = 0u64;
+        let mut len = 0;
+        for c in bitmap[2..].chars().filter(|c| *c != '_') {
+            mask <<= 1;
+            value <<= 1;
+            match c {
+                '0' => mask |= 1,
+                '1' => {
+                    mask |= 1;
+                    value |= 1;
+                }
+                'x' => (),
+                _ => return Err(format!("The bitmap {:?} contains {:?}.", bitmap, c)),
+            }
+            len += 1;
+        }
+        if len != bits {
+            return Err(format!(
+                "The bitmap {:?} has {} bits instead of {}.",
+                bitmap, len, bits
+            ));
+        }
+
+        Ok(RegisterBitmap { mask, value })
+    }
+
+    /// Returns `register` with the bits of the mask set to the ones of the value.
+    pub fn apply(&self, register: u64) -> u64 {
+        (register & !self.mask) | self.value
+    }
+}
+
+fn deserialize_hex_u32<'de, D>(deserializer: D) -> result::Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let number = String::deserialize(deserializer)?;
+    if !number.starts_with("0x") {
+        return Err(D::Error::custom(format!(
+            "The number {:?} does not start with 0x.",
+            number
+        )));
+    }
+    u32::from_str_radix(&number[2..], 16).map_err(|e| D::Error::custom(e.to_string()))
+}
+
+fn deserialize_cpuid_bitmap<'de, D>(
+    deserializer: D,
+) -> result::Result<Option<RegisterBitmap>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bitmap = String::deserialize(deserializer)?;
+    RegisterBitmap::parse(&bitmap, 32)
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+fn deserialize_msr_bitmap<'de, D>(deserializer: D) -> result::Result<RegisterBitmap, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bitmap = String::deserialize(deserializer)?;
+    RegisterBitmap::parse(&bitmap, 64).map_err(D::Error::custom)
+}
+
+// Returns the bits `bitmap` sets to 1 which are not set in the supported `register`.
+fn unsupported_cpuid_bits(register: u32, bitmap: Option<RegisterBitmap>) -> u32 {
+    bitmap.map_or(0, |bitmap| bitmap.value as u32 & !register)
+}
+
+fn apply_cpuid_bitmap(register: &mut u32, bitmap: Option<RegisterBitmap>) {
+    if let Some(bitmap) = bitmap {
+        *register = bitmap.apply(*register as u64) as u32;
+    }
+}
+
+/// The modification of the registers returned by a CPUID leaf.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CpuidLeafModifier {
+    /// The CPUID leaf, as a hexadecimal number.
+    #[serde(deserialize_with = "deserialize_hex_u32")]
+    pub leaf: u32,
+    /// The CPUID subleaf, as a hexadecimal number. Defaults to 0x0.
+    #[serde(default, deserialize_with = "deserialize_hex_u32")]
+    pub subleaf: u32,
+    /// The bits of EAX which are modified.
+    #[serde(default, deserialize_with = "deserialize_cpuid_bitmap")]
+    pub eax: Option<RegisterBitmap>,
+    /// The bits of EBX which are modified.
+    #[serde(default, deserialize_with = "deserialize_cpuid_bitmap")]
+    pub ebx: Option<RegisterBitmap>,
+    /// The bits of ECX which are modified.
+    #[serde(default, deserialize_with = "deserialize_cpuid_bitmap")]
+    pub ecx: Option<RegisterBitmap>,
+    /// The bits of EDX which are modified.
+    #[serde(default, deserialize_with = "deserialize_cpuid_bitmap")]
+    pub edx: Option<RegisterBitmap>,
+}
+
+/// The modification of a Model Specific Register (MSR).
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MsrModifier {
+    /// The index of the MSR, as a hexadecimal number.
+    #[serde(deserialize_with = "deserialize_hex_u32")]
+    pub addr: u32,
+    /// The bits of the MSR which are modified. The bits which are left unchanged keep the value
+    /// Firecracker sets up, or 0 for the MSRs Firecracker doesn't set up.
+    #[serde(deserialize_with = "deserialize_msr_bitmap")]
+    pub bitmap: RegisterBitmap,
+}
+
+/// A CPU template defined by the user, which is applied on top of the CPUID and MSRs set up by
+/// Firecracker, including the built-in CPU template of the machine configuration.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CustomCpuTemplate {
+    /// The modifications of the CPUID leaves.
+    #[serde(default)]
+    pub cpuid_modifiers: Vec<CpuidLeafModifier>,
+    /// The modifications of the MSRs.
+    #[serde(default)]
+    pub msr_modifiers: Vec<MsrModifier>,
+}
+
+impl CustomCpuTemplate {
+    /// Checks that the host supports all the CPUID leaves and the MSRs modified by the template,
+    /// and all the CPUID bits the template sets to 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `supported_cpuid` - The CPUID leaves supported by the host KVM.
+    /// * `supported_msrs` - The indices of the MSRs supported by the host KVM.
+    pub fn validate(
+        &self,
+        supported_cpuid: &mut CpuId,
+        supported_msrs: &[u32],
+    ) -> result::Result<(), VmConfigError> {
+        let entries = supported_cpuid.mut_entries_slice();
+        for modifier in &self.cpuid_modifiers {
+            let entry = entries
+                .iter()
+                .find(|entry| entry.function == modifier.leaf && entry.index == modifier.subleaf)
+                .ok_or(VmConfigError::UnsupportedCpuidLeaf(
+                    modifier.leaf,
+                    modifier.subleaf,
+                ))?;
+            for &(name, register, bitmap) in &[
+                ("eax", entry.eax, modifier.eax),
+                ("ebx", entry.ebx, modifier.ebx),
+                ("ecx", entry.ecx, modifier.ecx),
+                ("edx", entry.edx, modifier.edx),
+            ] {
+                let bits = unsupported_cpuid_bits(register, bitmap);
+                if bits != 0 {
+                    return Err(VmConfigError::UnsupportedCpuidBits(
+                        modifier.leaf,
+                        modifier.subleaf,
+                        name,
+                        bits,
+                    ));
+                }
+            }
+        }
+
+        for modifier in &self.msr_modifiers {
+            if !supported_msrs.contains(&modifier.addr) {
+                return Err(VmConfigError::UnsupportedMsr(modifier.addr));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the CPUID modifiers of the template to the entries of `cpuid`.
+    pub fn apply_to_cpuid(&self, cpuid: &mut CpuId) {
+        for entry in cpuid.mut_entries_slice().iter_mut() {
+            for modifier in &self.cpuid_modifiers {
+                if entry.function != modifier.leaf || entry.index != modifier.subleaf {
+                    continue;
+                }
+                apply_cpuid_bitmap(&mut entry.eax, modifier.eax);
+                apply_cpuid_bitmap(&mut entry.ebx, modifier.ebx);
+                apply_cpuid_bitmap(&mut entry.ecx, modifier.ecx);
+                apply_cpuid_bitmap(&mut entry.edx, modifier.edx);
+            }
+        }
+    }
+
+    /// Returns the MSR modifiers of the template, as overrides of the MSRs set up by Firecracker.
+    pub fn msr_overrides(&self) -> Vec<MsrOverride> {
+        self.msr_modifiers
+            .iter()
+            .map(|modifier| MsrOverride {
+                index: modifier.addr,
+                mask: modifier.bitmap.mask,
+                value: modifier.bitmap.value,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json;
+
+    #[test]
+    fn test_register_bitmap() {
+        let bitmap =
+            RegisterBitmap::parse("0bxxxx_xxxx_xxxx_xxxx_xxxx_xxxx_xxxx_x10x", 32).unwrap();
+        assert_eq!(bitmap.mask, 0b0110);
+        assert_eq!(bitmap.value, 0b0100);
+        assert_eq!(bitmap.apply(0xffff_ffff), 0xffff_fffd);
+        assert_eq!(bitmap.apply(0), 0b0100);
+
+        assert!(RegisterBitmap::parse("xxxx", 4).is_err());
+        assert!(RegisterBitmap::parse("0bxx2x", 4).is_err());
+        assert!(RegisterBitmap::parse("0bxx1", 4).is_err());
+        assert!(RegisterBitmap::parse("0bxx1xx", 4).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_template() {
+        let json = r#"{
+            "cpuid_modifiers": [
+                {
+                    "leaf": "0x1",
+                    "ecx": "0bxxxxxxxxxxxxxxxxxxxxxxxxxxx0xxxx"
+                },
+                {
+                    "leaf": "0x7",
+                    "subleaf": "0x0",
+                    "ebx": "0bxxxxxxxxxxxxxxxxxxxx0xxxxxx0xxxx"
+                }
+            ],
+            "msr_modifiers": [
+                {
+                    "addr": "0x10a",
+                    "bitmap": "0b0000000000000000000000000000000000000000000000000000000000000001"
+                }
+            ]
+        }"#;
+        let template: CustomCpuTemplate = serde_json::from_str(json).unwrap();
+        assert_eq!(template.cpuid_modifiers.len(), 2);
+        assert_eq!(template.cpuid_modifiers[0].leaf, 0x1);
+        assert_eq!(template.cpuid_modifiers[0].subleaf, 0x0);
+        assert!(template.cpuid_modifiers[0].eax.is_none());
+        assert_eq!(
+            template.cpuid_modifiers[0].ecx,
+            Some(RegisterBitmap {
+                mask: 1 << 4,
+                value: 0,
+            })
+        );
+        assert_eq!(
+            template.msr_overrides(),
+            vec![MsrOverride {
+                index: 0x10a,
+                mask: u64::max_value(),
+                value: 1,
+            }]
+        );
+
+        // The numbers are hexadecimal strings, and the bitmaps have the size of the registers.
+        assert!(serde_json::from_str::<CustomCpuTemplate>(
+            r#"{"cpuid_modifiers": [{"leaf": 1, "eax": "0b0"}]}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<CustomCpuTemplate>(
+            r#"{"cpuid_modifiers": [{"leaf": "0x1", "eax": "0b0"}]}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<CustomCpuTemplate>(
+            r#"{"msr_modifiers": [{"addr": "0x10a", "bitmap": "0bxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx"}]}"#
+        )
+        .is_err());
+        // An empty template is valid.
+        assert!(serde_json::from_str::<CustomCpuTemplate>("{}").is_ok());
+    }
+
+    #[test]
+    fn test_apply_and_validate() {
+        let template = CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidLeafModifier {
+                leaf: 0x7,
+                subleaf: 0x1,
+                eax: None,
+                ebx: Some(RegisterBitmap {
+                    mask: 0xf,
+                    value: 0x5,
+                }),
+                ecx: None,
+                edx: None,
+            }],
+            msr_modifiers: vec![MsrModifier {
+                addr: 0x10a,
+                bitmap: RegisterBitmap { mask: 1, value: 0 },
+            }],
+        };
+
+        let mut cpuid = CpuId::new(2);
+        {
+            let entries = cpuid.mut_entries_slice();
+            entries[0].function = 0x7;
+            entries[0].ebx = 0xff;
+            entries[1].function = 0x7;
+            entries[1].index = 0x1;
+            entries[1].ebx = 0xff;
+        }
+        template.apply_to_cpuid(&mut cpuid);
+        {
+            // Only the given subleaf is modified.
+            let entries = cpuid.mut_entries_slice();
+            assert_eq!(entries[0].ebx, 0xff);
+            assert_eq!(entries[1].ebx, 0xf5);
+        }
+
+        assert!(template.validate(&mut cpuid, &[0x10a]).is_ok());
+        assert_eq!(
+            template.validate(&mut cpuid, &[0x10b]),
+            Err(VmConfigError::UnsupportedMsr(0x10a))
+        );
+        let mut cpuid = CpuId::new(1);
+        assert_eq!(
+            template.validate(&mut cpuid, &[0x10a]),
+            Err(VmConfigError::UnsupportedCpuidLeaf(0x7, 0x1))
+        );
+
+        // The bits set to 1 must be supported, the ones set to 0 needn't be.
+        let mut cpuid = CpuId::new(1);
+        {
+            let entries = cpuid.mut_entries_slice();
+            entries[0].function = 0x7;
+            entries[0].index = 0x1;
+            entries[0].ebx = 0x1;
+        }
+        assert_eq!(
+            template.validate(&mut cpuid, &[0x10a]),
+            Err(VmConfigError::UnsupportedCpuidBits(0x7, 0x1, "ebx", 0x4))
+        );
+        cpuid.mut_entries_slice()[0].ebx = 0x4;
+        assert!(template.validate(&mut cpuid, &[0x10a]).is_ok());
+    }
+}
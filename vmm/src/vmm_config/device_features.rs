@@ -0,0 +1,64 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter, Result};
+
+/// The outcome of the virtio feature negotiation between a device and the guest driver.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeviceFeatures {
+    /// The virtio feature bits offered by the device.
+    pub offered_features: u64,
+    /// The virtio feature bits acknowledged by the guest driver.
+    pub acked_features: u64,
+    /// The queues of the device.
+    pub queues: Vec<QueueFeatures>,
+}
+
+/// The size of a virtio queue, as offered by the device and as selected by the guest driver.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct QueueFeatures {
+    /// The maximum number of elements the device supports.
+    pub max_size: u16,
+    /// The number of elements selected by the guest driver. It is 0 until the driver sets up
+    /// the queue.
+    pub size: u16,
+}
+
+/// Errors associated with querying the features of a device.
+#[derive(Debug, PartialEq)]
+pub enum DeviceFeaturesError {
+    /// No device with this ID is attached to the microVM.
+    DeviceNotFound(String),
+    /// The devices are only attached when the microVM starts.
+    MicroVMNotStarted,
+}
+
+impl Display for DeviceFeaturesError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use self::DeviceFeaturesError::*;
+        match *self {
+            DeviceNotFound(ref id) => write!(f, "No device with the ID {} is attached.", id),
+            MicroVMNotStarted => write!(
+                f,
+                "The device features are only available after the microVM starts."
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_device_features_error() {
+        assert_eq!(
+            DeviceFeaturesError::DeviceNotFound(String::from("rootfs")).to_string(),
+            "No device with the ID rootfs is attached."
+        );
+        assert_eq!(
+            DeviceFeaturesError::MicroVMNotStarted.to_string(),
+            "The device features are only available after the microVM starts."
+        );
+    }
+}
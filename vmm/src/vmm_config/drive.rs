@@ -4,6 +4,7 @@
 use std;
 use std::collections::VecDeque;
 use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
 use std::path::PathBuf;
 use std::result;
 
@@ -30,6 +31,8 @@ pub enum DriveError {
     UpdateNotAllowedPostBoot,
     /// A root block device was already added.
     RootBlockDeviceAlreadyAdded,
+    /// Cannot create the I/O trace file due to invalid permissions or path.
+    CannotOpenTraceFile,
 }
 
 impl Display for DriveError {
@@ -39,6 +42,9 @@ impl Display for DriveError {
             CannotOpenBlockDevice => {
                 write!(f, "Cannot open block device. Invalid permission/path.")
             }
+            CannotOpenTraceFile => {
+                write!(f, "Cannot create I/O trace file. Invalid permission/path.")
+            }
             InvalidBlockDeviceID => write!(f, "Invalid block device ID!"),
             InvalidBlockDevicePath => write!(f, "Invalid block device path!"),
             BlockDevicePathAlreadyExists => write!(
@@ -94,6 +100,28 @@ impl BlockDeviceConfig {
     }
 }
 
+/// The configuration of an I/O trace, which records every request of the guest to a block device
+/// as a JSON object, one per line, with the type, the offset, the length, the latency in
+/// microseconds and the virtio status of the request.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BlockDeviceTraceConfig {
+    /// Path of the trace file. An existing file is truncated.
+    pub path_on_host: PathBuf,
+}
+
+impl BlockDeviceTraceConfig {
+    /// Creates the trace file.
+    pub fn open(&self) -> Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path_on_host)
+            .map_err(|_| DriveError::CannotOpenTraceFile)
+    }
+}
+
 /// Wrapper for the collection that holds all the Block Devices Configs
 pub struct BlockDeviceConfigs {
     /// A list of `BlockDeviceConfig` objects.
@@ -575,4 +603,21 @@ mod tests {
             .is_ok());
         assert!(block_devices_configs.has_partuuid_root);
     }
+
+    #[test]
+    fn test_trace_config() {
+        let trace_file = NamedTempFile::new().unwrap();
+        let trace_config = BlockDeviceTraceConfig {
+            path_on_host: trace_file.path().to_path_buf(),
+        };
+        assert!(trace_config.open().is_ok());
+
+        let trace_config = BlockDeviceTraceConfig {
+            path_on_host: PathBuf::from("/invalid/path/trace.jsonl"),
+        };
+        assert_eq!(
+            trace_config.open().unwrap_err(),
+            DriveError::CannotOpenTraceFile
+        );
+    }
 }
@@ -0,0 +1,47 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter, Result};
+
+/// The kinds of failures of the guest reported to the API and to the embedding applications.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum GuestEventKind {
+    /// The guest kernel panicked, as reported through the pvpanic device.
+    Panic,
+    /// The guest kernel panicked and loaded a crash kernel to handle it, as reported through the
+    /// pvpanic device.
+    CrashLoaded,
+    /// A vCPU triple faulted and was shut down.
+    TripleFault,
+}
+
+impl Display for GuestEventKind {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            GuestEventKind::Panic => write!(f, "Panic"),
+            GuestEventKind::CrashLoaded => write!(f, "CrashLoaded"),
+            GuestEventKind::TripleFault => write!(f, "TripleFault"),
+        }
+    }
+}
+
+/// A failure of the guest, with the time it was detected at.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GuestEvent {
+    /// What happened to the guest.
+    pub kind: GuestEventKind,
+    /// The wall-clock time of the event, in microseconds since the Unix epoch.
+    pub timestamp_us: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_guest_event_kind() {
+        assert_eq!(GuestEventKind::Panic.to_string(), "Panic");
+        assert_eq!(GuestEventKind::CrashLoaded.to_string(), "CrashLoaded");
+        assert_eq!(GuestEventKind::TripleFault.to_string(), "TripleFault");
+    }
+}
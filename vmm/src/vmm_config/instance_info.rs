@@ -100,6 +100,9 @@ pub enum StartMicrovmError {
     VcpusNotConfigured,
     /// Cannot spawn a new vCPU thread.
     VcpuSpawn(std::io::Error),
+    #[cfg(feature = "vsock")]
+    /// Cannot listen on the host socket of a vsock port forwarding rule.
+    VsockPortForward(std::io::Error),
 }
 
 impl Display for StartMicrovmError {
@@ -236,6 +239,13 @@ impl Display for StartMicrovmError {
 
                 write!(f, "Cannot spawn vCPU thread. {}", err_msg)
             }
+            #[cfg(feature = "vsock")]
+            VsockPortForward(ref err) => {
+                let mut err_msg = format!("{:?}", err);
+                err_msg = err_msg.replace("\"", "");
+
+                write!(f, "Cannot forward vsock port. {}", err_msg)
+            }
         }
     }
 }
@@ -58,6 +58,8 @@ fn default_log_options() -> Value {
 pub enum LoggerConfigError {
     /// Cannot initialize the logger due to bad user input.
     InitializationFailure(String),
+    /// Cannot reset the metrics of a group which doesn't exist.
+    UnknownMetricsGroup(String),
 }
 
 impl Display for LoggerConfigError {
@@ -65,6 +67,7 @@ impl Display for LoggerConfigError {
         use self::LoggerConfigError::*;
         match *self {
             InitializationFailure(ref err_msg) => write!(f, "{}", err_msg),
+            UnknownMetricsGroup(ref group) => write!(f, "Unknown metrics group: {}", group),
         }
     }
 }
@@ -3,16 +3,34 @@
 
 use std::fmt::{Display, Formatter, Result};
 
+use sys_util;
+use x86_64;
+
 /// Errors associated with configuring the microVM.
 #[derive(Debug, PartialEq)]
 pub enum VmConfigError {
     /// The vcpu count is invalid. When hyperthreading is enabled, the `cpu_count` must be either
     /// 1 or an even number.
     InvalidVcpuCount,
+    /// The vcpu count is higher than the number of vCPUs supported by Firecracker and the host.
+    TooManyVcpus(u8),
     /// The memory size is invalid. The memory can only be an unsigned integer.
     InvalidMemorySize,
+    /// The size of the MMIO gap is out of the supported range.
+    InvalidMmioGapSize,
+    /// The start of the device MMIO region isn't page aligned or isn't in the MMIO gap.
+    InvalidMmioBase,
     /// Cannot update the configuration of the microvm post boot.
     UpdateNotAllowedPostBoot,
+    /// Cannot query the CPUID leaves or the MSRs supported by the host.
+    HostCpuQuery(sys_util::Error),
+    /// The host doesn't support the CPUID leaf and subleaf modified by the CPU template.
+    UnsupportedCpuidLeaf(u32, u32),
+    /// The host doesn't support the bits of the register of the CPUID leaf and subleaf which are
+    /// set by the CPU template.
+    UnsupportedCpuidBits(u32, u32, &'static str, u32),
+    /// The host doesn't support the MSR modified by the CPU template.
+    UnsupportedMsr(u32),
 }
 
 impl Display for VmConfigError {
@@ -24,10 +42,48 @@ impl Display for VmConfigError {
                 "The vCPU number is invalid! The vCPU number can only \
                  be 1 or an even number when hyperthreading is enabled.",
             ),
+            TooManyVcpus(max) => write!(
+                f,
+                "The vCPU number is invalid! At most {} vCPUs are supported.",
+                max
+            ),
             InvalidMemorySize => write!(f, "The memory size (MiB) is invalid.",),
+            InvalidMmioGapSize => write!(
+                f,
+                "The MMIO gap size (MiB) is invalid! It can only be between {} and {}.",
+                x86_64::MIN_32BIT_GAP_SIZE >> 20,
+                x86_64::MAX_32BIT_GAP_SIZE >> 20
+            ),
+            InvalidMmioBase => write!(
+                f,
+                "The MMIO base is invalid! It has to be page aligned, inside the MMIO gap and \
+                 below the IOAPIC.",
+            ),
             UpdateNotAllowedPostBoot => {
                 write!(f, "The update operation is not allowed after boot.")
             }
+            HostCpuQuery(ref e) => write!(
+                f,
+                "Cannot query the CPUID leaves and the MSRs supported by the host: {:?}",
+                e
+            ),
+            UnsupportedCpuidLeaf(leaf, subleaf) => write!(
+                f,
+                "The CPUID leaf {:#x}, subleaf {:#x}, of the CPU template is not supported by \
+                 the host.",
+                leaf, subleaf
+            ),
+            UnsupportedCpuidBits(leaf, subleaf, register, bits) => write!(
+                f,
+                "The bits {:#x} of {} in the CPUID leaf {:#x}, subleaf {:#x}, of the CPU \
+                 template are not supported by the host.",
+                bits, register, leaf, subleaf
+            ),
+            UnsupportedMsr(index) => write!(
+                f,
+                "The MSR {:#x} of the CPU template is not supported by the host.",
+                index
+            ),
         }
     }
 }
@@ -49,6 +105,39 @@ pub struct VmConfig {
     /// A CPU template that it is used to filter the CPU features exposed to the guest.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cpu_template: Option<CpuFeaturesTemplate>,
+    /// The transparent huge pages hint given to the host kernel for the guest memory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thp_policy: Option<ThpPolicy>,
+    /// Excludes the guest memory from the address space of the processes forked by Firecracker.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dontfork: Option<bool>,
+    /// Allocates all the guest memory when the microVM is built instead of on first access.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefault: Option<bool>,
+    /// Attaches a watchdog device, and sets what happens when the guest stops pinging it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watchdog_action: Option<WatchdogAction>,
+    /// Exposes the performance monitoring counters of the host to the guest, for profilers such
+    /// as `perf`. A guest using the counters can't be moved to a host with a different PMU.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vpmu: Option<bool>,
+    /// Lets the host kernel merge the identical pages of the guest memory with the ones of other
+    /// processes, such as other microVMs, through Kernel Samepage Merging (KSM).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mergeable: Option<bool>,
+    /// The size in MiB of the gap at the end of the 32bit address space, where the devices are
+    /// mapped instead of the guest memory. The memory which doesn't fit below it is moved above
+    /// 4GiB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mmio_gap_size_mib: Option<usize>,
+    /// The guest physical address the MMIO devices are mapped from. It defaults to the start of
+    /// the MMIO gap.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mmio_base: Option<u64>,
+    /// Also describes the vCPUs and the legacy devices to the guest in ACPI tables. The guest is
+    /// only described by the MP table otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acpi: Option<bool>,
 }
 
 impl Default for VmConfig {
@@ -58,6 +147,15 @@ impl Default for VmConfig {
             mem_size_mib: Some(128),
             ht_enabled: Some(false),
             cpu_template: None,
+            thp_policy: None,
+            dontfork: None,
+            prefault: None,
+            watchdog_action: None,
+            vpmu: None,
+            mergeable: None,
+            mmio_gap_size_mib: None,
+            mmio_base: None,
+            acpi: None,
         }
     }
 }
@@ -81,6 +179,43 @@ impl Display for CpuFeaturesTemplate {
     }
 }
 
+/// Transparent huge pages hints that can be given for the guest memory. When none is set, the
+/// host's system-wide transparent huge pages setting applies.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ThpPolicy {
+    /// Back the guest memory with huge pages whenever possible (`MADV_HUGEPAGE`).
+    Hugepage,
+    /// Never back the guest memory with huge pages (`MADV_NOHUGEPAGE`).
+    NoHugepage,
+}
+
+impl Display for ThpPolicy {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            ThpPolicy::Hugepage => write!(f, "Hugepage"),
+            ThpPolicy::NoHugepage => write!(f, "NoHugepage"),
+        }
+    }
+}
+
+/// Actions taken when the guest stops pinging its watchdog device.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum WatchdogAction {
+    /// Log the expiry and leave the guest running.
+    Log,
+    /// Treat the expiry like a reset requested by the guest, which stops the microVM.
+    Reset,
+}
+
+impl Display for WatchdogAction {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            WatchdogAction::Log => write!(f, "Log"),
+            WatchdogAction::Reset => write!(f, "Reset"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -91,19 +226,64 @@ mod tests {
         assert_eq!(CpuFeaturesTemplate::T2.to_string(), "T2".to_string());
     }
 
+    #[test]
+    fn test_display_thp_policy() {
+        assert_eq!(ThpPolicy::Hugepage.to_string(), "Hugepage".to_string());
+        assert_eq!(ThpPolicy::NoHugepage.to_string(), "NoHugepage".to_string());
+    }
+
+    #[test]
+    fn test_display_watchdog_action() {
+        assert_eq!(WatchdogAction::Log.to_string(), "Log".to_string());
+        assert_eq!(WatchdogAction::Reset.to_string(), "Reset".to_string());
+    }
+
     #[test]
     fn test_display_vm_config_error() {
         let expected_str = "The vCPU number is invalid! The vCPU number can only \
                             be 1 or an even number when hyperthreading is enabled.";
         assert_eq!(VmConfigError::InvalidVcpuCount.to_string(), expected_str);
 
+        let expected_str = "The vCPU number is invalid! At most 253 vCPUs are supported.";
+        assert_eq!(VmConfigError::TooManyVcpus(253).to_string(), expected_str);
+
         let expected_str = "The memory size (MiB) is invalid.";
         assert_eq!(VmConfigError::InvalidMemorySize.to_string(), expected_str);
 
+        let expected_str =
+            "The MMIO gap size (MiB) is invalid! It can only be between 64 and 3072.";
+        assert_eq!(VmConfigError::InvalidMmioGapSize.to_string(), expected_str);
+
+        let expected_str = "The MMIO base is invalid! It has to be page aligned, inside the MMIO \
+                            gap and below the IOAPIC.";
+        assert_eq!(VmConfigError::InvalidMmioBase.to_string(), expected_str);
+
         let expected_str = "The update operation is not allowed after boot.";
         assert_eq!(
             VmConfigError::UpdateNotAllowedPostBoot.to_string(),
             expected_str
         );
+
+        let expected_str =
+            "The CPUID leaf 0x80000001, subleaf 0x0, of the CPU template is not supported by \
+             the host.";
+        assert_eq!(
+            VmConfigError::UnsupportedCpuidLeaf(0x8000_0001, 0).to_string(),
+            expected_str
+        );
+
+        let expected_str =
+            "The bits 0x20 of ecx in the CPUID leaf 0x1, subleaf 0x0, of the CPU template are \
+             not supported by the host.";
+        assert_eq!(
+            VmConfigError::UnsupportedCpuidBits(0x1, 0, "ecx", 0x20).to_string(),
+            expected_str
+        );
+
+        let expected_str = "The MSR 0x10a of the CPU template is not supported by the host.";
+        assert_eq!(
+            VmConfigError::UnsupportedMsr(0x10a).to_string(),
+            expected_str
+        );
     }
 }
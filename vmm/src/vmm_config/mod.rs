@@ -3,8 +3,14 @@
 
 /// Wrapper for configuring the microVM boot source.
 pub mod boot_source;
+/// Wrapper for configuring the CPU templates defined by the user.
+pub mod cpu_template;
+/// Wrapper over the virtio features negotiated by the devices attached to the microVM.
+pub mod device_features;
 /// Wrapper for configuring the block devices.
 pub mod drive;
+/// Wrapper over the panics and crashes reported by the guest.
+pub mod guest_events;
 /// Wrapper over the microVM general information attached to the microVM.
 pub mod instance_info;
 /// Wrapper for configuring the logger.
@@ -13,6 +19,8 @@ pub mod logger;
 pub mod machine_config;
 /// Wrapper for configuring the network devices attached to the microVM.
 pub mod net;
+/// Wrapper over the input sent to the guest through the serial console.
+pub mod serial;
 #[cfg(feature = "vsock")]
 /// Wrapper for configuring the vsock devices attached to the microVM.
 pub mod vsock;
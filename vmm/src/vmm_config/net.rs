@@ -2,9 +2,12 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fmt::{Display, Formatter, Result};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::PathBuf;
 use std::result;
 
-use net_util::{MacAddr, Tap, TapError};
+use net_util::{open_pinned_program, MacAddr, PcapWriter, Tap, TapError, DEFAULT_SNAP_LEN};
 use rate_limiter::RateLimiter;
 
 /// This struct represents the strongly typed equivalent of the json body from net iface
@@ -22,6 +25,9 @@ pub struct NetworkInterfaceConfig {
     pub rx_rate_limiter: Option<RateLimiter>,
     /// Rate Limiter for transmitted packages.
     pub tx_rate_limiter: Option<RateLimiter>,
+    /// Path of an eBPF socket filter program pinned in a BPF filesystem, which filters the
+    /// packets sent by the host to the guest through the tap.
+    pub rx_ebpf_filter: Option<PathBuf>,
     #[serde(default = "default_allow_mmds_requests")]
     /// If this field is set, the device model will reply to HTTP GET
     /// requests sent to the MMDS address via this interface. In this case,
@@ -60,6 +66,49 @@ impl NetworkInterfaceConfig {
     }
 }
 
+/// The configuration of a packet capture, which mirrors the frames sent and received by a guest
+/// network interface to a pcap file.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PacketCaptureConfig {
+    /// Path of the pcap file. An existing file is truncated.
+    pub path_on_host: PathBuf,
+    /// The maximum number of bytes captured from every frame.
+    #[serde(default = "default_snap_len")]
+    pub snap_len: u32,
+    /// The maximum size of the pcap file. No more frames are captured once it is reached.
+    pub max_bytes: Option<u64>,
+}
+
+fn default_snap_len() -> u32 {
+    DEFAULT_SNAP_LEN
+}
+
+impl PacketCaptureConfig {
+    /// Creates the pcap file and returns a writer for the frames to capture.
+    pub fn open(&self) -> result::Result<PcapWriter<File>, NetworkInterfaceError> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path_on_host)
+            .and_then(|file| PcapWriter::new(file, self.snap_len, self.max_bytes))
+            .map_err(|e| NetworkInterfaceError::OpenPacketCapture(self.path_on_host.clone(), e))
+    }
+}
+
+/// The strongly typed equivalent of the json body of the PATCH requests on network interfaces,
+/// which update a running guest network interface.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkInterfaceUpdateConfig {
+    /// ID of the guest network interface.
+    pub iface_id: String,
+    /// The packet capture to start on the interface, replacing the running one. When it is not
+    /// set, the running capture is stopped.
+    pub capture: Option<PacketCaptureConfig>,
+}
+
 /// Errors associated with `NetworkInterfaceConfig`.
 #[derive(Debug)]
 pub enum NetworkInterfaceError {
@@ -69,8 +118,20 @@ pub enum NetworkInterfaceError {
     HostDeviceNameInUse(String),
     /// Cannot open/create tap device.
     OpenTap(TapError),
+    /// Cannot open the eBPF program pinned at the given path.
+    OpenEbpfFilter(PathBuf, io::Error),
+    /// Cannot attach the eBPF program to the tap device.
+    AttachEbpfFilter(TapError),
     /// The update is not allowed after booting the microvm.
     UpdateNotAllowedPostBoot,
+    /// The update is only allowed after booting the microvm.
+    UpdateNotAllowedPreBoot,
+    /// There is no network interface with the given ID.
+    InvalidIfaceId(String),
+    /// Cannot create the pcap file at the given path.
+    OpenPacketCapture(PathBuf, io::Error),
+    /// The device model of the network interface could not be updated.
+    DeviceUpdateFailed,
 }
 
 impl Display for NetworkInterfaceError {
@@ -100,9 +161,41 @@ impl Display for NetworkInterfaceError {
                     tap_err
                 )
             }
+            OpenEbpfFilter(ref path, ref e) => write!(
+                f,
+                "Cannot open the eBPF program pinned at {}. {}",
+                path.display(),
+                e
+            ),
+            AttachEbpfFilter(ref e) => {
+                // Same as for OpenTap, the quotes would result in an invalid json.
+                let tap_err = format!("{:?}", e).replace("\"", "");
+                write!(
+                    f,
+                    "Cannot attach the eBPF program to the TAP device. {}",
+                    tap_err
+                )
+            }
             UpdateNotAllowedPostBoot => {
                 write!(f, "The update operation is not allowed after boot.",)
             }
+            UpdateNotAllowedPreBoot => {
+                write!(f, "The update operation is not allowed before boot.")
+            }
+            InvalidIfaceId(ref iface_id) => {
+                write!(f, "Invalid network interface ID - {}.", iface_id)
+            }
+            OpenPacketCapture(ref path, ref e) => write!(
+                f,
+                "Cannot create the packet capture file {}. {}",
+                path.display(),
+                e
+            ),
+            DeviceUpdateFailed => write!(
+                f,
+                "The network interface could not be updated. The guest may not have activated \
+                 it yet."
+            ),
         }
     }
 }
@@ -193,15 +286,17 @@ impl NetworkInterfaceConfigs {
         // We are ignoring the tap field of the network interface we want to update. We are
         // manually setting this field to a newly created tap (corresponding to the host_dev_name)
         // or to the old tap device of the network interface we are trying to update.
-        updated_netif_config.tap =
-            if self.if_list[index].host_dev_name != updated_netif_config.host_dev_name {
-                Some(
-                    Tap::open_named(&updated_netif_config.host_dev_name.as_str())
-                        .map_err(NetworkInterfaceError::OpenTap)?,
-                )
-            } else {
-                self.if_list[index].tap.take()
-            };
+        if self.if_list[index].host_dev_name != updated_netif_config.host_dev_name {
+            let tap = Tap::open_named(&updated_netif_config.host_dev_name.as_str())
+                .map_err(NetworkInterfaceError::OpenTap)?;
+            set_ebpf_filter(&tap, &updated_netif_config)?;
+            updated_netif_config.tap = Some(tap);
+        } else {
+            if let Some(ref tap) = self.if_list[index].tap {
+                set_ebpf_filter(tap, &updated_netif_config)?;
+            }
+            updated_netif_config.tap = self.if_list[index].tap.take();
+        }
         self.if_list[index] = updated_netif_config;
 
         Ok(())
@@ -242,6 +337,7 @@ impl NetworkInterfaceConfigs {
         self.validate_create(&netif_config)?;
         let tap = Tap::open_named(netif_config.host_dev_name.as_str())
             .map_err(NetworkInterfaceError::OpenTap)?;
+        set_ebpf_filter(&tap, &netif_config)?;
         self.if_list.push(netif_config);
 
         let index = self.if_list.len() - 1;
@@ -250,12 +346,34 @@ impl NetworkInterfaceConfigs {
     }
 }
 
+// Attaches the eBPF filter of `netif_config` to `tap`, or detaches the current filter when the
+// configuration has none.
+fn set_ebpf_filter(
+    tap: &Tap,
+    netif_config: &NetworkInterfaceConfig,
+) -> result::Result<(), NetworkInterfaceError> {
+    let prog = match netif_config.rx_ebpf_filter {
+        Some(ref path) => Some(
+            open_pinned_program(path)
+                .map_err(|e| NetworkInterfaceError::OpenEbpfFilter(path.clone(), e))?,
+        ),
+        None => None,
+    };
+    // The tap keeps the program loaded after its file is closed.
+    tap.set_ebpf_filter(prog.as_ref())
+        .map_err(NetworkInterfaceError::AttachEbpfFilter)
+}
+
 #[cfg(test)]
 mod tests {
+    extern crate tempfile;
+
     use std::str;
 
+    use self::tempfile::NamedTempFile;
     use super::*;
     use net_util::MacAddr;
+    use serde_json;
 
     fn create_netif(id: &str, name: &str, mac: &str) -> NetworkInterfaceConfig {
         NetworkInterfaceConfig {
@@ -264,6 +382,7 @@ mod tests {
             guest_mac: Some(MacAddr::parse_str(mac).unwrap()),
             rx_rate_limiter: Some(RateLimiter::default()),
             tx_rate_limiter: Some(RateLimiter::default()),
+            rx_ebpf_filter: None,
             allow_mmds_requests: false,
             tap: None,
         }
@@ -279,6 +398,7 @@ mod tests {
                 guest_mac: self.guest_mac.clone(),
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                rx_ebpf_filter: self.rx_ebpf_filter.clone(),
                 allow_mmds_requests: self.allow_mmds_requests.clone(),
                 tap: None,
             }
@@ -391,5 +511,54 @@ mod tests {
                 .to_string(),
             expected_error
         );
+
+        // Error Case: Update netif_2 with an eBPF filter which is not pinned.
+        let mut netif_2 = create_netif(id_2, host_dev_name_2, guest_mac_2);
+        netif_2.rx_ebpf_filter = Some(PathBuf::from("/sys/fs/bpf/missing"));
+        match netif_configs.insert(netif_2) {
+            Err(NetworkInterfaceError::OpenEbpfFilter(ref path, _)) => {
+                assert_eq!(path, &PathBuf::from("/sys/fs/bpf/missing"))
+            }
+            _ => assert!(false),
+        }
+        // The tap of netif_2 was kept.
+        assert!(netif_configs.if_list[1].tap.is_some());
+    }
+
+    #[test]
+    fn test_packet_capture_config() {
+        let capture_file = NamedTempFile::new().unwrap();
+        let path = capture_file.path().to_path_buf();
+        let jstr = format!(r#"{{"path_on_host": "{}"}}"#, path.display());
+        let capture_config: PacketCaptureConfig = serde_json::from_str(&jstr).unwrap();
+        assert_eq!(
+            capture_config,
+            PacketCaptureConfig {
+                path_on_host: path.clone(),
+                snap_len: DEFAULT_SNAP_LEN,
+                max_bytes: None,
+            }
+        );
+
+        // Opening the capture truncates the file, and writes the pcap header to it.
+        assert!(capture_config.open().is_ok());
+        assert_eq!(path.metadata().unwrap().len(), 24);
+
+        let capture_config = PacketCaptureConfig {
+            path_on_host: PathBuf::from("/invalid/path/capture.pcap"),
+            snap_len: 128,
+            max_bytes: Some(4096),
+        };
+        match capture_config.open() {
+            Err(NetworkInterfaceError::OpenPacketCapture(ref path, _)) => {
+                assert_eq!(path, &PathBuf::from("/invalid/path/capture.pcap"))
+            }
+            _ => assert!(false),
+        }
+
+        // The capture is optional in the update of a network interface.
+        let update_config: NetworkInterfaceUpdateConfig =
+            serde_json::from_str(r#"{"iface_id": "id_1"}"#).unwrap();
+        assert_eq!(update_config.capture, None);
     }
 }
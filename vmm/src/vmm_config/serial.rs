@@ -0,0 +1,67 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter, Result};
+
+use sys_util;
+
+/// Input sent to the guest through the serial console.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SerialInput {
+    /// Raw bytes, as if they were typed on the console.
+    Bytes(Vec<u8>),
+    /// A magic SysRq key, sent as a break followed by the key.
+    SysRq(u8),
+}
+
+/// Errors associated with sending input to the serial console.
+#[derive(Debug, PartialEq)]
+pub enum SerialInputError {
+    /// The guest can only read the input after the microVM starts.
+    MicroVMNotStarted,
+    /// The guest didn't read enough of the pending input for the new input to fit.
+    BufferFull,
+    /// The input could not be queued on the serial console.
+    QueueInput(sys_util::Error),
+}
+
+impl Display for SerialInputError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use self::SerialInputError::*;
+        match *self {
+            MicroVMNotStarted => write!(
+                f,
+                "The serial input can only be sent after the microVM starts."
+            ),
+            BufferFull => write!(
+                f,
+                "The serial input buffer is full. The guest has to read the pending input first."
+            ),
+            QueueInput(ref e) => write!(f, "Cannot queue the serial input: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_serial_input_error() {
+        assert_eq!(
+            SerialInputError::MicroVMNotStarted.to_string(),
+            "The serial input can only be sent after the microVM starts."
+        );
+        assert_eq!(
+            SerialInputError::BufferFull.to_string(),
+            "The serial input buffer is full. The guest has to read the pending input first."
+        );
+        assert_eq!(
+            SerialInputError::QueueInput(sys_util::Error::new(0)).to_string(),
+            format!(
+                "Cannot queue the serial input: {:?}",
+                sys_util::Error::new(0)
+            )
+        );
+    }
+}
@@ -13,6 +13,20 @@ pub struct VsockDeviceConfig {
     pub id: String,
     /// A 32-bit Context Identifier (CID) used to identify the guest.
     pub guest_cid: u32,
+    /// Host Unix domain sockets whose connections are forwarded to vsock ports of the guest.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub port_forwards: Vec<VsockPortForward>,
+}
+
+/// A rule forwarding the connections made to a Unix domain socket on the host to a vsock port
+/// of the guest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VsockPortForward {
+    /// The vsock port the guest listens on.
+    pub guest_port: u32,
+    /// Path of the Unix domain socket created on the host.
+    pub uds_path: String,
 }
 
 /// Errors associated with `VsockDeviceConfig`.
@@ -20,6 +34,8 @@ pub struct VsockDeviceConfig {
 pub enum VsockError {
     /// The Context Identifier is already in use.
     GuestCIDAlreadyInUse(u32),
+    /// The same guest port is forwarded more than once.
+    PortAlreadyForwarded(u32),
     /// The update is not allowed after booting the microvm.
     UpdateNotAllowedPostBoot,
 }
@@ -31,6 +47,11 @@ impl Display for VsockError {
             GuestCIDAlreadyInUse(ref cid) => {
                 write!(f, "{}", format!("The guest CID {} is already in use.", cid))
             }
+            PortAlreadyForwarded(ref port) => write!(
+                f,
+                "{}",
+                format!("The guest port {} is forwarded more than once.", port)
+            ),
             UpdateNotAllowedPostBoot => {
                 write!(f, "The update operation is not allowed after boot.",)
             }
@@ -66,6 +87,14 @@ impl VsockDeviceConfigs {
         if self.contains_cid(cfg.guest_cid) {
             return Err(VsockError::GuestCIDAlreadyInUse(cfg.guest_cid));
         }
+        for (index, rule) in cfg.port_forwards.iter().enumerate() {
+            if cfg.port_forwards[..index]
+                .iter()
+                .any(|other| other.guest_port == rule.guest_port)
+            {
+                return Err(VsockError::PortAlreadyForwarded(rule.guest_port));
+            }
+        }
 
         match self
             .configs
@@ -84,3 +113,63 @@ impl VsockDeviceConfigs {
         self.configs.iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json;
+
+    #[test]
+    fn test_add_port_forwards() {
+        let mut configs = VsockDeviceConfigs::new();
+        let rule = VsockPortForward {
+            guest_port: 52,
+            uds_path: String::from("/tmp/agent.sock"),
+        };
+        let mut cfg = VsockDeviceConfig {
+            id: String::from("root"),
+            guest_cid: 3,
+            port_forwards: vec![rule.clone(), rule.clone()],
+        };
+        match configs.add(cfg.clone()) {
+            Err(VsockError::PortAlreadyForwarded(52)) => (),
+            _ => panic!("Expected a port already forwarded error."),
+        }
+
+        cfg.port_forwards[1].guest_port = 53;
+        assert!(configs.add(cfg).is_ok());
+        assert_eq!(configs.iter().next().unwrap().port_forwards.len(), 2);
+    }
+
+    #[test]
+    fn test_deserialize_port_forwards() {
+        let cfg: VsockDeviceConfig =
+            serde_json::from_str(r#"{"id": "root", "guest_cid": 3}"#).unwrap();
+        assert!(cfg.port_forwards.is_empty());
+
+        let cfg: VsockDeviceConfig = serde_json::from_str(
+            r#"{
+                "id": "root",
+                "guest_cid": 3,
+                "port_forwards": [{"guest_port": 52, "uds_path": "/tmp/agent.sock"}]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.port_forwards,
+            vec![VsockPortForward {
+                guest_port: 52,
+                uds_path: String::from("/tmp/agent.sock"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_display_vsock_error() {
+        assert_eq!(
+            VsockError::PortAlreadyForwarded(52).to_string(),
+            "The guest port 52 is forwarded more than once."
+        );
+    }
+}
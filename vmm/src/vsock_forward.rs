@@ -0,0 +1,510 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Forwarding of host Unix domain sockets to vsock ports of the guest.
+//!
+//! Every forwarding rule listens on a Unix domain socket on the host. Each connection accepted
+//! on it is relayed over a new vsock connection to the configured port of the guest, so host
+//! clients can reach guest agents without opening `AF_VSOCK` sockets themselves.
+//!
+//! All the connections of a rule are relayed by one thread, which waits for their sockets with
+//! epoll. The thread runs with its own seccomp filter, which also allows the vsock sockets, and
+//! it forwards at most `MAX_CONNECTIONS` connections at the same time.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Arc;
+use std::thread;
+
+use epoll;
+use libc;
+
+use default_syscalls;
+use logger::metrics::{SharedMetric, VsockPortMetrics};
+use logger::{Metric, METRICS};
+
+use vmm_config::vsock::VsockPortForward;
+
+// See /usr/include/linux/socket.h
+const AF_VSOCK: libc::c_int = 40;
+
+// The most connections forwarded at the same time for a rule. The ones accepted beyond it are
+// closed right away, so that the host clients can't exhaust the file descriptors of the process.
+const MAX_CONNECTIONS: usize = 64;
+// The number of bytes buffered for each direction of a connection.
+const BUF_LEN: usize = 4096;
+// The maximum number of events handled by a wait.
+const MAX_EVENTS: usize = 32;
+// The epoll data of the listening socket. The data of the sockets of a connection is its id
+// shifted left by one, with the lowest bit set for the vsock socket.
+const LISTENER_TOKEN: u64 = u64::max_value();
+
+// See /usr/include/linux/vm_sockets.h
+#[repr(C)]
+struct SockaddrVm {
+    svm_family: libc::sa_family_t,
+    svm_reserved1: libc::c_ushort,
+    svm_port: libc::c_uint,
+    svm_cid: libc::c_uint,
+    svm_zero: [libc::c_uchar; 4],
+}
+
+/// Starts listening on the socket of `rule` and relaying the accepted connections to the
+/// guest identified by `guest_cid`, on a thread filtered at `seccomp_level`.
+pub fn start(guest_cid: u32, rule: &VsockPortForward, seccomp_level: u32) -> io::Result<()> {
+    let listener = UnixListener::bind(&rule.uds_path)?;
+    listener.set_nonblocking(true)?;
+    let guest_port = rule.guest_port;
+    let mut forwarder = Forwarder::new(listener, guest_cid, guest_port)?;
+
+    thread::Builder::new()
+        .name(format!("fc_vsock_fwd_{}", guest_port))
+        .spawn(move || {
+            // The filter is loaded before the first connection is accepted.
+            // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping
+            // filters altogether is the desired behaviour.
+            if let Err(e) = default_syscalls::set_vsock_forward_seccomp_level(seccomp_level) {
+                panic!(
+                    "Failed to set the requested seccomp filters on the forwarding thread of \
+                     vsock port {}: Error: {:?}",
+                    guest_port, e
+                );
+            }
+            if let Err(e) = forwarder.run() {
+                error!("Stopped forwarding to vsock port {}: {}", guest_port, e);
+            }
+        })?;
+    Ok(())
+}
+
+// Relays the connections accepted on the socket of a rule to a vsock port of the guest.
+struct Forwarder {
+    listener: UnixListener,
+    guest_cid: u32,
+    guest_port: u32,
+    port_metrics: Arc<VsockPortMetrics>,
+    epoll_fd: RawFd,
+    connections: HashMap<u64, Connection>,
+    next_id: u64,
+}
+
+impl Forwarder {
+    fn new(listener: UnixListener, guest_cid: u32, guest_port: u32) -> io::Result<Self> {
+        let epoll_fd = epoll::create(true)?;
+        // The epoll file descriptor is closed by `drop` from now on.
+        let forwarder = Forwarder {
+            listener,
+            guest_cid,
+            guest_port,
+            port_metrics: METRICS.vsock.ports.register(guest_port),
+            epoll_fd,
+            connections: HashMap::new(),
+            next_id: 0,
+        };
+        forwarder.register(
+            forwarder.listener.as_raw_fd(),
+            LISTENER_TOKEN,
+            epoll::Events::EPOLLIN,
+        )?;
+        Ok(forwarder)
+    }
+
+    fn register(&self, fd: RawFd, token: u64, events: epoll::Events) -> io::Result<()> {
+        epoll::ctl(
+            self.epoll_fd,
+            epoll::ControlOptions::EPOLL_CTL_ADD,
+            fd,
+            epoll::Event::new(events, token),
+        )
+    }
+
+    // Serves the listening socket and the connections, until waiting for them fails.
+    fn run(&mut self) -> io::Result<()> {
+        let mut events = vec![epoll::Event::new(epoll::Events::empty(), 0); MAX_EVENTS];
+        loop {
+            let num_events = match epoll::wait(self.epoll_fd, -1, &mut events[..]) {
+                Ok(num_events) => num_events,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            for event in &events[..num_events] {
+                if event.data == LISTENER_TOKEN {
+                    self.accept();
+                } else {
+                    self.relay(event.data >> 1, event.data & 1 != 0);
+                }
+            }
+        }
+    }
+
+    fn reject(&self) {
+        METRICS.vsock.conn_rejected_count.inc();
+        self.port_metrics.conn_rejected_count.inc();
+    }
+
+    fn accept(&mut self) {
+        loop {
+            let client = match self.listener.accept() {
+                Ok((client, _)) => client,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!(
+                        "Cannot accept a connection for vsock port {}: {}",
+                        self.guest_port, e
+                    );
+                    return;
+                }
+            };
+            // The connections which are not forwarded are closed by dropping them.
+            if self.connections.len() >= MAX_CONNECTIONS {
+                warn!(
+                    "Too many connections forwarded to vsock port {}, closing a new one.",
+                    self.guest_port
+                );
+                self.reject();
+                continue;
+            }
+            if let Err(e) = self.connect(client) {
+                warn!("Cannot forward to vsock port {}: {}", self.guest_port, e);
+                self.reject();
+            }
+        }
+    }
+
+    fn connect(&mut self, client: UnixStream) -> io::Result<()> {
+        client.set_nonblocking(true)?;
+        let guest = connect(self.guest_cid, self.guest_port)?;
+
+        // The sockets are edge triggered, so they are never waited for again before they would
+        // block, and their interests never change.
+        let id = self.next_id;
+        self.next_id += 1;
+        let events = epoll::Events::EPOLLIN
+            | epoll::Events::EPOLLOUT
+            | epoll::Events::EPOLLRDHUP
+            | epoll::Events::EPOLLET;
+        self.register(client.as_raw_fd(), id << 1, events)?;
+        self.register(guest.as_raw_fd(), id << 1 | 1, events)?;
+        self.connections.insert(id, Connection::new(client, guest));
+        Ok(())
+    }
+
+    fn relay(&mut self, id: u64, guest_ready: bool) {
+        let result = match self.connections.get_mut(&id) {
+            Some(connection) => {
+                let was_connected = connection.connected;
+                let result = connection.relay(guest_ready, &self.port_metrics);
+                if !was_connected && connection.connected {
+                    METRICS.vsock.conn_established_count.inc();
+                    self.port_metrics.conn_established_count.inc();
+                }
+                result.map_err(|e| (e, was_connected || connection.connected))
+            }
+            // The connection was closed while handling earlier events.
+            None => return,
+        };
+        match result {
+            Ok(false) => return,
+            Ok(true) => (),
+            Err((e, connected)) => {
+                if !connected {
+                    self.reject();
+                }
+                warn!("Cannot forward to vsock port {}: {}", self.guest_port, e);
+            }
+        }
+        // Closing the sockets also removes them from the epoll set.
+        self.connections.remove(&id);
+    }
+}
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        let _ = epoll::close(self.epoll_fd);
+    }
+}
+
+// A host connection, and the vsock connection it is relayed over.
+struct Connection {
+    client: UnixStream,
+    guest: File,
+    // Whether the vsock connection is established, since it's connected without blocking.
+    connected: bool,
+    to_guest: Pipe,
+    to_client: Pipe,
+}
+
+impl Connection {
+    fn new(client: UnixStream, guest: File) -> Self {
+        Connection {
+            client,
+            guest,
+            connected: false,
+            to_guest: Pipe::new(),
+            to_client: Pipe::new(),
+        }
+    }
+
+    // Copies the bytes of both directions until the sockets would block. `guest_ready` is set
+    // when the vsock socket is the one which is ready.
+    //
+    // Returns `true` once both directions are done, and the connection can be closed.
+    fn relay(&mut self, guest_ready: bool, port_metrics: &VsockPortMetrics) -> io::Result<bool> {
+        if !self.connected {
+            // The vsock socket is ready once the connection is established, or once it failed.
+            if !guest_ready {
+                return Ok(false);
+            }
+            if let Some(e) = socket_error(&self.guest)? {
+                return Err(e);
+            }
+            self.connected = true;
+        }
+
+        // Each direction is copied until its sender stops writing, then the stop is propagated to
+        // the other end so that half closed connections keep working.
+        self.to_guest.pump(
+            &mut self.client,
+            &mut self.guest,
+            &[&METRICS.vsock.tx_bytes_count, &port_metrics.tx_bytes_count],
+        )?;
+        if self.to_guest.is_drained() && !self.to_guest.shut {
+            // This is safe because `guest` owns a valid socket.
+            unsafe { libc::shutdown(self.guest.as_raw_fd(), libc::SHUT_WR) };
+            self.to_guest.shut = true;
+        }
+        self.to_client.pump(
+            &mut self.guest,
+            &mut self.client,
+            &[&METRICS.vsock.rx_bytes_count, &port_metrics.rx_bytes_count],
+        )?;
+        if self.to_client.is_drained() && !self.to_client.shut {
+            let _ = self.client.shutdown(Shutdown::Write);
+            self.to_client.shut = true;
+        }
+        Ok(self.to_guest.shut && self.to_client.shut)
+    }
+}
+
+// The bytes read from one end of a connection, until they are written to the other end.
+struct Pipe {
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    // Whether the reading end stopped writing.
+    eof: bool,
+    // Whether the stop was propagated to the writing end.
+    shut: bool,
+}
+
+impl Pipe {
+    fn new() -> Self {
+        Pipe {
+            buf: vec![0; BUF_LEN],
+            start: 0,
+            end: 0,
+            eof: false,
+            shut: false,
+        }
+    }
+
+    // Same as `io::copy`, but returns once `reader` or `writer` would block, and accounts for the
+    // bytes in `byte_counts` as they are written, so that the metrics of long lived connections
+    // are kept up to date.
+    fn pump(
+        &mut self,
+        reader: &mut Read,
+        writer: &mut Write,
+        byte_counts: &[&SharedMetric],
+    ) -> io::Result<()> {
+        loop {
+            if self.start < self.end {
+                match writer.write(&self.buf[self.start..self.end]) {
+                    Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                    Ok(len) => {
+                        self.start += len;
+                        for count in byte_counts {
+                            count.add(len);
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(e) => return Err(e),
+                }
+            } else if self.eof {
+                return Ok(());
+            } else {
+                match reader.read(&mut self.buf) {
+                    Ok(0) => self.eof = true,
+                    Ok(len) => {
+                        self.start = 0;
+                        self.end = len;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    // Returns `true` once the reading end stopped writing, and all its bytes were written.
+    fn is_drained(&self) -> bool {
+        self.eof && self.start == self.end
+    }
+}
+
+// Starts connecting to `guest_port` without blocking. The connection is established once the
+// returned socket is writable.
+fn connect(guest_cid: u32, guest_port: u32) -> io::Result<File> {
+    // This is safe because we check the return value.
+    let fd = unsafe {
+        libc::socket(
+            AF_VSOCK,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // This is safe because we just created `fd` and nothing else owns it. It gets closed when
+    // the returned `File` is dropped.
+    let stream = unsafe { File::from_raw_fd(fd) };
+
+    let addr = SockaddrVm {
+        svm_family: AF_VSOCK as libc::sa_family_t,
+        svm_reserved1: 0,
+        svm_port: guest_port,
+        svm_cid: guest_cid,
+        svm_zero: [0; 4],
+    };
+    // This is safe because `addr` is a valid `sockaddr_vm` and we pass its exact size.
+    let ret = unsafe {
+        libc::connect(
+            stream.as_raw_fd(),
+            &addr as *const SockaddrVm as *const libc::sockaddr,
+            mem::size_of::<SockaddrVm>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(e);
+        }
+    }
+    Ok(stream)
+}
+
+// Returns the error of the connection of `socket`, such as the one which ended connecting it.
+fn socket_error(socket: &File) -> io::Result<Option<io::Error>> {
+    let mut error: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // This is safe because `error` and `len` are valid for writes of the sizes we pass, and we
+    // check the return value.
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ERROR,
+            &mut error as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(match error {
+        0 => None,
+        error => Some(io::Error::from_raw_os_error(error)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use seccomp::SECCOMP_LEVEL_NONE;
+
+    #[test]
+    fn test_start() {
+        let uds_path = env::temp_dir()
+            .join(format!("fc_vsock_fwd_test_{}.sock", process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let rule = VsockPortForward {
+            guest_port: 52,
+            uds_path: uds_path.clone(),
+        };
+        assert!(start(3, &rule, SECCOMP_LEVEL_NONE).is_ok());
+        assert!(UnixStream::connect(&uds_path).is_ok());
+
+        // The socket path is already taken.
+        assert!(start(3, &rule, SECCOMP_LEVEL_NONE).is_err());
+        fs::remove_file(&uds_path).unwrap();
+    }
+
+    // Accepts `capacity` bytes, then blocks.
+    struct SlowWriter {
+        buf: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.capacity - self.buf.len());
+            if len == 0 {
+                return Err(io::Error::from(io::ErrorKind::WouldBlock));
+            }
+            self.buf.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pump() {
+        let total = SharedMetric::default();
+        let port = SharedMetric::default();
+        let mut pipe = Pipe::new();
+        let mut reader: &[u8] = &[0xaa; 5000];
+        let mut writer = Vec::new();
+        assert!(pipe
+            .pump(&mut reader, &mut writer, &[&total, &port])
+            .is_ok());
+        assert_eq!(writer, vec![0xaa; 5000]);
+        assert_eq!(total.count(), 5000);
+        assert_eq!(port.count(), 5000);
+        assert!(pipe.is_drained());
+
+        // The bytes which the writer doesn't accept yet are kept until it is ready again.
+        let mut pipe = Pipe::new();
+        let mut reader: &[u8] = &[0xbb; 5000];
+        let mut writer = SlowWriter {
+            buf: Vec::new(),
+            capacity: 3000,
+        };
+        assert!(pipe.pump(&mut reader, &mut writer, &[&total]).is_ok());
+        assert_eq!(writer.buf.len(), 3000);
+        assert_eq!(total.count(), 8000);
+        assert!(!pipe.is_drained());
+        writer.capacity = 5000;
+        assert!(pipe.pump(&mut reader, &mut writer, &[&total]).is_ok());
+        assert_eq!(writer.buf, vec![0xbb; 5000]);
+        assert!(pipe.is_drained());
+    }
+}
@@ -19,6 +19,7 @@ use logger::{LogOption, LOGGER};
 use logger::{Metric, METRICS};
 use memory_model::{GuestAddress, GuestMemory, GuestMemoryError};
 use sys_util::EventFd;
+use vmm_config::cpu_template::CustomCpuTemplate;
 use vmm_config::machine_config::{CpuFeaturesTemplate, VmConfig};
 use x86_64::{interrupts, regs};
 
@@ -177,10 +178,13 @@ impl Vcpu {
     /// # Arguments
     ///
     /// * `kernel_load_offset` - Offset from `guest_mem` at which the kernel starts.
+    /// * `cpu_template` - The CPU template defined by the user, if any, applied after the built-in
+    ///                    one.
     /// nr cpus is required for checking populating the kvm_cpuid2 entry for ebx and edx registers
     pub fn configure(
         &mut self,
         machine_config: &VmConfig,
+        cpu_template: Option<&CustomCpuTemplate>,
         kernel_start_addr: GuestAddress,
         vm: &Vm,
     ) -> Result<()> {
@@ -191,6 +195,7 @@ impl Vcpu {
                 .vcpu_count
                 .ok_or(Error::VcpuCountNotInitialized)?,
             machine_config.ht_enabled.ok_or(Error::HTNotInitialized)?,
+            machine_config.vpmu.unwrap_or(false),
             &mut self.cpuid,
         ) {
             // For the moment, we do not have a showstopper error returned by the `filter_cpuid`.
@@ -211,12 +216,19 @@ impl Vcpu {
             },
             None => (),
         }
+        let msr_overrides = match cpu_template {
+            Some(template) => {
+                template.apply_to_cpuid(&mut self.cpuid);
+                template.msr_overrides()
+            }
+            None => Vec::new(),
+        };
 
         self.fd
             .set_cpuid2(&self.cpuid)
             .map_err(Error::SetSupportedCpusFailed)?;
 
-        regs::setup_msrs(&self.fd).map_err(Error::MSRSConfiguration)?;
+        regs::setup_msrs(&self.fd, &msr_overrides).map_err(Error::MSRSConfiguration)?;
         // Safe to unwrap because this method is called after the VM is configured
         let vm_memory = vm
             .get_memory()
@@ -252,6 +264,8 @@ mod tests {
 
     use std::os::unix::io::AsRawFd;
 
+    use vmm_config::cpu_template::{CpuidLeafModifier, MsrModifier, RegisterBitmap};
+
     #[test]
     fn create_vm() {
         let kvm_fd = Kvm::new().unwrap();
@@ -298,17 +312,46 @@ mod tests {
 
         let mut vcpu = Vcpu::new(1, &vm).unwrap();
         let vm_config = VmConfig::default();
-        assert!(vcpu.configure(&vm_config, GuestAddress(0), &vm).is_ok());
+        assert!(vcpu
+            .configure(&vm_config, None, GuestAddress(0), &vm)
+            .is_ok());
 
         // Test configure while using the T2 template.
         let mut vm_config = VmConfig::default();
         vm_config.cpu_template = Some(CpuFeaturesTemplate::T2);
-        assert!(vcpu.configure(&vm_config, GuestAddress(0), &vm).is_ok());
+        assert!(vcpu
+            .configure(&vm_config, None, GuestAddress(0), &vm)
+            .is_ok());
 
         // Test configure while using the C3 template.
         let mut vm_config = VmConfig::default();
         vm_config.cpu_template = Some(CpuFeaturesTemplate::C3);
-        assert!(vcpu.configure(&vm_config, GuestAddress(0), &vm).is_ok());
+        assert!(vcpu
+            .configure(&vm_config, None, GuestAddress(0), &vm)
+            .is_ok());
+
+        // Test configure while also using a CPU template defined by the user.
+        let cpu_template = CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidLeafModifier {
+                leaf: 0x1,
+                subleaf: 0x0,
+                eax: None,
+                ebx: None,
+                ecx: Some(RegisterBitmap {
+                    mask: 1 << 4,
+                    value: 0,
+                }),
+                edx: None,
+            }],
+            // Clear the fast strings bit of IA32_MISC_ENABLE.
+            msr_modifiers: vec![MsrModifier {
+                addr: 0x1a0,
+                bitmap: RegisterBitmap { mask: 1, value: 0 },
+            }],
+        };
+        assert!(vcpu
+            .configure(&vm_config, Some(&cpu_template), GuestAddress(0), &vm)
+            .is_ok());
     }
 
     #[test]
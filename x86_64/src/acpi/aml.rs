@@ -0,0 +1,440 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Minimal encoder for the subset of the ACPI Machine Language (AML) needed to describe the
+//! microVM devices in the DSDT. See chapter 20 of the ACPI 6.2 specification for the grammar.
+
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const NAME_OP: u8 = 0x08;
+const BYTE_PREFIX: u8 = 0x0a;
+const WORD_PREFIX: u8 = 0x0b;
+const DWORD_PREFIX: u8 = 0x0c;
+const STRING_PREFIX: u8 = 0x0d;
+const QWORD_PREFIX: u8 = 0x0e;
+const SCOPE_OP: u8 = 0x10;
+const BUFFER_OP: u8 = 0x11;
+const EXT_OP_PREFIX: u8 = 0x5b;
+const DEVICE_OP: u8 = 0x82;
+
+// Resource descriptor tags (ACPI 6.2 section 6.4).
+const IRQ_NO_FLAGS_TAG: u8 = 0x22;
+const IO_PORT_TAG: u8 = 0x47;
+const END_TAG: u8 = 0x79;
+const MEMORY32_FIXED_TAG: u8 = 0x86;
+
+/// Something that can be encoded as AML.
+pub trait Aml {
+    /// Appends the AML encoding of `self` to `bytes`.
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>);
+
+    /// Returns the AML encoding of `self`.
+    fn to_aml_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.append_aml_bytes(&mut bytes);
+        bytes
+    }
+}
+
+impl Aml for u8 {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        match *self {
+            0 => bytes.push(ZERO_OP),
+            1 => bytes.push(ONE_OP),
+            _ => {
+                bytes.push(BYTE_PREFIX);
+                bytes.push(*self);
+            }
+        }
+    }
+}
+
+impl Aml for u16 {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        if *self <= u16::from(::std::u8::MAX) {
+            return (*self as u8).append_aml_bytes(bytes);
+        }
+        bytes.push(WORD_PREFIX);
+        append_le(bytes, u64::from(*self), 2);
+    }
+}
+
+impl Aml for u32 {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        if *self <= u32::from(::std::u16::MAX) {
+            return (*self as u16).append_aml_bytes(bytes);
+        }
+        bytes.push(DWORD_PREFIX);
+        append_le(bytes, u64::from(*self), 4);
+    }
+}
+
+impl Aml for u64 {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        if *self <= u64::from(::std::u32::MAX) {
+            return (*self as u32).append_aml_bytes(bytes);
+        }
+        bytes.push(QWORD_PREFIX);
+        append_le(bytes, *self, 8);
+    }
+}
+
+impl<'a> Aml for &'a str {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.push(STRING_PREFIX);
+        bytes.extend_from_slice(self.as_bytes());
+        bytes.push(0);
+    }
+}
+
+// Appends the `len` least significant bytes of `value` in little endian order.
+fn append_le(bytes: &mut Vec<u8>, value: u64, len: usize) {
+    for i in 0..len {
+        bytes.push((value >> (8 * i)) as u8);
+    }
+}
+
+// Encodes a PkgLength (ACPI 6.2 section 20.2.4) for a package whose contents are `len` bytes
+// long. The encoded length includes the PkgLength bytes themselves.
+fn append_pkg_length(bytes: &mut Vec<u8>, len: usize) {
+    // One byte encodes up to 63 bytes; each additional byte adds 8 more bits on top of the 4
+    // bits left in the lead byte.
+    let pkg_len_bytes = if len + 1 < (1 << 6) {
+        1
+    } else if len + 2 < (1 << 12) {
+        2
+    } else if len + 3 < (1 << 20) {
+        3
+    } else {
+        4
+    };
+    let total = len + pkg_len_bytes;
+
+    if pkg_len_bytes == 1 {
+        bytes.push(total as u8);
+        return;
+    }
+    bytes.push((((pkg_len_bytes - 1) << 6) | (total & 0xf)) as u8);
+    for i in 1..pkg_len_bytes {
+        bytes.push((total >> (4 + 8 * (i - 1))) as u8);
+    }
+}
+
+/// A 4 character AML name segment, or a path made of such segments (e.g. `_SB_.COM1`).
+pub struct Path<'a>(pub &'a str);
+
+impl<'a> Aml for Path<'a> {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        const ROOT_PREFIX: u8 = b'\\';
+        const DUAL_NAME_PREFIX: u8 = 0x2e;
+        const MULTI_NAME_PREFIX: u8 = 0x2f;
+
+        let (root, path) = if self.0.starts_with('\\') {
+            (true, &self.0[1..])
+        } else {
+            (false, self.0)
+        };
+        if root {
+            bytes.push(ROOT_PREFIX);
+        }
+
+        let segments: Vec<&str> = path.split('.').collect();
+        match segments.len() {
+            1 => (),
+            2 => bytes.push(DUAL_NAME_PREFIX),
+            n => {
+                bytes.push(MULTI_NAME_PREFIX);
+                bytes.push(n as u8);
+            }
+        }
+        for segment in segments {
+            // Name segments are always 4 characters long, padded with '_'.
+            let mut seg = [b'_'; 4];
+            for (dst, src) in seg.iter_mut().zip(segment.bytes()) {
+                *dst = src;
+            }
+            bytes.extend_from_slice(&seg);
+        }
+    }
+}
+
+/// A compressed EISA identifier such as `PNP0501`.
+pub struct EisaId(pub u32);
+
+impl EisaId {
+    /// Compresses a 7 character EISA identifier (3 uppercase letters followed by 4 hex digits).
+    pub fn new(id: &str) -> Self {
+        let c = id.as_bytes();
+        assert_eq!(c.len(), 7, "Invalid EISA identifier: {}", id);
+        let hex = |b: u8| (b as char).to_digit(16).expect("Invalid EISA identifier");
+
+        let value = (u32::from(c[0] - 0x40) << 26)
+            | (u32::from(c[1] - 0x40) << 21)
+            | (u32::from(c[2] - 0x40) << 16)
+            | (hex(c[3]) << 12)
+            | (hex(c[4]) << 8)
+            | (hex(c[5]) << 4)
+            | hex(c[6]);
+        // The compressed identifier is stored in big endian order.
+        EisaId(value.swap_bytes())
+    }
+}
+
+impl Aml for EisaId {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.push(DWORD_PREFIX);
+        append_le(bytes, u64::from(self.0), 4);
+    }
+}
+
+/// A `Name(path, value)` object.
+pub struct Name<'a> {
+    path: Path<'a>,
+    value: Box<Aml + 'a>,
+}
+
+impl<'a> Name<'a> {
+    /// Creates a new named object.
+    pub fn new(path: &'a str, value: Box<Aml + 'a>) -> Self {
+        Name {
+            path: Path(path),
+            value,
+        }
+    }
+}
+
+impl<'a> Aml for Name<'a> {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.push(NAME_OP);
+        self.path.append_aml_bytes(bytes);
+        self.value.append_aml_bytes(bytes);
+    }
+}
+
+// Common encoding for the objects that consist of a path followed by a list of children.
+fn append_scoped<'a>(bytes: &mut Vec<u8>, opcode: &[u8], path: &Path, children: &[Box<Aml + 'a>]) {
+    let mut contents = path.to_aml_bytes();
+    for child in children {
+        child.append_aml_bytes(&mut contents);
+    }
+    bytes.extend_from_slice(opcode);
+    append_pkg_length(bytes, contents.len());
+    bytes.extend_from_slice(&contents);
+}
+
+/// A `Scope(path) { ... }` object.
+pub struct Scope<'a> {
+    path: Path<'a>,
+    children: Vec<Box<Aml + 'a>>,
+}
+
+impl<'a> Scope<'a> {
+    /// Creates a new scope holding `children`.
+    pub fn new(path: &'a str, children: Vec<Box<Aml + 'a>>) -> Self {
+        Scope {
+            path: Path(path),
+            children,
+        }
+    }
+}
+
+impl<'a> Aml for Scope<'a> {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        append_scoped(bytes, &[SCOPE_OP], &self.path, &self.children);
+    }
+}
+
+/// A `Device(path) { ... }` object.
+pub struct Device<'a> {
+    path: Path<'a>,
+    children: Vec<Box<Aml + 'a>>,
+}
+
+impl<'a> Device<'a> {
+    /// Creates a new device described by `children`.
+    pub fn new(path: &'a str, children: Vec<Box<Aml + 'a>>) -> Self {
+        Device {
+            path: Path(path),
+            children,
+        }
+    }
+}
+
+impl<'a> Aml for Device<'a> {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        append_scoped(
+            bytes,
+            &[EXT_OP_PREFIX, DEVICE_OP],
+            &self.path,
+            &self.children,
+        );
+    }
+}
+
+/// A resource descriptor that can be part of a `ResourceTemplate`.
+pub enum Resource {
+    /// 16-bit decoded I/O port range: base port and number of ports.
+    Io(u16, u8),
+    /// Legacy (ISA) interrupt line.
+    Irq(u8),
+    /// Fixed, read-write 32-bit memory range: base address and length.
+    Memory32Fixed(u32, u32),
+}
+
+impl Resource {
+    fn append_bytes(&self, bytes: &mut Vec<u8>) {
+        match *self {
+            Resource::Io(base, len) => {
+                bytes.push(IO_PORT_TAG);
+                // Decodes the full 16 bit address.
+                bytes.push(1);
+                append_le(bytes, u64::from(base), 2);
+                append_le(bytes, u64::from(base), 2);
+                // Alignment.
+                bytes.push(1);
+                bytes.push(len);
+            }
+            Resource::Irq(irq) => {
+                bytes.push(IRQ_NO_FLAGS_TAG);
+                append_le(bytes, 1u64 << irq, 2);
+            }
+            Resource::Memory32Fixed(base, len) => {
+                bytes.push(MEMORY32_FIXED_TAG);
+                // Length of the descriptor data.
+                append_le(bytes, 9, 2);
+                // Read-write.
+                bytes.push(1);
+                append_le(bytes, u64::from(base), 4);
+                append_le(bytes, u64::from(len), 4);
+            }
+        }
+    }
+}
+
+/// A `ResourceTemplate() { ... }` buffer, as returned by the `_CRS` of a device.
+pub struct ResourceTemplate(pub Vec<Resource>);
+
+impl Aml for ResourceTemplate {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        let mut data = Vec::new();
+        for resource in &self.0 {
+            resource.append_bytes(&mut data);
+        }
+        // The end tag is followed by a checksum; zero means the template is not checksummed.
+        data.push(END_TAG);
+        data.push(0);
+
+        let mut contents = (data.len() as u64).to_aml_bytes();
+        contents.extend_from_slice(&data);
+        bytes.push(BUFFER_OP);
+        append_pkg_length(bytes, contents.len());
+        bytes.extend_from_slice(&contents);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integers() {
+        assert_eq!(0u8.to_aml_bytes(), [ZERO_OP]);
+        assert_eq!(1u8.to_aml_bytes(), [ONE_OP]);
+        assert_eq!(0x42u8.to_aml_bytes(), [BYTE_PREFIX, 0x42]);
+        assert_eq!(0x42u64.to_aml_bytes(), [BYTE_PREFIX, 0x42]);
+        assert_eq!(0x1234u32.to_aml_bytes(), [WORD_PREFIX, 0x34, 0x12]);
+        assert_eq!(
+            0xdead_beefu64.to_aml_bytes(),
+            [DWORD_PREFIX, 0xef, 0xbe, 0xad, 0xde]
+        );
+        assert_eq!(
+            0x1_0000_0000u64.to_aml_bytes(),
+            [QWORD_PREFIX, 0, 0, 0, 0, 1, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_pkg_length() {
+        let mut bytes = vec![];
+        append_pkg_length(&mut bytes, 10);
+        assert_eq!(bytes, [11]);
+
+        // 62 bytes of content plus one byte of length still fit in a single byte.
+        let mut bytes = vec![];
+        append_pkg_length(&mut bytes, 62);
+        assert_eq!(bytes, [63]);
+
+        let mut bytes = vec![];
+        append_pkg_length(&mut bytes, 63);
+        assert_eq!(bytes, [(1 << 6) | (65 & 0xf), 65 >> 4]);
+
+        let mut bytes = vec![];
+        append_pkg_length(&mut bytes, 4093);
+        // 4095 bytes in total, 0xfff.
+        assert_eq!(bytes, [0x4f, 0xff]);
+
+        let mut bytes = vec![];
+        append_pkg_length(&mut bytes, 4094);
+        // 4097 bytes in total, 0x1001.
+        assert_eq!(bytes, [0x81, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_path() {
+        assert_eq!(Path("COM1").to_aml_bytes(), b"COM1");
+        assert_eq!(Path("_SB").to_aml_bytes(), b"_SB_");
+        assert_eq!(Path("\\_SB_").to_aml_bytes(), b"\\_SB_");
+        assert_eq!(Path("_SB_.COM1").to_aml_bytes(), b"\x2e_SB_COM1");
+        assert_eq!(
+            Path("\\_SB_.PCI0.ISA_").to_aml_bytes(),
+            b"\\\x2f\x03_SB_PCI0ISA_"
+        );
+    }
+
+    #[test]
+    fn test_eisa_id() {
+        // Values produced by the reference ASL compiler for EisaId("PNP0A03")/EisaId("PNP0501").
+        assert_eq!(EisaId::new("PNP0A03").0, 0x030a_d041);
+        assert_eq!(EisaId::new("PNP0501").0, 0x0105_d041);
+        assert_eq!(
+            EisaId::new("PNP0303").to_aml_bytes(),
+            [DWORD_PREFIX, 0x41, 0xd0, 0x03, 0x03]
+        );
+    }
+
+    #[test]
+    fn test_device() {
+        // Device(COM1) { Name(_HID, EisaId("PNP0501")) Name(_UID, Zero) }
+        let device = Device::new(
+            "COM1",
+            vec![
+                Box::new(Name::new("_HID", Box::new(EisaId::new("PNP0501")))),
+                Box::new(Name::new("_UID", Box::new(0u8))),
+            ],
+        );
+        let expected = [
+            0x5b, 0x82, 0x15, 0x43, 0x4f, 0x4d, 0x31, 0x08, 0x5f, 0x48, 0x49, 0x44, 0x0c, 0x41,
+            0xd0, 0x05, 0x01, 0x08, 0x5f, 0x55, 0x49, 0x44, 0x00,
+        ];
+        assert_eq!(device.to_aml_bytes(), &expected[..]);
+    }
+
+    #[test]
+    fn test_resource_template() {
+        // ResourceTemplate() { IO(Decode16, 0x3f8, 0x3f8, 1, 8) IRQNoFlags() {4} }
+        let crs = ResourceTemplate(vec![Resource::Io(0x3f8, 8), Resource::Irq(4)]);
+        let expected = [
+            0x11, 0x10, 0x0a, 0x0d, 0x47, 0x01, 0xf8, 0x03, 0xf8, 0x03, 0x01, 0x08, 0x22, 0x10,
+            0x00, 0x79, 0x00,
+        ];
+        assert_eq!(crs.to_aml_bytes(), &expected[..]);
+
+        // ResourceTemplate() { Memory32Fixed(ReadWrite, 0xd0000000, 0x1000) }
+        let crs = ResourceTemplate(vec![Resource::Memory32Fixed(0xd000_0000, 0x1000)]);
+        let expected = [
+            0x11, 0x11, 0x0a, 0x0e, 0x86, 0x09, 0x00, 0x01, 0x00, 0x00, 0x00, 0xd0, 0x00, 0x10,
+            0x00, 0x00, 0x79, 0x00,
+        ];
+        assert_eq!(crs.to_aml_bytes(), &expected[..]);
+    }
+}
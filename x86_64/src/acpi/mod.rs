@@ -0,0 +1,522 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Generation of the ACPI tables describing the microVM to the guest.
+//!
+//! The tables follow the ACPI 6.2 specification and describe a hardware-reduced platform: there
+//! is no PM timer, no SCI and no fixed hardware, only the CPUs (MADT) and the devices (DSDT).
+//! The RSDP is placed in the BIOS read-only area, where the guest kernel scans for it.
+
+pub mod aml;
+
+use std::result;
+
+use memory_model::{GuestAddress, GuestMemory};
+
+use self::aml::{Aml, Device, EisaId, Name, Resource, ResourceTemplate, Scope};
+use layout;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The tables do not fit in the memory area reserved for them.
+    TablesTooLarge,
+    /// Failure while writing the tables to guest memory.
+    WriteTables,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+const OEM_ID: &[u8; 6] = b"FIRECK";
+const OEM_REVISION: u32 = 0;
+const CREATOR_ID: &[u8; 4] = b"FCAT";
+const CREATOR_REVISION: u32 = 0x2018_1101;
+const SDT_HEADER_LEN: usize = 36;
+
+// Sourced from linux/arch/x86/include/asm/apicdef.h, same as the MP table.
+const IO_APIC_DEFAULT_PHYS_BASE: u32 = 0xfec0_0000;
+const APIC_DEFAULT_PHYS_BASE: u32 = 0xfee0_0000;
+
+// FADT flags and IA-PC boot architecture flags (ACPI 6.2 sections 5.2.9 and 5.2.9.3).
+const FADT_F_HW_REDUCED_ACPI: u32 = 1 << 20;
+const IAPC_BOOT_ARCH_8042: u16 = 1 << 1;
+const IAPC_BOOT_ARCH_VGA_NOT_PRESENT: u16 = 1 << 2;
+const IAPC_BOOT_ARCH_MSI_NOT_SUPPORTED: u16 = 1 << 3;
+const IAPC_BOOT_ARCH_PCIE_ASPM_CONTROLS: u16 = 1 << 4;
+
+// MADT structure types and flags (ACPI 6.2 section 5.2.12).
+const MADT_PCAT_COMPAT: u32 = 1;
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_LAPIC_ENABLED: u32 = 1;
+
+// The I/O port the legacy device manager registers the pvpanic device at.
+const PVPANIC_PORT: u16 = 0x505;
+
+/// A virtio device exposed through the MMIO transport, to be described in the DSDT.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MmioDeviceInfo {
+    /// Guest physical address of the device registers.
+    pub addr: u64,
+    /// Size of the device register window.
+    pub len: u64,
+    /// Legacy interrupt line used by the device.
+    pub irq: u32,
+}
+
+/// The optional legacy devices registered on the I/O bus, to be described in the DSDT next to the
+/// serial ports and the i8042 controller.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LegacyDevices {
+    /// Whether a pvpanic device is registered at I/O port 0x505.
+    pub pvpanic: bool,
+}
+
+// Computes the byte that has to be added to `data` so that all its bytes sum up to zero.
+fn compute_checksum(data: &[u8]) -> u8 {
+    let sum = data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    (!sum).wrapping_add(1)
+}
+
+fn append_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+}
+
+fn append_u32(bytes: &mut Vec<u8>, value: u32) {
+    append_u16(bytes, value as u16);
+    append_u16(bytes, (value >> 16) as u16);
+}
+
+fn append_u64(bytes: &mut Vec<u8>, value: u64) {
+    append_u32(bytes, value as u32);
+    append_u32(bytes, (value >> 32) as u32);
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset] = value as u8;
+    bytes[offset + 1] = (value >> 8) as u8;
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    write_u16(bytes, offset, value as u16);
+    write_u16(bytes, offset + 2, (value >> 16) as u16);
+}
+
+fn write_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    write_u32(bytes, offset, value as u32);
+    write_u32(bytes, offset + 4, (value >> 32) as u32);
+}
+
+/// A System Description Table: the common header followed by the table specific contents.
+pub struct Sdt {
+    data: Vec<u8>,
+}
+
+impl Sdt {
+    /// Creates a table with the given signature and revision, whose contents are
+    /// `len - SDT_HEADER_LEN` zero bytes.
+    fn new(signature: &[u8; 4], len: usize, revision: u8, oem_table_id: &[u8; 8]) -> Self {
+        assert!(len >= SDT_HEADER_LEN);
+        let mut data = Vec::with_capacity(len);
+        data.extend_from_slice(signature);
+        append_u32(&mut data, len as u32);
+        data.push(revision);
+        // Checksum, computed once the table is complete.
+        data.push(0);
+        data.extend_from_slice(OEM_ID);
+        data.extend_from_slice(oem_table_id);
+        append_u32(&mut data, OEM_REVISION);
+        data.extend_from_slice(CREATOR_ID);
+        append_u32(&mut data, CREATOR_REVISION);
+        data.resize(len, 0);
+
+        let mut sdt = Sdt { data };
+        sdt.update_checksum();
+        sdt
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+        let len = self.data.len() as u32;
+        write_u32(&mut self.data, 4, len);
+        self.update_checksum();
+    }
+
+    fn update_checksum(&mut self) {
+        self.data[9] = 0;
+        self.data[9] = compute_checksum(&self.data);
+    }
+
+    /// Returns the raw contents of the table, including the header.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the length of the table.
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+/// Builds the Root System Description Pointer (ACPI 6.2 section 5.2.5.3) for an XSDT placed at
+/// `xsdt_addr`.
+pub fn create_rsdp(xsdt_addr: u64) -> Vec<u8> {
+    const RSDP_LEN: usize = 36;
+    // The legacy checksum only covers the ACPI 1.0 part of the structure.
+    const RSDP_V1_LEN: usize = 20;
+
+    let mut rsdp = Vec::with_capacity(RSDP_LEN);
+    rsdp.extend_from_slice(b"RSD PTR ");
+    rsdp.push(0);
+    rsdp.extend_from_slice(OEM_ID);
+    // Revision 2 means the XSDT address is valid.
+    rsdp.push(2);
+    // The 32-bit RSDT address is not used.
+    append_u32(&mut rsdp, 0);
+    append_u32(&mut rsdp, RSDP_LEN as u32);
+    append_u64(&mut rsdp, xsdt_addr);
+    // Extended checksum and reserved bytes.
+    rsdp.extend_from_slice(&[0; 4]);
+
+    rsdp[8] = compute_checksum(&rsdp[..RSDP_V1_LEN]);
+    rsdp[32] = compute_checksum(&rsdp);
+    rsdp
+}
+
+/// Builds the Extended System Description Table pointing to the tables at `table_addrs`.
+pub fn create_xsdt(table_addrs: &[u64]) -> Sdt {
+    let mut xsdt = Sdt::new(b"XSDT", SDT_HEADER_LEN, 1, b"FCXSDT  ");
+    let mut entries = Vec::with_capacity(table_addrs.len() * 8);
+    for addr in table_addrs {
+        append_u64(&mut entries, *addr);
+    }
+    xsdt.append(&entries);
+    xsdt
+}
+
+/// Builds the Fixed ACPI Description Table for a hardware-reduced platform, pointing to the DSDT
+/// placed at `dsdt_addr`.
+pub fn create_fadt(dsdt_addr: u64) -> Sdt {
+    const FADT_LEN: usize = 276;
+    const IAPC_BOOT_ARCH_OFFSET: usize = 109;
+    const FLAGS_OFFSET: usize = 112;
+    const MINOR_REVISION_OFFSET: usize = 131;
+    const X_DSDT_OFFSET: usize = 140;
+    const HYPERVISOR_VENDOR_OFFSET: usize = 268;
+
+    let mut fadt = Sdt::new(b"FACP", FADT_LEN, 6, b"FCFADT  ");
+    {
+        let data = &mut fadt.data;
+        // The i8042 controller is present, but there is no VGA, no MSI and no PCIe.
+        write_u16(
+            data,
+            IAPC_BOOT_ARCH_OFFSET,
+            IAPC_BOOT_ARCH_8042
+                | IAPC_BOOT_ARCH_VGA_NOT_PRESENT
+                | IAPC_BOOT_ARCH_MSI_NOT_SUPPORTED
+                | IAPC_BOOT_ARCH_PCIE_ASPM_CONTROLS,
+        );
+        write_u32(data, FLAGS_OFFSET, FADT_F_HW_REDUCED_ACPI);
+        data[MINOR_REVISION_OFFSET] = 2;
+        write_u64(data, X_DSDT_OFFSET, dsdt_addr);
+        data[HYPERVISOR_VENDOR_OFFSET..HYPERVISOR_VENDOR_OFFSET + 8].copy_from_slice(b"FIRECKVM");
+    }
+    fadt.update_checksum();
+    fadt
+}
+
+/// Builds the Multiple APIC Description Table describing `num_cpus` local APICs and the IOAPIC.
+pub fn create_madt(num_cpus: u8) -> Sdt {
+    let mut madt = Sdt::new(b"APIC", SDT_HEADER_LEN, 4, b"FCMADT  ");
+    let mut contents = Vec::new();
+    append_u32(&mut contents, APIC_DEFAULT_PHYS_BASE);
+    // The legacy PIC is also emulated.
+    append_u32(&mut contents, MADT_PCAT_COMPAT);
+
+    for cpu_id in 0..num_cpus {
+        contents.extend_from_slice(&[MADT_LOCAL_APIC, 8, cpu_id, cpu_id]);
+        append_u32(&mut contents, MADT_LAPIC_ENABLED);
+    }
+
+    // Use the same IOAPIC ID as the MP table.
+    contents.extend_from_slice(&[MADT_IO_APIC, 12, num_cpus + 1, 0]);
+    append_u32(&mut contents, IO_APIC_DEFAULT_PHYS_BASE);
+    // Global system interrupt base.
+    append_u32(&mut contents, 0);
+
+    madt.append(&contents);
+    madt
+}
+
+fn legacy_device<'a>(path: &'a str, hid: &str, uid: u8, resources: Vec<Resource>) -> Box<Aml + 'a> {
+    Box::new(Device::new(
+        path,
+        vec![
+            Box::new(Name::new("_HID", Box::new(EisaId::new(hid)))),
+            Box::new(Name::new("_UID", Box::new(uid))),
+            Box::new(Name::new("_CRS", Box::new(ResourceTemplate(resources)))),
+        ],
+    ))
+}
+
+/// Builds the Differentiated System Description Table holding the AML description of the legacy
+/// devices, including the optional `legacy_devices`, and of the virtio-mmio devices in
+/// `mmio_devices`.
+pub fn create_dsdt(mmio_devices: &[MmioDeviceInfo], legacy_devices: &LegacyDevices) -> Sdt {
+    // The names of the virtio devices have to outlive the AML tree borrowing them.
+    let names: Vec<String> = (0..mmio_devices.len())
+        .map(|i| format!("V{:03X}", i))
+        .collect();
+
+    // These match the devices registered on the I/O bus by the legacy device manager.
+    let mut devices = vec![
+        legacy_device(
+            "COM1",
+            "PNP0501",
+            0,
+            vec![Resource::Io(0x3f8, 8), Resource::Irq(4)],
+        ),
+        legacy_device(
+            "COM2",
+            "PNP0501",
+            1,
+            vec![Resource::Io(0x2f8, 8), Resource::Irq(3)],
+        ),
+        // Only the command port of the i8042 controller is emulated. The guest driver falls back
+        // to the standard data port when none is described.
+        legacy_device(
+            "PS2_",
+            "PNP0303",
+            0,
+            vec![Resource::Io(0x64, 1), Resource::Irq(1)],
+        ),
+    ];
+    if legacy_devices.pvpanic {
+        // The pvpanic device has no EISA identifier, the guest driver matches this one.
+        devices.push(Box::new(Device::new(
+            "PEVT",
+            vec![
+                Box::new(Name::new("_HID", Box::new("QEMU0001"))),
+                Box::new(Name::new(
+                    "_CRS",
+                    Box::new(ResourceTemplate(vec![Resource::Io(PVPANIC_PORT, 1)])),
+                )),
+            ],
+        )));
+    }
+
+    for (name, info) in names.iter().zip(mmio_devices.iter()) {
+        devices.push(Box::new(Device::new(
+            name.as_str(),
+            vec![
+                Box::new(Name::new("_HID", Box::new("LNRO0005"))),
+                Box::new(Name::new("_UID", Box::new(u64::from(info.irq)))),
+                Box::new(Name::new(
+                    "_CRS",
+                    Box::new(ResourceTemplate(vec![
+                        Resource::Memory32Fixed(info.addr as u32, info.len as u32),
+                        Resource::Irq(info.irq as u8),
+                    ])),
+                )),
+            ],
+        )));
+    }
+
+    let mut dsdt = Sdt::new(b"DSDT", SDT_HEADER_LEN, 6, b"FCDSDT  ");
+    dsdt.append(&Scope::new("\\_SB_", devices).to_aml_bytes());
+    dsdt
+}
+
+/// Builds the ACPI tables and writes them to guest memory, starting with the RSDP at
+/// `layout::RSDP_START`.
+///
+/// # Arguments
+///
+/// * `mem` - The memory to be used by the guest.
+/// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `mmio_devices` - The virtio-mmio devices to describe in the DSDT.
+/// * `legacy_devices` - The optional legacy devices to describe in the DSDT.
+pub fn setup_acpi_tables(
+    mem: &GuestMemory,
+    num_cpus: u8,
+    mmio_devices: &[MmioDeviceInfo],
+    legacy_devices: &LegacyDevices,
+) -> Result<()> {
+    // The tables are laid out one after the other, right after the RSDP, in this order:
+    // RSDP, DSDT, FADT, MADT, XSDT.
+    let rsdp_addr = layout::RSDP_START as u64;
+    let dsdt_addr = rsdp_addr + 64;
+    let dsdt = create_dsdt(mmio_devices, legacy_devices);
+    let fadt_addr = dsdt_addr + dsdt.len() as u64;
+    let fadt = create_fadt(dsdt_addr);
+    let madt_addr = fadt_addr + fadt.len() as u64;
+    let madt = create_madt(num_cpus);
+    let xsdt_addr = madt_addr + madt.len() as u64;
+    let xsdt = create_xsdt(&[fadt_addr, madt_addr]);
+    let rsdp = create_rsdp(xsdt_addr);
+
+    if xsdt_addr + xsdt.len() as u64 > layout::ACPI_TABLES_END as u64 {
+        return Err(Error::TablesTooLarge);
+    }
+
+    let tables: [(&[u8], u64); 5] = [
+        (&rsdp, rsdp_addr),
+        (dsdt.as_slice(), dsdt_addr),
+        (fadt.as_slice(), fadt_addr),
+        (madt.as_slice(), madt_addr),
+        (xsdt.as_slice(), xsdt_addr),
+    ];
+    for &(table, addr) in tables.iter() {
+        let written = mem
+            .write_slice_at_addr(table, GuestAddress(addr as usize))
+            .map_err(|_| Error::WriteTables)?;
+        if written != table.len() {
+            return Err(Error::WriteTables);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksum_ok(data: &[u8]) -> bool {
+        data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) == 0
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        (0..4).fold(0, |acc, i| acc | (u32::from(data[offset + i]) << (8 * i)))
+    }
+
+    fn read_u64(data: &[u8], offset: usize) -> u64 {
+        u64::from(read_u32(data, offset)) | (u64::from(read_u32(data, offset + 4)) << 32)
+    }
+
+    #[test]
+    fn test_rsdp() {
+        let rsdp = create_rsdp(0x1234_5678_9abc);
+        assert_eq!(rsdp.len(), 36);
+        assert_eq!(&rsdp[..8], b"RSD PTR ");
+        assert!(checksum_ok(&rsdp[..20]));
+        assert!(checksum_ok(&rsdp));
+        assert_eq!(read_u64(&rsdp, 24), 0x1234_5678_9abc);
+    }
+
+    #[test]
+    fn test_sdt_header() {
+        let mut sdt = Sdt::new(b"TEST", 40, 1, b"FCTEST  ");
+        assert_eq!(sdt.len(), 40);
+        assert_eq!(&sdt.as_slice()[..4], b"TEST");
+        assert_eq!(read_u32(sdt.as_slice(), 4), 40);
+        assert!(checksum_ok(sdt.as_slice()));
+
+        // Appending updates both the length and the checksum.
+        sdt.append(&[1, 2, 3]);
+        assert_eq!(read_u32(sdt.as_slice(), 4), 43);
+        assert!(checksum_ok(sdt.as_slice()));
+    }
+
+    #[test]
+    fn test_madt() {
+        for num_cpus in [1u8, 2, 16, 32, 253].iter() {
+            let madt = create_madt(*num_cpus);
+            assert!(checksum_ok(madt.as_slice()));
+            assert_eq!(
+                madt.len(),
+                SDT_HEADER_LEN + 8 + 8 * (*num_cpus as usize) + 12
+            );
+
+            let data = madt.as_slice();
+            let mut offset = SDT_HEADER_LEN + 8;
+            let mut lapics = 0;
+            while offset < data.len() {
+                if data[offset] == MADT_LOCAL_APIC {
+                    assert_eq!(data[offset + 3], lapics);
+                    lapics += 1;
+                }
+                offset += data[offset + 1] as usize;
+            }
+            assert_eq!(lapics, *num_cpus);
+            // The IOAPIC comes last, with the ID after the last local APIC.
+            assert_eq!(data[data.len() - 12], MADT_IO_APIC);
+            assert_eq!(data[data.len() - 10], num_cpus + 1);
+            assert_ne!(data[data.len() - 10], 0xff);
+        }
+    }
+
+    #[test]
+    fn test_fadt() {
+        let fadt = create_fadt(0xe0040);
+        assert_eq!(fadt.len(), 276);
+        assert!(checksum_ok(fadt.as_slice()));
+        assert_eq!(read_u32(fadt.as_slice(), 112), FADT_F_HW_REDUCED_ACPI);
+        assert_eq!(read_u64(fadt.as_slice(), 140), 0xe0040);
+    }
+
+    #[test]
+    fn test_dsdt() {
+        let dsdt = create_dsdt(&[], &LegacyDevices::default());
+        assert!(checksum_ok(dsdt.as_slice()));
+        // The pvpanic device is only described when it is registered.
+        assert!(!dsdt.as_slice().windows(8).any(|w| w == b"QEMU0001"));
+        let dsdt_with_pvpanic = create_dsdt(&[], &LegacyDevices { pvpanic: true });
+        assert!(checksum_ok(dsdt_with_pvpanic.as_slice()));
+        assert!(dsdt_with_pvpanic
+            .as_slice()
+            .windows(8)
+            .any(|w| w == b"QEMU0001"));
+
+        let devices = [
+            MmioDeviceInfo {
+                addr: 0xd000_0000,
+                len: 0x1000,
+                irq: 5,
+            },
+            MmioDeviceInfo {
+                addr: 0xd000_1000,
+                len: 0x1000,
+                irq: 6,
+            },
+        ];
+        let dsdt_with_devices = create_dsdt(&devices, &LegacyDevices::default());
+        assert!(checksum_ok(dsdt_with_devices.as_slice()));
+        assert!(dsdt_with_devices.len() > dsdt.len());
+        let data = dsdt_with_devices.as_slice();
+        assert!(data.windows(4).any(|w| w == b"V001"));
+        assert!(data.windows(8).any(|w| w == b"LNRO0005"));
+    }
+
+    #[test]
+    fn test_setup_acpi_tables() {
+        let mem = GuestMemory::new(&[(GuestAddress(0), layout::HIMEM_START)]).unwrap();
+        setup_acpi_tables(&mem, 2, &[], &LegacyDevices::default()).unwrap();
+
+        let mut rsdp = [0u8; 36];
+        mem.read_slice_at_addr(&mut rsdp, GuestAddress(layout::RSDP_START))
+            .unwrap();
+        assert_eq!(&rsdp[..8], b"RSD PTR ");
+
+        // Follow the pointers down to the MADT.
+        let xsdt_addr = read_u64(&rsdp, 24) as usize;
+        let mut header = [0u8; SDT_HEADER_LEN];
+        mem.read_slice_at_addr(&mut header, GuestAddress(xsdt_addr))
+            .unwrap();
+        assert_eq!(&header[..4], b"XSDT");
+        assert_eq!(read_u32(&header, 4) as usize, SDT_HEADER_LEN + 16);
+
+        let madt_addr: u64 = mem
+            .read_obj_from_addr(GuestAddress(xsdt_addr + SDT_HEADER_LEN + 8))
+            .unwrap();
+        mem.read_slice_at_addr(&mut header, GuestAddress(madt_addr as usize))
+            .unwrap();
+        assert_eq!(&header[..4], b"APIC");
+
+        // Not enough guest memory to hold the tables.
+        let mem = GuestMemory::new(&[(GuestAddress(0), layout::RSDP_START + 16)]).unwrap();
+        assert_eq!(
+            setup_acpi_tables(&mem, 2, &[], &LegacyDevices::default()),
+            Err(Error::WriteTables)
+        );
+    }
+}
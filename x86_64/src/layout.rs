@@ -25,3 +25,6 @@ pub const MPTABLE_START: usize = 0x9fc00;
 pub const EBDA_START: u64 = 0x9fc00;
 // 1MB.  We don't put anything above here except the kernel itself.
 pub const HIMEM_START: usize = 0x100000;
+// ACPI tables, in the BIOS read-only area where the guest kernel searches for the RSDP.
+pub const RSDP_START: usize = 0xe0000;
+pub const ACPI_TABLES_END: usize = 0x100000;
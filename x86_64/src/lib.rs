@@ -37,6 +37,7 @@ unsafe impl memory_model::DataInit for mpspec::mpc_table {}
 unsafe impl memory_model::DataInit for mpspec::mpc_lintsrc {}
 unsafe impl memory_model::DataInit for mpspec::mpf_intel {}
 
+pub mod acpi;
 mod gdt;
 pub mod interrupts;
 pub mod layout;
@@ -50,12 +51,16 @@ use bootparam::boot_params;
 use bootparam::E820_RAM;
 use memory_model::{GuestAddress, GuestMemory};
 
+pub use acpi::Error as AcpiError;
 pub use interrupts::Error as IntError;
 pub use mptable::Error as MpTableError;
+pub use mptable::MAX_SUPPORTED_CPUS;
 pub use regs::Error as RegError;
 
 #[derive(Debug)]
 pub enum Error {
+    /// Error writing the ACPI tables to memory.
+    AcpiSetup(AcpiError),
     /// Invalid e820 setup params.
     E820Configuration,
     /// Error writing MP table to memory.
@@ -68,14 +73,23 @@ pub enum Error {
 pub type Result<T> = result::Result<T, Error>;
 
 const FIRST_ADDR_PAST_32BITS: usize = (1 << 32);
-const MEM_32BIT_GAP_SIZE: usize = (768 << 20);
+
+/// The default size of the carve out at the end of the 32bit address space.
+pub const DEFAULT_32BIT_GAP_SIZE: usize = (768 << 20);
+/// The smallest carve out, which still leaves some room for the devices below the IOAPIC.
+pub const MIN_32BIT_GAP_SIZE: usize = (64 << 20);
+/// The largest carve out, which still leaves 1GiB of memory below it for the kernel.
+pub const MAX_32BIT_GAP_SIZE: usize = (3 << 30);
+/// The end of the addresses usable by the MMIO devices, after which the IOAPIC and the local
+/// APICs are mapped.
+pub const MMIO_DEVICES_END: usize = 0xfec0_0000;
 
 /// Returns a Vec of the valid memory addresses.
 /// These should be used to configure the GuestMemory structure for the platform.
 /// For x86_64 all addresses are valid from the start of the kernel except a
-/// carve out at the end of 32bit address space.
-pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
-    let memory_gap_start = GuestAddress(FIRST_ADDR_PAST_32BITS - MEM_32BIT_GAP_SIZE);
+/// carve out of `gap_size` bytes at the end of 32bit address space.
+pub fn arch_memory_regions(size: usize, gap_size: usize) -> Vec<(GuestAddress, usize)> {
+    let memory_gap_start = GuestAddress(get_32bit_gap_start(gap_size));
     let memory_gap_end = GuestAddress(FIRST_ADDR_PAST_32BITS);
     let requested_memory_size = GuestAddress(size);
     let mut regions = Vec::new();
@@ -96,10 +110,10 @@ pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
     regions
 }
 
-/// X86 specific memory hole/ memory mapped devices/ reserved area.
+/// X86 specific memory hole/ memory mapped devices/ reserved area, of `gap_size` bytes.
 ///
-pub fn get_32bit_gap_start() -> usize {
-    FIRST_ADDR_PAST_32BITS - MEM_32BIT_GAP_SIZE
+pub fn get_32bit_gap_start(gap_size: usize) -> usize {
+    FIRST_ADDR_PAST_32BITS - gap_size
 }
 
 /// Configures the system and should be called once per vm before starting vcpu threads.
@@ -110,24 +124,37 @@ pub fn get_32bit_gap_start() -> usize {
 /// * `cmdline_addr` - Address in `guest_mem` where the kernel command line was loaded.
 /// * `cmdline_size` - Size of the kernel command line in bytes including the null terminator.
 /// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `gap_size` - Size of the carve out at the end of the 32bit address space, as passed to
+///                `arch_memory_regions`.
+/// * `acpi_devices` - The optional legacy devices to describe in ACPI tables, next to the MP
+///                    table, or `None` to describe the guest with the MP table only.
 pub fn configure_system(
     guest_mem: &GuestMemory,
     cmdline_addr: GuestAddress,
     cmdline_size: usize,
     num_cpus: u8,
+    gap_size: usize,
+    acpi_devices: Option<&acpi::LegacyDevices>,
 ) -> Result<()> {
     const KERNEL_BOOT_FLAG_MAGIC: u16 = 0xaa55;
     const KERNEL_HDR_MAGIC: u32 = 0x53726448;
     const KERNEL_LOADER_OTHER: u8 = 0xff;
     const KERNEL_MIN_ALIGNMENT_BYTES: u32 = 0x1000000; // Must be non-zero.
     let first_addr_past_32bits = GuestAddress(FIRST_ADDR_PAST_32BITS);
-    let end_32bit_gap_start = GuestAddress(get_32bit_gap_start());
+    let end_32bit_gap_start = GuestAddress(get_32bit_gap_start(gap_size));
 
     let himem_start = GuestAddress(layout::HIMEM_START);
 
     // Note that this puts the mptable at the last 1k of Linux's 640k base RAM
     mptable::setup_mptable(guest_mem, num_cpus).map_err(Error::MpTableSetup)?;
 
+    // The virtio devices are not described in the DSDT: they are already announced to the guest
+    // on the kernel command line and a kernel booting with ACPI would otherwise probe them twice.
+    if let Some(legacy_devices) = acpi_devices {
+        acpi::setup_acpi_tables(guest_mem, num_cpus, &[], legacy_devices)
+            .map_err(Error::AcpiSetup)?;
+    }
+
     let mut params: boot_params = Default::default();
 
     params.hdr.type_of_loader = KERNEL_LOADER_OTHER;
@@ -197,7 +224,7 @@ mod tests {
 
     #[test]
     fn regions_lt_4gb() {
-        let regions = arch_memory_regions(1usize << 29);
+        let regions = arch_memory_regions(1usize << 29, DEFAULT_32BIT_GAP_SIZE);
         assert_eq!(1, regions.len());
         assert_eq!(GuestAddress(0), regions[0].0);
         assert_eq!(1usize << 29, regions[0].1);
@@ -205,7 +232,7 @@ mod tests {
 
     #[test]
     fn regions_gt_4gb() {
-        let regions = arch_memory_regions((1usize << 32) + 0x8000);
+        let regions = arch_memory_regions((1usize << 32) + 0x8000, DEFAULT_32BIT_GAP_SIZE);
         assert_eq!(2, regions.len());
         assert_eq!(GuestAddress(0), regions[0].0);
         assert_eq!(GuestAddress(1usize << 32), regions[1].0);
@@ -213,9 +240,18 @@ mod tests {
 
     #[test]
     fn test_32bit_gap() {
+        assert_eq!(get_32bit_gap_start(DEFAULT_32BIT_GAP_SIZE), 0xc000_0000);
+        assert!(get_32bit_gap_start(MIN_32BIT_GAP_SIZE) < MMIO_DEVICES_END);
+        assert_eq!(get_32bit_gap_start(MAX_32BIT_GAP_SIZE), 1 << 30);
+
+        // The memory which doesn't fit before a larger gap is moved past 4GiB.
+        let regions = arch_memory_regions(2 << 30, MAX_32BIT_GAP_SIZE);
         assert_eq!(
-            get_32bit_gap_start(),
-            FIRST_ADDR_PAST_32BITS - MEM_32BIT_GAP_SIZE
+            regions,
+            vec![
+                (GuestAddress(0), 1 << 30),
+                (GuestAddress(FIRST_ADDR_PAST_32BITS), 1 << 30)
+            ]
         );
     }
 
@@ -223,25 +259,61 @@ mod tests {
     fn test_system_configuration() {
         let no_vcpus = 4;
         let gm = GuestMemory::new(&vec![(GuestAddress(0), 0x10000)]).unwrap();
-        assert!(configure_system(&gm, GuestAddress(0), 0, 1).is_err());
+        assert!(
+            configure_system(&gm, GuestAddress(0), 0, 1, DEFAULT_32BIT_GAP_SIZE, None).is_err()
+        );
 
         // Now assigning some memory that falls before the 32bit memory hole.
         let mem_size = 128 << 20;
-        let arch_mem_regions = arch_memory_regions(mem_size);
+        let arch_mem_regions = arch_memory_regions(mem_size, DEFAULT_32BIT_GAP_SIZE);
         let gm = GuestMemory::new(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, no_vcpus).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            no_vcpus,
+            DEFAULT_32BIT_GAP_SIZE,
+            None,
+        )
+        .unwrap();
+        // Without ACPI devices, the guest is only described by the MP table.
+        let mut signature = [0u8; 8];
+        gm.read_slice_at_addr(&mut signature, GuestAddress(layout::RSDP_START))
+            .unwrap();
+        assert_ne!(&signature, b"RSD PTR ");
 
         // Now assigning some memory that is equal to the start of the 32bit memory hole.
         let mem_size = 3328 << 20;
-        let arch_mem_regions = arch_memory_regions(mem_size);
+        let arch_mem_regions = arch_memory_regions(mem_size, DEFAULT_32BIT_GAP_SIZE);
         let gm = GuestMemory::new(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, no_vcpus).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            no_vcpus,
+            DEFAULT_32BIT_GAP_SIZE,
+            None,
+        )
+        .unwrap();
 
-        // Now assigning some memory that falls after the 32bit memory hole.
+        // Now assigning some memory that falls after the 32bit memory hole, with ACPI tables.
         let mem_size = 3330 << 20;
-        let arch_mem_regions = arch_memory_regions(mem_size);
+        let arch_mem_regions = arch_memory_regions(mem_size, DEFAULT_32BIT_GAP_SIZE);
         let gm = GuestMemory::new(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, no_vcpus).unwrap();
+        let acpi_devices = acpi::LegacyDevices { pvpanic: true };
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            no_vcpus,
+            DEFAULT_32BIT_GAP_SIZE,
+            Some(&acpi_devices),
+        )
+        .unwrap();
+        let mut signature = [0u8; 8];
+        gm.read_slice_at_addr(&mut signature, GuestAddress(layout::RSDP_START))
+            .unwrap();
+        assert_eq!(&signature, b"RSD PTR ");
     }
 
     #[test]
@@ -39,6 +39,8 @@ pub enum Error {
     WriteMpcLintsrc,
     /// Failure to write MP table header.
     WriteMpcTable,
+    /// There are more CPUs than the APIC IDs can address.
+    TooManyCpus,
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -62,6 +64,12 @@ const CPU_STEPPING: u32 = 0x600;
 const CPU_FEATURE_APIC: u32 = 0x200;
 const CPU_FEATURE_FPU: u32 = 0x001;
 
+/// The maximum number of CPUs the MP table, and the MADT, can describe.
+///
+/// The APIC IDs in the tables are 8 bits wide: the CPUs take the IDs from 0, the IOAPIC takes
+/// `num_cpus + 1`, and that must stay below the broadcast ID, 0xff.
+pub const MAX_SUPPORTED_CPUS: u8 = 0xfd;
+
 fn compute_checksum<T: Copy>(v: &T) -> u8 {
     // Safe because we are only reading the bytes within the size of the `T` reference `v`.
     let v_slice = unsafe { slice::from_raw_parts(v as *const T as *const u8, mem::size_of::<T>()) };
@@ -94,6 +102,10 @@ pub fn setup_mptable(mem: &GuestMemory, num_cpus: u8) -> Result<()> {
 
     let mp_size = compute_mp_size(num_cpus);
 
+    if num_cpus > MAX_SUPPORTED_CPUS {
+        return Err(Error::TooManyCpus);
+    }
+
     let mut checksum: u8 = 0;
     let ioapicid: u8 = num_cpus + 1;
 
@@ -335,14 +347,14 @@ mod tests {
 
     #[test]
     fn cpu_entry_count() {
-        const MAX_CPUS: u8 = 0xff;
+        const MAX_CPUS: u8 = MAX_SUPPORTED_CPUS;
         let mem = GuestMemory::new(&[(
             GuestAddress(layout::MPTABLE_START),
             compute_mp_size(MAX_CPUS),
         )])
         .unwrap();
 
-        for i in 0..MAX_CPUS {
+        for i in 0..MAX_CPUS + 1 {
             setup_mptable(&mem, i).unwrap();
 
             let mpf_intel: mpf_intel = mem
@@ -367,4 +379,39 @@ mod tests {
             assert_eq!(cpu_count, i);
         }
     }
+
+    #[test]
+    fn ioapic_id_with_max_cpus() {
+        let mem = GuestMemory::new(&[(
+            GuestAddress(layout::MPTABLE_START),
+            compute_mp_size(MAX_SUPPORTED_CPUS),
+        )])
+        .unwrap();
+
+        setup_mptable(&mem, MAX_SUPPORTED_CPUS).unwrap();
+
+        // The interrupts are routed to the IOAPIC, whose ID is never the broadcast ID.
+        let mpf_intel: mpf_intel = mem
+            .read_obj_from_addr(GuestAddress(layout::MPTABLE_START))
+            .unwrap();
+        let ioapic_offset = GuestAddress(
+            mpf_intel.physptr as usize
+                + mem::size_of::<mpc_table>()
+                + mem::size_of::<mpc_cpu>() * MAX_SUPPORTED_CPUS as usize
+                + mem::size_of::<mpc_bus>(),
+        );
+        let mpc_ioapic: mpc_ioapic = mem.read_obj_from_addr(ioapic_offset).unwrap();
+        assert_eq!(mpc_ioapic.type_, MP_IOAPIC as u8);
+        assert_eq!(mpc_ioapic.apicid, MAX_SUPPORTED_CPUS + 1);
+        assert_ne!(mpc_ioapic.apicid, 0xff);
+        let mpc_intsrc: mpc_intsrc = mem
+            .read_obj_from_addr(ioapic_offset.unchecked_add(mem::size_of::<mpc_ioapic>()))
+            .unwrap();
+        assert_eq!(mpc_intsrc.dstapic, mpc_ioapic.apicid);
+
+        match setup_mptable(&mem, MAX_SUPPORTED_CPUS + 1) {
+            Err(Error::TooManyCpus) => (),
+            _ => panic!("The MP table can't describe that many CPUs."),
+        }
+    }
 }
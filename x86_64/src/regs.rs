@@ -28,6 +28,8 @@ pub enum Error {
     SetFPURegisters(sys_util::Error),
     /// Setting up MSRs failed.
     SetModelSpecificRegisters(sys_util::Error),
+    /// KVM rejected an MSR, only the given number of the MSRs were set.
+    SetModelSpecificRegistersCount(i32),
     /// Failed to set SREGs for this CPU.
     SetStatusRegisters(sys_util::Error),
     /// Writing the GDT to RAM failed.
@@ -59,13 +61,27 @@ pub fn setup_fpu(vcpu: &kvm::VcpuFd) -> Result<()> {
     vcpu.set_fpu(&fpu).map_err(Error::SetFPURegisters)
 }
 
+/// Bits of a Model Specific Register (MSR) which are set to a given value instead of the default
+/// one. The MSRs which aren't set up by default start from 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MsrOverride {
+    /// The index of the MSR.
+    pub index: u32,
+    /// The bits of the MSR which are overridden.
+    pub mask: u64,
+    /// The value of the overridden bits.
+    pub value: u64,
+}
+
 /// Configure Model Specific Registers (MSRs) for a given CPU.
 ///
 /// # Arguments
 ///
 /// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
-pub fn setup_msrs(vcpu: &kvm::VcpuFd) -> Result<()> {
-    let entry_vec = create_msr_entries();
+/// * `overrides` - The bits of the MSRs which are set to a different value than the default one.
+pub fn setup_msrs(vcpu: &kvm::VcpuFd, overrides: &[MsrOverride]) -> Result<()> {
+    let mut entry_vec = create_msr_entries();
+    apply_msr_overrides(&mut entry_vec, overrides);
     let vec_size_bytes =
         mem::size_of::<kvm_msrs>() + (entry_vec.len() * mem::size_of::<kvm_msr_entry>());
     let vec: Vec<u8> = Vec::with_capacity(vec_size_bytes);
@@ -83,8 +99,14 @@ pub fn setup_msrs(vcpu: &kvm::VcpuFd) -> Result<()> {
     }
     msrs.nmsrs = entry_vec.len() as u32;
 
-    vcpu.set_msrs(msrs)
-        .map_err(Error::SetModelSpecificRegisters)
+    let written = vcpu
+        .set_msrs(msrs)
+        .map_err(Error::SetModelSpecificRegisters)?;
+    // KVM stops writing the MSRs at the first one it rejects.
+    if (written as u32) < msrs.nmsrs {
+        return Err(Error::SetModelSpecificRegistersCount(written));
+    }
+    Ok(())
 }
 
 /// Configure base registers for a given CPU.
@@ -279,6 +301,23 @@ fn create_msr_entries() -> Vec<kvm_msr_entry> {
     entries
 }
 
+fn apply_msr_overrides(entries: &mut Vec<kvm_msr_entry>, overrides: &[MsrOverride]) {
+    for msr in overrides {
+        let position = match entries.iter().position(|entry| entry.index == msr.index) {
+            Some(position) => position,
+            None => {
+                entries.push(kvm_msr_entry {
+                    index: msr.index,
+                    ..Default::default()
+                });
+                entries.len() - 1
+            }
+        };
+        let entry = &mut entries[position];
+        entry.data = (entry.data & !msr.mask) | (msr.value & msr.mask);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -366,7 +405,7 @@ mod tests {
         let kvm = Kvm::new().unwrap();
         let vm = kvm.create_vm().unwrap();
         let vcpu = vm.create_vcpu(0).unwrap();
-        setup_msrs(&vcpu).unwrap();
+        setup_msrs(&vcpu, &[]).unwrap();
 
         // This test will check against the last MSR entry configured (the tenth one).
         // See create_msr_entries for details.
@@ -402,6 +441,53 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_setup_msrs_rejected() {
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let vcpu = vm.create_vcpu(0).unwrap();
+
+        // KVM rejects the MSR which doesn't exist, after the default ones are written.
+        let overrides = [MsrOverride {
+            index: 0xdead_beef,
+            mask: 1,
+            value: 1,
+        }];
+        match setup_msrs(&vcpu, &overrides) {
+            Err(Error::SetModelSpecificRegistersCount(written)) => {
+                assert_eq!(written as usize, create_msr_entries().len())
+            }
+            _ => panic!("An MSR rejected by KVM must fail the setup."),
+        }
+    }
+
+    #[test]
+    fn test_apply_msr_overrides() {
+        let mut entries = create_msr_entries();
+        let len = entries.len();
+        let overrides = [
+            // Clear the fast strings bit of an MSR which is set up by default.
+            MsrOverride {
+                index: ::msr_index::MSR_IA32_MISC_ENABLE,
+                mask: ::msr_index::MSR_IA32_MISC_ENABLE_FAST_STRING as u64,
+                value: 0,
+            },
+            // Set the low byte of an MSR which isn't.
+            MsrOverride {
+                index: ::msr_index::MSR_IA32_TSC,
+                mask: 0xff,
+                value: 0x1234,
+            },
+        ];
+        apply_msr_overrides(&mut entries, &overrides);
+
+        assert_eq!(entries.len(), len + 1);
+        assert_eq!(entries[9].index, ::msr_index::MSR_IA32_MISC_ENABLE);
+        assert_eq!(entries[9].data, 0);
+        assert_eq!(entries[len].index, ::msr_index::MSR_IA32_TSC);
+        assert_eq!(entries[len].data, 0x34);
+    }
+
     #[test]
     fn test_setup_regs() {
         let kvm = Kvm::new().unwrap();